@@ -0,0 +1,135 @@
+//! Error types
+
+use num_derive::FromPrimitive;
+use solana_program::{
+    decode_error::DecodeError,
+    msg,
+    program_error::{PrintProgramError, ProgramError},
+};
+use thiserror::Error;
+
+/// Errors that may be returned by the Farm program.
+#[derive(Clone, Debug, Eq, Error, FromPrimitive, PartialEq)]
+pub enum FarmError {
+    // 0.
+    /// The account cannot be initialized because it is already being used.
+    #[error("Farm account already in use")]
+    AlreadyInUse,
+    /// The program address provided doesn't match the value generated by the program.
+    #[error("Invalid program address generated from bump seed and key")]
+    InvalidProgramAddress,
+    /// The owner of a vault isn't set to the program address generated by the program.
+    #[error("Vault owner is not the program address")]
+    InvalidOwner,
+    /// The deserialization of the account returned something besides State::Mint.
+    #[error("Deserialized account is not an SPL Token mint")]
+    ExpectedMint,
+    /// The deserialization of the account returned something besides State::Account.
+    #[error("Deserialized account is not an SPL Token account")]
+    ExpectedAccount,
+
+    // 5.
+    /// The provided token program does not match the token program expected by the farm
+    #[error("The provided token program does not match the token program expected by the farm")]
+    IncorrectTokenProgramId,
+    /// The provided mint does not match the farm
+    #[error("The provided mint does not match the farm")]
+    IncorrectMint,
+    /// The provided vault does not match the farm
+    #[error("The provided vault does not match the farm")]
+    IncorrectVault,
+    /// The staker does not belong to the farm
+    #[error("The staker does not belong to the farm")]
+    IncorrectFarm,
+    /// The signer is not the farm authority or the staker owner
+    #[error("The signer is not the farm authority or the staker owner")]
+    InvalidAuthority,
+
+    // 10.
+    /// The provided token account has a delegate.
+    #[error("Token account has a delegate")]
+    InvalidDelegate,
+    /// The provided token account has a close authority.
+    #[error("Token account has a close authority")]
+    InvalidCloseAuthority,
+    /// The farm has no room left for another reward
+    #[error("The farm has no room left for another reward")]
+    TooManyRewards,
+    /// The reward index does not name one of the farm's rewards
+    #[error("The reward index does not name one of the farm's rewards")]
+    InvalidRewardIndex,
+    /// The staker does not have enough tokens staked
+    #[error("The staker does not have enough tokens staked")]
+    InsufficientStake,
+
+    // 15.
+    /// General calculation failure due to overflow or underflow
+    #[error("General calculation failure due to overflow or underflow")]
+    CalculationFailure,
+    /// Invalid instruction number passed in.
+    #[error("Invalid instruction")]
+    InvalidInstruction,
+    /// The transfer fee of a mint could not be calculated
+    #[error("Transfer fee calculation failed")]
+    FeeCalculationFailure,
+    /// Lamport balance below rent-exempt threshold.
+    #[error("Lamport balance below rent-exempt threshold")]
+    NotRentExempt,
+}
+impl From<FarmError> for ProgramError {
+    fn from(e: FarmError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+impl<T> DecodeError<T> for FarmError {
+    fn type_of() -> &'static str {
+        "Farm Error"
+    }
+}
+
+impl PrintProgramError for FarmError {
+    fn print<E>(&self)
+    where
+        E: 'static
+            + std::error::Error
+            + DecodeError<E>
+            + PrintProgramError
+            + num_traits::FromPrimitive,
+    {
+        match self {
+            FarmError::AlreadyInUse => msg!("Error: Farm account already in use"),
+            FarmError::InvalidProgramAddress => {
+                msg!("Error: Invalid program address generated from bump seed and key")
+            }
+            FarmError::InvalidOwner => msg!("Error: Vault owner is not the program address"),
+            FarmError::ExpectedMint => msg!("Error: Deserialized account is not an SPL Token mint"),
+            FarmError::ExpectedAccount => {
+                msg!("Error: Deserialized account is not an SPL Token account")
+            }
+            FarmError::IncorrectTokenProgramId => msg!(
+                "Error: The provided token program does not match the token program expected by the farm"
+            ),
+            FarmError::IncorrectMint => msg!("Error: The provided mint does not match the farm"),
+            FarmError::IncorrectVault => msg!("Error: The provided vault does not match the farm"),
+            FarmError::IncorrectFarm => msg!("Error: The staker does not belong to the farm"),
+            FarmError::InvalidAuthority => {
+                msg!("Error: The signer is not the farm authority or the staker owner")
+            }
+            FarmError::InvalidDelegate => msg!("Error: Token account has a delegate"),
+            FarmError::InvalidCloseAuthority => msg!("Error: Token account has a close authority"),
+            FarmError::TooManyRewards => {
+                msg!("Error: The farm has no room left for another reward")
+            }
+            FarmError::InvalidRewardIndex => {
+                msg!("Error: The reward index does not name one of the farm's rewards")
+            }
+            FarmError::InsufficientStake => {
+                msg!("Error: The staker does not have enough tokens staked")
+            }
+            FarmError::CalculationFailure => msg!("Error: CalculationFailure"),
+            FarmError::InvalidInstruction => msg!("Error: InvalidInstruction"),
+            FarmError::FeeCalculationFailure => msg!("Error: Transfer fee calculation failed"),
+            FarmError::NotRentExempt => msg!("Error: Lamport balance below rent-exempt threshold"),
+        }
+    }
+}
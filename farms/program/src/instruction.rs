@@ -0,0 +1,533 @@
+//! Instruction types
+
+#![allow(clippy::too_many_arguments)]
+
+use crate::error::FarmError;
+use solana_program::{
+    clock::UnixTimestamp,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::convert::TryInto;
+use std::mem::size_of;
+
+/// AddReward instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddReward {
+    /// Reward tokens emitted per second, shared between all stakers
+    pub emission_rate: u64,
+    /// Time at which emissions stop
+    pub emission_end_timestamp: UnixTimestamp,
+}
+
+/// SetEmission instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetEmission {
+    /// Index of the reward in the farm
+    pub reward_index: u8,
+    /// New reward tokens emitted per second, shared between all stakers
+    pub emission_rate: u64,
+    /// New time at which emissions stop
+    pub emission_end_timestamp: UnixTimestamp,
+}
+
+/// Stake instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stake {
+    /// Amount of tokens to transfer to the farm, the stake is credited with
+    /// the amount left after any transfer fee
+    pub amount: u64,
+}
+
+/// Unstake instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unstake {
+    /// Amount of staked tokens to transfer back, any transfer fee is withheld
+    /// from it
+    pub amount: u64,
+}
+
+/// Claim instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Claim {
+    /// Index of the reward in the farm
+    pub reward_index: u8,
+}
+
+/// Instructions supported by the farm program.
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub enum FarmInstruction {
+    ///   Initializes a new farm, without any rewards.
+    ///
+    ///   0. `[writable, signer]` New farm to create, owned by the farm program
+    ///   and rent exempt.
+    ///   1. `[]` farm vault authority derived from `create_program_address(&[farm account])`
+    ///   2. `[]` Farm authority, allowed to add rewards and change their emissions
+    ///   3. `[]` Stake token mint, such as the pool mint of a token-swap pool
+    ///   4. `[]` Stake vault, owned by the farm vault authority, without a
+    ///   delegate or close authority
+    ///   5. `[]` Stake token program id
+    InitializeFarm,
+
+    ///   Adds a reward token emitted to the stakers from now on.  Rewards are
+    ///   paid from the reward vault, funded by transferring tokens to it.
+    ///
+    ///   0. `[writable]` Farm
+    ///   1. `[signer]` Farm authority
+    ///   2. `[]` farm vault authority
+    ///   3. `[]` Reward token mint
+    ///   4. `[]` Reward vault, owned by the farm vault authority, without a
+    ///   delegate or close authority
+    ///   5. `[]` Reward token program id
+    AddReward(AddReward),
+
+    ///   Changes the emission rate and end of a reward, after accruing the
+    ///   emissions of the previous schedule.
+    ///
+    ///   0. `[writable]` Farm
+    ///   1. `[signer]` Farm authority
+    SetEmission(SetEmission),
+
+    ///   Initializes an empty stake in a farm.
+    ///
+    ///   0. `[writable, signer]` New staker to create, owned by the farm program
+    ///   and rent exempt.
+    ///   1. `[]` Farm
+    ///   2. `[]` Staker owner, allowed to stake, unstake and claim
+    InitializeStaker,
+
+    ///   Stakes tokens in a farm, settling the rewards earned so far.
+    ///
+    ///   0. `[writable]` Farm
+    ///   1. `[writable]` Staker
+    ///   2. `[signer]` Staker owner, also transfer authority of the source
+    ///   3. `[writable]` Source token account
+    ///   4. `[]` Stake token mint
+    ///   5. `[writable]` Stake vault
+    ///   6. `[]` Stake token program id
+    Stake(Stake),
+
+    ///   Unstakes tokens from a farm, settling the rewards earned so far.
+    ///   Rewards owed beyond `u64::MAX` are forfeited rather than blocking the
+    ///   unstake.
+    ///
+    ///   0. `[writable]` Farm
+    ///   1. `[writable]` Staker
+    ///   2. `[signer]` Staker owner
+    ///   3. `[]` farm vault authority
+    ///   4. `[writable]` Stake vault
+    ///   5. `[]` Stake token mint
+    ///   6. `[writable]` Destination token account
+    ///   7. `[]` Stake token program id
+    Unstake(Unstake),
+
+    ///   Claims the rewards owed to a staker for one reward token, as far as
+    ///   the reward vault can pay them.  Any transfer fee of the reward token
+    ///   is withheld from the claimed rewards.
+    ///
+    ///   0. `[writable]` Farm
+    ///   1. `[writable]` Staker
+    ///   2. `[signer]` Staker owner
+    ///   3. `[]` farm vault authority
+    ///   4. `[writable]` Reward vault
+    ///   5. `[]` Reward token mint
+    ///   6. `[writable]` Destination token account
+    ///   7. `[]` Reward token program id
+    Claim(Claim),
+}
+
+impl FarmInstruction {
+    /// Unpacks a byte buffer into a [FarmInstruction](enum.FarmInstruction.html).
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&tag, rest) = input.split_first().ok_or(FarmError::InvalidInstruction)?;
+        Ok(match tag {
+            0 => Self::InitializeFarm,
+            1 => {
+                let (emission_rate, rest) = Self::unpack_u64(rest)?;
+                let (emission_end_timestamp, _rest) = Self::unpack_i64(rest)?;
+                Self::AddReward(AddReward {
+                    emission_rate,
+                    emission_end_timestamp,
+                })
+            }
+            2 => {
+                let (&reward_index, rest) =
+                    rest.split_first().ok_or(FarmError::InvalidInstruction)?;
+                let (emission_rate, rest) = Self::unpack_u64(rest)?;
+                let (emission_end_timestamp, _rest) = Self::unpack_i64(rest)?;
+                Self::SetEmission(SetEmission {
+                    reward_index,
+                    emission_rate,
+                    emission_end_timestamp,
+                })
+            }
+            3 => Self::InitializeStaker,
+            4 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::Stake(Stake { amount })
+            }
+            5 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::Unstake(Unstake { amount })
+            }
+            6 => {
+                let (&reward_index, _rest) =
+                    rest.split_first().ok_or(FarmError::InvalidInstruction)?;
+                Self::Claim(Claim { reward_index })
+            }
+            _ => return Err(FarmError::InvalidInstruction.into()),
+        })
+    }
+
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        if input.len() >= 8 {
+            let (amount, rest) = input.split_at(8);
+            let amount = amount
+                .get(..8)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or(FarmError::InvalidInstruction)?;
+            Ok((amount, rest))
+        } else {
+            Err(FarmError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_i64(input: &[u8]) -> Result<(i64, &[u8]), ProgramError> {
+        if input.len() >= 8 {
+            let (value, rest) = input.split_at(8);
+            let value = value
+                .get(..8)
+                .and_then(|slice| slice.try_into().ok())
+                .map(i64::from_le_bytes)
+                .ok_or(FarmError::InvalidInstruction)?;
+            Ok((value, rest))
+        } else {
+            Err(FarmError::InvalidInstruction.into())
+        }
+    }
+
+    /// Packs a [FarmInstruction](enum.FarmInstruction.html) into a byte buffer.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(size_of::<Self>());
+        match &*self {
+            Self::InitializeFarm => buf.push(0),
+            Self::AddReward(AddReward {
+                emission_rate,
+                emission_end_timestamp,
+            }) => {
+                buf.push(1);
+                buf.extend_from_slice(&emission_rate.to_le_bytes());
+                buf.extend_from_slice(&emission_end_timestamp.to_le_bytes());
+            }
+            Self::SetEmission(SetEmission {
+                reward_index,
+                emission_rate,
+                emission_end_timestamp,
+            }) => {
+                buf.push(2);
+                buf.push(*reward_index);
+                buf.extend_from_slice(&emission_rate.to_le_bytes());
+                buf.extend_from_slice(&emission_end_timestamp.to_le_bytes());
+            }
+            Self::InitializeStaker => buf.push(3),
+            Self::Stake(Stake { amount }) => {
+                buf.push(4);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Unstake(Unstake { amount }) => {
+                buf.push(5);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::Claim(Claim { reward_index }) => {
+                buf.push(6);
+                buf.push(*reward_index);
+            }
+        }
+        buf
+    }
+}
+
+/// Creates an 'initialize_farm' instruction.
+pub fn initialize_farm(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    farm_pubkey: &Pubkey,
+    vault_authority_pubkey: &Pubkey,
+    farm_authority_pubkey: &Pubkey,
+    stake_mint_pubkey: &Pubkey,
+    stake_vault_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = FarmInstruction::InitializeFarm.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*farm_pubkey, true),
+        AccountMeta::new_readonly(*vault_authority_pubkey, false),
+        AccountMeta::new_readonly(*farm_authority_pubkey, false),
+        AccountMeta::new_readonly(*stake_mint_pubkey, false),
+        AccountMeta::new_readonly(*stake_vault_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'add_reward' instruction.
+pub fn add_reward(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    farm_pubkey: &Pubkey,
+    farm_authority_pubkey: &Pubkey,
+    vault_authority_pubkey: &Pubkey,
+    reward_mint_pubkey: &Pubkey,
+    reward_vault_pubkey: &Pubkey,
+    instruction: AddReward,
+) -> Result<Instruction, ProgramError> {
+    let data = FarmInstruction::AddReward(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*farm_pubkey, false),
+        AccountMeta::new_readonly(*farm_authority_pubkey, true),
+        AccountMeta::new_readonly(*vault_authority_pubkey, false),
+        AccountMeta::new_readonly(*reward_mint_pubkey, false),
+        AccountMeta::new_readonly(*reward_vault_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_emission' instruction.
+pub fn set_emission(
+    program_id: &Pubkey,
+    farm_pubkey: &Pubkey,
+    farm_authority_pubkey: &Pubkey,
+    instruction: SetEmission,
+) -> Result<Instruction, ProgramError> {
+    let data = FarmInstruction::SetEmission(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*farm_pubkey, false),
+        AccountMeta::new_readonly(*farm_authority_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'initialize_staker' instruction.
+pub fn initialize_staker(
+    program_id: &Pubkey,
+    staker_pubkey: &Pubkey,
+    farm_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = FarmInstruction::InitializeStaker.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*staker_pubkey, true),
+        AccountMeta::new_readonly(*farm_pubkey, false),
+        AccountMeta::new_readonly(*owner_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'stake' instruction.
+pub fn stake(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    farm_pubkey: &Pubkey,
+    staker_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    stake_mint_pubkey: &Pubkey,
+    stake_vault_pubkey: &Pubkey,
+    instruction: Stake,
+) -> Result<Instruction, ProgramError> {
+    let data = FarmInstruction::Stake(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*farm_pubkey, false),
+        AccountMeta::new(*staker_pubkey, false),
+        AccountMeta::new_readonly(*owner_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new_readonly(*stake_mint_pubkey, false),
+        AccountMeta::new(*stake_vault_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'unstake' instruction.
+pub fn unstake(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    farm_pubkey: &Pubkey,
+    staker_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    vault_authority_pubkey: &Pubkey,
+    stake_vault_pubkey: &Pubkey,
+    stake_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    instruction: Unstake,
+) -> Result<Instruction, ProgramError> {
+    let data = FarmInstruction::Unstake(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*farm_pubkey, false),
+        AccountMeta::new(*staker_pubkey, false),
+        AccountMeta::new_readonly(*owner_pubkey, true),
+        AccountMeta::new_readonly(*vault_authority_pubkey, false),
+        AccountMeta::new(*stake_vault_pubkey, false),
+        AccountMeta::new_readonly(*stake_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'claim' instruction.
+pub fn claim(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    farm_pubkey: &Pubkey,
+    staker_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
+    vault_authority_pubkey: &Pubkey,
+    reward_vault_pubkey: &Pubkey,
+    reward_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    instruction: Claim,
+) -> Result<Instruction, ProgramError> {
+    let data = FarmInstruction::Claim(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*farm_pubkey, false),
+        AccountMeta::new(*staker_pubkey, false),
+        AccountMeta::new_readonly(*owner_pubkey, true),
+        AccountMeta::new_readonly(*vault_authority_pubkey, false),
+        AccountMeta::new(*reward_vault_pubkey, false),
+        AccountMeta::new_readonly(*reward_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_initialize() {
+        let check = FarmInstruction::InitializeFarm;
+        let packed = check.pack();
+        assert_eq!(packed, vec![0u8]);
+        assert_eq!(FarmInstruction::unpack(&packed).unwrap(), check);
+
+        let check = FarmInstruction::InitializeStaker;
+        let packed = check.pack();
+        assert_eq!(packed, vec![3u8]);
+        assert_eq!(FarmInstruction::unpack(&packed).unwrap(), check);
+    }
+
+    #[test]
+    fn pack_emissions() {
+        let emission_rate: u64 = 1_000;
+        let emission_end_timestamp: UnixTimestamp = 1_650_000_000;
+        let check = FarmInstruction::AddReward(AddReward {
+            emission_rate,
+            emission_end_timestamp,
+        });
+        let packed = check.pack();
+        let mut expect = vec![1u8];
+        expect.extend_from_slice(&emission_rate.to_le_bytes());
+        expect.extend_from_slice(&emission_end_timestamp.to_le_bytes());
+        assert_eq!(packed, expect);
+        assert_eq!(FarmInstruction::unpack(&expect).unwrap(), check);
+
+        let reward_index: u8 = 2;
+        let check = FarmInstruction::SetEmission(SetEmission {
+            reward_index,
+            emission_rate,
+            emission_end_timestamp,
+        });
+        let packed = check.pack();
+        let mut expect = vec![2u8, reward_index];
+        expect.extend_from_slice(&emission_rate.to_le_bytes());
+        expect.extend_from_slice(&emission_end_timestamp.to_le_bytes());
+        assert_eq!(packed, expect);
+        assert_eq!(FarmInstruction::unpack(&expect).unwrap(), check);
+
+        assert_eq!(
+            FarmInstruction::unpack(&expect[..expect.len() - 1]),
+            Err(FarmError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_staking() {
+        let amount: u64 = 5_000;
+        let check = FarmInstruction::Stake(Stake { amount });
+        let packed = check.pack();
+        let mut expect = vec![4u8];
+        expect.extend_from_slice(&amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        assert_eq!(FarmInstruction::unpack(&expect).unwrap(), check);
+
+        let check = FarmInstruction::Unstake(Unstake { amount });
+        let packed = check.pack();
+        let mut expect = vec![5u8];
+        expect.extend_from_slice(&amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        assert_eq!(FarmInstruction::unpack(&expect).unwrap(), check);
+
+        let check = FarmInstruction::Claim(Claim { reward_index: 1 });
+        let packed = check.pack();
+        assert_eq!(packed, vec![6u8, 1]);
+        assert_eq!(FarmInstruction::unpack(&packed).unwrap(), check);
+
+        assert_eq!(
+            FarmInstruction::unpack(&[7u8]),
+            Err(FarmError::InvalidInstruction.into())
+        );
+    }
+}
@@ -0,0 +1,17 @@
+#![deny(missing_docs)]
+
+//! A liquidity mining program, paying reward emissions to stakers of a token,
+//! such as the pool token of a token-swap pool.
+
+pub mod error;
+pub mod instruction;
+pub mod processor;
+pub mod state;
+
+#[cfg(not(feature = "no-entrypoint"))]
+mod entrypoint;
+
+// Export current sdk types for downstream users building with a different sdk version
+pub use solana_program;
+
+solana_program::declare_id!("EDAqPVuJwXwkfHsF5UoX8XYgVQpoFtY6nf6GZ5UpnB9B");
@@ -0,0 +1,1458 @@
+//! Program state processor
+
+use crate::{
+    error::FarmError,
+    instruction::{AddReward, Claim, FarmInstruction, SetEmission, Stake, Unstake},
+    state::{Farm, Reward, Staker, MAX_REWARDS},
+};
+use num_traits::FromPrimitive;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    decode_error::DecodeError,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    msg,
+    program::invoke_signed,
+    program_error::{PrintProgramError, ProgramError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    sysvar::Sysvar,
+};
+use spl_token_2022::{
+    error::TokenError,
+    extension::{transfer_fee::TransferFeeConfig, StateWithExtensions},
+    state::{Account, Mint},
+};
+use std::error::Error;
+
+/// Program state handler.
+pub struct Processor {}
+impl Processor {
+    /// Unpacks a spl_token or spl_token_2022 `Account`, ignoring extensions.
+    pub fn unpack_token_account(
+        account_info: &AccountInfo,
+        token_program_id: &Pubkey,
+    ) -> Result<Account, FarmError> {
+        if account_info.owner != token_program_id {
+            Err(FarmError::IncorrectTokenProgramId)
+        } else {
+            StateWithExtensions::<Account>::unpack(&account_info.data.borrow())
+                .map(|a| a.base)
+                .map_err(|_| FarmError::ExpectedAccount)
+        }
+    }
+
+    /// Unpacks a spl_token or spl_token_2022 `Mint`, ignoring extensions.
+    pub fn unpack_mint(
+        account_info: &AccountInfo,
+        token_program_id: &Pubkey,
+    ) -> Result<Mint, FarmError> {
+        if account_info.owner != token_program_id {
+            Err(FarmError::IncorrectTokenProgramId)
+        } else {
+            StateWithExtensions::<Mint>::unpack(&account_info.data.borrow())
+                .map(|m| m.base)
+                .map_err(|_| FarmError::ExpectedMint)
+        }
+    }
+
+    /// Calculates the transfer fee withheld by the mint when sending `amount`.
+    pub fn transfer_fee(mint_info: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+        let mint_data = mint_info.data.borrow();
+        let mint =
+            StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| FarmError::ExpectedMint)?;
+        if let Ok(transfer_fee_config) = mint.get_extension::<TransferFeeConfig>() {
+            transfer_fee_config
+                .calculate_epoch_fee(Clock::get()?.epoch, amount)
+                .ok_or_else(|| FarmError::FeeCalculationFailure.into())
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Calculates the vault authority id by generating a program address.
+    pub fn authority_id(
+        program_id: &Pubkey,
+        my_info: &Pubkey,
+        bump_seed: u8,
+    ) -> Result<Pubkey, FarmError> {
+        Pubkey::create_program_address(&[&my_info.to_bytes()[..32], &[bump_seed]], program_id)
+            .or(Err(FarmError::InvalidProgramAddress))
+    }
+
+    /// Issue a spl_token `TransferChecked` instruction.
+    #[allow(clippy::too_many_arguments)]
+    pub fn token_transfer<'a>(
+        farm: &Pubkey,
+        token_program: AccountInfo<'a>,
+        source: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
+        destination: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        bump_seed: u8,
+        amount: u64,
+        decimals: u8,
+    ) -> Result<(), ProgramError> {
+        let farm_bytes = farm.to_bytes();
+        let authority_signature_seeds = [&farm_bytes[..32], &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = spl_token_2022::instruction::transfer_checked(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?;
+        invoke_signed_wrapper::<TokenError>(
+            &ix,
+            &[source, mint, destination, authority, token_program],
+            signers,
+        )
+    }
+
+    /// Unpacks an initialized farm owned by the program.
+    fn unpack_farm(farm_info: &AccountInfo, program_id: &Pubkey) -> Result<Farm, ProgramError> {
+        if farm_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Farm::unpack(&farm_info.data.borrow())
+    }
+
+    /// Checks that a new program account is owned by the program and holds
+    /// enough daltons to be rent exempt.
+    fn check_new_account(account_info: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+        if account_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if !Rent::get()?.is_exempt(account_info.daltons(), account_info.data_len()) {
+            return Err(FarmError::NotRentExempt.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the farm authority signed the instruction.
+    fn check_farm_authority(farm: &Farm, authority_info: &AccountInfo) -> ProgramResult {
+        if !authority_info.is_signer || *authority_info.key != farm.authority {
+            return Err(FarmError::InvalidAuthority.into());
+        }
+        Ok(())
+    }
+
+    /// Unpacks a staker of the farm, checking that its owner signed the
+    /// instruction.
+    fn unpack_staker(
+        staker_info: &AccountInfo,
+        program_id: &Pubkey,
+        farm_info: &AccountInfo,
+        owner_info: &AccountInfo,
+    ) -> Result<Staker, ProgramError> {
+        if staker_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let staker = Staker::unpack(&staker_info.data.borrow())?;
+        if staker.farm != *farm_info.key {
+            return Err(FarmError::IncorrectFarm.into());
+        }
+        if !owner_info.is_signer || *owner_info.key != staker.owner {
+            return Err(FarmError::InvalidAuthority.into());
+        }
+        Ok(staker)
+    }
+
+    /// Checks that a token account can serve as a farm vault for the mint.
+    fn check_vault(
+        vault_info: &AccountInfo,
+        token_program_id: &Pubkey,
+        mint: &Pubkey,
+        vault_authority: &Pubkey,
+    ) -> ProgramResult {
+        let vault = Self::unpack_token_account(vault_info, token_program_id)?;
+        if vault.owner != *vault_authority {
+            return Err(FarmError::InvalidOwner.into());
+        }
+        if vault.mint != *mint {
+            return Err(FarmError::IncorrectMint.into());
+        }
+        if vault.delegate.is_some() {
+            return Err(FarmError::InvalidDelegate.into());
+        }
+        if vault.close_authority.is_some() {
+            return Err(FarmError::InvalidCloseAuthority.into());
+        }
+        Ok(())
+    }
+
+    /// Processes an [InitializeFarm](enum.Instruction.html).
+    pub fn process_initialize_farm(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let farm_info = next_account_info(account_info_iter)?;
+        let vault_authority_info = next_account_info(account_info_iter)?;
+        let farm_authority_info = next_account_info(account_info_iter)?;
+        let stake_mint_info = next_account_info(account_info_iter)?;
+        let stake_vault_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        Self::check_new_account(farm_info, program_id)?;
+        if Farm::unpack_unchecked(&farm_info.data.borrow())?.is_initialized {
+            return Err(FarmError::AlreadyInUse.into());
+        }
+        let (vault_authority, bump_seed) =
+            Pubkey::find_program_address(&[&farm_info.key.to_bytes()], program_id);
+        if *vault_authority_info.key != vault_authority {
+            return Err(FarmError::InvalidProgramAddress.into());
+        }
+        Self::unpack_mint(stake_mint_info, token_program_info.key)?;
+        Self::check_vault(
+            stake_vault_info,
+            token_program_info.key,
+            stake_mint_info.key,
+            &vault_authority,
+        )?;
+
+        let farm = Farm {
+            is_initialized: true,
+            bump_seed,
+            authority: *farm_authority_info.key,
+            stake_mint: *stake_mint_info.key,
+            stake_vault: *stake_vault_info.key,
+            stake_token_program_id: *token_program_info.key,
+            total_staked: 0,
+            last_update_timestamp: Clock::get()?.unix_timestamp,
+            reward_count: 0,
+            rewards: [Reward::default(); MAX_REWARDS],
+        };
+        Farm::pack(farm, &mut farm_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [AddReward](enum.Instruction.html).
+    pub fn process_add_reward(
+        program_id: &Pubkey,
+        add_reward: AddReward,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let farm_info = next_account_info(account_info_iter)?;
+        let farm_authority_info = next_account_info(account_info_iter)?;
+        let vault_authority_info = next_account_info(account_info_iter)?;
+        let reward_mint_info = next_account_info(account_info_iter)?;
+        let reward_vault_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut farm = Self::unpack_farm(farm_info, program_id)?;
+        Self::check_farm_authority(&farm, farm_authority_info)?;
+        if *vault_authority_info.key
+            != Self::authority_id(program_id, farm_info.key, farm.bump_seed)?
+        {
+            return Err(FarmError::InvalidProgramAddress.into());
+        }
+        let reward_count = usize::from(farm.reward_count);
+        if reward_count == MAX_REWARDS {
+            return Err(FarmError::TooManyRewards.into());
+        }
+        Self::unpack_mint(reward_mint_info, token_program_info.key)?;
+        Self::check_vault(
+            reward_vault_info,
+            token_program_info.key,
+            reward_mint_info.key,
+            vault_authority_info.key,
+        )?;
+        // rewards must never be paid out of the staked tokens or of another
+        // reward's vault
+        if *reward_vault_info.key == farm.stake_vault
+            || farm.rewards[..reward_count]
+                .iter()
+                .any(|reward| reward.vault == *reward_vault_info.key)
+        {
+            return Err(FarmError::IncorrectVault.into());
+        }
+
+        farm.update(Clock::get()?.unix_timestamp);
+        farm.rewards[reward_count] = Reward {
+            mint: *reward_mint_info.key,
+            vault: *reward_vault_info.key,
+            token_program_id: *token_program_info.key,
+            emission_rate: add_reward.emission_rate,
+            emission_end_timestamp: add_reward.emission_end_timestamp,
+            reward_per_share: 0,
+        };
+        farm.reward_count += 1;
+        Farm::pack(farm, &mut farm_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [SetEmission](enum.Instruction.html).
+    pub fn process_set_emission(
+        program_id: &Pubkey,
+        set_emission: SetEmission,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let farm_info = next_account_info(account_info_iter)?;
+        let farm_authority_info = next_account_info(account_info_iter)?;
+
+        let mut farm = Self::unpack_farm(farm_info, program_id)?;
+        Self::check_farm_authority(&farm, farm_authority_info)?;
+        let reward_index = usize::from(set_emission.reward_index);
+        if reward_index >= usize::from(farm.reward_count) {
+            return Err(FarmError::InvalidRewardIndex.into());
+        }
+
+        farm.update(Clock::get()?.unix_timestamp);
+        let reward = &mut farm.rewards[reward_index];
+        reward.emission_rate = set_emission.emission_rate;
+        reward.emission_end_timestamp = set_emission.emission_end_timestamp;
+        Farm::pack(farm, &mut farm_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [InitializeStaker](enum.Instruction.html).
+    pub fn process_initialize_staker(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let staker_info = next_account_info(account_info_iter)?;
+        let farm_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+
+        Self::check_new_account(staker_info, program_id)?;
+        if Staker::unpack_unchecked(&staker_info.data.borrow())?.is_initialized {
+            return Err(FarmError::AlreadyInUse.into());
+        }
+        let farm = Self::unpack_farm(farm_info, program_id)?;
+
+        let mut staker = Staker {
+            is_initialized: true,
+            farm: *farm_info.key,
+            owner: *owner_info.key,
+            ..Staker::default()
+        };
+        // nothing is staked yet, so this only records the current rewards per share
+        staker
+            .settle_rewards(&farm)
+            .ok_or(FarmError::CalculationFailure)?;
+        Staker::pack(staker, &mut staker_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [Stake](enum.Instruction.html).
+    pub fn process_stake(
+        program_id: &Pubkey,
+        amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let farm_info = next_account_info(account_info_iter)?;
+        let staker_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let stake_mint_info = next_account_info(account_info_iter)?;
+        let stake_vault_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut farm = Self::unpack_farm(farm_info, program_id)?;
+        let mut staker = Self::unpack_staker(staker_info, program_id, farm_info, owner_info)?;
+        if *stake_mint_info.key != farm.stake_mint {
+            return Err(FarmError::IncorrectMint.into());
+        }
+        if *stake_vault_info.key != farm.stake_vault {
+            return Err(FarmError::IncorrectVault.into());
+        }
+        if *token_program_info.key != farm.stake_token_program_id {
+            return Err(FarmError::IncorrectTokenProgramId.into());
+        }
+
+        farm.update(Clock::get()?.unix_timestamp);
+        staker
+            .settle_rewards(&farm)
+            .ok_or(FarmError::CalculationFailure)?;
+
+        let stake_mint = Self::unpack_mint(stake_mint_info, token_program_info.key)?;
+        let staked_amount = amount
+            .checked_sub(Self::transfer_fee(stake_mint_info, amount)?)
+            .ok_or(FarmError::FeeCalculationFailure)?;
+        Self::token_transfer(
+            farm_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            stake_mint_info.clone(),
+            stake_vault_info.clone(),
+            owner_info.clone(),
+            farm.bump_seed,
+            amount,
+            stake_mint.decimals,
+        )?;
+
+        staker.staked_amount = staker
+            .staked_amount
+            .checked_add(staked_amount)
+            .ok_or(FarmError::CalculationFailure)?;
+        farm.total_staked = farm
+            .total_staked
+            .checked_add(staked_amount)
+            .ok_or(FarmError::CalculationFailure)?;
+        Staker::pack(staker, &mut staker_info.data.borrow_mut())?;
+        Farm::pack(farm, &mut farm_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [Unstake](enum.Instruction.html).
+    pub fn process_unstake(
+        program_id: &Pubkey,
+        amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let farm_info = next_account_info(account_info_iter)?;
+        let staker_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let vault_authority_info = next_account_info(account_info_iter)?;
+        let stake_vault_info = next_account_info(account_info_iter)?;
+        let stake_mint_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut farm = Self::unpack_farm(farm_info, program_id)?;
+        let mut staker = Self::unpack_staker(staker_info, program_id, farm_info, owner_info)?;
+        if *vault_authority_info.key
+            != Self::authority_id(program_id, farm_info.key, farm.bump_seed)?
+        {
+            return Err(FarmError::InvalidProgramAddress.into());
+        }
+        if *stake_mint_info.key != farm.stake_mint {
+            return Err(FarmError::IncorrectMint.into());
+        }
+        if *stake_vault_info.key != farm.stake_vault {
+            return Err(FarmError::IncorrectVault.into());
+        }
+        if *token_program_info.key != farm.stake_token_program_id {
+            return Err(FarmError::IncorrectTokenProgramId.into());
+        }
+        if amount > staker.staked_amount {
+            return Err(FarmError::InsufficientStake.into());
+        }
+
+        farm.update(Clock::get()?.unix_timestamp);
+        // the principal must always come back, even if the rewards overflow
+        staker.settle_rewards_saturating(&farm);
+
+        let stake_mint = Self::unpack_mint(stake_mint_info, token_program_info.key)?;
+        Self::token_transfer(
+            farm_info.key,
+            token_program_info.clone(),
+            stake_vault_info.clone(),
+            stake_mint_info.clone(),
+            destination_info.clone(),
+            vault_authority_info.clone(),
+            farm.bump_seed,
+            amount,
+            stake_mint.decimals,
+        )?;
+
+        staker.staked_amount -= amount;
+        farm.total_staked = farm
+            .total_staked
+            .checked_sub(amount)
+            .ok_or(FarmError::CalculationFailure)?;
+        Staker::pack(staker, &mut staker_info.data.borrow_mut())?;
+        Farm::pack(farm, &mut farm_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [Claim](enum.Instruction.html).
+    pub fn process_claim(
+        program_id: &Pubkey,
+        reward_index: u8,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let farm_info = next_account_info(account_info_iter)?;
+        let staker_info = next_account_info(account_info_iter)?;
+        let owner_info = next_account_info(account_info_iter)?;
+        let vault_authority_info = next_account_info(account_info_iter)?;
+        let reward_vault_info = next_account_info(account_info_iter)?;
+        let reward_mint_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut farm = Self::unpack_farm(farm_info, program_id)?;
+        let mut staker = Self::unpack_staker(staker_info, program_id, farm_info, owner_info)?;
+        if *vault_authority_info.key
+            != Self::authority_id(program_id, farm_info.key, farm.bump_seed)?
+        {
+            return Err(FarmError::InvalidProgramAddress.into());
+        }
+        let reward_index = usize::from(reward_index);
+        if reward_index >= usize::from(farm.reward_count) {
+            return Err(FarmError::InvalidRewardIndex.into());
+        }
+        let reward = farm.rewards[reward_index];
+        if *reward_mint_info.key != reward.mint {
+            return Err(FarmError::IncorrectMint.into());
+        }
+        if *reward_vault_info.key != reward.vault {
+            return Err(FarmError::IncorrectVault.into());
+        }
+        if *token_program_info.key != reward.token_program_id {
+            return Err(FarmError::IncorrectTokenProgramId.into());
+        }
+
+        farm.update(Clock::get()?.unix_timestamp);
+        staker
+            .settle_rewards(&farm)
+            .ok_or(FarmError::CalculationFailure)?;
+
+        // an underfunded vault pays what it can, the rest stays owed until
+        // the vault is topped up
+        let reward_vault = Self::unpack_token_account(reward_vault_info, token_program_info.key)?;
+        let staker_reward = &mut staker.rewards[reward_index];
+        let amount = staker_reward.rewards_owed.min(reward_vault.amount);
+        if amount > 0 {
+            let reward_mint = Self::unpack_mint(reward_mint_info, token_program_info.key)?;
+            Self::token_transfer(
+                farm_info.key,
+                token_program_info.clone(),
+                reward_vault_info.clone(),
+                reward_mint_info.clone(),
+                destination_info.clone(),
+                vault_authority_info.clone(),
+                farm.bump_seed,
+                amount,
+                reward_mint.decimals,
+            )?;
+            staker_reward.rewards_owed -= amount;
+        }
+
+        Staker::pack(staker, &mut staker_info.data.borrow_mut())?;
+        Farm::pack(farm, &mut farm_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        let instruction = FarmInstruction::unpack(input)?;
+        match instruction {
+            FarmInstruction::InitializeFarm => {
+                msg!("Instruction: InitializeFarm");
+                Self::process_initialize_farm(program_id, accounts)
+            }
+            FarmInstruction::AddReward(add_reward) => {
+                msg!("Instruction: AddReward");
+                Self::process_add_reward(program_id, add_reward, accounts)
+            }
+            FarmInstruction::SetEmission(set_emission) => {
+                msg!("Instruction: SetEmission");
+                Self::process_set_emission(program_id, set_emission, accounts)
+            }
+            FarmInstruction::InitializeStaker => {
+                msg!("Instruction: InitializeStaker");
+                Self::process_initialize_staker(program_id, accounts)
+            }
+            FarmInstruction::Stake(Stake { amount }) => {
+                msg!("Instruction: Stake");
+                Self::process_stake(program_id, amount, accounts)
+            }
+            FarmInstruction::Unstake(Unstake { amount }) => {
+                msg!("Instruction: Unstake");
+                Self::process_unstake(program_id, amount, accounts)
+            }
+            FarmInstruction::Claim(Claim { reward_index }) => {
+                msg!("Instruction: Claim");
+                Self::process_claim(program_id, reward_index, accounts)
+            }
+        }
+    }
+}
+
+fn invoke_signed_wrapper<T>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> Result<(), ProgramError>
+where
+    T: 'static + PrintProgramError + DecodeError<T> + FromPrimitive + Error,
+{
+    invoke_signed(instruction, account_infos, signers_seeds).map_err(|err| {
+        err.print::<T>();
+        err
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruction::{
+        add_reward, claim, initialize_farm, initialize_staker, set_emission, stake, unstake,
+    };
+    use solana_program::{clock::UnixTimestamp, entrypoint::SUCCESS, program_stubs};
+    use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
+    use spl_token_2022::{
+        extension::{transfer_fee::instruction::initialize_transfer_fee_config, ExtensionType},
+        instruction::{initialize_account, initialize_mint, mint_to},
+    };
+
+    // Test program id for the farm program.
+    const FARM_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
+
+    struct TestSyscallStubs {}
+    impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            msg!("TestSyscallStubs::sol_invoke_signed()");
+
+            let mut new_account_infos = vec![];
+
+            // mimic check for token program in accounts
+            if !account_infos
+                .iter()
+                .any(|x| *x.key == spl_token::id() || *x.key == spl_token_2022::id())
+            {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            for meta in instruction.accounts.iter() {
+                for account_info in account_infos.iter() {
+                    if meta.pubkey == *account_info.key {
+                        let mut new_account_info = account_info.clone();
+                        for seeds in signers_seeds.iter() {
+                            let signer =
+                                Pubkey::create_program_address(seeds, &FARM_PROGRAM_ID).unwrap();
+                            if *account_info.key == signer {
+                                new_account_info.is_signer = true;
+                            }
+                        }
+                        new_account_infos.push(new_account_info);
+                    }
+                }
+            }
+
+            if instruction.program_id == spl_token::id() {
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &new_account_infos,
+                    &instruction.data,
+                )
+            } else {
+                spl_token_2022::processor::Processor::process(
+                    &instruction.program_id,
+                    &new_account_infos,
+                    &instruction.data,
+                )
+            }
+        }
+
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut _ as *mut Clock) = Clock {
+                    unix_timestamp: TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.get()),
+                    ..Clock::default()
+                };
+            }
+            SUCCESS
+        }
+
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut _ as *mut Rent) = Rent::default();
+            }
+            SUCCESS
+        }
+    }
+
+    thread_local! {
+        // unix timestamp reported by the clock sysvar to the current test
+        static TEST_UNIX_TIMESTAMP: std::cell::Cell<UnixTimestamp> = std::cell::Cell::new(0);
+    }
+
+    fn test_syscall_stubs() {
+        use std::sync::Once;
+        static ONCE: Once = Once::new();
+
+        ONCE.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
+        });
+    }
+
+    fn set_timestamp(timestamp: UnixTimestamp) {
+        TEST_UNIX_TIMESTAMP.with(|cell| cell.set(timestamp));
+    }
+
+    struct TokenAccountInfo {
+        key: Pubkey,
+        account: Account,
+    }
+
+    impl TokenAccountInfo {
+        fn amount(&self) -> u64 {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&self.account.data)
+                .unwrap()
+                .base
+                .amount
+        }
+    }
+
+    struct RewardAccountInfo {
+        token_program_id: Pubkey,
+        mint_key: Pubkey,
+        mint_account: Account,
+        vault: TokenAccountInfo,
+    }
+
+    struct StakerAccountInfo {
+        owner_key: Pubkey,
+        staker_key: Pubkey,
+        staker_account: Account,
+        wallet: TokenAccountInfo,
+    }
+
+    impl StakerAccountInfo {
+        fn state(&self) -> Staker {
+            Staker::unpack(&self.staker_account.data).unwrap()
+        }
+    }
+
+    struct FarmAccountInfo {
+        authority_key: Pubkey,
+        vault_authority_key: Pubkey,
+        farm_key: Pubkey,
+        farm_account: Account,
+        stake_token_program_id: Pubkey,
+        stake_mint_key: Pubkey,
+        stake_mint_account: Account,
+        stake_vault: TokenAccountInfo,
+        rewards: Vec<RewardAccountInfo>,
+    }
+
+    impl FarmAccountInfo {
+        fn new(stake_token_program_id: &Pubkey, transfer_fee_basis_points: Option<u16>) -> Self {
+            let authority_key = Pubkey::new_unique();
+            let farm_key = Pubkey::new_unique();
+            let farm_account = Account::new(
+                Rent::default().minimum_balance(Farm::LEN),
+                Farm::LEN,
+                &FARM_PROGRAM_ID,
+            );
+            let (vault_authority_key, _bump_seed) =
+                Pubkey::find_program_address(&[&farm_key.to_bytes()[..]], &FARM_PROGRAM_ID);
+            let (stake_mint_key, mut stake_mint_account) = create_mint(
+                stake_token_program_id,
+                &authority_key,
+                transfer_fee_basis_points,
+            );
+            let stake_vault = mint_token(
+                stake_token_program_id,
+                &stake_mint_key,
+                &mut stake_mint_account,
+                &authority_key,
+                &vault_authority_key,
+                0,
+            );
+            FarmAccountInfo {
+                authority_key,
+                vault_authority_key,
+                farm_key,
+                farm_account,
+                stake_token_program_id: *stake_token_program_id,
+                stake_mint_key,
+                stake_mint_account,
+                stake_vault,
+                rewards: vec![],
+            }
+        }
+
+        fn state(&self) -> Farm {
+            Farm::unpack(&self.farm_account.data).unwrap()
+        }
+
+        fn initialize_farm(&mut self) -> ProgramResult {
+            do_process_instruction(
+                initialize_farm(
+                    &FARM_PROGRAM_ID,
+                    &self.stake_token_program_id,
+                    &self.farm_key,
+                    &self.vault_authority_key,
+                    &self.authority_key,
+                    &self.stake_mint_key,
+                    &self.stake_vault.key,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.farm_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.stake_mint_account,
+                    &mut self.stake_vault.account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        fn new_reward(
+            &self,
+            token_program_id: &Pubkey,
+            transfer_fee_basis_points: Option<u16>,
+            vault_amount: u64,
+        ) -> RewardAccountInfo {
+            let (mint_key, mut mint_account) = create_mint(
+                token_program_id,
+                &self.authority_key,
+                transfer_fee_basis_points,
+            );
+            let vault = mint_token(
+                token_program_id,
+                &mint_key,
+                &mut mint_account,
+                &self.authority_key,
+                &self.vault_authority_key,
+                vault_amount,
+            );
+            RewardAccountInfo {
+                token_program_id: *token_program_id,
+                mint_key,
+                mint_account,
+                vault,
+            }
+        }
+
+        fn add_reward(
+            &mut self,
+            authority_key: &Pubkey,
+            mut reward: RewardAccountInfo,
+            emission_rate: u64,
+            emission_end_timestamp: UnixTimestamp,
+        ) -> ProgramResult {
+            do_process_instruction(
+                add_reward(
+                    &FARM_PROGRAM_ID,
+                    &reward.token_program_id,
+                    &self.farm_key,
+                    authority_key,
+                    &self.vault_authority_key,
+                    &reward.mint_key,
+                    &reward.vault.key,
+                    AddReward {
+                        emission_rate,
+                        emission_end_timestamp,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.farm_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut reward.mint_account,
+                    &mut reward.vault.account,
+                    &mut Account::default(),
+                ],
+            )?;
+            self.rewards.push(reward);
+            Ok(())
+        }
+
+        fn set_emission(
+            &mut self,
+            authority_key: &Pubkey,
+            reward_index: u8,
+            emission_rate: u64,
+            emission_end_timestamp: UnixTimestamp,
+        ) -> ProgramResult {
+            do_process_instruction(
+                set_emission(
+                    &FARM_PROGRAM_ID,
+                    &self.farm_key,
+                    authority_key,
+                    SetEmission {
+                        reward_index,
+                        emission_rate,
+                        emission_end_timestamp,
+                    },
+                )
+                .unwrap(),
+                vec![&mut self.farm_account, &mut Account::default()],
+            )
+        }
+
+        fn new_staker(&mut self, wallet_amount: u64) -> StakerAccountInfo {
+            let owner_key = Pubkey::new_unique();
+            let staker_key = Pubkey::new_unique();
+            let mut staker_account = Account::new(
+                Rent::default().minimum_balance(Staker::LEN),
+                Staker::LEN,
+                &FARM_PROGRAM_ID,
+            );
+            let wallet = mint_token(
+                &self.stake_token_program_id,
+                &self.stake_mint_key,
+                &mut self.stake_mint_account,
+                &self.authority_key,
+                &owner_key,
+                wallet_amount,
+            );
+            do_process_instruction(
+                initialize_staker(&FARM_PROGRAM_ID, &staker_key, &self.farm_key, &owner_key)
+                    .unwrap(),
+                vec![
+                    &mut staker_account,
+                    &mut self.farm_account,
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            StakerAccountInfo {
+                owner_key,
+                staker_key,
+                staker_account,
+                wallet,
+            }
+        }
+
+        fn stake(&mut self, staker: &mut StakerAccountInfo, amount: u64) -> ProgramResult {
+            do_process_instruction(
+                stake(
+                    &FARM_PROGRAM_ID,
+                    &self.stake_token_program_id,
+                    &self.farm_key,
+                    &staker.staker_key,
+                    &staker.owner_key,
+                    &staker.wallet.key,
+                    &self.stake_mint_key,
+                    &self.stake_vault.key,
+                    Stake { amount },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.farm_account,
+                    &mut staker.staker_account,
+                    &mut Account::default(),
+                    &mut staker.wallet.account,
+                    &mut self.stake_mint_account,
+                    &mut self.stake_vault.account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        fn unstake(&mut self, staker: &mut StakerAccountInfo, amount: u64) -> ProgramResult {
+            do_process_instruction(
+                unstake(
+                    &FARM_PROGRAM_ID,
+                    &self.stake_token_program_id,
+                    &self.farm_key,
+                    &staker.staker_key,
+                    &staker.owner_key,
+                    &self.vault_authority_key,
+                    &self.stake_vault.key,
+                    &self.stake_mint_key,
+                    &staker.wallet.key,
+                    Unstake { amount },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.farm_account,
+                    &mut staker.staker_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.stake_vault.account,
+                    &mut self.stake_mint_account,
+                    &mut staker.wallet.account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        fn new_reward_account(
+            &mut self,
+            reward_index: usize,
+            owner_key: &Pubkey,
+        ) -> TokenAccountInfo {
+            let reward = &mut self.rewards[reward_index];
+            mint_token(
+                &reward.token_program_id,
+                &reward.mint_key,
+                &mut reward.mint_account,
+                &self.authority_key,
+                owner_key,
+                0,
+            )
+        }
+
+        fn claim(
+            &mut self,
+            staker: &mut StakerAccountInfo,
+            reward_index: u8,
+            destination: &mut TokenAccountInfo,
+        ) -> ProgramResult {
+            let reward = &mut self.rewards[usize::from(reward_index)];
+            do_process_instruction(
+                claim(
+                    &FARM_PROGRAM_ID,
+                    &reward.token_program_id,
+                    &self.farm_key,
+                    &staker.staker_key,
+                    &staker.owner_key,
+                    &self.vault_authority_key,
+                    &reward.vault.key,
+                    &reward.mint_key,
+                    &destination.key,
+                    Claim { reward_index },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.farm_account,
+                    &mut staker.staker_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut reward.vault.account,
+                    &mut reward.mint_account,
+                    &mut destination.account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+    }
+
+    fn do_process_instruction(
+        instruction: Instruction,
+        accounts: Vec<&mut Account>,
+    ) -> ProgramResult {
+        test_syscall_stubs();
+
+        // approximate the logic in the actual runtime which runs the instruction
+        // and only updates accounts if the instruction is successful
+        let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
+        let mut meta = instruction
+            .accounts
+            .iter()
+            .zip(account_clones.iter_mut())
+            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
+            .collect::<Vec<_>>();
+        let mut account_infos = create_is_signer_account_infos(&mut meta);
+        let res = if instruction.program_id == FARM_PROGRAM_ID {
+            Processor::process(&instruction.program_id, &account_infos, &instruction.data)
+        } else if instruction.program_id == spl_token::id() {
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+            )
+        } else {
+            spl_token_2022::processor::Processor::process(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+            )
+        };
+
+        if res.is_ok() {
+            let mut account_metas = instruction
+                .accounts
+                .iter()
+                .zip(accounts)
+                .map(|(account_meta, account)| (&account_meta.pubkey, account))
+                .collect::<Vec<_>>();
+            for account_info in account_infos.iter_mut() {
+                for account_meta in account_metas.iter_mut() {
+                    if account_info.key == account_meta.0 {
+                        let account = &mut account_meta.1;
+                        account.owner = *account_info.owner;
+                        account.daltons = **account_info.daltons.borrow();
+                        account.data = account_info.data.borrow().to_vec();
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    fn mint_token(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        mint_account: &mut Account,
+        mint_authority_key: &Pubkey,
+        account_owner_key: &Pubkey,
+        amount: u64,
+    ) -> TokenAccountInfo {
+        let account_key = Pubkey::new_unique();
+        let space = if *program_id == spl_token_2022::id() {
+            let mint = StateWithExtensions::<Mint>::unpack(&mint_account.data).unwrap();
+            let mint_extensions = mint.get_extension_types().unwrap();
+            let required_extensions =
+                ExtensionType::get_required_init_account_extensions(&mint_extensions);
+            ExtensionType::get_account_len::<spl_token_2022::state::Account>(&required_extensions)
+        } else {
+            spl_token::state::Account::get_packed_len()
+        };
+        let mut account_account =
+            Account::new(Rent::default().minimum_balance(space), space, program_id);
+        let mut mint_authority_account = Account::default();
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        do_process_instruction(
+            initialize_account(program_id, &account_key, mint_key, account_owner_key).unwrap(),
+            vec![
+                &mut account_account,
+                mint_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar_account,
+            ],
+        )
+        .unwrap();
+
+        if amount > 0 {
+            do_process_instruction(
+                mint_to(
+                    program_id,
+                    mint_key,
+                    &account_key,
+                    mint_authority_key,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+                vec![
+                    mint_account,
+                    &mut account_account,
+                    &mut mint_authority_account,
+                ],
+            )
+            .unwrap();
+        }
+
+        TokenAccountInfo {
+            key: account_key,
+            account: account_account,
+        }
+    }
+
+    fn create_mint(
+        program_id: &Pubkey,
+        authority_key: &Pubkey,
+        transfer_fee_basis_points: Option<u16>,
+    ) -> (Pubkey, Account) {
+        let mint_key = Pubkey::new_unique();
+        let space = if transfer_fee_basis_points.is_some() {
+            ExtensionType::get_account_len::<Mint>(&[ExtensionType::TransferFeeConfig])
+        } else {
+            spl_token::state::Mint::get_packed_len()
+        };
+        let mut mint_account =
+            Account::new(Rent::default().minimum_balance(space), space, program_id);
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        if let Some(transfer_fee_basis_points) = transfer_fee_basis_points {
+            do_process_instruction(
+                initialize_transfer_fee_config(
+                    program_id,
+                    &mint_key,
+                    None,
+                    None,
+                    transfer_fee_basis_points,
+                    u64::MAX,
+                )
+                .unwrap(),
+                vec![&mut mint_account],
+            )
+            .unwrap();
+        }
+
+        do_process_instruction(
+            initialize_mint(program_id, &mint_key, authority_key, None, 2).unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar_account],
+        )
+        .unwrap();
+
+        (mint_key, mint_account)
+    }
+
+    #[test]
+    fn test_initialize_farm() {
+        set_timestamp(0);
+        let mut accounts = FarmAccountInfo::new(&spl_token::id(), None);
+
+        // farm account not owned by the program
+        accounts.farm_account.owner = Pubkey::new_unique();
+        assert_eq!(
+            Err(ProgramError::IncorrectProgramId),
+            accounts.initialize_farm()
+        );
+        accounts.farm_account.owner = FARM_PROGRAM_ID;
+
+        // farm account not rent exempt
+        let rent_exempt_daltons = accounts.farm_account.daltons;
+        accounts.farm_account.daltons = rent_exempt_daltons - 1;
+        assert_eq!(
+            Err(FarmError::NotRentExempt.into()),
+            accounts.initialize_farm()
+        );
+        accounts.farm_account.daltons = rent_exempt_daltons;
+
+        accounts.initialize_farm().unwrap();
+        assert!(accounts.state().is_initialized);
+    }
+
+    #[test]
+    fn test_initialize_staker() {
+        set_timestamp(0);
+        let mut accounts = FarmAccountInfo::new(&spl_token::id(), None);
+        accounts.initialize_farm().unwrap();
+        let owner_key = Pubkey::new_unique();
+        let staker_key = Pubkey::new_unique();
+        let mut staker_account = Account::new(
+            Rent::default().minimum_balance(Staker::LEN),
+            Staker::LEN,
+            &FARM_PROGRAM_ID,
+        );
+        let mut initialize = |staker_account: &mut Account| {
+            do_process_instruction(
+                initialize_staker(
+                    &FARM_PROGRAM_ID,
+                    &staker_key,
+                    &accounts.farm_key,
+                    &owner_key,
+                )
+                .unwrap(),
+                vec![
+                    staker_account,
+                    &mut accounts.farm_account,
+                    &mut Account::default(),
+                ],
+            )
+        };
+
+        // staker account not owned by the program
+        staker_account.owner = Pubkey::new_unique();
+        assert_eq!(
+            Err(ProgramError::IncorrectProgramId),
+            initialize(&mut staker_account)
+        );
+        staker_account.owner = FARM_PROGRAM_ID;
+
+        // staker account not rent exempt
+        let rent_exempt_daltons = staker_account.daltons;
+        staker_account.daltons = rent_exempt_daltons - 1;
+        assert_eq!(
+            Err(FarmError::NotRentExempt.into()),
+            initialize(&mut staker_account)
+        );
+        staker_account.daltons = rent_exempt_daltons;
+
+        initialize(&mut staker_account).unwrap();
+        assert_eq!(
+            Err(FarmError::AlreadyInUse.into()),
+            initialize(&mut staker_account)
+        );
+        let staker = Staker::unpack(&staker_account.data).unwrap();
+        assert_eq!(staker.owner, owner_key);
+        assert_eq!(staker.farm, accounts.farm_key);
+    }
+
+    #[test]
+    fn test_farm_rewards() {
+        set_timestamp(0);
+        let mut accounts = FarmAccountInfo::new(&spl_token::id(), None);
+        accounts.initialize_farm().unwrap();
+        assert_eq!(
+            Err(FarmError::AlreadyInUse.into()),
+            accounts.initialize_farm()
+        );
+
+        // only the farm authority adds rewards
+        let wrong_authority_key = Pubkey::new_unique();
+        let reward = accounts.new_reward(&spl_token::id(), None, 1_000_000);
+        assert_eq!(
+            Err(FarmError::InvalidAuthority.into()),
+            accounts.add_reward(&wrong_authority_key, reward, 100, 10_000)
+        );
+
+        // the staked tokens can never be paid out as rewards
+        let stake_vault = RewardAccountInfo {
+            token_program_id: accounts.stake_token_program_id,
+            mint_key: accounts.stake_mint_key,
+            mint_account: accounts.stake_mint_account.clone(),
+            vault: TokenAccountInfo {
+                key: accounts.stake_vault.key,
+                account: accounts.stake_vault.account.clone(),
+            },
+        };
+        let authority_key = accounts.authority_key;
+        assert_eq!(
+            Err(FarmError::IncorrectVault.into()),
+            accounts.add_reward(&authority_key, stake_vault, 100, 10_000)
+        );
+
+        // one plain reward, and one withholding a 1% transfer fee
+        let reward = accounts.new_reward(&spl_token::id(), None, 1_000_000);
+        accounts
+            .add_reward(&authority_key, reward, 100, 10_000)
+            .unwrap();
+        let reward = accounts.new_reward(&spl_token_2022::id(), Some(100), 1_000_000);
+        accounts
+            .add_reward(&authority_key, reward, 10, 10_000)
+            .unwrap();
+        assert_eq!(accounts.state().reward_count, 2);
+
+        let mut staker_1 = accounts.new_staker(1_000);
+        let mut staker_2 = accounts.new_staker(3_000);
+        accounts.stake(&mut staker_1, 1_000).unwrap();
+        assert_eq!(staker_1.wallet.amount(), 0);
+        assert_eq!(staker_1.state().staked_amount, 1_000);
+
+        // staker 1 earns alone for 100 seconds, then a quarter for 100 more
+        set_timestamp(100);
+        accounts.stake(&mut staker_2, 3_000).unwrap();
+        assert_eq!(accounts.state().total_staked, 4_000);
+        assert_eq!(accounts.stake_vault.amount(), 4_000);
+
+        set_timestamp(200);
+        let mut reward_0_1 = accounts.new_reward_account(0, &staker_1.owner_key);
+        let mut reward_0_2 = accounts.new_reward_account(0, &staker_2.owner_key);
+        let mut reward_1_1 = accounts.new_reward_account(1, &staker_1.owner_key);
+        accounts.claim(&mut staker_1, 0, &mut reward_0_1).unwrap();
+        assert_eq!(reward_0_1.amount(), 12_500);
+        accounts.claim(&mut staker_2, 0, &mut reward_0_2).unwrap();
+        assert_eq!(reward_0_2.amount(), 7_500);
+        assert_eq!(accounts.rewards[0].vault.amount(), 1_000_000 - 20_000);
+
+        // 1_250 reward tokens are paid, 13 of them withheld as the transfer fee
+        accounts.claim(&mut staker_1, 1, &mut reward_1_1).unwrap();
+        assert_eq!(reward_1_1.amount(), 1_237);
+        assert_eq!(staker_1.state().rewards[1].rewards_owed, 0);
+
+        // nothing more to claim at the same time
+        accounts.claim(&mut staker_1, 0, &mut reward_0_1).unwrap();
+        assert_eq!(reward_0_1.amount(), 12_500);
+
+        // only the owner claims and unstakes
+        let owner_key = staker_1.owner_key;
+        staker_1.owner_key = staker_2.owner_key;
+        assert_eq!(
+            Err(FarmError::InvalidAuthority.into()),
+            accounts.claim(&mut staker_1, 0, &mut reward_0_2)
+        );
+        assert_eq!(
+            Err(FarmError::InvalidAuthority.into()),
+            accounts.unstake(&mut staker_1, 1_000)
+        );
+        staker_1.owner_key = owner_key;
+
+        assert_eq!(
+            Err(FarmError::InsufficientStake.into()),
+            accounts.unstake(&mut staker_1, 1_001)
+        );
+        accounts.unstake(&mut staker_1, 1_000).unwrap();
+        assert_eq!(staker_1.wallet.amount(), 1_000);
+        assert_eq!(accounts.state().total_staked, 3_000);
+
+        // emissions stop, staker 2 keeps what it earned alone before that
+        set_timestamp(300);
+        assert_eq!(
+            Err(FarmError::InvalidAuthority.into()),
+            accounts.set_emission(&wrong_authority_key, 0, 0, 10_000)
+        );
+        assert_eq!(
+            Err(FarmError::InvalidRewardIndex.into()),
+            accounts.set_emission(&authority_key, 2, 0, 10_000)
+        );
+        accounts.set_emission(&authority_key, 0, 0, 10_000).unwrap();
+
+        set_timestamp(400);
+        accounts.claim(&mut staker_2, 0, &mut reward_0_2).unwrap();
+        // reward per share rounds down, in favour of the vault
+        assert_eq!(reward_0_2.amount(), 7_500 + 10_000 - 1);
+    }
+
+    #[test]
+    fn test_unstake_overflowing_rewards() {
+        set_timestamp(0);
+        let mut accounts = FarmAccountInfo::new(&spl_token::id(), None);
+        accounts.initialize_farm().unwrap();
+        let authority_key = accounts.authority_key;
+        let reward = accounts.new_reward(&spl_token::id(), None, 1_000_000);
+        accounts
+            .add_reward(&authority_key, reward, u64::MAX, 10_000)
+            .unwrap();
+        let mut staker = accounts.new_staker(2);
+        accounts.stake(&mut staker, 1).unwrap();
+        set_timestamp(1);
+        accounts.stake(&mut staker, 1).unwrap();
+        assert_eq!(staker.state().rewards[0].rewards_owed, u64::MAX);
+
+        // the rewards owed no longer fit in a u64, so they cannot be
+        // settled, but the stake still comes back
+        set_timestamp(2);
+        let mut reward_account = accounts.new_reward_account(0, &staker.owner_key);
+        assert_eq!(
+            Err(FarmError::CalculationFailure.into()),
+            accounts.claim(&mut staker, 0, &mut reward_account)
+        );
+        accounts.unstake(&mut staker, 2).unwrap();
+        assert_eq!(staker.wallet.amount(), 2);
+        assert_eq!(staker.state().staked_amount, 0);
+        assert_eq!(staker.state().rewards[0].rewards_owed, u64::MAX);
+        assert_eq!(accounts.state().total_staked, 0);
+    }
+
+    #[test]
+    fn test_farm_transfer_fees() {
+        set_timestamp(0);
+        // a stake token withholding a 1% transfer fee
+        let mut accounts = FarmAccountInfo::new(&spl_token_2022::id(), Some(100));
+        accounts.initialize_farm().unwrap();
+        let authority_key = accounts.authority_key;
+        let reward = accounts.new_reward(&spl_token::id(), None, 500);
+        accounts
+            .add_reward(&authority_key, reward, 100, 10_000)
+            .unwrap();
+
+        // the stake is credited with what the vault received
+        let mut staker = accounts.new_staker(1_000);
+        accounts.stake(&mut staker, 1_000).unwrap();
+        assert_eq!(staker.state().staked_amount, 990);
+        assert_eq!(accounts.state().total_staked, 990);
+
+        // an underfunded reward vault pays what it holds, the rest stays owed
+        set_timestamp(10);
+        let mut reward_account = accounts.new_reward_account(0, &staker.owner_key);
+        accounts.claim(&mut staker, 0, &mut reward_account).unwrap();
+        assert_eq!(reward_account.amount(), 500);
+        assert_eq!(staker.state().rewards[0].rewards_owed, 499);
+
+        let reward = &mut accounts.rewards[0];
+        do_process_instruction(
+            mint_to(
+                &spl_token::id(),
+                &reward.mint_key,
+                &reward.vault.key,
+                &authority_key,
+                &[],
+                1_000,
+            )
+            .unwrap(),
+            vec![
+                &mut reward.mint_account,
+                &mut reward.vault.account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        accounts.claim(&mut staker, 0, &mut reward_account).unwrap();
+        assert_eq!(reward_account.amount(), 999);
+        assert_eq!(staker.state().rewards[0].rewards_owed, 0);
+
+        // unstaking withholds the transfer fee from what the staker receives
+        accounts.unstake(&mut staker, 990).unwrap();
+        assert_eq!(staker.wallet.amount(), 980);
+        assert_eq!(accounts.state().total_staked, 0);
+        assert_eq!(accounts.stake_vault.amount(), 0);
+    }
+}
@@ -0,0 +1,498 @@
+//! State transition types
+
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    clock::UnixTimestamp,
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+    pubkey::Pubkey,
+};
+use spl_math::uint::U256;
+use std::convert::TryFrom;
+
+/// Maximum number of reward tokens paid by a farm
+pub const MAX_REWARDS: usize = 4;
+
+/// Emissions of one reward token
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Reward {
+    /// Mint of the reward token
+    pub mint: Pubkey,
+    /// Token account holding the rewards, owned by the farm authority
+    pub vault: Pubkey,
+    /// Program ID of the reward token
+    pub token_program_id: Pubkey,
+    /// Reward tokens emitted per second, shared between all stakers
+    pub emission_rate: u64,
+    /// Time at which emissions stop
+    pub emission_end_timestamp: UnixTimestamp,
+    /// Rewards emitted per staked token since the reward was added, as a
+    /// Q64.64 fixed point number allowed to wrap around
+    pub reward_per_share: u128,
+}
+
+impl Reward {
+    /// Adds the emissions between two times to the reward per share, unless
+    /// nothing is staked to receive them
+    pub fn accrue(&mut self, from: UnixTimestamp, to: UnixTimestamp, total_staked: u64) {
+        let to = to.min(self.emission_end_timestamp);
+        if total_staked == 0 || to <= from {
+            return;
+        }
+        let emitted = u128::from(self.emission_rate) * (to - from) as u128;
+        let reward_per_share = (U256::from(emitted) << 64) / U256::from(total_staked);
+        self.reward_per_share = self
+            .reward_per_share
+            .wrapping_add(reward_per_share.low_u128());
+    }
+}
+
+impl Sealed for Reward {}
+impl Pack for Reward {
+    const LEN: usize = 128;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 128];
+        let (
+            mint,
+            vault,
+            token_program_id,
+            emission_rate,
+            emission_end_timestamp,
+            reward_per_share,
+        ) = mut_array_refs![output, 32, 32, 32, 8, 8, 16];
+        mint.copy_from_slice(self.mint.as_ref());
+        vault.copy_from_slice(self.vault.as_ref());
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        *emission_rate = self.emission_rate.to_le_bytes();
+        *emission_end_timestamp = self.emission_end_timestamp.to_le_bytes();
+        *reward_per_share = self.reward_per_share.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 128];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            mint,
+            vault,
+            token_program_id,
+            emission_rate,
+            emission_end_timestamp,
+            reward_per_share,
+        ) = array_refs![input, 32, 32, 32, 8, 8, 16];
+        Ok(Self {
+            mint: Pubkey::new_from_array(*mint),
+            vault: Pubkey::new_from_array(*vault),
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            emission_rate: u64::from_le_bytes(*emission_rate),
+            emission_end_timestamp: UnixTimestamp::from_le_bytes(*emission_end_timestamp),
+            reward_per_share: u128::from_le_bytes(*reward_per_share),
+        })
+    }
+}
+
+/// Farm state, paying the emissions of up to `MAX_REWARDS` reward tokens to
+/// the stakers of a token in proportion to their stake.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct Farm {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Bump seed used in program address.
+    /// The program address is created deterministically with the bump seed,
+    /// farm program id, and farm account pubkey.  This program address has
+    /// authority over the farm's stake and reward vaults.
+    pub bump_seed: u8,
+
+    /// Authority allowed to add rewards and change their emission schedule
+    pub authority: Pubkey,
+
+    /// Mint of the staked token
+    pub stake_mint: Pubkey,
+    /// Token account holding the staked tokens
+    pub stake_vault: Pubkey,
+    /// Program ID of the staked token
+    pub stake_token_program_id: Pubkey,
+    /// Tokens staked by all stakers
+    pub total_staked: u64,
+    /// Time up to which the rewards have been accrued
+    pub last_update_timestamp: UnixTimestamp,
+
+    /// Number of rewards paid by the farm, the rest of `rewards` is left as
+    /// zeroes
+    pub reward_count: u8,
+    /// Rewards, in the order they were added
+    pub rewards: [Reward; MAX_REWARDS],
+}
+
+impl Farm {
+    /// Accrues the emissions of every reward up to the given time
+    pub fn update(&mut self, timestamp: UnixTimestamp) {
+        let reward_count = usize::from(self.reward_count);
+        for reward in self.rewards[..reward_count].iter_mut() {
+            reward.accrue(self.last_update_timestamp, timestamp, self.total_staked);
+        }
+        self.last_update_timestamp = self.last_update_timestamp.max(timestamp);
+    }
+}
+
+impl Sealed for Farm {}
+impl IsInitialized for Farm {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Farm {
+    const LEN: usize = 659;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 659];
+        let (
+            is_initialized,
+            bump_seed,
+            authority,
+            stake_mint,
+            stake_vault,
+            stake_token_program_id,
+            total_staked,
+            last_update_timestamp,
+            reward_count,
+            rewards,
+        ) = mut_array_refs![output, 1, 1, 32, 32, 32, 32, 8, 8, 1, 512];
+        is_initialized[0] = self.is_initialized as u8;
+        bump_seed[0] = self.bump_seed;
+        authority.copy_from_slice(self.authority.as_ref());
+        stake_mint.copy_from_slice(self.stake_mint.as_ref());
+        stake_vault.copy_from_slice(self.stake_vault.as_ref());
+        stake_token_program_id.copy_from_slice(self.stake_token_program_id.as_ref());
+        *total_staked = self.total_staked.to_le_bytes();
+        *last_update_timestamp = self.last_update_timestamp.to_le_bytes();
+        reward_count[0] = self.reward_count;
+        for (dst, reward) in rewards
+            .chunks_exact_mut(Reward::LEN)
+            .zip(self.rewards.iter())
+        {
+            reward.pack_into_slice(dst);
+        }
+    }
+
+    /// Unpacks a byte buffer into a [Farm](struct.Farm.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 659];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            bump_seed,
+            authority,
+            stake_mint,
+            stake_vault,
+            stake_token_program_id,
+            total_staked,
+            last_update_timestamp,
+            reward_count,
+            rewards,
+        ) = array_refs![input, 1, 1, 32, 32, 32, 32, 8, 8, 1, 512];
+        let reward_count = reward_count[0];
+        if usize::from(reward_count) > MAX_REWARDS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut farm = Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            bump_seed: bump_seed[0],
+            authority: Pubkey::new_from_array(*authority),
+            stake_mint: Pubkey::new_from_array(*stake_mint),
+            stake_vault: Pubkey::new_from_array(*stake_vault),
+            stake_token_program_id: Pubkey::new_from_array(*stake_token_program_id),
+            total_staked: u64::from_le_bytes(*total_staked),
+            last_update_timestamp: UnixTimestamp::from_le_bytes(*last_update_timestamp),
+            reward_count,
+            ..Self::default()
+        };
+        for (reward, src) in farm
+            .rewards
+            .iter_mut()
+            .zip(rewards.chunks_exact(Reward::LEN))
+        {
+            *reward = Reward::unpack_from_slice(src)?;
+        }
+        Ok(farm)
+    }
+}
+
+/// Share of one reward owed to a staker
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StakerReward {
+    /// Reward per share of the farm when the staker's rewards were last settled
+    pub reward_per_share: u128,
+    /// Rewards settled but not yet claimed
+    pub rewards_owed: u64,
+}
+
+/// Stake of one owner in a farm
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct Staker {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Farm the tokens are staked in
+    pub farm: Pubkey,
+    /// Owner allowed to stake, unstake and claim
+    pub owner: Pubkey,
+    /// Tokens staked, after any transfer fee
+    pub staked_amount: u64,
+    /// Share of each of the farm's rewards, in the farm's order
+    pub rewards: [StakerReward; MAX_REWARDS],
+}
+
+impl Staker {
+    /// Credits the staker with the rewards earned by its stake since they
+    /// were last settled, failing if the rewards owed overflow
+    pub fn settle_rewards(&mut self, farm: &Farm) -> Option<()> {
+        let reward_count = usize::from(farm.reward_count);
+        for (staker_reward, reward) in self
+            .rewards
+            .iter_mut()
+            .zip(farm.rewards[..reward_count].iter())
+        {
+            let earned = Self::earned(self.staked_amount, staker_reward, reward);
+            staker_reward.rewards_owed = staker_reward
+                .rewards_owed
+                .checked_add(u64::try_from(earned).ok()?)?;
+            staker_reward.reward_per_share = reward.reward_per_share;
+        }
+        Some(())
+    }
+
+    /// Credits the staker like `settle_rewards`, but caps the rewards owed
+    /// at `u64::MAX` instead of failing, so that the farm's emissions can
+    /// never lock the staked tokens
+    pub fn settle_rewards_saturating(&mut self, farm: &Farm) {
+        let reward_count = usize::from(farm.reward_count);
+        for (staker_reward, reward) in self
+            .rewards
+            .iter_mut()
+            .zip(farm.rewards[..reward_count].iter())
+        {
+            let earned = Self::earned(self.staked_amount, staker_reward, reward);
+            staker_reward.rewards_owed = staker_reward
+                .rewards_owed
+                .saturating_add(u64::try_from(earned).unwrap_or(u64::MAX));
+            staker_reward.reward_per_share = reward.reward_per_share;
+        }
+    }
+
+    /// Rewards earned by `staked_amount` since `staker_reward` was settled
+    fn earned(staked_amount: u64, staker_reward: &StakerReward, reward: &Reward) -> U256 {
+        let reward_growth = reward
+            .reward_per_share
+            .wrapping_sub(staker_reward.reward_per_share);
+        // at most 192 bits, so it cannot overflow
+        (U256::from(reward_growth) * U256::from(staked_amount)) >> 64
+    }
+}
+
+impl Sealed for Staker {}
+impl IsInitialized for Staker {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Staker {
+    const LEN: usize = 169;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 169];
+        let (is_initialized, farm, owner, staked_amount, rewards) =
+            mut_array_refs![output, 1, 32, 32, 8, 96];
+        is_initialized[0] = self.is_initialized as u8;
+        farm.copy_from_slice(self.farm.as_ref());
+        owner.copy_from_slice(self.owner.as_ref());
+        *staked_amount = self.staked_amount.to_le_bytes();
+        for (dst, reward) in rewards.chunks_exact_mut(24).zip(self.rewards.iter()) {
+            let (reward_per_share, rewards_owed) =
+                mut_array_refs![array_mut_ref![dst, 0, 24], 16, 8];
+            *reward_per_share = reward.reward_per_share.to_le_bytes();
+            *rewards_owed = reward.rewards_owed.to_le_bytes();
+        }
+    }
+
+    /// Unpacks a byte buffer into a [Staker](struct.Staker.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 169];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (is_initialized, farm, owner, staked_amount, rewards) =
+            array_refs![input, 1, 32, 32, 8, 96];
+        let mut staker = Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            farm: Pubkey::new_from_array(*farm),
+            owner: Pubkey::new_from_array(*owner),
+            staked_amount: u64::from_le_bytes(*staked_amount),
+            ..Self::default()
+        };
+        for (reward, src) in staker.rewards.iter_mut().zip(rewards.chunks_exact(24)) {
+            #[allow(clippy::ptr_offset_with_cast)]
+            let (reward_per_share, rewards_owed) = array_refs![array_ref![src, 0, 24], 16, 8];
+            *reward = StakerReward {
+                reward_per_share: u128::from_le_bytes(*reward_per_share),
+                rewards_owed: u64::from_le_bytes(*rewards_owed),
+            };
+        }
+        Ok(staker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_REWARD: Reward = Reward {
+        mint: Pubkey::new_from_array([4u8; 32]),
+        vault: Pubkey::new_from_array([5u8; 32]),
+        token_program_id: Pubkey::new_from_array([6u8; 32]),
+        emission_rate: 1_000,
+        emission_end_timestamp: 1_650_086_400,
+        reward_per_share: u128::MAX - 5,
+    };
+
+    #[test]
+    fn pack_farm() {
+        let mut rewards = [Reward::default(); MAX_REWARDS];
+        rewards[0] = TEST_REWARD;
+        let farm = Farm {
+            is_initialized: true,
+            bump_seed: 255,
+            authority: Pubkey::new_from_array([1u8; 32]),
+            stake_mint: Pubkey::new_from_array([2u8; 32]),
+            stake_vault: Pubkey::new_from_array([3u8; 32]),
+            stake_token_program_id: Pubkey::new_from_array([7u8; 32]),
+            total_staked: 1 << 40,
+            last_update_timestamp: 1_650_000_000,
+            reward_count: 1,
+            rewards,
+        };
+        let mut packed = [0u8; Farm::LEN];
+        Farm::pack_into_slice(&farm, &mut packed);
+        let unpacked = Farm::unpack(&packed).unwrap();
+        assert_eq!(farm, unpacked);
+
+        let mut expect = vec![1u8, 255];
+        expect.extend_from_slice(&[1u8; 32]);
+        expect.extend_from_slice(&[2u8; 32]);
+        expect.extend_from_slice(&[3u8; 32]);
+        expect.extend_from_slice(&[7u8; 32]);
+        expect.extend_from_slice(&farm.total_staked.to_le_bytes());
+        expect.extend_from_slice(&farm.last_update_timestamp.to_le_bytes());
+        expect.push(1);
+        expect.extend_from_slice(&[4u8; 32]);
+        expect.extend_from_slice(&[5u8; 32]);
+        expect.extend_from_slice(&[6u8; 32]);
+        expect.extend_from_slice(&TEST_REWARD.emission_rate.to_le_bytes());
+        expect.extend_from_slice(&TEST_REWARD.emission_end_timestamp.to_le_bytes());
+        expect.extend_from_slice(&TEST_REWARD.reward_per_share.to_le_bytes());
+        expect.extend_from_slice(&[0u8; 3 * Reward::LEN]);
+        assert_eq!(packed.to_vec(), expect);
+
+        let mut packed = packed;
+        packed[Farm::LEN - 4 * Reward::LEN - 1] = MAX_REWARDS as u8 + 1;
+        assert_eq!(Farm::unpack(&packed), Err(ProgramError::InvalidAccountData));
+    }
+
+    #[test]
+    fn pack_staker() {
+        let mut staker = Staker {
+            is_initialized: true,
+            farm: Pubkey::new_from_array([1u8; 32]),
+            owner: Pubkey::new_from_array([2u8; 32]),
+            staked_amount: 1_000,
+            ..Staker::default()
+        };
+        staker.rewards[1] = StakerReward {
+            reward_per_share: 1 << 100,
+            rewards_owed: 42,
+        };
+        let mut packed = [0u8; Staker::LEN];
+        Staker::pack_into_slice(&staker, &mut packed);
+        let unpacked = Staker::unpack(&packed).unwrap();
+        assert_eq!(staker, unpacked);
+
+        let mut expect = vec![1u8];
+        expect.extend_from_slice(&[1u8; 32]);
+        expect.extend_from_slice(&[2u8; 32]);
+        expect.extend_from_slice(&staker.staked_amount.to_le_bytes());
+        expect.extend_from_slice(&[0u8; 24]);
+        expect.extend_from_slice(&(1u128 << 100).to_le_bytes());
+        expect.extend_from_slice(&42u64.to_le_bytes());
+        expect.extend_from_slice(&[0u8; 48]);
+        assert_eq!(packed.to_vec(), expect);
+    }
+
+    #[test]
+    fn reward_accrual() {
+        let mut farm = Farm {
+            is_initialized: true,
+            total_staked: 4_000,
+            last_update_timestamp: 100,
+            reward_count: 1,
+            ..Farm::default()
+        };
+        farm.rewards[0] = Reward {
+            emission_rate: 10,
+            emission_end_timestamp: 300,
+            reward_per_share: u128::MAX,
+            ..Reward::default()
+        };
+        let mut staker = Staker {
+            is_initialized: true,
+            staked_amount: 1_000,
+            ..Staker::default()
+        };
+        staker.rewards[0].reward_per_share = u128::MAX;
+
+        // a quarter of 1_000 emitted tokens, with the reward per share
+        // wrapping around
+        farm.update(200);
+        assert_eq!(farm.last_update_timestamp, 200);
+        staker.settle_rewards(&farm).unwrap();
+        assert_eq!(staker.rewards[0].rewards_owed, 250);
+
+        // emissions stop at their end
+        farm.update(1_000);
+        staker.settle_rewards(&farm).unwrap();
+        assert_eq!(staker.rewards[0].rewards_owed, 500);
+        staker.settle_rewards(&farm).unwrap();
+        assert_eq!(staker.rewards[0].rewards_owed, 500);
+
+        // rewards owed beyond a u64 fail to settle, unless saturating
+        let mut whale = Staker {
+            is_initialized: true,
+            staked_amount: u64::MAX,
+            ..Staker::default()
+        };
+        whale.rewards[0].reward_per_share = farm.rewards[0].reward_per_share.wrapping_sub(2 << 64);
+        assert_eq!(whale.settle_rewards(&farm), None);
+        whale.settle_rewards_saturating(&farm);
+        assert_eq!(whale.rewards[0].rewards_owed, u64::MAX);
+        assert_eq!(
+            whale.rewards[0].reward_per_share,
+            farm.rewards[0].reward_per_share
+        );
+
+        // nothing accrues while nothing is staked
+        let reward_per_share = farm.rewards[0].reward_per_share;
+        farm.rewards[0].emission_end_timestamp = 2_000;
+        farm.total_staked = 0;
+        farm.update(1_500);
+        assert_eq!(farm.rewards[0].reward_per_share, reward_per_share);
+        assert_eq!(farm.last_update_timestamp, 1_500);
+    }
+}
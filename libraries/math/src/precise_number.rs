@@ -25,6 +25,14 @@ fn zero() -> InnerUint {
     InnerUint::from(0)
 }
 
+/// Multiply a number by 2 ^ shift, returning None on overflow
+fn shift_left(value: InnerUint, shift: u128) -> Option<InnerUint> {
+    if shift >= 256 {
+        return None;
+    }
+    value.checked_mul(InnerUint::from(1) << shift as usize)
+}
+
 impl PreciseNumber {
     /// Correction to apply to avoid truncation errors on division.  Since
     /// integer operations will always floor the result, we artifically bump it
@@ -361,6 +369,121 @@ impl PreciseNumber {
         let guess = self.checked_add(&one)?.checked_div(&two)?;
         self.newtonian_root_approximation(&two, guess, Self::MAX_APPROXIMATION_ITERATIONS)
     }
+
+    /// Extended precision used internally by checked_log2 and checked_exp2, as
+    /// ONE ^ 2.  Both approximations chain many operations, so the extra
+    /// digits keep the rounding error from each step below the precision of
+    /// ONE in the final result.
+    fn extended_one() -> InnerUint {
+        one().checked_mul(one()).unwrap()
+    }
+
+    /// The natural logarithm of 2 at extended precision,
+    /// ln(2) = 0.693147180559945309417232
+    fn extended_ln_2() -> InnerUint {
+        InnerUint::from(693_147_180_559_945_309_417_232u128)
+    }
+
+    /// Number of fractional bits computed by checked_log2, enough for an error
+    /// of 2 ^ -48 ~= 3.6e-15, well below the precision of ONE.
+    const LOG2_FRACTIONAL_BITS: u32 = 48;
+
+    /// Approximate the base 2 logarithm of a number, returning the result and
+    /// whether it is negative, in the same way as `unsigned_sub`.
+    ///
+    /// The integer part is found by scaling the number into [1, 2) with
+    /// powers of 2, and the fractional part is built bit by bit through
+    /// repeated squaring:
+    /// https://en.wikipedia.org/wiki/Binary_logarithm#Iterative_approximation
+    pub fn checked_log2(&self) -> Option<(Self, bool)> {
+        if *self == Self::zero() {
+            return None;
+        }
+        let extended_one = Self::extended_one();
+        let extended_two = extended_one.checked_mul(InnerUint::from(2))?;
+        let mut scaled = self.value.checked_mul(one())?;
+        let mut integer_part = 0u128;
+        let negative = scaled < extended_one;
+        while scaled >= extended_two {
+            scaled >>= 1;
+            integer_part = integer_part.checked_add(1)?;
+        }
+        while scaled < extended_one {
+            scaled <<= 1;
+            integer_part = integer_part.checked_add(1)?;
+        }
+
+        let mut fractional_part = zero();
+        let mut bit = extended_one >> 1;
+        for _ in 0..Self::LOG2_FRACTIONAL_BITS {
+            scaled = scaled.checked_mul(scaled)?.checked_div(extended_one)?;
+            if scaled >= extended_two {
+                scaled >>= 1;
+                fractional_part = fractional_part.checked_add(bit)?;
+            }
+            bit >>= 1;
+        }
+
+        let integer_part = Self::new(integer_part)?;
+        let fractional_part = Self {
+            value: fractional_part
+                .checked_add(Self::rounding_correction())?
+                .checked_div(one())?,
+        };
+        if negative {
+            // log2(x) = -n + f, where x * 2^n is in [1, 2)
+            Some((integer_part.checked_sub(&fractional_part)?, true))
+        } else {
+            Some((integer_part.checked_add(&fractional_part)?, false))
+        }
+    }
+
+    /// Approximate 2 raised to the power of this number, or to its negation if
+    /// `negative` is set.
+    ///
+    /// The integer part of the exponent is applied as a shift, and the
+    /// fractional part f uses the Taylor Series of e^x at x = f * ln(2),
+    /// which converges quickly since 0 <= x < ln(2).
+    pub fn checked_exp2(&self, negative: bool) -> Option<Self> {
+        let whole_exponent = self.floor()?;
+        let (remainder_exponent, _) = self.unsigned_sub(&whole_exponent);
+        let shift = whole_exponent.to_imprecise()?;
+
+        let extended_one = Self::extended_one();
+        let x = remainder_exponent
+            .value
+            .checked_mul(Self::extended_ln_2())?
+            .checked_div(one())?;
+        let mut result = extended_one;
+        let mut term = extended_one;
+        for k in 1..Self::MAX_APPROXIMATION_ITERATIONS {
+            term = term
+                .checked_mul(x)?
+                .checked_div(extended_one)?
+                .checked_div(InnerUint::from(k))?;
+            if term == zero() {
+                break;
+            }
+            result = result.checked_add(term)?;
+        }
+        let result = match shift_left(result, shift) {
+            Some(result) => result,
+            // 2 ^ -x is too small to represent, but still well-defined
+            None if negative => return Some(Self::zero()),
+            None => return None,
+        };
+
+        let value = if negative {
+            // 1 / 2^x, bringing the extended precision result back to ONE
+            let numerator = extended_one.checked_mul(one())?;
+            numerator.checked_add(result >> 1)?.checked_div(result)?
+        } else {
+            result
+                .checked_add(Self::rounding_correction())?
+                .checked_div(one())?
+        };
+        Some(Self { value })
+    }
 }
 
 #[cfg(test)]
@@ -443,6 +566,50 @@ mod tests {
         ); // 8.629769290
     }
 
+    fn check_log2(base: InnerUint, expected: InnerUint, expected_negative: bool) {
+        let precision = InnerUint::from(10); // correct to at least 10 decimal places
+        let base = PreciseNumber { value: base };
+        let (log, negative) = base.checked_log2().unwrap();
+        let expected = PreciseNumber { value: expected };
+        assert!(log.almost_eq(&expected, precision), "{:?}", log);
+        assert_eq!(negative, expected_negative);
+    }
+
+    #[test]
+    fn test_log2() {
+        let one = one();
+        assert_eq!(PreciseNumber::zero().checked_log2(), None);
+        check_log2(one, InnerUint::from(0), false);
+        check_log2(one * 8, one * 3, false);
+        check_log2(one / 2, one, true);
+        check_log2(one * 10, InnerUint::from(3_321928094887u128), false); // 3.32192809488736
+        check_log2(one * 3 / 10, InnerUint::from(1_736965594166u128), true); // -1.73696559416621
+        check_log2(one * (1u64 << 40), one * 40, false);
+    }
+
+    fn check_exp2(exponent: InnerUint, negative: bool, expected: InnerUint) {
+        let precision = InnerUint::from(10); // correct to at least 10 decimal places
+        let exponent = PreciseNumber { value: exponent };
+        let power = exponent.checked_exp2(negative).unwrap();
+        let expected = PreciseNumber { value: expected };
+        assert!(power.almost_eq(&expected, precision), "{:?}", power);
+    }
+
+    #[test]
+    fn test_exp2() {
+        let one = one();
+        check_exp2(InnerUint::from(0), false, one);
+        check_exp2(one * 3, false, one * 8);
+        check_exp2(one, true, one / 2);
+        check_exp2(one / 2, false, InnerUint::from(1_414213562373u128)); // 1.41421356237310
+        check_exp2(one * 3 / 2, true, InnerUint::from(353553390593u128)); // 0.35355339059327
+        assert_eq!(PreciseNumber::new(256).unwrap().checked_exp2(false), None);
+        assert_eq!(
+            PreciseNumber::new(256).unwrap().checked_exp2(true),
+            Some(PreciseNumber::zero())
+        );
+    }
+
     #[test]
     fn test_newtonian_approximation() {
         let test = PreciseNumber::new(0).unwrap();
@@ -343,7 +343,11 @@ async function swapUSDTtoUSDC(
       destination: userUSDCAccount,
       poolMint: poolInfo.poolMint,
       feeAccount: poolInfo.poolFeeAccount,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      sourceMint: poolInfo.usdtMint,
+      destinationMint: poolInfo.usdcMint,
+      sourceTokenProgram: TOKEN_PROGRAM_ID,
+      destinationTokenProgram: TOKEN_PROGRAM_ID,
+      poolTokenProgram: TOKEN_PROGRAM_ID,
     },
     {
       amountIn: new BN(usdtAmount),
@@ -458,7 +462,11 @@ async function swapUSDCtoUSDT(
       destination: userAccounts.userUSDTAccount,
       poolMint: poolInfo.poolMint,
       feeAccount: poolInfo.poolFeeAccount,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      sourceMint: poolInfo.usdcMint,
+      destinationMint: poolInfo.usdtMint,
+      sourceTokenProgram: TOKEN_PROGRAM_ID,
+      destinationTokenProgram: TOKEN_PROGRAM_ID,
+      poolTokenProgram: TOKEN_PROGRAM_ID,
     },
     {
       amountIn: new BN(usdcAmount),
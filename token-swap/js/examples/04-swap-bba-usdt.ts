@@ -446,7 +446,11 @@ async function swapBBAtoUSDT(
       destination: userUSDTAccount,
       poolMint: poolInfo.poolMint,
       feeAccount: poolInfo.poolFeeAccount,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      sourceMint: NATIVE_MINT,
+      destinationMint: poolInfo.usdtMint,
+      sourceTokenProgram: TOKEN_PROGRAM_ID,
+      destinationTokenProgram: TOKEN_PROGRAM_ID,
+      poolTokenProgram: TOKEN_PROGRAM_ID,
     },
     {
       amountIn: new BN(bbaAmountDaltons),
@@ -586,7 +590,11 @@ async function swapUSDTtoBBA(
       destination: userWBBAAccount,
       poolMint: poolInfo.poolMint,
       feeAccount: poolInfo.poolFeeAccount,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      sourceMint: poolInfo.usdtMint,
+      destinationMint: NATIVE_MINT,
+      sourceTokenProgram: TOKEN_PROGRAM_ID,
+      destinationTokenProgram: TOKEN_PROGRAM_ID,
+      poolTokenProgram: TOKEN_PROGRAM_ID,
     },
     {
       amountIn: new BN(usdtAmountSmallest),
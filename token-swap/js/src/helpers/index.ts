@@ -1,3 +1,4 @@
+import { PublicKey } from '@bbachain/web3.js';
 import BN from 'bn.js';
 import { Fees, SwapCurve, CurveType } from '../types';
 
@@ -104,3 +105,55 @@ export function createOffsetCurve(): SwapCurve {
     calculator: new Array(32).fill(0),
   };
 }
+
+/**
+ * Helper function to create a Weighted curve, such as 80/20 with
+ * `createWeightedCurve(80, 20)`
+ *
+ * @category helpers
+ */
+export function createWeightedCurve(
+  tokenAWeight: number | BN,
+  tokenBWeight: number | BN
+): SwapCurve {
+  const calculator = [
+    ...new BN(tokenAWeight).toArray('le', 8),
+    ...new BN(tokenBWeight).toArray('le', 8),
+    ...new Array(16).fill(0),
+  ];
+  return {
+    curveType: CurveType.Weighted,
+    calculator,
+  };
+}
+
+/**
+ * Helper function to find the canonical pool of a token pair, curve type and
+ * fee tier, the mints may be given in either order. The fee tier is the trade
+ * fee in hundredths of a basis point, such as 3000 for a 0.3% pool. A
+ * canonical pool holds its mints in sorted order.
+ *
+ * @category helpers
+ */
+export async function findCanonicalPoolAddress(
+  tokenAMint: PublicKey,
+  tokenBMint: PublicKey,
+  curveType: CurveType,
+  feeTier: number,
+  programId: PublicKey
+): Promise<[PublicKey, number]> {
+  const [firstMint, secondMint] =
+    Buffer.compare(tokenAMint.toBuffer(), tokenBMint.toBuffer()) <= 0
+      ? [tokenAMint, tokenBMint]
+      : [tokenBMint, tokenAMint];
+  return PublicKey.findProgramAddress(
+    [
+      Buffer.from('pool'),
+      firstMint.toBuffer(),
+      secondMint.toBuffer(),
+      Buffer.from([curveType]),
+      new BN(feeTier).toArrayLike(Buffer, 'le', 4),
+    ],
+    programId
+  );
+}
@@ -29,8 +29,16 @@ export type DepositAllTokenTypesInstructionAccounts = {
   poolMint: web3.PublicKey;
   /** Pool Account to deposit the generated tokens, user is the owner */
   poolAccount: web3.PublicKey;
-  /** Token program id */
-  tokenProgram: web3.PublicKey;
+  /** token_a mint */
+  mintA: web3.PublicKey;
+  /** token_b mint */
+  mintB: web3.PublicKey;
+  /** token_a program id, owning the token_a mint */
+  tokenProgramA: web3.PublicKey;
+  /** token_b program id, owning the token_b mint */
+  tokenProgramB: web3.PublicKey;
+  /** Pool token program id, owning the pool mint */
+  poolTokenProgram: web3.PublicKey;
   anchorRemainingAccounts?: web3.AccountMeta[];
 };
 
@@ -57,7 +65,7 @@ export function createDepositAllTokenTypesInstruction(
   const keys: web3.AccountMeta[] = [
     {
       pubkey: accounts.tokenSwap,
-      isWritable: false,
+      isWritable: true,
       isSigner: false,
     },
     {
@@ -68,7 +76,7 @@ export function createDepositAllTokenTypesInstruction(
     {
       pubkey: accounts.userTransferAuthority,
       isWritable: false,
-      isSigner: false,
+      isSigner: true,
     },
     {
       pubkey: accounts.sourceA,
@@ -101,7 +109,27 @@ export function createDepositAllTokenTypesInstruction(
       isSigner: false,
     },
     {
-      pubkey: accounts.tokenProgram,
+      pubkey: accounts.mintA,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.mintB,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.tokenProgramA,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.tokenProgramB,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.poolTokenProgram,
       isWritable: false,
       isSigner: false,
     },
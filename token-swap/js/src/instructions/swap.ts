@@ -26,8 +26,16 @@ export type SwapInstructionAccounts = {
   poolMint: web3.PublicKey;
   /** Fee account, to receive trading fees */
   feeAccount: web3.PublicKey;
-  /** Token program id */
-  tokenProgram: web3.PublicKey;
+  /** token_(A|B) SOURCE mint */
+  sourceMint: web3.PublicKey;
+  /** token_(A|B) DESTINATION mint */
+  destinationMint: web3.PublicKey;
+  /** token_(A|B) SOURCE program id, owning the SOURCE mint */
+  sourceTokenProgram: web3.PublicKey;
+  /** token_(A|B) DESTINATION program id, owning the DESTINATION mint */
+  destinationTokenProgram: web3.PublicKey;
+  /** Pool token program id, owning the pool mint */
+  poolTokenProgram: web3.PublicKey;
   /** [optional] Host fee account to receive additional trading fees */
   hostFeeAccount?: web3.PublicKey;
   anchorRemainingAccounts?: web3.AccountMeta[];
@@ -56,7 +64,7 @@ export function createSwapInstruction(
   const keys: web3.AccountMeta[] = [
     {
       pubkey: accounts.tokenSwap,
-      isWritable: false,
+      isWritable: true,
       isSigner: false,
     },
     {
@@ -67,7 +75,7 @@ export function createSwapInstruction(
     {
       pubkey: accounts.userTransferAuthority,
       isWritable: false,
-      isSigner: false,
+      isSigner: true,
     },
     {
       pubkey: accounts.source,
@@ -100,7 +108,27 @@ export function createSwapInstruction(
       isSigner: false,
     },
     {
-      pubkey: accounts.tokenProgram,
+      pubkey: accounts.sourceMint,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.destinationMint,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.sourceTokenProgram,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.destinationTokenProgram,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.poolTokenProgram,
       isWritable: false,
       isSigner: false,
     },
@@ -31,8 +31,16 @@ export type WithdrawAllTokenTypesInstructionAccounts = {
   userAccountB: web3.PublicKey;
   /** Fee account, to receive withdrawal fees */
   feeAccount: web3.PublicKey;
-  /** Token program id */
-  tokenProgram: web3.PublicKey;
+  /** token_a mint */
+  mintA: web3.PublicKey;
+  /** token_b mint */
+  mintB: web3.PublicKey;
+  /** Pool token program id, owning the pool mint */
+  poolTokenProgram: web3.PublicKey;
+  /** token_a program id, owning the token_a mint */
+  tokenProgramA: web3.PublicKey;
+  /** token_b program id, owning the token_b mint */
+  tokenProgramB: web3.PublicKey;
   anchorRemainingAccounts?: web3.AccountMeta[];
 };
 
@@ -59,7 +67,7 @@ export function createWithdrawAllTokenTypesInstruction(
   const keys: web3.AccountMeta[] = [
     {
       pubkey: accounts.tokenSwap,
-      isWritable: false,
+      isWritable: true,
       isSigner: false,
     },
     {
@@ -70,7 +78,7 @@ export function createWithdrawAllTokenTypesInstruction(
     {
       pubkey: accounts.userTransferAuthority,
       isWritable: false,
-      isSigner: false,
+      isSigner: true,
     },
     {
       pubkey: accounts.poolMint,
@@ -108,7 +116,27 @@ export function createWithdrawAllTokenTypesInstruction(
       isSigner: false,
     },
     {
-      pubkey: accounts.tokenProgram,
+      pubkey: accounts.mintA,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.mintB,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.poolTokenProgram,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.tokenProgramA,
+      isWritable: false,
+      isSigner: false,
+    },
+    {
+      pubkey: accounts.tokenProgramB,
       isWritable: false,
       isSigner: false,
     },
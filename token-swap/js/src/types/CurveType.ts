@@ -10,6 +10,7 @@ export enum CurveType {
   ConstantPrice,
   Stable,
   Offset,
+  Weighted,
 }
 
 /**
@@ -0,0 +1,173 @@
+import test from 'tape';
+import * as web3 from '@bbachain/web3.js';
+import BN from 'bn.js';
+import {
+  createDepositAllTokenTypesInstruction,
+  createSwapInstruction,
+  createWithdrawAllTokenTypesInstruction,
+} from '../src';
+
+// The account order of every instruction must match the program's
+// instruction.rs, with one token program per token
+
+function keys<T>(accounts: T): { [K in keyof T]: web3.PublicKey } {
+  const result = {} as { [K in keyof T]: web3.PublicKey };
+  for (const name of Object.keys(accounts) as (keyof T)[]) {
+    result[name] = web3.Keypair.generate().publicKey;
+  }
+  return result;
+}
+
+function layout(ix: web3.TransactionInstruction) {
+  return ix.keys.map(({ pubkey, isWritable, isSigner }) => ({
+    pubkey: pubkey.toBase58(),
+    isWritable,
+    isSigner,
+  }));
+}
+
+function expected(
+  accounts: { [name: string]: web3.PublicKey },
+  order: [string, boolean, boolean][]
+) {
+  return order.map(([name, isWritable, isSigner]) => ({
+    pubkey: accounts[name].toBase58(),
+    isWritable,
+    isSigner,
+  }));
+}
+
+test('swap: account layout', (t) => {
+  const accounts = keys({
+    tokenSwap: null,
+    authority: null,
+    userTransferAuthority: null,
+    source: null,
+    swapSource: null,
+    swapDestination: null,
+    destination: null,
+    poolMint: null,
+    feeAccount: null,
+    sourceMint: null,
+    destinationMint: null,
+    sourceTokenProgram: null,
+    destinationTokenProgram: null,
+    poolTokenProgram: null,
+    hostFeeAccount: null,
+  });
+  const ix = createSwapInstruction(accounts, {
+    amountIn: new BN(1),
+    minimumAmountOut: new BN(0),
+  });
+  t.deepEqual(
+    layout(ix),
+    expected(accounts, [
+      ['tokenSwap', true, false],
+      ['authority', false, false],
+      ['userTransferAuthority', false, true],
+      ['source', true, false],
+      ['swapSource', true, false],
+      ['swapDestination', true, false],
+      ['destination', true, false],
+      ['poolMint', true, false],
+      ['feeAccount', true, false],
+      ['sourceMint', false, false],
+      ['destinationMint', false, false],
+      ['sourceTokenProgram', false, false],
+      ['destinationTokenProgram', false, false],
+      ['poolTokenProgram', false, false],
+      ['hostFeeAccount', true, false],
+    ])
+  );
+  t.end();
+});
+
+test('depositAllTokenTypes: account layout', (t) => {
+  const accounts = keys({
+    tokenSwap: null,
+    authority: null,
+    userTransferAuthority: null,
+    sourceA: null,
+    sourceB: null,
+    intoA: null,
+    intoB: null,
+    poolMint: null,
+    poolAccount: null,
+    mintA: null,
+    mintB: null,
+    tokenProgramA: null,
+    tokenProgramB: null,
+    poolTokenProgram: null,
+  });
+  const ix = createDepositAllTokenTypesInstruction(accounts, {
+    poolTokenAmount: new BN(1),
+    maximumTokenAAmount: new BN(1),
+    maximumTokenBAmount: new BN(1),
+  });
+  t.deepEqual(
+    layout(ix),
+    expected(accounts, [
+      ['tokenSwap', true, false],
+      ['authority', false, false],
+      ['userTransferAuthority', false, true],
+      ['sourceA', true, false],
+      ['sourceB', true, false],
+      ['intoA', true, false],
+      ['intoB', true, false],
+      ['poolMint', true, false],
+      ['poolAccount', true, false],
+      ['mintA', false, false],
+      ['mintB', false, false],
+      ['tokenProgramA', false, false],
+      ['tokenProgramB', false, false],
+      ['poolTokenProgram', false, false],
+    ])
+  );
+  t.end();
+});
+
+test('withdrawAllTokenTypes: account layout', (t) => {
+  const accounts = keys({
+    tokenSwap: null,
+    authority: null,
+    userTransferAuthority: null,
+    poolMint: null,
+    source: null,
+    fromA: null,
+    fromB: null,
+    userAccountA: null,
+    userAccountB: null,
+    feeAccount: null,
+    mintA: null,
+    mintB: null,
+    poolTokenProgram: null,
+    tokenProgramA: null,
+    tokenProgramB: null,
+  });
+  const ix = createWithdrawAllTokenTypesInstruction(accounts, {
+    poolTokenAmount: new BN(1),
+    minimumTokenAAmount: new BN(0),
+    minimumTokenBAmount: new BN(0),
+  });
+  t.deepEqual(
+    layout(ix),
+    expected(accounts, [
+      ['tokenSwap', true, false],
+      ['authority', false, false],
+      ['userTransferAuthority', false, true],
+      ['poolMint', true, false],
+      ['source', true, false],
+      ['fromA', true, false],
+      ['fromB', true, false],
+      ['userAccountA', true, false],
+      ['userAccountB', true, false],
+      ['feeAccount', true, false],
+      ['mintA', false, false],
+      ['mintB', false, false],
+      ['poolTokenProgram', false, false],
+      ['tokenProgramA', false, false],
+      ['tokenProgramB', false, false],
+    ])
+  );
+  t.end();
+});
@@ -14,11 +14,12 @@ use {
             fees::Fees,
             offset::OffsetCurve,
             stable::StableCurve,
+            weighted::WeightedCurve,
         },
         error::SwapError,
         instruction::{
-            DepositAllTokenTypes, DepositSingleTokenTypeExactAmountIn, Swap, WithdrawAllTokenTypes,
-            WithdrawSingleTokenTypeExactAmountOut,
+            DepositAllTokenTypes, DepositSingleTokenTypeExactAmountIn, Swap, SwapExactAmountOut,
+            WithdrawAllTokenTypes, WithdrawSingleTokenTypeExactAmountOut,
         },
     },
     spl_token_swap_fuzz::{
@@ -43,6 +44,12 @@ enum FuzzInstruction {
         trade_direction: TradeDirection,
         instruction: Swap,
     },
+    SwapExactAmountOut {
+        token_a_id: AccountId,
+        token_b_id: AccountId,
+        trade_direction: TradeDirection,
+        instruction: SwapExactAmountOut,
+    },
     DepositAllTokenTypes {
         token_a_id: AccountId,
         token_b_id: AccountId,
@@ -126,6 +133,12 @@ fn run_fuzz(fuzz_data: FuzzData) {
                 ..
             } => (Some(token_a_id), Some(token_b_id), None),
 
+            FuzzInstruction::SwapExactAmountOut {
+                token_a_id,
+                token_b_id,
+                ..
+            } => (Some(token_a_id), Some(token_b_id), None),
+
             FuzzInstruction::DepositAllTokenTypes {
                 token_a_id,
                 token_b_id,
@@ -309,6 +322,27 @@ fn run_fuzz_instruction(
                 }
             }
         }
+        FuzzInstruction::SwapExactAmountOut {
+            token_a_id,
+            token_b_id,
+            trade_direction,
+            instruction,
+        } => {
+            let token_a_account = token_a_accounts.get_mut(&token_a_id).unwrap();
+            let token_b_account = token_b_accounts.get_mut(&token_b_id).unwrap();
+            match trade_direction {
+                TradeDirection::AtoB => token_swap.swap_exact_amount_out_a_to_b(
+                    token_a_account,
+                    token_b_account,
+                    instruction,
+                ),
+                TradeDirection::BtoA => token_swap.swap_exact_amount_out_b_to_a(
+                    token_b_account,
+                    token_a_account,
+                    instruction,
+                ),
+            }
+        }
         FuzzInstruction::DepositAllTokenTypes {
             token_a_id,
             token_b_id,
@@ -398,6 +432,9 @@ fn get_total_token_a_amount(fuzz_instructions: &[FuzzInstruction]) -> u64 {
     for fuzz_instruction in fuzz_instructions.iter() {
         match fuzz_instruction {
             FuzzInstruction::Swap { token_a_id, .. } => token_a_ids.insert(token_a_id),
+            FuzzInstruction::SwapExactAmountOut { token_a_id, .. } => {
+                token_a_ids.insert(token_a_id)
+            }
             FuzzInstruction::DepositAllTokenTypes { token_a_id, .. } => {
                 token_a_ids.insert(token_a_id)
             }
@@ -430,6 +467,9 @@ fn get_total_token_b_amount(fuzz_instructions: &[FuzzInstruction]) -> u64 {
     for fuzz_instruction in fuzz_instructions.iter() {
         match fuzz_instruction {
             FuzzInstruction::Swap { token_b_id, .. } => token_b_ids.insert(token_b_id),
+            FuzzInstruction::SwapExactAmountOut { token_b_id, .. } => {
+                token_b_ids.insert(token_b_id)
+            }
             FuzzInstruction::DepositAllTokenTypes { token_b_id, .. } => {
                 token_b_ids.insert(token_b_id)
             }
@@ -469,6 +509,10 @@ fn get_swap_curve(curve_type: CurveType) -> SwapCurve {
             CurveType::Offset => Arc::new(OffsetCurve {
                 token_b_offset: 100_000_000_000,
             }),
+            CurveType::Weighted => Arc::new(WeightedCurve {
+                token_a_weight: 80,
+                token_b_weight: 20,
+            }),
         },
     }
 }
@@ -7,7 +7,7 @@ use crate::native_token;
 use spl_token_swap::{
     curve::{base::SwapCurve, fees::Fees},
     instruction::{
-        self, DepositAllTokenTypes, DepositSingleTokenTypeExactAmountIn, Swap,
+        self, DepositAllTokenTypes, DepositSingleTokenTypeExactAmountIn, Swap, SwapExactAmountOut,
         WithdrawAllTokenTypes, WithdrawSingleTokenTypeExactAmountOut,
     },
     state::SwapVersion,
@@ -172,6 +172,8 @@ impl NativeTokenSwap {
         let swap_instruction = instruction::swap(
             &spl_token_swap::id(),
             &spl_token::id(),
+            &spl_token::id(),
+            &spl_token::id(),
             &self.swap_account.key,
             &self.authority_account.key,
             &user_transfer_account.key,
@@ -181,11 +183,15 @@ impl NativeTokenSwap {
             &token_b_account.key,
             &self.pool_mint_account.key,
             &self.pool_fee_account.key,
+            &self.token_a_mint_account.key,
+            &self.token_b_mint_account.key,
             Some(&self.pool_token_account.key),
             instruction,
         )
         .unwrap();
 
+        let token_program_info = self.token_program_account.as_account_info();
+
         do_process_instruction(
             swap_instruction,
             &[
@@ -198,7 +204,11 @@ impl NativeTokenSwap {
                 token_b_account.as_account_info(),
                 self.pool_mint_account.as_account_info(),
                 self.pool_fee_account.as_account_info(),
-                self.token_program_account.as_account_info(),
+                self.token_a_mint_account.as_account_info(),
+                self.token_b_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info.clone(),
+                token_program_info,
                 self.pool_token_account.as_account_info(),
             ],
         )
@@ -233,6 +243,8 @@ impl NativeTokenSwap {
         let swap_instruction = instruction::swap(
             &spl_token_swap::id(),
             &spl_token::id(),
+            &spl_token::id(),
+            &spl_token::id(),
             &self.swap_account.key,
             &self.authority_account.key,
             &user_transfer_account.key,
@@ -242,11 +254,15 @@ impl NativeTokenSwap {
             &token_a_account.key,
             &self.pool_mint_account.key,
             &self.pool_fee_account.key,
+            &self.token_b_mint_account.key,
+            &self.token_a_mint_account.key,
             Some(&self.pool_token_account.key),
             instruction,
         )
         .unwrap();
 
+        let token_program_info = self.token_program_account.as_account_info();
+
         do_process_instruction(
             swap_instruction,
             &[
@@ -259,7 +275,152 @@ impl NativeTokenSwap {
                 token_a_account.as_account_info(),
                 self.pool_mint_account.as_account_info(),
                 self.pool_fee_account.as_account_info(),
-                self.token_program_account.as_account_info(),
+                self.token_b_mint_account.as_account_info(),
+                self.token_a_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info.clone(),
+                token_program_info,
+                self.pool_token_account.as_account_info(),
+            ],
+        )
+    }
+
+    pub fn swap_exact_amount_out_a_to_b(
+        &mut self,
+        token_a_account: &mut NativeAccountData,
+        token_b_account: &mut NativeAccountData,
+        instruction: SwapExactAmountOut,
+    ) -> ProgramResult {
+        let mut user_transfer_account = NativeAccountData::new(0, system_program::id());
+        user_transfer_account.is_signer = true;
+        do_process_instruction(
+            approve(
+                &self.token_program_account.key,
+                &token_a_account.key,
+                &user_transfer_account.key,
+                &self.user_account.key,
+                &[],
+                instruction.maximum_amount_in,
+            )
+            .unwrap(),
+            &[
+                token_a_account.as_account_info(),
+                user_transfer_account.as_account_info(),
+                self.user_account.as_account_info(),
+            ],
+        )
+        .unwrap();
+        let swap_instruction = instruction::swap_exact_amount_out(
+            &spl_token_swap::id(),
+            &spl_token::id(),
+            &spl_token::id(),
+            &spl_token::id(),
+            &self.swap_account.key,
+            &self.authority_account.key,
+            &user_transfer_account.key,
+            &token_a_account.key,
+            &self.token_a_account.key,
+            &self.token_b_account.key,
+            &token_b_account.key,
+            &self.pool_mint_account.key,
+            &self.pool_fee_account.key,
+            &self.token_a_mint_account.key,
+            &self.token_b_mint_account.key,
+            Some(&self.pool_token_account.key),
+            instruction,
+        )
+        .unwrap();
+
+        let token_program_info = self.token_program_account.as_account_info();
+
+        do_process_instruction(
+            swap_instruction,
+            &[
+                self.swap_account.as_account_info(),
+                self.authority_account.as_account_info(),
+                user_transfer_account.as_account_info(),
+                token_a_account.as_account_info(),
+                self.token_a_account.as_account_info(),
+                self.token_b_account.as_account_info(),
+                token_b_account.as_account_info(),
+                self.pool_mint_account.as_account_info(),
+                self.pool_fee_account.as_account_info(),
+                self.token_a_mint_account.as_account_info(),
+                self.token_b_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info.clone(),
+                token_program_info,
+                self.pool_token_account.as_account_info(),
+            ],
+        )
+    }
+
+    pub fn swap_exact_amount_out_b_to_a(
+        &mut self,
+        token_b_account: &mut NativeAccountData,
+        token_a_account: &mut NativeAccountData,
+        instruction: SwapExactAmountOut,
+    ) -> ProgramResult {
+        let mut user_transfer_account = NativeAccountData::new(0, system_program::id());
+        user_transfer_account.is_signer = true;
+        do_process_instruction(
+            approve(
+                &self.token_program_account.key,
+                &token_b_account.key,
+                &user_transfer_account.key,
+                &self.user_account.key,
+                &[],
+                instruction.maximum_amount_in,
+            )
+            .unwrap(),
+            &[
+                token_b_account.as_account_info(),
+                user_transfer_account.as_account_info(),
+                self.user_account.as_account_info(),
+            ],
+        )
+        .unwrap();
+
+        let swap_instruction = instruction::swap_exact_amount_out(
+            &spl_token_swap::id(),
+            &spl_token::id(),
+            &spl_token::id(),
+            &spl_token::id(),
+            &self.swap_account.key,
+            &self.authority_account.key,
+            &user_transfer_account.key,
+            &token_b_account.key,
+            &self.token_b_account.key,
+            &self.token_a_account.key,
+            &token_a_account.key,
+            &self.pool_mint_account.key,
+            &self.pool_fee_account.key,
+            &self.token_b_mint_account.key,
+            &self.token_a_mint_account.key,
+            Some(&self.pool_token_account.key),
+            instruction,
+        )
+        .unwrap();
+
+        let token_program_info = self.token_program_account.as_account_info();
+
+        do_process_instruction(
+            swap_instruction,
+            &[
+                self.swap_account.as_account_info(),
+                self.authority_account.as_account_info(),
+                user_transfer_account.as_account_info(),
+                token_b_account.as_account_info(),
+                self.token_b_account.as_account_info(),
+                self.token_a_account.as_account_info(),
+                token_a_account.as_account_info(),
+                self.pool_mint_account.as_account_info(),
+                self.pool_fee_account.as_account_info(),
+                self.token_b_mint_account.as_account_info(),
+                self.token_a_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info.clone(),
+                token_program_info,
                 self.pool_token_account.as_account_info(),
             ],
         )
@@ -319,6 +480,8 @@ impl NativeTokenSwap {
         let deposit_instruction = instruction::deposit_all_token_types(
             &spl_token_swap::id(),
             &spl_token::id(),
+            &spl_token::id(),
+            &spl_token::id(),
             &self.swap_account.key,
             &self.authority_account.key,
             &user_transfer_account.key,
@@ -328,10 +491,13 @@ impl NativeTokenSwap {
             &self.token_b_account.key,
             &self.pool_mint_account.key,
             &pool_account.key,
+            &self.token_a_mint_account.key,
+            &self.token_b_mint_account.key,
             instruction,
         )
         .unwrap();
 
+        let token_program_info = self.token_program_account.as_account_info();
         do_process_instruction(
             deposit_instruction,
             &[
@@ -344,7 +510,11 @@ impl NativeTokenSwap {
                 self.token_b_account.as_account_info(),
                 self.pool_mint_account.as_account_info(),
                 pool_account.as_account_info(),
-                self.token_program_account.as_account_info(),
+                self.token_a_mint_account.as_account_info(),
+                self.token_b_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info.clone(),
+                token_program_info,
             ],
         )
     }
@@ -385,6 +555,8 @@ impl NativeTokenSwap {
         let withdraw_instruction = instruction::withdraw_all_token_types(
             &spl_token_swap::id(),
             &spl_token::id(),
+            &spl_token::id(),
+            &spl_token::id(),
             &self.swap_account.key,
             &self.authority_account.key,
             &user_transfer_account.key,
@@ -395,10 +567,13 @@ impl NativeTokenSwap {
             &self.token_b_account.key,
             &token_a_account.key,
             &token_b_account.key,
+            &self.token_a_mint_account.key,
+            &self.token_b_mint_account.key,
             instruction,
         )
         .unwrap();
 
+        let token_program_info = self.token_program_account.as_account_info();
         do_process_instruction(
             withdraw_instruction,
             &[
@@ -412,7 +587,11 @@ impl NativeTokenSwap {
                 token_a_account.as_account_info(),
                 token_b_account.as_account_info(),
                 self.pool_fee_account.as_account_info(),
-                self.token_program_account.as_account_info(),
+                self.token_a_mint_account.as_account_info(),
+                self.token_b_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info.clone(),
+                token_program_info,
             ],
         )
     }
@@ -449,9 +628,16 @@ impl NativeTokenSwap {
             instruction.minimum_pool_token_amount = 2;
         }
 
+        let source_mint_key = native_token::get_token_mint(source_token_account);
+        let source_mint_account = if source_mint_key == self.token_b_mint_account.key {
+            &mut self.token_b_mint_account
+        } else {
+            &mut self.token_a_mint_account
+        };
         let deposit_instruction = instruction::deposit_single_token_type_exact_amount_in(
             &spl_token_swap::id(),
             &spl_token::id(),
+            &spl_token::id(),
             &self.swap_account.key,
             &self.authority_account.key,
             &user_transfer_account.key,
@@ -460,10 +646,12 @@ impl NativeTokenSwap {
             &self.token_b_account.key,
             &self.pool_mint_account.key,
             &pool_account.key,
+            &source_mint_account.key,
             instruction,
         )
         .unwrap();
 
+        let token_program_info = self.token_program_account.as_account_info();
         do_process_instruction(
             deposit_instruction,
             &[
@@ -475,7 +663,9 @@ impl NativeTokenSwap {
                 self.token_b_account.as_account_info(),
                 self.pool_mint_account.as_account_info(),
                 pool_account.as_account_info(),
-                self.token_program_account.as_account_info(),
+                source_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info,
             ],
         )
     }
@@ -512,9 +702,16 @@ impl NativeTokenSwap {
         )
         .unwrap();
 
+        let destination_mint_key = native_token::get_token_mint(destination_token_account);
+        let destination_mint_account = if destination_mint_key == self.token_b_mint_account.key {
+            &mut self.token_b_mint_account
+        } else {
+            &mut self.token_a_mint_account
+        };
         let withdraw_instruction = instruction::withdraw_single_token_type_exact_amount_out(
             &spl_token_swap::id(),
             &spl_token::id(),
+            &spl_token::id(),
             &self.swap_account.key,
             &self.authority_account.key,
             &user_transfer_account.key,
@@ -524,10 +721,12 @@ impl NativeTokenSwap {
             &self.token_a_account.key,
             &self.token_b_account.key,
             &destination_token_account.key,
+            &destination_mint_account.key,
             instruction,
         )
         .unwrap();
 
+        let token_program_info = self.token_program_account.as_account_info();
         do_process_instruction(
             withdraw_instruction,
             &[
@@ -540,7 +739,9 @@ impl NativeTokenSwap {
                 self.token_b_account.as_account_info(),
                 destination_token_account.as_account_info(),
                 self.pool_fee_account.as_account_info(),
-                self.token_program_account.as_account_info(),
+                destination_mint_account.as_account_info(),
+                token_program_info.clone(),
+                token_program_info,
             ],
         )
     }
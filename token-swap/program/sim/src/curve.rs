@@ -0,0 +1,479 @@
+//! Reference models of every two-token curve of the swap program
+
+use {
+    crate::{
+        math::{exp, ln, pow, ratio, sqrt},
+        stable::StableSwapModel,
+    },
+    num_bigint::BigInt,
+    num_rational::BigRational,
+    num_traits::{One, Zero},
+};
+
+/// The direction of a trade, mirroring the program's `TradeDirection`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TradeDirection {
+    /// Input token A, output token B
+    AtoB,
+    /// Input token B, output token A
+    BtoA,
+}
+
+/// Exact model of a curve, with the same parameters as the program's curve
+/// of the same name.
+///
+/// Every operation returns the exact amount given by the curve's invariant,
+/// without any rounding, or to `PRECISION_BITS` bits when the invariant
+/// requires roots or powers.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CurveModel {
+    /// Uniswap-style constant product curve, x * y = k
+    ConstantProduct,
+    /// Flat line, always trading 1 token B for `token_b_price` token A
+    ConstantPrice {
+        /// Amount of token A required to get 1 token B
+        token_b_price: u64,
+    },
+    /// Stableswap curve
+    Stable {
+        /// Amplification coefficient
+        amp: u64,
+    },
+    /// Constant product curve with an offset added to token B
+    Offset {
+        /// Amount to offset the token B liquidity account
+        token_b_offset: u64,
+    },
+    /// Balancer-style weighted curve
+    Weighted {
+        /// Weight of token A, relative to token B
+        token_a_weight: u64,
+        /// Weight of token B, relative to token A
+        token_b_weight: u64,
+    },
+}
+
+/// Destination amount received by a weighted swap:
+///
+/// swap_destination * (1 - (swap_source / (swap_source + source)) ^ (w_source / w_destination))
+fn weighted_swap(
+    source_amount: &BigRational,
+    swap_source_amount: &BigRational,
+    swap_destination_amount: &BigRational,
+    source_weight: u64,
+    destination_weight: u64,
+) -> BigRational {
+    let exponent = BigRational::new(source_weight.into(), destination_weight.into());
+    let base = swap_source_amount / (swap_source_amount + source_amount);
+    swap_destination_amount * (BigRational::one() - pow(&base, &exponent))
+}
+
+/// Source amount needed by a weighted swap:
+///
+/// swap_source * ((swap_destination / (swap_destination - destination)) ^ (w_destination / w_source) - 1)
+fn weighted_swap_exact_out(
+    destination_amount: &BigRational,
+    swap_source_amount: &BigRational,
+    swap_destination_amount: &BigRational,
+    source_weight: u64,
+    destination_weight: u64,
+) -> BigRational {
+    let exponent = BigRational::new(destination_weight.into(), source_weight.into());
+    let base = swap_destination_amount / (swap_destination_amount - destination_amount);
+    swap_source_amount * (pow(&base, &exponent) - BigRational::one())
+}
+
+impl CurveModel {
+    /// The (token A, token B) reserves as seen by the invariant
+    fn invariant_reserves(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> (BigRational, BigRational) {
+        let token_b_offset = match self {
+            CurveModel::Offset { token_b_offset } => *token_b_offset as u128,
+            _ => 0,
+        };
+        (
+            ratio(swap_token_a_amount),
+            ratio(swap_token_b_amount) + ratio(token_b_offset),
+        )
+    }
+
+    /// The (source, destination) reserves as seen by the invariant
+    fn invariant_swap_reserves(
+        &self,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> (BigRational, BigRational) {
+        match trade_direction {
+            TradeDirection::AtoB => {
+                self.invariant_reserves(swap_source_amount, swap_destination_amount)
+            }
+            TradeDirection::BtoA => {
+                let (destination, source) =
+                    self.invariant_reserves(swap_destination_amount, swap_source_amount);
+                (source, destination)
+            }
+        }
+    }
+
+    /// The (source, destination) weights of a weighted curve
+    fn weights(&self, trade_direction: TradeDirection) -> (u64, u64) {
+        match (self, trade_direction) {
+            (
+                CurveModel::Weighted {
+                    token_a_weight,
+                    token_b_weight,
+                },
+                TradeDirection::AtoB,
+            ) => (*token_a_weight, *token_b_weight),
+            (
+                CurveModel::Weighted {
+                    token_a_weight,
+                    token_b_weight,
+                },
+                TradeDirection::BtoA,
+            ) => (*token_b_weight, *token_a_weight),
+            _ => (1, 1),
+        }
+    }
+
+    /// The value of the whole pool in token A, for the constant price curve
+    fn constant_price_value(
+        token_b_price: u64,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> BigRational {
+        ratio(swap_token_a_amount) + ratio(swap_token_b_amount) * ratio(token_b_price as u128)
+    }
+
+    /// Amount of destination token received for `source_amount`
+    pub fn swap(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> BigRational {
+        match self {
+            CurveModel::ConstantPrice { token_b_price } => match trade_direction {
+                TradeDirection::AtoB => {
+                    BigRational::new(source_amount.into(), (*token_b_price).into())
+                }
+                TradeDirection::BtoA => ratio(source_amount) * ratio(*token_b_price as u128),
+            },
+            CurveModel::Stable { amp } => {
+                StableSwapModel::new(*amp, vec![swap_source_amount, swap_destination_amount])
+                    .exchange(0, 1, source_amount)
+            }
+            _ => {
+                let (source_weight, destination_weight) = self.weights(trade_direction);
+                let (swap_source_amount, swap_destination_amount) = self.invariant_swap_reserves(
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                );
+                if source_weight == destination_weight {
+                    let source_amount = ratio(source_amount);
+                    &swap_destination_amount * &source_amount / (swap_source_amount + source_amount)
+                } else {
+                    weighted_swap(
+                        &ratio(source_amount),
+                        &swap_source_amount,
+                        &swap_destination_amount,
+                        source_weight,
+                        destination_weight,
+                    )
+                }
+            }
+        }
+    }
+
+    /// Amount of source token needed to receive `destination_amount`
+    pub fn swap_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> BigRational {
+        match self {
+            CurveModel::ConstantPrice { token_b_price } => match trade_direction {
+                TradeDirection::AtoB => ratio(destination_amount) * ratio(*token_b_price as u128),
+                TradeDirection::BtoA => {
+                    BigRational::new(destination_amount.into(), (*token_b_price).into())
+                }
+            },
+            CurveModel::Stable { amp } => {
+                StableSwapModel::new(*amp, vec![swap_source_amount, swap_destination_amount])
+                    .exchange_exact_out(0, 1, destination_amount)
+            }
+            _ => {
+                let (source_weight, destination_weight) = self.weights(trade_direction);
+                let (swap_source_amount, swap_destination_amount) = self.invariant_swap_reserves(
+                    swap_source_amount,
+                    swap_destination_amount,
+                    trade_direction,
+                );
+                if source_weight == destination_weight {
+                    let destination_amount = ratio(destination_amount);
+                    &swap_source_amount * &destination_amount
+                        / (swap_destination_amount - destination_amount)
+                } else {
+                    weighted_swap_exact_out(
+                        &ratio(destination_amount),
+                        &swap_source_amount,
+                        &swap_destination_amount,
+                        source_weight,
+                        destination_weight,
+                    )
+                }
+            }
+        }
+    }
+
+    /// Amounts of (token A, token B) worth `pool_tokens`
+    ///
+    /// The constant price curve values the share of the pool entirely in
+    /// token A, or entirely in token B, valuing the pool at half of its
+    /// reserves like the program.
+    pub fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> (BigRational, BigRational) {
+        let share = BigRational::new(pool_tokens.into(), pool_token_supply.into());
+        match self {
+            CurveModel::ConstantPrice { token_b_price } => {
+                let value = Self::constant_price_value(
+                    *token_b_price,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                ) * share
+                    / BigInt::from(2);
+                let token_b_amount = &value / BigInt::from(*token_b_price);
+                (value, token_b_amount)
+            }
+            _ => {
+                let (token_a_amount, token_b_amount) =
+                    self.invariant_reserves(swap_token_a_amount, swap_token_b_amount);
+                (token_a_amount * &share, token_b_amount * share)
+            }
+        }
+    }
+
+    /// Pool tokens minted for depositing `source_amount` of one token
+    pub fn deposit_single_token_type(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> BigRational {
+        self.single_token_pool_tokens(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            true,
+        )
+    }
+
+    /// Pool tokens burned for withdrawing `source_amount` of one token
+    pub fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> BigRational {
+        self.single_token_pool_tokens(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            false,
+        )
+    }
+
+    fn single_token_pool_tokens(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        deposit: bool,
+    ) -> BigRational {
+        match self {
+            CurveModel::ConstantPrice { token_b_price } => {
+                let given_value = match trade_direction {
+                    TradeDirection::AtoB => ratio(source_amount),
+                    TradeDirection::BtoA => ratio(source_amount) * ratio(*token_b_price as u128),
+                };
+                ratio(pool_supply) * given_value
+                    / Self::constant_price_value(
+                        *token_b_price,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                    )
+            }
+            CurveModel::Stable { amp } => {
+                let model =
+                    StableSwapModel::new(*amp, vec![swap_token_a_amount, swap_token_b_amount]);
+                let i = match trade_direction {
+                    TradeDirection::AtoB => 0,
+                    TradeDirection::BtoA => 1,
+                };
+                if deposit {
+                    model.deposit_one(i, source_amount, pool_supply)
+                } else {
+                    model.withdraw_one_exact_out(i, source_amount, pool_supply)
+                }
+            }
+            _ => {
+                // The Balancer single asset formulas, with the source weight
+                // normalized by the total weight
+                let (source_weight, destination_weight) = self.weights(trade_direction);
+                let (token_a_amount, token_b_amount) =
+                    self.invariant_reserves(swap_token_a_amount, swap_token_b_amount);
+                let swap_source_amount = match trade_direction {
+                    TradeDirection::AtoB => token_a_amount,
+                    TradeDirection::BtoA => token_b_amount,
+                };
+                let source_amount = ratio(source_amount);
+                let exponent = BigRational::new(
+                    source_weight.into(),
+                    (source_weight + destination_weight).into(),
+                );
+                let growth = if deposit {
+                    (&swap_source_amount + source_amount) / swap_source_amount
+                } else {
+                    (&swap_source_amount - source_amount) / swap_source_amount
+                };
+                let power = if source_weight == destination_weight {
+                    sqrt(&growth)
+                } else {
+                    pow(&growth, &exponent)
+                };
+                if deposit {
+                    ratio(pool_supply) * (power - BigRational::one())
+                } else {
+                    ratio(pool_supply) * (BigRational::one() - power)
+                }
+            }
+        }
+    }
+
+    /// The value of the pool used to check that its value never decreases,
+    /// matching `CurveCalculator::normalized_value`
+    pub fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> BigRational {
+        match self {
+            CurveModel::ConstantPrice { token_b_price } => {
+                Self::constant_price_value(*token_b_price, swap_token_a_amount, swap_token_b_amount)
+                    / BigInt::from(2)
+            }
+            CurveModel::Stable { amp } => {
+                StableSwapModel::new(*amp, vec![swap_token_a_amount, swap_token_b_amount])
+                    .compute_d()
+            }
+            CurveModel::Weighted {
+                token_a_weight,
+                token_b_weight,
+            } => {
+                if swap_token_a_amount == 0 || swap_token_b_amount == 0 {
+                    return BigRational::zero();
+                }
+                let total_weight =
+                    BigRational::from_integer((token_a_weight + token_b_weight).into());
+                exp(
+                    &((ln(&ratio(swap_token_a_amount)) * BigInt::from(*token_a_weight)
+                        + ln(&ratio(swap_token_b_amount)) * BigInt::from(*token_b_weight))
+                        / total_weight),
+                )
+            }
+            _ => {
+                let (token_a_amount, token_b_amount) =
+                    self.invariant_reserves(swap_token_a_amount, swap_token_b_amount);
+                sqrt(&(token_a_amount * token_b_amount))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_product_swaps() {
+        let model = CurveModel::ConstantProduct;
+        assert_eq!(
+            model.swap(1_000, 1_000, 3_000, TradeDirection::AtoB),
+            ratio(1_500)
+        );
+        assert_eq!(
+            model.swap_exact_out(1_500, 1_000, 3_000, TradeDirection::AtoB),
+            ratio(1_000)
+        );
+        assert_eq!(model.normalized_value(1_000, 4_000), ratio(2_000));
+    }
+
+    #[test]
+    fn offset_adds_to_token_b() {
+        let model = CurveModel::Offset {
+            token_b_offset: 2_000,
+        };
+        assert_eq!(
+            model.swap(1_000, 1_000, 1_000, TradeDirection::AtoB),
+            ratio(1_500)
+        );
+        assert_eq!(
+            model.swap(1_000, 1_000, 1_000, TradeDirection::BtoA),
+            BigRational::new(1_000.into(), 4.into())
+        );
+    }
+
+    #[test]
+    fn balanced_weights_match_constant_product() {
+        let weighted = CurveModel::Weighted {
+            token_a_weight: 80,
+            token_b_weight: 80,
+        };
+        let model = CurveModel::ConstantProduct;
+        assert_eq!(
+            weighted.swap(1_234, 56_789, 98_765, TradeDirection::BtoA),
+            model.swap(1_234, 56_789, 98_765, TradeDirection::BtoA)
+        );
+        assert_eq!(
+            weighted.deposit_single_token_type(1_234, 56_789, 98_765, 1_000, TradeDirection::AtoB),
+            model.deposit_single_token_type(1_234, 56_789, 98_765, 1_000, TradeDirection::AtoB)
+        );
+    }
+
+    #[test]
+    fn weighted_swap_round_trip() {
+        let model = CurveModel::Weighted {
+            token_a_weight: 80,
+            token_b_weight: 20,
+        };
+        // 80/20 pools trade like constant product with token A counted 4 times
+        let received = model.swap(1_000_000, 1_000_000, 1_000_000, TradeDirection::AtoB);
+        let expected = ratio(1_000_000) * BigRational::new(15.into(), 16.into());
+        let difference = &received - &expected;
+        assert!(difference < BigRational::new(1.into(), 1_000_000_000.into()));
+        assert!(-difference < BigRational::new(1.into(), 1_000_000_000.into()));
+    }
+}
@@ -0,0 +1,190 @@
+//! High-precision approximations of the irrational functions used by the
+//! curves, on top of exact big rationals
+
+use {
+    num_bigint::BigInt,
+    num_integer::Integer,
+    num_rational::BigRational,
+    num_traits::{One, Signed, ToPrimitive, Zero},
+};
+
+/// Bits kept after the binary point by every approximation, so that results
+/// are exact to far below a single token even for `u128` amounts
+pub const PRECISION_BITS: usize = 256;
+
+/// Extra bits carried through the series expansions, absorbing the rounding
+/// of each term
+const GUARD_BITS: usize = 64;
+
+/// Total bits after the binary point used inside the series expansions
+const WORKING_BITS: usize = PRECISION_BITS + GUARD_BITS;
+
+/// Convert an integer amount into a rational
+pub fn ratio(amount: u128) -> BigRational {
+    BigRational::from_integer(BigInt::from(amount))
+}
+
+/// Round a rational down to a multiple of 2 ^ -bits, returning the numerator
+fn to_fixed(value: &BigRational, bits: usize) -> BigInt {
+    (value.numer() << bits).div_floor(value.denom())
+}
+
+/// The rational represented by a numerator over 2 ^ bits
+fn from_fixed(value: BigInt, bits: usize) -> BigRational {
+    BigRational::new(value, BigInt::one() << bits)
+}
+
+/// Multiply by 2 ^ exponent, for any sign of exponent
+fn scale_by_power_of_two(value: BigRational, exponent: i64) -> BigRational {
+    let power = BigInt::one() << exponent.unsigned_abs();
+    if exponent < 0 {
+        value / power
+    } else {
+        value * power
+    }
+}
+
+/// Round a rational down to `PRECISION_BITS` bits after the binary point,
+/// keeping the numerators and denominators of iterative algorithms small
+pub fn truncate(value: &BigRational) -> BigRational {
+    from_fixed(to_fixed(value, PRECISION_BITS), PRECISION_BITS)
+}
+
+/// Square root, rounded down to `PRECISION_BITS` bits after the binary point
+pub fn sqrt(value: &BigRational) -> BigRational {
+    assert!(!value.is_negative(), "square root of negative number");
+    from_fixed(to_fixed(value, 2 * PRECISION_BITS).sqrt(), PRECISION_BITS)
+}
+
+/// atanh(t) = t + t^3 / 3 + t^5 / 5 + ..., with t in [0, 1/3] given and
+/// returned in `WORKING_BITS` fixed point
+fn atanh(t: &BigInt) -> BigInt {
+    let t_squared = (t * t) >> WORKING_BITS;
+    let mut power = t.clone();
+    let mut sum = BigInt::zero();
+    let mut divisor = 1u32;
+    while !power.is_zero() {
+        sum += &power / divisor;
+        power = (&power * &t_squared) >> WORKING_BITS;
+        divisor += 2;
+    }
+    sum
+}
+
+/// ln(2) = 2 * atanh(1 / 3), in `WORKING_BITS` fixed point
+fn ln_2() -> BigInt {
+    atanh(&((BigInt::one() << WORKING_BITS) / 3u32)) * 2u32
+}
+
+/// Natural logarithm of a positive rational
+///
+/// The value is split into m * 2 ^ k with m in [1, 2), and
+/// ln(m) = 2 * atanh((m - 1) / (m + 1)) converges quickly since
+/// (m - 1) / (m + 1) is at most 1 / 3.
+pub fn ln(value: &BigRational) -> BigRational {
+    assert!(value.is_positive(), "logarithm of non-positive number");
+    let mut exponent = value.numer().bits() as i64 - value.denom().bits() as i64;
+    let mut mantissa = scale_by_power_of_two(value.clone(), -exponent);
+    if mantissa < BigRational::one() {
+        mantissa *= BigInt::from(2u32);
+        exponent -= 1;
+    }
+    let one = BigInt::one() << WORKING_BITS;
+    let mantissa = to_fixed(&mantissa, WORKING_BITS);
+    let t = ((&mantissa - &one) << WORKING_BITS) / (&mantissa + &one);
+    from_fixed(atanh(&t) * 2u32 + ln_2() * exponent, WORKING_BITS)
+}
+
+/// Exponential of a rational
+///
+/// The argument is reduced to r = value - k * ln(2) with |r| <= ln(2) / 2,
+/// giving e ^ value = e ^ r * 2 ^ k, with e ^ r from its Taylor series.
+pub fn exp(value: &BigRational) -> BigRational {
+    let ln_2 = ln_2();
+    let value = to_fixed(value, WORKING_BITS);
+    let exponent = (&value + (&ln_2 >> 1usize)).div_floor(&ln_2);
+    let remainder = value - &exponent * &ln_2;
+    let mut term = BigInt::one() << WORKING_BITS;
+    let mut sum = term.clone();
+    let mut divisor = 1u32;
+    loop {
+        term = ((&term * &remainder) >> WORKING_BITS) / divisor;
+        if term.is_zero() {
+            break;
+        }
+        sum += &term;
+        divisor += 1;
+    }
+    let exponent = exponent
+        .to_i64()
+        .expect("exponential out of representable range");
+    scale_by_power_of_two(from_fixed(sum, WORKING_BITS), exponent)
+}
+
+/// base ^ exponent for a non-negative base, as e ^ (exponent * ln(base))
+pub fn pow(base: &BigRational, exponent: &BigRational) -> BigRational {
+    assert!(!base.is_negative(), "power of negative number");
+    if base.is_zero() {
+        assert!(exponent.is_positive(), "non-positive power of zero");
+        BigRational::zero()
+    } else {
+        exp(&(exponent * ln(base)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: BigRational, expected: BigRational) {
+        let epsilon = BigRational::new(BigInt::one(), BigInt::one() << (PRECISION_BITS - 8));
+        let difference = (&value - &expected).abs();
+        assert!(
+            difference <= epsilon * expected.abs().max(BigRational::one()),
+            "value={}, expected={}",
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn square_roots() {
+        assert_eq!(sqrt(&ratio(0)), ratio(0));
+        assert_eq!(sqrt(&ratio(1 << 64)), ratio(1 << 32));
+        let root = sqrt(&ratio(u128::MAX));
+        assert!(root > ratio(u64::MAX as u128) && root < ratio(1 << 64));
+        let root = sqrt(&ratio(2));
+        assert!(&root * &root <= ratio(2));
+        assert_close(&root * &root, ratio(2));
+    }
+
+    #[test]
+    fn logarithms_and_exponentials() {
+        assert_eq!(ln(&ratio(1)), ratio(0));
+        assert_close(exp(&ratio(0)), ratio(1));
+        assert_close(exp(&ln(&ratio(1_000_000_007))), ratio(1_000_000_007));
+        assert_close(
+            exp(&ln(&BigRational::new(
+                BigInt::from(3),
+                BigInt::from(1u128 << 100),
+            ))),
+            BigRational::new(BigInt::from(3), BigInt::from(1u128 << 100)),
+        );
+        assert_close(ln(&ratio(1 << 100)), ln(&ratio(2)) * BigInt::from(100));
+    }
+
+    #[test]
+    fn powers() {
+        let half = BigRational::new(BigInt::from(1), BigInt::from(2));
+        assert_close(pow(&ratio(1 << 64), &half), ratio(1 << 32));
+        assert_close(pow(&ratio(10), &ratio(20)), ratio(10u128.pow(20)));
+        assert_close(
+            pow(
+                &ratio(27),
+                &BigRational::new(BigInt::from(-1), BigInt::from(3)),
+            ),
+            BigRational::new(BigInt::from(1), BigInt::from(3)),
+        );
+        assert_eq!(pow(&ratio(0), &half), ratio(0));
+    }
+}
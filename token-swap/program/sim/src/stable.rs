@@ -0,0 +1,226 @@
+//! Reference model of the curve.fi stable swap invariant, for any number of
+//! tokens
+
+use {
+    crate::math::{ratio, sqrt, truncate, PRECISION_BITS},
+    num_bigint::BigInt,
+    num_rational::BigRational,
+    num_traits::{One, Signed, Zero},
+};
+
+/// Most Newton iterations when solving for D, far more than any pool needs
+const MAX_ITERATIONS: usize = 1_000;
+
+/// Stable swap pool with exact reserves, solving the invariant
+///
+/// leverage * sum(x_i) + D = leverage * D + D ^ (n + 1) / prod(n * x_i + 1)
+///
+/// to `PRECISION_BITS` bits, with the same leverage `amp * n` as the on-chain
+/// curves.  As in curve.fi, and in the on-chain curves, D is found with each
+/// `n * x_i` raised by one to avoid dividing by an empty reserve, which
+/// slightly raises D for small reserves.  Solving for a reserve given D uses
+/// the plain product.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StableSwapModel {
+    /// Amplification coefficient
+    pub amp: u64,
+    /// Token reserves of the pool
+    pub reserves: Vec<u128>,
+}
+
+impl StableSwapModel {
+    /// Create a model of the pool with the given reserves
+    pub fn new(amp: u64, reserves: Vec<u128>) -> Self {
+        assert!(reserves.len() >= 2, "stable pools need at least two tokens");
+        Self { amp, reserves }
+    }
+
+    fn n_coins(&self) -> BigInt {
+        BigInt::from(self.reserves.len())
+    }
+
+    fn leverage(&self) -> BigRational {
+        BigRational::from_integer(BigInt::from(self.amp) * self.n_coins())
+    }
+
+    fn exact_reserves(&self) -> Vec<BigRational> {
+        self.reserves
+            .iter()
+            .map(|reserve| ratio(*reserve))
+            .collect()
+    }
+
+    /// D ^ (n + 1) / (n ^ n * prod(x_i)) for the given reserves
+    fn d_product(&self, d: &BigRational, product: &BigRational) -> BigRational {
+        let n_coins = self.reserves.len() as i32;
+        d.pow(n_coins + 1)
+            / (BigRational::from_integer(self.n_coins().pow(n_coins as u32)) * product)
+    }
+
+    /// Solve for the invariant D of the given reserves
+    ///
+    /// f(D) = D ^ (n + 1) / prod(n * x_i + 1) + (leverage - 1) * D - leverage * sum(x_i)
+    /// is convex and increasing for positive D, so Newton's method from the
+    /// sum converges to its only positive root, from above after at most one
+    /// step.
+    fn compute_d_of(&self, reserves: &[BigRational]) -> BigRational {
+        let sum = reserves
+            .iter()
+            .fold(BigRational::zero(), |sum, reserve| sum + reserve);
+        if sum.is_zero() {
+            return sum;
+        }
+        let n_coins = BigRational::from_integer(self.n_coins());
+        let guarded_product = reserves
+            .iter()
+            .fold(BigRational::one(), |product, reserve| {
+                product * (&n_coins * reserve + BigRational::one())
+            });
+        let leverage = self.leverage();
+        let epsilon = BigRational::new(BigInt::one(), BigInt::one() << PRECISION_BITS);
+
+        let mut d = sum.clone();
+        for _ in 0..MAX_ITERATIONS {
+            let d_product = d.pow(reserves.len() as i32 + 1) / &guarded_product;
+            let value = &d_product + (&leverage - BigRational::one()) * &d - &leverage * &sum;
+            let derivative =
+                (&n_coins + BigRational::one()) * &d_product / &d + &leverage - BigRational::one();
+            let next_d = truncate(&(&d - value / derivative));
+            if (&next_d - &d).abs() <= epsilon {
+                return next_d;
+            }
+            d = next_d;
+        }
+        panic!("D did not converge for reserves {:?}", self.reserves);
+    }
+
+    /// Solve for reserve `j` given all other reserves and the invariant D
+    ///
+    /// The invariant is then the quadratic
+    /// y ^ 2 + (sum' + D / leverage - D) * y - D ^ (n + 1) / (n ^ n * prod' * leverage) = 0
+    /// where sum' and prod' only cover the other reserves.
+    fn compute_y(&self, reserves: &[BigRational], j: usize, d: &BigRational) -> BigRational {
+        let leverage = self.leverage();
+        let (sum, product) = reserves.iter().enumerate().filter(|(i, _)| *i != j).fold(
+            (BigRational::zero(), BigRational::one()),
+            |(sum, product), (_, reserve)| (sum + reserve, product * reserve),
+        );
+        let b = sum + d / &leverage - d;
+        let c = self.d_product(d, &product) / &leverage;
+        let two = BigRational::from_integer(BigInt::from(2));
+        (sqrt(&(&b * &b + &two * &two * c)) - b) / two
+    }
+
+    /// The invariant D of the pool
+    pub fn compute_d(&self) -> BigRational {
+        self.compute_d_of(&self.exact_reserves())
+    }
+
+    /// How far reserve `j` moves, with all other reserves fixed, for a
+    /// change of one token in D, from the implicit derivative of the invariant
+    ///
+    /// dx_j / dD = (leverage - 1 + (n + 1) * K) / (leverage + K * D / x_j)
+    ///
+    /// where K = D ^ n / (n ^ n * prod(x_i)).  Any error in D is multiplied
+    /// by this when solving for a reserve.
+    pub fn reserve_sensitivity(&self, j: usize) -> BigRational {
+        let reserves = self.exact_reserves();
+        let d = self.compute_d_of(&reserves);
+        let product = reserves
+            .iter()
+            .fold(BigRational::one(), |product, reserve| product * reserve);
+        let k = self.d_product(&d, &product) / &d;
+        let n_coins = BigRational::from_integer(self.n_coins());
+        let leverage = self.leverage();
+        (&leverage - BigRational::one() + (n_coins + BigRational::one()) * &k)
+            / (leverage + k * d / &reserves[j])
+    }
+
+    /// Amount of token `j` received for `source_amount` of token `i`
+    pub fn exchange(&self, i: usize, j: usize, source_amount: u128) -> BigRational {
+        let mut reserves = self.exact_reserves();
+        let d = self.compute_d_of(&reserves);
+        reserves[i] += ratio(source_amount);
+        &reserves[j] - self.compute_y(&reserves, j, &d)
+    }
+
+    /// Amount of token `i` needed to receive `destination_amount` of token `j`
+    pub fn exchange_exact_out(&self, i: usize, j: usize, destination_amount: u128) -> BigRational {
+        let mut reserves = self.exact_reserves();
+        let d = self.compute_d_of(&reserves);
+        reserves[j] -= ratio(destination_amount);
+        self.compute_y(&reserves, i, &d) - &reserves[i]
+    }
+
+    /// Pool tokens minted for depositing `source_amount` of token `i`, in
+    /// proportion to the growth of D
+    pub fn deposit_one(&self, i: usize, source_amount: u128, pool_supply: u128) -> BigRational {
+        let mut reserves = self.exact_reserves();
+        let d_0 = self.compute_d_of(&reserves);
+        reserves[i] += ratio(source_amount);
+        let d_1 = self.compute_d_of(&reserves);
+        (d_1 - &d_0) * ratio(pool_supply) / d_0
+    }
+
+    /// Pool tokens burned for withdrawing `destination_amount` of token `i`,
+    /// in proportion to the decrease of D
+    pub fn withdraw_one_exact_out(
+        &self,
+        i: usize,
+        destination_amount: u128,
+        pool_supply: u128,
+    ) -> BigRational {
+        let mut reserves = self.exact_reserves();
+        let d_0 = self.compute_d_of(&reserves);
+        reserves[i] -= ratio(destination_amount);
+        let d_1 = self.compute_d_of(&reserves);
+        (&d_0 - d_1) * ratio(pool_supply) / d_0
+    }
+
+    /// Amount of token `i` received for burning `pool_tokens`, lowering D in
+    /// proportion to the pool tokens burned
+    pub fn withdraw_one(&self, i: usize, pool_tokens: u128, pool_supply: u128) -> BigRational {
+        let reserves = self.exact_reserves();
+        let d_0 = self.compute_d_of(&reserves);
+        let d_1 = d_0 * ratio(pool_supply - pool_tokens) / ratio(pool_supply);
+        &reserves[i] - self.compute_y(&reserves, i, &d_1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::convert::TryFrom};
+
+    #[test]
+    fn balanced_pool() {
+        // A balanced pool trades like a constant sum, with D = sum(x_i) up
+        // to the guard against empty reserves
+        let model = StableSwapModel::new(100, vec![1_000_000; 3]);
+        let d = model.compute_d();
+        assert!(d > ratio(3_000_000) && d < ratio(3_000_001));
+        let model = StableSwapModel::new(100, vec![1_000_000_000, 1_000_000_000]);
+        // and every token of D is a token of any reserve
+        assert_eq!(model.reserve_sensitivity(1).round(), ratio(1));
+        let received = model.exchange(0, 1, 1_000);
+        assert!(received < ratio(1_000) && received > ratio(999));
+        let paid = model.exchange_exact_out(0, 1, 1_000);
+        assert!(paid > ratio(1_000) && paid < ratio(1_001));
+    }
+
+    #[test]
+    fn round_trips() {
+        let model = StableSwapModel::new(10, vec![5_000_000, 20_000_000, 1_000_000]);
+        let received = model.exchange(2, 0, 100_000).floor().to_integer();
+        let received = u128::try_from(received).unwrap();
+        let paid = model.exchange_exact_out(2, 0, received);
+        assert!(paid <= ratio(100_000) && paid > ratio(99_990));
+
+        let withdrawn = model.withdraw_one(0, 1_000, 1_000_000);
+        let burned = model.withdraw_one_exact_out(0, 1, 1_000_000);
+        assert!(withdrawn.is_positive() && burned.is_positive());
+
+        let pool_tokens = model.deposit_one(1, 1_000_000, 1_000_000);
+        let withdrawn = model.withdraw_one_exact_out(1, 1_000_000, 1_000_000);
+        assert!(pool_tokens < withdrawn);
+    }
+}
@@ -12,6 +12,7 @@ use crate::curve::{
     fees::Fees,
     offset::OffsetCurve,
     stable::StableCurve,
+    weighted::WeightedCurve,
 };
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use std::convert::{TryFrom, TryInto};
@@ -34,6 +35,9 @@ pub enum CurveType {
     Stable,
     /// Offset curve, like Uniswap, but the token B side has a faked offset
     Offset,
+    /// Balancer-style weighted curve, like Uniswap, but with arbitrary weights
+    /// on each token, such as 80/20
+    Weighted,
 }
 
 /// Encodes all results of swapping from a source token to a destination token
@@ -105,6 +109,43 @@ impl SwapCurve {
         })
     }
 
+    /// Calculate how much source token must be provided, including fees, to
+    /// receive an exact amount of destination token.
+    pub fn swap_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<SwapResult> {
+        let SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        } = self.calculator.swap_without_fees_exact_out(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )?;
+
+        // credit the fees on top of the amount swapped, any rounding left over
+        // after the fees stays in the pool
+        let source_amount_swapped = fees.pre_trading_fee_amount(source_amount_swapped)?;
+        let trade_fee = fees.trading_fee(source_amount_swapped)?;
+        let owner_fee = fees.owner_trading_fee(source_amount_swapped)?;
+
+        Some(SwapResult {
+            new_swap_source_amount: swap_source_amount.checked_add(source_amount_swapped)?,
+            new_swap_destination_amount: swap_destination_amount
+                .checked_sub(destination_amount_swapped)?,
+            source_amount_swapped,
+            destination_amount_swapped,
+            trade_fee,
+            owner_fee,
+        })
+    }
+
     /// Get the amount of pool tokens for the deposited amount of token A or B
     pub fn deposit_single_token_type(
         &self,
@@ -118,11 +159,7 @@ impl SwapCurve {
         if source_amount == 0 {
             return Some(0);
         }
-        // Get the trading fee incurred if *half* the source amount is swapped
-        // for the other side. Reference at:
-        // https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BMath.sol#L117
-        let half_source_amount = std::cmp::max(1, source_amount.checked_div(2)?);
-        let trade_fee = fees.trading_fee(half_source_amount)?;
+        let trade_fee = fees.single_token_type_trading_fee(source_amount)?;
         let source_amount = source_amount.checked_sub(trade_fee)?;
         self.calculator.deposit_single_token_type(
             source_amount,
@@ -146,11 +183,7 @@ impl SwapCurve {
         if source_amount == 0 {
             return Some(0);
         }
-        // Get the trading fee incurred if *half* the source amount is swapped
-        // for the other side. Reference at:
-        // https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BMath.sol#L117
-        let half_source_amount = std::cmp::max(1, source_amount.checked_div(2)?);
-        let trade_fee = fees.trading_fee(half_source_amount)?;
+        let trade_fee = fees.single_token_type_trading_fee(source_amount)?;
         let source_amount = source_amount.checked_sub(trade_fee)?;
         self.calculator.withdraw_single_token_type_exact_out(
             source_amount,
@@ -224,6 +257,7 @@ impl Pack for SwapCurve {
                 }
                 CurveType::Stable => Arc::new(StableCurve::unpack_from_slice(calculator)?),
                 CurveType::Offset => Arc::new(OffsetCurve::unpack_from_slice(calculator)?),
+                CurveType::Weighted => Arc::new(WeightedCurve::unpack_from_slice(calculator)?),
             },
         })
     }
@@ -254,6 +288,7 @@ impl TryFrom<u8> for CurveType {
             1 => Ok(CurveType::ConstantPrice),
             2 => Ok(CurveType::Stable),
             3 => Ok(CurveType::Offset),
+            4 => Ok(CurveType::Weighted),
             _ => Err(ProgramError::InvalidAccountData),
         }
     }
@@ -398,4 +433,49 @@ mod tests {
         assert_eq!(result.destination_amount_swapped, 4545);
         assert_eq!(result.new_swap_destination_amount, 45455);
     }
+
+    #[test]
+    fn constant_product_exact_out_trade_fee() {
+        let swap_source_amount = 1000;
+        let swap_destination_amount = 50000;
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            ..Fees::default()
+        };
+        let destination_amount = 4504;
+        let curve = ConstantProductCurve {};
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(curve),
+        };
+        let result = swap_curve
+            .swap_exact_out(
+                destination_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        // matches the exact-in swap: 99 tokens are swapped, plus 1 token in fees
+        assert_eq!(result.source_amount_swapped, 100);
+        assert_eq!(result.new_swap_source_amount, 1100);
+        assert_eq!(result.destination_amount_swapped, 4504);
+        assert_eq!(result.new_swap_destination_amount, 45496);
+        assert_eq!(result.trade_fee, 1);
+        assert_eq!(result.owner_fee, 0);
+
+        // the exact-in swap gives the same amount for the same source amount
+        let result = swap_curve
+            .swap(
+                result.source_amount_swapped,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, destination_amount);
+    }
 }
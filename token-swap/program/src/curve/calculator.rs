@@ -95,6 +95,18 @@ pub trait CurveCalculator: Debug + DynPack {
         trade_direction: TradeDirection,
     ) -> Option<SwapWithoutFeesResult>;
 
+    /// Calculate how much source token must be provided to receive an exact
+    /// amount of destination token.  The returned destination amount is
+    /// always the amount requested, and the source amount is rounded in
+    /// favor of the pool.
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult>;
+
     /// Get the supply for a new pool
     /// The default implementation is a Balancer-style fixed initial supply
     fn new_pool_supply(&self) -> u128 {
@@ -196,7 +208,8 @@ pub trait CurveCalculator: Debug + DynPack {
 pub mod test {
     use super::*;
     use proptest::prelude::*;
-    use spl_math::uint::U256;
+    use sim::{ratio, BigRational, CurveModel};
+    use spl_math::{precise_number::ONE, uint::U256};
 
     /// The epsilon for most curves when performing the conversion test,
     /// comparing a one-sided deposit to a swap + deposit.
@@ -436,6 +449,53 @@ pub mod test {
         assert!(difference <= epsilon);
     }
 
+    /// Test function to check that an exact-out swap delivers exactly the
+    /// requested destination amount and never reduces the curve's value.
+    ///
+    /// The source amount is rounded up, so unlike an exact-in swap, the value
+    /// may increase by up to the value of one source token.
+    pub fn check_curve_value_from_swap_exact_out(
+        curve: &dyn CurveCalculator,
+        destination_token_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) {
+        let results = curve
+            .swap_without_fees_exact_out(
+                destination_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+            )
+            .unwrap();
+        assert_eq!(results.destination_amount_swapped, destination_token_amount);
+
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        let previous_value = curve
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+
+        let new_swap_source_amount = swap_source_amount
+            .checked_add(results.source_amount_swapped)
+            .unwrap();
+        let new_swap_destination_amount = swap_destination_amount
+            .checked_sub(results.destination_amount_swapped)
+            .unwrap();
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (new_swap_source_amount, new_swap_destination_amount),
+            TradeDirection::BtoA => (new_swap_destination_amount, new_swap_source_amount),
+        };
+
+        let new_value = curve
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+        assert!(new_value.greater_than_or_equal(&previous_value));
+    }
+
     /// Test function checking that a deposit never reduces the value of pool
     /// tokens.
     ///
@@ -536,6 +596,232 @@ pub mod test {
             .greater_than_or_equal(&value.checked_mul(&new_pool_token_supply).unwrap()));
     }
 
+    /// Convert a trade direction for the curve models of the `sim` crate
+    fn model_trade_direction(trade_direction: TradeDirection) -> sim::TradeDirection {
+        match trade_direction {
+            TradeDirection::AtoB => sim::TradeDirection::AtoB,
+            TradeDirection::BtoA => sim::TradeDirection::BtoA,
+        }
+    }
+
+    /// Check that an amount is within `tolerance` tokens of the exact amount,
+    /// in either direction
+    pub fn check_within_tolerance(
+        operation: &str,
+        amount: u128,
+        exact: &BigRational,
+        tolerance: u128,
+    ) {
+        let amount = ratio(amount);
+        let difference = if &amount > exact {
+            &amount - exact
+        } else {
+            exact - &amount
+        };
+        assert!(
+            difference <= ratio(tolerance),
+            "{}: amount={}, model={}, tolerance={}",
+            operation,
+            amount,
+            exact,
+            tolerance
+        );
+    }
+
+    /// Test function to check every operation of a curve against the
+    /// high-precision model of its invariant from the `sim` crate.
+    ///
+    /// Integer amounts can never match the model exactly, so every amount of
+    /// trading tokens given by the curve must be within `tolerance` of the
+    /// model, and every amount of pool tokens for single-token deposits and
+    /// withdrawals within `pool_token_tolerance`, covering both the rounding
+    /// to whole tokens and the approximations of the curve.  Operations
+    /// rejected by the curve are not checked.
+    #[allow(clippy::too_many_arguments)]
+    pub fn check_curve_against_model(
+        curve: &dyn CurveCalculator,
+        model: &CurveModel,
+        source_token_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+        pool_supply: u128,
+        tolerance: u128,
+        pool_token_tolerance: u128,
+    ) {
+        let model_direction = model_trade_direction(trade_direction);
+        if let Some(results) = curve.swap_without_fees(
+            source_token_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        ) {
+            assert!(results.source_amount_swapped <= source_token_amount);
+            let exact = model.swap(
+                results.source_amount_swapped,
+                swap_source_amount,
+                swap_destination_amount,
+                model_direction,
+            );
+            check_within_tolerance(
+                "swap",
+                results.destination_amount_swapped,
+                &exact,
+                tolerance,
+            );
+
+            if let Some(results) = curve.swap_without_fees_exact_out(
+                results.destination_amount_swapped,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+            ) {
+                let exact = model.swap_exact_out(
+                    results.destination_amount_swapped,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    model_direction,
+                );
+                check_within_tolerance(
+                    "swap exact out",
+                    results.source_amount_swapped,
+                    &exact,
+                    tolerance,
+                );
+            }
+        }
+
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        let pool_tokens = std::cmp::min(source_token_amount, pool_supply);
+        let (exact_a, exact_b) = model.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        );
+        if let Some(results) = curve.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            RoundDirection::Floor,
+        ) {
+            check_within_tolerance(
+                "withdraw token A",
+                results.token_a_amount,
+                &exact_a,
+                tolerance,
+            );
+            check_within_tolerance(
+                "withdraw token B",
+                results.token_b_amount,
+                &exact_b,
+                tolerance,
+            );
+        }
+        if let Some(results) = curve.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            RoundDirection::Ceiling,
+        ) {
+            check_within_tolerance(
+                "deposit token A",
+                results.token_a_amount,
+                &exact_a,
+                tolerance,
+            );
+            check_within_tolerance(
+                "deposit token B",
+                results.token_b_amount,
+                &exact_b,
+                tolerance,
+            );
+        }
+
+        if let Some(pool_tokens) = curve.deposit_single_token_type(
+            source_token_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        ) {
+            let exact = model.deposit_single_token_type(
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                model_direction,
+            );
+            check_within_tolerance(
+                "deposit single token type",
+                pool_tokens,
+                &exact,
+                pool_token_tolerance,
+            );
+        }
+
+        // the model cannot value a pool with an empty reserve
+        if source_token_amount < swap_source_amount {
+            if let Some(pool_tokens) = curve.withdraw_single_token_type_exact_out(
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+            ) {
+                let exact = model.withdraw_single_token_type_exact_out(
+                    source_token_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_supply,
+                    model_direction,
+                );
+                check_within_tolerance(
+                    "withdraw single token type exact out",
+                    pool_tokens,
+                    &exact,
+                    pool_token_tolerance,
+                );
+            }
+        }
+    }
+
+    /// Test function to check the normalized value of a curve against the
+    /// high-precision model of its invariant, within `tolerance` tokens.
+    pub fn check_normalized_value_against_model(
+        curve: &dyn CurveCalculator,
+        model: &CurveModel,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        tolerance: u128,
+    ) {
+        let value = match curve.normalized_value(swap_token_a_amount, swap_token_b_amount) {
+            Some(value) => value,
+            None => return,
+        };
+        let exact = model.normalized_value(swap_token_a_amount, swap_token_b_amount);
+        let value = format!("{}/{}", value.value, ONE)
+            .parse::<BigRational>()
+            .unwrap();
+        let difference = if value > exact {
+            value - &exact
+        } else {
+            &exact - value
+        };
+        assert!(
+            difference <= ratio(tolerance),
+            "normalized value: difference={}, model={}, tolerance={}",
+            difference,
+            exact,
+            tolerance
+        );
+    }
+
     prop_compose! {
         pub fn total_and_intermediate()(total in 1..u64::MAX)
                         (intermediate in 1..total, total in Just(total))
@@ -0,0 +1,1021 @@
+//! Concentrated liquidity math, where liquidity is only provided between two
+//! price ticks, in the style of Uniswap v3.
+//!
+//! Prices are of token A in units of token B, and are stored as their square
+//! root in Q64.64 fixed point.  Liquidity `L` held between the square root
+//! prices `sqrt_lower` and `sqrt_upper` is worth `L * (1 / sqrt_lower - 1 /
+//! sqrt_upper)` of token A and `L * (sqrt_upper - sqrt_lower)` of token B, and
+//! tick `i` is the price `1.0001^i`.
+
+use {
+    crate::{curve::calculator::RoundDirection, error::SwapError},
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{
+        precise_number::{PreciseNumber, ONE},
+        uint::U256,
+    },
+    std::convert::TryFrom,
+};
+
+/// Lowest tick, whose price is about 2^-128
+pub const MIN_TICK: i32 = -443_636;
+/// Highest tick, whose price is about 2^128
+pub const MAX_TICK: i32 = 443_636;
+/// Square root price of `MIN_TICK`, in Q64.64
+pub const MIN_SQRT_PRICE: u128 = 4_295_048_017;
+/// Square root price of `MAX_TICK`, in Q64.64
+pub const MAX_SQRT_PRICE: u128 = 79_226_673_515_401_279_992_447_579_062;
+/// Most ticks that can hold liquidity in a pool at the same time
+pub const MAX_TICKS: usize = 64;
+
+/// Fractional bits of square root prices and fee growths
+const RESOLUTION: usize = 64;
+
+/// log2(1.0001), scaled by `spl_math::precise_number::ONE`
+const LOG2_TICK_BASE: i128 = 144_262_291;
+
+/// 2^128 / sqrt(1.0001)^(2^i), multiplied together for every bit i set in a
+/// tick's absolute value to find its square root price
+const SQRT_PRICE_FACTORS: [u128; 19] = [
+    0xfffc_b933_bd6f_ad37_aa2d_162d_1a59_4001,
+    0xfff9_7272_373d_4132_59a4_6990_580e_2139,
+    0xfff2_e50f_5f65_6932_ef12_357c_f3c7_fdcb,
+    0xffe5_caca_7e10_e4e6_1c36_24ea_a094_1ccf,
+    0xffcb_9843_d60f_6159_c9db_5883_5c92_6643,
+    0xff97_3b41_fa98_c081_472e_6896_dfb2_54bf,
+    0xff2e_a164_66c9_6a38_43ec_78b3_26b5_2860,
+    0xfe5d_ee04_6a99_a2a8_11c4_61f1_969c_3052,
+    0xfcbe_86c7_900a_88ae_dcff_c83b_479a_a3a3,
+    0xf987_a725_3ac4_1317_6f2b_074c_f781_5e53,
+    0xf339_2b08_22b7_0005_940c_7a39_8e4b_70f2,
+    0xe715_9475_a2c2_9b74_43b2_9c7f_a6e8_89d8,
+    0xd097_f3bd_fd20_22b8_845a_d8f7_92aa_5825,
+    0xa9f7_4646_2d87_0fdf_8a65_dc1f_90e0_61e4,
+    0x70d8_69a1_56d2_a1b8_90bb_3df6_2baf_32f6,
+    0x31be_135f_97d0_8fd9_8123_1505_542f_cfa5,
+    0x09aa_508b_5b7a_84e1_c677_de54_f3e9_9bc8,
+    0x005d_6af8_dedb_8119_6699_c329_225e_e604,
+    0x0000_2216_e584_f5fa_1ea9_2604_1bed_fe97,
+];
+
+/// Divides, rounding in the given direction
+fn div_round(numerator: U256, denominator: U256, round_direction: RoundDirection) -> Option<U256> {
+    let quotient = numerator.checked_div(denominator)?;
+    match round_direction {
+        RoundDirection::Floor => Some(quotient),
+        RoundDirection::Ceiling => {
+            if quotient.checked_mul(denominator)? == numerator {
+                Some(quotient)
+            } else {
+                quotient.checked_add(U256::one())
+            }
+        }
+    }
+}
+
+/// Applies a signed change to an amount of liquidity
+fn add_liquidity_delta(liquidity: u128, liquidity_delta: i128) -> Option<u128> {
+    if liquidity_delta >= 0 {
+        liquidity.checked_add(liquidity_delta as u128)
+    } else {
+        liquidity.checked_sub(liquidity_delta.unsigned_abs())
+    }
+}
+
+/// Square root price of a tick, in Q64.64, rounded up
+pub fn sqrt_price_at_tick(tick: i32) -> Option<u128> {
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return None;
+    }
+    let abs_tick = tick.unsigned_abs();
+    // 1 / sqrt(1.0001)^|tick| in Q128.128
+    let mut ratio = U256::one() << 128;
+    for (bit, factor) in SQRT_PRICE_FACTORS.iter().enumerate() {
+        if abs_tick & (1 << bit) != 0 {
+            ratio = ratio.checked_mul(U256::from(*factor))? >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX.checked_div(ratio)?;
+    }
+    let sqrt_price = div_round(ratio, U256::one() << RESOLUTION, RoundDirection::Ceiling)?;
+    u128::try_from(sqrt_price).ok()
+}
+
+/// Greatest tick whose square root price is at most `sqrt_price`
+pub fn tick_at_sqrt_price(sqrt_price: u128) -> Option<i32> {
+    if !(MIN_SQRT_PRICE..=MAX_SQRT_PRICE).contains(&sqrt_price) {
+        return None;
+    }
+    let (log2, negative) = PreciseNumber::new(sqrt_price)?.checked_log2()?;
+    let log2 = i128::try_from(u128::try_from(log2.value).ok()?).ok()?;
+    let log2 = if negative { -log2 } else { log2 };
+    // the square root price is in Q64.64, and half the log of the price
+    let log2_price = log2.checked_sub(64 * ONE as i128)?.checked_mul(2)?;
+    let estimate = i32::try_from(log2_price.div_euclid(LOG2_TICK_BASE)).ok()?;
+    // the logarithm is approximate, so the estimate may be off by one
+    let mut tick = estimate.max(MIN_TICK).min(MAX_TICK);
+    while tick < MAX_TICK && sqrt_price_at_tick(tick + 1)? <= sqrt_price {
+        tick += 1;
+    }
+    while sqrt_price_at_tick(tick)? > sqrt_price {
+        tick -= 1;
+    }
+    Some(tick)
+}
+
+/// Amount of token A held by `liquidity` between two square root prices
+pub fn amount_a_delta(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_direction: RoundDirection,
+) -> Option<u128> {
+    // L * (sqrt_upper - sqrt_lower) / (sqrt_upper * sqrt_lower)
+    let numerator = (U256::from(liquidity) << RESOLUTION)
+        .checked_mul(U256::from(sqrt_price_upper.checked_sub(sqrt_price_lower)?))?;
+    let denominator = U256::from(sqrt_price_upper).checked_mul(U256::from(sqrt_price_lower))?;
+    u128::try_from(div_round(numerator, denominator, round_direction)?).ok()
+}
+
+/// Amount of token B held by `liquidity` between two square root prices
+pub fn amount_b_delta(
+    sqrt_price_lower: u128,
+    sqrt_price_upper: u128,
+    liquidity: u128,
+    round_direction: RoundDirection,
+) -> Option<u128> {
+    // L * (sqrt_upper - sqrt_lower)
+    let numerator = U256::from(liquidity)
+        .checked_mul(U256::from(sqrt_price_upper.checked_sub(sqrt_price_lower)?))?;
+    u128::try_from(div_round(
+        numerator,
+        U256::one() << RESOLUTION,
+        round_direction,
+    )?)
+    .ok()
+}
+
+/// Square root price reached by adding `amount_in` to `liquidity` at
+/// `sqrt_price`, rounded so that the price moves no further than paid for
+fn next_sqrt_price_from_input(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount_in: u128,
+    a_to_b: bool,
+) -> Option<u128> {
+    let scaled_liquidity = U256::from(liquidity) << RESOLUTION;
+    let next_sqrt_price = if a_to_b {
+        // L / (L / sqrt_price + amount_in)
+        let denominator = scaled_liquidity
+            .checked_div(U256::from(sqrt_price))?
+            .checked_add(U256::from(amount_in))?;
+        div_round(scaled_liquidity, denominator, RoundDirection::Ceiling)?
+    } else {
+        // sqrt_price + amount_in / L
+        U256::from(sqrt_price).checked_add(
+            (U256::from(amount_in) << RESOLUTION).checked_div(U256::from(liquidity))?,
+        )?
+    };
+    u128::try_from(next_sqrt_price).ok()
+}
+
+/// Result of swapping within a single range of constant liquidity
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapStep {
+    /// Square root price after the step
+    pub sqrt_price: u128,
+    /// Amount of the source token used up by the step
+    pub amount_in: u128,
+    /// Amount of the destination token paid out by the step
+    pub amount_out: u128,
+}
+
+/// Swaps up to `amount_remaining` from `sqrt_price` towards
+/// `sqrt_price_target`, without crossing it
+pub fn compute_swap_step(
+    sqrt_price: u128,
+    sqrt_price_target: u128,
+    liquidity: u128,
+    amount_remaining: u128,
+    a_to_b: bool,
+) -> Option<SwapStep> {
+    let max_amount_in = if a_to_b {
+        amount_a_delta(
+            sqrt_price_target,
+            sqrt_price,
+            liquidity,
+            RoundDirection::Ceiling,
+        )
+    } else {
+        amount_b_delta(
+            sqrt_price,
+            sqrt_price_target,
+            liquidity,
+            RoundDirection::Ceiling,
+        )
+    };
+    // an overflowing amount can't be reached by any input
+    let (next_sqrt_price, amount_in) = match max_amount_in {
+        Some(max_amount_in) if amount_remaining >= max_amount_in => {
+            (sqrt_price_target, max_amount_in)
+        }
+        _ => (
+            next_sqrt_price_from_input(sqrt_price, liquidity, amount_remaining, a_to_b)?,
+            amount_remaining,
+        ),
+    };
+    let amount_out = if a_to_b {
+        amount_b_delta(
+            next_sqrt_price,
+            sqrt_price,
+            liquidity,
+            RoundDirection::Floor,
+        )?
+    } else {
+        amount_a_delta(
+            sqrt_price,
+            next_sqrt_price,
+            liquidity,
+            RoundDirection::Floor,
+        )?
+    };
+    Some(SwapStep {
+        sqrt_price: next_sqrt_price,
+        amount_in,
+        amount_out,
+    })
+}
+
+/// Tick bounding at least one position, tracking the liquidity that starts
+/// or stops there and the fees earned on its far side
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tick {
+    /// Index of the tick
+    pub index: i32,
+    /// Total liquidity of the positions bounded by the tick
+    pub liquidity_gross: u128,
+    /// Liquidity added when the price crosses the tick upwards, and removed
+    /// when crossing downwards
+    pub liquidity_net: i128,
+    /// Token A fee growth per unit of liquidity on the other side of the
+    /// tick from the current price
+    pub fee_growth_outside_a: u128,
+    /// Token B fee growth per unit of liquidity on the other side of the
+    /// tick from the current price
+    pub fee_growth_outside_b: u128,
+}
+
+impl IsInitialized for Tick {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for Tick {}
+impl Pack for Tick {
+    const LEN: usize = 68;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 68];
+        let (index, liquidity_gross, liquidity_net, fee_growth_outside_a, fee_growth_outside_b) =
+            mut_array_refs![output, 4, 16, 16, 16, 16];
+        *index = self.index.to_le_bytes();
+        *liquidity_gross = self.liquidity_gross.to_le_bytes();
+        *liquidity_net = self.liquidity_net.to_le_bytes();
+        *fee_growth_outside_a = self.fee_growth_outside_a.to_le_bytes();
+        *fee_growth_outside_b = self.fee_growth_outside_b.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Tick, ProgramError> {
+        let input = array_ref![input, 0, 68];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (index, liquidity_gross, liquidity_net, fee_growth_outside_a, fee_growth_outside_b) =
+            array_refs![input, 4, 16, 16, 16, 16];
+        Ok(Self {
+            index: i32::from_le_bytes(*index),
+            liquidity_gross: u128::from_le_bytes(*liquidity_gross),
+            liquidity_net: i128::from_le_bytes(*liquidity_net),
+            fee_growth_outside_a: u128::from_le_bytes(*fee_growth_outside_a),
+            fee_growth_outside_b: u128::from_le_bytes(*fee_growth_outside_b),
+        })
+    }
+}
+
+/// Price, active liquidity and initialized ticks of a concentrated liquidity
+/// pool.  Fee growths are in Q64.64 per unit of liquidity, and wrap around
+/// like Uniswap v3's, since only their differences are meaningful.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConcentratedCurve {
+    /// Positions may only be bounded by multiples of the tick spacing
+    pub tick_spacing: u16,
+    /// Current square root price, in Q64.64
+    pub sqrt_price: u128,
+    /// Greatest tick at or below the current price
+    pub tick: i32,
+    /// Liquidity of the positions whose range contains the current tick
+    pub liquidity: u128,
+    /// Token A fees earned per unit of liquidity since the pool was created
+    pub fee_growth_global_a: u128,
+    /// Token B fees earned per unit of liquidity since the pool was created
+    pub fee_growth_global_b: u128,
+    /// Ticks bounding positions with liquidity, sorted by index
+    pub ticks: Vec<Tick>,
+}
+
+impl ConcentratedCurve {
+    /// Creates an empty curve at `sqrt_price`
+    pub fn new(tick_spacing: u16, sqrt_price: u128) -> Result<Self, SwapError> {
+        if tick_spacing == 0 || i32::from(tick_spacing) > MAX_TICK {
+            return Err(SwapError::InvalidCurve);
+        }
+        let tick = tick_at_sqrt_price(sqrt_price).ok_or(SwapError::InvalidCurve)?;
+        Ok(Self {
+            tick_spacing,
+            sqrt_price,
+            tick,
+            ..Self::default()
+        })
+    }
+
+    /// Checks that a position's range is ordered, within bounds, and on the
+    /// tick spacing
+    pub fn check_ticks(&self, tick_lower: i32, tick_upper: i32) -> Result<(), SwapError> {
+        let tick_spacing = i32::from(self.tick_spacing);
+        if tick_lower >= tick_upper
+            || tick_lower < MIN_TICK
+            || tick_upper > MAX_TICK
+            || tick_lower % tick_spacing != 0
+            || tick_upper % tick_spacing != 0
+        {
+            return Err(SwapError::InvalidTickRange);
+        }
+        Ok(())
+    }
+
+    /// Fee growths outside of a tick, zero if it holds no liquidity
+    fn fee_growth_outside(&self, index: i32) -> (u128, u128) {
+        self.ticks
+            .binary_search_by_key(&index, |tick| tick.index)
+            .map(|position| {
+                let tick = &self.ticks[position];
+                (tick.fee_growth_outside_a, tick.fee_growth_outside_b)
+            })
+            .unwrap_or((0, 0))
+    }
+
+    /// Token A and B fee growths per unit of liquidity between two ticks
+    pub fn fee_growth_inside(&self, tick_lower: i32, tick_upper: i32) -> (u128, u128) {
+        let (lower_a, lower_b) = self.fee_growth_outside(tick_lower);
+        let (upper_a, upper_b) = self.fee_growth_outside(tick_upper);
+        let (below_a, below_b) = if self.tick >= tick_lower {
+            (lower_a, lower_b)
+        } else {
+            (
+                self.fee_growth_global_a.wrapping_sub(lower_a),
+                self.fee_growth_global_b.wrapping_sub(lower_b),
+            )
+        };
+        let (above_a, above_b) = if self.tick < tick_upper {
+            (upper_a, upper_b)
+        } else {
+            (
+                self.fee_growth_global_a.wrapping_sub(upper_a),
+                self.fee_growth_global_b.wrapping_sub(upper_b),
+            )
+        };
+        (
+            self.fee_growth_global_a
+                .wrapping_sub(below_a)
+                .wrapping_sub(above_a),
+            self.fee_growth_global_b
+                .wrapping_sub(below_b)
+                .wrapping_sub(above_b),
+        )
+    }
+
+    /// Adds liquidity starting or stopping at a tick, initializing it if
+    /// needed
+    fn update_tick(
+        &mut self,
+        index: i32,
+        liquidity_delta: i128,
+        is_upper: bool,
+    ) -> Result<(), SwapError> {
+        let position = match self.ticks.binary_search_by_key(&index, |tick| tick.index) {
+            Ok(position) => position,
+            Err(position) => {
+                if self.ticks.len() >= MAX_TICKS {
+                    return Err(SwapError::TooManyTicks);
+                }
+                // all fees so far are taken to have been earned below the
+                // current tick
+                let (fee_growth_outside_a, fee_growth_outside_b) = if index <= self.tick {
+                    (self.fee_growth_global_a, self.fee_growth_global_b)
+                } else {
+                    (0, 0)
+                };
+                self.ticks.insert(
+                    position,
+                    Tick {
+                        index,
+                        fee_growth_outside_a,
+                        fee_growth_outside_b,
+                        ..Tick::default()
+                    },
+                );
+                position
+            }
+        };
+        let tick = &mut self.ticks[position];
+        tick.liquidity_gross = add_liquidity_delta(tick.liquidity_gross, liquidity_delta)
+            .ok_or(SwapError::CalculationFailure)?;
+        tick.liquidity_net = if is_upper {
+            tick.liquidity_net.checked_sub(liquidity_delta)
+        } else {
+            tick.liquidity_net.checked_add(liquidity_delta)
+        }
+        .ok_or(SwapError::CalculationFailure)?;
+        Ok(())
+    }
+
+    /// Adds or removes liquidity between two ticks, returning the fee
+    /// growths inside of them for the position to settle its fees with
+    pub fn update_liquidity(
+        &mut self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity_delta: i128,
+    ) -> Result<(u128, u128), SwapError> {
+        self.check_ticks(tick_lower, tick_upper)?;
+        self.update_tick(tick_lower, liquidity_delta, false)?;
+        self.update_tick(tick_upper, liquidity_delta, true)?;
+        let fee_growth_inside = self.fee_growth_inside(tick_lower, tick_upper);
+        if (tick_lower..tick_upper).contains(&self.tick) {
+            self.liquidity = add_liquidity_delta(self.liquidity, liquidity_delta)
+                .ok_or(SwapError::CalculationFailure)?;
+        }
+        self.ticks.retain(|tick| tick.liquidity_gross > 0);
+        Ok(fee_growth_inside)
+    }
+
+    /// Token A and B amounts held by `liquidity` between two ticks at the
+    /// current price
+    pub fn token_amounts(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        round_direction: RoundDirection,
+    ) -> Option<(u128, u128)> {
+        let sqrt_price_lower = sqrt_price_at_tick(tick_lower)?;
+        let sqrt_price_upper = sqrt_price_at_tick(tick_upper)?;
+        if self.tick < tick_lower {
+            let amount_a = amount_a_delta(
+                sqrt_price_lower,
+                sqrt_price_upper,
+                liquidity,
+                round_direction,
+            )?;
+            Some((amount_a, 0))
+        } else if self.tick < tick_upper {
+            let amount_a = amount_a_delta(
+                self.sqrt_price,
+                sqrt_price_upper,
+                liquidity,
+                round_direction,
+            )?;
+            let amount_b = amount_b_delta(
+                sqrt_price_lower,
+                self.sqrt_price,
+                liquidity,
+                round_direction,
+            )?;
+            Some((amount_a, amount_b))
+        } else {
+            let amount_b = amount_b_delta(
+                sqrt_price_lower,
+                sqrt_price_upper,
+                liquidity,
+                round_direction,
+            )?;
+            Some((0, amount_b))
+        }
+    }
+
+    /// Most liquidity between two ticks that the given token amounts pay for
+    /// at the current price
+    pub fn liquidity_for_amounts(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount_a: u128,
+        amount_b: u128,
+    ) -> Option<u128> {
+        let sqrt_price_lower = sqrt_price_at_tick(tick_lower)?;
+        let sqrt_price_upper = sqrt_price_at_tick(tick_upper)?;
+        // amount_a * sqrt_lower * sqrt_upper / (sqrt_upper - sqrt_lower)
+        let liquidity_a = |sqrt_price_lower: u128| {
+            let numerator = U256::from(amount_a)
+                .checked_mul(U256::from(sqrt_price_lower))?
+                .checked_mul(U256::from(sqrt_price_upper))?;
+            let denominator =
+                U256::from(sqrt_price_upper.checked_sub(sqrt_price_lower)?) << RESOLUTION;
+            u128::try_from(numerator.checked_div(denominator)?).ok()
+        };
+        // amount_b / (sqrt_upper - sqrt_lower)
+        let liquidity_b = |sqrt_price_upper: u128| {
+            let numerator = U256::from(amount_b) << RESOLUTION;
+            let denominator = U256::from(sqrt_price_upper.checked_sub(sqrt_price_lower)?);
+            u128::try_from(numerator.checked_div(denominator)?).ok()
+        };
+        if self.tick < tick_lower {
+            liquidity_a(sqrt_price_lower)
+        } else if self.tick < tick_upper {
+            Some(std::cmp::min(
+                liquidity_a(self.sqrt_price)?,
+                liquidity_b(self.sqrt_price)?,
+            ))
+        } else {
+            liquidity_b(sqrt_price_upper)
+        }
+    }
+
+    /// Moves the price across an initialized tick, switching the positions
+    /// it bounds in or out of the active liquidity
+    fn cross_tick(&mut self, index: i32, a_to_b: bool) -> Option<()> {
+        let position = self
+            .ticks
+            .binary_search_by_key(&index, |tick| tick.index)
+            .ok()?;
+        let tick = &mut self.ticks[position];
+        tick.fee_growth_outside_a = self
+            .fee_growth_global_a
+            .wrapping_sub(tick.fee_growth_outside_a);
+        tick.fee_growth_outside_b = self
+            .fee_growth_global_b
+            .wrapping_sub(tick.fee_growth_outside_b);
+        let liquidity_delta = if a_to_b {
+            tick.liquidity_net.checked_neg()?
+        } else {
+            tick.liquidity_net
+        };
+        self.liquidity = add_liquidity_delta(self.liquidity, liquidity_delta)?;
+        Some(())
+    }
+
+    /// Swaps `amount_in`, after fees, across as many ticks as needed,
+    /// returning the amount out.  The trade fee is shared between the ranges
+    /// the swap goes through, in proportion to the amount each one takes.
+    pub fn swap(
+        &mut self,
+        amount_in: u128,
+        trade_fee: u128,
+        a_to_b: bool,
+    ) -> Result<u128, SwapError> {
+        let mut amount_remaining = amount_in;
+        let mut trade_fee_remaining = trade_fee;
+        let mut amount_out = 0u128;
+        while amount_remaining > 0 {
+            let next_tick = if a_to_b {
+                self.ticks.iter().rev().find(|tick| tick.index <= self.tick)
+            } else {
+                self.ticks.iter().find(|tick| tick.index > self.tick)
+            }
+            .map(|tick| tick.index);
+            let sqrt_price_target = match next_tick {
+                Some(index) => sqrt_price_at_tick(index).ok_or(SwapError::CalculationFailure)?,
+                None if a_to_b => MIN_SQRT_PRICE,
+                None => MAX_SQRT_PRICE,
+            };
+            let step = compute_swap_step(
+                self.sqrt_price,
+                sqrt_price_target,
+                self.liquidity,
+                amount_remaining,
+                a_to_b,
+            )
+            .ok_or(SwapError::CalculationFailure)?;
+            amount_remaining = amount_remaining
+                .checked_sub(step.amount_in)
+                .ok_or(SwapError::CalculationFailure)?;
+            amount_out = amount_out
+                .checked_add(step.amount_out)
+                .ok_or(SwapError::CalculationFailure)?;
+
+            if step.amount_in > 0 {
+                let step_fee = if amount_remaining == 0 {
+                    trade_fee_remaining
+                } else {
+                    trade_fee
+                        .checked_mul(step.amount_in)
+                        .map(|fee| fee / amount_in)
+                        .ok_or(SwapError::CalculationFailure)?
+                };
+                trade_fee_remaining = trade_fee_remaining
+                    .checked_sub(step_fee)
+                    .ok_or(SwapError::CalculationFailure)?;
+                let fee_growth = (U256::from(step_fee) << RESOLUTION)
+                    .checked_div(U256::from(self.liquidity))
+                    .and_then(|fee_growth| u128::try_from(fee_growth).ok())
+                    .ok_or(SwapError::CalculationFailure)?;
+                if a_to_b {
+                    self.fee_growth_global_a = self.fee_growth_global_a.wrapping_add(fee_growth);
+                } else {
+                    self.fee_growth_global_b = self.fee_growth_global_b.wrapping_add(fee_growth);
+                }
+            }
+
+            self.sqrt_price = step.sqrt_price;
+            match next_tick {
+                Some(index) if step.sqrt_price == sqrt_price_target => {
+                    self.cross_tick(index, a_to_b)
+                        .ok_or(SwapError::CalculationFailure)?;
+                    self.tick = if a_to_b { index - 1 } else { index };
+                }
+                None if amount_remaining > 0 => return Err(SwapError::InsufficientLiquidity),
+                _ => {
+                    self.tick =
+                        tick_at_sqrt_price(step.sqrt_price).ok_or(SwapError::CalculationFailure)?;
+                }
+            }
+        }
+        Ok(amount_out)
+    }
+}
+
+impl IsInitialized for ConcentratedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for ConcentratedCurve {}
+impl Pack for ConcentratedCurve {
+    const LEN: usize = 72 + MAX_TICKS * Tick::LEN;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, ConcentratedCurve::LEN];
+        let (
+            tick_spacing,
+            sqrt_price,
+            tick,
+            liquidity,
+            fee_growth_global_a,
+            fee_growth_global_b,
+            tick_count,
+            ticks,
+        ) = mut_array_refs![output, 2, 16, 4, 16, 16, 16, 2, MAX_TICKS * Tick::LEN];
+        *tick_spacing = self.tick_spacing.to_le_bytes();
+        *sqrt_price = self.sqrt_price.to_le_bytes();
+        *tick = self.tick.to_le_bytes();
+        *liquidity = self.liquidity.to_le_bytes();
+        *fee_growth_global_a = self.fee_growth_global_a.to_le_bytes();
+        *fee_growth_global_b = self.fee_growth_global_b.to_le_bytes();
+        *tick_count = (self.ticks.len() as u16).to_le_bytes();
+        ticks.fill(0);
+        for (dst, tick) in ticks.chunks_exact_mut(Tick::LEN).zip(self.ticks.iter()) {
+            tick.pack_into_slice(dst);
+        }
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<ConcentratedCurve, ProgramError> {
+        let input = array_ref![input, 0, ConcentratedCurve::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            tick_spacing,
+            sqrt_price,
+            tick,
+            liquidity,
+            fee_growth_global_a,
+            fee_growth_global_b,
+            tick_count,
+            ticks,
+        ) = array_refs![input, 2, 16, 4, 16, 16, 16, 2, MAX_TICKS * Tick::LEN];
+        let tick_count = usize::from(u16::from_le_bytes(*tick_count));
+        if tick_count > MAX_TICKS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Self {
+            tick_spacing: u16::from_le_bytes(*tick_spacing),
+            sqrt_price: u128::from_le_bytes(*sqrt_price),
+            tick: i32::from_le_bytes(*tick),
+            liquidity: u128::from_le_bytes(*liquidity),
+            fee_growth_global_a: u128::from_le_bytes(*fee_growth_global_a),
+            fee_growth_global_b: u128::from_le_bytes(*fee_growth_global_b),
+            ticks: ticks
+                .chunks_exact(Tick::LEN)
+                .take(tick_count)
+                .map(Tick::unpack_from_slice)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Square root price of a tick in floating point, for comparison
+    fn float_sqrt_price(tick: i32) -> f64 {
+        1.0001f64.powf(f64::from(tick) / 2.0) * 2f64.powi(64)
+    }
+
+    #[test]
+    fn tick_bounds() {
+        assert_eq!(sqrt_price_at_tick(MIN_TICK), Some(MIN_SQRT_PRICE));
+        assert_eq!(sqrt_price_at_tick(MAX_TICK), Some(MAX_SQRT_PRICE));
+        assert_eq!(sqrt_price_at_tick(0), Some(1 << 64));
+        assert_eq!(sqrt_price_at_tick(MIN_TICK - 1), None);
+        assert_eq!(sqrt_price_at_tick(MAX_TICK + 1), None);
+        assert_eq!(tick_at_sqrt_price(MIN_SQRT_PRICE), Some(MIN_TICK));
+        assert_eq!(tick_at_sqrt_price(MAX_SQRT_PRICE), Some(MAX_TICK));
+        assert_eq!(tick_at_sqrt_price(1 << 64), Some(0));
+        assert_eq!(tick_at_sqrt_price((1 << 64) - 1), Some(-1));
+        assert_eq!(tick_at_sqrt_price(MIN_SQRT_PRICE - 1), None);
+        assert_eq!(tick_at_sqrt_price(MAX_SQRT_PRICE + 1), None);
+    }
+
+    #[test]
+    fn amounts_round_towards_pool() {
+        let sqrt_price_lower = sqrt_price_at_tick(-100).unwrap();
+        let sqrt_price_upper = sqrt_price_at_tick(100).unwrap();
+        let liquidity = 1_000_000_007;
+        let a_floor = amount_a_delta(
+            sqrt_price_lower,
+            sqrt_price_upper,
+            liquidity,
+            RoundDirection::Floor,
+        )
+        .unwrap();
+        let a_ceiling = amount_a_delta(
+            sqrt_price_lower,
+            sqrt_price_upper,
+            liquidity,
+            RoundDirection::Ceiling,
+        )
+        .unwrap();
+        assert_eq!(a_ceiling, a_floor + 1);
+        // L * (1.0001^50 - 1.0001^-50) = 9_999_541.76
+        assert_eq!(a_floor, 9_999_541);
+        let b_floor = amount_b_delta(
+            sqrt_price_lower,
+            sqrt_price_upper,
+            liquidity,
+            RoundDirection::Floor,
+        )
+        .unwrap();
+        assert_eq!(b_floor, a_floor);
+    }
+
+    #[test]
+    fn swap_step_stops_at_target() {
+        let sqrt_price = 1 << 64;
+        let sqrt_price_target = sqrt_price_at_tick(-10).unwrap();
+        let liquidity = 1_000_000_000;
+        let step = compute_swap_step(
+            sqrt_price,
+            sqrt_price_target,
+            liquidity,
+            u64::MAX.into(),
+            true,
+        )
+        .unwrap();
+        assert_eq!(step.sqrt_price, sqrt_price_target);
+        assert_eq!(
+            step.amount_in,
+            amount_a_delta(
+                sqrt_price_target,
+                sqrt_price,
+                liquidity,
+                RoundDirection::Ceiling
+            )
+            .unwrap()
+        );
+        assert!(step.amount_out < step.amount_in);
+
+        let step =
+            compute_swap_step(sqrt_price, sqrt_price_target, liquidity, 1_000, true).unwrap();
+        assert!(step.sqrt_price > sqrt_price_target && step.sqrt_price < sqrt_price);
+        assert_eq!(step.amount_in, 1_000);
+        assert_eq!(step.amount_out, 999);
+
+        // without liquidity the price moves for free
+        let step = compute_swap_step(sqrt_price, sqrt_price_target, 0, 1_000, true).unwrap();
+        assert_eq!(step.sqrt_price, sqrt_price_target);
+        assert_eq!(step.amount_in, 0);
+        assert_eq!(step.amount_out, 0);
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let curve = ConcentratedCurve::new(10, 1 << 64).unwrap();
+        assert_eq!(curve.check_ticks(-10, 10), Ok(()));
+        assert_eq!(curve.check_ticks(10, 10), Err(SwapError::InvalidTickRange));
+        assert_eq!(curve.check_ticks(10, -10), Err(SwapError::InvalidTickRange));
+        assert_eq!(curve.check_ticks(-15, 10), Err(SwapError::InvalidTickRange));
+        assert_eq!(
+            curve.check_ticks(MIN_TICK - 4, 10),
+            Err(SwapError::InvalidTickRange)
+        );
+        assert_eq!(
+            ConcentratedCurve::new(0, 1 << 64),
+            Err(SwapError::InvalidCurve)
+        );
+        assert_eq!(
+            ConcentratedCurve::new(1, MAX_SQRT_PRICE + 1),
+            Err(SwapError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn liquidity_and_ticks() {
+        let mut curve = ConcentratedCurve::new(10, 1 << 64).unwrap();
+        assert_eq!(curve.update_liquidity(-100, 100, 1_000), Ok((0, 0)));
+        assert_eq!(curve.update_liquidity(0, 200, 500), Ok((0, 0)));
+        assert_eq!(curve.update_liquidity(200, 300, 100), Ok((0, 0)));
+        assert_eq!(curve.liquidity, 1_500);
+        assert_eq!(
+            curve
+                .ticks
+                .iter()
+                .map(|tick| (tick.index, tick.liquidity_net))
+                .collect::<Vec<_>>(),
+            vec![
+                (-100, 1_000),
+                (0, 500),
+                (100, -1_000),
+                (200, -400),
+                (300, -100)
+            ]
+        );
+
+        // emptied ticks are removed
+        curve.update_liquidity(200, 300, -100).unwrap();
+        curve.update_liquidity(0, 200, -500).unwrap();
+        assert_eq!(curve.liquidity, 1_000);
+        assert_eq!(
+            curve
+                .ticks
+                .iter()
+                .map(|tick| tick.index)
+                .collect::<Vec<_>>(),
+            vec![-100, 100]
+        );
+        assert_eq!(
+            curve.update_liquidity(-100, 100, -1_001),
+            Err(SwapError::CalculationFailure)
+        );
+
+        // the tick array is bounded
+        for i in 0..(MAX_TICKS as i32 - 2) / 2 {
+            curve
+                .update_liquidity(1_000 + 20 * i, 1_010 + 20 * i, 1)
+                .unwrap();
+        }
+        assert_eq!(curve.ticks.len(), MAX_TICKS);
+        assert_eq!(
+            curve.update_liquidity(-20, 20, 1),
+            Err(SwapError::TooManyTicks)
+        );
+        curve.update_liquidity(-100, 100, 1).unwrap();
+    }
+
+    #[test]
+    fn swap_across_ticks() {
+        let liquidity = 1_000_000_000;
+        let mut curve = ConcentratedCurve::new(10, 1 << 64).unwrap();
+        curve
+            .update_liquidity(-100, 100, liquidity as i128)
+            .unwrap();
+        curve
+            .update_liquidity(-200, -100, liquidity as i128)
+            .unwrap();
+        let amount_to_lower_tick = amount_a_delta(
+            sqrt_price_at_tick(-100).unwrap(),
+            1 << 64,
+            liquidity,
+            RoundDirection::Ceiling,
+        )
+        .unwrap();
+        let (_, amount_b) = curve
+            .token_amounts(-100, 100, liquidity, RoundDirection::Floor)
+            .unwrap();
+
+        // leaving the first range takes all of its token B
+        let amount_out = curve.swap(amount_to_lower_tick + 1_000, 100, true).unwrap();
+        assert!(curve.tick < -100 && curve.tick >= -200);
+        assert_eq!(curve.liquidity, liquidity);
+        assert!(amount_out > amount_b);
+        assert_eq!(
+            curve.fee_growth_inside(-100, 100).0 + curve.fee_growth_inside(-200, -100).0,
+            curve.fee_growth_global_a
+        );
+        assert!(curve.fee_growth_inside(-200, -100).0 > 0);
+        assert_eq!(curve.fee_growth_global_b, 0);
+
+        // past the last range there is nothing left to trade against
+        let mut drained = curve.clone();
+        assert_eq!(
+            drained.swap(u64::MAX.into(), 0, true),
+            Err(SwapError::InsufficientLiquidity)
+        );
+
+        // swapping back crosses into the first range again
+        let amount_back = curve.swap(amount_out + 1_000, 0, false).unwrap();
+        assert!(amount_back > amount_to_lower_tick);
+        assert!(curve.tick >= 0 && curve.tick < 100);
+        assert_eq!(curve.liquidity, liquidity);
+    }
+
+    #[test]
+    fn liquidity_for_amounts_rounds_down() {
+        let curve = ConcentratedCurve::new(1, sqrt_price_at_tick(50).unwrap()).unwrap();
+        for (tick_lower, tick_upper) in [(-100, 100), (100, 200), (-200, -100)].iter() {
+            let liquidity = curve
+                .liquidity_for_amounts(*tick_lower, *tick_upper, 1_000_000, 2_000_000)
+                .unwrap();
+            let (amount_a, amount_b) = curve
+                .token_amounts(*tick_lower, *tick_upper, liquidity, RoundDirection::Ceiling)
+                .unwrap();
+            assert!(amount_a <= 1_000_000 && amount_b <= 2_000_000);
+            assert!(amount_a >= 999_000 || amount_b >= 1_998_000);
+        }
+    }
+
+    #[test]
+    fn pack_curve() {
+        let mut curve = ConcentratedCurve::new(10, 1 << 64).unwrap();
+        curve.update_liquidity(-100, 100, 1_000).unwrap();
+        curve.fee_growth_global_a = u128::MAX;
+        let mut packed = [0u8; ConcentratedCurve::LEN];
+        ConcentratedCurve::pack(curve.clone(), &mut packed).unwrap();
+        assert_eq!(ConcentratedCurve::unpack(&packed).unwrap(), curve);
+        packed[70] = MAX_TICKS as u8 + 1;
+        assert_eq!(
+            ConcentratedCurve::unpack(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn sqrt_price_matches_float(tick in MIN_TICK..=MAX_TICK) {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap() as f64;
+            let expected = float_sqrt_price(tick);
+            prop_assert!((sqrt_price - expected).abs() / expected < 1e-9);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn tick_round_trip(tick in MIN_TICK..MAX_TICK) {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            let next_sqrt_price = sqrt_price_at_tick(tick + 1).unwrap();
+            prop_assert!(sqrt_price < next_sqrt_price);
+            prop_assert_eq!(tick_at_sqrt_price(sqrt_price), Some(tick));
+            prop_assert_eq!(tick_at_sqrt_price(next_sqrt_price - 1), Some(tick));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn swap_never_pays_out_more_than_held(
+            amount_in in 1..u64::MAX as u128 >> 16,
+            a_to_b in any::<bool>(),
+            tick in -1_000i32..1_000,
+        ) {
+            let mut curve = ConcentratedCurve::new(1, sqrt_price_at_tick(tick).unwrap()).unwrap();
+            let liquidity = 1_000_000_000_000u128;
+            curve.update_liquidity(-2_000, 2_000, liquidity as i128).unwrap();
+            curve.update_liquidity(-500, 500, liquidity as i128).unwrap();
+            let held = |curve: &ConcentratedCurve, round_direction| {
+                let (a0, b0) = curve
+                    .token_amounts(-2_000, 2_000, liquidity, round_direction)
+                    .unwrap();
+                let (a1, b1) = curve
+                    .token_amounts(-500, 500, liquidity, round_direction)
+                    .unwrap();
+                (a0 + a1, b0 + b1)
+            };
+            let (a_before, b_before) = held(&curve, RoundDirection::Ceiling);
+            let amount_out = match curve.swap(amount_in, 0, a_to_b) {
+                Ok(amount_out) => amount_out,
+                Err(error) => {
+                    prop_assert_eq!(error, SwapError::InsufficientLiquidity);
+                    return Ok(());
+                }
+            };
+            let (a_after, b_after) = held(&curve, RoundDirection::Floor);
+            // what positions can withdraw afterwards is covered by the pool
+            if a_to_b {
+                prop_assert!(a_after <= a_before + amount_in);
+                prop_assert!(b_after + amount_out <= b_before);
+            } else {
+                prop_assert!(b_after <= b_before + amount_in);
+                prop_assert!(a_after + amount_out <= a_before);
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
-//! Simple constant price swap curve, set at init
+//! Simple constant price swap curve, set at init, or pegged to an on-chain
+//! price feed
 use {
     crate::{
         curve::calculator::{
@@ -7,14 +8,20 @@ use {
         },
         error::SwapError,
     },
-    arrayref::{array_mut_ref, array_ref},
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
     solana_program::{
+        clock::UnixTimestamp,
         program_error::ProgramError,
         program_pack::{IsInitialized, Pack, Sealed},
+        pubkey::Pubkey,
     },
     spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber, uint::U256},
+    std::convert::{TryFrom, TryInto},
 };
 
+#[cfg(feature = "fuzz")]
+use arbitrary::Arbitrary;
+
 /// Get the amount of pool tokens for the given amount of token A or B.
 ///
 /// The constant product implementation uses the Balancer formulas found at
@@ -98,6 +105,35 @@ impl CurveCalculator for ConstantPriceCurve {
         })
     }
 
+    /// Constant price curve charges the exact price, rounding up the amount of
+    /// token B needed to buy a fraction of its worth of token A
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        _swap_source_amount: u128,
+        _swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let token_b_price = self.token_b_price as u128;
+
+        let source_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => destination_amount.checked_mul(token_b_price)?,
+            TradeDirection::BtoA => {
+                let mut source_amount_swapped = destination_amount.checked_div(token_b_price)?;
+                if destination_amount.checked_rem(token_b_price)? > 0 {
+                    source_amount_swapped = source_amount_swapped.checked_add(1)?;
+                }
+                source_amount_swapped
+            }
+        };
+        let source_amount_swapped = map_zero_to_none(source_amount_swapped)?;
+        let destination_amount_swapped = map_zero_to_none(destination_amount)?;
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        })
+    }
+
     /// Get the amount of trading tokens for the given amount of pool tokens,
     /// provided the total trading tokens and supply of pool tokens.
     /// For the constant price curve, the total value of the pool is weighted
@@ -259,18 +295,228 @@ impl DynPack for ConstantPriceCurve {
     }
 }
 
+/// Start of the price in a record account, after the version and authority
+/// written by the record program
+pub const RECORD_PRICE_OFFSET: usize = 33;
+
+/// The record program, which must own the account of a `PriceSource::Record`
+/// feed
+pub mod record_program {
+    solana_program::declare_id!("ReciQBw6sQKH9TVVJQDnbnJ5W7FP539tPHjZhRF4E9r");
+}
+
+/// Denominator of `PriceOracle::max_deviation_bps`
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// Kind of account a price oracle reads the price of token B from
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PriceSource {
+    /// Record account holding the amount of token A for 1 token B, then the
+    /// unix timestamp at which it was published, both little-endian, from
+    /// `RECORD_PRICE_OFFSET`
+    Record,
+    /// Token-swap pool whose token A is this pool's token B, and whose token
+    /// B is this pool's token A, using the time-weighted average price of its
+    /// token A
+    SwapTokenA,
+    /// Token-swap pool with the same token A and token B as this pool, using
+    /// the time-weighted average price of its token B
+    SwapTokenB,
+}
+
+impl Default for PriceSource {
+    fn default() -> Self {
+        PriceSource::Record
+    }
+}
+
+impl TryFrom<u8> for PriceSource {
+    type Error = ProgramError;
+
+    fn try_from(price_source: u8) -> Result<Self, Self::Error> {
+        match price_source {
+            0 => Ok(PriceSource::Record),
+            1 => Ok(PriceSource::SwapTokenA),
+            2 => Ok(PriceSource::SwapTokenB),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+/// Unpacks the price of token B and its publish timestamp from the data of a
+/// record account
+pub fn unpack_record_price(input: &[u8]) -> Option<(u64, UnixTimestamp)> {
+    let input = input.get(RECORD_PRICE_OFFSET..RECORD_PRICE_OFFSET + 16)?;
+    let (price, timestamp) = input.split_at(8);
+    Some((
+        u64::from_le_bytes(price.try_into().ok()?),
+        i64::from_le_bytes(timestamp.try_into().ok()?),
+    ))
+}
+
+/// Price feed pegging the price of a constant price curve.
+///
+/// Anyone may refresh the curve's `token_b_price` from the feed, as long as
+/// the new price is recent, and moves by at most `max_deviation_bps` from
+/// the current one.  Instructions trading at the curve price fail once the
+/// price is older than `max_staleness`, so a feed that stops updating halts
+/// the pool instead of leaving an outdated price to be arbitraged.
+///
+/// A feed that jumps by more than `max_deviation_bps` halts the pool the
+/// same way: every refresh is refused until the feed comes back within
+/// bounds of the curve price, or the admin sets the oracle again, which
+/// accepts the next recent price however far it moves.
+///
+/// The curve price is a whole amount of token A, so token-swap feeds are
+/// rounded to the nearest one, and a feed worth less than half a token A
+/// per token B cannot be refreshed from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PriceOracle {
+    /// Account holding the price feed, the default pubkey when the pool has
+    /// no oracle
+    pub price_account: Pubkey,
+    /// Kind of account holding the price feed
+    pub price_source: PriceSource,
+    /// Longest time, in seconds, a price may be traded at after it was
+    /// published
+    pub max_staleness: u64,
+    /// Largest change of price accepted by a refresh, in basis points of the
+    /// current price
+    pub max_deviation_bps: u64,
+    /// Unix timestamp at which the curve price was published by the feed,
+    /// zero until the first refresh
+    pub price_timestamp: UnixTimestamp,
+    /// Cumulative price of a token-swap feed as of `observation_timestamp`,
+    /// see `PriceObservation`
+    pub price_cumulative: u128,
+    /// Unix timestamp of the last observation of a token-swap feed
+    pub observation_timestamp: UnixTimestamp,
+}
+
+impl PriceOracle {
+    /// Is the curve price read from a price feed
+    pub fn is_enabled(&self) -> bool {
+        self.price_account != Pubkey::default()
+    }
+
+    /// Validate that the price can be refreshed and go stale
+    pub fn validate(&self) -> Result<(), SwapError> {
+        if self.max_staleness == 0 || self.max_deviation_bps == 0 {
+            Err(SwapError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Is a price published at `price_timestamp` too old to be traded at
+    /// `timestamp`
+    pub fn is_stale_at(&self, price_timestamp: UnixTimestamp, timestamp: UnixTimestamp) -> bool {
+        price_timestamp == 0
+            || u64::try_from(timestamp.saturating_sub(price_timestamp)).unwrap_or(0)
+                > self.max_staleness
+    }
+
+    /// Is the curve price too old to be traded at `timestamp`
+    pub fn is_stale(&self, timestamp: UnixTimestamp) -> bool {
+        self.is_stale_at(self.price_timestamp, timestamp)
+    }
+
+    /// Checks that a price published by the feed at `price_timestamp` can
+    /// replace `token_b_price` at `timestamp`.  The first price after the
+    /// oracle is set only needs to be recent.
+    pub fn check_price(
+        &self,
+        token_b_price: u64,
+        price: u64,
+        price_timestamp: UnixTimestamp,
+        timestamp: UnixTimestamp,
+    ) -> Result<(), SwapError> {
+        if price == 0 {
+            return Err(SwapError::InvalidOracleAccount);
+        }
+        if self.is_stale_at(price_timestamp, timestamp) {
+            return Err(SwapError::StaleOraclePrice);
+        }
+        if self.price_timestamp != 0 {
+            let deviation = u128::from(price.max(token_b_price) - price.min(token_b_price));
+            if deviation * u128::from(BASIS_POINTS)
+                > u128::from(self.max_deviation_bps) * u128::from(token_b_price)
+            {
+                return Err(SwapError::OraclePriceDeviation);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Sealed for PriceOracle {}
+impl IsInitialized for PriceOracle {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for PriceOracle {
+    const LEN: usize = 81;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 81];
+        let (
+            price_account,
+            price_source,
+            max_staleness,
+            max_deviation_bps,
+            price_timestamp,
+            price_cumulative,
+            observation_timestamp,
+        ) = mut_array_refs![output, 32, 1, 8, 8, 8, 16, 8];
+        price_account.copy_from_slice(self.price_account.as_ref());
+        price_source[0] = self.price_source as u8;
+        *max_staleness = self.max_staleness.to_le_bytes();
+        *max_deviation_bps = self.max_deviation_bps.to_le_bytes();
+        *price_timestamp = self.price_timestamp.to_le_bytes();
+        *price_cumulative = self.price_cumulative.to_le_bytes();
+        *observation_timestamp = self.observation_timestamp.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<PriceOracle, ProgramError> {
+        let input = array_ref![input, 0, 81];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            price_account,
+            price_source,
+            max_staleness,
+            max_deviation_bps,
+            price_timestamp,
+            price_cumulative,
+            observation_timestamp,
+        ) = array_refs![input, 32, 1, 8, 8, 8, 16, 8];
+        Ok(Self {
+            price_account: Pubkey::new_from_array(*price_account),
+            price_source: price_source[0].try_into()?,
+            max_staleness: u64::from_le_bytes(*max_staleness),
+            max_deviation_bps: u64::from_le_bytes(*max_deviation_bps),
+            price_timestamp: i64::from_le_bytes(*price_timestamp),
+            price_cumulative: u128::from_le_bytes(*price_cumulative),
+            observation_timestamp: i64::from_le_bytes(*observation_timestamp),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::curve::calculator::{
         test::{
-            check_curve_value_from_swap, check_deposit_token_conversion,
-            check_withdraw_token_conversion, total_and_intermediate,
-            CONVERSION_BASIS_POINTS_GUARANTEE,
+            check_curve_against_model, check_curve_value_from_swap,
+            check_curve_value_from_swap_exact_out, check_deposit_token_conversion,
+            check_normalized_value_against_model, check_withdraw_token_conversion,
+            total_and_intermediate, CONVERSION_BASIS_POINTS_GUARANTEE,
         },
         INITIAL_SWAP_POOL_AMOUNT,
     };
     use proptest::prelude::*;
+    use sim::CurveModel;
 
     #[test]
     fn swap_calculation_no_price() {
@@ -385,6 +631,143 @@ mod tests {
         assert_eq!(result.destination_amount_swapped, 1u128);
     }
 
+    #[test]
+    fn swap_exact_out_calculation_large_price() {
+        let token_b_price = 1123513u128;
+        let curve = ConstantPriceCurve {
+            token_b_price: token_b_price as u64,
+        };
+        let token_b_amount = 500u128;
+        let token_a_amount = token_b_amount * token_b_price;
+        let bad_result = curve.swap_without_fees_exact_out(
+            0,
+            token_a_amount,
+            token_b_amount,
+            TradeDirection::AtoB,
+        );
+        assert!(bad_result.is_none());
+        let result = curve
+            .swap_without_fees_exact_out(1, token_a_amount, token_b_amount, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, token_b_price);
+        assert_eq!(result.destination_amount_swapped, 1u128);
+
+        // any fraction of token B's price in token A costs a whole token B
+        let result = curve
+            .swap_without_fees_exact_out(1, token_b_amount, token_a_amount, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 1u128);
+        assert_eq!(result.destination_amount_swapped, 1u128);
+        let result = curve
+            .swap_without_fees_exact_out(
+                token_b_price + 1,
+                token_b_amount,
+                token_a_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 2u128);
+        assert_eq!(result.destination_amount_swapped, token_b_price + 1);
+    }
+
+    #[test]
+    fn pack_price_oracle() {
+        let oracle = PriceOracle {
+            price_account: Pubkey::new_unique(),
+            price_source: PriceSource::SwapTokenB,
+            max_staleness: 600,
+            max_deviation_bps: 50,
+            price_timestamp: 1_650_000_000,
+            price_cumulative: u128::MAX - 1,
+            observation_timestamp: 1_650_000_100,
+        };
+
+        let mut packed = [0u8; PriceOracle::LEN];
+        Pack::pack_into_slice(&oracle, &mut packed[..]);
+        let unpacked = PriceOracle::unpack(&packed).unwrap();
+        assert_eq!(oracle, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(oracle.price_account.as_ref());
+        packed.push(2);
+        packed.extend_from_slice(&oracle.max_staleness.to_le_bytes());
+        packed.extend_from_slice(&oracle.max_deviation_bps.to_le_bytes());
+        packed.extend_from_slice(&oracle.price_timestamp.to_le_bytes());
+        packed.extend_from_slice(&oracle.price_cumulative.to_le_bytes());
+        packed.extend_from_slice(&oracle.observation_timestamp.to_le_bytes());
+        let unpacked = PriceOracle::unpack(&packed).unwrap();
+        assert_eq!(oracle, unpacked);
+
+        packed[32] = 3;
+        assert_eq!(
+            PriceOracle::unpack(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn record_price() {
+        let mut data = vec![1u8; RECORD_PRICE_OFFSET];
+        data.extend_from_slice(&1_051_000u64.to_le_bytes());
+        assert_eq!(unpack_record_price(&data), None);
+        data.extend_from_slice(&1_650_000_000i64.to_le_bytes());
+        assert_eq!(unpack_record_price(&data), Some((1_051_000, 1_650_000_000)));
+    }
+
+    #[test]
+    fn price_oracle_checks() {
+        let timestamp = 1_650_000_000;
+        let mut oracle = PriceOracle {
+            price_account: Pubkey::new_unique(),
+            max_staleness: 600,
+            max_deviation_bps: 100,
+            ..PriceOracle::default()
+        };
+        assert!(oracle.is_enabled());
+        assert_eq!(oracle.validate(), Ok(()));
+        assert!(!PriceOracle::default().is_enabled());
+        assert_eq!(
+            PriceOracle {
+                max_deviation_bps: 0,
+                ..oracle
+            }
+            .validate(),
+            Err(SwapError::InvalidCurve)
+        );
+
+        // stale until the first refresh, which only needs a recent price
+        assert!(oracle.is_stale(timestamp));
+        assert_eq!(
+            oracle.check_price(1_000_000, 2_000_000, timestamp - 600, timestamp),
+            Ok(())
+        );
+        assert_eq!(
+            oracle.check_price(1_000_000, 2_000_000, timestamp - 601, timestamp),
+            Err(SwapError::StaleOraclePrice)
+        );
+        assert_eq!(
+            oracle.check_price(1_000_000, 0, timestamp, timestamp),
+            Err(SwapError::InvalidOracleAccount)
+        );
+
+        // later prices move by at most 1%
+        oracle.price_timestamp = timestamp;
+        assert!(!oracle.is_stale(timestamp + 600));
+        assert!(oracle.is_stale(timestamp + 601));
+        for price in [990_000, 1_010_000] {
+            assert_eq!(
+                oracle.check_price(1_000_000, price, timestamp, timestamp),
+                Ok(())
+            );
+        }
+        for price in [989_999, 1_010_001] {
+            assert_eq!(
+                oracle.check_price(1_000_000, price, timestamp, timestamp),
+                Err(SwapError::OraclePriceDeviation)
+            );
+        }
+    }
+
     proptest! {
         #[test]
         fn deposit_token_conversion_a_to_b(
@@ -557,6 +940,34 @@ mod tests {
         }
     }
 
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap_exact_out(
+            destination_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            token_b_price in 1..u64::MAX,
+        ) {
+            let curve = ConstantPriceCurve { token_b_price };
+            // There must be enough tokens to get back on the other side
+            let destination_token_amount = destination_token_amount % swap_destination_amount + 1;
+            check_curve_value_from_swap_exact_out(
+                &curve,
+                destination_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::AtoB
+            );
+            check_curve_value_from_swap_exact_out(
+                &curve,
+                destination_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::BtoA
+            );
+        }
+    }
+
     proptest! {
         #[test]
         fn curve_value_does_not_decrease_from_deposit(
@@ -653,4 +1064,40 @@ mod tests {
             assert!(new_value.checked_mul(&pool_token_supply).unwrap().greater_than_or_equal(&value.checked_mul(&new_pool_token_supply).unwrap()));
         }
     }
+
+    proptest! {
+        #[test]
+        fn curve_matches_model(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+            token_b_price in 1..u32::MAX,
+        ) {
+            let curve = ConstantPriceCurve { token_b_price: token_b_price as u64 };
+            let model = CurveModel::ConstantPrice { token_b_price: token_b_price as u64 };
+            // pool token conversions round the normalized value down first,
+            // losing up to half a token of value
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_curve_against_model(
+                    &curve,
+                    &model,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                    pool_supply,
+                    2,
+                    1,
+                );
+            }
+            check_normalized_value_against_model(
+                &curve,
+                &model,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                1,
+            );
+        }
+    }
 }
@@ -45,6 +45,35 @@ pub fn swap(
     })
 }
 
+/// The constant product exact-out swap calculation, factored out of its class
+/// for reuse.
+///
+/// The new source amount is rounded up so that the invariant never decreases,
+/// and the pool can never be fully drained of the destination token.
+pub fn swap_exact_out(
+    destination_amount: u128,
+    swap_source_amount: u128,
+    swap_destination_amount: u128,
+) -> Option<SwapWithoutFeesResult> {
+    let invariant = swap_source_amount.checked_mul(swap_destination_amount)?;
+
+    let new_swap_destination_amount =
+        map_zero_to_none(swap_destination_amount.checked_sub(destination_amount)?)?;
+    let mut new_swap_source_amount = invariant.checked_div(new_swap_destination_amount)?;
+    if invariant.checked_rem(new_swap_destination_amount)? > 0 {
+        new_swap_source_amount = new_swap_source_amount.checked_add(1)?;
+    }
+
+    let source_amount_swapped =
+        map_zero_to_none(new_swap_source_amount.checked_sub(swap_source_amount)?)?;
+    let destination_amount_swapped = map_zero_to_none(destination_amount)?;
+
+    Some(SwapWithoutFeesResult {
+        source_amount_swapped,
+        destination_amount_swapped,
+    })
+}
+
 /// Get the amount of trading tokens for the given amount of pool tokens,
 /// provided the total trading tokens and supply of pool tokens.
 ///
@@ -182,6 +211,22 @@ impl CurveCalculator for ConstantProductCurve {
         swap(source_amount, swap_source_amount, swap_destination_amount)
     }
 
+    /// Constant product exact-out swap rounds the new source amount up to
+    /// keep x * y >= constant
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        _trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        swap_exact_out(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+        )
+    }
+
     /// The constant product implementation is a simple ratio calculation for how many
     /// trading tokens correspond to a certain number of pool tokens
     fn pool_tokens_to_trading_tokens(
@@ -278,14 +323,16 @@ mod tests {
     use super::*;
     use crate::curve::calculator::{
         test::{
-            check_curve_value_from_swap, check_deposit_token_conversion,
-            check_pool_value_from_deposit, check_pool_value_from_withdraw,
-            check_withdraw_token_conversion, total_and_intermediate,
-            CONVERSION_BASIS_POINTS_GUARANTEE,
+            check_curve_against_model, check_curve_value_from_swap,
+            check_curve_value_from_swap_exact_out, check_deposit_token_conversion,
+            check_normalized_value_against_model, check_pool_value_from_deposit,
+            check_pool_value_from_withdraw, check_withdraw_token_conversion,
+            total_and_intermediate, CONVERSION_BASIS_POINTS_GUARANTEE,
         },
         RoundDirection, INITIAL_SWAP_POOL_AMOUNT,
     };
     use proptest::prelude::*;
+    use sim::CurveModel;
 
     #[test]
     fn initial_pool_amount() {
@@ -414,6 +461,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn constant_product_swap_exact_out_rounding() {
+        let curve = ConstantProductCurve::default();
+
+        // can't drain the destination side
+        assert!(curve
+            .swap_without_fees_exact_out(30_000, 20_000, 30_000, TradeDirection::AtoB)
+            .is_none());
+
+        let tests: &[(u128, u128, u128, u128)] = &[
+            (1, 70_000_000_000, 4_000_000, 17_501), // spot: 1 * 70b / 4m = 17,500
+            (174_999, 4_000_000, 70_000_000_000, 10), // spot: 174,999 * 4m / 70b = 9.99
+            (6, 30_000 - 20, 10_000, 18),           // spot: 6 * 2.998 = 17.99
+            (14, 20_000, 30_000, 10),               // spot: 14 * 2 / 3 = 9.33
+            (15, 20_000 - 10, 30_000, 10),          // spot: 15 * 1.999 / 3 = 9.995
+            (49, 60_000, 30_000, 99),               // spot: 49 * 2 = 98
+            (48, 60_000, 30_000, 97),               // spot: 48 * 2 = 96
+        ];
+        for (
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            expected_source_amount,
+        ) in tests.iter()
+        {
+            let invariant = swap_source_amount * swap_destination_amount;
+            let result = curve
+                .swap_without_fees_exact_out(
+                    *destination_amount,
+                    *swap_source_amount,
+                    *swap_destination_amount,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            assert_eq!(result.source_amount_swapped, *expected_source_amount);
+            assert_eq!(result.destination_amount_swapped, *destination_amount);
+            let new_invariant = (swap_source_amount + result.source_amount_swapped)
+                * (swap_destination_amount - result.destination_amount_swapped);
+            assert!(new_invariant >= invariant);
+        }
+    }
+
     proptest! {
         #[test]
         fn deposit_token_conversion(
@@ -546,4 +635,86 @@ mod tests {
             );
         }
     }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap_exact_out(
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 2..u64::MAX,
+            destination_token_amount in 1..u64::MAX,
+        ) {
+            let destination_token_amount = destination_token_amount % (swap_destination_amount - 1) + 1;
+            let curve = ConstantProductCurve {};
+            check_curve_value_from_swap_exact_out(
+                &curve,
+                destination_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::AtoB
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn swap_exact_out_requires_minimum_source(
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 2..u64::MAX,
+            destination_token_amount in 1..u64::MAX,
+        ) {
+            let destination_token_amount = destination_token_amount % (swap_destination_amount - 1) + 1;
+            let curve = ConstantProductCurve {};
+            let result = curve.swap_without_fees_exact_out(
+                destination_token_amount as u128,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::AtoB,
+            ).unwrap();
+            // one less source token must not be enough for the same output
+            if let Some(exact_in) = curve.swap_without_fees(
+                result.source_amount_swapped - 1,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                TradeDirection::AtoB,
+            ) {
+                prop_assert!(exact_in.destination_amount_swapped < destination_token_amount as u128);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_matches_model(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+        ) {
+            let curve = ConstantProductCurve {};
+            // single-token deposits and withdrawals take `PreciseNumber` square
+            // roots of ratios only accurate to 1e-12, which gives errors of up
+            // to 1e-6 of the supply when withdrawing almost a whole reserve
+            let pool_token_tolerance = 1 + pool_supply / 500_000;
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_curve_against_model(
+                    &curve,
+                    &CurveModel::ConstantProduct,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                    pool_supply,
+                    1,
+                    pool_token_tolerance,
+                );
+            }
+            check_normalized_value_against_model(
+                &curve,
+                &CurveModel::ConstantProduct,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                1,
+            );
+        }
+    }
 }
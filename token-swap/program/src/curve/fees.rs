@@ -3,9 +3,11 @@
 use crate::error::SwapError;
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use solana_program::{
+    clock::UnixTimestamp,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
 };
+use spl_math::uint::U256;
 use std::convert::TryFrom;
 
 /// Encapsulates all fee information and calculations for swap operations
@@ -71,6 +73,32 @@ fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), SwapError>
     }
 }
 
+/// Check that `numerator / denominator` is at most `max_bps` basis points
+fn validate_fraction_at_most(
+    numerator: u64,
+    denominator: u64,
+    max_bps: u64,
+) -> Result<(), SwapError> {
+    if u128::from(numerator) * u128::from(BASIS_POINTS)
+        > u128::from(max_bps) * u128::from(denominator)
+    {
+        Err(SwapError::InvalidFee)
+    } else {
+        Ok(())
+    }
+}
+
+/// Basis points in a whole
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// Largest trade fee, and largest owner trade fee, that an admin can set on
+/// an existing pool, in basis points
+pub const MAX_UPDATED_TRADE_FEE_BPS: u64 = 1_000;
+
+/// Largest owner withdraw fee that an admin can set on an existing pool, in
+/// basis points
+pub const MAX_UPDATED_OWNER_WITHDRAW_FEE_BPS: u64 = 500;
+
 impl Fees {
     /// Calculate the withdraw fee in pool tokens
     pub fn owner_withdraw_fee(&self, pool_tokens: u128) -> Option<u128> {
@@ -99,6 +127,75 @@ impl Fees {
         )
     }
 
+    /// Calculate the total trading and owner trading fees in trading tokens
+    fn total_trading_fees(&self, trading_tokens: u128) -> Option<u128> {
+        self.trading_fee(trading_tokens)?
+            .checked_add(self.owner_trading_fee(trading_tokens)?)
+    }
+
+    /// Calculate the amount of trading tokens required so that
+    /// `post_fee_amount` is left after debiting the trading and owner trading
+    /// fees, the inverse of the fee calculation used by exact-out swaps.
+    pub fn pre_trading_fee_amount(&self, post_fee_amount: u128) -> Option<u128> {
+        if post_fee_amount == 0 {
+            return Some(0);
+        }
+        let trade_fee_numerator = u128::try_from(self.trade_fee_numerator).ok()?;
+        let trade_fee_denominator = u128::try_from(self.trade_fee_denominator).ok()?;
+        let owner_trade_fee_numerator = u128::try_from(self.owner_trade_fee_numerator).ok()?;
+        let owner_trade_fee_denominator = u128::try_from(self.owner_trade_fee_denominator).ok()?;
+        // combine both fee fractions into one
+        let (fee_numerator, fee_denominator) = if trade_fee_numerator == 0 {
+            (owner_trade_fee_numerator, owner_trade_fee_denominator)
+        } else if owner_trade_fee_numerator == 0 {
+            (trade_fee_numerator, trade_fee_denominator)
+        } else {
+            (
+                trade_fee_numerator
+                    .checked_mul(owner_trade_fee_denominator)?
+                    .checked_add(owner_trade_fee_numerator.checked_mul(trade_fee_denominator)?)?,
+                trade_fee_denominator.checked_mul(owner_trade_fee_denominator)?,
+            )
+        };
+        let mut pre_fee_amount = if fee_numerator == 0 {
+            post_fee_amount
+        } else {
+            let remaining = fee_denominator.checked_sub(fee_numerator)?;
+            post_fee_amount
+                .checked_mul(fee_denominator)?
+                .checked_add(remaining.checked_sub(1)?)?
+                .checked_div(remaining)?
+        };
+        // Each fee is floored separately, with a minimum of one token, so the
+        // combined estimate can be off by a few tokens in either direction
+        while pre_fee_amount.checked_sub(self.total_trading_fees(pre_fee_amount)?)?
+            < post_fee_amount
+        {
+            pre_fee_amount = pre_fee_amount.checked_add(1)?;
+        }
+        while pre_fee_amount > post_fee_amount {
+            let smaller_amount = pre_fee_amount.checked_sub(1)?;
+            let fees = self.total_trading_fees(smaller_amount)?;
+            if smaller_amount.saturating_sub(fees) < post_fee_amount {
+                break;
+            }
+            pre_fee_amount = smaller_amount;
+        }
+        Some(pre_fee_amount)
+    }
+
+    /// Calculate the trading fee of depositing or withdrawing a single token
+    /// type, incurred as if *half* the trading tokens were swapped for the
+    /// other side. Reference at:
+    /// https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BMath.sol#L117
+    pub fn single_token_type_trading_fee(&self, trading_tokens: u128) -> Option<u128> {
+        if trading_tokens == 0 {
+            return Some(0);
+        }
+        let half_trading_tokens = std::cmp::max(1, trading_tokens.checked_div(2)?);
+        self.trading_fee(half_trading_tokens)
+    }
+
     /// Calculate the host fee based on the owner fee, only used in production
     /// situations where a program is hosted by multiple frontends
     pub fn host_fee(&self, owner_fee: u128) -> Option<u128> {
@@ -123,6 +220,239 @@ impl Fees {
         validate_fraction(self.host_fee_numerator, self.host_fee_denominator)?;
         Ok(())
     }
+
+    /// Validate fees set on an existing pool, which liquidity providers
+    /// have already deposited into, against hard upper bounds
+    pub fn validate_update(&self) -> Result<(), SwapError> {
+        self.validate()?;
+        validate_fraction_at_most(
+            self.trade_fee_numerator,
+            self.trade_fee_denominator,
+            MAX_UPDATED_TRADE_FEE_BPS,
+        )?;
+        validate_fraction_at_most(
+            self.owner_trade_fee_numerator,
+            self.owner_trade_fee_denominator,
+            MAX_UPDATED_TRADE_FEE_BPS,
+        )?;
+        validate_fraction_at_most(
+            self.owner_withdraw_fee_numerator,
+            self.owner_withdraw_fee_denominator,
+            MAX_UPDATED_OWNER_WITHDRAW_FEE_BPS,
+        )
+    }
+}
+
+/// Volatility of a 100% change of the pool price
+pub const VOLATILITY_PRECISION: u64 = 1_000_000;
+
+/// Trade fee following the recent volatility of the pool price, from a
+/// minimum while the price is stable up to a maximum while it moves.
+///
+/// The volatility is the sum of the relative changes of the price given by
+/// the pool balances over every swap, decaying by half every
+/// `volatility_half_life` seconds.  While enabled, the trade fee numerator of
+/// the pool's `Fees` is replaced by the dynamic one before every trade, over
+/// the same trade fee denominator.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DynamicFee {
+    /// Trade fee numerator at zero volatility
+    pub min_trade_fee_numerator: u64,
+    /// Trade fee numerator at `max_volatility` and above
+    pub max_trade_fee_numerator: u64,
+    /// Volatility at which the maximum trade fee is reached, with
+    /// `VOLATILITY_PRECISION` for a 100% price change.  Zero when the
+    /// dynamic fee is disabled.
+    pub max_volatility: u64,
+    /// Seconds for the volatility to decay by half
+    pub volatility_half_life: u64,
+    /// Volatility as of the last update
+    pub volatility: u64,
+    /// Unix timestamp of the last update to the volatility
+    pub last_update_timestamp: UnixTimestamp,
+}
+
+impl DynamicFee {
+    /// Creates a dynamic fee without any volatility, checking that its trade
+    /// fees are valid over the trade fee denominator of `fees`
+    pub fn new(
+        min_trade_fee_numerator: u64,
+        max_trade_fee_numerator: u64,
+        max_volatility: u64,
+        volatility_half_life: u64,
+        fees: &Fees,
+    ) -> Result<Self, SwapError> {
+        let dynamic_fee = Self {
+            min_trade_fee_numerator,
+            max_trade_fee_numerator,
+            max_volatility,
+            volatility_half_life,
+            ..Self::default()
+        };
+        dynamic_fee.validate(fees)?;
+        Ok(dynamic_fee)
+    }
+
+    /// Is the trade fee set by the volatility
+    pub fn is_enabled(&self) -> bool {
+        self.max_volatility > 0
+    }
+
+    /// Validate that the trade fees are reasonable over the trade fee
+    /// denominator of `fees`, within the trade fee an admin can set with
+    /// `Fees::validate_update`, and the volatility can decay
+    pub fn validate(&self, fees: &Fees) -> Result<(), SwapError> {
+        if self.max_volatility == 0
+            || self.volatility_half_life == 0
+            || self.min_trade_fee_numerator > self.max_trade_fee_numerator
+        {
+            return Err(SwapError::InvalidFee);
+        }
+        validate_fraction(self.max_trade_fee_numerator, fees.trade_fee_denominator)?;
+        validate_fraction_at_most(
+            self.max_trade_fee_numerator,
+            fees.trade_fee_denominator,
+            MAX_UPDATED_TRADE_FEE_BPS,
+        )
+    }
+
+    /// Volatility at `timestamp`, halving every `volatility_half_life`
+    /// seconds since the last update, and decaying linearly in between
+    pub fn volatility_at(&self, timestamp: UnixTimestamp) -> u64 {
+        let elapsed = timestamp.saturating_sub(self.last_update_timestamp);
+        if elapsed <= 0 || self.volatility_half_life == 0 {
+            return self.volatility;
+        }
+        let elapsed = elapsed as u64;
+        let half_lives = elapsed / self.volatility_half_life;
+        if half_lives >= u64::from(u64::BITS) {
+            return 0;
+        }
+        let volatility = self.volatility >> half_lives;
+        let remainder = elapsed % self.volatility_half_life;
+        // at most half the volatility, so it fits back in a u64
+        let decay = u128::from(volatility) * u128::from(remainder)
+            / (2 * u128::from(self.volatility_half_life));
+        volatility - decay as u64
+    }
+
+    /// Trade fee numerator at the given volatility, moving linearly from the
+    /// minimum to the maximum as the volatility reaches `max_volatility`
+    pub fn trade_fee_numerator(&self, volatility: u64) -> u64 {
+        if !self.is_enabled() {
+            return self.min_trade_fee_numerator;
+        }
+        let volatility = std::cmp::min(volatility, self.max_volatility);
+        let fee_range = self
+            .max_trade_fee_numerator
+            .saturating_sub(self.min_trade_fee_numerator);
+        // at most the fee range, so it fits back in a u64
+        let increase =
+            u128::from(fee_range) * u128::from(volatility) / u128::from(self.max_volatility);
+        self.min_trade_fee_numerator + increase as u64
+    }
+
+    /// Decays the volatility up to `timestamp`, returning the trade fee
+    /// numerator to charge at that time
+    pub fn update(&mut self, timestamp: UnixTimestamp) -> u64 {
+        self.volatility = self.volatility_at(timestamp);
+        self.last_update_timestamp = std::cmp::max(self.last_update_timestamp, timestamp);
+        self.trade_fee_numerator(self.volatility)
+    }
+
+    /// Adds the change of the price given by the pool balances, from before
+    /// to after a swap, to the volatility
+    pub fn accumulate(
+        &mut self,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        new_token_a_amount: u64,
+        new_token_b_amount: u64,
+    ) {
+        self.volatility = self.volatility.saturating_add(relative_price_change(
+            token_a_amount,
+            token_b_amount,
+            new_token_a_amount,
+            new_token_b_amount,
+        ));
+    }
+}
+
+/// Relative change of the price of token A in token B, given by the pool
+/// balances, in `VOLATILITY_PRECISION` units.  Zero if either price is
+/// undefined.
+pub fn relative_price_change(
+    token_a_amount: u64,
+    token_b_amount: u64,
+    new_token_a_amount: u64,
+    new_token_b_amount: u64,
+) -> u64 {
+    // |b1 / a1 - b0 / a0| / (b0 / a0) = |b1 * a0 - b0 * a1| / (b0 * a1)
+    let old_value = U256::from(token_b_amount) * U256::from(new_token_a_amount);
+    let new_value = U256::from(new_token_b_amount) * U256::from(token_a_amount);
+    if old_value.is_zero() || new_value.is_zero() {
+        return 0;
+    }
+    let change = if new_value > old_value {
+        new_value - old_value
+    } else {
+        old_value - new_value
+    };
+    // the change is below 2 ^ 128, so this cannot overflow
+    let change = change * U256::from(VOLATILITY_PRECISION) / old_value;
+    if change > U256::from(u64::MAX) {
+        u64::MAX
+    } else {
+        change.as_u64()
+    }
+}
+
+impl Sealed for DynamicFee {}
+impl IsInitialized for DynamicFee {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Pack for DynamicFee {
+    const LEN: usize = 48;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 48];
+        let (
+            min_trade_fee_numerator,
+            max_trade_fee_numerator,
+            max_volatility,
+            volatility_half_life,
+            volatility,
+            last_update_timestamp,
+        ) = mut_array_refs![output, 8, 8, 8, 8, 8, 8];
+        *min_trade_fee_numerator = self.min_trade_fee_numerator.to_le_bytes();
+        *max_trade_fee_numerator = self.max_trade_fee_numerator.to_le_bytes();
+        *max_volatility = self.max_volatility.to_le_bytes();
+        *volatility_half_life = self.volatility_half_life.to_le_bytes();
+        *volatility = self.volatility.to_le_bytes();
+        *last_update_timestamp = self.last_update_timestamp.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<DynamicFee, ProgramError> {
+        let input = array_ref![input, 0, 48];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            min_trade_fee_numerator,
+            max_trade_fee_numerator,
+            max_volatility,
+            volatility_half_life,
+            volatility,
+            last_update_timestamp,
+        ) = array_refs![input, 8, 8, 8, 8, 8, 8];
+        Ok(Self {
+            min_trade_fee_numerator: u64::from_le_bytes(*min_trade_fee_numerator),
+            max_trade_fee_numerator: u64::from_le_bytes(*max_trade_fee_numerator),
+            max_volatility: u64::from_le_bytes(*max_volatility),
+            volatility_half_life: u64::from_le_bytes(*volatility_half_life),
+            volatility: u64::from_le_bytes(*volatility),
+            last_update_timestamp: i64::from_le_bytes(*last_update_timestamp),
+        })
+    }
 }
 
 /// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
@@ -225,4 +555,169 @@ mod tests {
         let unpacked = Fees::unpack_from_slice(&packed).unwrap();
         assert_eq!(fees, unpacked);
     }
+
+    #[test]
+    fn pre_trading_fee_amount() {
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        assert_eq!(fees.pre_trading_fee_amount(0), Some(0));
+        // 10_000 * 10_000 / 9_970 = 10_030.09, but the fees of 25 and 5
+        // are each floored, so 10_030 is enough
+        assert_eq!(fees.pre_trading_fee_amount(10_000), Some(10_030));
+        // minimum fees of one token each
+        assert_eq!(fees.pre_trading_fee_amount(1), Some(3));
+
+        for post_fee_amount in 1..5_000 {
+            let pre_fee_amount = fees.pre_trading_fee_amount(post_fee_amount).unwrap();
+            let fee = fees.total_trading_fees(pre_fee_amount).unwrap();
+            assert!(pre_fee_amount - fee >= post_fee_amount);
+            let fee = fees.total_trading_fees(pre_fee_amount - 1).unwrap();
+            assert!(pre_fee_amount - 1 - fee < post_fee_amount);
+        }
+
+        let no_fees = Fees::default();
+        assert_eq!(no_fees.pre_trading_fee_amount(10_000), Some(10_000));
+
+        let all_fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 2,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 2,
+            ..Fees::default()
+        };
+        assert_eq!(all_fees.pre_trading_fee_amount(10_000), None);
+    }
+    #[test]
+    fn dynamic_fee() {
+        let fees = Fees {
+            trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        assert_eq!(
+            DynamicFee::new(10, 5, 1, 1, &fees),
+            Err(SwapError::InvalidFee)
+        );
+        assert_eq!(
+            DynamicFee::new(10, 10_000, 1, 1, &fees),
+            Err(SwapError::InvalidFee)
+        );
+        assert_eq!(
+            DynamicFee::new(10, MAX_UPDATED_TRADE_FEE_BPS + 1, 1, 1, &fees),
+            Err(SwapError::InvalidFee)
+        );
+        assert!(DynamicFee::new(10, MAX_UPDATED_TRADE_FEE_BPS, 1, 1, &fees).is_ok());
+        assert_eq!(
+            DynamicFee::new(5, 10, 0, 1, &fees),
+            Err(SwapError::InvalidFee)
+        );
+        assert_eq!(
+            DynamicFee::new(5, 10, 1, 0, &fees),
+            Err(SwapError::InvalidFee)
+        );
+        assert!(!DynamicFee::default().is_enabled());
+
+        let mut dynamic_fee = DynamicFee::new(10, 100, 50_000, 600, &fees).unwrap();
+        assert!(dynamic_fee.is_enabled());
+        assert_eq!(dynamic_fee.trade_fee_numerator(0), 10);
+        assert_eq!(dynamic_fee.trade_fee_numerator(25_000), 55);
+        assert_eq!(dynamic_fee.trade_fee_numerator(50_000), 100);
+        assert_eq!(dynamic_fee.trade_fee_numerator(u64::MAX), 100);
+
+        // the volatility halves every half life, linearly in between
+        dynamic_fee.volatility = 40_000;
+        dynamic_fee.last_update_timestamp = 1_000;
+        assert_eq!(dynamic_fee.volatility_at(900), 40_000);
+        assert_eq!(dynamic_fee.volatility_at(1_300), 30_000);
+        assert_eq!(dynamic_fee.volatility_at(1_600), 20_000);
+        assert_eq!(dynamic_fee.volatility_at(2_500), 7_500);
+        assert_eq!(dynamic_fee.volatility_at(1_000 + 64 * 600), 0);
+        assert_eq!(dynamic_fee.update(1_600), 10 + 90 * 20_000 / 50_000);
+        assert_eq!(dynamic_fee.volatility, 20_000);
+        assert_eq!(dynamic_fee.last_update_timestamp, 1_600);
+
+        // price changes add up in either direction
+        dynamic_fee.accumulate(1_000, 1_000, 1_000, 1_100);
+        assert_eq!(dynamic_fee.volatility, 120_000);
+        dynamic_fee.accumulate(1_000, 1_100, 1_100, 1_000);
+        assert_eq!(dynamic_fee.volatility, 120_000 + 173_553);
+        dynamic_fee.volatility = u64::MAX;
+        dynamic_fee.accumulate(1, 1, 1, 2);
+        assert_eq!(dynamic_fee.volatility, u64::MAX);
+    }
+
+    #[test]
+    fn validate_update_bounds() {
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 10,
+            owner_withdraw_fee_numerator: 5,
+            owner_withdraw_fee_denominator: 100,
+            host_fee_numerator: 1,
+            host_fee_denominator: 2,
+        };
+        assert_eq!(fees.validate_update(), Ok(()));
+        assert_eq!(Fees::default().validate_update(), Ok(()));
+
+        let fees_above_bounds = [
+            Fees {
+                trade_fee_numerator: 1_001,
+                trade_fee_denominator: 10_000,
+                ..fees.clone()
+            },
+            Fees {
+                owner_trade_fee_numerator: 1_001,
+                owner_trade_fee_denominator: 10_000,
+                ..fees.clone()
+            },
+            Fees {
+                owner_withdraw_fee_numerator: 501,
+                owner_withdraw_fee_denominator: 10_000,
+                ..fees.clone()
+            },
+        ];
+        for fees in fees_above_bounds {
+            assert_eq!(fees.validate_update(), Err(SwapError::InvalidFee));
+        }
+    }
+
+    #[test]
+    fn relative_price_changes() {
+        assert_eq!(relative_price_change(100, 100, 100, 100), 0);
+        assert_eq!(relative_price_change(100, 100, 100, 200), 1_000_000);
+        assert_eq!(relative_price_change(100, 100, 200, 100), 500_000);
+        assert_eq!(relative_price_change(0, 100, 200, 100), 0);
+        assert_eq!(relative_price_change(100, 0, 200, 100), 0);
+        assert_eq!(relative_price_change(u64::MAX, 1, 1, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn pack_dynamic_fee() {
+        let dynamic_fee = DynamicFee {
+            min_trade_fee_numerator: 1,
+            max_trade_fee_numerator: 2,
+            max_volatility: 3,
+            volatility_half_life: 4,
+            volatility: 5,
+            last_update_timestamp: -6,
+        };
+        let mut packed = [0u8; DynamicFee::LEN];
+        Pack::pack_into_slice(&dynamic_fee, &mut packed[..]);
+        let unpacked = DynamicFee::unpack_from_slice(&packed).unwrap();
+        assert_eq!(dynamic_fee, unpacked);
+
+        let mut packed = vec![];
+        for value in 1u64..=5 {
+            packed.extend_from_slice(&value.to_le_bytes());
+        }
+        packed.extend_from_slice(&(-6i64).to_le_bytes());
+        let unpacked = DynamicFee::unpack_from_slice(&packed).unwrap();
+        assert_eq!(dynamic_fee, unpacked);
+    }
 }
@@ -0,0 +1,453 @@
+//! The curve.fi invariant calculator for pools of more than two tokens.
+//!
+//! `StableCurve` implements `CurveCalculator`, which only knows about two
+//! reserves, so pools of up to `MAX_TOKENS` tokens use these functions on
+//! slices of reserves instead.  The math follows curve.fi's pool contracts,
+//! modelled exactly by the `sim` crate.
+
+use {crate::curve::fees::Fees, spl_math::uint::U256, std::convert::TryFrom};
+
+/// Fewest tokens in a multi-token stable pool
+pub const MIN_TOKENS: usize = 2;
+/// Most tokens in a multi-token stable pool
+pub const MAX_TOKENS: usize = 4;
+const ITERATIONS: u16 = 255;
+
+/// Calculates the leverage `A * n` for deriving D, see `stable::compute_a`
+/// for why the amp is only multiplied by n
+fn compute_leverage(amp: u64, n_coins: usize) -> Option<U256> {
+    U256::from(amp).checked_mul(U256::from(n_coins))
+}
+
+/// Whether two successive approximations are equal with the precision of 1
+fn converged(a: U256, b: U256) -> bool {
+    if a > b {
+        a - b <= U256::one()
+    } else {
+        b - a <= U256::one()
+    }
+}
+
+/// Compute stable swap invariant (D) of any number of reserves
+/// Equation:
+/// A * sum(x_i) * n**n + D = A * D * n**n + D**(n+1) / (n**n * prod(x_i))
+pub fn compute_d(leverage: U256, reserves: &[u128]) -> Option<u128> {
+    let n_coins = U256::from(reserves.len());
+    let sum_x = reserves
+        .iter()
+        .try_fold(U256::zero(), |sum, x| sum.checked_add(U256::from(*x)))?;
+    if sum_x.is_zero() {
+        return Some(0);
+    }
+    let mut d = sum_x;
+    // Newton's method to approximate D
+    for _ in 0..ITERATIONS {
+        let mut d_product = d;
+        for x in reserves {
+            d_product = d_product.checked_mul(d)?.checked_div(
+                U256::from(*x)
+                    .checked_mul(n_coins)?
+                    .checked_add(U256::one())?,
+            )?;
+        }
+        let d_previous = d;
+        // d = (leverage * sum_x + d_p * n_coins) * d / ((leverage - 1) * d + (n_coins + 1) * d_p)
+        let numerator = leverage
+            .checked_mul(sum_x)?
+            .checked_add(d_product.checked_mul(n_coins)?)?
+            .checked_mul(d)?;
+        let denominator = leverage
+            .checked_sub(U256::one())?
+            .checked_mul(d)?
+            .checked_add(n_coins.checked_add(U256::one())?.checked_mul(d_product)?)?;
+        d = numerator.checked_div(denominator)?;
+        if converged(d, d_previous) {
+            break;
+        }
+    }
+    u128::try_from(d).ok()
+}
+
+/// Compute the reserve `y` of token `j` that keeps the invariant at `d`,
+/// given the reserves of all other tokens.  `reserves[j]` is ignored.
+/// Solve for y:
+/// y**2 + y * (sum' - (A*n**n - 1) * D / (A * n**n)) = D ** (n + 1) / (n ** (2 * n) * prod' * A)
+/// y**2 + b*y = c
+pub fn compute_y(leverage: U256, reserves: &[u128], j: usize, d: u128) -> Option<u128> {
+    let n_coins = U256::from(reserves.len());
+    let d = U256::from(d);
+    let mut c = d;
+    let mut sum = U256::zero();
+    for (k, x) in reserves.iter().enumerate() {
+        if k != j {
+            let x = U256::from(*x);
+            sum = sum.checked_add(x)?;
+            c = c.checked_mul(d)?.checked_div(x.checked_mul(n_coins)?)?;
+        }
+    }
+    // c = D ** (n + 1) / (n ** n * prod' * A * n)
+    c = c
+        .checked_mul(d)?
+        .checked_div(leverage.checked_mul(n_coins)?)?;
+    // b = sum' + D / (A * n), the D is subtracted in the denominator below
+    let b = sum.checked_add(d.checked_div(leverage)?)?;
+
+    let mut y = d;
+    for _ in 0..ITERATIONS {
+        let y_previous = y;
+        // y = (y**2 + c) / (2 * y + b - D)
+        y = y.checked_mul(y)?.checked_add(c)?.checked_div(
+            y.checked_mul(U256::from(2u8))?
+                .checked_add(b)?
+                .checked_sub(d)?,
+        )?;
+        if converged(y, y_previous) {
+            break;
+        }
+    }
+    u128::try_from(y).ok()
+}
+
+/// Fee charged on the part of a deposit or withdrawal that moves the pool
+/// away from balance.  curve.fi charges `fee * n / (4 * (n - 1))`, which is
+/// what an equivalent swap would have paid.
+fn imbalance_fee(difference: u128, n_coins: usize, fees: &Fees) -> Option<u128> {
+    let n_coins = u128::try_from(n_coins).ok()?;
+    let scaled = difference
+        .checked_mul(n_coins)?
+        .checked_div(n_coins.checked_sub(1)?.checked_mul(4)?)?;
+    fees.trading_fee(scaled)
+}
+
+/// `amount * numerator / denominator` without overflowing in the product
+fn mul_div(amount: u128, numerator: u128, denominator: u128) -> Option<u128> {
+    let result = U256::from(amount)
+        .checked_mul(U256::from(numerator))?
+        .checked_div(U256::from(denominator))?;
+    u128::try_from(result).ok()
+}
+
+/// Stable curve over any number of tokens between `MIN_TOKENS` and
+/// `MAX_TOKENS`, sharing the amp convention of `StableCurve`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MultiStableCurve {
+    /// Amplifier constant
+    pub amp: u64,
+}
+
+impl MultiStableCurve {
+    fn leverage(&self, reserves: &[u128]) -> Option<U256> {
+        if !(MIN_TOKENS..=MAX_TOKENS).contains(&reserves.len()) {
+            return None;
+        }
+        compute_leverage(self.amp, reserves.len())
+    }
+
+    /// Amount of token `j` received for `source_amount` of token `i`, before
+    /// fees.  Rounds down by one token, in favor of the pool.
+    pub fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        i: usize,
+        j: usize,
+        reserves: &[u128],
+    ) -> Option<u128> {
+        if i == j || i >= reserves.len() || j >= reserves.len() {
+            return None;
+        }
+        let leverage = self.leverage(reserves)?;
+        let d = compute_d(leverage, reserves)?;
+        let mut new_reserves = reserves.to_vec();
+        new_reserves[i] = new_reserves[i].checked_add(source_amount)?;
+        let new_destination_amount = compute_y(leverage, &new_reserves, j, d)?;
+        Some(
+            reserves[j]
+                .checked_sub(new_destination_amount)?
+                .saturating_sub(1),
+        )
+    }
+
+    /// Pool tokens minted for depositing `amounts` of every token, with the
+    /// trading fee charged on the imbalance the deposit introduces, as in
+    /// curve.fi's `add_liquidity`.  Depositing a single token means zeroes
+    /// for all other amounts.
+    pub fn deposit(
+        &self,
+        amounts: &[u128],
+        reserves: &[u128],
+        pool_supply: u128,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if amounts.len() != reserves.len() {
+            return None;
+        }
+        let leverage = self.leverage(reserves)?;
+        let d0 = compute_d(leverage, reserves)?;
+        let new_reserves = reserves
+            .iter()
+            .zip(amounts)
+            .map(|(reserve, amount)| reserve.checked_add(*amount))
+            .collect::<Option<Vec<_>>>()?;
+        let d1 = compute_d(leverage, &new_reserves)?;
+        if d1 <= d0 {
+            return Some(0);
+        }
+        let adjusted_reserves = new_reserves
+            .iter()
+            .zip(reserves)
+            .map(|(new_reserve, reserve)| {
+                let ideal_reserve = mul_div(*reserve, d1, d0)?;
+                let difference = if ideal_reserve > *new_reserve {
+                    ideal_reserve - new_reserve
+                } else {
+                    new_reserve - ideal_reserve
+                };
+                new_reserve.checked_sub(imbalance_fee(difference, reserves.len(), fees)?)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let d2 = compute_d(leverage, &adjusted_reserves)?;
+        mul_div(pool_supply, d2.saturating_sub(d0), d0)
+    }
+
+    /// Amounts of every token received for burning `pool_tokens`, in
+    /// proportion to the reserves and rounded down
+    pub fn withdraw(
+        &self,
+        pool_tokens: u128,
+        pool_supply: u128,
+        reserves: &[u128],
+    ) -> Option<Vec<u128>> {
+        reserves
+            .iter()
+            .map(|reserve| mul_div(pool_tokens, *reserve, pool_supply))
+            .collect()
+    }
+
+    /// Amount of token `i` received for burning `pool_tokens`, with the
+    /// trading fee charged on the imbalance the withdrawal introduces, as in
+    /// curve.fi's `calc_withdraw_one_coin`.  Rounds down by one token, in
+    /// favor of the pool.
+    pub fn withdraw_one(
+        &self,
+        pool_tokens: u128,
+        i: usize,
+        reserves: &[u128],
+        pool_supply: u128,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if i >= reserves.len() {
+            return None;
+        }
+        let leverage = self.leverage(reserves)?;
+        let d0 = compute_d(leverage, reserves)?;
+        let d1 = d0.checked_sub(mul_div(pool_tokens, d0, pool_supply)?)?;
+        let new_y = compute_y(leverage, reserves, i, d1)?;
+        let reduced_reserves = reserves
+            .iter()
+            .enumerate()
+            .map(|(k, reserve)| {
+                let ideal_reserve = mul_div(*reserve, d1, d0)?;
+                let difference = if k == i {
+                    ideal_reserve.saturating_sub(new_y)
+                } else {
+                    reserve.saturating_sub(ideal_reserve)
+                };
+                reserve.checked_sub(imbalance_fee(difference, reserves.len(), fees)?)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let new_reserve = compute_y(leverage, &reduced_reserves, i, d1)?;
+        Some(
+            reduced_reserves[i]
+                .checked_sub(new_reserve)?
+                .saturating_sub(1),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{
+        calculator::{test::check_within_tolerance, CurveCalculator, TradeDirection},
+        stable::StableCurve,
+    };
+    use proptest::prelude::*;
+    use sim::StableSwapModel;
+
+    fn fees() -> Fees {
+        Fees {
+            trade_fee_numerator: 4,
+            trade_fee_denominator: 10_000,
+            ..Fees::default()
+        }
+    }
+
+    #[test]
+    fn unsupported_token_counts() {
+        let curve = MultiStableCurve { amp: 100 };
+        assert_eq!(curve.swap_without_fees(10, 0, 1, &[1_000]), None);
+        assert_eq!(curve.swap_without_fees(10, 0, 1, &[1_000; 5]), None);
+        assert_eq!(curve.swap_without_fees(10, 1, 1, &[1_000; 3]), None);
+        assert_eq!(curve.swap_without_fees(10, 0, 3, &[1_000; 3]), None);
+    }
+
+    #[test]
+    fn swap_balanced_pool() {
+        let curve = MultiStableCurve { amp: 100 };
+        let reserves = [1_000_000_000, 1_000_000_000, 1_000_000_000];
+        let amount = curve.swap_without_fees(1_000_000, 0, 2, &reserves).unwrap();
+        // close to par, but never more than was put in
+        assert!(amount < 1_000_000);
+        assert!(amount > 999_000);
+    }
+
+    #[test]
+    fn deposit_proportional() {
+        let curve = MultiStableCurve { amp: 100 };
+        let reserves = [1_000_000, 2_000_000, 3_000_000];
+        let supply = 6_000_000;
+        let minted = curve
+            .deposit(&[100_000, 200_000, 300_000], &reserves, supply, &fees())
+            .unwrap();
+        // balanced deposits only pay rounding fees
+        assert!(minted <= 600_000);
+        assert!(minted >= 599_990);
+    }
+
+    #[test]
+    fn deposit_single_sided_pays_fee() {
+        let curve = MultiStableCurve { amp: 100 };
+        let reserves = [1_000_000, 1_000_000, 1_000_000, 1_000_000];
+        let supply = 4_000_000;
+        let with_fees = curve
+            .deposit(&[0, 0, 100_000, 0], &reserves, supply, &fees())
+            .unwrap();
+        let without_fees = curve
+            .deposit(&[0, 0, 100_000, 0], &reserves, supply, &Fees::default())
+            .unwrap();
+        assert!(with_fees < without_fees);
+        assert!(without_fees <= 100_000);
+    }
+
+    #[test]
+    fn withdraw_proportional() {
+        let curve = MultiStableCurve { amp: 100 };
+        let reserves = [1_000, 2_000, 3_001];
+        assert_eq!(
+            curve.withdraw(10, 100, &reserves).unwrap(),
+            vec![100, 200, 300]
+        );
+        assert_eq!(curve.withdraw(10, 0, &reserves), None);
+    }
+
+    #[test]
+    fn withdraw_one_pays_fee() {
+        let curve = MultiStableCurve { amp: 100 };
+        let reserves = [1_000_000, 1_000_000, 1_000_000];
+        let supply = 3_000_000;
+        let with_fees = curve
+            .withdraw_one(30_000, 1, &reserves, supply, &fees())
+            .unwrap();
+        let without_fees = curve
+            .withdraw_one(30_000, 1, &reserves, supply, &Fees::default())
+            .unwrap();
+        assert!(with_fees < without_fees);
+        assert!(without_fees <= 30_000);
+    }
+
+    proptest! {
+        #[test]
+        fn two_tokens_match_stable_curve(
+            source_amount in 1..u64::MAX >> 4,
+            swap_source_amount in 1..u64::MAX >> 4,
+            swap_destination_amount in 1..u64::MAX >> 4,
+            amp in 1..100u64,
+        ) {
+            let curve = MultiStableCurve { amp };
+            let stable = StableCurve { amp };
+            let expected = stable
+                .swap_without_fees(
+                    source_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    TradeDirection::AtoB,
+                )
+                .unwrap()
+                .destination_amount_swapped;
+            let amount = curve
+                .swap_without_fees(
+                    source_amount as u128,
+                    0,
+                    1,
+                    &[swap_source_amount as u128, swap_destination_amount as u128],
+                )
+                .unwrap();
+            let difference = if amount > expected {
+                amount - expected
+            } else {
+                expected - amount
+            };
+            prop_assert!(difference <= 2);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn swap_no_fee(
+            source_amount in 1..u64::MAX >> 16,
+            base_reserve in 1_000_000..u64::MAX >> 16,
+            weights in prop::collection::vec(1..100u64, MIN_TOKENS..=MAX_TOKENS),
+            amp in 1..150u64,
+        ) {
+            let reserves = weights
+                .into_iter()
+                .map(|weight| base_reserve as u128 * weight as u128)
+                .collect::<Vec<_>>();
+            let j = reserves.len() - 1;
+            let curve = MultiStableCurve { amp };
+            let amount = curve
+                .swap_without_fees(source_amount as u128, 0, j, &reserves)
+                .unwrap();
+
+            let model = StableSwapModel::new(amp, reserves);
+            let expected = model.exchange(0, j, source_amount.into());
+            // the curve rounds down by one token and finds y to within one,
+            // and D to within one token, which moves y by the sensitivity
+            let sensitivity =
+                u128::try_from(model.reserve_sensitivity(j).ceil().to_integer()).unwrap();
+            let tolerance = 2 + sensitivity;
+            check_within_tolerance("swap", amount, &expected, tolerance);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn withdraw_one_no_fee(
+            base_reserve in 1_000_000..u64::MAX >> 16,
+            weights in prop::collection::vec(1..100u64, MIN_TOKENS..=MAX_TOKENS),
+            pool_supply in 1_000..u64::MAX >> 8,
+            amp in 1..150u64,
+        ) {
+            let reserves = weights
+                .into_iter()
+                .map(|weight| base_reserve as u128 * weight as u128)
+                .collect::<Vec<_>>();
+            let pool_tokens = pool_supply as u128 / 10;
+            let i = reserves.len() - 1;
+            let curve = MultiStableCurve { amp };
+            let amount = curve
+                .withdraw_one(pool_tokens, i, &reserves, pool_supply.into(), &Fees::default())
+                .unwrap();
+
+            let model = StableSwapModel::new(amp, reserves);
+            let expected = model.withdraw_one(i, pool_tokens, pool_supply.into());
+            // the curve rounds down by one token and finds y to within one,
+            // and the reduced D to within two tokens, which moves y by the
+            // sensitivity per token
+            let sensitivity =
+                u128::try_from(model.reserve_sensitivity(i).ceil().to_integer()).unwrap();
+            let tolerance = 2 + 2 * sensitivity;
+            check_within_tolerance("withdraw one", amount, &expected, tolerance);
+        }
+    }
+}
@@ -9,7 +9,7 @@ use {
             },
             constant_product::{
                 deposit_single_token_type, normalized_value, pool_tokens_to_trading_tokens, swap,
-                withdraw_single_token_type_exact_out,
+                swap_exact_out, withdraw_single_token_type_exact_out,
             },
         },
         error::SwapError,
@@ -56,6 +56,35 @@ impl CurveCalculator for OffsetCurve {
         swap(source_amount, swap_source_amount, swap_destination_amount)
     }
 
+    /// Constant product exact-out swap with the offset added to token B.
+    /// The offset is never paid out, so the destination amount must be
+    /// covered by the real destination liquidity.
+    fn swap_without_fees_exact_out(
+        &self,
+        destination_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        if destination_amount > swap_destination_amount {
+            return None;
+        }
+        let token_b_offset = self.token_b_offset as u128;
+        let swap_source_amount = match trade_direction {
+            TradeDirection::AtoB => swap_source_amount,
+            TradeDirection::BtoA => swap_source_amount.checked_add(token_b_offset)?,
+        };
+        let swap_destination_amount = match trade_direction {
+            TradeDirection::AtoB => swap_destination_amount.checked_add(token_b_offset)?,
+            TradeDirection::BtoA => swap_destination_amount,
+        };
+        swap_exact_out(
+            destination_amount,
+            swap_source_amount,
+            swap_destination_amount,
+        )
+    }
+
     /// The conversion for the offset curve needs to take into account the
     /// offset
     fn pool_tokens_to_trading_tokens(
@@ -188,14 +217,16 @@ mod tests {
     use super::*;
     use crate::curve::calculator::{
         test::{
-            check_curve_value_from_swap, check_deposit_token_conversion,
-            check_pool_value_from_deposit, check_pool_value_from_withdraw,
-            check_withdraw_token_conversion, total_and_intermediate,
-            CONVERSION_BASIS_POINTS_GUARANTEE,
+            check_curve_against_model, check_curve_value_from_swap,
+            check_curve_value_from_swap_exact_out, check_deposit_token_conversion,
+            check_normalized_value_against_model, check_pool_value_from_deposit,
+            check_pool_value_from_withdraw, check_withdraw_token_conversion,
+            total_and_intermediate, CONVERSION_BASIS_POINTS_GUARANTEE,
         },
         INITIAL_SWAP_POOL_AMOUNT,
     };
     use proptest::prelude::*;
+    use sim::CurveModel;
 
     #[test]
     fn pack_curve() {
@@ -509,6 +540,66 @@ mod tests {
         }
     }
 
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap_exact_out_a_to_b(
+            destination_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            token_b_offset in 1..u64::MAX,
+        ) {
+            let curve = OffsetCurve { token_b_offset };
+
+            let swap_source_amount = swap_source_amount as u128;
+            let swap_destination_amount = swap_destination_amount as u128;
+            let token_b_offset = token_b_offset as u128;
+
+            // The invariant needs to fit in a u128
+            // invariant = swap_source_amount * (swap_destination_amount + token_b_offset)
+            prop_assume!(!(swap_destination_amount + token_b_offset).overflowing_mul(swap_source_amount).1);
+
+            // Only the real token B liquidity can be paid out
+            let destination_token_amount = destination_token_amount as u128 % swap_destination_amount + 1;
+            check_curve_value_from_swap_exact_out(
+                &curve,
+                destination_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap_exact_out_b_to_a(
+            destination_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 2..u64::MAX,
+            token_b_offset in 1..u64::MAX,
+        ) {
+            let curve = OffsetCurve { token_b_offset };
+
+            let swap_source_amount = swap_source_amount as u128;
+            let swap_destination_amount = swap_destination_amount as u128;
+            let token_b_offset = token_b_offset as u128;
+
+            // The invariant needs to fit in a u128
+            // invariant = swap_destination_amount * (swap_source_amount + token_b_offset)
+            prop_assume!(!(swap_source_amount + token_b_offset).overflowing_mul(swap_destination_amount).1);
+
+            // Token A has no offset, so it can never be fully drained
+            let destination_token_amount = destination_token_amount as u128 % (swap_destination_amount - 1) + 1;
+            check_curve_value_from_swap_exact_out(
+                &curve,
+                destination_token_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::BtoA
+            );
+        }
+    }
+
     proptest! {
         #[test]
         fn curve_value_does_not_decrease_from_deposit(
@@ -578,4 +669,41 @@ mod tests {
             );
         }
     }
+
+    proptest! {
+        #[test]
+        fn curve_matches_model(
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+            pool_supply in INITIAL_SWAP_POOL_AMOUNT..u64::MAX as u128,
+            token_b_offset in 1..u64::MAX,
+        ) {
+            let curve = OffsetCurve { token_b_offset };
+            let model = CurveModel::Offset { token_b_offset };
+            // see the constant product curve for the error on single-token
+            // deposits and withdrawals
+            let pool_token_tolerance = 1 + pool_supply / 500_000;
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA] {
+                check_curve_against_model(
+                    &curve,
+                    &model,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    trade_direction,
+                    pool_supply,
+                    1,
+                    pool_token_tolerance,
+                );
+            }
+            check_normalized_value_against_model(
+                &curve,
+                &model,
+                swap_source_amount as u128,
+                swap_destination_amount as u128,
+                1,
+            );
+        }
+    }
 }
@@ -106,6 +106,49 @@ pub enum SwapError {
     /// The operation cannot be performed on the given curve
     #[error("The operation cannot be performed on the given curve")]
     UnsupportedCurveOperation,
+    /// Swaps and deposits are paused by the swap admin
+    #[error("Swaps and deposits are paused")]
+    SwapPaused,
+    /// The swap's state version does not support the operation
+    #[error("The operation is not supported by the swap's state version")]
+    UnsupportedSwapVersion,
+    /// The swap is locked by a flash loan in progress
+    #[error("The swap is locked by a flash loan in progress")]
+    SwapLocked,
+    /// The flash loan plus its fee was not repaid to the swap
+    #[error("The flash loan plus its fee was not repaid")]
+    FlashLoanNotRepaid,
+    /// The tokens of a multi-token stable swap have different decimals
+    #[error("The tokens of a multi-token stable swap must have the same decimals")]
+    MismatchedDecimals,
+    /// The position's ticks are unordered, out of bounds, or not on the
+    /// pool's tick spacing
+    #[error("The position's tick range is invalid for the pool")]
+    InvalidTickRange,
+    /// The concentrated liquidity pool has no room for another tick
+    #[error("The pool has no room left for another initialized tick")]
+    TooManyTicks,
+    /// The pool's liquidity ran out before the swap could be filled
+    #[error("The pool does not have enough liquidity to fill the swap")]
+    InsufficientLiquidity,
+    /// The position does not belong to the swap, or the position token does
+    /// not match the position
+    #[error("The position or position token does not match the swap")]
+    IncorrectPosition,
+    /// The position still holds liquidity or fees
+    #[error("The position still holds liquidity or uncollected fees")]
+    PositionNotEmpty,
+    /// The price account does not match the swap's oracle, or does not hold
+    /// a valid price
+    #[error("The price account does not match the oracle or holds no valid price")]
+    InvalidOracleAccount,
+    /// The oracle price is older than the oracle's maximum staleness
+    #[error("The oracle price is stale")]
+    StaleOraclePrice,
+    /// The new oracle price moves further from the current price than the
+    /// oracle's maximum deviation
+    #[error("The oracle price deviates too far from the current price")]
+    OraclePriceDeviation,
 }
 impl From<SwapError> for ProgramError {
     fn from(e: SwapError) -> Self {
@@ -186,6 +229,39 @@ impl PrintProgramError for SwapError {
             SwapError::UnsupportedCurveOperation => {
                 msg!("Error: The operation cannot be performed on the given curve")
             }
+            SwapError::SwapPaused => msg!("Error: Swaps and deposits are paused"),
+            SwapError::UnsupportedSwapVersion => {
+                msg!("Error: The operation is not supported by the swap's state version")
+            }
+            SwapError::SwapLocked => msg!("Error: The swap is locked by a flash loan in progress"),
+            SwapError::FlashLoanNotRepaid => {
+                msg!("Error: The flash loan plus its fee was not repaid")
+            }
+            SwapError::MismatchedDecimals => {
+                msg!("Error: The tokens of a multi-token stable swap must have the same decimals")
+            }
+            SwapError::InvalidTickRange => {
+                msg!("Error: The position's tick range is invalid for the pool")
+            }
+            SwapError::TooManyTicks => {
+                msg!("Error: The pool has no room left for another initialized tick")
+            }
+            SwapError::InsufficientLiquidity => {
+                msg!("Error: The pool does not have enough liquidity to fill the swap")
+            }
+            SwapError::IncorrectPosition => {
+                msg!("Error: The position or position token does not match the swap")
+            }
+            SwapError::PositionNotEmpty => {
+                msg!("Error: The position still holds liquidity or uncollected fees")
+            }
+            SwapError::InvalidOracleAccount => {
+                msg!("Error: The price account does not match the oracle or holds no valid price")
+            }
+            SwapError::StaleOraclePrice => msg!("Error: The oracle price is stale"),
+            SwapError::OraclePriceDeviation => {
+                msg!("Error: The oracle price deviates too far from the current price")
+            }
         }
     }
 }
@@ -2,13 +2,21 @@
 
 #![allow(clippy::too_many_arguments)]
 
-use crate::curve::{base::SwapCurve, fees::Fees};
+use crate::curve::{
+    base::SwapCurve,
+    calculator::TradeDirection,
+    constant_price::PriceSource,
+    fees::Fees,
+    multi_stable::{MAX_TOKENS, MIN_TOKENS},
+};
 use crate::error::SwapError;
 use solana_program::{
+    clock::UnixTimestamp,
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
+    system_program,
 };
 use std::convert::TryInto;
 use std::mem::size_of;
@@ -25,6 +33,9 @@ pub struct Initialize {
     /// swap curve info for pool, including CurveType and anything
     /// else that may be required
     pub swap_curve: SwapCurve,
+    /// fee tier of a canonical pool created at its registry address, or None
+    /// for a pool at a freshly generated keypair address
+    pub fee_tier: Option<u32>,
 }
 
 /// Swap instruction data
@@ -90,13 +101,372 @@ pub struct WithdrawSingleTokenTypeExactAmountOut {
     pub maximum_pool_token_amount: u64,
 }
 
+/// SwapExactAmountOut instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapExactAmountOut {
+    /// Exact amount of DESTINATION token to receive
+    pub amount_out: u64,
+    /// Maximum amount of SOURCE token to transfer, prevents excessive slippage
+    pub maximum_amount_in: u64,
+}
+
+/// RouteSwap instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteSwap {
+    /// SOURCE amount to transfer into the first pool of the route
+    pub amount_in: u64,
+    /// Minimum amount of the final DESTINATION token, prevents excessive
+    /// slippage over the whole route
+    pub minimum_amount_out: u64,
+}
+
+/// UpdateFees instruction data
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct UpdateFees {
+    /// new swap fees, replacing all of the current ones
+    pub fees: Fees,
+}
+
+/// SetPaused instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetPaused {
+    /// Whether swaps and deposits are paused.  Withdrawals are always allowed.
+    pub is_paused: bool,
+}
+
+/// RampAmp instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RampAmp {
+    /// Amplification coefficient to reach at the end of the ramp
+    pub target_amp: u64,
+    /// Unix timestamp at which the ramp reaches `target_amp`
+    pub stop_timestamp: UnixTimestamp,
+}
+
+/// SetDynamicFee instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetDynamicFee {
+    /// Trade fee numerator while the pool price is stable
+    pub min_trade_fee_numerator: u64,
+    /// Trade fee numerator once the volatility reaches `max_volatility`
+    pub max_trade_fee_numerator: u64,
+    /// Volatility at which the maximum trade fee is reached, see
+    /// `DynamicFee`.  Zero disables the dynamic fee.
+    pub max_volatility: u64,
+    /// Seconds for the volatility to decay by half
+    pub volatility_half_life: u64,
+}
+
+/// SetPriceOracle instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetPriceOracle {
+    /// Kind of account holding the price feed
+    pub price_source: PriceSource,
+    /// Longest time, in seconds, a price may be traded at after it was
+    /// published.  Zero removes the oracle.
+    pub max_staleness: u64,
+    /// Largest change of price accepted by a refresh, in basis points of the
+    /// current price
+    pub max_deviation_bps: u64,
+}
+
+/// FlashLoan instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlashLoan {
+    /// Amount of token A or B to lend
+    pub amount: u64,
+}
+
+/// InitializeMultiStable instruction data
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct InitializeMultiStable {
+    /// all swap fees
+    pub fees: Fees,
+    /// Amplifier constant of the stable curve
+    pub amp: u64,
+}
+
+/// DepositMultiStable instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepositMultiStable {
+    /// Amount of every token to deposit, in pool order.  Zero amounts are
+    /// skipped, so depositing a single token is a deposit of one non-zero
+    /// amount.
+    pub token_amounts: Vec<u64>,
+    /// Minimum amount of pool tokens to mint, prevents excessive slippage
+    pub minimum_pool_token_amount: u64,
+}
+
+/// WithdrawMultiStable instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawMultiStable {
+    /// Amount of pool tokens to burn.  Every token amount is set by the
+    /// current reserves and size of the pool
+    pub pool_token_amount: u64,
+    /// Minimum amount of every token to receive, in pool order, prevents
+    /// excessive slippage
+    pub minimum_token_amounts: Vec<u64>,
+}
+
+/// WithdrawOneMultiStable instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawOneMultiStable {
+    /// Amount of pool tokens to burn
+    pub pool_token_amount: u64,
+    /// Minimum amount of DESTINATION token to receive, prevents excessive
+    /// slippage
+    pub minimum_token_amount: u64,
+}
+
+/// InitializeConcentrated instruction data
+#[repr(C)]
+#[derive(Debug, PartialEq)]
+pub struct InitializeConcentrated {
+    /// all swap fees
+    pub fees: Fees,
+    /// Positions may only be bounded by multiples of the tick spacing
+    pub tick_spacing: u16,
+    /// Square root of the starting price of token A in token B, in Q64.64
+    pub initial_sqrt_price: u128,
+}
+
+/// OpenPosition instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenPosition {
+    /// Lower tick of the position's range
+    pub tick_lower: i32,
+    /// Upper tick of the position's range
+    pub tick_upper: i32,
+}
+
+/// IncreaseLiquidity instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct IncreaseLiquidity {
+    /// Liquidity to add to the position.  Token amounts are set by the
+    /// current price and the position's range
+    pub liquidity: u128,
+    /// Maximum token A amount to deposit, prevents excessive slippage
+    pub maximum_token_a_amount: u64,
+    /// Maximum token B amount to deposit, prevents excessive slippage
+    pub maximum_token_b_amount: u64,
+}
+
+/// DecreaseLiquidity instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecreaseLiquidity {
+    /// Liquidity to remove from the position, possibly zero to only collect
+    /// its fees
+    pub liquidity: u128,
+    /// Minimum token A amount to receive, fees included, prevents excessive
+    /// slippage
+    pub minimum_token_a_amount: u64,
+    /// Minimum token B amount to receive, fees included, prevents excessive
+    /// slippage
+    pub minimum_token_b_amount: u64,
+}
+
+/// Operation whose result is computed by a [Quote](struct.Quote.html)
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuoteOperation {
+    /// [Swap](struct.Swap.html) of `amount` SOURCE tokens
+    Swap,
+    /// [SwapExactAmountOut](struct.SwapExactAmountOut.html) receiving `amount`
+    /// DESTINATION tokens
+    SwapExactAmountOut,
+    /// [DepositSingleTokenTypeExactAmountIn](struct.DepositSingleTokenTypeExactAmountIn.html)
+    /// of `amount` SOURCE tokens
+    DepositSingleTokenTypeExactAmountIn,
+    /// [WithdrawSingleTokenTypeExactAmountOut](struct.WithdrawSingleTokenTypeExactAmountOut.html)
+    /// receiving `amount` DESTINATION tokens
+    WithdrawSingleTokenTypeExactAmountOut,
+}
+
+/// Quote instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quote {
+    /// Operation to quote
+    pub operation: QuoteOperation,
+    /// `AtoB` to swap token A for token B, or to deposit or withdraw token
+    /// A, and `BtoA` for the reverse
+    pub trade_direction: TradeDirection,
+    /// Amount of the operation, as given to its instruction
+    pub amount: u64,
+}
+
+/// Result of a [Quote](struct.Quote.html), set as the return data of the
+/// instruction
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QuoteResult {
+    /// Amount sent by the user, including any transfer fee: SOURCE tokens
+    /// for swaps and deposits, pool tokens for withdrawals
+    pub amount_in: u64,
+    /// Amount received by the user, after any transfer fee: DESTINATION
+    /// tokens for swaps and withdrawals, pool tokens for deposits
+    pub amount_out: u64,
+    /// Trading fee kept by the pool for liquidity providers, in the trading
+    /// token going into the pool, or coming out of it for withdrawals
+    pub trade_fee: u64,
+    /// Owner fee in pool tokens, including the host fee: the pool tokens
+    /// minted to the pool fee account by swaps, or the withdrawal fee
+    pub owner_fee: u64,
+    /// Part of the owner fee minted to the host fee account of a swap, if
+    /// one is given
+    pub host_fee: u64,
+    /// Token A balance of the pool after the operation
+    pub new_token_a_amount: u64,
+    /// Token B balance of the pool after the operation
+    pub new_token_b_amount: u64,
+}
+
+impl QuoteResult {
+    /// Length of a packed [QuoteResult](struct.QuoteResult.html)
+    pub const LEN: usize = 56;
+
+    /// Unpacks a byte buffer into a [QuoteResult](struct.QuoteResult.html).
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (amount_in, rest) = SwapInstruction::unpack_u64(input)?;
+        let (amount_out, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (trade_fee, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (owner_fee, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (host_fee, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (new_token_a_amount, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (new_token_b_amount, _rest) = SwapInstruction::unpack_u64(rest)?;
+        Ok(Self {
+            amount_in,
+            amount_out,
+            trade_fee,
+            owner_fee,
+            host_fee,
+            new_token_a_amount,
+            new_token_b_amount,
+        })
+    }
+
+    /// Packs a [QuoteResult](struct.QuoteResult.html) into a byte buffer.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::LEN);
+        buf.extend_from_slice(&self.amount_in.to_le_bytes());
+        buf.extend_from_slice(&self.amount_out.to_le_bytes());
+        buf.extend_from_slice(&self.trade_fee.to_le_bytes());
+        buf.extend_from_slice(&self.owner_fee.to_le_bytes());
+        buf.extend_from_slice(&self.host_fee.to_le_bytes());
+        buf.extend_from_slice(&self.new_token_a_amount.to_le_bytes());
+        buf.extend_from_slice(&self.new_token_b_amount.to_le_bytes());
+        buf
+    }
+}
+
+/// Instruction data the receiver program of a
+/// [FlashLoan](struct.FlashLoan.html) is invoked with
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceiveFlashLoan {
+    /// Amount lent to the receiver
+    pub amount: u64,
+    /// Fee to repay on top of the amount, kept by the pool
+    pub fee: u64,
+}
+
+impl ReceiveFlashLoan {
+    /// Instruction tag leading the receiver's instruction data
+    pub const TAG: u8 = 0;
+
+    /// Unpacks a byte buffer into a [ReceiveFlashLoan](struct.ReceiveFlashLoan.html).
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&tag, rest) = input.split_first().ok_or(SwapError::InvalidInstruction)?;
+        if tag != Self::TAG {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        let (amount, rest) = SwapInstruction::unpack_u64(rest)?;
+        let (fee, _rest) = SwapInstruction::unpack_u64(rest)?;
+        Ok(Self { amount, fee })
+    }
+
+    /// Packs a [ReceiveFlashLoan](struct.ReceiveFlashLoan.html) into a byte buffer.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(size_of::<Self>() + 1);
+        buf.push(Self::TAG);
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        buf.extend_from_slice(&self.fee.to_le_bytes());
+        buf
+    }
+}
+
+/// Accounts of one pool, or leg, of a [RouteSwap](struct.RouteSwap.html)
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteSwapLeg {
+    /// Token-swap
+    pub swap_pubkey: Pubkey,
+    /// Swap authority
+    pub authority_pubkey: Pubkey,
+    /// Swap account to swap INTO, holding the leg's SOURCE token
+    pub swap_source_pubkey: Pubkey,
+    /// Swap account to swap FROM, holding the leg's DESTINATION token
+    pub swap_destination_pubkey: Pubkey,
+    /// Pool token mint, to generate trading fees
+    pub pool_mint_pubkey: Pubkey,
+    /// Fee account, to receive trading fees
+    pub pool_fee_pubkey: Pubkey,
+    /// Leg SOURCE mint
+    pub source_mint_pubkey: Pubkey,
+    /// Leg DESTINATION mint
+    pub destination_mint_pubkey: Pubkey,
+    /// Leg SOURCE token program id
+    pub source_token_program_id: Pubkey,
+    /// Leg DESTINATION token program id
+    pub destination_token_program_id: Pubkey,
+    /// Pool token program id
+    pub pool_token_program_id: Pubkey,
+}
+
 /// Instructions supported by the token swap program.
 #[repr(C)]
 #[derive(Debug, PartialEq)]
 pub enum SwapInstruction {
-    ///   Initializes a new swap
+    ///   Initializes a new swap.  Swap accounts of `SwapVersion::LATEST_LEN`
+    ///   bytes track time-weighted prices, while accounts of
+    ///   `SwapVersion::V1_LEN` bytes use the original layout without them.
+    ///   With a fee tier, the swap account is instead created at the
+    ///   canonical address from `registry::find_pool_address`, so that each
+    ///   token pair, curve type and fee tier has a single canonical pool.
+    ///   Its token A mint must sort before its token B mint, and its trade
+    ///   fee always stays the one named by the fee tier.
     ///
-    ///   0. `[writable, signer]` New Token-swap to create.
+    ///   0. `[writable, signer]` New Token-swap to create, not a signer for
+    ///   a canonical pool.
     ///   1. `[]` swap authority derived from `create_program_address(&[Token-swap account])`
     ///   2. `[]` token_a Account. Must be non zero, owned by swap authority.
     ///   3. `[]` token_b Account. Must be non zero, owned by swap authority.
@@ -105,12 +475,15 @@ pub enum SwapInstruction {
     ///   Must be empty, not owned by swap authority
     ///   6. `[writable]` Pool Token Account to deposit the initial pool token
     ///   supply.  Must be empty, not owned by swap authority.
-    ///   7. `[]` Token program id
+    ///   7. `[]` Pool Token program id
+    ///   8. `[writable, signer]` Payer of the canonical pool account, only
+    ///   with a fee tier
+    ///   9. `[]` System program, only with a fee tier
     Initialize(Initialize),
 
     ///   Swap the tokens in the pool.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
@@ -119,15 +492,19 @@ pub enum SwapInstruction {
     ///   6. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
     ///   7. `[writable]` Pool token mint, to generate trading fees
     ///   8. `[writable]` Fee account, to receive trading fees
-    ///   9. `[]` Token program id
-    ///   10. `[optional, writable]` Host fee account to receive additional trading fees
+    ///   9. `[]` Token (A|B) SOURCE mint
+    ///   10. `[]` Token (A|B) DESTINATION mint
+    ///   11. `[]` Token (A|B) SOURCE program id
+    ///   12. `[]` Token (A|B) DESTINATION program id
+    ///   13. `[]` Pool Token program id
+    ///   14. `[optional, writable]` Host fee account to receive additional trading fees
     Swap(Swap),
 
     ///   Deposit both types of tokens into the pool.  The output is a "pool"
     ///   token representing ownership in the pool. Inputs are converted to
     ///   the current ratio.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` token_a user transfer authority can transfer amount,
@@ -136,14 +513,18 @@ pub enum SwapInstruction {
     ///   6. `[writable]` token_b Base Account to deposit into.
     ///   7. `[writable]` Pool MINT account, swap authority is the owner.
     ///   8. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
-    ///   9. `[]` Token program id
+    ///   9. `[]` Token A mint
+    ///   10. `[]` Token B mint
+    ///   11. `[]` Token A program id
+    ///   12. `[]` Token B program id
+    ///   13. `[]` Pool Token program id
     DepositAllTokenTypes(DepositAllTokenTypes),
 
     ///   Withdraw both types of tokens from the pool at the current ratio, given
     ///   pool tokens.  The pool tokens are burned in exchange for an equivalent
     ///   amount of token A and B.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` Pool mint account, swap authority is the owner
@@ -153,14 +534,18 @@ pub enum SwapInstruction {
     ///   7. `[writable]` token_a user Account to credit.
     ///   8. `[writable]` token_b user Account to credit.
     ///   9. `[writable]` Fee account, to receive withdrawal fees
-    ///   10. `[]` Token program id
+    ///   10. `[]` Token A mint
+    ///   11. `[]` Token B mint
+    ///   12. `[]` Pool Token program id
+    ///   13. `[]` Token A program id
+    ///   14. `[]` Token B program id
     WithdrawAllTokenTypes(WithdrawAllTokenTypes),
 
     ///   Deposit one type of tokens into the pool.  The output is a "pool" token
     ///   representing ownership into the pool. Input token is converted as if
     ///   a swap and deposit all token types were performed.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
@@ -168,13 +553,15 @@ pub enum SwapInstruction {
     ///   5. `[writable]` token_b Swap Account, may deposit INTO.
     ///   6. `[writable]` Pool MINT account, swap authority is the owner.
     ///   7. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
-    ///   8. `[]` Token program id
+    ///   8. `[]` Token (A|B) SOURCE mint
+    ///   9. `[]` Token (A|B) SOURCE program id
+    ///   10. `[]` Pool Token program id
     DepositSingleTokenTypeExactAmountIn(DepositSingleTokenTypeExactAmountIn),
 
     ///   Withdraw one token type from the pool at the current ratio given the
     ///   exact amount out expected.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` Pool mint account, swap authority is the owner
@@ -183,8 +570,350 @@ pub enum SwapInstruction {
     ///   6. `[writable]` token_b Swap Account to potentially withdraw from.
     ///   7. `[writable]` token_(A|B) User Account to credit
     ///   8. `[writable]` Fee account, to receive withdrawal fees
-    ///   9. `[]` Token program id
+    ///   9. `[]` Token (A|B) DESTINATION mint
+    ///   10. `[]` Pool Token program id
+    ///   11. `[]` Token (A|B) DESTINATION program id
     WithdrawSingleTokenTypeExactAmountOut(WithdrawSingleTokenTypeExactAmountOut),
+
+    ///   Swap the tokens in the pool, receiving an exact amount of the
+    ///   destination token.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   5. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   6. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
+    ///   7. `[writable]` Pool token mint, to generate trading fees
+    ///   8. `[writable]` Fee account, to receive trading fees
+    ///   9. `[]` Token (A|B) SOURCE mint
+    ///   10. `[]` Token (A|B) DESTINATION mint
+    ///   11. `[]` Token (A|B) SOURCE program id
+    ///   12. `[]` Token (A|B) DESTINATION program id
+    ///   13. `[]` Pool Token program id
+    ///   14. `[optional, writable]` Host fee account to receive additional trading fees
+    SwapExactAmountOut(SwapExactAmountOut),
+
+    ///   Swap through an ordered route of pools, A -> B -> C, in one
+    ///   instruction.  Each leg's DESTINATION token is moved straight from
+    ///   its pool into the next pool, so the user needs no intermediate
+    ///   token accounts, and slippage is only checked on the final amount.
+    ///
+    ///   0. `[]` user transfer authority
+    ///   1. `[writable]` SOURCE Account of the first leg, amount is transferable by user transfer authority,
+    ///   2. `[writable]` DESTINATION Account of the last leg, assigned to USER as the owner.
+    ///
+    ///   Followed by 11 accounts for each leg, in route order:
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the leg SOURCE token.
+    ///   3. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the leg DESTINATION token.
+    ///   4. `[writable]` Pool token mint, to generate trading fees
+    ///   5. `[writable]` Fee account, to receive trading fees
+    ///   6. `[]` Token (A|B) SOURCE mint, the previous leg's DESTINATION mint
+    ///   7. `[]` Token (A|B) DESTINATION mint
+    ///   8. `[]` Token (A|B) SOURCE program id
+    ///   9. `[]` Token (A|B) DESTINATION program id
+    ///   10. `[]` Pool Token program id
+    RouteSwap(RouteSwap),
+
+    ///   Replace the pool's fees.  The new fees must be valid, at most
+    ///   `MAX_UPDATED_TRADE_FEE_BPS` for the trade and owner trade fees and
+    ///   `MAX_UPDATED_OWNER_WITHDRAW_FEE_BPS` for the owner withdraw fee,
+    ///   within the bounds of the program's `SwapConstraints`, if any, and
+    ///   keep the trade fee named by the fee tier of a canonical pool.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` Swap admin, owner of the pool fee account, and the
+    ///   constraints owner if the program has constraints
+    ///   2. `[]` Pool fee account
+    UpdateFees(UpdateFees),
+
+    ///   Pause or unpause swaps and deposits.  Withdrawals are still allowed
+    ///   while paused, so liquidity providers can always exit.  Only
+    ///   supported by `SwapV2` pools.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` Swap admin, owner of the pool fee account, and the
+    ///   constraints owner if the program has constraints
+    ///   2. `[]` Pool fee account
+    SetPaused(SetPaused),
+
+    ///   Linearly ramp the amplification coefficient of a stable curve pool
+    ///   from its current value to a target value.  Only supported by
+    ///   `SwapV2` pools.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` Swap admin, owner of the pool fee account, and the
+    ///   constraints owner if the program has constraints
+    ///   2. `[]` Pool fee account
+    RampAmp(RampAmp),
+
+    ///   Move trading and withdrawal fees to a new pool fee account.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` Swap admin, owner of the current pool fee account, and
+    ///   the constraints owner if the program has constraints
+    ///   2. `[]` Current pool fee account
+    ///   3. `[]` New pool fee account.  Must be for the pool mint, not owned
+    ///   by swap authority.
+    SetFeeAccount,
+
+    ///   Lend token A or B from the pool to a receiver program, which is
+    ///   invoked with a `ReceiveFlashLoan` and must pay back the amount plus
+    ///   the trading fee before returning.  The fee stays in the pool for
+    ///   liquidity providers, and every instruction on the swap is refused
+    ///   while the loan is out.  Only supported by `SwapV2` pools.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` token_(A|B) Base Account to lend FROM, and to repay.
+    ///   3. `[writable]` token_(A|B) DESTINATION Account receiving the loan
+    ///   4. `[]` Token (A|B) mint
+    ///   5. `[]` Token (A|B) program id
+    ///   6. `[]` Flash loan receiver program id
+    ///   7. ..7+N. `[]` N accounts passed through to the receiver program
+    ///
+    ///   The receiver program is invoked with the accounts:
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[writable]` token_(A|B) DESTINATION Account holding the loan
+    ///   2. `[writable]` token_(A|B) Base Account to repay
+    ///   3. `[]` Token (A|B) mint
+    ///   4. `[]` Token (A|B) program id
+    ///   5. ..5+N. The N accounts passed through, with the same permissions
+    FlashLoan(FlashLoan),
+
+    ///   Initializes a new stable swap pool of between 2 and 4 tokens, all
+    ///   owned by the same token program.  The pool's tokens are given by the
+    ///   trailing accounts, in pool order, and must share their decimals.
+    ///
+    ///   0. `[writable, signer]` New multi-token stable swap to create.
+    ///   1. `[]` swap authority derived from `create_program_address(&[Token-swap account])`
+    ///   2. `[writable]` Pool Token Mint. Must be empty, owned by swap authority.
+    ///   3. `[]` Pool Token Account to deposit trading and withdraw fees.
+    ///   Must be empty, not owned by swap authority
+    ///   4. `[writable]` Pool Token Account to deposit the initial pool token
+    ///   supply.  Must be empty, not owned by swap authority.
+    ///   5. `[]` Token program id
+    ///   6. ..6+2N. `[]` N pairs of a token Account, non zero and owned by
+    ///   swap authority, and its mint
+    InitializeMultiStable(InitializeMultiStable),
+
+    ///   Swap any token of a multi-token stable swap for any other.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` DESTINATION Account assigned to USER as the owner.
+    ///   5. `[writable]` Pool token mint, to generate trading fees
+    ///   6. `[writable]` Fee account, to receive trading fees
+    ///   7. `[]` SOURCE mint
+    ///   8. `[]` DESTINATION mint
+    ///   9. `[]` Token program id
+    ///   10. ..10+N. `[writable]` The swap's N token Accounts, in pool order
+    SwapMultiStable(Swap),
+
+    ///   Deposit any amounts of the tokens of a multi-token stable swap.  The
+    ///   part of the deposit that unbalances the pool pays the trading fee.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` Pool MINT account, swap authority is the owner.
+    ///   4. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   5. `[]` Token program id
+    ///   6. ..6+3N. `[writable]` N triples of a SOURCE Account, amount is
+    ///   transferable by user transfer authority, the swap's token Account to
+    ///   deposit into, and `[]` their mint, in pool order
+    DepositMultiStable(DepositMultiStable),
+
+    ///   Withdraw every token of a multi-token stable swap in proportion to
+    ///   its reserves.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` Pool mint account, swap authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[writable]` Fee account, to receive withdrawal fees
+    ///   6. `[]` Token program id
+    ///   7. ..7+3N. `[writable]` N triples of the swap's token Account to
+    ///   withdraw from, the user Account to credit, and `[]` their mint, in
+    ///   pool order
+    WithdrawMultiStable(WithdrawMultiStable),
+
+    ///   Withdraw a single token of a multi-token stable swap.  The
+    ///   withdrawal unbalances the pool, so it pays the trading fee.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` Pool mint account, swap authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[writable]` Fee account, to receive withdrawal fees
+    ///   6. `[writable]` DESTINATION Account assigned to USER as the owner.
+    ///   7. `[]` DESTINATION mint
+    ///   8. `[]` Token program id
+    ///   9. ..9+N. `[writable]` The swap's N token Accounts, in pool order
+    WithdrawOneMultiStable(WithdrawOneMultiStable),
+
+    ///   Initializes a new concentrated liquidity swap.  Liquidity is added
+    ///   later by positions, so the token accounts may be empty.
+    ///
+    ///   0. `[writable, signer]` New concentrated liquidity swap to create.
+    ///   1. `[]` swap authority derived from `create_program_address(&[Token-swap account])`
+    ///   2. `[]` token_a Account. Must be owned by swap authority.
+    ///   3. `[]` token_b Account. Must be owned by swap authority.
+    ///   4. `[]` token_a mint
+    ///   5. `[]` token_b mint
+    ///   6. `[]` Fee owner, allowed to collect the owner trading fees
+    ///   7. `[]` Token program id
+    InitializeConcentrated(InitializeConcentrated),
+
+    ///   Opens an empty position in a concentrated liquidity swap, minting
+    ///   the single token that represents it.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable, signer]` New position to create, owned by the swap program.
+    ///   3. `[writable]` Position mint. Must be empty, with no decimals, owned
+    ///   by swap authority, and without a freeze authority.
+    ///   4. `[writable]` Position token Account to receive the position token,
+    ///   assigned to USER as the owner.
+    ///   5. `[]` Token program id
+    OpenPosition(OpenPosition),
+
+    ///   Adds liquidity to a position, depositing the token amounts it is
+    ///   worth at the current price.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` Position
+    ///   4. `[writable]` token_a SOURCE Account, amount is transferable by user transfer authority,
+    ///   5. `[writable]` token_b SOURCE Account, amount is transferable by user transfer authority,
+    ///   6. `[writable]` token_a Base Account to deposit into.
+    ///   7. `[writable]` token_b Base Account to deposit into.
+    ///   8. `[]` token_a mint
+    ///   9. `[]` token_b mint
+    ///   10. `[]` Token program id
+    IncreaseLiquidity(IncreaseLiquidity),
+
+    ///   Removes liquidity from a position, withdrawing the token amounts it
+    ///   is worth at the current price along with all of its fees.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` Position owner, owning the position token Account
+    ///   3. `[writable]` Position
+    ///   4. `[]` Position token Account, holding the position token
+    ///   5. `[writable]` token_a Swap Account to withdraw FROM.
+    ///   6. `[writable]` token_b Swap Account to withdraw FROM.
+    ///   7. `[writable]` token_a user Account to credit.
+    ///   8. `[writable]` token_b user Account to credit.
+    ///   9. `[]` token_a mint
+    ///   10. `[]` token_b mint
+    ///   11. `[]` Token program id
+    DecreaseLiquidity(DecreaseLiquidity),
+
+    ///   Closes a position without liquidity or fees, burning its token.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[signer]` Position owner, owning the position token Account
+    ///   2. `[writable]` Position
+    ///   3. `[writable]` Position mint
+    ///   4. `[writable]` Position token Account, holding the position token
+    ///   5. `[writable]` Account to receive the position's balance
+    ///   6. `[]` Token program id
+    ClosePosition,
+
+    ///   Swap token A for token B in a concentrated liquidity swap, or the
+    ///   reverse, crossing as many ticks as needed.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
+    ///   4. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   5. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   6. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
+    ///   7. `[]` Token (A|B) SOURCE mint
+    ///   8. `[]` Token (A|B) DESTINATION mint
+    ///   9. `[]` Token program id
+    SwapConcentrated(Swap),
+
+    ///   Transfers the owner trading fees held by a concentrated liquidity
+    ///   swap to the fee owner.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` Fee owner
+    ///   3. `[writable]` token_a Swap Account to withdraw FROM.
+    ///   4. `[writable]` token_b Swap Account to withdraw FROM.
+    ///   5. `[writable]` token_a Account to credit.
+    ///   6. `[writable]` token_b Account to credit.
+    ///   7. `[]` token_a mint
+    ///   8. `[]` token_b mint
+    ///   9. `[]` Token program id
+    CollectOwnerFees,
+
+    ///   Computes the result of a swap, exact-out swap, single token deposit
+    ///   or single token withdrawal on a two-token pool without moving any
+    ///   tokens.  The curve, trading fees and transfer fees are applied
+    ///   exactly as by the operation itself, which would fail with the same
+    ///   error as the quote, and the result is set as the return data, as a
+    ///   packed `QuoteResult`.  Slippage limits are not checked.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` token_a Swap Account
+    ///   2. `[]` token_b Swap Account
+    ///   3. `[]` Pool token mint
+    ///   4. `[]` token_a mint
+    ///   5. `[]` token_b mint
+    Quote(Quote),
+
+    ///   Make the trade fee follow the volatility of the pool price, between
+    ///   a minimum and a maximum numerator over the current trade fee
+    ///   denominator, or go back to the current fixed trade fee if
+    ///   `max_volatility` is zero.  The maximum is capped at
+    ///   `MAX_UPDATED_TRADE_FEE_BPS`, and both bounds must satisfy the
+    ///   program's `SwapConstraints`, if any, and be the trade fee named by
+    ///   the fee tier of a canonical pool.  Only supported by `SwapV2` pools.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` Swap admin, owner of the pool fee account, and the
+    ///   constraints owner if the program has constraints
+    ///   2. `[]` Pool fee account
+    SetDynamicFee(SetDynamicFee),
+
+    ///   Peg the price of a constant price curve to a price feed, or keep the
+    ///   current price fixed from now on if `max_staleness` is zero.  The
+    ///   pool cannot trade until the price is first refreshed from the feed.
+    ///   Only supported by `SwapV2` pools.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` Swap admin, owner of the pool fee account, and the
+    ///   constraints owner if the program has constraints
+    ///   2. `[]` Pool fee account
+    ///   3. `[]` Price account, a record account owned by the record program
+    ///   or another token-swap trading the same tokens, depending on the price
+    ///   source, ignored when removing the oracle
+    SetPriceOracle(SetPriceOracle),
+
+    ///   Set the price of a constant price curve from its price feed.  The
+    ///   price must be recent, and move by at most the oracle's maximum
+    ///   deviation.  Trading at the curve price fails once the last refreshed
+    ///   price is stale, so this is usually included ahead of trades.  Once
+    ///   the feed jumps beyond the maximum deviation, trading stays halted
+    ///   until it comes back in bounds or the admin sets the oracle again.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` Price account of the swap's oracle
+    RefreshOraclePrice,
 }
 
 impl SwapInstruction {
@@ -196,8 +925,23 @@ impl SwapInstruction {
                 if rest.len() >= Fees::LEN {
                     let (fees, rest) = rest.split_at(Fees::LEN);
                     let fees = Fees::unpack_unchecked(fees)?;
-                    let swap_curve = SwapCurve::unpack_unchecked(rest)?;
-                    Self::Initialize(Initialize { fees, swap_curve })
+                    if rest.len() < SwapCurve::LEN {
+                        return Err(SwapError::InvalidInstruction.into());
+                    }
+                    let (swap_curve, rest) = rest.split_at(SwapCurve::LEN);
+                    let swap_curve = SwapCurve::unpack_unchecked(swap_curve)?;
+                    let fee_tier = match rest.len() {
+                        0 => None,
+                        4 => Some(u32::from_le_bytes(
+                            rest.try_into().map_err(|_| SwapError::InvalidInstruction)?,
+                        )),
+                        _ => return Err(SwapError::InvalidInstruction.into()),
+                    };
+                    Self::Initialize(Initialize {
+                        fees,
+                        swap_curve,
+                        fee_tier,
+                    })
                 } else {
                     return Err(SwapError::InvalidInstruction.into());
                 }
@@ -246,10 +990,203 @@ impl SwapInstruction {
                     maximum_pool_token_amount,
                 })
             }
-            _ => return Err(SwapError::InvalidInstruction.into()),
-        })
-    }
-
+            6 => {
+                let (amount_out, rest) = Self::unpack_u64(rest)?;
+                let (maximum_amount_in, _rest) = Self::unpack_u64(rest)?;
+                Self::SwapExactAmountOut(SwapExactAmountOut {
+                    amount_out,
+                    maximum_amount_in,
+                })
+            }
+            7 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, _rest) = Self::unpack_u64(rest)?;
+                Self::RouteSwap(RouteSwap {
+                    amount_in,
+                    minimum_amount_out,
+                })
+            }
+            8 => {
+                if rest.len() >= Fees::LEN {
+                    let fees = Fees::unpack_unchecked(&rest[..Fees::LEN])?;
+                    Self::UpdateFees(UpdateFees { fees })
+                } else {
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+            }
+            9 => {
+                let is_paused = match rest.first() {
+                    Some(0) => false,
+                    Some(1) => true,
+                    _ => return Err(SwapError::InvalidInstruction.into()),
+                };
+                Self::SetPaused(SetPaused { is_paused })
+            }
+            10 => {
+                let (target_amp, rest) = Self::unpack_u64(rest)?;
+                let (stop_timestamp, _rest) = Self::unpack_u64(rest)?;
+                Self::RampAmp(RampAmp {
+                    target_amp,
+                    stop_timestamp: stop_timestamp as UnixTimestamp,
+                })
+            }
+            11 => Self::SetFeeAccount,
+            12 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::FlashLoan(FlashLoan { amount })
+            }
+            13 => {
+                if rest.len() >= Fees::LEN {
+                    let (fees, rest) = rest.split_at(Fees::LEN);
+                    let fees = Fees::unpack_unchecked(fees)?;
+                    let (amp, _rest) = Self::unpack_u64(rest)?;
+                    Self::InitializeMultiStable(InitializeMultiStable { fees, amp })
+                } else {
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+            }
+            14 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, _rest) = Self::unpack_u64(rest)?;
+                Self::SwapMultiStable(Swap {
+                    amount_in,
+                    minimum_amount_out,
+                })
+            }
+            15 => {
+                let (minimum_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let token_amounts = Self::unpack_token_amounts(rest)?;
+                Self::DepositMultiStable(DepositMultiStable {
+                    token_amounts,
+                    minimum_pool_token_amount,
+                })
+            }
+            16 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let minimum_token_amounts = Self::unpack_token_amounts(rest)?;
+                Self::WithdrawMultiStable(WithdrawMultiStable {
+                    pool_token_amount,
+                    minimum_token_amounts,
+                })
+            }
+            17 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::WithdrawOneMultiStable(WithdrawOneMultiStable {
+                    pool_token_amount,
+                    minimum_token_amount,
+                })
+            }
+            18 => {
+                if rest.len() >= Fees::LEN {
+                    let (fees, rest) = rest.split_at(Fees::LEN);
+                    let fees = Fees::unpack_unchecked(fees)?;
+                    let (tick_spacing, rest) = Self::unpack_u16(rest)?;
+                    let (initial_sqrt_price, _rest) = Self::unpack_u128(rest)?;
+                    Self::InitializeConcentrated(InitializeConcentrated {
+                        fees,
+                        tick_spacing,
+                        initial_sqrt_price,
+                    })
+                } else {
+                    return Err(SwapError::InvalidInstruction.into());
+                }
+            }
+            19 => {
+                let (tick_lower, rest) = Self::unpack_i32(rest)?;
+                let (tick_upper, _rest) = Self::unpack_i32(rest)?;
+                Self::OpenPosition(OpenPosition {
+                    tick_lower,
+                    tick_upper,
+                })
+            }
+            20 => {
+                let (liquidity, rest) = Self::unpack_u128(rest)?;
+                let (maximum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_b_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::IncreaseLiquidity(IncreaseLiquidity {
+                    liquidity,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                })
+            }
+            21 => {
+                let (liquidity, rest) = Self::unpack_u128(rest)?;
+                let (minimum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_b_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::DecreaseLiquidity(DecreaseLiquidity {
+                    liquidity,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                })
+            }
+            22 => Self::ClosePosition,
+            23 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, _rest) = Self::unpack_u64(rest)?;
+                Self::SwapConcentrated(Swap {
+                    amount_in,
+                    minimum_amount_out,
+                })
+            }
+            24 => Self::CollectOwnerFees,
+            25 => {
+                let (&operation, rest) = rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let operation = match operation {
+                    0 => QuoteOperation::Swap,
+                    1 => QuoteOperation::SwapExactAmountOut,
+                    2 => QuoteOperation::DepositSingleTokenTypeExactAmountIn,
+                    3 => QuoteOperation::WithdrawSingleTokenTypeExactAmountOut,
+                    _ => return Err(SwapError::InvalidInstruction.into()),
+                };
+                let (&trade_direction, rest) =
+                    rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let trade_direction = match trade_direction {
+                    0 => TradeDirection::AtoB,
+                    1 => TradeDirection::BtoA,
+                    _ => return Err(SwapError::InvalidInstruction.into()),
+                };
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::Quote(Quote {
+                    operation,
+                    trade_direction,
+                    amount,
+                })
+            }
+            26 => {
+                let (min_trade_fee_numerator, rest) = Self::unpack_u64(rest)?;
+                let (max_trade_fee_numerator, rest) = Self::unpack_u64(rest)?;
+                let (max_volatility, rest) = Self::unpack_u64(rest)?;
+                let (volatility_half_life, _rest) = Self::unpack_u64(rest)?;
+                Self::SetDynamicFee(SetDynamicFee {
+                    min_trade_fee_numerator,
+                    max_trade_fee_numerator,
+                    max_volatility,
+                    volatility_half_life,
+                })
+            }
+            27 => {
+                let (&price_source, rest) =
+                    rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let price_source = match price_source {
+                    0 => PriceSource::Record,
+                    1 => PriceSource::SwapTokenA,
+                    2 => PriceSource::SwapTokenB,
+                    _ => return Err(SwapError::InvalidInstruction.into()),
+                };
+                let (max_staleness, rest) = Self::unpack_u64(rest)?;
+                let (max_deviation_bps, _rest) = Self::unpack_u64(rest)?;
+                Self::SetPriceOracle(SetPriceOracle {
+                    price_source,
+                    max_staleness,
+                    max_deviation_bps,
+                })
+            }
+            28 => Self::RefreshOraclePrice,
+            _ => return Err(SwapError::InvalidInstruction.into()),
+        })
+    }
+
     fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
         if input.len() >= 8 {
             let (amount, rest) = input.split_at(8);
@@ -264,11 +1201,72 @@ impl SwapInstruction {
         }
     }
 
+    fn unpack_u16(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+        if input.len() >= 2 {
+            let (value, rest) = input.split_at(2);
+            let value = value
+                .get(..2)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u16::from_le_bytes)
+                .ok_or(SwapError::InvalidInstruction)?;
+            Ok((value, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_i32(input: &[u8]) -> Result<(i32, &[u8]), ProgramError> {
+        if input.len() >= 4 {
+            let (value, rest) = input.split_at(4);
+            let value = value
+                .get(..4)
+                .and_then(|slice| slice.try_into().ok())
+                .map(i32::from_le_bytes)
+                .ok_or(SwapError::InvalidInstruction)?;
+            Ok((value, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_u128(input: &[u8]) -> Result<(u128, &[u8]), ProgramError> {
+        if input.len() >= 16 {
+            let (value, rest) = input.split_at(16);
+            let value = value
+                .get(..16)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u128::from_le_bytes)
+                .ok_or(SwapError::InvalidInstruction)?;
+            Ok((value, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    /// Unpacks one amount per token of a multi-token pool, filling the rest
+    /// of the input
+    fn unpack_token_amounts(mut input: &[u8]) -> Result<Vec<u64>, ProgramError> {
+        let mut amounts = Vec::with_capacity(MAX_TOKENS);
+        while !input.is_empty() {
+            let (amount, rest) = Self::unpack_u64(input)?;
+            amounts.push(amount);
+            input = rest;
+        }
+        if !(MIN_TOKENS..=MAX_TOKENS).contains(&amounts.len()) {
+            return Err(SwapError::InvalidInstruction.into());
+        }
+        Ok(amounts)
+    }
+
     /// Packs a [SwapInstruction](enum.SwapInstruction.html) into a byte buffer.
     pub fn pack(&self) -> Vec<u8> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
         match &*self {
-            Self::Initialize(Initialize { fees, swap_curve }) => {
+            Self::Initialize(Initialize {
+                fees,
+                swap_curve,
+                fee_tier,
+            }) => {
                 buf.push(0);
                 let mut fees_slice = [0u8; Fees::LEN];
                 Pack::pack_into_slice(fees, &mut fees_slice[..]);
@@ -276,6 +1274,9 @@ impl SwapInstruction {
                 let mut swap_curve_slice = [0u8; SwapCurve::LEN];
                 Pack::pack_into_slice(swap_curve, &mut swap_curve_slice[..]);
                 buf.extend_from_slice(&swap_curve_slice);
+                if let Some(fee_tier) = fee_tier {
+                    buf.extend_from_slice(&fee_tier.to_le_bytes());
+                }
             }
             Self::Swap(Swap {
                 amount_in,
@@ -323,6 +1324,177 @@ impl SwapInstruction {
                 buf.extend_from_slice(&destination_token_amount.to_le_bytes());
                 buf.extend_from_slice(&maximum_pool_token_amount.to_le_bytes());
             }
+            Self::SwapExactAmountOut(SwapExactAmountOut {
+                amount_out,
+                maximum_amount_in,
+            }) => {
+                buf.push(6);
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+                buf.extend_from_slice(&maximum_amount_in.to_le_bytes());
+            }
+            Self::RouteSwap(RouteSwap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                buf.push(7);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+            }
+            Self::UpdateFees(UpdateFees { fees }) => {
+                buf.push(8);
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+            }
+            Self::SetPaused(SetPaused { is_paused }) => {
+                buf.push(9);
+                buf.push(*is_paused as u8);
+            }
+            Self::RampAmp(RampAmp {
+                target_amp,
+                stop_timestamp,
+            }) => {
+                buf.push(10);
+                buf.extend_from_slice(&target_amp.to_le_bytes());
+                buf.extend_from_slice(&stop_timestamp.to_le_bytes());
+            }
+            Self::SetFeeAccount => {
+                buf.push(11);
+            }
+            Self::FlashLoan(FlashLoan { amount }) => {
+                buf.push(12);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::InitializeMultiStable(InitializeMultiStable { fees, amp }) => {
+                buf.push(13);
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                buf.extend_from_slice(&amp.to_le_bytes());
+            }
+            Self::SwapMultiStable(Swap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                buf.push(14);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+            }
+            Self::DepositMultiStable(DepositMultiStable {
+                token_amounts,
+                minimum_pool_token_amount,
+            }) => {
+                buf.push(15);
+                buf.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+                for amount in token_amounts {
+                    buf.extend_from_slice(&amount.to_le_bytes());
+                }
+            }
+            Self::WithdrawMultiStable(WithdrawMultiStable {
+                pool_token_amount,
+                minimum_token_amounts,
+            }) => {
+                buf.push(16);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                for amount in minimum_token_amounts {
+                    buf.extend_from_slice(&amount.to_le_bytes());
+                }
+            }
+            Self::WithdrawOneMultiStable(WithdrawOneMultiStable {
+                pool_token_amount,
+                minimum_token_amount,
+            }) => {
+                buf.push(17);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_amount.to_le_bytes());
+            }
+            Self::InitializeConcentrated(InitializeConcentrated {
+                fees,
+                tick_spacing,
+                initial_sqrt_price,
+            }) => {
+                buf.push(18);
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                buf.extend_from_slice(&tick_spacing.to_le_bytes());
+                buf.extend_from_slice(&initial_sqrt_price.to_le_bytes());
+            }
+            Self::OpenPosition(OpenPosition {
+                tick_lower,
+                tick_upper,
+            }) => {
+                buf.push(19);
+                buf.extend_from_slice(&tick_lower.to_le_bytes());
+                buf.extend_from_slice(&tick_upper.to_le_bytes());
+            }
+            Self::IncreaseLiquidity(IncreaseLiquidity {
+                liquidity,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+            }) => {
+                buf.push(20);
+                buf.extend_from_slice(&liquidity.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_b_amount.to_le_bytes());
+            }
+            Self::DecreaseLiquidity(DecreaseLiquidity {
+                liquidity,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+            }) => {
+                buf.push(21);
+                buf.extend_from_slice(&liquidity.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_b_amount.to_le_bytes());
+            }
+            Self::ClosePosition => {
+                buf.push(22);
+            }
+            Self::SwapConcentrated(Swap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                buf.push(23);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+            }
+            Self::CollectOwnerFees => {
+                buf.push(24);
+            }
+            Self::Quote(Quote {
+                operation,
+                trade_direction,
+                amount,
+            }) => {
+                buf.push(25);
+                buf.push(*operation as u8);
+                buf.push(*trade_direction as u8);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::SetDynamicFee(SetDynamicFee {
+                min_trade_fee_numerator,
+                max_trade_fee_numerator,
+                max_volatility,
+                volatility_half_life,
+            }) => {
+                buf.push(26);
+                buf.extend_from_slice(&min_trade_fee_numerator.to_le_bytes());
+                buf.extend_from_slice(&max_trade_fee_numerator.to_le_bytes());
+                buf.extend_from_slice(&max_volatility.to_le_bytes());
+                buf.extend_from_slice(&volatility_half_life.to_le_bytes());
+            }
+            Self::SetPriceOracle(SetPriceOracle {
+                price_source,
+                max_staleness,
+                max_deviation_bps,
+            }) => {
+                buf.push(27);
+                buf.push(*price_source as u8);
+                buf.extend_from_slice(&max_staleness.to_le_bytes());
+                buf.extend_from_slice(&max_deviation_bps.to_le_bytes());
+            }
+            Self::RefreshOraclePrice => buf.push(28),
         }
         buf
     }
@@ -342,7 +1514,11 @@ pub fn initialize(
     fees: Fees,
     swap_curve: SwapCurve,
 ) -> Result<Instruction, ProgramError> {
-    let init_data = SwapInstruction::Initialize(Initialize { fees, swap_curve });
+    let init_data = SwapInstruction::Initialize(Initialize {
+        fees,
+        swap_curve,
+        fee_tier: None,
+    });
     let data = init_data.pack();
 
     let accounts = vec![
@@ -363,10 +1539,56 @@ pub fn initialize(
     })
 }
 
+/// Creates an 'initialize' instruction for a canonical pool, whose swap
+/// account is created at `registry::find_pool_address` and funded by the payer.
+pub fn initialize_canonical(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    token_a_pubkey: &Pubkey,
+    token_b_pubkey: &Pubkey,
+    pool_pubkey: &Pubkey,
+    fee_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    fees: Fees,
+    swap_curve: SwapCurve,
+    fee_tier: u32,
+) -> Result<Instruction, ProgramError> {
+    let init_data = SwapInstruction::Initialize(Initialize {
+        fees,
+        swap_curve,
+        fee_tier: Some(fee_tier),
+    });
+    let data = init_data.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*token_a_pubkey, false),
+        AccountMeta::new_readonly(*token_b_pubkey, false),
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new_readonly(*fee_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
 /// Creates a 'deposit_all_token_types' instruction.
 pub fn deposit_all_token_types(
     program_id: &Pubkey,
-    token_program_id: &Pubkey,
+    token_a_program_id: &Pubkey,
+    token_b_program_id: &Pubkey,
+    pool_token_program_id: &Pubkey,
     swap_pubkey: &Pubkey,
     authority_pubkey: &Pubkey,
     user_transfer_authority_pubkey: &Pubkey,
@@ -376,12 +1598,14 @@ pub fn deposit_all_token_types(
     swap_token_b_pubkey: &Pubkey,
     pool_mint_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
+    token_b_mint_pubkey: &Pubkey,
     instruction: DepositAllTokenTypes,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::DepositAllTokenTypes(instruction).pack();
 
     let accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*deposit_token_a_pubkey, false),
@@ -390,7 +1614,11 @@ pub fn deposit_all_token_types(
         AccountMeta::new(*swap_token_b_pubkey, false),
         AccountMeta::new(*pool_mint_pubkey, false),
         AccountMeta::new(*destination_pubkey, false),
-        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*token_a_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_b_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_a_program_id, false),
+        AccountMeta::new_readonly(*token_b_program_id, false),
+        AccountMeta::new_readonly(*pool_token_program_id, false),
     ];
 
     Ok(Instruction {
@@ -403,7 +1631,9 @@ pub fn deposit_all_token_types(
 /// Creates a 'withdraw_all_token_types' instruction.
 pub fn withdraw_all_token_types(
     program_id: &Pubkey,
-    token_program_id: &Pubkey,
+    pool_token_program_id: &Pubkey,
+    token_a_program_id: &Pubkey,
+    token_b_program_id: &Pubkey,
     swap_pubkey: &Pubkey,
     authority_pubkey: &Pubkey,
     user_transfer_authority_pubkey: &Pubkey,
@@ -414,12 +1644,14 @@ pub fn withdraw_all_token_types(
     swap_token_b_pubkey: &Pubkey,
     destination_token_a_pubkey: &Pubkey,
     destination_token_b_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
+    token_b_mint_pubkey: &Pubkey,
     instruction: WithdrawAllTokenTypes,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::WithdrawAllTokenTypes(instruction).pack();
 
     let accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*pool_mint_pubkey, false),
@@ -429,7 +1661,11 @@ pub fn withdraw_all_token_types(
         AccountMeta::new(*destination_token_a_pubkey, false),
         AccountMeta::new(*destination_token_b_pubkey, false),
         AccountMeta::new(*fee_account_pubkey, false),
-        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*token_a_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_b_mint_pubkey, false),
+        AccountMeta::new_readonly(*pool_token_program_id, false),
+        AccountMeta::new_readonly(*token_a_program_id, false),
+        AccountMeta::new_readonly(*token_b_program_id, false),
     ];
 
     Ok(Instruction {
@@ -442,7 +1678,8 @@ pub fn withdraw_all_token_types(
 /// Creates a 'deposit_single_token_type_exact_amount_in' instruction.
 pub fn deposit_single_token_type_exact_amount_in(
     program_id: &Pubkey,
-    token_program_id: &Pubkey,
+    source_token_program_id: &Pubkey,
+    pool_token_program_id: &Pubkey,
     swap_pubkey: &Pubkey,
     authority_pubkey: &Pubkey,
     user_transfer_authority_pubkey: &Pubkey,
@@ -451,12 +1688,13 @@ pub fn deposit_single_token_type_exact_amount_in(
     swap_token_b_pubkey: &Pubkey,
     pool_mint_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    source_mint_pubkey: &Pubkey,
     instruction: DepositSingleTokenTypeExactAmountIn,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::DepositSingleTokenTypeExactAmountIn(instruction).pack();
 
     let accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*source_token_pubkey, false),
@@ -464,7 +1702,9 @@ pub fn deposit_single_token_type_exact_amount_in(
         AccountMeta::new(*swap_token_b_pubkey, false),
         AccountMeta::new(*pool_mint_pubkey, false),
         AccountMeta::new(*destination_pubkey, false),
-        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*source_mint_pubkey, false),
+        AccountMeta::new_readonly(*source_token_program_id, false),
+        AccountMeta::new_readonly(*pool_token_program_id, false),
     ];
 
     Ok(Instruction {
@@ -477,7 +1717,8 @@ pub fn deposit_single_token_type_exact_amount_in(
 /// Creates a 'withdraw_single_token_type_exact_amount_out' instruction.
 pub fn withdraw_single_token_type_exact_amount_out(
     program_id: &Pubkey,
-    token_program_id: &Pubkey,
+    pool_token_program_id: &Pubkey,
+    destination_token_program_id: &Pubkey,
     swap_pubkey: &Pubkey,
     authority_pubkey: &Pubkey,
     user_transfer_authority_pubkey: &Pubkey,
@@ -487,12 +1728,13 @@ pub fn withdraw_single_token_type_exact_amount_out(
     swap_token_a_pubkey: &Pubkey,
     swap_token_b_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    destination_mint_pubkey: &Pubkey,
     instruction: WithdrawSingleTokenTypeExactAmountOut,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::WithdrawSingleTokenTypeExactAmountOut(instruction).pack();
 
     let accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*pool_mint_pubkey, false),
@@ -501,7 +1743,9 @@ pub fn withdraw_single_token_type_exact_amount_out(
         AccountMeta::new(*swap_token_b_pubkey, false),
         AccountMeta::new(*destination_pubkey, false),
         AccountMeta::new(*fee_account_pubkey, false),
-        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*destination_mint_pubkey, false),
+        AccountMeta::new_readonly(*pool_token_program_id, false),
+        AccountMeta::new_readonly(*destination_token_program_id, false),
     ];
 
     Ok(Instruction {
@@ -514,7 +1758,9 @@ pub fn withdraw_single_token_type_exact_amount_out(
 /// Creates a 'swap' instruction.
 pub fn swap(
     program_id: &Pubkey,
-    token_program_id: &Pubkey,
+    source_token_program_id: &Pubkey,
+    destination_token_program_id: &Pubkey,
+    pool_token_program_id: &Pubkey,
     swap_pubkey: &Pubkey,
     authority_pubkey: &Pubkey,
     user_transfer_authority_pubkey: &Pubkey,
@@ -524,13 +1770,15 @@ pub fn swap(
     destination_pubkey: &Pubkey,
     pool_mint_pubkey: &Pubkey,
     pool_fee_pubkey: &Pubkey,
+    source_mint_pubkey: &Pubkey,
+    destination_mint_pubkey: &Pubkey,
     host_fee_pubkey: Option<&Pubkey>,
     instruction: Swap,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::Swap(instruction).pack();
 
     let mut accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*source_pubkey, false),
@@ -539,7 +1787,11 @@ pub fn swap(
         AccountMeta::new(*destination_pubkey, false),
         AccountMeta::new(*pool_mint_pubkey, false),
         AccountMeta::new(*pool_fee_pubkey, false),
-        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*source_mint_pubkey, false),
+        AccountMeta::new_readonly(*destination_mint_pubkey, false),
+        AccountMeta::new_readonly(*source_token_program_id, false),
+        AccountMeta::new_readonly(*destination_token_program_id, false),
+        AccountMeta::new_readonly(*pool_token_program_id, false),
     ];
     if let Some(host_fee_pubkey) = host_fee_pubkey {
         accounts.push(AccountMeta::new(*host_fee_pubkey, false));
@@ -552,26 +1804,794 @@ pub fn swap(
     })
 }
 
-/// Unpacks a reference from a bytes buffer.
-/// TODO actually pack / unpack instead of relying on normal memory layout.
-pub fn unpack<T>(input: &[u8]) -> Result<&T, ProgramError> {
-    if input.len() < size_of::<u8>() + size_of::<T>() {
-        return Err(ProgramError::InvalidAccountData);
-    }
-    #[allow(clippy::cast_ptr_alignment)]
-    let val: &T = unsafe { &*(&input[1] as *const u8 as *const T) };
-    Ok(val)
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::curve::{base::CurveType, stable::StableCurve};
-    use std::sync::Arc;
+/// Creates a 'swap_exact_amount_out' instruction.
+pub fn swap_exact_amount_out(
+    program_id: &Pubkey,
+    source_token_program_id: &Pubkey,
+    destination_token_program_id: &Pubkey,
+    pool_token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    source_mint_pubkey: &Pubkey,
+    destination_mint_pubkey: &Pubkey,
+    host_fee_pubkey: Option<&Pubkey>,
+    instruction: SwapExactAmountOut,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SwapExactAmountOut(instruction).pack();
 
-    #[test]
-    fn pack_intialize() {
-        let trade_fee_numerator: u64 = 1;
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*pool_fee_pubkey, false),
+        AccountMeta::new_readonly(*source_mint_pubkey, false),
+        AccountMeta::new_readonly(*destination_mint_pubkey, false),
+        AccountMeta::new_readonly(*source_token_program_id, false),
+        AccountMeta::new_readonly(*destination_token_program_id, false),
+        AccountMeta::new_readonly(*pool_token_program_id, false),
+    ];
+    if let Some(host_fee_pubkey) = host_fee_pubkey {
+        accounts.push(AccountMeta::new(*host_fee_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'route_swap' instruction.
+pub fn route_swap(
+    program_id: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    legs: &[RouteSwapLeg],
+    instruction: RouteSwap,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::RouteSwap(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+    ];
+    for leg in legs {
+        accounts.extend_from_slice(&[
+            AccountMeta::new(leg.swap_pubkey, false),
+            AccountMeta::new_readonly(leg.authority_pubkey, false),
+            AccountMeta::new(leg.swap_source_pubkey, false),
+            AccountMeta::new(leg.swap_destination_pubkey, false),
+            AccountMeta::new(leg.pool_mint_pubkey, false),
+            AccountMeta::new(leg.pool_fee_pubkey, false),
+            AccountMeta::new_readonly(leg.source_mint_pubkey, false),
+            AccountMeta::new_readonly(leg.destination_mint_pubkey, false),
+            AccountMeta::new_readonly(leg.source_token_program_id, false),
+            AccountMeta::new_readonly(leg.destination_token_program_id, false),
+            AccountMeta::new_readonly(leg.pool_token_program_id, false),
+        ]);
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'update_fees' instruction.
+pub fn update_fees(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: UpdateFees,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::UpdateFees(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*pool_fee_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_paused' instruction.
+pub fn set_paused(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: SetPaused,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetPaused(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*pool_fee_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'ramp_amp' instruction.
+pub fn ramp_amp(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: RampAmp,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::RampAmp(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*pool_fee_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_fee_account' instruction.
+pub fn set_fee_account(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    new_pool_fee_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetFeeAccount.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*pool_fee_pubkey, false),
+        AccountMeta::new_readonly(*new_pool_fee_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'flash_loan' instruction.
+pub fn flash_loan(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    swap_token_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    token_mint_pubkey: &Pubkey,
+    receiver_program_id: &Pubkey,
+    receiver_accounts: &[AccountMeta],
+    instruction: FlashLoan,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::FlashLoan(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*swap_token_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*receiver_program_id, false),
+    ];
+    accounts.extend_from_slice(receiver_accounts);
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'initialize_multi_stable' instruction.
+pub fn initialize_multi_stable(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    pool_pubkey: &Pubkey,
+    fee_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    token_pubkeys: &[Pubkey],
+    token_mint_pubkeys: &[Pubkey],
+    instruction: InitializeMultiStable,
+) -> Result<Instruction, ProgramError> {
+    if token_pubkeys.len() != token_mint_pubkeys.len() {
+        return Err(SwapError::InvalidInput.into());
+    }
+    let data = SwapInstruction::InitializeMultiStable(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*pool_pubkey, false),
+        AccountMeta::new_readonly(*fee_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for (token_pubkey, token_mint_pubkey) in token_pubkeys.iter().zip(token_mint_pubkeys) {
+        accounts.push(AccountMeta::new_readonly(*token_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(*token_mint_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap_multi_stable' instruction.
+pub fn swap_multi_stable(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    source_mint_pubkey: &Pubkey,
+    destination_mint_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    instruction: Swap,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SwapMultiStable(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*pool_fee_pubkey, false),
+        AccountMeta::new_readonly(*source_mint_pubkey, false),
+        AccountMeta::new_readonly(*destination_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for swap_token_pubkey in swap_token_pubkeys {
+        accounts.push(AccountMeta::new(*swap_token_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'deposit_multi_stable' instruction.
+pub fn deposit_multi_stable(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    source_pubkeys: &[Pubkey],
+    swap_token_pubkeys: &[Pubkey],
+    token_mint_pubkeys: &[Pubkey],
+    instruction: DepositMultiStable,
+) -> Result<Instruction, ProgramError> {
+    if source_pubkeys.len() != swap_token_pubkeys.len()
+        || token_mint_pubkeys.len() != swap_token_pubkeys.len()
+    {
+        return Err(SwapError::InvalidInput.into());
+    }
+    let data = SwapInstruction::DepositMultiStable(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for ((source_pubkey, swap_token_pubkey), token_mint_pubkey) in source_pubkeys
+        .iter()
+        .zip(swap_token_pubkeys)
+        .zip(token_mint_pubkeys)
+    {
+        accounts.push(AccountMeta::new(*source_pubkey, false));
+        accounts.push(AccountMeta::new(*swap_token_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(*token_mint_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_multi_stable' instruction.
+pub fn withdraw_multi_stable(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    fee_account_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    destination_pubkeys: &[Pubkey],
+    token_mint_pubkeys: &[Pubkey],
+    instruction: WithdrawMultiStable,
+) -> Result<Instruction, ProgramError> {
+    if destination_pubkeys.len() != swap_token_pubkeys.len()
+        || token_mint_pubkeys.len() != swap_token_pubkeys.len()
+    {
+        return Err(SwapError::InvalidInput.into());
+    }
+    let data = SwapInstruction::WithdrawMultiStable(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*fee_account_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for ((swap_token_pubkey, destination_pubkey), token_mint_pubkey) in swap_token_pubkeys
+        .iter()
+        .zip(destination_pubkeys)
+        .zip(token_mint_pubkeys)
+    {
+        accounts.push(AccountMeta::new(*swap_token_pubkey, false));
+        accounts.push(AccountMeta::new(*destination_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(*token_mint_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_one_multi_stable' instruction.
+pub fn withdraw_one_multi_stable(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    fee_account_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    destination_mint_pubkey: &Pubkey,
+    swap_token_pubkeys: &[Pubkey],
+    instruction: WithdrawOneMultiStable,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::WithdrawOneMultiStable(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*fee_account_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*destination_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for swap_token_pubkey in swap_token_pubkeys {
+        accounts.push(AccountMeta::new(*swap_token_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'initialize_concentrated' instruction.
+pub fn initialize_concentrated(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    token_a_pubkey: &Pubkey,
+    token_b_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
+    token_b_mint_pubkey: &Pubkey,
+    fee_owner_pubkey: &Pubkey,
+    instruction: InitializeConcentrated,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::InitializeConcentrated(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, true),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*token_a_pubkey, false),
+        AccountMeta::new_readonly(*token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_a_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_b_mint_pubkey, false),
+        AccountMeta::new_readonly(*fee_owner_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'open_position' instruction.
+pub fn open_position(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    position_mint_pubkey: &Pubkey,
+    position_token_pubkey: &Pubkey,
+    instruction: OpenPosition,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::OpenPosition(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*position_pubkey, true),
+        AccountMeta::new(*position_mint_pubkey, false),
+        AccountMeta::new(*position_token_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates an 'increase_liquidity' instruction.
+pub fn increase_liquidity(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    deposit_token_a_pubkey: &Pubkey,
+    deposit_token_b_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
+    token_b_mint_pubkey: &Pubkey,
+    instruction: IncreaseLiquidity,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::IncreaseLiquidity(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*position_pubkey, false),
+        AccountMeta::new(*deposit_token_a_pubkey, false),
+        AccountMeta::new(*deposit_token_b_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_a_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_b_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'decrease_liquidity' instruction.
+pub fn decrease_liquidity(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    position_owner_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    position_token_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
+    token_b_mint_pubkey: &Pubkey,
+    instruction: DecreaseLiquidity,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::DecreaseLiquidity(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*position_owner_pubkey, true),
+        AccountMeta::new(*position_pubkey, false),
+        AccountMeta::new_readonly(*position_token_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_token_a_pubkey, false),
+        AccountMeta::new(*destination_token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_a_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_b_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'close_position' instruction.
+pub fn close_position(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    position_owner_pubkey: &Pubkey,
+    position_pubkey: &Pubkey,
+    position_mint_pubkey: &Pubkey,
+    position_token_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::ClosePosition.pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*position_owner_pubkey, true),
+        AccountMeta::new(*position_pubkey, false),
+        AccountMeta::new(*position_mint_pubkey, false),
+        AccountMeta::new(*position_token_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap_concentrated' instruction.
+pub fn swap_concentrated(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    source_mint_pubkey: &Pubkey,
+    destination_mint_pubkey: &Pubkey,
+    instruction: Swap,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SwapConcentrated(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*source_mint_pubkey, false),
+        AccountMeta::new_readonly(*destination_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'collect_owner_fees' instruction.
+pub fn collect_owner_fees(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    fee_owner_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
+    token_b_mint_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::CollectOwnerFees.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*fee_owner_pubkey, true),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_token_a_pubkey, false),
+        AccountMeta::new(*destination_token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_a_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_b_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'quote' instruction.
+pub fn quote(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    token_a_mint_pubkey: &Pubkey,
+    token_b_mint_pubkey: &Pubkey,
+    instruction: Quote,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::Quote(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*swap_token_a_pubkey, false),
+        AccountMeta::new_readonly(*swap_token_b_pubkey, false),
+        AccountMeta::new_readonly(*pool_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_a_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_b_mint_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_dynamic_fee' instruction.
+pub fn set_dynamic_fee(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    instruction: SetDynamicFee,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetDynamicFee(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*pool_fee_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_price_oracle' instruction.
+pub fn set_price_oracle(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    price_pubkey: &Pubkey,
+    instruction: SetPriceOracle,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetPriceOracle(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*pool_fee_pubkey, false),
+        AccountMeta::new_readonly(*price_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'refresh_oracle_price' instruction.
+pub fn refresh_oracle_price(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    price_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::RefreshOraclePrice.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*price_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Unpacks a reference from a bytes buffer.
+/// TODO actually pack / unpack instead of relying on normal memory layout.
+pub fn unpack<T>(input: &[u8]) -> Result<&T, ProgramError> {
+    if input.len() < size_of::<u8>() + size_of::<T>() {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    #[allow(clippy::cast_ptr_alignment)]
+    let val: &T = unsafe { &*(&input[1] as *const u8 as *const T) };
+    Ok(val)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{base::CurveType, stable::StableCurve};
+    use std::sync::Arc;
+
+    #[test]
+    fn pack_intialize() {
+        let trade_fee_numerator: u64 = 1;
         let trade_fee_denominator: u64 = 4;
         let owner_trade_fee_numerator: u64 = 2;
         let owner_trade_fee_denominator: u64 = 5;
@@ -596,7 +2616,11 @@ mod tests {
             curve_type,
             calculator,
         };
-        let check = SwapInstruction::Initialize(Initialize { fees, swap_curve });
+        let check = SwapInstruction::Initialize(Initialize {
+            fees: fees.clone(),
+            swap_curve: swap_curve.clone(),
+            fee_tier: None,
+        });
         let packed = check.pack();
         let mut expect = vec![0u8];
         expect.extend_from_slice(&trade_fee_numerator.to_le_bytes());
@@ -613,6 +2637,24 @@ mod tests {
         assert_eq!(packed, expect);
         let unpacked = SwapInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, check);
+
+        let fee_tier: u32 = 3_000;
+        let check = SwapInstruction::Initialize(Initialize {
+            fees,
+            swap_curve,
+            fee_tier: Some(fee_tier),
+        });
+        let packed = check.pack();
+        expect.extend_from_slice(&fee_tier.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        expect.push(0);
+        assert_eq!(
+            SwapInstruction::unpack(&expect),
+            Err(SwapError::InvalidInstruction.into())
+        );
     }
 
     #[test]
@@ -709,4 +2751,425 @@ mod tests {
         let unpacked = SwapInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, check);
     }
+
+    #[test]
+    fn pack_swap_exact_amount_out() {
+        let amount_out: u64 = 10;
+        let maximum_amount_in: u64 = 2;
+        let check = SwapInstruction::SwapExactAmountOut(SwapExactAmountOut {
+            amount_out,
+            maximum_amount_in,
+        });
+        let packed = check.pack();
+        let mut expect = vec![6];
+        expect.extend_from_slice(&amount_out.to_le_bytes());
+        expect.extend_from_slice(&maximum_amount_in.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_route_swap() {
+        let amount_in: u64 = 2;
+        let minimum_amount_out: u64 = 10;
+        let check = SwapInstruction::RouteSwap(RouteSwap {
+            amount_in,
+            minimum_amount_out,
+        });
+        let packed = check.pack();
+        let mut expect = vec![7];
+        expect.extend_from_slice(&amount_in.to_le_bytes());
+        expect.extend_from_slice(&minimum_amount_out.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_update_fees() {
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 4,
+            owner_trade_fee_numerator: 2,
+            owner_trade_fee_denominator: 5,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 3,
+            host_fee_numerator: 5,
+            host_fee_denominator: 20,
+        };
+        let check = SwapInstruction::UpdateFees(UpdateFees { fees: fees.clone() });
+        let packed = check.pack();
+        let mut expect = vec![8];
+        expect.extend_from_slice(&fees.trade_fee_numerator.to_le_bytes());
+        expect.extend_from_slice(&fees.trade_fee_denominator.to_le_bytes());
+        expect.extend_from_slice(&fees.owner_trade_fee_numerator.to_le_bytes());
+        expect.extend_from_slice(&fees.owner_trade_fee_denominator.to_le_bytes());
+        expect.extend_from_slice(&fees.owner_withdraw_fee_numerator.to_le_bytes());
+        expect.extend_from_slice(&fees.owner_withdraw_fee_denominator.to_le_bytes());
+        expect.extend_from_slice(&fees.host_fee_numerator.to_le_bytes());
+        expect.extend_from_slice(&fees.host_fee_denominator.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_paused() {
+        let check = SwapInstruction::SetPaused(SetPaused { is_paused: true });
+        let packed = check.pack();
+        let expect = vec![9, 1];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+        assert_eq!(
+            SwapInstruction::unpack(&[9, 2]).unwrap_err(),
+            SwapError::InvalidInstruction.into()
+        );
+    }
+
+    #[test]
+    fn pack_ramp_amp() {
+        let target_amp: u64 = 200;
+        let stop_timestamp: UnixTimestamp = 1_000_000;
+        let check = SwapInstruction::RampAmp(RampAmp {
+            target_amp,
+            stop_timestamp,
+        });
+        let packed = check.pack();
+        let mut expect = vec![10];
+        expect.extend_from_slice(&target_amp.to_le_bytes());
+        expect.extend_from_slice(&stop_timestamp.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_fee_account() {
+        let check = SwapInstruction::SetFeeAccount;
+        let packed = check.pack();
+        let expect = vec![11];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_flash_loan() {
+        let amount: u64 = 1_000;
+        let check = SwapInstruction::FlashLoan(FlashLoan { amount });
+        let packed = check.pack();
+        let mut expect = vec![12];
+        expect.extend_from_slice(&amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let fee: u64 = 3;
+        let check = ReceiveFlashLoan { amount, fee };
+        let packed = check.pack();
+        let mut expect = vec![ReceiveFlashLoan::TAG];
+        expect.extend_from_slice(&amount.to_le_bytes());
+        expect.extend_from_slice(&fee.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = ReceiveFlashLoan::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_multi_stable() {
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 4,
+            owner_trade_fee_numerator: 3,
+            owner_trade_fee_denominator: 10,
+            owner_withdraw_fee_numerator: 2,
+            owner_withdraw_fee_denominator: 7,
+            host_fee_numerator: 5,
+            host_fee_denominator: 20,
+        };
+        let amp: u64 = 100;
+        let check = SwapInstruction::InitializeMultiStable(InitializeMultiStable {
+            fees: fees.clone(),
+            amp,
+        });
+        let packed = check.pack();
+        let mut expect = vec![13];
+        let mut fees_slice = [0u8; Fees::LEN];
+        fees.pack_into_slice(&mut fees_slice);
+        expect.extend_from_slice(&fees_slice);
+        expect.extend_from_slice(&amp.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let amount_in: u64 = 2;
+        let minimum_amount_out: u64 = 10;
+        let check = SwapInstruction::SwapMultiStable(Swap {
+            amount_in,
+            minimum_amount_out,
+        });
+        let packed = check.pack();
+        let mut expect = vec![14];
+        expect.extend_from_slice(&amount_in.to_le_bytes());
+        expect.extend_from_slice(&minimum_amount_out.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let token_amounts = vec![5u64, 0, 7];
+        let minimum_pool_token_amount: u64 = 11;
+        let check = SwapInstruction::DepositMultiStable(DepositMultiStable {
+            token_amounts: token_amounts.clone(),
+            minimum_pool_token_amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![15];
+        expect.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+        for amount in &token_amounts {
+            expect.extend_from_slice(&amount.to_le_bytes());
+        }
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let pool_token_amount: u64 = 1212438012089;
+        let minimum_token_amounts = vec![1u64, 2, 3, 4];
+        let check = SwapInstruction::WithdrawMultiStable(WithdrawMultiStable {
+            pool_token_amount,
+            minimum_token_amounts: minimum_token_amounts.clone(),
+        });
+        let packed = check.pack();
+        let mut expect = vec![16];
+        expect.extend_from_slice(&pool_token_amount.to_le_bytes());
+        for amount in &minimum_token_amounts {
+            expect.extend_from_slice(&amount.to_le_bytes());
+        }
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        // one token, five tokens, or a partial amount are all invalid
+        assert_eq!(
+            SwapInstruction::unpack(&expect[..1 + 8 + 8]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+        let mut five_tokens = expect.clone();
+        five_tokens.extend_from_slice(&5u64.to_le_bytes());
+        assert_eq!(
+            SwapInstruction::unpack(&five_tokens),
+            Err(SwapError::InvalidInstruction.into())
+        );
+        assert_eq!(
+            SwapInstruction::unpack(&expect[..expect.len() - 1]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+
+        let minimum_token_amount: u64 = 3;
+        let check = SwapInstruction::WithdrawOneMultiStable(WithdrawOneMultiStable {
+            pool_token_amount,
+            minimum_token_amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![17];
+        expect.extend_from_slice(&pool_token_amount.to_le_bytes());
+        expect.extend_from_slice(&minimum_token_amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_concentrated() {
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 4,
+            owner_trade_fee_numerator: 3,
+            owner_trade_fee_denominator: 10,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 5,
+            host_fee_denominator: 20,
+        };
+        let tick_spacing: u16 = 64;
+        let initial_sqrt_price: u128 = 1 << 64;
+        let check = SwapInstruction::InitializeConcentrated(InitializeConcentrated {
+            fees: fees.clone(),
+            tick_spacing,
+            initial_sqrt_price,
+        });
+        let packed = check.pack();
+        let mut expect = vec![18];
+        let mut fees_slice = [0u8; Fees::LEN];
+        fees.pack_into_slice(&mut fees_slice);
+        expect.extend_from_slice(&fees_slice);
+        expect.extend_from_slice(&tick_spacing.to_le_bytes());
+        expect.extend_from_slice(&initial_sqrt_price.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+        assert_eq!(
+            SwapInstruction::unpack(&expect[..expect.len() - 1]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+
+        let tick_lower: i32 = -128;
+        let tick_upper: i32 = 64;
+        let check = SwapInstruction::OpenPosition(OpenPosition {
+            tick_lower,
+            tick_upper,
+        });
+        let packed = check.pack();
+        let mut expect = vec![19];
+        expect.extend_from_slice(&tick_lower.to_le_bytes());
+        expect.extend_from_slice(&tick_upper.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let liquidity: u128 = 1 << 100;
+        let token_a_amount: u64 = 3;
+        let token_b_amount: u64 = 4;
+        let check = SwapInstruction::IncreaseLiquidity(IncreaseLiquidity {
+            liquidity,
+            maximum_token_a_amount: token_a_amount,
+            maximum_token_b_amount: token_b_amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![20];
+        expect.extend_from_slice(&liquidity.to_le_bytes());
+        expect.extend_from_slice(&token_a_amount.to_le_bytes());
+        expect.extend_from_slice(&token_b_amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let check = SwapInstruction::DecreaseLiquidity(DecreaseLiquidity {
+            liquidity,
+            minimum_token_a_amount: token_a_amount,
+            minimum_token_b_amount: token_b_amount,
+        });
+        let packed = check.pack();
+        expect[0] = 21;
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let check = SwapInstruction::ClosePosition;
+        let packed = check.pack();
+        assert_eq!(packed, vec![22]);
+        let unpacked = SwapInstruction::unpack(&[22]).unwrap();
+        assert_eq!(unpacked, check);
+
+        let amount_in: u64 = 2;
+        let minimum_amount_out: u64 = 10;
+        let check = SwapInstruction::SwapConcentrated(Swap {
+            amount_in,
+            minimum_amount_out,
+        });
+        let packed = check.pack();
+        let mut expect = vec![23];
+        expect.extend_from_slice(&amount_in.to_le_bytes());
+        expect.extend_from_slice(&minimum_amount_out.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let check = SwapInstruction::CollectOwnerFees;
+        let packed = check.pack();
+        assert_eq!(packed, vec![24]);
+        let unpacked = SwapInstruction::unpack(&[24]).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_quote() {
+        let amount: u64 = 1_000;
+        let check = SwapInstruction::Quote(Quote {
+            operation: QuoteOperation::WithdrawSingleTokenTypeExactAmountOut,
+            trade_direction: TradeDirection::BtoA,
+            amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![25, 3, 1];
+        expect.extend_from_slice(&amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        expect[1] = 4;
+        assert_eq!(
+            SwapInstruction::unpack(&expect),
+            Err(SwapError::InvalidInstruction.into())
+        );
+
+        let check = QuoteResult {
+            amount_in: 1,
+            amount_out: 2,
+            trade_fee: 3,
+            owner_fee: 4,
+            host_fee: 5,
+            new_token_a_amount: 6,
+            new_token_b_amount: 7,
+        };
+        let packed = check.pack();
+        let expect = (1u64..=7).flat_map(u64::to_le_bytes).collect::<Vec<_>>();
+        assert_eq!(packed, expect);
+        assert_eq!(packed.len(), QuoteResult::LEN);
+        let unpacked = QuoteResult::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+    #[test]
+    fn pack_set_dynamic_fee() {
+        let min_trade_fee_numerator: u64 = 5;
+        let max_trade_fee_numerator: u64 = 100;
+        let max_volatility: u64 = 50_000;
+        let volatility_half_life: u64 = 600;
+        let check = SwapInstruction::SetDynamicFee(SetDynamicFee {
+            min_trade_fee_numerator,
+            max_trade_fee_numerator,
+            max_volatility,
+            volatility_half_life,
+        });
+        let packed = check.pack();
+        let mut expect = vec![26];
+        expect.extend_from_slice(&min_trade_fee_numerator.to_le_bytes());
+        expect.extend_from_slice(&max_trade_fee_numerator.to_le_bytes());
+        expect.extend_from_slice(&max_volatility.to_le_bytes());
+        expect.extend_from_slice(&volatility_half_life.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_price_oracle() {
+        let max_staleness: u64 = 600;
+        let max_deviation_bps: u64 = 50;
+        let check = SwapInstruction::SetPriceOracle(SetPriceOracle {
+            price_source: PriceSource::SwapTokenA,
+            max_staleness,
+            max_deviation_bps,
+        });
+        let packed = check.pack();
+        let mut expect = vec![27, 1];
+        expect.extend_from_slice(&max_staleness.to_le_bytes());
+        expect.extend_from_slice(&max_deviation_bps.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+        expect[1] = 3;
+        assert_eq!(
+            SwapInstruction::unpack(&expect),
+            Err(SwapError::InvalidInstruction.into())
+        );
+
+        let check = SwapInstruction::RefreshOraclePrice;
+        let packed = check.pack();
+        let expect = vec![28];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
 }
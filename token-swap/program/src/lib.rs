@@ -7,6 +7,7 @@ pub mod curve;
 pub mod error;
 pub mod instruction;
 pub mod processor;
+pub mod registry;
 pub mod state;
 
 #[cfg(not(feature = "no-entrypoint"))]
@@ -3,62 +3,184 @@
 use crate::constraints::{SwapConstraints, SWAP_CONSTRAINTS};
 use crate::{
     curve::{
-        base::SwapCurve,
-        calculator::{RoundDirection, TradeDirection},
-        fees::Fees,
+        base::{CurveType, SwapCurve},
+        calculator::{RoundDirection, TradeDirection, INITIAL_SWAP_POOL_AMOUNT},
+        concentrated::ConcentratedCurve,
+        constant_price::{
+            record_program, unpack_record_price, ConstantPriceCurve, PriceOracle, PriceSource,
+        },
+        fees::{DynamicFee, Fees},
+        multi_stable::{MAX_TOKENS, MIN_TOKENS},
+        stable::{AmpRamp, StableCurve, MAX_AMP},
     },
     error::SwapError,
     instruction::{
-        DepositAllTokenTypes, DepositSingleTokenTypeExactAmountIn, Initialize, Swap,
-        SwapInstruction, WithdrawAllTokenTypes, WithdrawSingleTokenTypeExactAmountOut,
+        DecreaseLiquidity, DepositAllTokenTypes, DepositMultiStable,
+        DepositSingleTokenTypeExactAmountIn, FlashLoan, IncreaseLiquidity, Initialize,
+        InitializeConcentrated, InitializeMultiStable, OpenPosition, Quote, QuoteOperation,
+        QuoteResult, RampAmp, ReceiveFlashLoan, RouteSwap, SetDynamicFee, SetPaused,
+        SetPriceOracle, Swap, SwapExactAmountOut, SwapInstruction, UpdateFees,
+        WithdrawAllTokenTypes, WithdrawMultiStable, WithdrawOneMultiStable,
+        WithdrawSingleTokenTypeExactAmountOut,
+    },
+    registry::{self, POOL_SEED_PREFIX},
+    state::{
+        ConcentratedSwap, MultiStableSwap, Position, SwapState, SwapV1, SwapV2, SwapVersion,
+        PRICE_FRACTIONAL_BITS,
     },
-    state::{SwapState, SwapV1, SwapVersion},
 };
 use num_traits::FromPrimitive;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    clock::{Clock, UnixTimestamp},
     decode_error::DecodeError,
     entrypoint::ProgramResult,
-    instruction::Instruction,
+    instruction::{AccountMeta, Instruction},
     msg,
-    program::invoke_signed,
+    program::{invoke, invoke_signed, set_return_data},
     program_error::{PrintProgramError, ProgramError},
     program_option::COption,
     program_pack::Pack,
     pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+    sysvar::Sysvar,
+};
+use spl_token_2022::{
+    check_spl_token_program_account,
+    error::TokenError,
+    extension::{transfer_fee::TransferFeeConfig, StateWithExtensions},
+    instruction::AuthorityType,
+    state::{Account, Mint},
+};
+use std::{
+    convert::{TryFrom, TryInto},
+    error::Error,
+    sync::Arc,
 };
-use spl_token::error::TokenError;
-use std::{convert::TryInto, error::Error};
+
+/// The side of a swap whose amount is fixed by the instruction
+#[derive(Clone, Copy)]
+enum SwapAmount {
+    /// Exact source amount in, with a minimum destination amount out
+    ExactIn {
+        amount_in: u64,
+        minimum_amount_out: u64,
+    },
+    /// Exact destination amount out, with a maximum source amount in
+    ExactOut {
+        amount_out: u64,
+        maximum_amount_in: u64,
+    },
+}
+
+/// Amounts moved by a swap on a two-token pool
+struct SwapAmounts {
+    /// SOURCE tokens taken from the user, including the transfer fee
+    source_transfer_amount: u64,
+    /// DESTINATION tokens sent by the pool, including the transfer fee
+    destination_transfer_amount: u64,
+    /// DESTINATION tokens received by the user, after the transfer fee
+    destination_amount_received: u64,
+    /// SOURCE tokens kept by the pool as the trading fee
+    trade_fee: u128,
+    /// Pool tokens minted for the owner trading fee, including the host fee
+    owner_fee_pool_token_amount: u128,
+    /// Part of the owner trading fee minted to the host fee account, if any
+    host_fee: u128,
+    /// Token A balance of the pool after the swap
+    swap_token_a_amount: u128,
+    /// Token B balance of the pool after the swap
+    swap_token_b_amount: u128,
+}
+
+/// Accounts of one leg, or pool, of a routed swap
+struct RouteSwapLegInfo<'a, 'b> {
+    swap_info: &'a AccountInfo<'b>,
+    authority_info: &'a AccountInfo<'b>,
+    swap_source_info: &'a AccountInfo<'b>,
+    swap_destination_info: &'a AccountInfo<'b>,
+    pool_mint_info: &'a AccountInfo<'b>,
+    pool_fee_account_info: &'a AccountInfo<'b>,
+    source_token_mint_info: &'a AccountInfo<'b>,
+    destination_token_mint_info: &'a AccountInfo<'b>,
+    source_token_program_info: &'a AccountInfo<'b>,
+    destination_token_program_info: &'a AccountInfo<'b>,
+    pool_token_program_info: &'a AccountInfo<'b>,
+}
 
 /// Program state handler.
 pub struct Processor {}
 impl Processor {
-    /// Unpacks a spl_token `Account`.
+    /// Unpacks a spl_token or spl_token_2022 `Account`, ignoring extensions.
     pub fn unpack_token_account(
         account_info: &AccountInfo,
         token_program_id: &Pubkey,
-    ) -> Result<spl_token::state::Account, SwapError> {
+    ) -> Result<Account, SwapError> {
         if account_info.owner != token_program_id {
             Err(SwapError::IncorrectTokenProgramId)
         } else {
-            spl_token::state::Account::unpack(&account_info.data.borrow())
+            StateWithExtensions::<Account>::unpack(&account_info.data.borrow())
+                .map(|a| a.base)
                 .map_err(|_| SwapError::ExpectedAccount)
         }
     }
 
-    /// Unpacks a spl_token `Mint`.
+    /// Unpacks a spl_token or spl_token_2022 `Mint`, ignoring extensions.
     pub fn unpack_mint(
         account_info: &AccountInfo,
         token_program_id: &Pubkey,
-    ) -> Result<spl_token::state::Mint, SwapError> {
+    ) -> Result<Mint, SwapError> {
         if account_info.owner != token_program_id {
             Err(SwapError::IncorrectTokenProgramId)
         } else {
-            spl_token::state::Mint::unpack(&account_info.data.borrow())
+            StateWithExtensions::<Mint>::unpack(&account_info.data.borrow())
+                .map(|m| m.base)
                 .map_err(|_| SwapError::ExpectedMint)
         }
     }
 
+    /// Unpacks one of the swap's own token accounts when the instruction does
+    /// not carry its token program. Callers must still check the account key
+    /// against the swap state.
+    fn unpack_swap_token_account(account_info: &AccountInfo) -> Result<Account, SwapError> {
+        check_spl_token_program_account(account_info.owner)
+            .map_err(|_| SwapError::IncorrectTokenProgramId)?;
+        Self::unpack_token_account(account_info, account_info.owner)
+    }
+
+    /// Calculates the transfer fee withheld by the mint when sending `amount`.
+    pub fn transfer_fee(mint_info: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+        let mint_data = mint_info.data.borrow();
+        let mint =
+            StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| SwapError::ExpectedMint)?;
+        if let Ok(transfer_fee_config) = mint.get_extension::<TransferFeeConfig>() {
+            transfer_fee_config
+                .calculate_epoch_fee(Clock::get()?.epoch, amount)
+                .ok_or_else(|| SwapError::FeeCalculationFailure.into())
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Calculates the transfer fee withheld by the mint when sending enough
+    /// for `post_fee_amount` to arrive.
+    pub fn inverse_transfer_fee(
+        mint_info: &AccountInfo,
+        post_fee_amount: u64,
+    ) -> Result<u64, ProgramError> {
+        let mint_data = mint_info.data.borrow();
+        let mint =
+            StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| SwapError::ExpectedMint)?;
+        if let Ok(transfer_fee_config) = mint.get_extension::<TransferFeeConfig>() {
+            transfer_fee_config
+                .calculate_inverse_epoch_fee(Clock::get()?.epoch, post_fee_amount)
+                .ok_or_else(|| SwapError::FeeCalculationFailure.into())
+        } else {
+            Ok(0)
+        }
+    }
+
     /// Calculates the authority id by generating a program address.
     pub fn authority_id(
         program_id: &Pubkey,
@@ -83,7 +205,7 @@ impl Processor {
         let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
         let signers = &[&authority_signature_seeds[..]];
 
-        let ix = spl_token::instruction::burn(
+        let ix = spl_token_2022::instruction::burn(
             token_program.key,
             burn_account.key,
             mint.key,
@@ -112,7 +234,7 @@ impl Processor {
         let swap_bytes = swap.to_bytes();
         let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
         let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::mint_to(
+        let ix = spl_token_2022::instruction::mint_to(
             token_program.key,
             mint.key,
             destination.key,
@@ -128,34 +250,203 @@ impl Processor {
         )
     }
 
-    /// Issue a spl_token `Transfer` instruction.
+    /// Issue a spl_token `TransferChecked` instruction.
+    #[allow(clippy::too_many_arguments)]
     pub fn token_transfer<'a>(
         swap: &Pubkey,
         token_program: AccountInfo<'a>,
         source: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
         destination: AccountInfo<'a>,
         authority: AccountInfo<'a>,
         bump_seed: u8,
         amount: u64,
+        decimals: u8,
     ) -> Result<(), ProgramError> {
         let swap_bytes = swap.to_bytes();
         let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
         let signers = &[&authority_signature_seeds[..]];
-        let ix = spl_token::instruction::transfer(
+        let ix = spl_token_2022::instruction::transfer_checked(
             token_program.key,
             source.key,
+            mint.key,
             destination.key,
             authority.key,
             &[],
             amount,
+            decimals,
         )?;
         invoke_signed_wrapper::<TokenError>(
             &ix,
-            &[source, destination, authority, token_program],
+            &[source, mint, destination, authority, token_program],
             signers,
         )
     }
 
+    /// Issue a spl_token `SetAuthority` instruction removing a mint's
+    /// authority, fixing its supply.
+    pub fn token_remove_mint_authority<'a>(
+        swap: &Pubkey,
+        token_program: AccountInfo<'a>,
+        mint: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        bump_seed: u8,
+    ) -> Result<(), ProgramError> {
+        let swap_bytes = swap.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = spl_token_2022::instruction::set_authority(
+            token_program.key,
+            mint.key,
+            None,
+            AuthorityType::MintTokens,
+            authority.key,
+            &[],
+        )?;
+
+        invoke_signed_wrapper::<TokenError>(&ix, &[mint, authority, token_program], signers)
+    }
+
+    /// Brings the swap's price accumulators up to date with the token
+    /// balances held before the current instruction.
+    pub fn update_price_observation(
+        swap_info: &AccountInfo,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) -> ProgramResult {
+        SwapVersion::update_price_observation(
+            &mut swap_info.data.borrow_mut(),
+            token_a_amount,
+            token_b_amount,
+            Clock::get()?.unix_timestamp,
+        )
+    }
+
+    /// Brings a ramping stable curve's amp up to date, before the curve is
+    /// used by the current instruction.
+    pub fn update_amp_ramp(swap_info: &AccountInfo) -> ProgramResult {
+        SwapVersion::update_amp_ramp(
+            &mut swap_info.data.borrow_mut(),
+            Clock::get()?.unix_timestamp,
+        )
+    }
+
+    /// Brings a dynamic trade fee up to date with the volatility decayed
+    /// since the last trade, before the fees are used by the current
+    /// instruction.
+    pub fn update_dynamic_fee(swap_info: &AccountInfo) -> ProgramResult {
+        SwapVersion::update_dynamic_fee(
+            &mut swap_info.data.borrow_mut(),
+            Clock::get()?.unix_timestamp,
+        )
+    }
+
+    /// Checks that the price of an oracle-pegged constant price curve is
+    /// recent enough to trade at, before the curve is used by the current
+    /// instruction.
+    fn check_price_oracle(token_swap: &dyn SwapState) -> ProgramResult {
+        if let Some(price_oracle) = token_swap.price_oracle() {
+            if price_oracle.is_stale(Clock::get()?.unix_timestamp) {
+                return Err(SwapError::StaleOraclePrice.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpacks the price of token B and its publish timestamp from a record
+    /// account used as a price feed.
+    fn unpack_record_feed(price_info: &AccountInfo) -> Result<(u64, UnixTimestamp), ProgramError> {
+        if *price_info.owner != record_program::id() {
+            return Err(SwapError::InvalidOracleAccount.into());
+        }
+        unpack_record_price(&price_info.data.borrow())
+            .ok_or_else(|| SwapError::InvalidOracleAccount.into())
+    }
+
+    /// Unpacks the latest price observation of another token-swap used as a
+    /// price feed, returning the cumulative price used by `price_source` and
+    /// the timestamp of the observation.  The feed must trade the same mints
+    /// as the swap, in the orientation given by `price_source`.
+    fn unpack_feed_observation(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        token_a_mint: &Pubkey,
+        token_b_mint: &Pubkey,
+        price_info: &AccountInfo,
+        price_source: PriceSource,
+    ) -> Result<(u128, UnixTimestamp), ProgramError> {
+        if price_info.owner != program_id || price_info.key == swap_info.key {
+            return Err(SwapError::InvalidOracleAccount.into());
+        }
+        let feed = SwapVersion::unpack(&price_info.data.borrow())
+            .ok()
+            .filter(|feed| feed.is_initialized())
+            .ok_or(SwapError::InvalidOracleAccount)?;
+        let feed_mints = (feed.token_a_mint(), feed.token_b_mint());
+        let observation = feed
+            .price_observation()
+            .ok_or(SwapError::InvalidOracleAccount)?;
+        let price_cumulative = match price_source {
+            PriceSource::SwapTokenA if feed_mints == (token_b_mint, token_a_mint) => {
+                observation.price_a_cumulative
+            }
+            PriceSource::SwapTokenB if feed_mints == (token_a_mint, token_b_mint) => {
+                observation.price_b_cumulative
+            }
+            _ => return Err(SwapError::InvalidOracleAccount.into()),
+        };
+        Ok((price_cumulative, observation.timestamp))
+    }
+
+    /// Sets the flash loan lock of the swap, only supported by `SwapV2`.
+    fn set_locked(swap_info: &AccountInfo, is_locked: bool) -> ProgramResult {
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        match &mut swap_version {
+            SwapVersion::SwapV2(swap_v2) => swap_v2.is_locked = is_locked,
+            _ => return Err(SwapError::UnsupportedSwapVersion.into()),
+        }
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())
+    }
+
+    /// Checks that the swap admin signed, owns the pool fee account, and is
+    /// the owner of the program's constraints, if any.
+    fn check_admin(
+        token_swap: &dyn SwapState,
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        admin_info: &AccountInfo,
+        pool_fee_account_info: &AccountInfo,
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if !admin_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        if *pool_fee_account_info.key != *token_swap.pool_fee_account() {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        let pool_fee_account =
+            Self::unpack_token_account(pool_fee_account_info, token_swap.token_program_id())?;
+        if pool_fee_account.owner != *admin_info.key {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if let Some(swap_constraints) = swap_constraints {
+            let owner_key = swap_constraints
+                .owner_key
+                .parse::<Pubkey>()
+                .map_err(|_| SwapError::InvalidOwner)?;
+            if *admin_info.key != owner_key {
+                return Err(SwapError::InvalidOwner.into());
+            }
+        }
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn check_accounts(
         token_swap: &dyn SwapState,
@@ -208,11 +499,92 @@ impl Processor {
         Ok(())
     }
 
+    /// Creates the swap account of a canonical pool at its registry address.
+    /// Daltons already sent to the address are topped up rather than
+    /// rejected, so that funding it ahead of time cannot squat the pool.
+    #[allow(clippy::too_many_arguments)]
+    fn create_canonical_pool_account<'a>(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        token_a_mint: &Pubkey,
+        token_b_mint: &Pubkey,
+        curve_type: CurveType,
+        fee_tier: u32,
+    ) -> ProgramResult {
+        registry::validate_mint_order(token_a_mint, token_b_mint)?;
+        let (pool_address, pool_bump_seed) = registry::find_pool_address(
+            program_id,
+            token_a_mint,
+            token_b_mint,
+            curve_type,
+            fee_tier,
+        );
+        if *swap_info.key != pool_address {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let curve_type = [curve_type as u8];
+        let fee_tier = fee_tier.to_le_bytes();
+        let bump_seed = [pool_bump_seed];
+        let signer_seeds: &[&[u8]] = &[
+            POOL_SEED_PREFIX,
+            token_a_mint.as_ref(),
+            token_b_mint.as_ref(),
+            &curve_type,
+            &fee_tier,
+            &bump_seed,
+        ];
+
+        let space = SwapVersion::LATEST_LEN;
+        let required_daltons = Rent::get()?.minimum_balance(space);
+        if swap_info.daltons() == 0 {
+            return invoke_signed(
+                &system_instruction::create_account(
+                    payer_info.key,
+                    swap_info.key,
+                    required_daltons,
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    payer_info.clone(),
+                    swap_info.clone(),
+                    system_program_info.clone(),
+                ],
+                &[signer_seeds],
+            );
+        }
+
+        let shortfall = required_daltons.saturating_sub(swap_info.daltons());
+        if shortfall > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, swap_info.key, shortfall),
+                &[
+                    payer_info.clone(),
+                    swap_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(swap_info.key, space as u64),
+            &[swap_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(swap_info.key, program_id),
+            &[swap_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )
+    }
+
     /// Processes an [Initialize](enum.Instruction.html).
     pub fn process_initialize(
         program_id: &Pubkey,
         fees: Fees,
         swap_curve: SwapCurve,
+        fee_tier: Option<u32>,
         accounts: &[AccountInfo],
         swap_constraints: &Option<SwapConstraints>,
     ) -> ProgramResult {
@@ -236,11 +608,16 @@ impl Processor {
         if *authority_info.key != swap_authority {
             return Err(SwapError::InvalidProgramAddress.into());
         }
-        let token_a = Self::unpack_token_account(token_a_info, &token_program_id)?;
-        let token_b = Self::unpack_token_account(token_b_info, &token_program_id)?;
+        // Initialize only takes the pool token program, while the trading
+        // tokens may belong to either token program
+        let token_a = Self::unpack_swap_token_account(token_a_info)?;
+        let token_b = Self::unpack_swap_token_account(token_b_info)?;
         let fee_account = Self::unpack_token_account(fee_account_info, &token_program_id)?;
         let destination = Self::unpack_token_account(destination_info, &token_program_id)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, &token_program_id)?;
+        if *pool_mint_info.owner != token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
         if *authority_info.key != token_a.owner {
             return Err(SwapError::InvalidOwner.into());
         }
@@ -300,6 +677,22 @@ impl Processor {
         fees.validate()?;
         swap_curve.calculator.validate()?;
 
+        if let Some(fee_tier) = fee_tier {
+            let payer_info = next_account_info(account_info_iter)?;
+            let system_program_info = next_account_info(account_info_iter)?;
+            registry::validate_fee_tier(&fees, fee_tier)?;
+            Self::create_canonical_pool_account(
+                program_id,
+                swap_info,
+                payer_info,
+                system_program_info,
+                &token_a.mint,
+                &token_b.mint,
+                swap_curve.curve_type,
+                fee_tier,
+            )?;
+        }
+
         let initial_amount = swap_curve.calculator.new_pool_supply();
 
         Self::token_mint_to(
@@ -312,19 +705,46 @@ impl Processor {
             to_u64(initial_amount)?,
         )?;
 
-        let obj = SwapVersion::SwapV1(SwapV1 {
-            is_initialized: true,
-            bump_seed,
-            token_program_id,
-            token_a: *token_a_info.key,
-            token_b: *token_b_info.key,
-            pool_mint: *pool_mint_info.key,
-            token_a_mint: token_a.mint,
-            token_b_mint: token_b.mint,
-            pool_fee_account: *fee_account_info.key,
-            fees,
-            swap_curve,
-        });
+        // Accounts sized for the original layout keep working, without price
+        // accumulators
+        let obj = if swap_info.data_len() == SwapVersion::V1_LEN {
+            SwapVersion::SwapV1(SwapV1 {
+                is_initialized: true,
+                bump_seed,
+                token_program_id,
+                token_a: *token_a_info.key,
+                token_b: *token_b_info.key,
+                pool_mint: *pool_mint_info.key,
+                token_a_mint: token_a.mint,
+                token_b_mint: token_b.mint,
+                pool_fee_account: *fee_account_info.key,
+                fees,
+                swap_curve,
+            })
+        } else {
+            SwapVersion::SwapV2(SwapV2 {
+                is_initialized: true,
+                bump_seed,
+                token_program_id,
+                token_a: *token_a_info.key,
+                token_b: *token_b_info.key,
+                pool_mint: *pool_mint_info.key,
+                token_a_mint: token_a.mint,
+                token_b_mint: token_b.mint,
+                pool_fee_account: *fee_account_info.key,
+                fees,
+                swap_curve,
+                price_a_cumulative: 0,
+                price_b_cumulative: 0,
+                last_update_timestamp: Clock::get()?.unix_timestamp,
+                is_paused: false,
+                amp_ramp: AmpRamp::default(),
+                is_locked: false,
+                dynamic_fee: DynamicFee::default(),
+                price_oracle: PriceOracle::default(),
+                fee_tier,
+            })
+        };
         SwapVersion::pack(obj, &mut swap_info.data.borrow_mut())?;
         Ok(())
     }
@@ -335,6 +755,141 @@ impl Processor {
         amount_in: u64,
         minimum_amount_out: u64,
         accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        Self::process_swap_amount(
+            program_id,
+            SwapAmount::ExactIn {
+                amount_in,
+                minimum_amount_out,
+            },
+            accounts,
+        )
+    }
+
+    /// Processes a [SwapExactAmountOut](enum.Instruction.html).
+    pub fn process_swap_exact_amount_out(
+        program_id: &Pubkey,
+        amount_out: u64,
+        maximum_amount_in: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        Self::process_swap_amount(
+            program_id,
+            SwapAmount::ExactOut {
+                amount_out,
+                maximum_amount_in,
+            },
+            accounts,
+        )
+    }
+
+    /// Computes the amounts moved by a swap on a two-token pool from the
+    /// pool's balances, using the curve, trading fees and transfer fees.
+    #[allow(clippy::too_many_arguments)]
+    fn swap_amounts(
+        token_swap: &dyn SwapState,
+        swap_amount: SwapAmount,
+        trade_direction: TradeDirection,
+        swap_source_amount: u64,
+        swap_destination_amount: u64,
+        pool_mint_supply: u64,
+        source_token_mint_info: &AccountInfo,
+        destination_token_mint_info: &AccountInfo,
+    ) -> Result<SwapAmounts, ProgramError> {
+        let result = match swap_amount {
+            SwapAmount::ExactIn { amount_in, .. } => {
+                // The pool only receives what is left after the source transfer fee
+                let actual_amount_in = amount_in
+                    .checked_sub(Self::transfer_fee(source_token_mint_info, amount_in)?)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+                token_swap.swap_curve().swap(
+                    to_u128(actual_amount_in)?,
+                    to_u128(swap_source_amount)?,
+                    to_u128(swap_destination_amount)?,
+                    trade_direction,
+                    token_swap.fees(),
+                )
+            }
+            SwapAmount::ExactOut { amount_out, .. } => {
+                // The pool sends enough for the exact amount to arrive after
+                // the destination transfer fee
+                let actual_amount_out = amount_out
+                    .checked_add(Self::inverse_transfer_fee(
+                        destination_token_mint_info,
+                        amount_out,
+                    )?)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+                token_swap.swap_curve().swap_exact_out(
+                    to_u128(actual_amount_out)?,
+                    to_u128(swap_source_amount)?,
+                    to_u128(swap_destination_amount)?,
+                    trade_direction,
+                    token_swap.fees(),
+                )
+            }
+        }
+        .ok_or(SwapError::ZeroTradingTokens)?;
+
+        // Gross up the amount taken from the user so that the curve's source
+        // amount arrives in the pool after the transfer fee
+        let source_amount_swapped = to_u64(result.source_amount_swapped)?;
+        let source_transfer_amount = source_amount_swapped
+            .checked_add(Self::inverse_transfer_fee(
+                source_token_mint_info,
+                source_amount_swapped,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+
+        let destination_transfer_amount = to_u64(result.destination_amount_swapped)?;
+        let destination_amount_received = destination_transfer_amount
+            .checked_sub(Self::transfer_fee(
+                destination_token_mint_info,
+                destination_transfer_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                result.new_swap_source_amount,
+                result.new_swap_destination_amount,
+            ),
+            TradeDirection::BtoA => (
+                result.new_swap_destination_amount,
+                result.new_swap_source_amount,
+            ),
+        };
+        let owner_fee_pool_token_amount = token_swap
+            .swap_curve()
+            .withdraw_single_token_type_exact_out(
+                result.owner_fee,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                to_u128(pool_mint_supply)?,
+                trade_direction,
+                token_swap.fees(),
+            )
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        let host_fee = token_swap
+            .fees()
+            .host_fee(owner_fee_pool_token_amount)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+
+        Ok(SwapAmounts {
+            source_transfer_amount,
+            destination_transfer_amount,
+            destination_amount_received,
+            trade_fee: result.trade_fee,
+            owner_fee_pool_token_amount,
+            host_fee,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        })
+    }
+
+    fn process_swap_amount(
+        program_id: &Pubkey,
+        swap_amount: SwapAmount,
+        accounts: &[AccountInfo],
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let swap_info = next_account_info(account_info_iter)?;
@@ -346,12 +901,25 @@ impl Processor {
         let destination_info = next_account_info(account_info_iter)?;
         let pool_mint_info = next_account_info(account_info_iter)?;
         let pool_fee_account_info = next_account_info(account_info_iter)?;
-        let token_program_info = next_account_info(account_info_iter)?;
+        let source_token_mint_info = next_account_info(account_info_iter)?;
+        let destination_token_mint_info = next_account_info(account_info_iter)?;
+        let source_token_program_info = next_account_info(account_info_iter)?;
+        let destination_token_program_info = next_account_info(account_info_iter)?;
+        let pool_token_program_info = next_account_info(account_info_iter)?;
 
         if swap_info.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
+        Self::update_amp_ramp(swap_info)?;
+        Self::update_dynamic_fee(swap_info)?;
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        Self::check_price_oracle(token_swap.as_ref())?;
+        if token_swap.is_paused() {
+            return Err(SwapError::SwapPaused.into());
+        }
 
         if *authority_info.key
             != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed())?
@@ -383,68 +951,95 @@ impl Processor {
         if *pool_fee_account_info.key != *token_swap.pool_fee_account() {
             return Err(SwapError::IncorrectFeeAccount.into());
         }
-        if *token_program_info.key != *token_swap.token_program_id() {
+        if *pool_token_program_info.key != *token_swap.token_program_id() {
             return Err(SwapError::IncorrectTokenProgramId.into());
         }
 
         let source_account =
-            Self::unpack_token_account(swap_source_info, token_swap.token_program_id())?;
+            Self::unpack_token_account(swap_source_info, source_token_program_info.key)?;
         let dest_account =
-            Self::unpack_token_account(swap_destination_info, token_swap.token_program_id())?;
+            Self::unpack_token_account(swap_destination_info, destination_token_program_info.key)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let source_mint = Self::unpack_mint(source_token_mint_info, source_token_program_info.key)?;
+        let destination_mint = Self::unpack_mint(
+            destination_token_mint_info,
+            destination_token_program_info.key,
+        )?;
 
         let trade_direction = if *swap_source_info.key == *token_swap.token_a_account() {
             TradeDirection::AtoB
         } else {
             TradeDirection::BtoA
         };
-        let result = token_swap
-            .swap_curve()
-            .swap(
-                to_u128(amount_in)?,
-                to_u128(source_account.amount)?,
-                to_u128(dest_account.amount)?,
-                trade_direction,
-                token_swap.fees(),
-            )
-            .ok_or(SwapError::ZeroTradingTokens)?;
-        if result.destination_amount_swapped < to_u128(minimum_amount_out)? {
-            return Err(SwapError::ExceededSlippage.into());
+        match trade_direction {
+            TradeDirection::AtoB => Self::update_price_observation(
+                swap_info,
+                source_account.amount,
+                dest_account.amount,
+            )?,
+            TradeDirection::BtoA => Self::update_price_observation(
+                swap_info,
+                dest_account.amount,
+                source_account.amount,
+            )?,
+        }
+
+        let swap_amounts = Self::swap_amounts(
+            token_swap.as_ref(),
+            swap_amount,
+            trade_direction,
+            source_account.amount,
+            dest_account.amount,
+            pool_mint.supply,
+            source_token_mint_info,
+            destination_token_mint_info,
+        )?;
+
+        // Slippage is checked against what the user receives after the
+        // destination transfer fee, or what the user sends including the
+        // source transfer fee
+        match swap_amount {
+            SwapAmount::ExactIn {
+                minimum_amount_out, ..
+            } => {
+                if swap_amounts.destination_amount_received < minimum_amount_out {
+                    return Err(SwapError::ExceededSlippage.into());
+                }
+            }
+            SwapAmount::ExactOut {
+                maximum_amount_in, ..
+            } => {
+                if swap_amounts.source_transfer_amount > maximum_amount_in {
+                    return Err(SwapError::ExceededSlippage.into());
+                }
+            }
         }
 
         let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
-            TradeDirection::AtoB => (
-                result.new_swap_source_amount,
-                result.new_swap_destination_amount,
-            ),
-            TradeDirection::BtoA => (
-                result.new_swap_destination_amount,
-                result.new_swap_source_amount,
-            ),
+            TradeDirection::AtoB => (source_account.amount, dest_account.amount),
+            TradeDirection::BtoA => (dest_account.amount, source_account.amount),
         };
+        SwapVersion::accumulate_volatility(
+            &mut swap_info.data.borrow_mut(),
+            swap_token_a_amount,
+            swap_token_b_amount,
+            to_u64(swap_amounts.swap_token_a_amount)?,
+            to_u64(swap_amounts.swap_token_b_amount)?,
+        )?;
 
         Self::token_transfer(
             swap_info.key,
-            token_program_info.clone(),
+            source_token_program_info.clone(),
             source_info.clone(),
+            source_token_mint_info.clone(),
             swap_source_info.clone(),
             user_transfer_authority_info.clone(),
             token_swap.bump_seed(),
-            to_u64(result.source_amount_swapped)?,
+            swap_amounts.source_transfer_amount,
+            source_mint.decimals,
         )?;
 
-        let mut pool_token_amount = token_swap
-            .swap_curve()
-            .withdraw_single_token_type_exact_out(
-                result.owner_fee,
-                swap_token_a_amount,
-                swap_token_b_amount,
-                to_u128(pool_mint.supply)?,
-                trade_direction,
-                token_swap.fees(),
-            )
-            .ok_or(SwapError::FeeCalculationFailure)?;
-
+        let mut pool_token_amount = swap_amounts.owner_fee_pool_token_amount;
         if pool_token_amount > 0 {
             // Allow error to fall through
             if let Ok(host_fee_account_info) = next_account_info(account_info_iter) {
@@ -455,17 +1050,14 @@ impl Processor {
                 if *pool_mint_info.key != host_fee_account.mint {
                     return Err(SwapError::IncorrectPoolMint.into());
                 }
-                let host_fee = token_swap
-                    .fees()
-                    .host_fee(pool_token_amount)
-                    .ok_or(SwapError::FeeCalculationFailure)?;
+                let host_fee = swap_amounts.host_fee;
                 if host_fee > 0 {
                     pool_token_amount = pool_token_amount
                         .checked_sub(host_fee)
                         .ok_or(SwapError::FeeCalculationFailure)?;
                     Self::token_mint_to(
                         swap_info.key,
-                        token_program_info.clone(),
+                        pool_token_program_info.clone(),
                         pool_mint_info.clone(),
                         host_fee_account_info.clone(),
                         authority_info.clone(),
@@ -476,7 +1068,7 @@ impl Processor {
             }
             Self::token_mint_to(
                 swap_info.key,
-                token_program_info.clone(),
+                pool_token_program_info.clone(),
                 pool_mint_info.clone(),
                 pool_fee_account_info.clone(),
                 authority_info.clone(),
@@ -487,12 +1079,295 @@ impl Processor {
 
         Self::token_transfer(
             swap_info.key,
-            token_program_info.clone(),
+            destination_token_program_info.clone(),
             swap_destination_info.clone(),
+            destination_token_mint_info.clone(),
             destination_info.clone(),
             authority_info.clone(),
             token_swap.bump_seed(),
-            to_u64(result.destination_amount_swapped)?,
+            swap_amounts.destination_transfer_amount,
+            destination_mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    /// Processes a [RouteSwap](enum.Instruction.html).
+    pub fn process_route_swap(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+
+        let mut legs = vec![];
+        while !account_info_iter.as_slice().is_empty() {
+            legs.push(RouteSwapLegInfo {
+                swap_info: next_account_info(account_info_iter)?,
+                authority_info: next_account_info(account_info_iter)?,
+                swap_source_info: next_account_info(account_info_iter)?,
+                swap_destination_info: next_account_info(account_info_iter)?,
+                pool_mint_info: next_account_info(account_info_iter)?,
+                pool_fee_account_info: next_account_info(account_info_iter)?,
+                source_token_mint_info: next_account_info(account_info_iter)?,
+                destination_token_mint_info: next_account_info(account_info_iter)?,
+                source_token_program_info: next_account_info(account_info_iter)?,
+                destination_token_program_info: next_account_info(account_info_iter)?,
+                pool_token_program_info: next_account_info(account_info_iter)?,
+            });
+        }
+        if legs.is_empty() {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // The previous leg, with its bump seed and destination mint decimals,
+        // whose output has not been moved out of its pool yet
+        let mut previous: Option<(&RouteSwapLegInfo, u8, u8)> = None;
+        let mut leg_amount_in = amount_in;
+        for leg in legs.iter() {
+            if leg.swap_info.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+            Self::update_amp_ramp(leg.swap_info)?;
+            Self::update_dynamic_fee(leg.swap_info)?;
+            let token_swap = SwapVersion::unpack(&leg.swap_info.data.borrow())?;
+            if token_swap.is_locked() {
+                return Err(SwapError::SwapLocked.into());
+            }
+            Self::check_price_oracle(token_swap.as_ref())?;
+            if token_swap.is_paused() {
+                return Err(SwapError::SwapPaused.into());
+            }
+
+            if *leg.authority_info.key
+                != Self::authority_id(program_id, leg.swap_info.key, token_swap.bump_seed())?
+            {
+                return Err(SwapError::InvalidProgramAddress.into());
+            }
+            if !(*leg.swap_source_info.key == *token_swap.token_a_account()
+                || *leg.swap_source_info.key == *token_swap.token_b_account())
+            {
+                return Err(SwapError::IncorrectSwapAccount.into());
+            }
+            if !(*leg.swap_destination_info.key == *token_swap.token_a_account()
+                || *leg.swap_destination_info.key == *token_swap.token_b_account())
+            {
+                return Err(SwapError::IncorrectSwapAccount.into());
+            }
+            if *leg.swap_source_info.key == *leg.swap_destination_info.key {
+                return Err(SwapError::InvalidInput.into());
+            }
+            if *leg.pool_mint_info.key != *token_swap.pool_mint() {
+                return Err(SwapError::IncorrectPoolMint.into());
+            }
+            if *leg.pool_fee_account_info.key != *token_swap.pool_fee_account() {
+                return Err(SwapError::IncorrectFeeAccount.into());
+            }
+            if *leg.pool_token_program_info.key != *token_swap.token_program_id() {
+                return Err(SwapError::IncorrectTokenProgramId.into());
+            }
+            match previous {
+                None => {
+                    if leg.swap_source_info.key == source_info.key {
+                        return Err(SwapError::InvalidInput.into());
+                    }
+                }
+                Some((previous_leg, _, _)) => {
+                    // Consecutive legs must connect through the same mint,
+                    // and may not swap back and forth in the same pool
+                    if previous_leg.destination_token_mint_info.key
+                        != leg.source_token_mint_info.key
+                    {
+                        return Err(SwapError::InvalidInput.into());
+                    }
+                    if previous_leg.swap_info.key == leg.swap_info.key {
+                        return Err(SwapError::InvalidInput.into());
+                    }
+                }
+            }
+
+            let source_account = Self::unpack_token_account(
+                leg.swap_source_info,
+                leg.source_token_program_info.key,
+            )?;
+            let dest_account = Self::unpack_token_account(
+                leg.swap_destination_info,
+                leg.destination_token_program_info.key,
+            )?;
+            if source_account.mint != *leg.source_token_mint_info.key
+                || dest_account.mint != *leg.destination_token_mint_info.key
+            {
+                return Err(SwapError::InvalidInput.into());
+            }
+            let pool_mint = Self::unpack_mint(leg.pool_mint_info, token_swap.token_program_id())?;
+            let source_mint = Self::unpack_mint(
+                leg.source_token_mint_info,
+                leg.source_token_program_info.key,
+            )?;
+            let destination_mint = Self::unpack_mint(
+                leg.destination_token_mint_info,
+                leg.destination_token_program_info.key,
+            )?;
+
+            let trade_direction = if *leg.swap_source_info.key == *token_swap.token_a_account() {
+                TradeDirection::AtoB
+            } else {
+                TradeDirection::BtoA
+            };
+            match trade_direction {
+                TradeDirection::AtoB => Self::update_price_observation(
+                    leg.swap_info,
+                    source_account.amount,
+                    dest_account.amount,
+                )?,
+                TradeDirection::BtoA => Self::update_price_observation(
+                    leg.swap_info,
+                    dest_account.amount,
+                    source_account.amount,
+                )?,
+            }
+
+            // The pool only receives what is left after the source transfer fee
+            let actual_amount_in = leg_amount_in
+                .checked_sub(Self::transfer_fee(
+                    leg.source_token_mint_info,
+                    leg_amount_in,
+                )?)
+                .ok_or(SwapError::FeeCalculationFailure)?;
+            let result = token_swap
+                .swap_curve()
+                .swap(
+                    to_u128(actual_amount_in)?,
+                    to_u128(source_account.amount)?,
+                    to_u128(dest_account.amount)?,
+                    trade_direction,
+                    token_swap.fees(),
+                )
+                .ok_or(SwapError::ZeroTradingTokens)?;
+
+            let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+                TradeDirection::AtoB => (
+                    result.new_swap_source_amount,
+                    result.new_swap_destination_amount,
+                ),
+                TradeDirection::BtoA => (
+                    result.new_swap_destination_amount,
+                    result.new_swap_source_amount,
+                ),
+            };
+            let (old_swap_token_a_amount, old_swap_token_b_amount) = match trade_direction {
+                TradeDirection::AtoB => (source_account.amount, dest_account.amount),
+                TradeDirection::BtoA => (dest_account.amount, source_account.amount),
+            };
+            SwapVersion::accumulate_volatility(
+                &mut leg.swap_info.data.borrow_mut(),
+                old_swap_token_a_amount,
+                old_swap_token_b_amount,
+                to_u64(swap_token_a_amount)?,
+                to_u64(swap_token_b_amount)?,
+            )?;
+
+            match previous {
+                None => {
+                    // Gross up the amount taken from the user so that the
+                    // curve's source amount arrives in the pool after the
+                    // transfer fee
+                    let source_amount_swapped = to_u64(result.source_amount_swapped)?;
+                    let source_transfer_amount = source_amount_swapped
+                        .checked_add(Self::inverse_transfer_fee(
+                            leg.source_token_mint_info,
+                            source_amount_swapped,
+                        )?)
+                        .ok_or(SwapError::FeeCalculationFailure)?;
+                    Self::token_transfer(
+                        leg.swap_info.key,
+                        leg.source_token_program_info.clone(),
+                        source_info.clone(),
+                        leg.source_token_mint_info.clone(),
+                        leg.swap_source_info.clone(),
+                        user_transfer_authority_info.clone(),
+                        token_swap.bump_seed(),
+                        source_transfer_amount,
+                        source_mint.decimals,
+                    )?;
+                }
+                Some((previous_leg, previous_bump_seed, _)) => {
+                    // The whole previous output moves into this pool, so any
+                    // amount the curve rounds away stays in this pool
+                    Self::token_transfer(
+                        previous_leg.swap_info.key,
+                        previous_leg.destination_token_program_info.clone(),
+                        previous_leg.swap_destination_info.clone(),
+                        previous_leg.destination_token_mint_info.clone(),
+                        leg.swap_source_info.clone(),
+                        previous_leg.authority_info.clone(),
+                        previous_bump_seed,
+                        leg_amount_in,
+                        source_mint.decimals,
+                    )?;
+                }
+            }
+
+            let pool_token_amount = token_swap
+                .swap_curve()
+                .withdraw_single_token_type_exact_out(
+                    result.owner_fee,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    to_u128(pool_mint.supply)?,
+                    trade_direction,
+                    token_swap.fees(),
+                )
+                .ok_or(SwapError::FeeCalculationFailure)?;
+            if pool_token_amount > 0 {
+                Self::token_mint_to(
+                    leg.swap_info.key,
+                    leg.pool_token_program_info.clone(),
+                    leg.pool_mint_info.clone(),
+                    leg.pool_fee_account_info.clone(),
+                    leg.authority_info.clone(),
+                    token_swap.bump_seed(),
+                    to_u64(pool_token_amount)?,
+                )?;
+            }
+
+            leg_amount_in = to_u64(result.destination_amount_swapped)?;
+            previous = Some((leg, token_swap.bump_seed(), destination_mint.decimals));
+        }
+
+        let (last_leg, last_bump_seed, last_decimals) =
+            previous.ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if last_leg.swap_destination_info.key == destination_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+
+        // Slippage is only checked on what the user receives at the end of
+        // the route, after the destination transfer fee
+        let destination_transfer_amount = leg_amount_in;
+        let destination_amount_received = destination_transfer_amount
+            .checked_sub(Self::transfer_fee(
+                last_leg.destination_token_mint_info,
+                destination_transfer_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if destination_amount_received < minimum_amount_out {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+
+        Self::token_transfer(
+            last_leg.swap_info.key,
+            last_leg.destination_token_program_info.clone(),
+            last_leg.swap_destination_info.clone(),
+            last_leg.destination_token_mint_info.clone(),
+            destination_info.clone(),
+            last_leg.authority_info.clone(),
+            last_bump_seed,
+            destination_transfer_amount,
+            last_decimals,
         )?;
 
         Ok(())
@@ -516,9 +1391,21 @@ impl Processor {
         let token_b_info = next_account_info(account_info_iter)?;
         let pool_mint_info = next_account_info(account_info_iter)?;
         let dest_info = next_account_info(account_info_iter)?;
-        let token_program_info = next_account_info(account_info_iter)?;
+        let token_a_mint_info = next_account_info(account_info_iter)?;
+        let token_b_mint_info = next_account_info(account_info_iter)?;
+        let token_a_program_info = next_account_info(account_info_iter)?;
+        let token_b_program_info = next_account_info(account_info_iter)?;
+        let pool_token_program_info = next_account_info(account_info_iter)?;
 
+        Self::update_amp_ramp(swap_info)?;
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        Self::check_price_oracle(token_swap.as_ref())?;
+        if token_swap.is_paused() {
+            return Err(SwapError::SwapPaused.into());
+        }
         let calculator = &token_swap.swap_curve().calculator;
         if !calculator.allows_deposits() {
             return Err(SwapError::UnsupportedCurveOperation.into());
@@ -531,15 +1418,18 @@ impl Processor {
             token_a_info,
             token_b_info,
             pool_mint_info,
-            token_program_info,
+            pool_token_program_info,
             Some(source_a_info),
             Some(source_b_info),
             None,
         )?;
 
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
+        let token_a = Self::unpack_token_account(token_a_info, token_a_program_info.key)?;
+        let token_b = Self::unpack_token_account(token_b_info, token_b_program_info.key)?;
+        Self::update_price_observation(swap_info, token_a.amount, token_b.amount)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let token_a_mint = Self::unpack_mint(token_a_mint_info, token_a_program_info.key)?;
+        let token_b_mint = Self::unpack_mint(token_b_mint_info, token_b_program_info.key)?;
         let current_pool_mint_supply = to_u128(pool_mint.supply)?;
         let (pool_token_amount, pool_mint_supply) = if current_pool_mint_supply > 0 {
             (to_u128(pool_token_amount)?, current_pool_mint_supply)
@@ -557,43 +1447,60 @@ impl Processor {
             )
             .ok_or(SwapError::ZeroTradingTokens)?;
         let token_a_amount = to_u64(results.token_a_amount)?;
-        if token_a_amount > maximum_token_a_amount {
-            return Err(SwapError::ExceededSlippage.into());
-        }
         if token_a_amount == 0 {
             return Err(SwapError::ZeroTradingTokens.into());
         }
-        let token_b_amount = to_u64(results.token_b_amount)?;
-        if token_b_amount > maximum_token_b_amount {
+        // The depositor pays the transfer fee on top of what reaches the pool
+        let token_a_transfer_amount = token_a_amount
+            .checked_add(Self::inverse_transfer_fee(
+                token_a_mint_info,
+                token_a_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_a_transfer_amount > maximum_token_a_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
+        let token_b_amount = to_u64(results.token_b_amount)?;
         if token_b_amount == 0 {
             return Err(SwapError::ZeroTradingTokens.into());
         }
+        let token_b_transfer_amount = token_b_amount
+            .checked_add(Self::inverse_transfer_fee(
+                token_b_mint_info,
+                token_b_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_b_transfer_amount > maximum_token_b_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
 
         let pool_token_amount = to_u64(pool_token_amount)?;
 
         Self::token_transfer(
             swap_info.key,
-            token_program_info.clone(),
+            token_a_program_info.clone(),
             source_a_info.clone(),
+            token_a_mint_info.clone(),
             token_a_info.clone(),
             user_transfer_authority_info.clone(),
             token_swap.bump_seed(),
-            token_a_amount,
+            token_a_transfer_amount,
+            token_a_mint.decimals,
         )?;
         Self::token_transfer(
             swap_info.key,
-            token_program_info.clone(),
+            token_b_program_info.clone(),
             source_b_info.clone(),
+            token_b_mint_info.clone(),
             token_b_info.clone(),
             user_transfer_authority_info.clone(),
             token_swap.bump_seed(),
-            token_b_amount,
+            token_b_transfer_amount,
+            token_b_mint.decimals,
         )?;
         Self::token_mint_to(
             swap_info.key,
-            token_program_info.clone(),
+            pool_token_program_info.clone(),
             pool_mint_info.clone(),
             dest_info.clone(),
             authority_info.clone(),
@@ -623,9 +1530,18 @@ impl Processor {
         let dest_token_a_info = next_account_info(account_info_iter)?;
         let dest_token_b_info = next_account_info(account_info_iter)?;
         let pool_fee_account_info = next_account_info(account_info_iter)?;
-        let token_program_info = next_account_info(account_info_iter)?;
+        let token_a_mint_info = next_account_info(account_info_iter)?;
+        let token_b_mint_info = next_account_info(account_info_iter)?;
+        let pool_token_program_info = next_account_info(account_info_iter)?;
+        let token_a_program_info = next_account_info(account_info_iter)?;
+        let token_b_program_info = next_account_info(account_info_iter)?;
 
+        Self::update_amp_ramp(swap_info)?;
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        Self::check_price_oracle(token_swap.as_ref())?;
         Self::check_accounts(
             token_swap.as_ref(),
             program_id,
@@ -634,15 +1550,18 @@ impl Processor {
             token_a_info,
             token_b_info,
             pool_mint_info,
-            token_program_info,
+            pool_token_program_info,
             Some(dest_token_a_info),
             Some(dest_token_b_info),
             Some(pool_fee_account_info),
         )?;
 
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
+        let token_a = Self::unpack_token_account(token_a_info, token_a_program_info.key)?;
+        let token_b = Self::unpack_token_account(token_b_info, token_b_program_info.key)?;
+        Self::update_price_observation(swap_info, token_a.amount, token_b.amount)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let token_a_mint = Self::unpack_mint(token_a_mint_info, token_a_program_info.key)?;
+        let token_b_mint = Self::unpack_mint(token_b_mint_info, token_b_program_info.key)?;
 
         let calculator = &token_swap.swap_curve().calculator;
 
@@ -670,7 +1589,11 @@ impl Processor {
             .ok_or(SwapError::ZeroTradingTokens)?;
         let token_a_amount = to_u64(results.token_a_amount)?;
         let token_a_amount = std::cmp::min(token_a.amount, token_a_amount);
-        if token_a_amount < minimum_token_a_amount {
+        // Slippage is checked against what arrives after the transfer fee
+        let token_a_amount_received = token_a_amount
+            .checked_sub(Self::transfer_fee(token_a_mint_info, token_a_amount)?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_a_amount_received < minimum_token_a_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
         if token_a_amount == 0 && token_a.amount != 0 {
@@ -678,7 +1601,10 @@ impl Processor {
         }
         let token_b_amount = to_u64(results.token_b_amount)?;
         let token_b_amount = std::cmp::min(token_b.amount, token_b_amount);
-        if token_b_amount < minimum_token_b_amount {
+        let token_b_amount_received = token_b_amount
+            .checked_sub(Self::transfer_fee(token_b_mint_info, token_b_amount)?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_b_amount_received < minimum_token_b_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
         if token_b_amount == 0 && token_b.amount != 0 {
@@ -688,17 +1614,19 @@ impl Processor {
         if withdraw_fee > 0 {
             Self::token_transfer(
                 swap_info.key,
-                token_program_info.clone(),
+                pool_token_program_info.clone(),
                 source_info.clone(),
+                pool_mint_info.clone(),
                 pool_fee_account_info.clone(),
                 user_transfer_authority_info.clone(),
                 token_swap.bump_seed(),
                 to_u64(withdraw_fee)?,
+                pool_mint.decimals,
             )?;
         }
         Self::token_burn(
             swap_info.key,
-            token_program_info.clone(),
+            pool_token_program_info.clone(),
             source_info.clone(),
             pool_mint_info.clone(),
             user_transfer_authority_info.clone(),
@@ -709,28 +1637,70 @@ impl Processor {
         if token_a_amount > 0 {
             Self::token_transfer(
                 swap_info.key,
-                token_program_info.clone(),
+                token_a_program_info.clone(),
                 token_a_info.clone(),
+                token_a_mint_info.clone(),
                 dest_token_a_info.clone(),
                 authority_info.clone(),
                 token_swap.bump_seed(),
                 token_a_amount,
+                token_a_mint.decimals,
             )?;
         }
         if token_b_amount > 0 {
             Self::token_transfer(
                 swap_info.key,
-                token_program_info.clone(),
+                token_b_program_info.clone(),
                 token_b_info.clone(),
+                token_b_mint_info.clone(),
                 dest_token_b_info.clone(),
                 authority_info.clone(),
                 token_swap.bump_seed(),
                 token_b_amount,
+                token_b_mint.decimals,
             )?;
         }
         Ok(())
     }
 
+    /// Computes the amount of a single token deposit that reaches a two-token
+    /// pool after the transfer fee, and the pool tokens it mints.
+    fn deposit_single_token_type_amounts(
+        token_swap: &dyn SwapState,
+        source_token_amount: u64,
+        trade_direction: TradeDirection,
+        swap_token_a_amount: u64,
+        swap_token_b_amount: u64,
+        pool_mint_supply: u64,
+        source_token_mint_info: &AccountInfo,
+    ) -> Result<(u64, u128), ProgramError> {
+        // Only what is left after the transfer fee is deposited into the pool
+        let actual_source_token_amount = source_token_amount
+            .checked_sub(Self::transfer_fee(
+                source_token_mint_info,
+                source_token_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+
+        let pool_mint_supply = to_u128(pool_mint_supply)?;
+        let pool_token_amount = if pool_mint_supply > 0 {
+            token_swap
+                .swap_curve()
+                .deposit_single_token_type(
+                    to_u128(actual_source_token_amount)?,
+                    to_u128(swap_token_a_amount)?,
+                    to_u128(swap_token_b_amount)?,
+                    pool_mint_supply,
+                    trade_direction,
+                    token_swap.fees(),
+                )
+                .ok_or(SwapError::ZeroTradingTokens)?
+        } else {
+            token_swap.swap_curve().calculator.new_pool_supply()
+        };
+        Ok((actual_source_token_amount, pool_token_amount))
+    }
+
     /// Processes DepositSingleTokenTypeExactAmountIn
     pub fn process_deposit_single_token_type_exact_amount_in(
         program_id: &Pubkey,
@@ -747,27 +1717,43 @@ impl Processor {
         let swap_token_b_info = next_account_info(account_info_iter)?;
         let pool_mint_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?;
-        let token_program_info = next_account_info(account_info_iter)?;
+        let source_token_mint_info = next_account_info(account_info_iter)?;
+        let source_token_program_info = next_account_info(account_info_iter)?;
+        let pool_token_program_info = next_account_info(account_info_iter)?;
 
+        Self::update_amp_ramp(swap_info)?;
+        Self::update_dynamic_fee(swap_info)?;
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        Self::check_price_oracle(token_swap.as_ref())?;
+        if token_swap.is_paused() {
+            return Err(SwapError::SwapPaused.into());
+        }
         let calculator = &token_swap.swap_curve().calculator;
         if !calculator.allows_deposits() {
             return Err(SwapError::UnsupportedCurveOperation.into());
         }
         let source_account =
-            Self::unpack_token_account(source_info, token_swap.token_program_id())?;
-        let swap_token_a =
-            Self::unpack_token_account(swap_token_a_info, token_swap.token_program_id())?;
-        let swap_token_b =
-            Self::unpack_token_account(swap_token_b_info, token_swap.token_program_id())?;
-
-        let trade_direction = if source_account.mint == swap_token_a.mint {
+            Self::unpack_token_account(source_info, source_token_program_info.key)?;
+        let trade_direction = if source_account.mint == *token_swap.token_a_mint() {
             TradeDirection::AtoB
-        } else if source_account.mint == swap_token_b.mint {
+        } else if source_account.mint == *token_swap.token_b_mint() {
             TradeDirection::BtoA
         } else {
             return Err(SwapError::IncorrectSwapAccount.into());
         };
+        let (swap_token_a, swap_token_b) = match trade_direction {
+            TradeDirection::AtoB => (
+                Self::unpack_token_account(swap_token_a_info, source_token_program_info.key)?,
+                Self::unpack_swap_token_account(swap_token_b_info)?,
+            ),
+            TradeDirection::BtoA => (
+                Self::unpack_swap_token_account(swap_token_a_info)?,
+                Self::unpack_token_account(swap_token_b_info, source_token_program_info.key)?,
+            ),
+        };
 
         let (source_a_info, source_b_info) = match trade_direction {
             TradeDirection::AtoB => (Some(source_info), None),
@@ -782,29 +1768,25 @@ impl Processor {
             swap_token_a_info,
             swap_token_b_info,
             pool_mint_info,
-            token_program_info,
+            pool_token_program_info,
             source_a_info,
             source_b_info,
             None,
         )?;
 
+        Self::update_price_observation(swap_info, swap_token_a.amount, swap_token_b.amount)?;
+
+        let source_mint = Self::unpack_mint(source_token_mint_info, source_token_program_info.key)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
-        let pool_mint_supply = to_u128(pool_mint.supply)?;
-        let pool_token_amount = if pool_mint_supply > 0 {
-            token_swap
-                .swap_curve()
-                .deposit_single_token_type(
-                    to_u128(source_token_amount)?,
-                    to_u128(swap_token_a.amount)?,
-                    to_u128(swap_token_b.amount)?,
-                    pool_mint_supply,
-                    trade_direction,
-                    token_swap.fees(),
-                )
-                .ok_or(SwapError::ZeroTradingTokens)?
-        } else {
-            calculator.new_pool_supply()
-        };
+        let (_, pool_token_amount) = Self::deposit_single_token_type_amounts(
+            token_swap.as_ref(),
+            source_token_amount,
+            trade_direction,
+            swap_token_a.amount,
+            swap_token_b.amount,
+            pool_mint.supply,
+            source_token_mint_info,
+        )?;
 
         let pool_token_amount = to_u64(pool_token_amount)?;
         if pool_token_amount < minimum_pool_token_amount {
@@ -818,29 +1800,33 @@ impl Processor {
             TradeDirection::AtoB => {
                 Self::token_transfer(
                     swap_info.key,
-                    token_program_info.clone(),
+                    source_token_program_info.clone(),
                     source_info.clone(),
+                    source_token_mint_info.clone(),
                     swap_token_a_info.clone(),
                     user_transfer_authority_info.clone(),
                     token_swap.bump_seed(),
                     source_token_amount,
+                    source_mint.decimals,
                 )?;
             }
             TradeDirection::BtoA => {
                 Self::token_transfer(
                     swap_info.key,
-                    token_program_info.clone(),
+                    source_token_program_info.clone(),
                     source_info.clone(),
+                    source_token_mint_info.clone(),
                     swap_token_b_info.clone(),
                     user_transfer_authority_info.clone(),
                     token_swap.bump_seed(),
                     source_token_amount,
+                    source_mint.decimals,
                 )?;
             }
         }
         Self::token_mint_to(
             swap_info.key,
-            token_program_info.clone(),
+            pool_token_program_info.clone(),
             pool_mint_info.clone(),
             destination_info.clone(),
             authority_info.clone(),
@@ -851,6 +1837,41 @@ impl Processor {
         Ok(())
     }
 
+    /// Computes the amount sent by a two-token pool for a single token
+    /// withdrawal, including the transfer fee, and the pool tokens it burns,
+    /// without the withdrawal fee.
+    fn withdraw_single_token_type_amounts(
+        token_swap: &dyn SwapState,
+        destination_token_amount: u64,
+        trade_direction: TradeDirection,
+        swap_token_a_amount: u64,
+        swap_token_b_amount: u64,
+        pool_mint_supply: u64,
+        destination_token_mint_info: &AccountInfo,
+    ) -> Result<(u64, u128), ProgramError> {
+        // The pool sends enough for the requested amount to arrive after the
+        // transfer fee
+        let destination_transfer_amount = destination_token_amount
+            .checked_add(Self::inverse_transfer_fee(
+                destination_token_mint_info,
+                destination_token_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+
+        let burn_pool_token_amount = token_swap
+            .swap_curve()
+            .withdraw_single_token_type_exact_out(
+                to_u128(destination_transfer_amount)?,
+                to_u128(swap_token_a_amount)?,
+                to_u128(swap_token_b_amount)?,
+                to_u128(pool_mint_supply)?,
+                trade_direction,
+                token_swap.fees(),
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        Ok((destination_transfer_amount, burn_pool_token_amount))
+    }
+
     /// Processes a [WithdrawSingleTokenTypeExactAmountOut](enum.Instruction.html).
     pub fn process_withdraw_single_token_type_exact_amount_out(
         program_id: &Pubkey,
@@ -868,23 +1889,36 @@ impl Processor {
         let swap_token_b_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?;
         let pool_fee_account_info = next_account_info(account_info_iter)?;
-        let token_program_info = next_account_info(account_info_iter)?;
+        let destination_token_mint_info = next_account_info(account_info_iter)?;
+        let pool_token_program_info = next_account_info(account_info_iter)?;
+        let destination_token_program_info = next_account_info(account_info_iter)?;
 
+        Self::update_amp_ramp(swap_info)?;
+        Self::update_dynamic_fee(swap_info)?;
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        Self::check_price_oracle(token_swap.as_ref())?;
         let destination_account =
-            Self::unpack_token_account(destination_info, token_swap.token_program_id())?;
-        let swap_token_a =
-            Self::unpack_token_account(swap_token_a_info, token_swap.token_program_id())?;
-        let swap_token_b =
-            Self::unpack_token_account(swap_token_b_info, token_swap.token_program_id())?;
-
-        let trade_direction = if destination_account.mint == swap_token_a.mint {
+            Self::unpack_token_account(destination_info, destination_token_program_info.key)?;
+        let trade_direction = if destination_account.mint == *token_swap.token_a_mint() {
             TradeDirection::AtoB
-        } else if destination_account.mint == swap_token_b.mint {
+        } else if destination_account.mint == *token_swap.token_b_mint() {
             TradeDirection::BtoA
         } else {
             return Err(SwapError::IncorrectSwapAccount.into());
         };
+        let (swap_token_a, swap_token_b) = match trade_direction {
+            TradeDirection::AtoB => (
+                Self::unpack_token_account(swap_token_a_info, destination_token_program_info.key)?,
+                Self::unpack_swap_token_account(swap_token_b_info)?,
+            ),
+            TradeDirection::BtoA => (
+                Self::unpack_swap_token_account(swap_token_a_info)?,
+                Self::unpack_token_account(swap_token_b_info, destination_token_program_info.key)?,
+            ),
+        };
 
         let (destination_a_info, destination_b_info) = match trade_direction {
             TradeDirection::AtoB => (Some(destination_info), None),
@@ -898,28 +1932,29 @@ impl Processor {
             swap_token_a_info,
             swap_token_b_info,
             pool_mint_info,
-            token_program_info,
+            pool_token_program_info,
             destination_a_info,
             destination_b_info,
             Some(pool_fee_account_info),
         )?;
 
-        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
-        let pool_mint_supply = to_u128(pool_mint.supply)?;
-        let swap_token_a_amount = to_u128(swap_token_a.amount)?;
-        let swap_token_b_amount = to_u128(swap_token_b.amount)?;
+        Self::update_price_observation(swap_info, swap_token_a.amount, swap_token_b.amount)?;
 
-        let burn_pool_token_amount = token_swap
-            .swap_curve()
-            .withdraw_single_token_type_exact_out(
-                to_u128(destination_token_amount)?,
-                swap_token_a_amount,
-                swap_token_b_amount,
-                pool_mint_supply,
+        let destination_mint = Self::unpack_mint(
+            destination_token_mint_info,
+            destination_token_program_info.key,
+        )?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let (destination_transfer_amount, burn_pool_token_amount) =
+            Self::withdraw_single_token_type_amounts(
+                token_swap.as_ref(),
+                destination_token_amount,
                 trade_direction,
-                token_swap.fees(),
-            )
-            .ok_or(SwapError::ZeroTradingTokens)?;
+                swap_token_a.amount,
+                swap_token_b.amount,
+                pool_mint.supply,
+                destination_token_mint_info,
+            )?;
 
         let withdraw_fee: u128 = if *pool_fee_account_info.key == *source_info.key {
             // withdrawing from the fee account, don't assess withdraw fee
@@ -944,17 +1979,19 @@ impl Processor {
         if withdraw_fee > 0 {
             Self::token_transfer(
                 swap_info.key,
-                token_program_info.clone(),
+                pool_token_program_info.clone(),
                 source_info.clone(),
+                pool_mint_info.clone(),
                 pool_fee_account_info.clone(),
                 user_transfer_authority_info.clone(),
                 token_swap.bump_seed(),
                 to_u64(withdraw_fee)?,
+                pool_mint.decimals,
             )?;
         }
         Self::token_burn(
             swap_info.key,
-            token_program_info.clone(),
+            pool_token_program_info.clone(),
             source_info.clone(),
             pool_mint_info.clone(),
             user_transfer_authority_info.clone(),
@@ -966,23 +2003,27 @@ impl Processor {
             TradeDirection::AtoB => {
                 Self::token_transfer(
                     swap_info.key,
-                    token_program_info.clone(),
+                    destination_token_program_info.clone(),
                     swap_token_a_info.clone(),
+                    destination_token_mint_info.clone(),
                     destination_info.clone(),
                     authority_info.clone(),
                     token_swap.bump_seed(),
-                    destination_token_amount,
+                    destination_transfer_amount,
+                    destination_mint.decimals,
                 )?;
             }
             TradeDirection::BtoA => {
                 Self::token_transfer(
                     swap_info.key,
-                    token_program_info.clone(),
+                    destination_token_program_info.clone(),
                     swap_token_b_info.clone(),
+                    destination_token_mint_info.clone(),
                     destination_info.clone(),
                     authority_info.clone(),
                     token_swap.bump_seed(),
-                    destination_token_amount,
+                    destination_transfer_amount,
+                    destination_mint.decimals,
                 )?;
             }
         }
@@ -990,3211 +2031,9302 @@ impl Processor {
         Ok(())
     }
 
-    /// Processes an [Instruction](enum.Instruction.html).
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
-        Self::process_with_constraints(program_id, accounts, input, &SWAP_CONSTRAINTS)
+    /// Processes an [UpdateFees](enum.Instruction.html).
+    pub fn process_update_fees(
+        program_id: &Pubkey,
+        fees: Fees,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_admin(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            admin_info,
+            pool_fee_account_info,
+            swap_constraints,
+        )?;
+        if let Some(swap_constraints) = swap_constraints {
+            swap_constraints.validate_fees(&fees)?;
+        }
+        fees.validate_update()?;
+        if let Some(fee_tier) = token_swap.fee_tier() {
+            // a canonical pool keeps the trade fee named by its fee tier
+            registry::validate_fee_tier(&fees, fee_tier)?;
+        }
+
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        if let SwapVersion::SwapV2(swap_v2) = &swap_version {
+            // The dynamic fee keeps setting the trade fee numerator, over the
+            // new trade fee denominator
+            if swap_v2.dynamic_fee.is_enabled() {
+                swap_v2.dynamic_fee.validate(&fees)?;
+            }
+        }
+        swap_version.set_fees(fees);
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())?;
+        Ok(())
     }
 
-    /// Processes an instruction given extra constraint
-    pub fn process_with_constraints(
+    /// Processes a [SetPaused](enum.Instruction.html).
+    pub fn process_set_paused(
         program_id: &Pubkey,
+        is_paused: bool,
         accounts: &[AccountInfo],
-        input: &[u8],
         swap_constraints: &Option<SwapConstraints>,
     ) -> ProgramResult {
-        let instruction = SwapInstruction::unpack(input)?;
-        match instruction {
-            SwapInstruction::Initialize(Initialize { fees, swap_curve }) => {
-                msg!("Instruction: Init");
-                Self::process_initialize(program_id, fees, swap_curve, accounts, swap_constraints)
-            }
-            SwapInstruction::Swap(Swap {
-                amount_in,
-                minimum_amount_out,
-            }) => {
-                msg!("Instruction: Swap");
-                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
-            }
-            SwapInstruction::DepositAllTokenTypes(DepositAllTokenTypes {
-                pool_token_amount,
-                maximum_token_a_amount,
-                maximum_token_b_amount,
-            }) => {
-                msg!("Instruction: DepositAllTokenTypes");
-                Self::process_deposit_all_token_types(
-                    program_id,
-                    pool_token_amount,
-                    maximum_token_a_amount,
-                    maximum_token_b_amount,
-                    accounts,
-                )
-            }
-            SwapInstruction::WithdrawAllTokenTypes(WithdrawAllTokenTypes {
-                pool_token_amount,
-                minimum_token_a_amount,
-                minimum_token_b_amount,
-            }) => {
-                msg!("Instruction: WithdrawAllTokenTypes");
-                Self::process_withdraw_all_token_types(
-                    program_id,
-                    pool_token_amount,
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                    accounts,
-                )
-            }
-            SwapInstruction::DepositSingleTokenTypeExactAmountIn(
-                DepositSingleTokenTypeExactAmountIn {
-                    source_token_amount,
-                    minimum_pool_token_amount,
-                },
-            ) => {
-                msg!("Instruction: DepositSingleTokenTypeExactAmountIn");
-                Self::process_deposit_single_token_type_exact_amount_in(
-                    program_id,
-                    source_token_amount,
-                    minimum_pool_token_amount,
-                    accounts,
-                )
-            }
-            SwapInstruction::WithdrawSingleTokenTypeExactAmountOut(
-                WithdrawSingleTokenTypeExactAmountOut {
-                    destination_token_amount,
-                    maximum_pool_token_amount,
-                },
-            ) => {
-                msg!("Instruction: WithdrawSingleTokenTypeExactAmountOut");
-                Self::process_withdraw_single_token_type_exact_amount_out(
-                    program_id,
-                    destination_token_amount,
-                    maximum_pool_token_amount,
-                    accounts,
-                )
-            }
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_admin(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            admin_info,
+            pool_fee_account_info,
+            swap_constraints,
+        )?;
+
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        match &mut swap_version {
+            SwapVersion::SwapV2(swap_v2) => swap_v2.is_paused = is_paused,
+            _ => return Err(SwapError::UnsupportedSwapVersion.into()),
         }
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())?;
+        Ok(())
     }
-}
 
-fn to_u128(val: u64) -> Result<u128, SwapError> {
-    val.try_into().map_err(|_| SwapError::ConversionFailure)
-}
+    /// Processes a [RampAmp](enum.Instruction.html).
+    pub fn process_ramp_amp(
+        program_id: &Pubkey,
+        target_amp: u64,
+        stop_timestamp: UnixTimestamp,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
 
-fn to_u64(val: u128) -> Result<u64, SwapError> {
-    val.try_into().map_err(|_| SwapError::ConversionFailure)
-}
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_admin(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            admin_info,
+            pool_fee_account_info,
+            swap_constraints,
+        )?;
 
-fn invoke_signed_wrapper<T>(
-    instruction: &Instruction,
-    account_infos: &[AccountInfo],
-    signers_seeds: &[&[&[u8]]],
-) -> Result<(), ProgramError>
-where
-    T: 'static + PrintProgramError + DecodeError<T> + FromPrimitive + Error,
-{
-    invoke_signed(instruction, account_infos, signers_seeds).map_err(|err| {
-        err.print::<T>();
-        err
-    })
-}
+        // A new ramp starts from wherever the current one has got to
+        Self::update_amp_ramp(swap_info)?;
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        let swap_v2 = match &mut swap_version {
+            SwapVersion::SwapV2(swap_v2) => swap_v2,
+            _ => return Err(SwapError::UnsupportedSwapVersion.into()),
+        };
+        if swap_v2.swap_curve.curve_type != CurveType::Stable {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        let mut calculator = [0u8; StableCurve::LEN];
+        swap_v2
+            .swap_curve
+            .calculator
+            .pack_into_slice(&mut calculator);
+        let current_amp = StableCurve::unpack_from_slice(&calculator)?.amp;
+        swap_v2.amp_ramp = AmpRamp::new(
+            current_amp,
+            target_amp,
+            Clock::get()?.unix_timestamp,
+            stop_timestamp,
+        )?;
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        curve::calculator::{CurveCalculator, INITIAL_SWAP_POOL_AMOUNT},
-        curve::{
-            base::CurveType, constant_price::ConstantPriceCurve,
-            constant_product::ConstantProductCurve, offset::OffsetCurve,
-        },
-        instruction::{
-            deposit_all_token_types, deposit_single_token_type_exact_amount_in, initialize, swap,
-            withdraw_all_token_types, withdraw_single_token_type_exact_amount_out,
-        },
-    };
-    use solana_program::{instruction::Instruction, program_stubs, rent::Rent};
-    use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
-    use spl_token::{
-        error::TokenError,
-        instruction::{
-            approve, freeze_account, initialize_account, initialize_mint, mint_to, revoke,
-            set_authority, AuthorityType,
-        },
-    };
-    use std::sync::Arc;
+    /// Processes a [SetDynamicFee](enum.Instruction.html).
+    pub fn process_set_dynamic_fee(
+        program_id: &Pubkey,
+        set_dynamic_fee: SetDynamicFee,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
 
-    // Test program id for the swap program.
-    const SWAP_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_admin(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            admin_info,
+            pool_fee_account_info,
+            swap_constraints,
+        )?;
 
-    struct TestSyscallStubs {}
-    impl program_stubs::SyscallStubs for TestSyscallStubs {
-        fn sol_invoke_signed(
-            &self,
-            instruction: &Instruction,
-            account_infos: &[AccountInfo],
-            signers_seeds: &[&[&[u8]]],
-        ) -> ProgramResult {
-            msg!("TestSyscallStubs::sol_invoke_signed()");
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        let swap_v2 = match &mut swap_version {
+            SwapVersion::SwapV2(swap_v2) => swap_v2,
+            _ => return Err(SwapError::UnsupportedSwapVersion.into()),
+        };
+        if set_dynamic_fee.max_volatility == 0 {
+            // the trade fee numerator stays wherever the dynamic fee left it
+            swap_v2.dynamic_fee = DynamicFee::default();
+        } else {
+            let mut dynamic_fee = DynamicFee::new(
+                set_dynamic_fee.min_trade_fee_numerator,
+                set_dynamic_fee.max_trade_fee_numerator,
+                set_dynamic_fee.max_volatility,
+                set_dynamic_fee.volatility_half_life,
+                &swap_v2.fees,
+            )?;
+            // the trade fee moves anywhere between the minimum and maximum
+            for trade_fee_numerator in [
+                dynamic_fee.min_trade_fee_numerator,
+                dynamic_fee.max_trade_fee_numerator,
+            ] {
+                let mut bound_fees = swap_v2.fees.clone();
+                bound_fees.trade_fee_numerator = trade_fee_numerator;
+                if let Some(swap_constraints) = swap_constraints {
+                    swap_constraints.validate_fees(&bound_fees)?;
+                }
+                // a canonical pool keeps the trade fee named by its fee tier
+                if let Some(fee_tier) = swap_v2.fee_tier {
+                    registry::validate_fee_tier(&bound_fees, fee_tier)?;
+                }
+            }
+            // The volatility built up so far carries over to the new fees
+            let timestamp = Clock::get()?.unix_timestamp;
+            dynamic_fee.volatility = swap_v2.dynamic_fee.volatility_at(timestamp);
+            dynamic_fee.last_update_timestamp = timestamp;
+            swap_v2.fees.trade_fee_numerator =
+                dynamic_fee.trade_fee_numerator(dynamic_fee.volatility);
+            swap_v2.dynamic_fee = dynamic_fee;
+        }
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-            let mut new_account_infos = vec![];
+    /// Processes a [SetPriceOracle](enum.Instruction.html).
+    pub fn process_set_price_oracle(
+        program_id: &Pubkey,
+        set_price_oracle: SetPriceOracle,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let price_info = next_account_info(account_info_iter)?;
 
-            // mimic check for token program in accounts
-            if !account_infos.iter().any(|x| *x.key == spl_token::id()) {
-                return Err(ProgramError::InvalidAccountData);
-            }
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_admin(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            admin_info,
+            pool_fee_account_info,
+            swap_constraints,
+        )?;
 
-            for meta in instruction.accounts.iter() {
-                for account_info in account_infos.iter() {
-                    if meta.pubkey == *account_info.key {
-                        let mut new_account_info = account_info.clone();
-                        for seeds in signers_seeds.iter() {
-                            let signer =
-                                Pubkey::create_program_address(seeds, &SWAP_PROGRAM_ID).unwrap();
-                            if *account_info.key == signer {
-                                new_account_info.is_signer = true;
-                            }
-                        }
-                        new_account_infos.push(new_account_info);
-                    }
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        let swap_v2 = match &mut swap_version {
+            SwapVersion::SwapV2(swap_v2) => swap_v2,
+            _ => return Err(SwapError::UnsupportedSwapVersion.into()),
+        };
+        if set_price_oracle.max_staleness == 0 {
+            // the curve keeps the last price refreshed from the feed
+            swap_v2.price_oracle = PriceOracle::default();
+        } else {
+            if swap_v2.swap_curve.curve_type != CurveType::ConstantPrice {
+                return Err(SwapError::UnsupportedCurveOperation.into());
+            }
+            let mut price_oracle = PriceOracle {
+                price_account: *price_info.key,
+                price_source: set_price_oracle.price_source,
+                max_staleness: set_price_oracle.max_staleness,
+                max_deviation_bps: set_price_oracle.max_deviation_bps,
+                ..PriceOracle::default()
+            };
+            price_oracle.validate()?;
+            match price_oracle.price_source {
+                PriceSource::Record => {
+                    Self::unpack_record_feed(price_info)?;
+                }
+                PriceSource::SwapTokenA | PriceSource::SwapTokenB => {
+                    // the first refresh averages the feed price from now on
+                    let (price_cumulative, observation_timestamp) = Self::unpack_feed_observation(
+                        program_id,
+                        swap_info,
+                        &swap_v2.token_a_mint,
+                        &swap_v2.token_b_mint,
+                        price_info,
+                        price_oracle.price_source,
+                    )?;
+                    price_oracle.price_cumulative = price_cumulative;
+                    price_oracle.observation_timestamp = observation_timestamp;
                 }
             }
-
-            spl_token::processor::Processor::process(
-                &instruction.program_id,
-                &new_account_infos,
-                &instruction.data,
-            )
+            swap_v2.price_oracle = price_oracle;
         }
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())?;
+        Ok(())
     }
 
-    fn test_syscall_stubs() {
-        use std::sync::Once;
-        static ONCE: Once = Once::new();
+    /// Processes a [RefreshOraclePrice](enum.Instruction.html).
+    pub fn process_refresh_oracle_price(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let price_info = next_account_info(account_info_iter)?;
 
-        ONCE.call_once(|| {
-            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        let swap_v2 = match &mut swap_version {
+            SwapVersion::SwapV2(swap_v2) => swap_v2,
+            _ => return Err(SwapError::UnsupportedSwapVersion.into()),
+        };
+        if swap_v2.is_locked {
+            return Err(SwapError::SwapLocked.into());
+        }
+        let mut price_oracle = swap_v2.price_oracle;
+        if !price_oracle.is_enabled() {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        if *price_info.key != price_oracle.price_account {
+            return Err(SwapError::InvalidOracleAccount.into());
+        }
+
+        let (price, price_timestamp) = match price_oracle.price_source {
+            PriceSource::Record => Self::unpack_record_feed(price_info)?,
+            PriceSource::SwapTokenA | PriceSource::SwapTokenB => {
+                let (price_cumulative, observation_timestamp) = Self::unpack_feed_observation(
+                    program_id,
+                    swap_info,
+                    &swap_v2.token_a_mint,
+                    &swap_v2.token_b_mint,
+                    price_info,
+                    price_oracle.price_source,
+                )?;
+                let elapsed =
+                    observation_timestamp.saturating_sub(price_oracle.observation_timestamp);
+                if elapsed <= 0 {
+                    // nothing was traded on the feed since the last refresh
+                    return Ok(());
+                }
+                let average_price =
+                    price_cumulative.wrapping_sub(price_oracle.price_cumulative) / elapsed as u128;
+                price_oracle.price_cumulative = price_cumulative;
+                price_oracle.observation_timestamp = observation_timestamp;
+                // round to the nearest whole price, an average under half a
+                // token A per token B cannot be traded and is refused
+                let average_price = average_price
+                    .checked_add(1 << (PRICE_FRACTIONAL_BITS - 1))
+                    .ok_or(SwapError::CalculationFailure)?;
+                (
+                    to_u64(average_price >> PRICE_FRACTIONAL_BITS)?,
+                    observation_timestamp,
+                )
+            }
+        };
+        if price_timestamp <= price_oracle.price_timestamp {
+            // the feed has not published a new price since the last refresh
+            return Ok(());
+        }
+
+        let mut swap_curve = [0u8; SwapCurve::LEN];
+        swap_v2.swap_curve.pack_into_slice(&mut swap_curve);
+        let curve = ConstantPriceCurve::unpack_from_slice(&swap_curve[1..])?;
+        price_oracle.check_price(
+            curve.token_b_price,
+            price,
+            price_timestamp,
+            Clock::get()?.unix_timestamp,
+        )?;
+        price_oracle.price_timestamp = price_timestamp;
+        swap_v2.price_oracle = price_oracle;
+        swap_v2.swap_curve.calculator = Arc::new(ConstantPriceCurve {
+            token_b_price: price,
         });
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())?;
+        Ok(())
     }
 
-    struct SwapAccountInfo {
-        bump_seed: u8,
-        authority_key: Pubkey,
-        fees: Fees,
-        swap_curve: SwapCurve,
-        swap_key: Pubkey,
-        swap_account: Account,
-        pool_mint_key: Pubkey,
-        pool_mint_account: Account,
-        pool_fee_key: Pubkey,
-        pool_fee_account: Account,
-        pool_token_key: Pubkey,
-        pool_token_account: Account,
-        token_a_key: Pubkey,
-        token_a_account: Account,
-        token_a_mint_key: Pubkey,
-        token_a_mint_account: Account,
-        token_b_key: Pubkey,
-        token_b_account: Account,
-        token_b_mint_key: Pubkey,
-        token_b_mint_account: Account,
-    }
+    /// Processes a [SetFeeAccount](enum.Instruction.html).
+    pub fn process_set_fee_account(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let new_pool_fee_account_info = next_account_info(account_info_iter)?;
 
-    impl SwapAccountInfo {
-        pub fn new(
-            user_key: &Pubkey,
-            fees: Fees,
-            swap_curve: SwapCurve,
-            token_a_amount: u64,
-            token_b_amount: u64,
-        ) -> Self {
-            let swap_key = Pubkey::new_unique();
-            let swap_account = Account::new(0, SwapVersion::LATEST_LEN, &SWAP_PROGRAM_ID);
-            let (authority_key, bump_seed) =
-                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
-
-            let (pool_mint_key, mut pool_mint_account) =
-                create_mint(&spl_token::id(), &authority_key, None);
-            let (pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &pool_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                user_key,
-                0,
-            );
-            let (pool_fee_key, pool_fee_account) = mint_token(
-                &spl_token::id(),
-                &pool_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                user_key,
-                0,
-            );
-            let (token_a_mint_key, mut token_a_mint_account) =
-                create_mint(&spl_token::id(), user_key, None);
-            let (token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &token_a_mint_key,
-                &mut token_a_mint_account,
-                user_key,
-                &authority_key,
-                token_a_amount,
-            );
-            let (token_b_mint_key, mut token_b_mint_account) =
-                create_mint(&spl_token::id(), user_key, None);
-            let (token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &token_b_mint_key,
-                &mut token_b_mint_account,
-                user_key,
-                &authority_key,
-                token_b_amount,
-            );
-
-            SwapAccountInfo {
-                bump_seed,
-                authority_key,
-                fees,
-                swap_curve,
-                swap_key,
-                swap_account,
-                pool_mint_key,
-                pool_mint_account,
-                pool_fee_key,
-                pool_fee_account,
-                pool_token_key,
-                pool_token_account,
-                token_a_key,
-                token_a_account,
-                token_a_mint_key,
-                token_a_mint_account,
-                token_b_key,
-                token_b_account,
-                token_b_mint_key,
-                token_b_mint_account,
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_admin(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            admin_info,
+            pool_fee_account_info,
+            swap_constraints,
+        )?;
+        let new_pool_fee_account =
+            Self::unpack_token_account(new_pool_fee_account_info, token_swap.token_program_id())?;
+        if new_pool_fee_account.mint != *token_swap.pool_mint() {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if new_pool_fee_account.owner
+            == Self::authority_id(program_id, swap_info.key, token_swap.bump_seed())?
+        {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        if let Some(swap_constraints) = swap_constraints {
+            let owner_key = swap_constraints
+                .owner_key
+                .parse::<Pubkey>()
+                .map_err(|_| SwapError::InvalidOwner)?;
+            if new_pool_fee_account.owner != owner_key {
+                return Err(SwapError::InvalidOwner.into());
             }
         }
 
-        pub fn initialize_swap(&mut self) -> ProgramResult {
-            do_process_instruction(
-                initialize(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    &self.pool_token_key,
-                    self.fees.clone(),
-                    self.swap_curve.clone(),
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    &mut self.pool_fee_account,
-                    &mut self.pool_token_account,
-                    &mut Account::default(),
-                ],
-            )
+        let mut swap_version = SwapVersion::unpack_version(&swap_info.data.borrow())?;
+        swap_version.set_pool_fee_account(*new_pool_fee_account_info.key);
+        SwapVersion::pack(swap_version, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [FlashLoan](enum.Instruction.html).
+    pub fn process_flash_loan(
+        program_id: &Pubkey,
+        amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let swap_token_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let receiver_program_info = next_account_info(account_info_iter)?;
+        let receiver_account_infos = account_info_iter.as_slice();
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        Self::update_dynamic_fee(swap_info)?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        if token_swap.is_paused() {
+            return Err(SwapError::SwapPaused.into());
+        }
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed())?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if !(*swap_token_info.key == *token_swap.token_a_account()
+            || *swap_token_info.key == *token_swap.token_b_account())
+        {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if swap_token_info.key == destination_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if receiver_program_info.key == program_id {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
         }
 
-        pub fn setup_token_accounts(
-            &mut self,
-            mint_owner: &Pubkey,
-            account_owner: &Pubkey,
-            a_amount: u64,
-            b_amount: u64,
-            pool_amount: u64,
-        ) -> (Pubkey, Account, Pubkey, Account, Pubkey, Account) {
-            let (token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &self.token_a_mint_key,
-                &mut self.token_a_mint_account,
-                mint_owner,
-                account_owner,
-                a_amount,
-            );
-            let (token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &self.token_b_mint_key,
-                &mut self.token_b_mint_account,
-                mint_owner,
-                account_owner,
-                b_amount,
-            );
-            let (pool_key, pool_account) = mint_token(
-                &spl_token::id(),
-                &self.pool_mint_key,
-                &mut self.pool_mint_account,
-                &self.authority_key,
-                account_owner,
-                pool_amount,
-            );
-            (
-                token_a_key,
-                token_a_account,
-                token_b_key,
-                token_b_account,
-                pool_key,
-                pool_account,
-            )
+        let swap_token = Self::unpack_token_account(swap_token_info, token_program_info.key)?;
+        let token_mint = Self::unpack_mint(token_mint_info, token_program_info.key)?;
+        let fee = to_u64(
+            token_swap
+                .fees()
+                .trading_fee(u128::from(amount))
+                .ok_or(SwapError::FeeCalculationFailure)?,
+        )?;
+        let repaid_amount = swap_token
+            .amount
+            .checked_add(fee)
+            .ok_or(SwapError::CalculationFailure)?;
+
+        // Every instruction on the swap is refused until the loan is repaid,
+        // so the receiver cannot trade against the lent reserves
+        Self::set_locked(swap_info, true)?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_token_info.clone(),
+            token_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed(),
+            amount,
+            token_mint.decimals,
+        )?;
+
+        let mut receiver_accounts = vec![
+            AccountMeta::new_readonly(*swap_info.key, false),
+            AccountMeta::new(*destination_info.key, false),
+            AccountMeta::new(*swap_token_info.key, false),
+            AccountMeta::new_readonly(*token_mint_info.key, false),
+            AccountMeta::new_readonly(*token_program_info.key, false),
+        ];
+        receiver_accounts.extend(
+            receiver_account_infos
+                .iter()
+                .map(|account_info| AccountMeta {
+                    pubkey: *account_info.key,
+                    is_signer: account_info.is_signer,
+                    is_writable: account_info.is_writable,
+                }),
+        );
+        let receive_flash_loan = Instruction {
+            program_id: *receiver_program_info.key,
+            accounts: receiver_accounts,
+            data: ReceiveFlashLoan { amount, fee }.pack(),
+        };
+        invoke(&receive_flash_loan, accounts)?;
+
+        let swap_token = Self::unpack_token_account(swap_token_info, token_program_info.key)?;
+        if swap_token.amount < repaid_amount {
+            return Err(SwapError::FlashLoanNotRepaid.into());
         }
+        Self::set_locked(swap_info, false)
+    }
 
-        fn get_token_account(&self, account_key: &Pubkey) -> &Account {
-            if *account_key == self.token_a_key {
-                return &self.token_a_account;
-            } else if *account_key == self.token_b_key {
-                return &self.token_b_account;
+    /// Checks the accounts shared by every instruction on a multi-token
+    /// stable swap, returning the swap and the reserves of its token
+    /// accounts, given in pool order
+    fn unpack_multi_stable(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        authority_info: &AccountInfo,
+        pool_mint_info: &AccountInfo,
+        token_program_info: &AccountInfo,
+        swap_token_infos: &[&AccountInfo],
+    ) -> Result<(MultiStableSwap, Vec<u128>), ProgramError> {
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = MultiStableSwap::unpack_account(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if *pool_mint_info.key != token_swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if *token_program_info.key != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        if swap_token_infos.len() != token_swap.tokens().len() {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        let mut reserves = Vec::with_capacity(swap_token_infos.len());
+        for (swap_token_info, token) in swap_token_infos.iter().zip(token_swap.tokens()) {
+            if swap_token_info.key != token {
+                return Err(SwapError::IncorrectSwapAccount.into());
             }
-            panic!("Could not find matching swap token account");
+            let swap_token = Self::unpack_token_account(swap_token_info, token_program_info.key)?;
+            reserves.push(to_u128(swap_token.amount)?);
         }
+        Ok((token_swap, reserves))
+    }
 
-        fn set_token_account(&mut self, account_key: &Pubkey, account: Account) {
-            if *account_key == self.token_a_key {
-                self.token_a_account = account;
-                return;
-            } else if *account_key == self.token_b_key {
-                self.token_b_account = account;
-                return;
-            }
-            panic!("Could not find matching swap token account");
+    /// Checks that the given mints are the swap's, in pool order
+    fn check_multi_stable_mints(
+        token_swap: &MultiStableSwap,
+        token_mint_infos: &[&AccountInfo],
+    ) -> ProgramResult {
+        if token_mint_infos
+            .iter()
+            .map(|token_mint_info| token_mint_info.key)
+            .ne(token_swap.token_mints().iter())
+        {
+            return Err(SwapError::IncorrectSwapAccount.into());
         }
+        Ok(())
+    }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn swap(
-            &mut self,
-            user_key: &Pubkey,
-            user_source_key: &Pubkey,
-            user_source_account: &mut Account,
-            swap_source_key: &Pubkey,
-            swap_destination_key: &Pubkey,
-            user_destination_key: &Pubkey,
-            user_destination_account: &mut Account,
-            amount_in: u64,
-            minimum_amount_out: u64,
-        ) -> ProgramResult {
-            let user_transfer_key = Pubkey::new_unique();
-            // approve moving from user source account
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    user_source_key,
-                    &user_transfer_key,
-                    user_key,
-                    &[],
-                    amount_in,
-                )
-                .unwrap(),
-                vec![
-                    user_source_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+    /// Processes an [InitializeMultiStable](enum.Instruction.html).
+    pub fn process_initialize_multi_stable(
+        program_id: &Pubkey,
+        fees: Fees,
+        amp: u64,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let fee_account_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_infos = account_info_iter.as_slice();
 
-            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
-            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+        let token_count = token_infos.len() / 2;
+        if token_infos.len() % 2 != 0 || !(MIN_TOKENS..=MAX_TOKENS).contains(&token_count) {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let token_program_id = *token_program_info.key;
+        if SwapVersion::is_initialized(&swap_info.data.borrow()) {
+            return Err(SwapError::AlreadyInUse.into());
+        }
 
-            // perform the swap
-            do_process_instruction(
-                swap(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_key,
-                    user_source_key,
-                    swap_source_key,
-                    swap_destination_key,
-                    user_destination_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    None,
-                    Swap {
-                        amount_in,
-                        minimum_amount_out,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    user_source_account,
-                    &mut swap_source_account,
-                    &mut swap_destination_account,
-                    user_destination_account,
-                    &mut self.pool_mint_account,
-                    &mut self.pool_fee_account,
-                    &mut Account::default(),
-                ],
-            )?;
+        let (swap_authority, bump_seed) =
+            Pubkey::find_program_address(&[&swap_info.key.to_bytes()], program_id);
+        if *authority_info.key != swap_authority {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        let fee_account = Self::unpack_token_account(fee_account_info, &token_program_id)?;
+        let destination = Self::unpack_token_account(destination_info, &token_program_id)?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, &token_program_id)?;
+        if *pool_mint_info.owner != token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        if *authority_info.key == destination.owner {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        if *authority_info.key == fee_account.owner {
+            return Err(SwapError::InvalidOutputOwner.into());
+        }
+        if COption::Some(*authority_info.key) != pool_mint.mint_authority {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if pool_mint.supply != 0 {
+            return Err(SwapError::InvalidSupply.into());
+        }
+        if pool_mint.freeze_authority.is_some() {
+            return Err(SwapError::InvalidFreezeAuthority.into());
+        }
+        if *pool_mint_info.key != fee_account.mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
 
-            self.set_token_account(swap_source_key, swap_source_account);
-            self.set_token_account(swap_destination_key, swap_destination_account);
+        let mut token_swap = MultiStableSwap {
+            is_initialized: true,
+            bump_seed,
+            token_program_id,
+            pool_mint: *pool_mint_info.key,
+            pool_fee_account: *fee_account_info.key,
+            fees,
+            amp,
+            token_count: token_count as u8,
+            ..MultiStableSwap::default()
+        };
+        let mut decimals = None;
+        for (index, token_accounts) in token_infos.chunks_exact(2).enumerate() {
+            let (token_info, token_mint_info) = (&token_accounts[0], &token_accounts[1]);
+            // the curve treats one unit of every token as equal, and all
+            // tokens share the swap's token program
+            if *token_info.owner != token_program_id || *token_mint_info.owner != token_program_id {
+                return Err(SwapError::IncorrectTokenProgramId.into());
+            }
+            let token = Self::unpack_token_account(token_info, &token_program_id)?;
+            let token_mint = Self::unpack_mint(token_mint_info, &token_program_id)?;
+            if *authority_info.key != token.owner {
+                return Err(SwapError::InvalidOwner.into());
+            }
+            if token.delegate.is_some() {
+                return Err(SwapError::InvalidDelegate.into());
+            }
+            if token.close_authority.is_some() {
+                return Err(SwapError::InvalidCloseAuthority.into());
+            }
+            if token.amount == 0 {
+                return Err(SwapError::EmptySupply.into());
+            }
+            if *token_mint_info.key != token.mint {
+                return Err(SwapError::InvalidInput.into());
+            }
+            if token_swap.token_mints[..index].contains(&token.mint) {
+                return Err(SwapError::RepeatedMint.into());
+            }
+            if *decimals.get_or_insert(token_mint.decimals) != token_mint.decimals {
+                return Err(SwapError::MismatchedDecimals.into());
+            }
+            token_swap.tokens[index] = *token_info.key;
+            token_swap.token_mints[index] = token.mint;
+        }
 
-            Ok(())
+        if let Some(swap_constraints) = swap_constraints {
+            let owner_key = swap_constraints
+                .owner_key
+                .parse::<Pubkey>()
+                .map_err(|_| SwapError::InvalidOwner)?;
+            if fee_account.owner != owner_key {
+                return Err(SwapError::InvalidOwner.into());
+            }
+            if !swap_constraints
+                .valid_curve_types
+                .contains(&CurveType::Stable)
+            {
+                return Err(SwapError::UnsupportedCurveType.into());
+            }
+            swap_constraints.validate_fees(&token_swap.fees)?;
+        }
+        token_swap.fees.validate()?;
+        if amp == 0 || amp > MAX_AMP {
+            return Err(SwapError::InvalidCurve.into());
         }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn deposit_all_token_types(
-            &mut self,
-            depositor_key: &Pubkey,
-            depositor_token_a_key: &Pubkey,
-            depositor_token_a_account: &mut Account,
-            depositor_token_b_key: &Pubkey,
-            depositor_token_b_account: &mut Account,
-            depositor_pool_key: &Pubkey,
-            depositor_pool_account: &mut Account,
-            pool_token_amount: u64,
-            maximum_token_a_amount: u64,
-            maximum_token_b_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority = Pubkey::new_unique();
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    depositor_token_a_key,
-                    &user_transfer_authority,
-                    depositor_key,
-                    &[],
-                    maximum_token_a_amount,
-                )
-                .unwrap(),
-                vec![
-                    depositor_token_a_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            bump_seed,
+            to_u64(INITIAL_SWAP_POOL_AMOUNT)?,
+        )?;
 
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    depositor_token_b_key,
-                    &user_transfer_authority,
-                    depositor_key,
-                    &[],
-                    maximum_token_b_amount,
-                )
-                .unwrap(),
-                vec![
-                    depositor_token_b_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        MultiStableSwap::pack_account(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-            do_process_instruction(
-                deposit_all_token_types(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority,
-                    depositor_token_a_key,
-                    depositor_token_b_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    depositor_pool_key,
-                    DepositAllTokenTypes {
-                        pool_token_amount,
-                        maximum_token_a_amount,
-                        maximum_token_b_amount,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    depositor_token_a_account,
-                    depositor_token_b_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    depositor_pool_account,
-                    &mut Account::default(),
-                ],
+    /// Processes a [SwapMultiStable](enum.Instruction.html).
+    pub fn process_swap_multi_stable(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let source_token_mint_info = next_account_info(account_info_iter)?;
+        let destination_token_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let swap_token_infos = account_info_iter.as_slice().iter().collect::<Vec<_>>();
+
+        let (token_swap, reserves) = Self::unpack_multi_stable(
+            program_id,
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            token_program_info,
+            &swap_token_infos,
+        )?;
+        if *pool_fee_account_info.key != token_swap.pool_fee_account {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if token_swap.tokens().contains(source_info.key)
+            || token_swap.tokens().contains(destination_info.key)
+        {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let source = Self::unpack_token_account(source_info, token_program_info.key)?;
+        let destination = Self::unpack_token_account(destination_info, token_program_info.key)?;
+        let source_index = token_swap
+            .token_mints()
+            .iter()
+            .position(|mint| *mint == source.mint)
+            .ok_or(SwapError::IncorrectSwapAccount)?;
+        let destination_index = token_swap
+            .token_mints()
+            .iter()
+            .position(|mint| *mint == destination.mint)
+            .ok_or(SwapError::IncorrectSwapAccount)?;
+        if source_index == destination_index {
+            return Err(SwapError::RepeatedMint.into());
+        }
+        let source_mint = Self::unpack_mint(source_token_mint_info, token_program_info.key)?;
+        let destination_mint =
+            Self::unpack_mint(destination_token_mint_info, token_program_info.key)?;
+
+        // Fees are taken from what arrives after the transfer fee
+        let actual_amount_in = to_u128(
+            amount_in
+                .checked_sub(Self::transfer_fee(source_token_mint_info, amount_in)?)
+                .ok_or(SwapError::FeeCalculationFailure)?,
+        )?;
+        let fees = &token_swap.fees;
+        let trade_fee = fees
+            .trading_fee(actual_amount_in)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        let owner_fee = fees
+            .owner_trading_fee(actual_amount_in)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        let source_amount_swapped = actual_amount_in
+            .checked_sub(trade_fee)
+            .and_then(|amount| amount.checked_sub(owner_fee))
+            .ok_or(SwapError::FeeCalculationFailure)?;
+
+        let curve = token_swap.curve();
+        let destination_amount = curve
+            .swap_without_fees(
+                source_amount_swapped,
+                source_index,
+                destination_index,
+                &reserves,
             )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        if destination_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        let destination_amount = to_u64(destination_amount)?;
+        let destination_amount_received = destination_amount
+            .checked_sub(Self::transfer_fee(
+                destination_token_mint_info,
+                destination_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if destination_amount_received < minimum_amount_out {
+            return Err(SwapError::ExceededSlippage.into());
         }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn withdraw_all_token_types(
-            &mut self,
-            user_key: &Pubkey,
-            pool_key: &Pubkey,
-            pool_account: &mut Account,
-            token_a_key: &Pubkey,
-            token_a_account: &mut Account,
-            token_b_key: &Pubkey,
-            token_b_account: &mut Account,
-            pool_token_amount: u64,
-            minimum_token_a_amount: u64,
-            minimum_token_b_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            // approve user transfer authority to take out pool tokens
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    pool_key,
-                    &user_transfer_authority_key,
-                    user_key,
-                    &[],
-                    pool_token_amount,
-                )
-                .unwrap(),
-                vec![
-                    pool_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            source_token_mint_info.clone(),
+            swap_token_infos[source_index].clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.bump_seed,
+            amount_in,
+            source_mint.decimals,
+        )?;
 
-            // withdraw token a and b correctly
-            do_process_instruction(
-                withdraw_all_token_types(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    pool_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    token_a_key,
-                    token_b_key,
-                    WithdrawAllTokenTypes {
-                        pool_token_amount,
-                        minimum_token_a_amount,
-                        minimum_token_b_amount,
-                    },
+        if owner_fee > 0 {
+            // the owner fee stays in the pool as a single-sided deposit made
+            // after the trade, paid for in pool tokens
+            let mut new_reserves = reserves;
+            new_reserves[source_index] = new_reserves[source_index]
+                .checked_add(source_amount_swapped)
+                .and_then(|amount| amount.checked_add(trade_fee))
+                .ok_or(SwapError::CalculationFailure)?;
+            new_reserves[destination_index] = new_reserves[destination_index]
+                .checked_sub(to_u128(destination_amount)?)
+                .ok_or(SwapError::CalculationFailure)?;
+            let mut owner_deposit = vec![0; new_reserves.len()];
+            owner_deposit[source_index] = owner_fee;
+            let pool_mint = Self::unpack_mint(pool_mint_info, token_program_info.key)?;
+            let pool_token_amount = curve
+                .deposit(
+                    &owner_deposit,
+                    &new_reserves,
+                    to_u128(pool_mint.supply)?,
+                    &Fees::default(),
                 )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut self.pool_mint_account,
-                    pool_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    token_a_account,
-                    token_b_account,
-                    &mut self.pool_fee_account,
-                    &mut Account::default(),
-                ],
-            )
+                .ok_or(SwapError::FeeCalculationFailure)?;
+            if pool_token_amount > 0 {
+                Self::token_mint_to(
+                    swap_info.key,
+                    token_program_info.clone(),
+                    pool_mint_info.clone(),
+                    pool_fee_account_info.clone(),
+                    authority_info.clone(),
+                    token_swap.bump_seed,
+                    to_u64(pool_token_amount)?,
+                )?;
+            }
         }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn deposit_single_token_type_exact_amount_in(
-            &mut self,
-            depositor_key: &Pubkey,
-            deposit_account_key: &Pubkey,
-            deposit_token_account: &mut Account,
-            deposit_pool_key: &Pubkey,
-            deposit_pool_account: &mut Account,
-            source_token_amount: u64,
-            minimum_pool_token_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    deposit_account_key,
-                    &user_transfer_authority_key,
-                    depositor_key,
-                    &[],
-                    source_token_amount,
-                )
-                .unwrap(),
-                vec![
-                    deposit_token_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_token_infos[destination_index].clone(),
+            destination_token_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+            destination_amount,
+            destination_mint.decimals,
+        )?;
+        Ok(())
+    }
 
-            do_process_instruction(
-                deposit_single_token_type_exact_amount_in(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    deposit_account_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    deposit_pool_key,
-                    DepositSingleTokenTypeExactAmountIn {
-                        source_token_amount,
-                        minimum_pool_token_amount,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    deposit_token_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    deposit_pool_account,
-                    &mut Account::default(),
-                ],
-            )
+    /// Processes a [DepositMultiStable](enum.Instruction.html).
+    pub fn process_deposit_multi_stable(
+        program_id: &Pubkey,
+        token_amounts: &[u64],
+        minimum_pool_token_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_infos = account_info_iter.as_slice();
+        if token_infos.len() != 3 * token_amounts.len() {
+            return Err(SwapError::InvalidInput.into());
         }
+        let source_infos = token_infos.iter().step_by(3).collect::<Vec<_>>();
+        let swap_token_infos = token_infos.iter().skip(1).step_by(3).collect::<Vec<_>>();
+        let token_mint_infos = token_infos.iter().skip(2).step_by(3).collect::<Vec<_>>();
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn withdraw_single_token_type_exact_amount_out(
-            &mut self,
-            user_key: &Pubkey,
-            pool_key: &Pubkey,
-            pool_account: &mut Account,
-            destination_key: &Pubkey,
-            destination_account: &mut Account,
-            destination_token_amount: u64,
-            maximum_pool_token_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            // approve user transfer authority to take out pool tokens
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    pool_key,
-                    &user_transfer_authority_key,
-                    user_key,
-                    &[],
-                    maximum_pool_token_amount,
-                )
-                .unwrap(),
-                vec![
-                    pool_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+        let (token_swap, reserves) = Self::unpack_multi_stable(
+            program_id,
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            token_program_info,
+            &swap_token_infos,
+        )?;
+        Self::check_multi_stable_mints(&token_swap, &token_mint_infos)?;
+        if source_infos
+            .iter()
+            .any(|source_info| token_swap.tokens().contains(source_info.key))
+        {
+            return Err(SwapError::InvalidInput.into());
+        }
 
-            do_process_instruction(
-                withdraw_single_token_type_exact_amount_out(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    pool_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    destination_key,
-                    WithdrawSingleTokenTypeExactAmountOut {
-                        destination_token_amount,
-                        maximum_pool_token_amount,
-                    },
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut self.pool_mint_account,
-                    pool_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    destination_account,
-                    &mut self.pool_fee_account,
-                    &mut Account::default(),
-                ],
+        // The pool only receives what arrives after the transfer fee
+        let mut actual_amounts = Vec::with_capacity(token_amounts.len());
+        for (token_amount, token_mint_info) in token_amounts.iter().zip(&token_mint_infos) {
+            let actual_amount = if *token_amount > 0 {
+                token_amount
+                    .checked_sub(Self::transfer_fee(token_mint_info, *token_amount)?)
+                    .ok_or(SwapError::FeeCalculationFailure)?
+            } else {
+                0
+            };
+            actual_amounts.push(to_u128(actual_amount)?);
+        }
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_program_info.key)?;
+        let pool_token_amount = token_swap
+            .curve()
+            .deposit(
+                &actual_amounts,
+                &reserves,
+                to_u128(pool_mint.supply)?,
+                &token_swap.fees,
             )
+            .ok_or(SwapError::CalculationFailure)?;
+        let pool_token_amount = to_u64(pool_token_amount)?;
+        if pool_token_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        if pool_token_amount < minimum_pool_token_amount {
+            return Err(SwapError::ExceededSlippage.into());
         }
-    }
-
-    fn mint_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(spl_token::state::Mint::get_packed_len())
-    }
-
-    fn account_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(spl_token::state::Account::get_packed_len())
-    }
-
-    fn do_process_instruction_with_fee_constraints(
-        instruction: Instruction,
-        accounts: Vec<&mut Account>,
-        swap_constraints: &Option<SwapConstraints>,
-    ) -> ProgramResult {
-        test_syscall_stubs();
 
-        // approximate the logic in the actual runtime which runs the instruction
-        // and only updates accounts if the instruction is successful
-        let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
-        let mut meta = instruction
-            .accounts
+        for (((token_amount, source_info), swap_token_info), token_mint_info) in token_amounts
             .iter()
-            .zip(account_clones.iter_mut())
-            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
-            .collect::<Vec<_>>();
-        let mut account_infos = create_is_signer_account_infos(&mut meta);
-        let res = if instruction.program_id == SWAP_PROGRAM_ID {
-            Processor::process_with_constraints(
-                &instruction.program_id,
-                &account_infos,
-                &instruction.data,
-                swap_constraints,
-            )
-        } else {
-            spl_token::processor::Processor::process(
-                &instruction.program_id,
-                &account_infos,
-                &instruction.data,
-            )
-        };
-
-        if res.is_ok() {
-            let mut account_metas = instruction
-                .accounts
-                .iter()
-                .zip(accounts)
-                .map(|(account_meta, account)| (&account_meta.pubkey, account))
-                .collect::<Vec<_>>();
-            for account_info in account_infos.iter_mut() {
-                for account_meta in account_metas.iter_mut() {
-                    if account_info.key == account_meta.0 {
-                        let account = &mut account_meta.1;
-                        account.owner = *account_info.owner;
-                        account.daltons = **account_info.daltons.borrow();
-                        account.data = account_info.data.borrow().to_vec();
-                    }
-                }
+            .zip(source_infos)
+            .zip(swap_token_infos)
+            .zip(token_mint_infos)
+        {
+            if *token_amount > 0 {
+                let token_mint = Self::unpack_mint(token_mint_info, token_program_info.key)?;
+                Self::token_transfer(
+                    swap_info.key,
+                    token_program_info.clone(),
+                    source_info.clone(),
+                    token_mint_info.clone(),
+                    swap_token_info.clone(),
+                    user_transfer_authority_info.clone(),
+                    token_swap.bump_seed,
+                    *token_amount,
+                    token_mint.decimals,
+                )?;
             }
         }
-        res
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+            pool_token_amount,
+        )?;
+        Ok(())
     }
 
-    fn do_process_instruction(
-        instruction: Instruction,
-        accounts: Vec<&mut Account>,
-    ) -> ProgramResult {
-        do_process_instruction_with_fee_constraints(instruction, accounts, &SWAP_CONSTRAINTS)
+    /// Pool tokens taken as the owner's withdraw fee when burning
+    /// `pool_token_amount` from `source_info`
+    fn multi_stable_withdraw_fee(
+        token_swap: &MultiStableSwap,
+        pool_token_amount: u64,
+        source_info: &AccountInfo,
+        pool_fee_account_info: &AccountInfo,
+    ) -> Result<u128, ProgramError> {
+        if *pool_fee_account_info.key != token_swap.pool_fee_account {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if *pool_fee_account_info.key == *source_info.key {
+            // withdrawing from the fee account, don't assess withdraw fee
+            Ok(0)
+        } else {
+            Ok(token_swap
+                .fees
+                .owner_withdraw_fee(to_u128(pool_token_amount)?)
+                .ok_or(SwapError::FeeCalculationFailure)?)
+        }
     }
 
-    fn mint_token(
+    /// Processes a [WithdrawMultiStable](enum.Instruction.html).
+    pub fn process_withdraw_multi_stable(
         program_id: &Pubkey,
-        mint_key: &Pubkey,
-        mint_account: &mut Account,
-        mint_authority_key: &Pubkey,
-        account_owner_key: &Pubkey,
-        amount: u64,
-    ) -> (Pubkey, Account) {
-        let account_key = Pubkey::new_unique();
-        let mut account_account = Account::new(
-            account_minimum_balance(),
-            spl_token::state::Account::get_packed_len(),
+        pool_token_amount: u64,
+        minimum_token_amounts: &[u64],
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let token_infos = account_info_iter.as_slice();
+        if token_infos.len() != 3 * minimum_token_amounts.len() {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let swap_token_infos = token_infos.iter().step_by(3).collect::<Vec<_>>();
+        let destination_infos = token_infos.iter().skip(1).step_by(3).collect::<Vec<_>>();
+        let token_mint_infos = token_infos.iter().skip(2).step_by(3).collect::<Vec<_>>();
+
+        let (token_swap, reserves) = Self::unpack_multi_stable(
             program_id,
-        );
-        let mut mint_authority_account = Account::default();
-        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            token_program_info,
+            &swap_token_infos,
+        )?;
+        Self::check_multi_stable_mints(&token_swap, &token_mint_infos)?;
+        let withdraw_fee = Self::multi_stable_withdraw_fee(
+            &token_swap,
+            pool_token_amount,
+            source_info,
+            pool_fee_account_info,
+        )?;
+        let pool_token_amount = to_u128(pool_token_amount)?
+            .checked_sub(withdraw_fee)
+            .ok_or(SwapError::CalculationFailure)?;
 
-        do_process_instruction(
-            initialize_account(program_id, &account_key, mint_key, account_owner_key).unwrap(),
-            vec![
-                &mut account_account,
-                mint_account,
-                &mut mint_authority_account,
-                &mut rent_sysvar_account,
-            ],
-        )
-        .unwrap();
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_program_info.key)?;
+        let token_amounts = token_swap
+            .curve()
+            .withdraw(pool_token_amount, to_u128(pool_mint.supply)?, &reserves)
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let token_amounts = token_amounts
+            .into_iter()
+            .map(to_u64)
+            .collect::<Result<Vec<_>, _>>()?;
+        if token_amounts.iter().all(|token_amount| *token_amount == 0) {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        for ((token_amount, minimum_token_amount), token_mint_info) in token_amounts
+            .iter()
+            .zip(minimum_token_amounts)
+            .zip(&token_mint_infos)
+        {
+            // Slippage is checked against what arrives after the transfer fee
+            let token_amount_received = token_amount
+                .checked_sub(Self::transfer_fee(token_mint_info, *token_amount)?)
+                .ok_or(SwapError::FeeCalculationFailure)?;
+            if token_amount_received < *minimum_token_amount {
+                return Err(SwapError::ExceededSlippage.into());
+            }
+        }
 
-        if amount > 0 {
-            do_process_instruction(
-                mint_to(
-                    program_id,
-                    mint_key,
-                    &account_key,
-                    mint_authority_key,
-                    &[],
-                    amount,
-                )
-                .unwrap(),
-                vec![
-                    mint_account,
-                    &mut account_account,
-                    &mut mint_authority_account,
-                ],
-            )
-            .unwrap();
+        if withdraw_fee > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_info.clone(),
+                pool_mint_info.clone(),
+                pool_fee_account_info.clone(),
+                user_transfer_authority_info.clone(),
+                token_swap.bump_seed,
+                to_u64(withdraw_fee)?,
+                pool_mint.decimals,
+            )?;
         }
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.bump_seed,
+            to_u64(pool_token_amount)?,
+        )?;
 
-        (account_key, account_account)
+        for (((token_amount, swap_token_info), destination_info), token_mint_info) in token_amounts
+            .into_iter()
+            .zip(swap_token_infos)
+            .zip(destination_infos)
+            .zip(token_mint_infos)
+        {
+            if token_amount > 0 {
+                let token_mint = Self::unpack_mint(token_mint_info, token_program_info.key)?;
+                Self::token_transfer(
+                    swap_info.key,
+                    token_program_info.clone(),
+                    swap_token_info.clone(),
+                    token_mint_info.clone(),
+                    destination_info.clone(),
+                    authority_info.clone(),
+                    token_swap.bump_seed,
+                    token_amount,
+                    token_mint.decimals,
+                )?;
+            }
+        }
+        Ok(())
     }
 
-    fn create_mint(
+    /// Processes a [WithdrawOneMultiStable](enum.Instruction.html).
+    pub fn process_withdraw_one_multi_stable(
         program_id: &Pubkey,
-        authority_key: &Pubkey,
-        freeze_authority: Option<&Pubkey>,
-    ) -> (Pubkey, Account) {
-        let mint_key = Pubkey::new_unique();
-        let mut mint_account = Account::new(
-            mint_minimum_balance(),
-            spl_token::state::Mint::get_packed_len(),
+        pool_token_amount: u64,
+        minimum_token_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let destination_token_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let swap_token_infos = account_info_iter.as_slice().iter().collect::<Vec<_>>();
+
+        let (token_swap, reserves) = Self::unpack_multi_stable(
             program_id,
-        );
-        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+            swap_info,
+            authority_info,
+            pool_mint_info,
+            token_program_info,
+            &swap_token_infos,
+        )?;
+        if token_swap.tokens().contains(destination_info.key) {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let destination = Self::unpack_token_account(destination_info, token_program_info.key)?;
+        let destination_index = token_swap
+            .token_mints()
+            .iter()
+            .position(|mint| *mint == destination.mint)
+            .ok_or(SwapError::IncorrectSwapAccount)?;
+        let withdraw_fee = Self::multi_stable_withdraw_fee(
+            &token_swap,
+            pool_token_amount,
+            source_info,
+            pool_fee_account_info,
+        )?;
+        let pool_token_amount = to_u128(pool_token_amount)?
+            .checked_sub(withdraw_fee)
+            .ok_or(SwapError::CalculationFailure)?;
 
-        do_process_instruction(
-            initialize_mint(program_id, &mint_key, authority_key, freeze_authority, 2).unwrap(),
-            vec![&mut mint_account, &mut rent_sysvar_account],
-        )
-        .unwrap();
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_program_info.key)?;
+        let destination_mint =
+            Self::unpack_mint(destination_token_mint_info, token_program_info.key)?;
+        let token_amount = token_swap
+            .curve()
+            .withdraw_one(
+                pool_token_amount,
+                destination_index,
+                &reserves,
+                to_u128(pool_mint.supply)?,
+                &token_swap.fees,
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let token_amount = to_u64(token_amount)?;
+        if token_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        // Slippage is checked against what arrives after the transfer fee
+        let token_amount_received = token_amount
+            .checked_sub(Self::transfer_fee(
+                destination_token_mint_info,
+                token_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_amount_received < minimum_token_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
 
-        (mint_key, mint_account)
+        if withdraw_fee > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_info.clone(),
+                pool_mint_info.clone(),
+                pool_fee_account_info.clone(),
+                user_transfer_authority_info.clone(),
+                token_swap.bump_seed,
+                to_u64(withdraw_fee)?,
+                pool_mint.decimals,
+            )?;
+        }
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.bump_seed,
+            to_u64(pool_token_amount)?,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_token_infos[destination_index].clone(),
+            destination_token_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+            token_amount,
+            destination_mint.decimals,
+        )?;
+        Ok(())
     }
 
-    #[test]
-    fn test_token_program_id_error() {
-        test_syscall_stubs();
-        let swap_key = Pubkey::new_unique();
-        let mut mint = (Pubkey::new_unique(), Account::default());
-        let mut destination = (Pubkey::new_unique(), Account::default());
-        let token_program = (spl_token::id(), Account::default());
-        let (authority_key, bump_seed) =
-            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
-        let mut authority = (authority_key, Account::default());
-        let swap_bytes = swap_key.to_bytes();
-        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
-        let signers = &[&authority_signature_seeds[..]];
-        let ix = mint_to(
-            &token_program.0,
-            &mint.0,
-            &destination.0,
-            &authority.0,
-            &[],
-            10,
-        )
-        .unwrap();
-        let mint = (&mut mint).into();
-        let destination = (&mut destination).into();
-        let authority = (&mut authority).into();
-
-        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
-        assert_eq!(err, ProgramError::InvalidAccountData);
+    /// Checks the accounts shared by every instruction on a concentrated
+    /// liquidity swap, returning the swap
+    fn unpack_concentrated(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        authority_info: &AccountInfo,
+        token_program_info: &AccountInfo,
+    ) -> Result<ConcentratedSwap, ProgramError> {
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = ConcentratedSwap::unpack_account(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if *token_program_info.key != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        Ok(token_swap)
     }
 
-    #[test]
-    fn test_token_error() {
-        test_syscall_stubs();
-        let token_id = spl_token::id();
-        let swap_key = Pubkey::new_unique();
-        let mut mint = (
-            Pubkey::new_unique(),
-            Account::new(
-                mint_minimum_balance(),
-                spl_token::state::Mint::get_packed_len(),
-                &token_id,
-            ),
-        );
-        let mut destination = (
-            Pubkey::new_unique(),
-            Account::new(
-                account_minimum_balance(),
-                spl_token::state::Account::get_packed_len(),
-                &token_id,
-            ),
-        );
-        let mut token_program = (token_id, Account::default());
-        let (authority_key, bump_seed) =
-            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
-        let mut authority = (authority_key, Account::default());
-        let swap_bytes = swap_key.to_bytes();
-        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
-        let signers = &[&authority_signature_seeds[..]];
-        let mut rent_sysvar = (
-            Pubkey::new_unique(),
-            create_account_for_test(&Rent::default()),
-        );
-        do_process_instruction(
-            initialize_mint(
-                &token_program.0,
-                &mint.0,
-                &authority.0,
-                Some(&authority.0),
-                2,
-            )
-            .unwrap(),
-            vec![&mut mint.1, &mut rent_sysvar.1],
-        )
-        .unwrap();
-        do_process_instruction(
-            initialize_account(&token_program.0, &destination.0, &mint.0, &authority.0).unwrap(),
-            vec![
-                &mut destination.1,
-                &mut mint.1,
-                &mut authority.1,
-                &mut rent_sysvar.1,
-                &mut token_program.1,
-            ],
-        )
-        .unwrap();
-        do_process_instruction(
-            freeze_account(&token_program.0, &destination.0, &mint.0, &authority.0, &[]).unwrap(),
-            vec![
-                &mut destination.1,
-                &mut mint.1,
-                &mut authority.1,
-                &mut token_program.1,
-            ],
-        )
-        .unwrap();
-        let ix = mint_to(
-            &token_program.0,
-            &mint.0,
-            &destination.0,
-            &authority.0,
-            &[],
-            10,
-        )
-        .unwrap();
-        let mint_info = (&mut mint).into();
-        let destination_info = (&mut destination).into();
-        let authority_info = (&mut authority).into();
-        let token_program_info = (&mut token_program).into();
-
-        let err = invoke_signed_wrapper::<TokenError>(
-            &ix,
-            &[
-                mint_info,
-                destination_info,
-                authority_info,
-                token_program_info,
-            ],
-            signers,
-        )
-        .unwrap_err();
-        assert_eq!(err, ProgramError::Custom(TokenError::AccountFrozen as u32));
+    /// Checks that the given token accounts and mints are the swap's, token
+    /// A first
+    fn check_concentrated_tokens(
+        token_swap: &ConcentratedSwap,
+        token_a_info: &AccountInfo,
+        token_b_info: &AccountInfo,
+        token_a_mint_info: &AccountInfo,
+        token_b_mint_info: &AccountInfo,
+    ) -> ProgramResult {
+        if *token_a_info.key != token_swap.token_a
+            || *token_b_info.key != token_swap.token_b
+            || *token_a_mint_info.key != token_swap.token_a_mint
+            || *token_b_mint_info.key != token_swap.token_b_mint
+        {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        Ok(())
     }
 
-    #[test]
-    fn test_initialize() {
-        let user_key = Pubkey::new_unique();
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 2;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 20;
-        let host_fee_denominator = 100;
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
-        };
+    /// Unpacks a position of the given swap
+    fn unpack_position(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        position_info: &AccountInfo,
+    ) -> Result<Position, ProgramError> {
+        if position_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let position = Position::unpack(&position_info.data.borrow())?;
+        if position.swap != *swap_info.key {
+            return Err(SwapError::IncorrectPosition.into());
+        }
+        Ok(position)
+    }
 
-        let token_a_amount = 1000;
-        let token_b_amount = 2000;
-        let pool_token_amount = 10;
-        let curve_type = CurveType::ConstantProduct;
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Arc::new(ConstantProductCurve {}),
-        };
+    /// Checks that the position owner signed and holds the position token
+    fn check_position_owner(
+        position: &Position,
+        position_owner_info: &AccountInfo,
+        position_token_info: &AccountInfo,
+        token_program_id: &Pubkey,
+    ) -> ProgramResult {
+        if !position_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let position_token = Self::unpack_token_account(position_token_info, token_program_id)?;
+        if position_token.mint != position.position_mint || position_token.amount != 1 {
+            return Err(SwapError::IncorrectPosition.into());
+        }
+        if position_token.owner != *position_owner_info.key {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        Ok(())
+    }
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+    /// Processes an [InitializeConcentrated](enum.Instruction.html).
+    pub fn process_initialize_concentrated(
+        program_id: &Pubkey,
+        fees: Fees,
+        tick_spacing: u16,
+        initial_sqrt_price: u128,
+        accounts: &[AccountInfo],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let token_a_mint_info = next_account_info(account_info_iter)?;
+        let token_b_mint_info = next_account_info(account_info_iter)?;
+        let fee_owner_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
 
-        // uninitialized token a account
-        {
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = Account::new(0, 0, &spl_token::id());
-            assert_eq!(
-                Err(SwapError::ExpectedAccount.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        let token_program_id = *token_program_info.key;
+        if SwapVersion::is_initialized(&swap_info.data.borrow()) {
+            return Err(SwapError::AlreadyInUse.into());
         }
 
-        // uninitialized token b account
-        {
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = Account::new(0, 0, &spl_token::id());
-            assert_eq!(
-                Err(SwapError::ExpectedAccount.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
+        let (swap_authority, bump_seed) =
+            Pubkey::find_program_address(&[&swap_info.key.to_bytes()], program_id);
+        if *authority_info.key != swap_authority {
+            return Err(SwapError::InvalidProgramAddress.into());
         }
-
-        // uninitialized pool mint
-        {
-            let old_account = accounts.pool_mint_account;
-            accounts.pool_mint_account = Account::new(0, 0, &spl_token::id());
-            assert_eq!(
-                Err(SwapError::ExpectedMint.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_mint_account = old_account;
+        // positions are minted by the swap's token program, so the tokens
+        // share it as well
+        if *token_a_info.owner != token_program_id || *token_b_info.owner != token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        let token_a = Self::unpack_token_account(token_a_info, &token_program_id)?;
+        let token_b = Self::unpack_token_account(token_b_info, &token_program_id)?;
+        Self::unpack_mint(token_a_mint_info, &token_program_id)?;
+        Self::unpack_mint(token_b_mint_info, &token_program_id)?;
+        if *authority_info.key != token_a.owner || *authority_info.key != token_b.owner {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if token_a.delegate.is_some() || token_b.delegate.is_some() {
+            return Err(SwapError::InvalidDelegate.into());
+        }
+        if token_a.close_authority.is_some() || token_b.close_authority.is_some() {
+            return Err(SwapError::InvalidCloseAuthority.into());
+        }
+        if *token_a_mint_info.key != token_a.mint || *token_b_mint_info.key != token_b.mint {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if token_a.mint == token_b.mint {
+            return Err(SwapError::RepeatedMint.into());
         }
 
-        // token A account owner is not swap authority
-        {
-            let (_token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        if let Some(swap_constraints) = swap_constraints {
+            let owner_key = swap_constraints
+                .owner_key
+                .parse::<Pubkey>()
+                .map_err(|_| SwapError::InvalidOwner)?;
+            if *fee_owner_info.key != owner_key {
+                return Err(SwapError::InvalidOwner.into());
+            }
+            swap_constraints.validate_fees(&fees)?;
         }
+        fees.validate()?;
+        let curve = ConcentratedCurve::new(tick_spacing, initial_sqrt_price)?;
 
-        // token B account owner is not swap authority
-        {
-            let (_token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
-            assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
-        }
+        let token_swap = ConcentratedSwap {
+            is_initialized: true,
+            bump_seed,
+            token_program_id,
+            token_a: *token_a_info.key,
+            token_b: *token_b_info.key,
+            token_a_mint: token_a.mint,
+            token_b_mint: token_b.mint,
+            fee_owner: *fee_owner_info.key,
+            fees,
+            owner_fee_a: 0,
+            owner_fee_b: 0,
+            curve,
+        };
+        ConcentratedSwap::pack_account(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-        // pool token account owner is swap authority
-        {
-            let (_pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.pool_token_account;
-            accounts.pool_token_account = pool_token_account;
-            assert_eq!(
-                Err(SwapError::InvalidOutputOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_token_account = old_account;
-        }
+    /// Processes an [OpenPosition](enum.Instruction.html).
+    pub fn process_open_position(
+        program_id: &Pubkey,
+        tick_lower: i32,
+        tick_upper: i32,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let position_info = next_account_info(account_info_iter)?;
+        let position_mint_info = next_account_info(account_info_iter)?;
+        let position_token_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
 
-        // pool fee account owner is swap authority
-        {
-            let (_pool_fee_key, pool_fee_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.pool_fee_account;
-            accounts.pool_fee_account = pool_fee_account;
-            assert_eq!(
-                Err(SwapError::InvalidOutputOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_fee_account = old_account;
+        let token_swap =
+            Self::unpack_concentrated(program_id, swap_info, authority_info, token_program_info)?;
+        if position_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
         }
-
-        // pool mint authority is not swap authority
-        {
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &user_key, None);
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
-            assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_mint_account = old_mint;
+        if Position::unpack_unchecked(&position_info.data.borrow())?.is_initialized {
+            return Err(SwapError::AlreadyInUse.into());
         }
+        token_swap.curve.check_ticks(tick_lower, tick_upper)?;
 
-        // pool mint token has freeze authority
-        {
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, Some(&user_key));
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
-            assert_eq!(
-                Err(SwapError::InvalidFreezeAuthority.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_mint_account = old_mint;
+        let position_mint = Self::unpack_mint(position_mint_info, token_program_info.key)?;
+        if *position_mint_info.owner != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
         }
-
-        // token A account owned by wrong program
-        {
-            let (_token_a_key, mut token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                token_a_amount,
-            );
-            token_a_account.owner = SWAP_PROGRAM_ID;
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        if COption::Some(*authority_info.key) != position_mint.mint_authority {
+            return Err(SwapError::InvalidOwner.into());
         }
-
-        // token B account owned by wrong program
-        {
-            let (_token_b_key, mut token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                token_b_amount,
-            );
-            token_b_account.owner = SWAP_PROGRAM_ID;
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
+        if position_mint.supply != 0 {
+            return Err(SwapError::InvalidSupply.into());
         }
-
-        // empty token A account
-        {
-            let (_token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(SwapError::EmptySupply.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+        if position_mint.freeze_authority.is_some() {
+            return Err(SwapError::InvalidFreezeAuthority.into());
         }
-
-        // empty token B account
-        {
-            let (_token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
-            assert_eq!(
-                Err(SwapError::EmptySupply.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
+        if position_mint.decimals != 0 {
+            return Err(SwapError::InvalidInput.into());
         }
 
-        // invalid pool tokens
-        {
-            let old_mint = accounts.pool_mint_account;
-            let old_pool_account = accounts.pool_token_account;
-
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            accounts.pool_mint_account = pool_mint_account;
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            position_mint_info.clone(),
+            position_token_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+            1,
+        )?;
+        Self::token_remove_mint_authority(
+            swap_info.key,
+            token_program_info.clone(),
+            position_mint_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+        )?;
 
-            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                0,
-            );
+        let position = Position {
+            is_initialized: true,
+            swap: *swap_info.key,
+            position_mint: *position_mint_info.key,
+            tick_lower,
+            tick_upper,
+            ..Position::default()
+        };
+        Position::pack(position, &mut position_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-            let (_pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                pool_token_amount,
-            );
+    /// Processes an [IncreaseLiquidity](enum.Instruction.html).
+    pub fn process_increase_liquidity(
+        program_id: &Pubkey,
+        liquidity: u128,
+        maximum_token_a_amount: u64,
+        maximum_token_b_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let position_info = next_account_info(account_info_iter)?;
+        let source_a_info = next_account_info(account_info_iter)?;
+        let source_b_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let token_a_mint_info = next_account_info(account_info_iter)?;
+        let token_b_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
 
-            // non-empty pool token account
-            accounts.pool_token_account = pool_token_account;
-            assert_eq!(
-                Err(SwapError::InvalidSupply.into()),
-                accounts.initialize_swap()
-            );
+        let mut token_swap =
+            Self::unpack_concentrated(program_id, swap_info, authority_info, token_program_info)?;
+        Self::check_concentrated_tokens(
+            &token_swap,
+            token_a_info,
+            token_b_info,
+            token_a_mint_info,
+            token_b_mint_info,
+        )?;
+        let mut position = Self::unpack_position(program_id, swap_info, position_info)?;
+        if liquidity == 0 {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let liquidity_delta =
+            i128::try_from(liquidity).map_err(|_| SwapError::ConversionFailure)?;
 
-            // pool tokens already in circulation
-            accounts.pool_token_account = empty_pool_token_account;
-            assert_eq!(
-                Err(SwapError::InvalidSupply.into()),
-                accounts.initialize_swap()
-            );
+        let (token_a_amount, token_b_amount) = token_swap
+            .curve
+            .token_amounts(
+                position.tick_lower,
+                position.tick_upper,
+                liquidity,
+                RoundDirection::Ceiling,
+            )
+            .ok_or(SwapError::CalculationFailure)?;
+        let fee_growth_inside = token_swap.curve.update_liquidity(
+            position.tick_lower,
+            position.tick_upper,
+            liquidity_delta,
+        )?;
+        position
+            .settle_fees(fee_growth_inside)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        position.liquidity = position
+            .liquidity
+            .checked_add(liquidity)
+            .ok_or(SwapError::CalculationFailure)?;
 
-            accounts.pool_mint_account = old_mint;
-            accounts.pool_token_account = old_pool_account;
+        // The depositor pays the transfer fee on top of what reaches the pool
+        let token_a_amount = to_u64(token_a_amount)?;
+        let token_a_transfer_amount = token_a_amount
+            .checked_add(Self::inverse_transfer_fee(
+                token_a_mint_info,
+                token_a_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_a_transfer_amount > maximum_token_a_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        let token_b_amount = to_u64(token_b_amount)?;
+        let token_b_transfer_amount = token_b_amount
+            .checked_add(Self::inverse_transfer_fee(
+                token_b_mint_info,
+                token_b_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_b_transfer_amount > maximum_token_b_amount {
+            return Err(SwapError::ExceededSlippage.into());
         }
 
-        // pool fee account has wrong mint
-        {
-            let (_pool_fee_key, pool_fee_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.pool_fee_account;
-            accounts.pool_fee_account = pool_fee_account;
-            assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_fee_account = old_account;
+        if token_a_amount > 0 {
+            let token_a_mint = Self::unpack_mint(token_a_mint_info, token_program_info.key)?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_a_info.clone(),
+                token_a_mint_info.clone(),
+                token_a_info.clone(),
+                user_transfer_authority_info.clone(),
+                token_swap.bump_seed,
+                token_a_transfer_amount,
+                token_a_mint.decimals,
+            )?;
+        }
+        if token_b_amount > 0 {
+            let token_b_mint = Self::unpack_mint(token_b_mint_info, token_program_info.key)?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_b_info.clone(),
+                token_b_mint_info.clone(),
+                token_b_info.clone(),
+                user_transfer_authority_info.clone(),
+                token_swap.bump_seed,
+                token_b_transfer_amount,
+                token_b_mint.decimals,
+            )?;
         }
 
-        // token A account is delegated
-        {
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    &user_key,
-                    &accounts.authority_key,
-                    &[],
-                    1,
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.token_a_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidDelegate.into()),
-                accounts.initialize_swap()
-            );
+        Position::pack(position, &mut position_info.data.borrow_mut())?;
+        ConcentratedSwap::pack_account(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-            do_process_instruction(
-                revoke(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+    /// Processes a [DecreaseLiquidity](enum.Instruction.html).
+    pub fn process_decrease_liquidity(
+        program_id: &Pubkey,
+        liquidity: u128,
+        minimum_token_a_amount: u64,
+        minimum_token_b_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let position_owner_info = next_account_info(account_info_iter)?;
+        let position_info = next_account_info(account_info_iter)?;
+        let position_token_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let dest_token_a_info = next_account_info(account_info_iter)?;
+        let dest_token_b_info = next_account_info(account_info_iter)?;
+        let token_a_mint_info = next_account_info(account_info_iter)?;
+        let token_b_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut token_swap =
+            Self::unpack_concentrated(program_id, swap_info, authority_info, token_program_info)?;
+        Self::check_concentrated_tokens(
+            &token_swap,
+            token_a_info,
+            token_b_info,
+            token_a_mint_info,
+            token_b_mint_info,
+        )?;
+        let mut position = Self::unpack_position(program_id, swap_info, position_info)?;
+        Self::check_position_owner(
+            &position,
+            position_owner_info,
+            position_token_info,
+            token_program_info.key,
+        )?;
+        if liquidity > position.liquidity {
+            return Err(SwapError::InvalidInput.into());
         }
 
-        // token B account is delegated
-        {
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    &user_key,
-                    &accounts.authority_key,
-                    &[],
-                    1,
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.token_b_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
+        let (token_a_amount, token_b_amount) = token_swap
+            .curve
+            .token_amounts(
+                position.tick_lower,
+                position.tick_upper,
+                liquidity,
+                RoundDirection::Floor,
             )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidDelegate.into()),
-                accounts.initialize_swap()
-            );
+            .ok_or(SwapError::CalculationFailure)?;
+        // without any liquidity to remove, only the position's fees are
+        // collected
+        let fee_growth_inside = if liquidity > 0 {
+            let liquidity_delta =
+                i128::try_from(liquidity).map_err(|_| SwapError::ConversionFailure)?;
+            token_swap.curve.update_liquidity(
+                position.tick_lower,
+                position.tick_upper,
+                -liquidity_delta,
+            )?
+        } else {
+            token_swap
+                .curve
+                .fee_growth_inside(position.tick_lower, position.tick_upper)
+        };
+        position
+            .settle_fees(fee_growth_inside)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        position.liquidity -= liquidity;
 
-            do_process_instruction(
-                revoke(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
+        let token_a_amount = to_u64(token_a_amount)?
+            .checked_add(position.fees_owed_a)
+            .ok_or(SwapError::CalculationFailure)?;
+        let token_b_amount = to_u64(token_b_amount)?
+            .checked_add(position.fees_owed_b)
+            .ok_or(SwapError::CalculationFailure)?;
+        position.fees_owed_a = 0;
+        position.fees_owed_b = 0;
+        let token_a_amount_received = token_a_amount
+            .checked_sub(Self::transfer_fee(token_a_mint_info, token_a_amount)?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_a_amount_received < minimum_token_a_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        let token_b_amount_received = token_b_amount
+            .checked_sub(Self::transfer_fee(token_b_mint_info, token_b_amount)?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if token_b_amount_received < minimum_token_b_amount {
+            return Err(SwapError::ExceededSlippage.into());
         }
 
-        // token A account has close authority
-        {
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
-            );
-
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+        if token_a_amount > 0 {
+            let token_a_mint = Self::unpack_mint(token_a_mint_info, token_program_info.key)?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_a_info.clone(),
+                token_a_mint_info.clone(),
+                dest_token_a_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                token_a_amount,
+                token_a_mint.decimals,
+            )?;
+        }
+        if token_b_amount > 0 {
+            let token_b_mint = Self::unpack_mint(token_b_mint_info, token_program_info.key)?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_b_info.clone(),
+                token_b_mint_info.clone(),
+                dest_token_b_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                token_b_amount,
+                token_b_mint.decimals,
+            )?;
         }
 
-        // token B account has close authority
-        {
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
-            );
+        Position::pack(position, &mut position_info.data.borrow_mut())?;
+        ConcentratedSwap::pack_account(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
 
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
+    /// Processes a [ClosePosition](enum.Instruction.html).
+    pub fn process_close_position(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let position_owner_info = next_account_info(account_info_iter)?;
+        let position_info = next_account_info(account_info_iter)?;
+        let position_mint_info = next_account_info(account_info_iter)?;
+        let position_token_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = ConcentratedSwap::unpack_account(&swap_info.data.borrow())?;
+        if *token_program_info.key != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        let position = Self::unpack_position(program_id, swap_info, position_info)?;
+        Self::check_position_owner(
+            &position,
+            position_owner_info,
+            position_token_info,
+            token_program_info.key,
+        )?;
+        if *position_mint_info.key != position.position_mint {
+            return Err(SwapError::IncorrectPosition.into());
+        }
+        if position.liquidity != 0 || position.fees_owed_a != 0 || position.fees_owed_b != 0 {
+            return Err(SwapError::PositionNotEmpty.into());
         }
 
-        // wrong token program id
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            position_token_info.clone(),
+            position_mint_info.clone(),
+            position_owner_info.clone(),
+            token_swap.bump_seed,
+            1,
+        )?;
+
+        let destination_daltons = destination_info
+            .daltons()
+            .checked_add(position_info.daltons())
+            .ok_or(SwapError::CalculationFailure)?;
+        **destination_info.daltons.borrow_mut() = destination_daltons;
+        **position_info.daltons.borrow_mut() = 0;
+        position_info.data.borrow_mut().fill(0);
+        Ok(())
+    }
+
+    /// Processes a [SwapConcentrated](enum.Instruction.html).
+    pub fn process_swap_concentrated(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let swap_source_info = next_account_info(account_info_iter)?;
+        let swap_destination_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let source_token_mint_info = next_account_info(account_info_iter)?;
+        let destination_token_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut token_swap =
+            Self::unpack_concentrated(program_id, swap_info, authority_info, token_program_info)?;
+        let a_to_b = *swap_source_info.key == token_swap.token_a;
+        if a_to_b {
+            Self::check_concentrated_tokens(
+                &token_swap,
+                swap_source_info,
+                swap_destination_info,
+                source_token_mint_info,
+                destination_token_mint_info,
+            )?;
+        } else {
+            Self::check_concentrated_tokens(
+                &token_swap,
+                swap_destination_info,
+                swap_source_info,
+                destination_token_mint_info,
+                source_token_mint_info,
+            )?;
+        }
+        if *source_info.key == *swap_source_info.key
+            || *destination_info.key == *swap_destination_info.key
         {
-            let wrong_program_id = Pubkey::new_unique();
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    initialize(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_program_id,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &accounts.pool_token_key,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.pool_fee_account,
-                        &mut accounts.pool_token_account,
-                        &mut Account::default(),
-                    ],
-                )
-            );
+            return Err(SwapError::InvalidInput.into());
         }
+        let source_mint = Self::unpack_mint(source_token_mint_info, token_program_info.key)?;
+        let destination_mint =
+            Self::unpack_mint(destination_token_mint_info, token_program_info.key)?;
 
-        // create swap with same token A and B
-        {
-            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                10,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_a_repeat_account;
-            assert_eq!(
-                Err(SwapError::RepeatedMint.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
+        // Fees are taken from what arrives after the transfer fee
+        let actual_amount_in = to_u128(
+            amount_in
+                .checked_sub(Self::transfer_fee(source_token_mint_info, amount_in)?)
+                .ok_or(SwapError::FeeCalculationFailure)?,
+        )?;
+        let fees = &token_swap.fees;
+        let trade_fee = fees
+            .trading_fee(actual_amount_in)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        let owner_fee = fees
+            .owner_trading_fee(actual_amount_in)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        let source_amount_swapped = actual_amount_in
+            .checked_sub(trade_fee)
+            .and_then(|amount| amount.checked_sub(owner_fee))
+            .ok_or(SwapError::FeeCalculationFailure)?;
+
+        let destination_amount = token_swap
+            .curve
+            .swap(source_amount_swapped, trade_fee, a_to_b)?;
+        if destination_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        let destination_amount = to_u64(destination_amount)?;
+        let destination_amount_received = destination_amount
+            .checked_sub(Self::transfer_fee(
+                destination_token_mint_info,
+                destination_amount,
+            )?)
+            .ok_or(SwapError::FeeCalculationFailure)?;
+        if destination_amount_received < minimum_amount_out {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        // the owner fee stays in the pool until the fee owner collects it
+        let owner_fee = to_u64(owner_fee)?;
+        if a_to_b {
+            token_swap.owner_fee_a = token_swap
+                .owner_fee_a
+                .checked_add(owner_fee)
+                .ok_or(SwapError::FeeCalculationFailure)?;
+        } else {
+            token_swap.owner_fee_b = token_swap
+                .owner_fee_b
+                .checked_add(owner_fee)
+                .ok_or(SwapError::FeeCalculationFailure)?;
         }
 
-        // create valid swap
-        accounts.initialize_swap().unwrap();
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            source_token_mint_info.clone(),
+            swap_source_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.bump_seed,
+            amount_in,
+            source_mint.decimals,
+        )?;
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_destination_info.clone(),
+            destination_token_mint_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+            destination_amount,
+            destination_mint.decimals,
+        )?;
 
-        // create invalid flat swap
-        {
-            let token_b_price = 0;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantPrice,
-                calculator: Arc::new(ConstantPriceCurve { token_b_price }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            assert_eq!(
-                Err(SwapError::InvalidCurve.into()),
-                accounts.initialize_swap()
-            );
+        ConcentratedSwap::pack_account(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [CollectOwnerFees](enum.Instruction.html).
+    pub fn process_collect_owner_fees(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let fee_owner_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let dest_token_a_info = next_account_info(account_info_iter)?;
+        let dest_token_b_info = next_account_info(account_info_iter)?;
+        let token_a_mint_info = next_account_info(account_info_iter)?;
+        let token_b_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        let mut token_swap =
+            Self::unpack_concentrated(program_id, swap_info, authority_info, token_program_info)?;
+        Self::check_concentrated_tokens(
+            &token_swap,
+            token_a_info,
+            token_b_info,
+            token_a_mint_info,
+            token_b_mint_info,
+        )?;
+        if !fee_owner_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if *fee_owner_info.key != token_swap.fee_owner {
+            return Err(SwapError::InvalidOwner.into());
         }
 
-        // create valid flat swap
-        {
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let token_b_price = 10_000;
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantPrice,
-                calculator: Arc::new(ConstantPriceCurve { token_b_price }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            accounts.initialize_swap().unwrap();
+        if token_swap.owner_fee_a > 0 {
+            let token_a_mint = Self::unpack_mint(token_a_mint_info, token_program_info.key)?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_a_info.clone(),
+                token_a_mint_info.clone(),
+                dest_token_a_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                token_swap.owner_fee_a,
+                token_a_mint.decimals,
+            )?;
+        }
+        if token_swap.owner_fee_b > 0 {
+            let token_b_mint = Self::unpack_mint(token_b_mint_info, token_program_info.key)?;
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_b_info.clone(),
+                token_b_mint_info.clone(),
+                dest_token_b_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                token_swap.owner_fee_b,
+                token_b_mint.decimals,
+            )?;
         }
 
-        // create invalid offset swap
+        token_swap.owner_fee_a = 0;
+        token_swap.owner_fee_b = 0;
+        ConcentratedSwap::pack_account(token_swap, &mut swap_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [Quote](enum.Instruction.html).
+    pub fn process_quote(
+        program_id: &Pubkey,
+        operation: QuoteOperation,
+        trade_direction: TradeDirection,
+        amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let swap_token_a_info = next_account_info(account_info_iter)?;
+        let swap_token_b_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let token_a_mint_info = next_account_info(account_info_iter)?;
+        let token_b_mint_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        // Bring a ramping amp up to date on a copy of the swap, leaving the
+        // account untouched
+        let mut swap_data = swap_info.data.borrow().to_vec();
+        let timestamp = Clock::get()?.unix_timestamp;
+        SwapVersion::update_amp_ramp(&mut swap_data, timestamp)?;
+        SwapVersion::update_dynamic_fee(&mut swap_data, timestamp)?;
+        let token_swap = SwapVersion::unpack(&swap_data)?;
+        if token_swap.is_locked() {
+            return Err(SwapError::SwapLocked.into());
+        }
+        Self::check_price_oracle(token_swap.as_ref())?;
+        if token_swap.is_paused()
+            && operation != QuoteOperation::WithdrawSingleTokenTypeExactAmountOut
         {
-            let token_b_offset = 0;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::Offset,
-                calculator: Arc::new(OffsetCurve { token_b_offset }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            assert_eq!(
-                Err(SwapError::InvalidCurve.into()),
-                accounts.initialize_swap()
-            );
+            return Err(SwapError::SwapPaused.into());
         }
-
-        // create valid offset swap
+        if *swap_token_a_info.key != *token_swap.token_a_account()
+            || *swap_token_b_info.key != *token_swap.token_b_account()
         {
-            let token_b_offset = 10;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::Offset,
-                calculator: Arc::new(OffsetCurve { token_b_offset }),
-            };
-            let mut accounts =
-                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-            accounts.initialize_swap().unwrap();
+            return Err(SwapError::IncorrectSwapAccount.into());
         }
-
-        // wrong owner key in constraint
+        if *pool_mint_info.key != *token_swap.pool_mint() {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if *token_a_mint_info.key != *token_swap.token_a_mint()
+            || *token_b_mint_info.key != *token_swap.token_b_mint()
         {
-            let new_key = Pubkey::new_unique();
-            let trade_fee_numerator = 25;
-            let trade_fee_denominator = 10000;
-            let owner_trade_fee_numerator = 5;
-            let owner_trade_fee_denominator = 10000;
-            let host_fee_numerator = 20;
-            let host_fee_denominator = 100;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let curve = ConstantProductCurve {};
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantProduct,
-                calculator: Arc::new(curve),
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+
+        // The mints are checked against the swap, so their owners are the
+        // token programs of the swap's token accounts
+        let swap_token_a = Self::unpack_token_account(swap_token_a_info, token_a_mint_info.owner)?;
+        let swap_token_b = Self::unpack_token_account(swap_token_b_info, token_b_mint_info.owner)?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+
+        // The token going into the pool, or coming out of it for withdrawals,
+        // and the other token of the pool
+        let (token_mint_info, other_token_mint_info, swap_token_amount, other_swap_token_amount) =
+            match trade_direction {
+                TradeDirection::AtoB => (
+                    token_a_mint_info,
+                    token_b_mint_info,
+                    swap_token_a.amount,
+                    swap_token_b.amount,
+                ),
+                TradeDirection::BtoA => (
+                    token_b_mint_info,
+                    token_a_mint_info,
+                    swap_token_b.amount,
+                    swap_token_a.amount,
+                ),
             };
-            let owner_key = &new_key.to_string();
-            let valid_curve_types = &[CurveType::ConstantProduct];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                fees.clone(),
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
-            assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                do_process_instruction_with_fee_constraints(
-                    initialize(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &accounts.pool_token_key,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.pool_fee_account,
-                        &mut accounts.pool_token_account,
-                        &mut Account::default(),
-                    ],
-                    &constraints,
-                )
-            );
-        }
-
-        // wrong fee in constraint
-        {
-            let trade_fee_numerator = 25;
-            let trade_fee_denominator = 10000;
-            let owner_trade_fee_numerator = 5;
-            let owner_trade_fee_denominator = 10000;
-            let host_fee_numerator = 20;
-            let host_fee_denominator = 100;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let curve = ConstantProductCurve {};
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantProduct,
-                calculator: Arc::new(curve),
-            };
-            let owner_key = &user_key.to_string();
-            let valid_curve_types = &[CurveType::ConstantProduct];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut bad_fees = fees.clone();
-            bad_fees.trade_fee_numerator = trade_fee_numerator - 1;
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                bad_fees,
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
-            assert_eq!(
-                Err(SwapError::InvalidFee.into()),
-                do_process_instruction_with_fee_constraints(
-                    initialize(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &accounts.pool_token_key,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.pool_fee_account,
-                        &mut accounts.pool_token_account,
-                        &mut Account::default(),
-                    ],
-                    &constraints,
-                )
-            );
-        }
-
-        // create valid swap with constraints
-        {
-            let trade_fee_numerator = 25;
-            let trade_fee_denominator = 10000;
-            let owner_trade_fee_numerator = 5;
-            let owner_trade_fee_denominator = 10000;
-            let host_fee_numerator = 20;
-            let host_fee_denominator = 100;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let curve = ConstantProductCurve {};
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::ConstantProduct,
-                calculator: Arc::new(curve),
-            };
-            let owner_key = &user_key.to_string();
-            let valid_curve_types = &[CurveType::ConstantProduct];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                fees.clone(),
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
-            do_process_instruction_with_fee_constraints(
-                initialize(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &accounts.swap_key,
-                    &accounts.authority_key,
-                    &accounts.token_a_key,
-                    &accounts.token_b_key,
-                    &accounts.pool_mint_key,
-                    &accounts.pool_fee_key,
-                    &accounts.pool_token_key,
-                    accounts.fees,
-                    accounts.swap_curve.clone(),
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.swap_account,
-                    &mut Account::default(),
-                    &mut accounts.token_a_account,
-                    &mut accounts.token_b_account,
-                    &mut accounts.pool_mint_account,
-                    &mut accounts.pool_fee_account,
-                    &mut accounts.pool_token_account,
-                    &mut Account::default(),
-                ],
-                &constraints,
-            )
-            .unwrap();
-        }
-
-        // create again
-        {
-            assert_eq!(
-                Err(SwapError::AlreadyInUse.into()),
-                accounts.initialize_swap()
-            );
-        }
-        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
-        assert!(swap_state.is_initialized());
-        assert_eq!(swap_state.bump_seed(), accounts.bump_seed);
-        assert_eq!(
-            swap_state.swap_curve().curve_type,
-            accounts.swap_curve.curve_type
-        );
-        assert_eq!(*swap_state.token_a_account(), accounts.token_a_key);
-        assert_eq!(*swap_state.token_b_account(), accounts.token_b_key);
-        assert_eq!(*swap_state.pool_mint(), accounts.pool_mint_key);
-        assert_eq!(*swap_state.token_a_mint(), accounts.token_a_mint_key);
-        assert_eq!(*swap_state.token_b_mint(), accounts.token_b_mint_key);
-        assert_eq!(*swap_state.pool_fee_account(), accounts.pool_fee_key);
-        let token_a = spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, token_a_amount);
-        let token_b = spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, token_b_amount);
-        let pool_account =
-            spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-        assert_eq!(pool_mint.supply, pool_account.amount);
-    }
-
-    #[test]
-    fn test_deposit() {
-        let user_key = Pubkey::new_unique();
-        let depositor_key = Pubkey::new_unique();
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 2;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 20;
-        let host_fee_denominator = 100;
-
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
+        let with_new_swap_token_amount = |new_swap_token_amount: u64| match trade_direction {
+            TradeDirection::AtoB => (new_swap_token_amount, swap_token_b.amount),
+            TradeDirection::BtoA => (swap_token_a.amount, new_swap_token_amount),
         };
 
-        let token_a_amount = 1000;
-        let token_b_amount = 9000;
-        let curve_type = CurveType::ConstantProduct;
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Arc::new(ConstantProductCurve {}),
+        let fees = token_swap.fees();
+        let quote_result = match operation {
+            QuoteOperation::Swap | QuoteOperation::SwapExactAmountOut => {
+                let swap_amount = if operation == QuoteOperation::Swap {
+                    SwapAmount::ExactIn {
+                        amount_in: amount,
+                        minimum_amount_out: 0,
+                    }
+                } else {
+                    SwapAmount::ExactOut {
+                        amount_out: amount,
+                        maximum_amount_in: u64::MAX,
+                    }
+                };
+                let swap_amounts = Self::swap_amounts(
+                    token_swap.as_ref(),
+                    swap_amount,
+                    trade_direction,
+                    swap_token_amount,
+                    other_swap_token_amount,
+                    pool_mint.supply,
+                    token_mint_info,
+                    other_token_mint_info,
+                )?;
+                QuoteResult {
+                    amount_in: swap_amounts.source_transfer_amount,
+                    amount_out: swap_amounts.destination_amount_received,
+                    trade_fee: to_u64(swap_amounts.trade_fee)?,
+                    owner_fee: to_u64(swap_amounts.owner_fee_pool_token_amount)?,
+                    host_fee: to_u64(swap_amounts.host_fee)?,
+                    new_token_a_amount: to_u64(swap_amounts.swap_token_a_amount)?,
+                    new_token_b_amount: to_u64(swap_amounts.swap_token_b_amount)?,
+                }
+            }
+            QuoteOperation::DepositSingleTokenTypeExactAmountIn => {
+                if !token_swap.swap_curve().calculator.allows_deposits() {
+                    return Err(SwapError::UnsupportedCurveOperation.into());
+                }
+                let (actual_source_token_amount, pool_token_amount) =
+                    Self::deposit_single_token_type_amounts(
+                        token_swap.as_ref(),
+                        amount,
+                        trade_direction,
+                        swap_token_a.amount,
+                        swap_token_b.amount,
+                        pool_mint.supply,
+                        token_mint_info,
+                    )?;
+                let pool_token_amount = to_u64(pool_token_amount)?;
+                if pool_token_amount == 0 {
+                    return Err(SwapError::ZeroTradingTokens.into());
+                }
+                // The first deposit into an empty pool mints its initial
+                // supply without any fee
+                let trade_fee = if pool_mint.supply > 0 {
+                    fees.single_token_type_trading_fee(to_u128(actual_source_token_amount)?)
+                        .ok_or(SwapError::FeeCalculationFailure)?
+                } else {
+                    0
+                };
+                let (new_token_a_amount, new_token_b_amount) = with_new_swap_token_amount(
+                    swap_token_amount
+                        .checked_add(actual_source_token_amount)
+                        .ok_or(SwapError::CalculationFailure)?,
+                );
+                QuoteResult {
+                    amount_in: amount,
+                    amount_out: pool_token_amount,
+                    trade_fee: to_u64(trade_fee)?,
+                    owner_fee: 0,
+                    host_fee: 0,
+                    new_token_a_amount,
+                    new_token_b_amount,
+                }
+            }
+            QuoteOperation::WithdrawSingleTokenTypeExactAmountOut => {
+                let (destination_transfer_amount, burn_pool_token_amount) =
+                    Self::withdraw_single_token_type_amounts(
+                        token_swap.as_ref(),
+                        amount,
+                        trade_direction,
+                        swap_token_a.amount,
+                        swap_token_b.amount,
+                        pool_mint.supply,
+                        token_mint_info,
+                    )?;
+                let withdraw_fee = fees
+                    .owner_withdraw_fee(burn_pool_token_amount)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+                let pool_token_amount = burn_pool_token_amount
+                    .checked_add(withdraw_fee)
+                    .ok_or(SwapError::CalculationFailure)?;
+                if pool_token_amount == 0 {
+                    return Err(SwapError::ZeroTradingTokens.into());
+                }
+                let trade_fee = fees
+                    .single_token_type_trading_fee(to_u128(destination_transfer_amount)?)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+                let (new_token_a_amount, new_token_b_amount) = with_new_swap_token_amount(
+                    swap_token_amount
+                        .checked_sub(destination_transfer_amount)
+                        .ok_or(SwapError::CalculationFailure)?,
+                );
+                QuoteResult {
+                    amount_in: to_u64(pool_token_amount)?,
+                    amount_out: amount,
+                    trade_fee: to_u64(trade_fee)?,
+                    owner_fee: to_u64(withdraw_fee)?,
+                    host_fee: 0,
+                    new_token_a_amount,
+                    new_token_b_amount,
+                }
+            }
         };
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        set_return_data(&quote_result.pack());
+        Ok(())
+    }
 
-        // depositing 10% of the current pool amount in token A and B means
-        // that our pool tokens will be worth 1 / 10 of the current pool amount
-        let pool_amount = INITIAL_SWAP_POOL_AMOUNT / 10;
-        let deposit_a = token_a_amount / 10;
-        let deposit_b = token_b_amount / 10;
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        Self::process_with_constraints(program_id, accounts, input, &SWAP_CONSTRAINTS)
+    }
 
-        // swap not initialized
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+    /// Processes an instruction given extra constraint
+    pub fn process_with_constraints(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        input: &[u8],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let instruction = SwapInstruction::unpack(input)?;
+        match instruction {
+            SwapInstruction::Initialize(Initialize {
+                fees,
+                swap_curve,
+                fee_tier,
+            }) => {
+                msg!("Instruction: Init");
+                Self::process_initialize(
+                    program_id,
+                    fees,
+                    swap_curve,
+                    fee_tier,
+                    accounts,
+                    swap_constraints,
                 )
-            );
-        }
-
-        accounts.initialize_swap().unwrap();
-
-        // wrong owner for swap account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
-            assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+            }
+            SwapInstruction::Swap(Swap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: Swap");
+                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            SwapInstruction::DepositAllTokenTypes(DepositAllTokenTypes {
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+            }) => {
+                msg!("Instruction: DepositAllTokenTypes");
+                Self::process_deposit_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    accounts,
                 )
-            );
-            accounts.swap_account = old_swap_account;
-        }
-
-        // wrong bump seed for authority_key
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+            }
+            SwapInstruction::WithdrawAllTokenTypes(WithdrawAllTokenTypes {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+            }) => {
+                msg!("Instruction: WithdrawAllTokenTypes");
+                Self::process_withdraw_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    accounts,
                 )
-            );
-            accounts.authority_key = old_authority;
-        }
-
-        // not enough token A
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a / 2,
-                deposit_b,
-                0,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+            }
+            SwapInstruction::DepositSingleTokenTypeExactAmountIn(
+                DepositSingleTokenTypeExactAmountIn {
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                },
+            ) => {
+                msg!("Instruction: DepositSingleTokenTypeExactAmountIn");
+                Self::process_deposit_single_token_type_exact_amount_in(
+                    program_id,
+                    source_token_amount,
+                    minimum_pool_token_amount,
+                    accounts,
                 )
-            );
-        }
-
-        // not enough token B
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a,
-                deposit_b / 2,
-                0,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+            }
+            SwapInstruction::WithdrawSingleTokenTypeExactAmountOut(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                },
+            ) => {
+                msg!("Instruction: WithdrawSingleTokenTypeExactAmountOut");
+                Self::process_withdraw_single_token_type_exact_amount_out(
+                    program_id,
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    accounts,
                 )
-            );
-        }
-
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+            }
+            SwapInstruction::SwapExactAmountOut(SwapExactAmountOut {
+                amount_out,
+                maximum_amount_in,
+            }) => {
+                msg!("Instruction: SwapExactAmountOut");
+                Self::process_swap_exact_amount_out(
+                    program_id,
+                    amount_out,
+                    maximum_amount_in,
+                    accounts,
                 )
-            );
-        }
-
-        // wrong pool token account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (
-                wrong_token_key,
-                mut wrong_token_account,
-                _token_b_key,
-                mut _token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &wrong_token_key,
-                    &mut wrong_token_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+            }
+            SwapInstruction::RouteSwap(RouteSwap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: RouteSwap");
+                Self::process_route_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            SwapInstruction::UpdateFees(UpdateFees { fees }) => {
+                msg!("Instruction: UpdateFees");
+                Self::process_update_fees(program_id, fees, accounts, swap_constraints)
+            }
+            SwapInstruction::SetPaused(SetPaused { is_paused }) => {
+                msg!("Instruction: SetPaused");
+                Self::process_set_paused(program_id, is_paused, accounts, swap_constraints)
+            }
+            SwapInstruction::RampAmp(RampAmp {
+                target_amp,
+                stop_timestamp,
+            }) => {
+                msg!("Instruction: RampAmp");
+                Self::process_ramp_amp(
+                    program_id,
+                    target_amp,
+                    stop_timestamp,
+                    accounts,
+                    swap_constraints,
                 )
-            );
-        }
-
-        // no approval
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    deposit_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositAllTokenTypes {
-                            pool_token_amount: pool_amount.try_into().unwrap(),
-                            maximum_token_a_amount: deposit_a,
-                            maximum_token_b_amount: deposit_b,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
+            }
+            SwapInstruction::SetFeeAccount => {
+                msg!("Instruction: SetFeeAccount");
+                Self::process_set_fee_account(program_id, accounts, swap_constraints)
+            }
+            SwapInstruction::FlashLoan(FlashLoan { amount }) => {
+                msg!("Instruction: FlashLoan");
+                Self::process_flash_loan(program_id, amount, accounts)
+            }
+            SwapInstruction::InitializeMultiStable(InitializeMultiStable { fees, amp }) => {
+                msg!("Instruction: InitializeMultiStable");
+                Self::process_initialize_multi_stable(
+                    program_id,
+                    fees,
+                    amp,
+                    accounts,
+                    swap_constraints,
                 )
-            );
-        }
-
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    deposit_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositAllTokenTypes {
-                            pool_token_amount: pool_amount.try_into().unwrap(),
-                            maximum_token_a_amount: deposit_a,
-                            maximum_token_b_amount: deposit_b,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
+            }
+            SwapInstruction::SwapMultiStable(Swap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: SwapMultiStable");
+                Self::process_swap_multi_stable(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            SwapInstruction::DepositMultiStable(DepositMultiStable {
+                token_amounts,
+                minimum_pool_token_amount,
+            }) => {
+                msg!("Instruction: DepositMultiStable");
+                Self::process_deposit_multi_stable(
+                    program_id,
+                    &token_amounts,
+                    minimum_pool_token_amount,
+                    accounts,
                 )
-            );
+            }
+            SwapInstruction::WithdrawMultiStable(WithdrawMultiStable {
+                pool_token_amount,
+                minimum_token_amounts,
+            }) => {
+                msg!("Instruction: WithdrawMultiStable");
+                Self::process_withdraw_multi_stable(
+                    program_id,
+                    pool_token_amount,
+                    &minimum_token_amounts,
+                    accounts,
+                )
+            }
+            SwapInstruction::WithdrawOneMultiStable(WithdrawOneMultiStable {
+                pool_token_amount,
+                minimum_token_amount,
+            }) => {
+                msg!("Instruction: WithdrawOneMultiStable");
+                Self::process_withdraw_one_multi_stable(
+                    program_id,
+                    pool_token_amount,
+                    minimum_token_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::InitializeConcentrated(InitializeConcentrated {
+                fees,
+                tick_spacing,
+                initial_sqrt_price,
+            }) => {
+                msg!("Instruction: InitializeConcentrated");
+                Self::process_initialize_concentrated(
+                    program_id,
+                    fees,
+                    tick_spacing,
+                    initial_sqrt_price,
+                    accounts,
+                    swap_constraints,
+                )
+            }
+            SwapInstruction::OpenPosition(OpenPosition {
+                tick_lower,
+                tick_upper,
+            }) => {
+                msg!("Instruction: OpenPosition");
+                Self::process_open_position(program_id, tick_lower, tick_upper, accounts)
+            }
+            SwapInstruction::IncreaseLiquidity(IncreaseLiquidity {
+                liquidity,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+            }) => {
+                msg!("Instruction: IncreaseLiquidity");
+                Self::process_increase_liquidity(
+                    program_id,
+                    liquidity,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::DecreaseLiquidity(DecreaseLiquidity {
+                liquidity,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+            }) => {
+                msg!("Instruction: DecreaseLiquidity");
+                Self::process_decrease_liquidity(
+                    program_id,
+                    liquidity,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::ClosePosition => {
+                msg!("Instruction: ClosePosition");
+                Self::process_close_position(program_id, accounts)
+            }
+            SwapInstruction::SwapConcentrated(Swap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: SwapConcentrated");
+                Self::process_swap_concentrated(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            SwapInstruction::CollectOwnerFees => {
+                msg!("Instruction: CollectOwnerFees");
+                Self::process_collect_owner_fees(program_id, accounts)
+            }
+            SwapInstruction::Quote(Quote {
+                operation,
+                trade_direction,
+                amount,
+            }) => {
+                msg!("Instruction: Quote");
+                Self::process_quote(program_id, operation, trade_direction, amount, accounts)
+            }
+            SwapInstruction::SetDynamicFee(set_dynamic_fee) => {
+                msg!("Instruction: SetDynamicFee");
+                Self::process_set_dynamic_fee(
+                    program_id,
+                    set_dynamic_fee,
+                    accounts,
+                    swap_constraints,
+                )
+            }
+            SwapInstruction::SetPriceOracle(set_price_oracle) => {
+                msg!("Instruction: SetPriceOracle");
+                Self::process_set_price_oracle(
+                    program_id,
+                    set_price_oracle,
+                    accounts,
+                    swap_constraints,
+                )
+            }
+            SwapInstruction::RefreshOraclePrice => {
+                msg!("Instruction: RefreshOraclePrice");
+                Self::process_refresh_oracle_price(program_id, accounts)
+            }
         }
+    }
+}
 
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+fn to_u128(val: u64) -> Result<u128, SwapError> {
+    val.try_into().map_err(|_| SwapError::ConversionFailure)
+}
 
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
+fn to_u64(val: u128) -> Result<u64, SwapError> {
+    val.try_into().map_err(|_| SwapError::ConversionFailure)
+}
 
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
+fn invoke_signed_wrapper<T>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> Result<(), ProgramError>
+where
+    T: 'static + PrintProgramError + DecodeError<T> + FromPrimitive + Error,
+{
+    invoke_signed(instruction, account_infos, signers_seeds).map_err(|err| {
+        err.print::<T>();
+        err
+    })
+}
 
-            // wrong swap token a account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        curve::calculator::{CurveCalculator, INITIAL_SWAP_POOL_AMOUNT},
+        curve::{
+            base::CurveType,
+            concentrated::sqrt_price_at_tick,
+            constant_price::{record_program, ConstantPriceCurve, RECORD_PRICE_OFFSET},
+            constant_product::ConstantProductCurve,
+            fees::relative_price_change,
+            multi_stable::MultiStableCurve,
+            offset::OffsetCurve,
+            stable::StableCurve,
+            weighted::WeightedCurve,
+        },
+        instruction::{
+            close_position, collect_owner_fees, decrease_liquidity, deposit_all_token_types,
+            deposit_multi_stable, deposit_single_token_type_exact_amount_in, flash_loan,
+            increase_liquidity, initialize, initialize_canonical, initialize_concentrated,
+            initialize_multi_stable, open_position, quote, ramp_amp, refresh_oracle_price,
+            route_swap, set_dynamic_fee, set_fee_account, set_paused, set_price_oracle, swap,
+            swap_concentrated, swap_exact_amount_out, swap_multi_stable, update_fees,
+            withdraw_all_token_types, withdraw_multi_stable, withdraw_one_multi_stable,
+            withdraw_single_token_type_exact_amount_out, RouteSwapLeg,
+        },
+        state::{PriceObservation, PRICE_FRACTIONAL_BITS},
+    };
+    use solana_program::{
+        clock::{Clock, UnixTimestamp},
+        entrypoint::SUCCESS,
+        instruction::Instruction,
+        program_pack::Pack,
+        program_stubs,
+        program_utils::limited_deserialize,
+        rent::Rent,
+        system_instruction::SystemInstruction,
+        system_program,
+    };
+    use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
+    use spl_token_2022::{
+        error::TokenError,
+        extension::{transfer_fee::instruction::initialize_transfer_fee_config, ExtensionType},
+        instruction::{
+            approve, freeze_account, initialize_account, initialize_mint, mint_to, revoke,
+            set_authority, AuthorityType,
+        },
+    };
+    use std::sync::Arc;
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
+    // Test program id for the swap program.
+    const SWAP_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
 
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+    // Test program id for the flash loan receiver program.
+    const FLASH_LOAN_RECEIVER_ID: Pubkey = Pubkey::new_from_array([3u8; 32]);
 
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+    struct TestSyscallStubs {}
+    impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            msg!("TestSyscallStubs::sol_invoke_signed()");
 
-            // wrong swap token b account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
+            let mut new_account_infos = vec![];
 
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
-        }
+            // mimic check for token program in accounts
+            if !account_infos.iter().any(|x| {
+                *x.key == spl_token::id()
+                    || *x.key == spl_token_2022::id()
+                    || *x.key == system_program::id()
+            }) {
+                return Err(ProgramError::InvalidAccountData);
+            }
 
-        // wrong mint
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+            for meta in instruction.accounts.iter() {
+                for account_info in account_infos.iter() {
+                    if meta.pubkey == *account_info.key {
+                        let mut new_account_info = account_info.clone();
+                        for seeds in signers_seeds.iter() {
+                            let signer =
+                                Pubkey::create_program_address(seeds, &SWAP_PROGRAM_ID).unwrap();
+                            if *account_info.key == signer {
+                                new_account_info.is_signer = true;
+                            }
+                        }
+                        new_account_infos.push(new_account_info);
+                    }
+                }
+            }
 
-            assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+            if instruction.program_id == FLASH_LOAN_RECEIVER_ID {
+                process_flash_loan_receiver(&new_account_infos, &instruction.data)
+            } else if instruction.program_id == system_program::id() {
+                process_system_instruction(&new_account_infos, &instruction.data)
+            } else if instruction.program_id == spl_token::id() {
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &new_account_infos,
+                    &instruction.data,
                 )
-            );
+            } else {
+                spl_token_2022::processor::Processor::process(
+                    &instruction.program_id,
+                    &new_account_infos,
+                    &instruction.data,
+                )
+            }
+        }
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut _ as *mut Clock) = Clock {
+                    unix_timestamp: TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.get()),
+                    ..Clock::default()
+                };
+            }
+            SUCCESS
         }
 
-        // deposit 1 pool token fails beacuse it equates to 0 swap tokens
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(SwapError::ZeroTradingTokens.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    1,
-                    deposit_a,
-                    deposit_b,
-                )
-            );
+        fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut _ as *mut Rent) = Rent::default();
+            }
+            SUCCESS
         }
 
-        // slippage exceeded
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            // maximum A amount in too low
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a / 10,
-                    deposit_b,
-                )
-            );
-            // maximum B amount in too low
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b / 10,
-                )
-            );
+        fn sol_set_return_data(&self, data: &[u8]) {
+            TEST_RETURN_DATA.with(|return_data| return_data.replace(data.to_vec()));
         }
+    }
 
-        // invalid input: can't use swap pool tokens as source
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            let authority_key = accounts.authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.deposit_all_token_types(
-                    &authority_key,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
+    // System program handling what canonical pool creation needs.  Test
+    // accounts cannot be resized, so allocated accounts must already have
+    // their final size
+    fn process_system_instruction(account_infos: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        let transfer = |from: &AccountInfo, to: &AccountInfo, daltons: u64| {
+            if !from.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            let from_daltons = from
+                .daltons()
+                .checked_sub(daltons)
+                .ok_or(ProgramError::InsufficientFunds)?;
+            **from.daltons.borrow_mut() = from_daltons;
+            **to.daltons.borrow_mut() += daltons;
+            Ok(())
+        };
+        let allocate = |account: &AccountInfo, space: u64| {
+            if !account.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+            if account.data_len() as u64 != space {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            Ok(())
+        };
+        match limited_deserialize(input, 1024).map_err(|_| ProgramError::InvalidInstructionData)? {
+            SystemInstruction::CreateAccount {
+                daltons,
+                space,
+                owner,
+            } => {
+                if account_infos[1].daltons() > 0 {
+                    return Err(ProgramError::AccountAlreadyInitialized);
+                }
+                transfer(&account_infos[0], &account_infos[1], daltons)?;
+                allocate(&account_infos[1], space)?;
+                account_infos[1].assign(&owner);
+                Ok(())
+            }
+            SystemInstruction::Transfer { daltons } => {
+                transfer(&account_infos[0], &account_infos[1], daltons)
+            }
+            SystemInstruction::Allocate { space } => allocate(&account_infos[0], space),
+            SystemInstruction::Assign { owner } => {
+                allocate(&account_infos[0], account_infos[0].data_len() as u64)?;
+                account_infos[0].assign(&owner);
+                Ok(())
+            }
+            _ => Err(ProgramError::InvalidInstructionData),
         }
+    }
 
-        // correctly deposit
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            accounts
-                .deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-                .unwrap();
+    // Flash loan receiver checking that the swap is locked, and paying its
+    // whole balance back to the swap using the account owner passed through
+    fn process_flash_loan_receiver(account_infos: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        let receive_flash_loan = ReceiveFlashLoan::unpack(input)?;
+        let swap_info = &account_infos[0];
+        let destination_info = &account_infos[1];
+        let swap_token_info = &account_infos[2];
+        let owner_info = &account_infos[5];
+        assert!(SwapVersion::unpack(&swap_info.data.borrow())?.is_locked());
+
+        let destination = spl_token::state::Account::unpack(&destination_info.data.borrow())?;
+        assert!(destination.amount >= receive_flash_loan.amount);
+        let repay = spl_token::instruction::transfer(
+            &spl_token::id(),
+            destination_info.key,
+            swap_token_info.key,
+            owner_info.key,
+            &[],
+            destination.amount,
+        )?;
+        spl_token::processor::Processor::process(
+            &spl_token::id(),
+            &[
+                destination_info.clone(),
+                swap_token_info.clone(),
+                owner_info.clone(),
+            ],
+            &repay.data,
+        )
+    }
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, 0);
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 0);
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-            let swap_pool_account =
-                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            assert_eq!(
-                pool_mint.supply,
-                pool_account.amount + swap_pool_account.amount
-            );
-        }
+    thread_local! {
+        // unix timestamp reported by the clock sysvar to the current test
+        static TEST_UNIX_TIMESTAMP: std::cell::Cell<UnixTimestamp> = std::cell::Cell::new(0);
+        // return data set by the last instruction of the current test
+        static TEST_RETURN_DATA: std::cell::RefCell<Vec<u8>> = std::cell::RefCell::new(vec![]);
     }
 
-    #[test]
-    fn test_withdraw() {
-        let user_key = Pubkey::new_unique();
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 2;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 7;
-        let host_fee_denominator = 100;
+    fn test_syscall_stubs() {
+        use std::sync::Once;
+        static ONCE: Once = Once::new();
 
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
-        };
+        ONCE.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
+        });
+    }
 
-        let token_a_amount = 1000;
-        let token_b_amount = 2000;
-        let curve_type = CurveType::ConstantProduct;
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Arc::new(ConstantProductCurve {}),
-        };
+    struct SwapAccountInfo {
+        bump_seed: u8,
+        authority_key: Pubkey,
+        fees: Fees,
+        swap_curve: SwapCurve,
+        swap_key: Pubkey,
+        swap_account: Account,
+        pool_mint_key: Pubkey,
+        pool_mint_account: Account,
+        pool_fee_key: Pubkey,
+        pool_fee_account: Account,
+        pool_token_key: Pubkey,
+        pool_token_account: Account,
+        token_a_key: Pubkey,
+        token_a_account: Account,
+        token_a_mint_key: Pubkey,
+        token_a_mint_account: Account,
+        token_b_key: Pubkey,
+        token_b_account: Account,
+        token_b_mint_key: Pubkey,
+        token_b_mint_account: Account,
+        pool_token_program_id: Pubkey,
+        token_a_program_id: Pubkey,
+        token_b_program_id: Pubkey,
+    }
 
-        let withdrawer_key = Pubkey::new_unique();
-        let initial_a = token_a_amount / 10;
-        let initial_b = token_b_amount / 10;
-        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
-        let withdraw_amount = initial_pool / 4;
-        let minimum_token_a_amount = initial_a / 40;
-        let minimum_token_b_amount = initial_b / 40;
+    impl SwapAccountInfo {
+        pub fn new(
+            user_key: &Pubkey,
+            fees: Fees,
+            swap_curve: SwapCurve,
+            token_a_amount: u64,
+            token_b_amount: u64,
+        ) -> Self {
+            Self::new_with_token_programs(
+                user_key,
+                fees,
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+                &spl_token::id(),
+                &spl_token::id(),
+                &spl_token::id(),
+                0,
+            )
+        }
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        /// Creates a swap whose token A, token B and pool mints belong to the
+        /// given token programs.  Any spl-token-2022 mint for token A or B
+        /// gets a transfer fee of `transfer_fee_basis_points`.
+        #[allow(clippy::too_many_arguments)]
+        pub fn new_with_token_programs(
+            user_key: &Pubkey,
+            fees: Fees,
+            swap_curve: SwapCurve,
+            token_a_amount: u64,
+            token_b_amount: u64,
+            pool_token_program_id: &Pubkey,
+            token_a_program_id: &Pubkey,
+            token_b_program_id: &Pubkey,
+            transfer_fee_basis_points: u16,
+        ) -> Self {
+            let transfer_fee = |program_id: &Pubkey| {
+                if *program_id == spl_token_2022::id() {
+                    Some(transfer_fee_basis_points)
+                } else {
+                    None
+                }
+            };
+            let swap_key = Pubkey::new_unique();
+            let swap_account = Account::new(0, SwapVersion::LATEST_LEN, &SWAP_PROGRAM_ID);
+            let (authority_key, bump_seed) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
 
-        // swap not initialized
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
+            let (pool_mint_key, mut pool_mint_account) =
+                create_mint(pool_token_program_id, &authority_key, None);
+            let (pool_token_key, pool_token_account) = mint_token(
+                pool_token_program_id,
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let (pool_fee_key, pool_fee_account) = mint_token(
+                pool_token_program_id,
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let (token_a_mint_key, mut token_a_mint_account) = create_mint_with_transfer_fee(
+                token_a_program_id,
+                user_key,
+                None,
+                transfer_fee(token_a_program_id),
+            );
+            let (token_a_key, token_a_account) = mint_token(
+                token_a_program_id,
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &authority_key,
+                token_a_amount,
+            );
+            let (token_b_mint_key, mut token_b_mint_account) = create_mint_with_transfer_fee(
+                token_b_program_id,
+                user_key,
+                None,
+                transfer_fee(token_b_program_id),
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                token_b_program_id,
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &authority_key,
+                token_b_amount,
             );
-        }
-
-        accounts.initialize_swap().unwrap();
 
-        // wrong owner for swap account
-        {
-            let (
+            SwapAccountInfo {
+                bump_seed,
+                authority_key,
+                fees,
+                swap_curve,
+                swap_key,
+                swap_account,
+                pool_mint_key,
+                pool_mint_account,
+                pool_fee_key,
+                pool_fee_account,
+                pool_token_key,
+                pool_token_account,
                 token_a_key,
-                mut token_a_account,
+                token_a_account,
+                token_a_mint_key,
+                token_a_mint_account,
                 token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
-            assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
-            );
-            accounts.swap_account = old_swap_account;
+                token_b_account,
+                token_b_mint_key,
+                token_b_mint_account,
+                pool_token_program_id: *pool_token_program_id,
+                token_a_program_id: *token_a_program_id,
+                token_b_program_id: *token_b_program_id,
+            }
         }
 
-        // wrong bump seed for authority_key
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
+        // Pool at its canonical registry address, whose swap account does not
+        // exist yet but already has its final size, see `process_system_instruction`
+        pub fn new_canonical(
+            user_key: &Pubkey,
+            fees: Fees,
+            swap_curve: SwapCurve,
+            token_a_amount: u64,
+            token_b_amount: u64,
+            fee_tier: u32,
+        ) -> Self {
+            let token_program_id = spl_token::id();
+            let mut token_a_mint = create_mint(&token_program_id, user_key, None);
+            let mut token_b_mint = create_mint(&token_program_id, user_key, None);
+            // canonical pools hold their mints in sorted order
+            if token_a_mint.0 > token_b_mint.0 {
+                std::mem::swap(&mut token_a_mint, &mut token_b_mint);
+            }
+            let (token_a_mint_key, mut token_a_mint_account) = token_a_mint;
+            let (token_b_mint_key, mut token_b_mint_account) = token_b_mint;
+            let (swap_key, _) = registry::find_pool_address(
+                &SWAP_PROGRAM_ID,
+                &token_a_mint_key,
+                &token_b_mint_key,
+                swap_curve.curve_type,
+                fee_tier,
             );
-            accounts.authority_key = old_authority;
-        }
+            let swap_account = Account::new(0, SwapVersion::LATEST_LEN, &system_program::id());
+            let (authority_key, bump_seed) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
 
-        // not enough pool tokens
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                to_u64(withdraw_amount).unwrap() / 2u64,
+            let (pool_mint_key, mut pool_mint_account) =
+                create_mint(&token_program_id, &authority_key, None);
+            let (pool_token_key, pool_token_account) = mint_token(
+                &token_program_id,
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
             );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount / 2,
-                    minimum_token_b_amount / 2,
-                )
+            let (pool_fee_key, pool_fee_account) = mint_token(
+                &token_program_id,
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let (token_a_key, token_a_account) = mint_token(
+                &token_program_id,
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &authority_key,
+                token_a_amount,
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                &token_program_id,
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &authority_key,
+                token_b_amount,
             );
-        }
 
-        // wrong token a / b accounts
-        {
-            let (
+            SwapAccountInfo {
+                bump_seed,
+                authority_key,
+                fees,
+                swap_curve,
+                swap_key,
+                swap_account,
+                pool_mint_key,
+                pool_mint_account,
+                pool_fee_key,
+                pool_fee_account,
+                pool_token_key,
+                pool_token_account,
                 token_a_key,
-                mut token_a_account,
+                token_a_account,
+                token_a_mint_key,
+                token_a_mint_account,
                 token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+                token_b_account,
+                token_b_mint_key,
+                token_b_mint_account,
+                pool_token_program_id: token_program_id,
+                token_a_program_id: token_program_id,
+                token_b_program_id: token_program_id,
+            }
+        }
+
+        pub fn initialize_swap(&mut self) -> ProgramResult {
+            do_process_instruction(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &self.pool_token_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &self.pool_token_key,
+                    self.fees.clone(),
+                    self.swap_curve.clone(),
                 )
-            );
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut self.pool_token_account,
+                    &mut Account::default(),
+                ],
+            )
         }
 
-        // wrong pool token account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let (
-                wrong_token_a_key,
-                mut wrong_token_a_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                withdraw_amount.try_into().unwrap(),
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &wrong_token_a_key,
-                    &mut wrong_token_a_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
+        pub fn initialize_canonical_swap(
+            &mut self,
+            payer_key: &Pubkey,
+            payer_account: &mut Account,
+            fee_tier: u32,
+        ) -> ProgramResult {
+            do_process_instruction(
+                initialize_canonical(
+                    &SWAP_PROGRAM_ID,
+                    &self.pool_token_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &self.pool_token_key,
+                    payer_key,
+                    self.fees.clone(),
+                    self.swap_curve.clone(),
+                    fee_tier,
                 )
-            );
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut self.pool_token_account,
+                    &mut Account::default(),
+                    payer_account,
+                    &mut Account::default(),
+                ],
+            )
         }
 
-        // wrong pool fee account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                wrong_pool_key,
-                wrong_pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
+        pub fn setup_token_accounts(
+            &mut self,
+            mint_owner: &Pubkey,
+            account_owner: &Pubkey,
+            a_amount: u64,
+            b_amount: u64,
+            pool_amount: u64,
+        ) -> (Pubkey, Account, Pubkey, Account, Pubkey, Account) {
+            let (token_a_key, token_a_account) = mint_token(
+                &self.token_a_program_id,
+                &self.token_a_mint_key,
+                &mut self.token_a_mint_account,
+                mint_owner,
+                account_owner,
+                a_amount,
             );
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
+            let (token_b_key, token_b_account) = mint_token(
+                &self.token_b_program_id,
+                &self.token_b_mint_key,
+                &mut self.token_b_mint_account,
+                mint_owner,
+                account_owner,
+                b_amount,
             );
-            let old_pool_fee_account = accounts.pool_fee_account;
-            let old_pool_fee_key = accounts.pool_fee_key;
-            accounts.pool_fee_account = wrong_pool_account;
-            accounts.pool_fee_key = wrong_pool_key;
-            assert_eq!(
-                Err(SwapError::IncorrectFeeAccount.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                ),
+            let (pool_key, pool_account) = mint_token(
+                &self.pool_token_program_id,
+                &self.pool_mint_key,
+                &mut self.pool_mint_account,
+                &self.authority_key,
+                account_owner,
+                pool_amount,
             );
-            accounts.pool_fee_account = old_pool_fee_account;
-            accounts.pool_fee_key = old_pool_fee_key;
-        }
-
-        // no approval
-        {
-            let (
+            (
                 token_a_key,
-                mut token_a_account,
+                token_a_account,
                 token_b_key,
-                mut token_b_account,
+                token_b_account,
                 pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                0,
-                0,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    withdraw_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &token_b_key,
-                        WithdrawAllTokenTypes {
-                            pool_token_amount: withdraw_amount.try_into().unwrap(),
-                            minimum_token_a_amount,
-                            minimum_token_b_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.pool_fee_account,
-                        &mut Account::default(),
-                    ],
-                )
-            );
+                pool_account,
+            )
         }
 
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                withdraw_amount.try_into().unwrap(),
-            );
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    withdraw_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        &token_b_key,
-                        WithdrawAllTokenTypes {
-                            pool_token_amount: withdraw_amount.try_into().unwrap(),
-                            minimum_token_a_amount,
-                            minimum_token_b_amount,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.pool_fee_account,
-                        &mut Account::default(),
-                    ],
+        fn get_token_account(&self, account_key: &Pubkey) -> &Account {
+            if *account_key == self.token_a_key {
+                return &self.token_a_account;
+            } else if *account_key == self.token_b_key {
+                return &self.token_b_account;
+            }
+            panic!("Could not find matching swap token account");
+        }
+
+        fn get_token_mint(&self, account_key: &Pubkey) -> (Pubkey, Account) {
+            if *account_key == self.token_a_key {
+                return (self.token_a_mint_key, self.token_a_mint_account.clone());
+            } else if *account_key == self.token_b_key {
+                return (self.token_b_mint_key, self.token_b_mint_account.clone());
+            }
+            panic!("Could not find matching swap token account");
+        }
+
+        fn get_token_program_id(&self, account_key: &Pubkey) -> &Pubkey {
+            if *account_key == self.token_a_key {
+                return &self.token_a_program_id;
+            } else if *account_key == self.token_b_key {
+                return &self.token_b_program_id;
+            }
+            panic!("Could not find matching swap token account");
+        }
+
+        // mint and token program of a user account, defaulting to token A
+        // for accounts that are not token accounts
+        fn get_user_token_mint(&self, account: &Account) -> (Pubkey, Account, Pubkey) {
+            let mint = StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+                .map(|a| a.base.mint)
+                .unwrap_or_default();
+            if mint == self.token_b_mint_key {
+                (
+                    self.token_b_mint_key,
+                    self.token_b_mint_account.clone(),
+                    self.token_b_program_id,
                 )
-            );
+            } else {
+                (
+                    self.token_a_mint_key,
+                    self.token_a_mint_account.clone(),
+                    self.token_a_program_id,
+                )
+            }
         }
 
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
+        fn set_token_account(&mut self, account_key: &Pubkey, account: Account) {
+            if *account_key == self.token_a_key {
+                self.token_a_account = account;
+                return;
+            } else if *account_key == self.token_b_key {
+                self.token_b_account = account;
+                return;
+            }
+            panic!("Could not find matching swap token account");
+        }
 
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            let source_token_program_id = *self.get_token_program_id(swap_source_key);
+            let destination_token_program_id = *self.get_token_program_id(swap_destination_key);
+            // approve moving from user source account
+            do_process_instruction(
+                approve(
+                    &source_token_program_id,
+                    user_source_key,
+                    &user_transfer_key,
+                    user_key,
+                    &[],
+                    amount_in,
+                )
+                .unwrap(),
+                vec![
+                    user_source_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
+            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+            let (source_mint_key, mut source_mint_account) = self.get_token_mint(swap_source_key);
+            let (destination_mint_key, mut destination_mint_account) =
+                self.get_token_mint(swap_destination_key);
+
+            // perform the swap
+            do_process_instruction(
+                swap(
+                    &SWAP_PROGRAM_ID,
+                    &source_token_program_id,
+                    &destination_token_program_id,
+                    &self.pool_token_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    user_source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    user_destination_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &source_mint_key,
+                    &destination_mint_key,
+                    None,
+                    Swap {
+                        amount_in,
+                        minimum_amount_out,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    user_source_account,
+                    &mut swap_source_account,
+                    &mut swap_destination_account,
+                    user_destination_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut source_mint_account,
+                    &mut destination_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )?;
+
+            self.set_token_account(swap_source_key, swap_source_account);
+            self.set_token_account(swap_destination_key, swap_destination_account);
+
+            Ok(())
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap_exact_amount_out(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            amount_out: u64,
+            maximum_amount_in: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            let source_token_program_id = *self.get_token_program_id(swap_source_key);
+            let destination_token_program_id = *self.get_token_program_id(swap_destination_key);
+            // approve moving from user source account
+            do_process_instruction(
+                approve(
+                    &source_token_program_id,
+                    user_source_key,
+                    &user_transfer_key,
+                    user_key,
+                    &[],
+                    maximum_amount_in,
+                )
+                .unwrap(),
+                vec![
+                    user_source_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
+            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+            let (source_mint_key, mut source_mint_account) = self.get_token_mint(swap_source_key);
+            let (destination_mint_key, mut destination_mint_account) =
+                self.get_token_mint(swap_destination_key);
+
+            // perform the swap
+            do_process_instruction(
+                swap_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &source_token_program_id,
+                    &destination_token_program_id,
+                    &self.pool_token_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    user_source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    user_destination_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &source_mint_key,
+                    &destination_mint_key,
+                    None,
+                    SwapExactAmountOut {
+                        amount_out,
+                        maximum_amount_in,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    user_source_account,
+                    &mut swap_source_account,
+                    &mut swap_destination_account,
+                    user_destination_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut source_mint_account,
+                    &mut destination_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )?;
+
+            self.set_token_account(swap_source_key, swap_source_account);
+            self.set_token_account(swap_destination_key, swap_destination_account);
+
+            Ok(())
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_all_token_types(
+            &mut self,
+            depositor_key: &Pubkey,
+            depositor_token_a_key: &Pubkey,
+            depositor_token_a_account: &mut Account,
+            depositor_token_b_key: &Pubkey,
+            depositor_token_b_account: &mut Account,
+            depositor_pool_key: &Pubkey,
+            depositor_pool_account: &mut Account,
+            pool_token_amount: u64,
+            maximum_token_a_amount: u64,
+            maximum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority = Pubkey::new_unique();
+            do_process_instruction(
+                approve(
+                    &self.token_a_program_id,
+                    depositor_token_a_key,
+                    &user_transfer_authority,
+                    depositor_key,
+                    &[],
+                    maximum_token_a_amount,
+                )
+                .unwrap(),
+                vec![
+                    depositor_token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                approve(
+                    &self.token_b_program_id,
+                    depositor_token_b_key,
+                    &user_transfer_authority,
+                    depositor_key,
+                    &[],
+                    maximum_token_b_amount,
+                )
+                .unwrap(),
+                vec![
+                    depositor_token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                deposit_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &self.token_a_program_id,
+                    &self.token_b_program_id,
+                    &self.pool_token_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority,
+                    depositor_token_a_key,
+                    depositor_token_b_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    depositor_pool_key,
+                    &self.token_a_mint_key,
+                    &self.token_b_mint_key,
+                    DepositAllTokenTypes {
+                        pool_token_amount,
+                        maximum_token_a_amount,
+                        maximum_token_b_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    depositor_token_a_account,
+                    depositor_token_b_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    depositor_pool_account,
+                    &mut self.token_a_mint_account,
+                    &mut self.token_b_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_all_token_types(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            token_a_key: &Pubkey,
+            token_a_account: &mut Account,
+            token_b_key: &Pubkey,
+            token_b_account: &mut Account,
+            pool_token_amount: u64,
+            minimum_token_a_amount: u64,
+            minimum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &self.pool_token_program_id,
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            // withdraw token a and b correctly
+            do_process_instruction(
+                withdraw_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &self.pool_token_program_id,
+                    &self.token_a_program_id,
+                    &self.token_b_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    token_a_key,
+                    token_b_key,
+                    &self.token_a_mint_key,
+                    &self.token_b_mint_key,
+                    WithdrawAllTokenTypes {
+                        pool_token_amount,
+                        minimum_token_a_amount,
+                        minimum_token_b_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    token_a_account,
+                    token_b_account,
+                    &mut self.pool_fee_account,
+                    &mut self.token_a_mint_account,
+                    &mut self.token_b_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_single_token_type_exact_amount_in(
+            &mut self,
+            depositor_key: &Pubkey,
+            deposit_account_key: &Pubkey,
+            deposit_token_account: &mut Account,
+            deposit_pool_key: &Pubkey,
+            deposit_pool_account: &mut Account,
+            source_token_amount: u64,
+            minimum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            let (source_mint_key, mut source_mint_account, source_token_program_id) =
+                self.get_user_token_mint(deposit_token_account);
+            do_process_instruction(
+                approve(
+                    &source_token_program_id,
+                    deposit_account_key,
+                    &user_transfer_authority_key,
+                    depositor_key,
+                    &[],
+                    source_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    deposit_token_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                deposit_single_token_type_exact_amount_in(
+                    &SWAP_PROGRAM_ID,
+                    &source_token_program_id,
+                    &self.pool_token_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    deposit_account_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    deposit_pool_key,
+                    &source_mint_key,
+                    DepositSingleTokenTypeExactAmountIn {
+                        source_token_amount,
+                        minimum_pool_token_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    deposit_token_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    deposit_pool_account,
+                    &mut source_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_single_token_type_exact_amount_out(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+            destination_token_amount: u64,
+            maximum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            let (destination_mint_key, mut destination_mint_account, destination_token_program_id) =
+                self.get_user_token_mint(destination_account);
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &self.pool_token_program_id,
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    maximum_pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                withdraw_single_token_type_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &self.pool_token_program_id,
+                    &destination_token_program_id,
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    destination_key,
+                    &destination_mint_key,
+                    WithdrawSingleTokenTypeExactAmountOut {
+                        destination_token_amount,
+                        maximum_pool_token_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    destination_account,
+                    &mut self.pool_fee_account,
+                    &mut destination_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        pub fn quote(
+            &mut self,
+            operation: QuoteOperation,
+            trade_direction: TradeDirection,
+            amount: u64,
+        ) -> Result<QuoteResult, ProgramError> {
+            do_process_instruction(
+                quote(
+                    &SWAP_PROGRAM_ID,
+                    &self.swap_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &self.token_a_mint_key,
+                    &self.token_b_mint_key,
+                    Quote {
+                        operation,
+                        trade_direction,
+                        amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut self.token_a_mint_account,
+                    &mut self.token_b_mint_account,
+                ],
+            )?;
+            TEST_RETURN_DATA.with(|return_data| QuoteResult::unpack(&return_data.borrow()))
+        }
+    }
+
+    /// Accounts of a multi-token stable swap, with every token owned by the
+    /// same token program
+    struct MultiStableSwapAccountInfo {
+        authority_key: Pubkey,
+        fees: Fees,
+        amp: u64,
+        swap_key: Pubkey,
+        swap_account: Account,
+        pool_mint_key: Pubkey,
+        pool_mint_account: Account,
+        pool_fee_key: Pubkey,
+        pool_fee_account: Account,
+        pool_token_key: Pubkey,
+        pool_token_account: Account,
+        token_keys: Vec<Pubkey>,
+        token_accounts: Vec<Account>,
+        token_mint_keys: Vec<Pubkey>,
+        token_mint_accounts: Vec<Account>,
+    }
+
+    impl MultiStableSwapAccountInfo {
+        pub fn new(user_key: &Pubkey, fees: Fees, amp: u64, token_amounts: &[u64]) -> Self {
+            let token_program_id = spl_token::id();
+            let swap_key = Pubkey::new_unique();
+            let swap_account = Account::new(0, MultiStableSwap::ACCOUNT_LEN, &SWAP_PROGRAM_ID);
+            let (authority_key, _bump_seed) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+
+            let (pool_mint_key, mut pool_mint_account) =
+                create_mint(&token_program_id, &authority_key, None);
+            let (pool_token_key, pool_token_account) = mint_token(
+                &token_program_id,
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let (pool_fee_key, pool_fee_account) = mint_token(
+                &token_program_id,
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let mut token_keys = vec![];
+            let mut token_accounts = vec![];
+            let mut token_mint_keys = vec![];
+            let mut token_mint_accounts = vec![];
+            for token_amount in token_amounts {
+                let (token_mint_key, mut token_mint_account) =
+                    create_mint(&token_program_id, user_key, None);
+                let (token_key, token_account) = mint_token(
+                    &token_program_id,
+                    &token_mint_key,
+                    &mut token_mint_account,
+                    user_key,
+                    &authority_key,
+                    *token_amount,
+                );
+                token_keys.push(token_key);
+                token_accounts.push(token_account);
+                token_mint_keys.push(token_mint_key);
+                token_mint_accounts.push(token_mint_account);
+            }
+
+            MultiStableSwapAccountInfo {
+                authority_key,
+                fees,
+                amp,
+                swap_key,
+                swap_account,
+                pool_mint_key,
+                pool_mint_account,
+                pool_fee_key,
+                pool_fee_account,
+                pool_token_key,
+                pool_token_account,
+                token_keys,
+                token_accounts,
+                token_mint_keys,
+                token_mint_accounts,
+            }
+        }
+
+        pub fn initialize_swap(&mut self) -> ProgramResult {
+            let instruction = initialize_multi_stable(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &self.pool_mint_key,
+                &self.pool_fee_key,
+                &self.pool_token_key,
+                &self.token_keys,
+                &self.token_mint_keys,
+                InitializeMultiStable {
+                    fees: self.fees.clone(),
+                    amp: self.amp,
+                },
+            )
+            .unwrap();
+            let mut authority_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut self.pool_mint_account,
+                &mut self.pool_fee_account,
+                &mut self.pool_token_account,
+                &mut token_program_account,
+            ];
+            for (token_account, token_mint_account) in self
+                .token_accounts
+                .iter_mut()
+                .zip(self.token_mint_accounts.iter_mut())
+            {
+                accounts.push(token_account);
+                accounts.push(token_mint_account);
+            }
+            do_process_instruction(instruction, accounts)
+        }
+
+        pub fn setup_token_accounts(
+            &mut self,
+            mint_owner: &Pubkey,
+            account_owner: &Pubkey,
+            token_amounts: &[u64],
+            pool_amount: u64,
+        ) -> (Vec<Pubkey>, Vec<Account>, Pubkey, Account) {
+            let mut token_keys = vec![];
+            let mut token_accounts = vec![];
+            for ((token_amount, token_mint_key), token_mint_account) in token_amounts
+                .iter()
+                .zip(&self.token_mint_keys)
+                .zip(self.token_mint_accounts.iter_mut())
+            {
+                let (token_key, token_account) = mint_token(
+                    &spl_token::id(),
+                    token_mint_key,
+                    token_mint_account,
+                    mint_owner,
+                    account_owner,
+                    *token_amount,
+                );
+                token_keys.push(token_key);
+                token_accounts.push(token_account);
+            }
+            let (pool_key, pool_account) = mint_token(
+                &spl_token::id(),
+                &self.pool_mint_key,
+                &mut self.pool_mint_account,
+                &self.authority_key,
+                account_owner,
+                pool_amount,
+            );
+            (token_keys, token_accounts, pool_key, pool_account)
+        }
+
+        /// Approves `user_transfer_key` to move `amount` out of the user's
+        /// account
+        fn approve(
+            user_key: &Pubkey,
+            user_transfer_key: &Pubkey,
+            account_key: &Pubkey,
+            account: &mut Account,
+            amount: u64,
+        ) {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    account_key,
+                    user_transfer_key,
+                    user_key,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+                vec![account, &mut Account::default(), &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            source_index: usize,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            destination_index: usize,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            Self::approve(
+                user_key,
+                &user_transfer_key,
+                user_source_key,
+                user_source_account,
+                amount_in,
+            );
+            let instruction = swap_multi_stable(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_key,
+                user_source_key,
+                user_destination_key,
+                &self.pool_mint_key,
+                &self.pool_fee_key,
+                &self.token_mint_keys[source_index],
+                &self.token_mint_keys[destination_index],
+                &self.token_keys,
+                Swap {
+                    amount_in,
+                    minimum_amount_out,
+                },
+            )
+            .unwrap();
+            let mut source_mint_account = self.token_mint_accounts[source_index].clone();
+            let mut destination_mint_account = self.token_mint_accounts[destination_index].clone();
+            let mut authority_account = Account::default();
+            let mut user_transfer_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_account,
+                user_source_account,
+                user_destination_account,
+                &mut self.pool_mint_account,
+                &mut self.pool_fee_account,
+                &mut source_mint_account,
+                &mut destination_mint_account,
+                &mut token_program_account,
+            ];
+            accounts.extend(self.token_accounts.iter_mut());
+            do_process_instruction(instruction, accounts)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_keys: &[Pubkey],
+            user_source_accounts: &mut [Account],
+            user_pool_key: &Pubkey,
+            user_pool_account: &mut Account,
+            token_amounts: &[u64],
+            minimum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            for ((user_source_key, user_source_account), token_amount) in user_source_keys
+                .iter()
+                .zip(user_source_accounts.iter_mut())
+                .zip(token_amounts)
+            {
+                Self::approve(
+                    user_key,
+                    &user_transfer_key,
+                    user_source_key,
+                    user_source_account,
+                    *token_amount,
+                );
+            }
+            let instruction = deposit_multi_stable(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_key,
+                &self.pool_mint_key,
+                user_pool_key,
+                user_source_keys,
+                &self.token_keys,
+                &self.token_mint_keys,
+                DepositMultiStable {
+                    token_amounts: token_amounts.to_vec(),
+                    minimum_pool_token_amount,
+                },
+            )
+            .unwrap();
+            let mut authority_account = Account::default();
+            let mut user_transfer_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_account,
+                &mut self.pool_mint_account,
+                user_pool_account,
+                &mut token_program_account,
+            ];
+            for ((user_source_account, token_account), token_mint_account) in user_source_accounts
+                .iter_mut()
+                .zip(self.token_accounts.iter_mut())
+                .zip(self.token_mint_accounts.iter_mut())
+            {
+                accounts.push(user_source_account);
+                accounts.push(token_account);
+                accounts.push(token_mint_account);
+            }
+            do_process_instruction(instruction, accounts)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw(
+            &mut self,
+            user_key: &Pubkey,
+            user_pool_key: &Pubkey,
+            user_pool_account: &mut Account,
+            user_destination_keys: &[Pubkey],
+            user_destination_accounts: &mut [Account],
+            pool_token_amount: u64,
+            minimum_token_amounts: &[u64],
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            Self::approve(
+                user_key,
+                &user_transfer_key,
+                user_pool_key,
+                user_pool_account,
+                pool_token_amount,
+            );
+            let instruction = withdraw_multi_stable(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_key,
+                &self.pool_mint_key,
+                user_pool_key,
+                &self.pool_fee_key,
+                &self.token_keys,
+                user_destination_keys,
+                &self.token_mint_keys,
+                WithdrawMultiStable {
+                    pool_token_amount,
+                    minimum_token_amounts: minimum_token_amounts.to_vec(),
+                },
+            )
+            .unwrap();
+            let mut authority_account = Account::default();
+            let mut user_transfer_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_account,
+                &mut self.pool_mint_account,
+                user_pool_account,
+                &mut self.pool_fee_account,
+                &mut token_program_account,
+            ];
+            for ((token_account, user_destination_account), token_mint_account) in self
+                .token_accounts
+                .iter_mut()
+                .zip(user_destination_accounts.iter_mut())
+                .zip(self.token_mint_accounts.iter_mut())
+            {
+                accounts.push(token_account);
+                accounts.push(user_destination_account);
+                accounts.push(token_mint_account);
+            }
+            do_process_instruction(instruction, accounts)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_one(
+            &mut self,
+            user_key: &Pubkey,
+            user_pool_key: &Pubkey,
+            user_pool_account: &mut Account,
+            destination_index: usize,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            pool_token_amount: u64,
+            minimum_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            Self::approve(
+                user_key,
+                &user_transfer_key,
+                user_pool_key,
+                user_pool_account,
+                pool_token_amount,
+            );
+            let instruction = withdraw_one_multi_stable(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_key,
+                &self.pool_mint_key,
+                user_pool_key,
+                &self.pool_fee_key,
+                user_destination_key,
+                &self.token_mint_keys[destination_index],
+                &self.token_keys,
+                WithdrawOneMultiStable {
+                    pool_token_amount,
+                    minimum_token_amount,
+                },
+            )
+            .unwrap();
+            let mut destination_mint_account = self.token_mint_accounts[destination_index].clone();
+            let mut authority_account = Account::default();
+            let mut user_transfer_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_account,
+                &mut self.pool_mint_account,
+                user_pool_account,
+                &mut self.pool_fee_account,
+                user_destination_account,
+                &mut destination_mint_account,
+                &mut token_program_account,
+            ];
+            accounts.extend(self.token_accounts.iter_mut());
+            do_process_instruction(instruction, accounts)
+        }
+
+        /// Reserves of the swap's token accounts, in pool order
+        fn reserves(&self) -> Vec<u128> {
+            self.token_accounts
+                .iter()
+                .map(|token_account| {
+                    spl_token::state::Account::unpack(&token_account.data)
+                        .unwrap()
+                        .amount as u128
+                })
+                .collect()
+        }
+
+        fn pool_supply(&self) -> u128 {
+            spl_token::state::Mint::unpack(&self.pool_mint_account.data)
+                .unwrap()
+                .supply as u128
+        }
+    }
+
+    struct ConcentratedSwapAccountInfo {
+        authority_key: Pubkey,
+        fees: Fees,
+        tick_spacing: u16,
+        initial_sqrt_price: u128,
+        fee_owner_key: Pubkey,
+        swap_key: Pubkey,
+        swap_account: Account,
+        token_a_key: Pubkey,
+        token_a_account: Account,
+        token_b_key: Pubkey,
+        token_b_account: Account,
+        token_a_mint_key: Pubkey,
+        token_a_mint_account: Account,
+        token_b_mint_key: Pubkey,
+        token_b_mint_account: Account,
+    }
+
+    /// Accounts of a position in a concentrated liquidity swap
+    struct PositionAccountInfo {
+        position_key: Pubkey,
+        position_account: Account,
+        mint_key: Pubkey,
+        mint_account: Account,
+        token_key: Pubkey,
+        token_account: Account,
+    }
+
+    impl PositionAccountInfo {
+        /// Creates an uninitialized position whose mint, with `decimals`, is
+        /// owned by `mint_authority_key`, and whose token goes to `owner_key`
+        pub fn new(mint_authority_key: &Pubkey, owner_key: &Pubkey, decimals: u8) -> Self {
+            let token_program_id = spl_token::id();
+            let position_key = Pubkey::new_unique();
+            let position_account = Account::new(
+                Rent::default().minimum_balance(Position::LEN),
+                Position::LEN,
+                &SWAP_PROGRAM_ID,
+            );
+            let mint_key = Pubkey::new_unique();
+            let space = spl_token::state::Mint::get_packed_len();
+            let mut mint_account = Account::new(
+                Rent::default().minimum_balance(space),
+                space,
+                &token_program_id,
+            );
+            let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+            do_process_instruction(
+                initialize_mint(
+                    &token_program_id,
+                    &mint_key,
+                    mint_authority_key,
+                    None,
+                    decimals,
+                )
+                .unwrap(),
+                vec![&mut mint_account, &mut rent_sysvar_account],
+            )
+            .unwrap();
+            let (token_key, token_account) = mint_token(
+                &token_program_id,
+                &mint_key,
+                &mut mint_account,
+                mint_authority_key,
+                owner_key,
+                0,
+            );
+            PositionAccountInfo {
+                position_key,
+                position_account,
+                mint_key,
+                mint_account,
+                token_key,
+                token_account,
+            }
+        }
+
+        fn position(&self) -> Position {
+            Position::unpack(&self.position_account.data).unwrap()
+        }
+    }
+
+    impl ConcentratedSwapAccountInfo {
+        pub fn new(
+            user_key: &Pubkey,
+            fee_owner_key: &Pubkey,
+            fees: Fees,
+            tick_spacing: u16,
+            initial_sqrt_price: u128,
+        ) -> Self {
+            let token_program_id = spl_token::id();
+            let swap_key = Pubkey::new_unique();
+            let swap_account = Account::new(0, ConcentratedSwap::ACCOUNT_LEN, &SWAP_PROGRAM_ID);
+            let (authority_key, _bump_seed) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+
+            let (token_a_mint_key, mut token_a_mint_account) =
+                create_mint(&token_program_id, user_key, None);
+            let (token_a_key, token_a_account) = mint_token(
+                &token_program_id,
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &authority_key,
+                0,
+            );
+            let (token_b_mint_key, mut token_b_mint_account) =
+                create_mint(&token_program_id, user_key, None);
+            let (token_b_key, token_b_account) = mint_token(
+                &token_program_id,
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &authority_key,
+                0,
+            );
+
+            ConcentratedSwapAccountInfo {
+                authority_key,
+                fees,
+                tick_spacing,
+                initial_sqrt_price,
+                fee_owner_key: *fee_owner_key,
+                swap_key,
+                swap_account,
+                token_a_key,
+                token_a_account,
+                token_b_key,
+                token_b_account,
+                token_a_mint_key,
+                token_a_mint_account,
+                token_b_mint_key,
+                token_b_mint_account,
+            }
+        }
+
+        pub fn initialize_swap(&mut self) -> ProgramResult {
+            let instruction = initialize_concentrated(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                &self.token_a_mint_key,
+                &self.token_b_mint_key,
+                &self.fee_owner_key,
+                InitializeConcentrated {
+                    fees: self.fees.clone(),
+                    tick_spacing: self.tick_spacing,
+                    initial_sqrt_price: self.initial_sqrt_price,
+                },
+            )
+            .unwrap();
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.token_a_mint_account,
+                    &mut self.token_b_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        pub fn setup_token_accounts(
+            &mut self,
+            mint_owner: &Pubkey,
+            account_owner: &Pubkey,
+            token_a_amount: u64,
+            token_b_amount: u64,
+        ) -> (Pubkey, Account, Pubkey, Account) {
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &self.token_a_mint_key,
+                &mut self.token_a_mint_account,
+                mint_owner,
+                account_owner,
+                token_a_amount,
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &self.token_b_mint_key,
+                &mut self.token_b_mint_account,
+                mint_owner,
+                account_owner,
+                token_b_amount,
+            );
+            (token_a_key, token_a_account, token_b_key, token_b_account)
+        }
+
+        /// Approves `user_transfer_key` to move `amount` out of the user's
+        /// account
+        fn approve(
+            user_key: &Pubkey,
+            user_transfer_key: &Pubkey,
+            account_key: &Pubkey,
+            account: &mut Account,
+            amount: u64,
+        ) {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    account_key,
+                    user_transfer_key,
+                    user_key,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+                vec![account, &mut Account::default(), &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        pub fn open_position(
+            &mut self,
+            position: &mut PositionAccountInfo,
+            tick_lower: i32,
+            tick_upper: i32,
+        ) -> ProgramResult {
+            let instruction = open_position(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &position.position_key,
+                &position.mint_key,
+                &position.token_key,
+                OpenPosition {
+                    tick_lower,
+                    tick_upper,
+                },
+            )
+            .unwrap();
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut position.position_account,
+                    &mut position.mint_account,
+                    &mut position.token_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn increase_liquidity(
+            &mut self,
+            user_key: &Pubkey,
+            position: &mut PositionAccountInfo,
+            source_a_key: &Pubkey,
+            source_a_account: &mut Account,
+            source_b_key: &Pubkey,
+            source_b_account: &mut Account,
+            liquidity: u128,
+            maximum_token_a_amount: u64,
+            maximum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            Self::approve(
+                user_key,
+                &user_transfer_key,
+                source_a_key,
+                source_a_account,
+                maximum_token_a_amount,
+            );
+            Self::approve(
+                user_key,
+                &user_transfer_key,
+                source_b_key,
+                source_b_account,
+                maximum_token_b_amount,
+            );
+            let instruction = increase_liquidity(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_key,
+                &position.position_key,
+                source_a_key,
+                source_b_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                &self.token_a_mint_key,
+                &self.token_b_mint_key,
+                IncreaseLiquidity {
+                    liquidity,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                },
+            )
+            .unwrap();
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut position.position_account,
+                    source_a_account,
+                    source_b_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.token_a_mint_account,
+                    &mut self.token_b_mint_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn decrease_liquidity(
+            &mut self,
+            position_owner_key: &Pubkey,
+            position: &mut PositionAccountInfo,
+            destination_a_key: &Pubkey,
+            destination_a_account: &mut Account,
+            destination_b_key: &Pubkey,
+            destination_b_account: &mut Account,
+            liquidity: u128,
+            minimum_token_a_amount: u64,
+            minimum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let instruction = decrease_liquidity(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                position_owner_key,
+                &position.position_key,
+                &position.token_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                destination_a_key,
+                destination_b_key,
+                &self.token_a_mint_key,
+                &self.token_b_mint_key,
+                DecreaseLiquidity {
+                    liquidity,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                },
+            )
+            .unwrap();
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut position.position_account,
+                    &mut position.token_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    destination_a_account,
+                    destination_b_account,
+                    &mut self.token_a_mint_account,
+                    &mut self.token_b_mint_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        pub fn close_position(
+            &mut self,
+            position_owner_key: &Pubkey,
+            position: &mut PositionAccountInfo,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+        ) -> ProgramResult {
+            let instruction = close_position(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                position_owner_key,
+                &position.position_key,
+                &position.mint_key,
+                &position.token_key,
+                destination_key,
+            )
+            .unwrap();
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut position.position_account,
+                    &mut position.mint_account,
+                    &mut position.token_account,
+                    destination_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            a_to_b: bool,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            Self::approve(
+                user_key,
+                &user_transfer_key,
+                user_source_key,
+                user_source_account,
+                amount_in,
+            );
+            let (swap_source_key, swap_destination_key, source_mint_key, destination_mint_key) =
+                if a_to_b {
+                    (
+                        self.token_a_key,
+                        self.token_b_key,
+                        self.token_a_mint_key,
+                        self.token_b_mint_key,
+                    )
+                } else {
+                    (
+                        self.token_b_key,
+                        self.token_a_key,
+                        self.token_b_mint_key,
+                        self.token_a_mint_key,
+                    )
+                };
+            let instruction = swap_concentrated(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                &user_transfer_key,
+                user_source_key,
+                &swap_source_key,
+                &swap_destination_key,
+                user_destination_key,
+                &source_mint_key,
+                &destination_mint_key,
+                Swap {
+                    amount_in,
+                    minimum_amount_out,
+                },
+            )
+            .unwrap();
+            let (swap_source_account, swap_destination_account) = if a_to_b {
+                (&mut self.token_a_account, &mut self.token_b_account)
+            } else {
+                (&mut self.token_b_account, &mut self.token_a_account)
+            };
+            let (mut source_mint_account, mut destination_mint_account) = if a_to_b {
+                (
+                    self.token_a_mint_account.clone(),
+                    self.token_b_mint_account.clone(),
+                )
+            } else {
+                (
+                    self.token_b_mint_account.clone(),
+                    self.token_a_mint_account.clone(),
+                )
+            };
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    user_source_account,
+                    swap_source_account,
+                    swap_destination_account,
+                    user_destination_account,
+                    &mut source_mint_account,
+                    &mut destination_mint_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        pub fn collect_owner_fees(
+            &mut self,
+            fee_owner_key: &Pubkey,
+            destination_a_key: &Pubkey,
+            destination_a_account: &mut Account,
+            destination_b_key: &Pubkey,
+            destination_b_account: &mut Account,
+        ) -> ProgramResult {
+            let instruction = collect_owner_fees(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &self.swap_key,
+                &self.authority_key,
+                fee_owner_key,
+                &self.token_a_key,
+                &self.token_b_key,
+                destination_a_key,
+                destination_b_key,
+                &self.token_a_mint_key,
+                &self.token_b_mint_key,
+            )
+            .unwrap();
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    destination_a_account,
+                    destination_b_account,
+                    &mut self.token_a_mint_account,
+                    &mut self.token_b_mint_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        fn state(&self) -> ConcentratedSwap {
+            ConcentratedSwap::unpack_account(&self.swap_account.data).unwrap()
+        }
+    }
+
+    fn mint_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(spl_token::state::Mint::get_packed_len())
+    }
+
+    fn account_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(spl_token::state::Account::get_packed_len())
+    }
+
+    fn do_process_instruction_with_fee_constraints(
+        instruction: Instruction,
+        accounts: Vec<&mut Account>,
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        test_syscall_stubs();
+
+        // approximate the logic in the actual runtime which runs the instruction
+        // and only updates accounts if the instruction is successful
+        let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
+        let mut meta = instruction
+            .accounts
+            .iter()
+            .zip(account_clones.iter_mut())
+            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
+            .collect::<Vec<_>>();
+        let mut account_infos = create_is_signer_account_infos(&mut meta);
+        let res = if instruction.program_id == SWAP_PROGRAM_ID {
+            Processor::process_with_constraints(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+                swap_constraints,
+            )
+        } else if instruction.program_id == spl_token::id() {
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+            )
+        } else {
+            spl_token_2022::processor::Processor::process(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+            )
+        };
+
+        if res.is_ok() {
+            let mut account_metas = instruction
+                .accounts
+                .iter()
+                .zip(accounts)
+                .map(|(account_meta, account)| (&account_meta.pubkey, account))
+                .collect::<Vec<_>>();
+            for account_info in account_infos.iter_mut() {
+                for account_meta in account_metas.iter_mut() {
+                    if account_info.key == account_meta.0 {
+                        let account = &mut account_meta.1;
+                        account.owner = *account_info.owner;
+                        account.daltons = **account_info.daltons.borrow();
+                        account.data = account_info.data.borrow().to_vec();
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    fn do_process_instruction(
+        instruction: Instruction,
+        accounts: Vec<&mut Account>,
+    ) -> ProgramResult {
+        do_process_instruction_with_fee_constraints(instruction, accounts, &SWAP_CONSTRAINTS)
+    }
+
+    fn mint_token(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        mint_account: &mut Account,
+        mint_authority_key: &Pubkey,
+        account_owner_key: &Pubkey,
+        amount: u64,
+    ) -> (Pubkey, Account) {
+        let account_key = Pubkey::new_unique();
+        let space = if *program_id == spl_token_2022::id() {
+            let mint = StateWithExtensions::<Mint>::unpack(&mint_account.data).unwrap();
+            let mint_extensions = mint.get_extension_types().unwrap();
+            let required_extensions =
+                ExtensionType::get_required_init_account_extensions(&mint_extensions);
+            ExtensionType::get_account_len::<spl_token_2022::state::Account>(&required_extensions)
+        } else {
+            spl_token::state::Account::get_packed_len()
+        };
+        let mut account_account =
+            Account::new(Rent::default().minimum_balance(space), space, program_id);
+        let mut mint_authority_account = Account::default();
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        do_process_instruction(
+            initialize_account(program_id, &account_key, mint_key, account_owner_key).unwrap(),
+            vec![
+                &mut account_account,
+                mint_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar_account,
+            ],
+        )
+        .unwrap();
+
+        if amount > 0 {
+            do_process_instruction(
+                mint_to(
+                    program_id,
+                    mint_key,
+                    &account_key,
+                    mint_authority_key,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+                vec![
+                    mint_account,
+                    &mut account_account,
+                    &mut mint_authority_account,
+                ],
+            )
+            .unwrap();
+        }
+
+        (account_key, account_account)
+    }
+
+    fn create_mint(
+        program_id: &Pubkey,
+        authority_key: &Pubkey,
+        freeze_authority: Option<&Pubkey>,
+    ) -> (Pubkey, Account) {
+        create_mint_with_transfer_fee(program_id, authority_key, freeze_authority, None)
+    }
+
+    fn create_mint_with_transfer_fee(
+        program_id: &Pubkey,
+        authority_key: &Pubkey,
+        freeze_authority: Option<&Pubkey>,
+        transfer_fee_basis_points: Option<u16>,
+    ) -> (Pubkey, Account) {
+        let mint_key = Pubkey::new_unique();
+        let space = if transfer_fee_basis_points.is_some() {
+            ExtensionType::get_account_len::<Mint>(&[ExtensionType::TransferFeeConfig])
+        } else {
+            spl_token::state::Mint::get_packed_len()
+        };
+        let mut mint_account =
+            Account::new(Rent::default().minimum_balance(space), space, program_id);
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        if let Some(transfer_fee_basis_points) = transfer_fee_basis_points {
+            do_process_instruction(
+                initialize_transfer_fee_config(
+                    program_id,
+                    &mint_key,
+                    None,
+                    None,
+                    transfer_fee_basis_points,
+                    u64::MAX,
+                )
+                .unwrap(),
+                vec![&mut mint_account],
+            )
+            .unwrap();
+        }
+
+        do_process_instruction(
+            initialize_mint(program_id, &mint_key, authority_key, freeze_authority, 2).unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar_account],
+        )
+        .unwrap();
+
+        (mint_key, mint_account)
+    }
+
+    #[test]
+    fn test_token_program_id_error() {
+        test_syscall_stubs();
+        let swap_key = Pubkey::new_unique();
+        let mut mint = (Pubkey::new_unique(), Account::default());
+        let mut destination = (Pubkey::new_unique(), Account::default());
+        let token_program = (spl_token::id(), Account::default());
+        let (authority_key, bump_seed) =
+            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+        let mut authority = (authority_key, Account::default());
+        let swap_bytes = swap_key.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = mint_to(
+            &token_program.0,
+            &mint.0,
+            &destination.0,
+            &authority.0,
+            &[],
+            10,
+        )
+        .unwrap();
+        let mint = (&mut mint).into();
+        let destination = (&mut destination).into();
+        let authority = (&mut authority).into();
+
+        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+    }
+
+    #[test]
+    fn test_token_error() {
+        test_syscall_stubs();
+        let token_id = spl_token::id();
+        let swap_key = Pubkey::new_unique();
+        let mut mint = (
+            Pubkey::new_unique(),
+            Account::new(
+                mint_minimum_balance(),
+                spl_token::state::Mint::get_packed_len(),
+                &token_id,
+            ),
+        );
+        let mut destination = (
+            Pubkey::new_unique(),
+            Account::new(
+                account_minimum_balance(),
+                spl_token::state::Account::get_packed_len(),
+                &token_id,
+            ),
+        );
+        let mut token_program = (token_id, Account::default());
+        let (authority_key, bump_seed) =
+            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+        let mut authority = (authority_key, Account::default());
+        let swap_bytes = swap_key.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+        let mut rent_sysvar = (
+            Pubkey::new_unique(),
+            create_account_for_test(&Rent::default()),
+        );
+        do_process_instruction(
+            initialize_mint(
+                &token_program.0,
+                &mint.0,
+                &authority.0,
+                Some(&authority.0),
+                2,
+            )
+            .unwrap(),
+            vec![&mut mint.1, &mut rent_sysvar.1],
+        )
+        .unwrap();
+        do_process_instruction(
+            initialize_account(&token_program.0, &destination.0, &mint.0, &authority.0).unwrap(),
+            vec![
+                &mut destination.1,
+                &mut mint.1,
+                &mut authority.1,
+                &mut rent_sysvar.1,
+                &mut token_program.1,
+            ],
+        )
+        .unwrap();
+        do_process_instruction(
+            freeze_account(&token_program.0, &destination.0, &mint.0, &authority.0, &[]).unwrap(),
+            vec![
+                &mut destination.1,
+                &mut mint.1,
+                &mut authority.1,
+                &mut token_program.1,
+            ],
+        )
+        .unwrap();
+        let ix = mint_to(
+            &token_program.0,
+            &mint.0,
+            &destination.0,
+            &authority.0,
+            &[],
+            10,
+        )
+        .unwrap();
+        let mint_info = (&mut mint).into();
+        let destination_info = (&mut destination).into();
+        let authority_info = (&mut authority).into();
+        let token_program_info = (&mut token_program).into();
+
+        let err = invoke_signed_wrapper::<TokenError>(
+            &ix,
+            &[
+                mint_info,
+                destination_info,
+                authority_info,
+                token_program_info,
+            ],
+            signers,
+        )
+        .unwrap_err();
+        assert_eq!(err, ProgramError::Custom(TokenError::AccountFrozen as u32));
+    }
+
+    #[test]
+    fn test_initialize() {
+        let user_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 2;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 20;
+        let host_fee_denominator = 100;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let pool_token_amount = 10;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // uninitialized token a account
+        {
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(SwapError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // uninitialized token b account
+        {
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(SwapError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // uninitialized pool mint
+        {
+            let old_account = accounts.pool_mint_account;
+            accounts.pool_mint_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(SwapError::ExpectedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_account;
+        }
+
+        // token A account owner is not swap authority
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // token B account owner is not swap authority
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // pool token account owner is swap authority
+        {
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.pool_token_account;
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidOutputOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_token_account = old_account;
+        }
+
+        // pool fee account owner is swap authority
+        {
+            let (_pool_fee_key, pool_fee_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.pool_fee_account;
+            accounts.pool_fee_account = pool_fee_account;
+            assert_eq!(
+                Err(SwapError::InvalidOutputOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_fee_account = old_account;
+        }
+
+        // pool mint authority is not swap authority
+        {
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &user_key, None);
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // pool mint token has freeze authority
+        {
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, Some(&user_key));
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(SwapError::InvalidFreezeAuthority.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // token A account owned by wrong program
+        {
+            let (_token_a_key, mut token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                token_a_amount,
+            );
+            token_a_account.owner = SWAP_PROGRAM_ID;
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // token B account owned by wrong program
+        {
+            let (_token_b_key, mut token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                token_b_amount,
+            );
+            token_b_account.owner = SWAP_PROGRAM_ID;
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // empty token A account
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // empty token B account
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // invalid pool tokens
+        {
+            let old_mint = accounts.pool_mint_account;
+            let old_pool_account = accounts.pool_token_account;
+
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            accounts.pool_mint_account = pool_mint_account;
+
+            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                0,
+            );
+
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                pool_token_amount,
+            );
+
+            // non-empty pool token account
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            // pool tokens already in circulation
+            accounts.pool_token_account = empty_pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.pool_mint_account = old_mint;
+            accounts.pool_token_account = old_pool_account;
+        }
+
+        // pool fee account has wrong mint
+        {
+            let (_pool_fee_key, pool_fee_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.pool_fee_account;
+            accounts.pool_fee_account = pool_fee_account;
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_fee_account = old_account;
+        }
+
+        // token A account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token A account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // wrong token program id
+        {
+            let wrong_program_id = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_program_id,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.pool_token_key,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.pool_token_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // create swap with same token A and B
+        {
+            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                10,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_a_repeat_account;
+            assert_eq!(
+                Err(SwapError::RepeatedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // create valid swap
+        accounts.initialize_swap().unwrap();
+
+        // create invalid flat swap
+        {
+            let token_b_price = 0;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantPrice,
+                calculator: Arc::new(ConstantPriceCurve { token_b_price }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            assert_eq!(
+                Err(SwapError::InvalidCurve.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // create valid flat swap
+        {
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let token_b_price = 10_000;
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantPrice,
+                calculator: Arc::new(ConstantPriceCurve { token_b_price }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            accounts.initialize_swap().unwrap();
+        }
+
+        // create invalid offset swap
+        {
+            let token_b_offset = 0;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Offset,
+                calculator: Arc::new(OffsetCurve { token_b_offset }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            assert_eq!(
+                Err(SwapError::InvalidCurve.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // create valid offset swap
+        {
+            let token_b_offset = 10;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Offset,
+                calculator: Arc::new(OffsetCurve { token_b_offset }),
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            accounts.initialize_swap().unwrap();
+        }
+
+        // wrong owner key in constraint
+        {
+            let new_key = Pubkey::new_unique();
+            let trade_fee_numerator = 25;
+            let trade_fee_denominator = 10000;
+            let owner_trade_fee_numerator = 5;
+            let owner_trade_fee_denominator = 10000;
+            let host_fee_numerator = 20;
+            let host_fee_denominator = 100;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let curve = ConstantProductCurve {};
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Arc::new(curve),
+            };
+            let owner_key = &new_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+            });
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                do_process_instruction_with_fee_constraints(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.pool_token_key,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.pool_token_account,
+                        &mut Account::default(),
+                    ],
+                    &constraints,
+                )
+            );
+        }
+
+        // wrong fee in constraint
+        {
+            let trade_fee_numerator = 25;
+            let trade_fee_denominator = 10000;
+            let owner_trade_fee_numerator = 5;
+            let owner_trade_fee_denominator = 10000;
+            let host_fee_numerator = 20;
+            let host_fee_denominator = 100;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let curve = ConstantProductCurve {};
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Arc::new(curve),
+            };
+            let owner_key = &user_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+            });
+            let mut bad_fees = fees.clone();
+            bad_fees.trade_fee_numerator = trade_fee_numerator - 1;
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                bad_fees,
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                do_process_instruction_with_fee_constraints(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.pool_token_key,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.pool_token_account,
+                        &mut Account::default(),
+                    ],
+                    &constraints,
+                )
+            );
+        }
+
+        // create valid swap with constraints
+        {
+            let trade_fee_numerator = 25;
+            let trade_fee_denominator = 10000;
+            let owner_trade_fee_numerator = 5;
+            let owner_trade_fee_denominator = 10000;
+            let host_fee_numerator = 20;
+            let host_fee_denominator = 100;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let curve = ConstantProductCurve {};
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Arc::new(curve),
+            };
+            let owner_key = &user_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+            });
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            do_process_instruction_with_fee_constraints(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &accounts.pool_mint_key,
+                    &accounts.pool_fee_key,
+                    &accounts.pool_token_key,
+                    accounts.fees,
+                    accounts.swap_curve.clone(),
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.pool_fee_account,
+                    &mut accounts.pool_token_account,
+                    &mut Account::default(),
+                ],
+                &constraints,
+            )
+            .unwrap();
+        }
+
+        // create again
+        {
+            assert_eq!(
+                Err(SwapError::AlreadyInUse.into()),
+                accounts.initialize_swap()
+            );
+        }
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.is_initialized());
+        assert_eq!(swap_state.bump_seed(), accounts.bump_seed);
+        assert_eq!(
+            swap_state.swap_curve().curve_type,
+            accounts.swap_curve.curve_type
+        );
+        assert_eq!(*swap_state.token_a_account(), accounts.token_a_key);
+        assert_eq!(*swap_state.token_b_account(), accounts.token_b_key);
+        assert_eq!(*swap_state.pool_mint(), accounts.pool_mint_key);
+        assert_eq!(*swap_state.token_a_mint(), accounts.token_a_mint_key);
+        assert_eq!(*swap_state.token_b_mint(), accounts.token_b_mint_key);
+        assert_eq!(*swap_state.pool_fee_account(), accounts.pool_fee_key);
+        let token_a = spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, token_a_amount);
+        let token_b = spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, token_b_amount);
+        let pool_account =
+            spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, pool_account.amount);
+    }
+
+    #[test]
+    fn test_deposit() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 2;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 20;
+        let host_fee_denominator = 100;
+
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 9000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // depositing 10% of the current pool amount in token A and B means
+        // that our pool tokens will be worth 1 / 10 of the current pool amount
+        let pool_amount = INITIAL_SWAP_POOL_AMOUNT / 10;
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong bump seed for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough token A
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a / 2,
+                deposit_b,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // not enough token B
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a,
+                deposit_b / 2,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (
+                wrong_token_key,
+                mut wrong_token_account,
+                _token_b_key,
+                mut _token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &wrong_token_key,
+                    &mut wrong_token_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    deposit_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        DepositAllTokenTypes {
+                            pool_token_amount: pool_amount.try_into().unwrap(),
+                            maximum_token_a_amount: deposit_a,
+                            maximum_token_b_amount: deposit_b,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    deposit_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &wrong_key,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        DepositAllTokenTypes {
+                            pool_token_amount: pool_amount.try_into().unwrap(),
+                            maximum_token_a_amount: deposit_a,
+                            maximum_token_b_amount: deposit_b,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // deposit 1 pool token fails beacuse it equates to 0 swap tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(SwapError::ZeroTradingTokens.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    1,
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            // maximum A amount in too low
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a / 10,
+                    deposit_b,
+                )
+            );
+            // maximum B amount in too low
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b / 10,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as source
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            let authority_key = accounts.authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.deposit_all_token_types(
+                    &authority_key,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // correctly deposit
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            accounts
+                .deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 0);
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 0);
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            let swap_pool_account =
+                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(
+                pool_mint.supply,
+                pool_account.amount + swap_pool_account.amount
+            );
+        }
+    }
+
+    #[test]
+    fn test_withdraw() {
+        let user_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 2;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 7;
+        let host_fee_denominator = 100;
+
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+
+        let withdrawer_key = Pubkey::new_unique();
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
+        let withdraw_amount = initial_pool / 4;
+        let minimum_token_a_amount = initial_a / 40;
+        let minimum_token_b_amount = initial_b / 40;
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong bump seed for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough pool tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                to_u64(withdraw_amount).unwrap() / 2u64,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount / 2,
+                    minimum_token_b_amount / 2,
+                )
+            );
+        }
+
+        // wrong token a / b accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let (
+                wrong_token_a_key,
+                mut wrong_token_a_account,
+                _token_b_key,
+                _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                withdraw_amount.try_into().unwrap(),
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &wrong_token_a_key,
+                    &mut wrong_token_a_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        // wrong pool fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                wrong_pool_key,
+                wrong_pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let old_pool_fee_account = accounts.pool_fee_account;
+            let old_pool_fee_key = accounts.pool_fee_key;
+            accounts.pool_fee_account = wrong_pool_account;
+            accounts.pool_fee_key = wrong_pool_key;
+            assert_eq!(
+                Err(SwapError::IncorrectFeeAccount.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                ),
+            );
+            accounts.pool_fee_account = old_pool_fee_account;
+            accounts.pool_fee_key = old_pool_fee_key;
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                0,
+                0,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    withdraw_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        WithdrawAllTokenTypes {
+                            pool_token_amount: withdraw_amount.try_into().unwrap(),
+                            minimum_token_a_amount,
+                            minimum_token_b_amount,
+                        }
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                withdraw_amount.try_into().unwrap(),
+            );
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    withdraw_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &wrong_key,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        WithdrawAllTokenTypes {
+                            pool_token_amount: withdraw_amount.try_into().unwrap(),
+                            minimum_token_a_amount,
+                            minimum_token_b_amount,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // withdrawing 1 pool token fails because it equates to 0 output tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            assert_eq!(
+                Err(SwapError::ZeroTradingTokens.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    1,
+                    0,
+                    0,
+                )
+            );
+        }
+
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            // minimum A amount out too high
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount * 10,
+                    minimum_token_b_amount,
+                )
+            );
+            // minimum B amount out too high
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount * 10,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as destination
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+            );
+        }
+
+        // correct withdrawal
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+
+            accounts
+                .withdraw_all_token_types(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    withdraw_amount.try_into().unwrap(),
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            let withdraw_fee = accounts.fees.owner_withdraw_fee(withdraw_amount).unwrap();
+            let results = accounts
+                .swap_curve
+                .calculator
+                .pool_tokens_to_trading_tokens(
+                    withdraw_amount - withdraw_fee,
+                    pool_mint.supply.try_into().unwrap(),
+                    swap_token_a.amount.try_into().unwrap(),
+                    swap_token_b.amount.try_into().unwrap(),
+                    RoundDirection::Floor,
+                )
+                .unwrap();
+            assert_eq!(
+                swap_token_a.amount,
+                token_a_amount - to_u64(results.token_a_amount).unwrap()
+            );
+            assert_eq!(
+                swap_token_b.amount,
+                token_b_amount - to_u64(results.token_b_amount).unwrap()
+            );
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(
+                token_a.amount,
+                initial_a + to_u64(results.token_a_amount).unwrap()
+            );
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(
+                token_b.amount,
+                initial_b + to_u64(results.token_b_amount).unwrap()
+            );
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(
+                pool_account.amount,
+                to_u64(initial_pool - withdraw_amount).unwrap()
+            );
+            let fee_account =
+                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+            assert_eq!(
+                fee_account.amount,
+                TryInto::<u64>::try_into(withdraw_fee).unwrap()
+            );
+        }
+
+        // correct withdrawal from fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+
+            let pool_fee_key = accounts.pool_fee_key;
+            let mut pool_fee_account = accounts.pool_fee_account.clone();
+            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
+            let pool_fee_amount = fee_account.amount;
+
+            accounts
+                .withdraw_all_token_types(
+                    &user_key,
+                    &pool_fee_key,
+                    &mut pool_fee_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    pool_fee_amount,
+                    0,
+                    0,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            let results = accounts
+                .swap_curve
+                .calculator
+                .pool_tokens_to_trading_tokens(
+                    pool_fee_amount.try_into().unwrap(),
+                    pool_mint.supply.try_into().unwrap(),
+                    swap_token_a.amount.try_into().unwrap(),
+                    swap_token_b.amount.try_into().unwrap(),
+                    RoundDirection::Floor,
+                )
+                .unwrap();
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(
+                token_a.amount,
+                TryInto::<u64>::try_into(results.token_a_amount).unwrap()
+            );
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(
+                token_b.amount,
+                TryInto::<u64>::try_into(results.token_b_amount).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_deposit_one_exact_in() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 2;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 20;
+        let host_fee_denominator = 100;
+
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 9000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+        let pool_amount = to_u64(INITIAL_SWAP_POOL_AMOUNT / 100).unwrap();
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong bump seed for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough token A / B
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a / 2,
+                deposit_b / 2,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    0,
+                )
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b,
+                    0,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    deposit_single_token_type_exact_amount_in(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &token_a_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_mint_key,
+                        DepositSingleTokenTypeExactAmountIn {
+                            source_token_amount: deposit_a,
+                            minimum_pool_token_amount: pool_amount,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    deposit_single_token_type_exact_amount_in(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        &accounts.token_a_mint_key,
+                        DepositSingleTokenTypeExactAmountIn {
+                            source_token_amount: deposit_a,
+                            minimum_pool_token_amount: pool_amount,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account;
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            // minimum pool amount too high
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a / 10,
+                    pool_amount,
+                )
+            );
+            // minimum pool amount too high
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b / 10,
+                    pool_amount,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as source
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            let authority_key = accounts.authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &authority_key,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+            );
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.deposit_single_token_type_exact_amount_in(
+                    &authority_key,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b,
+                    pool_amount,
+                )
+            );
+        }
+
+        // correctly deposit
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            accounts
+                .deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_a,
+                    pool_amount,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 0);
+
+            accounts
+                .deposit_single_token_type_exact_amount_in(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    deposit_b,
+                    pool_amount,
+                )
+                .unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 0);
+
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            let swap_pool_account =
+                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(
+                pool_mint.supply,
+                pool_account.amount + swap_pool_account.amount
+            );
+        }
+    }
+
+    #[test]
+    fn test_withdraw_one_exact_out() {
+        let user_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 2;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 7;
+        let host_fee_denominator = 100;
+
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 100_000;
+        let token_b_amount = 200_000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+
+        let withdrawer_key = Pubkey::new_unique();
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
+        let maximum_pool_token_amount = to_u64(initial_pool / 4).unwrap();
+        let destination_a_amount = initial_a / 40;
+        let destination_b_amount = initial_b / 40;
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong bump seed for authority_key
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough pool tokens
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount / 1000,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                maximum_pool_token_amount,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        // wrong pool fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                wrong_pool_key,
+                wrong_pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let old_pool_fee_account = accounts.pool_fee_account;
+            let old_pool_fee_key = accounts.pool_fee_key;
+            accounts.pool_fee_account = wrong_pool_account;
+            accounts.pool_fee_key = wrong_pool_key;
+            assert_eq!(
+                Err(SwapError::IncorrectFeeAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.pool_fee_account = old_pool_fee_account;
+            accounts.pool_fee_key = old_pool_fee_key;
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                0,
+                0,
+                maximum_pool_token_amount,
+            );
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    withdraw_single_token_type_exact_amount_out(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &accounts.token_a_mint_key,
+                        WithdrawSingleTokenTypeExactAmountOut {
+                            destination_token_amount: destination_a_amount,
+                            maximum_pool_token_amount,
+                        }
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    withdraw_single_token_type_exact_amount_out(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &accounts.token_a_mint_key,
+                        WithdrawSingleTokenTypeExactAmountOut {
+                            destination_token_amount: destination_a_amount,
+                            maximum_pool_token_amount,
+                        }
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
+
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+
+            // maximum pool token amount too low
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount / 1000,
+                )
+            );
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount / 1000,
+                )
+            );
+        }
+
+        // invalid input: can't use swap pool tokens as destination
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        // correct withdrawal
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
+            );
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+
+            let pool_token_amount = accounts
+                .swap_curve
+                .withdraw_single_token_type_exact_out(
+                    destination_a_amount.try_into().unwrap(),
+                    swap_token_a.amount.try_into().unwrap(),
+                    swap_token_b.amount.try_into().unwrap(),
+                    pool_mint.supply.try_into().unwrap(),
+                    TradeDirection::AtoB,
+                    &accounts.fees,
+                )
+                .unwrap();
+            let withdraw_fee = accounts.fees.owner_withdraw_fee(pool_token_amount).unwrap();
+
+            accounts
+                .withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            assert_eq!(swap_token_a.amount, token_a_amount - destination_a_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a + destination_a_amount);
+
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(
+                pool_account.amount,
+                to_u64(initial_pool - pool_token_amount - withdraw_fee).unwrap()
+            );
+            let fee_account =
+                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+            assert_eq!(fee_account.amount, to_u64(withdraw_fee).unwrap());
+        }
+
+        // correct withdrawal from fee account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+
+            let fee_a_amount = 2;
+            let pool_fee_key = accounts.pool_fee_key;
+            let mut pool_fee_account = accounts.pool_fee_account.clone();
+            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
+            let pool_fee_amount = fee_account.amount;
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            let token_a_amount = swap_token_a.amount;
+            accounts
+                .withdraw_single_token_type_exact_amount_out(
+                    &user_key,
+                    &pool_fee_key,
+                    &mut pool_fee_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    fee_a_amount,
+                    pool_fee_amount,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            assert_eq!(swap_token_a.amount, token_a_amount - fee_a_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a + fee_a_amount);
+        }
+    }
+
+    fn check_valid_swap_curve(
+        fees: Fees,
+        curve_type: CurveType,
+        calculator: Arc<dyn CurveCalculator + Send + Sync>,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+        );
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        accounts.initialize_swap().unwrap();
+
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+        // swap one way
+        let a_to_b_amount = initial_a / 10;
+        let minimum_token_b_amount = 0;
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        let initial_supply = pool_mint.supply;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                minimum_token_b_amount,
+            )
+            .unwrap();
+
+        let results = swap_curve
+            .swap(
+                a_to_b_amount.try_into().unwrap(),
+                token_a_amount.try_into().unwrap(),
+                token_b_amount.try_into().unwrap(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        let token_a_amount = swap_token_a.amount;
+        assert_eq!(
+            token_a_amount,
+            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
+        );
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, initial_a - a_to_b_amount);
+
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        let token_b_amount = swap_token_b.amount;
+        assert_eq!(
+            token_b_amount,
+            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
+        );
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(
+            token_b.amount,
+            initial_b + to_u64(results.destination_amount_swapped).unwrap()
+        );
+
+        let first_fee = swap_curve
+            .withdraw_single_token_type_exact_out(
+                results.owner_fee,
+                token_a_amount.try_into().unwrap(),
+                token_b_amount.try_into().unwrap(),
+                initial_supply.try_into().unwrap(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let fee_account =
+            spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+        assert_eq!(
+            fee_account.amount,
+            TryInto::<u64>::try_into(first_fee).unwrap()
+        );
+
+        let first_swap_amount = results.destination_amount_swapped;
+
+        // swap the other way
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        let initial_supply = pool_mint.supply;
+
+        let b_to_a_amount = initial_b / 10;
+        let minimum_a_amount = 0;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                b_to_a_amount,
+                minimum_a_amount,
+            )
+            .unwrap();
+
+        let results = swap_curve
+            .swap(
+                b_to_a_amount.try_into().unwrap(),
+                token_b_amount.try_into().unwrap(),
+                token_a_amount.try_into().unwrap(),
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap();
+
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        let token_a_amount = swap_token_a.amount;
+        assert_eq!(
+            token_a_amount,
+            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
+        );
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(
+            token_a.amount,
+            initial_a - a_to_b_amount + to_u64(results.destination_amount_swapped).unwrap()
+        );
+
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        let token_b_amount = swap_token_b.amount;
+        assert_eq!(
+            token_b_amount,
+            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
+        );
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(
+            token_b.amount,
+            initial_b + to_u64(first_swap_amount).unwrap()
+                - to_u64(results.source_amount_swapped).unwrap()
+        );
+
+        let second_fee = swap_curve
+            .withdraw_single_token_type_exact_out(
+                results.owner_fee,
+                token_a_amount.try_into().unwrap(),
+                token_b_amount.try_into().unwrap(),
+                initial_supply.try_into().unwrap(),
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap();
+        let fee_account =
+            spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+        assert_eq!(fee_account.amount, to_u64(first_fee + second_fee).unwrap());
+    }
+
+    fn check_valid_swap_exact_amount_out_curve(
+        fees: Fees,
+        curve_type: CurveType,
+        calculator: Arc<dyn CurveCalculator + Send + Sync>,
+        token_a_amount: u64,
+        token_b_amount: u64,
+    ) {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+        );
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        accounts.initialize_swap().unwrap();
+
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+
+        let b_out_amount = initial_b / 10;
+        let results = swap_curve
+            .swap_exact_out(
+                b_out_amount.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let a_in_amount = to_u64(results.source_amount_swapped).unwrap();
+
+        // one token short of the required input
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.swap_exact_amount_out(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                b_out_amount,
+                a_in_amount - 1,
+            )
+        );
+        accounts
+            .swap_exact_amount_out(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                b_out_amount,
+                a_in_amount,
+            )
+            .unwrap();
+
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(
+            swap_token_a.amount,
+            to_u64(results.new_swap_source_amount).unwrap()
+        );
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, initial_a - a_in_amount);
+
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(
+            swap_token_b.amount,
+            to_u64(results.new_swap_destination_amount).unwrap()
+        );
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, initial_b + b_out_amount);
+
+        // swap the other way
+        let token_a_amount = swap_token_a.amount;
+        let token_b_amount = swap_token_b.amount;
+        let a_out_amount = initial_a / 10;
+        let results = swap_curve
+            .swap_exact_out(
+                a_out_amount.into(),
+                token_b_amount.into(),
+                token_a_amount.into(),
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap();
+        let b_in_amount = to_u64(results.source_amount_swapped).unwrap();
+        accounts
+            .swap_exact_amount_out(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                a_out_amount,
+                b_in_amount,
+            )
+            .unwrap();
+
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, initial_a - a_in_amount + a_out_amount);
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, initial_b + b_out_amount - b_in_amount);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(
+            swap_token_b.amount,
+            to_u64(results.new_swap_source_amount).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_valid_swap_exact_amount_out_curves() {
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 30,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 30,
+            host_fee_numerator: 20,
+            host_fee_denominator: 100,
+        };
+
+        let token_a_amount = 10_000_000_000;
+        let token_b_amount = 50_000_000_000;
+
+        check_valid_swap_exact_amount_out_curve(
+            fees.clone(),
+            CurveType::ConstantProduct,
+            Arc::new(ConstantProductCurve {}),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_price = 1;
+        check_valid_swap_exact_amount_out_curve(
+            fees.clone(),
+            CurveType::ConstantPrice,
+            Arc::new(ConstantPriceCurve { token_b_price }),
+            token_a_amount,
+            token_b_amount,
+        );
+        check_valid_swap_exact_amount_out_curve(
+            fees.clone(),
+            CurveType::Stable,
+            Arc::new(StableCurve { amp: 100 }),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_offset = 10_000_000_000;
+        check_valid_swap_exact_amount_out_curve(
+            fees.clone(),
+            CurveType::Offset,
+            Arc::new(OffsetCurve { token_b_offset }),
+            token_a_amount,
+            token_b_amount,
+        );
+        check_valid_swap_exact_amount_out_curve(
+            fees,
+            CurveType::Weighted,
+            Arc::new(WeightedCurve {
+                token_a_weight: 80,
+                token_b_weight: 20,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
+    }
+
+    #[test]
+    fn test_valid_swap_curves_all_fees() {
+        // All fees
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 10;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 30;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 30;
+        let host_fee_numerator = 20;
+        let host_fee_denominator = 100;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 10_000_000_000;
+        let token_b_amount = 50_000_000_000;
+
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantProduct,
+            Arc::new(ConstantProductCurve {}),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_price = 1;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantPrice,
+            Arc::new(ConstantPriceCurve { token_b_price }),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_offset = 10_000_000_000;
+        check_valid_swap_curve(
+            fees,
+            CurveType::Offset,
+            Arc::new(OffsetCurve { token_b_offset }),
+            token_a_amount,
+            token_b_amount,
+        );
+    }
+
+    #[test]
+    fn test_valid_swap_curves_trade_fee_only() {
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 10;
+        let owner_trade_fee_numerator = 0;
+        let owner_trade_fee_denominator = 0;
+        let owner_withdraw_fee_numerator = 0;
+        let owner_withdraw_fee_denominator = 0;
+        let host_fee_numerator = 0;
+        let host_fee_denominator = 0;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 10_000_000_000;
+        let token_b_amount = 50_000_000_000;
+
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantProduct,
+            Arc::new(ConstantProductCurve {}),
+            token_a_amount,
+            token_b_amount,
+        );
+        let token_b_price = 10_000;
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::ConstantPrice,
+            Arc::new(ConstantPriceCurve { token_b_price }),
+            token_a_amount,
+            token_b_amount / token_b_price,
+        );
+        let token_b_offset = 1;
+        check_valid_swap_curve(
+            fees,
+            CurveType::Offset,
+            Arc::new(OffsetCurve { token_b_offset }),
+            token_a_amount,
+            token_b_amount,
+        );
+    }
+
+    #[test]
+    fn test_valid_swap_with_fee_constraints() {
+        let owner_key = Pubkey::new_unique();
+
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 10;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 30;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 30;
+        let host_fee_numerator = 10;
+        let host_fee_denominator = 100;
+
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 5_000_000;
+
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let curve = ConstantProductCurve {};
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(curve),
+        };
+
+        let owner_key_str = &owner_key.to_string();
+        let valid_curve_types = &[CurveType::ConstantProduct];
+        let constraints = Some(SwapConstraints {
+            owner_key: owner_key_str,
+            valid_curve_types,
+            fees: &fees,
+        });
+        let mut accounts = SwapAccountInfo::new(
+            &owner_key,
+            fees.clone(),
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+
+        // initialize swap
+        do_process_instruction_with_fee_constraints(
+            initialize(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &accounts.pool_mint_key,
+                &accounts.pool_fee_key,
+                &accounts.pool_token_key,
+                accounts.fees.clone(),
+                accounts.swap_curve.clone(),
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.pool_fee_account,
+                &mut accounts.pool_token_account,
+                &mut Account::default(),
+            ],
+            &constraints,
+        )
+        .unwrap();
+
+        let authority_key = accounts.authority_key;
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(
+            &owner_key,
+            &authority_key,
+            token_a_amount,
+            token_b_amount,
+            0,
+        );
+
+        let amount_in = token_a_amount / 2;
+        let minimum_amount_out = 0;
+
+        // perform the swap
+        do_process_instruction_with_fee_constraints(
+            swap(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &spl_token::id(),
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                &token_a_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &token_b_key,
+                &accounts.pool_mint_key,
+                &accounts.pool_fee_key,
+                &accounts.token_a_mint_key,
+                &accounts.token_b_mint_key,
+                Some(&pool_key),
+                Swap {
+                    amount_in,
+                    minimum_amount_out,
+                },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut token_a_account,
+                &mut accounts.token_a_account,
+                &mut accounts.token_b_account,
+                &mut token_b_account,
+                &mut accounts.pool_mint_account,
+                &mut accounts.pool_fee_account,
+                &mut accounts.token_a_mint_account,
+                &mut accounts.token_b_mint_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut pool_account,
+            ],
+            &constraints,
+        )
+        .unwrap();
+
+        // check that fees were taken in the host fee account
+        let host_fee_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+        let owner_fee_account =
+            spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+        let total_fee = owner_fee_account.amount * host_fee_denominator
+            / (host_fee_denominator - host_fee_numerator);
+        assert_eq!(
+            total_fee,
+            host_fee_account.amount + owner_fee_account.amount
+        );
+    }
+
+    #[test]
+    fn test_invalid_swap() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 4;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 9;
+        let host_fee_denominator = 100;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 5000;
+        let curve_type = CurveType::ConstantProduct;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        let initial_a = token_a_amount / 5;
+        let initial_b = token_b_amount / 5;
+        let minimum_token_b_amount = initial_b / 2;
 
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
 
-            // wrong swap token a account
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(ProgramError::UninitializedAccount),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
+                    initial_a,
                     minimum_token_b_amount,
                 )
             );
+        }
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
-
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
-
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+        accounts.initialize_swap().unwrap();
 
-            // wrong swap token b account
+        // wrong swap account program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(ProgramError::IncorrectProgramId),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
+                    initial_a,
                     minimum_token_b_amount,
                 )
             );
-
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
+            accounts.swap_account = old_swap_account;
         }
 
-        // wrong mint
+        // wrong bump seed
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
             );
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
-
+            accounts.authority_key = bad_authority_key;
             assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
+                    initial_a,
                     minimum_token_b_amount,
                 )
             );
-
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
+            accounts.authority_key = old_authority;
         }
 
-        // withdrawing 1 pool token fails because it equates to 0 output tokens
+        // wrong token program id
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let wrong_program_id = Pubkey::new_unique();
             assert_eq!(
-                Err(SwapError::ZeroTradingTokens.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    1,
-                    0,
-                    0,
-                )
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    swap(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_program_id,
+                        &wrong_program_id,
+                        &wrong_program_id,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        None,
+                        Swap {
+                            amount_in: initial_a,
+                            minimum_amount_out: minimum_token_b_amount,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                ),
             );
         }
 
-        // slippage exceeded
+        // not enough token a to swap
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            // minimum A amount out too high
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount * 10,
-                    minimum_token_b_amount,
+                    initial_a * 2,
+                    minimum_token_b_amount * 2,
                 )
             );
-            // minimum B amount out too high
+        }
+
+        // wrong swap token A / B accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let user_transfer_key = Pubkey::new_unique();
             assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount * 10,
-                )
+                Err(SwapError::IncorrectSwapAccount.into()),
+                do_process_instruction(
+                    swap(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_key,
+                        &token_a_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        None,
+                        Swap {
+                            amount_in: initial_a,
+                            minimum_amount_out: minimum_token_b_amount,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account.clone(),
+                        &mut token_a_account,
+                        &mut token_b_account.clone(),
+                        &mut token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                ),
             );
         }
 
-        // invalid input: can't use swap pool tokens as destination
+        // wrong user token A / B accounts
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
+                Err(TokenError::MintMismatch.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_b_key,
                     &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                )
-            );
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
+                    initial_a,
                     minimum_token_b_amount,
                 )
             );
         }
 
-        // correct withdrawal
+        // swap from a to a
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-
-            accounts
-                .withdraw_all_token_types(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+                _token_b_key,
+                _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account.clone(),
+                    &swap_token_a_key,
+                    &swap_token_a_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    withdraw_amount.try_into().unwrap(),
-                    minimum_token_a_amount,
+                    initial_a,
                     minimum_token_b_amount,
                 )
-                .unwrap();
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            let withdraw_fee = accounts.fees.owner_withdraw_fee(withdraw_amount).unwrap();
-            let results = accounts
-                .swap_curve
-                .calculator
-                .pool_tokens_to_trading_tokens(
-                    withdraw_amount - withdraw_fee,
-                    pool_mint.supply.try_into().unwrap(),
-                    swap_token_a.amount.try_into().unwrap(),
-                    swap_token_b.amount.try_into().unwrap(),
-                    RoundDirection::Floor,
-                )
-                .unwrap();
-            assert_eq!(
-                swap_token_a.amount,
-                token_a_amount - to_u64(results.token_a_amount).unwrap()
-            );
-            assert_eq!(
-                swap_token_b.amount,
-                token_b_amount - to_u64(results.token_b_amount).unwrap()
-            );
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(
-                token_a.amount,
-                initial_a + to_u64(results.token_a_amount).unwrap()
-            );
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(
-                token_b.amount,
-                initial_b + to_u64(results.token_b_amount).unwrap()
-            );
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-            assert_eq!(
-                pool_account.amount,
-                to_u64(initial_pool - withdraw_amount).unwrap()
-            );
-            let fee_account =
-                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
-            assert_eq!(
-                fee_account.amount,
-                TryInto::<u64>::try_into(withdraw_fee).unwrap()
             );
         }
 
-        // correct withdrawal from fee account
+        // incorrect mint provided
         {
             let (
                 token_a_key,
@@ -4202,278 +11334,348 @@ mod tests {
                 token_b_key,
                 mut token_b_account,
                 _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
-
-            let pool_fee_key = accounts.pool_fee_key;
-            let mut pool_fee_account = accounts.pool_fee_account.clone();
-            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
-            let pool_fee_amount = fee_account.amount;
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
 
-            accounts
-                .withdraw_all_token_types(
-                    &user_key,
-                    &pool_fee_key,
-                    &mut pool_fee_account,
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    pool_fee_amount,
-                    0,
-                    0,
-                )
-                .unwrap();
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            let results = accounts
-                .swap_curve
-                .calculator
-                .pool_tokens_to_trading_tokens(
-                    pool_fee_amount.try_into().unwrap(),
-                    pool_mint.supply.try_into().unwrap(),
-                    swap_token_a.amount.try_into().unwrap(),
-                    swap_token_b.amount.try_into().unwrap(),
-                    RoundDirection::Floor,
+                    initial_a,
+                    minimum_token_b_amount,
                 )
-                .unwrap();
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(
-                token_a.amount,
-                TryInto::<u64>::try_into(results.token_a_amount).unwrap()
-            );
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(
-                token_b.amount,
-                TryInto::<u64>::try_into(results.token_b_amount).unwrap()
             );
-        }
-    }
-
-    #[test]
-    fn test_deposit_one_exact_in() {
-        let user_key = Pubkey::new_unique();
-        let depositor_key = Pubkey::new_unique();
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 2;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 20;
-        let host_fee_denominator = 100;
-
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
-        };
-
-        let token_a_amount = 1000;
-        let token_b_amount = 9000;
-        let curve_type = CurveType::ConstantProduct;
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Arc::new(ConstantProductCurve {}),
-        };
-
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
 
-        let deposit_a = token_a_amount / 10;
-        let deposit_b = token_b_amount / 10;
-        let pool_amount = to_u64(INITIAL_SWAP_POOL_AMOUNT / 100).unwrap();
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
 
-        // swap not initialized
+        // incorrect fee account provided
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                token_b_key,
+                mut token_b_account,
+                wrong_pool_key,
+                wrong_pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let old_pool_fee_account = accounts.pool_fee_account;
+            let old_pool_fee_key = accounts.pool_fee_key;
+            accounts.pool_fee_account = wrong_pool_account;
+            accounts.pool_fee_key = wrong_pool_key;
             assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+                Err(SwapError::IncorrectFeeAccount.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    initial_a,
+                    minimum_token_b_amount,
                 )
             );
+            accounts.pool_fee_account = old_pool_fee_account;
+            accounts.pool_fee_key = old_pool_fee_key;
         }
 
-        accounts.initialize_swap().unwrap();
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let user_transfer_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    swap(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_key,
+                        &token_a_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        None,
+                        Swap {
+                            amount_in: initial_a,
+                            minimum_amount_out: minimum_token_b_amount,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                ),
+            );
+        }
 
-        // wrong owner for swap account
+        // output token value 0
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+                Err(SwapError::ZeroTradingTokens.into()),
+                accounts.swap(
+                    &swapper_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &swap_token_b_key,
+                    &swap_token_a_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
+                    1,
+                    1,
                 )
             );
-            accounts.swap_account = old_swap_account;
         }
 
-        // wrong bump seed for authority_key
+        // slippage exceeded: minimum out amount too high
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    initial_a,
+                    minimum_token_b_amount * 2,
                 )
             );
-            accounts.authority_key = old_authority;
         }
 
-        // not enough token A / B
+        // invalid input: can't use swap pool as user source / dest
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a / 2,
-                deposit_b / 2,
-                0,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            let authority_key = accounts.authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.swap(
+                    &authority_key,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    initial_a,
+                    minimum_token_b_amount,
+                )
             );
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
             assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+                Err(SwapError::InvalidInput.into()),
+                accounts.swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    0,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    initial_a,
+                    minimum_token_b_amount,
                 )
             );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+        }
+
+        // still correct: constraint specified, no host fee account
+        {
+            let authority_key = accounts.authority_key;
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
+            let owner_key = &swapper_key.to_string();
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types: &[],
+                fees: &fees,
+            });
+            do_process_instruction_with_fee_constraints(
+                swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &spl_token::id(),
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.authority_key,
+                    &token_a_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
                     &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_b,
-                    0,
+                    &accounts.pool_mint_key,
+                    &accounts.pool_fee_key,
+                    &accounts.token_a_mint_key,
+                    &accounts.token_b_mint_key,
+                    None,
+                    Swap {
+                        amount_in: initial_a,
+                        minimum_amount_out: minimum_token_b_amount,
+                    },
                 )
-            );
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut token_a_account,
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut token_b_account,
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.pool_fee_account,
+                    &mut accounts.token_a_mint_account,
+                    &mut accounts.token_b_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+                &constraints,
+            )
+            .unwrap();
         }
 
-        // wrong pool token account
+        // invalid mint for host fee account
         {
+            let authority_key = accounts.authority_key;
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
                 _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
-        }
-
-        // no approval
-        {
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
             let (
-                token_a_key,
-                mut token_a_account,
+                bad_token_a_key,
+                mut bad_token_a_account,
                 _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let user_transfer_authority_key = Pubkey::new_unique();
+                mut _token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
+            let owner_key = &swapper_key.to_string();
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+            };
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types: &[],
+                fees: &fees,
+            });
             assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    deposit_single_token_type_exact_amount_in(
+                Err(SwapError::IncorrectPoolMint.into()),
+                do_process_instruction_with_fee_constraints(
+                    swap(
                         &SWAP_PROGRAM_ID,
                         &spl_token::id(),
+                        &spl_token::id(),
+                        &spl_token::id(),
                         &accounts.swap_key,
                         &accounts.authority_key,
-                        &user_transfer_authority_key,
+                        &accounts.authority_key,
                         &token_a_key,
                         &accounts.token_a_key,
                         &accounts.token_b_key,
+                        &token_b_key,
                         &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositSingleTokenTypeExactAmountIn {
-                            source_token_amount: deposit_a,
-                            minimum_pool_token_amount: pool_amount,
+                        &accounts.pool_fee_key,
+                        &accounts.token_a_mint_key,
+                        &accounts.token_b_mint_key,
+                        Some(&bad_token_a_key),
+                        Swap {
+                            amount_in: initial_a,
+                            minimum_amount_out: 0,
                         },
                     )
                     .unwrap(),
@@ -4484,302 +11686,438 @@ mod tests {
                         &mut token_a_account,
                         &mut accounts.token_a_account,
                         &mut accounts.token_b_account,
+                        &mut token_b_account,
                         &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
-                )
-            );
-        }
-
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    deposit_single_token_type_exact_amount_in(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositSingleTokenTypeExactAmountIn {
-                            source_token_amount: deposit_a,
-                            minimum_pool_token_amount: pool_amount,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut accounts.token_b_mint_account,
                         &mut Account::default(),
                         &mut Account::default(),
-                        &mut token_a_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
                         &mut Account::default(),
+                        &mut bad_token_a_account,
                     ],
-                )
+                    &constraints,
+                ),
             );
         }
+    }
+
+    #[test]
+    fn test_overdraw_offset_curve() {
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 10;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 30;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 30;
+        let host_fee_numerator = 10;
+        let host_fee_denominator = 100;
+
+        let token_a_amount = 1_000_000_000;
+        let token_b_amount = 0;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let token_b_offset = 2_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Offset,
+            calculator: Arc::new(OffsetCurve { token_b_offset }),
+        };
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        accounts.initialize_swap().unwrap();
+
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let initial_a = 500_000;
+        let initial_b = 1_000;
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+
+        // swap a to b way, fails, there's no liquidity
+        let a_to_b_amount = initial_a;
+        let minimum_token_b_amount = 0;
+
+        assert_eq!(
+            Err(SwapError::ZeroTradingTokens.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                minimum_token_b_amount,
+            )
+        );
+
+        // swap b to a, succeeds at offset price
+        let b_to_a_amount = initial_b;
+        let minimum_token_a_amount = 0;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                b_to_a_amount,
+                minimum_token_a_amount,
+            )
+            .unwrap();
+
+        // try a to b again, succeeds due to new liquidity
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                minimum_token_b_amount,
+            )
+            .unwrap();
+
+        // try a to b again, fails due to no more liquidity
+        assert_eq!(
+            Err(SwapError::ZeroTradingTokens.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                minimum_token_b_amount,
+            )
+        );
 
-        // wrong swap token accounts
+        // Try to deposit, fails because deposits are not allowed for offset
+        // curve swaps
         {
+            let initial_a = 100;
+            let initial_b = 100;
+            let pool_amount = 100;
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
-                token_b_account,
+                mut token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
-
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
-
-            // wrong swap token a account
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
+                Err(SwapError::UnsupportedCurveOperation.into()),
+                accounts.deposit_all_token_types(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
                     &pool_key,
                     &mut pool_account,
-                    deposit_a,
                     pool_amount,
+                    initial_a,
+                    initial_b,
                 )
             );
+        }
+    }
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
+    #[test]
+    fn test_withdraw_all_offset_curve() {
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 10;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 30;
+        let owner_withdraw_fee_numerator = 0;
+        let owner_withdraw_fee_denominator = 30;
+        let host_fee_numerator = 10;
+        let host_fee_denominator = 100;
 
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+        let token_a_amount = 1_000_000_000;
+        let token_b_amount = 10;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
 
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account;
+        let token_b_offset = 2_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Offset,
+            calculator: Arc::new(OffsetCurve { token_b_offset }),
+        };
+        let total_pool = swap_curve.calculator.new_pool_supply();
+        let user_key = Pubkey::new_unique();
+        let withdrawer_key = Pubkey::new_unique();
 
-            // wrong swap token b account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
 
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
-        }
+        accounts.initialize_swap().unwrap();
 
-        // wrong mint
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
 
-            assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
+        let pool_key = accounts.pool_token_key;
+        let mut pool_account = accounts.pool_token_account.clone();
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
-        }
+        // WithdrawAllTokenTypes takes all tokens for A and B.
+        // The curve's calculation for token B will say to transfer
+        // `token_b_offset + token_b_amount`, but only `token_b_amount` will be
+        // moved.
+        accounts
+            .withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                total_pool.try_into().unwrap(),
+                0,
+                0,
+            )
+            .unwrap();
 
-        // slippage exceeded
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            // minimum pool amount too high
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a / 10,
-                    pool_amount,
-                )
-            );
-            // minimum pool amount too high
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_b / 10,
-                    pool_amount,
-                )
-            );
-        }
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, token_a_amount);
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, token_b_amount);
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(swap_token_a.amount, 0);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_b.amount, 0);
+    }
+
+    #[test]
+    fn test_withdraw_all_constant_price_curve() {
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 10;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 30;
+        let owner_withdraw_fee_numerator = 0;
+        let owner_withdraw_fee_denominator = 30;
+        let host_fee_numerator = 10;
+        let host_fee_denominator = 100;
+
+        // initialize "unbalanced", so that withdrawing all will have some issues
+        // A: 1_000_000_000
+        // B: 2_000_000_000 (1_000 * 2_000_000)
+        let swap_token_a_amount = 1_000_000_000;
+        let swap_token_b_amount = 1_000;
+        let token_b_price = 2_000_000;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+        };
+
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve { token_b_price }),
+        };
+        let total_pool = swap_curve.calculator.new_pool_supply();
+        let user_key = Pubkey::new_unique();
+        let withdrawer_key = Pubkey::new_unique();
+
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees,
+            swap_curve,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        );
+
+        accounts.initialize_swap().unwrap();
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+
+        let pool_key = accounts.pool_token_key;
+        let mut pool_account = accounts.pool_token_account.clone();
 
-        // invalid input: can't use swap pool tokens as source
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            let authority_key = accounts.authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &authority_key,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-            );
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.deposit_single_token_type_exact_amount_in(
-                    &authority_key,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_b,
-                    pool_amount,
-                )
-            );
-        }
+        // WithdrawAllTokenTypes will not take all token A and B, since their
+        // ratio is unbalanced.  It will try to take 1_500_000_000 worth of
+        // each token, which means 1_500_000_000 token A, and 750 token B.
+        // With no slippage, this will leave 250 token B in the pool.
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                total_pool.try_into().unwrap(),
+                swap_token_a_amount,
+                swap_token_b_amount,
+            )
+        );
 
-        // correctly deposit
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            accounts
-                .deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_a,
-                    pool_amount,
-                )
-                .unwrap();
+        accounts
+            .withdraw_all_token_types(
+                &user_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                total_pool.try_into().unwrap(),
+                0,
+                0,
+            )
+            .unwrap();
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, swap_token_a_amount);
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, 750);
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(swap_token_a.amount, 0);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_b.amount, 250);
 
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, 0);
+        // deposit now, not enough to cover the tokens already in there
+        let token_b_amount = 10;
+        let token_a_amount = token_b_amount * token_b_price;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(
+            &user_key,
+            &withdrawer_key,
+            token_a_amount,
+            token_b_amount,
+            0,
+        );
 
-            accounts
-                .deposit_single_token_type_exact_amount_in(
-                    &depositor_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    deposit_b,
-                    pool_amount,
-                )
-                .unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.deposit_all_token_types(
+                &withdrawer_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1, // doesn't matter
+                token_a_amount,
+                token_b_amount,
+            )
+        );
 
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 0);
+        // deposit enough tokens, success!
+        let token_b_amount = 125;
+        let token_a_amount = token_b_amount * token_b_price;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(
+            &user_key,
+            &withdrawer_key,
+            token_a_amount,
+            token_b_amount,
+            0,
+        );
 
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-            let swap_pool_account =
-                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            assert_eq!(
-                pool_mint.supply,
-                pool_account.amount + swap_pool_account.amount
-            );
-        }
+        accounts
+            .deposit_all_token_types(
+                &withdrawer_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1, // doesn't matter
+                token_a_amount,
+                token_b_amount,
+            )
+            .unwrap();
     }
 
     #[test]
-    fn test_withdraw_one_exact_out() {
-        let user_key = Pubkey::new_unique();
+    fn test_deposits_allowed_single_token() {
         let trade_fee_numerator = 1;
-        let trade_fee_denominator = 2;
+        let trade_fee_denominator = 10;
         let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 7;
+        let owner_trade_fee_denominator = 30;
+        let owner_withdraw_fee_numerator = 0;
+        let owner_withdraw_fee_denominator = 30;
+        let host_fee_numerator = 10;
         let host_fee_denominator = 100;
 
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 0;
         let fees = Fees {
             trade_fee_numerator,
             trade_fee_denominator,
@@ -4791,646 +12129,1241 @@ mod tests {
             host_fee_denominator,
         };
 
-        let token_a_amount = 100_000;
-        let token_b_amount = 200_000;
-        let curve_type = CurveType::ConstantProduct;
+        let token_b_offset = 2_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Offset,
+            calculator: Arc::new(OffsetCurve { token_b_offset }),
+        };
+        let creator_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+
+        let mut accounts = SwapAccountInfo::new(
+            &creator_key,
+            fees,
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+
+        accounts.initialize_swap().unwrap();
+
+        let initial_a = 1_000_000;
+        let initial_b = 2_000_000;
+        let (
+            _depositor_token_a_key,
+            _depositor_token_a_account,
+            depositor_token_b_key,
+            mut depositor_token_b_account,
+            depositor_pool_key,
+            mut depositor_pool_account,
+        ) = accounts.setup_token_accounts(&creator_key, &depositor_key, initial_a, initial_b, 0);
+
+        assert_eq!(
+            Err(SwapError::UnsupportedCurveOperation.into()),
+            accounts.deposit_single_token_type_exact_amount_in(
+                &depositor_key,
+                &depositor_token_b_key,
+                &mut depositor_token_b_account,
+                &depositor_pool_key,
+                &mut depositor_pool_account,
+                initial_b,
+                0,
+            )
+        );
+    }
+
+    #[test]
+    fn test_swap_with_transfer_fees() {
+        let fees = Fees {
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 1,
+            owner_trade_fee_numerator: 0,
+            owner_trade_fee_denominator: 1,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
         let swap_curve = SwapCurve {
-            curve_type,
+            curve_type: CurveType::ConstantProduct,
             calculator: Arc::new(ConstantProductCurve {}),
         };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
 
-        let withdrawer_key = Pubkey::new_unique();
-        let initial_a = token_a_amount / 10;
-        let initial_b = token_b_amount / 10;
-        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
-        let maximum_pool_token_amount = to_u64(initial_pool / 4).unwrap();
-        let destination_a_amount = initial_a / 40;
-        let destination_b_amount = initial_b / 40;
+        // both trading tokens withhold 1% on every transfer
+        let mut accounts = SwapAccountInfo::new_with_token_programs(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+            &spl_token::id(),
+            &spl_token_2022::id(),
+            &spl_token_2022::id(),
+            100,
+        );
+        accounts.initialize_swap().unwrap();
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        let initial_a = 100_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
 
-        // swap not initialized
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
+        // every token must come with the token program owning it
+        let (token_a_mint_key, mut token_a_mint_account) =
+            accounts.get_token_mint(&swap_token_a_key);
+        let (token_b_mint_key, mut token_b_mint_account) =
+            accounts.get_token_mint(&swap_token_b_key);
+        let mut swap_token_a_account = accounts.token_a_account.clone();
+        let mut swap_token_b_account = accounts.token_b_account.clone();
+        assert_eq!(
+            Err(SwapError::IncorrectTokenProgramId.into()),
+            do_process_instruction(
+                swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &spl_token_2022::id(),
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &swapper_key,
                     &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &accounts.pool_mint_key,
+                    &accounts.pool_fee_key,
+                    &token_a_mint_key,
+                    &token_b_mint_key,
+                    None,
+                    Swap {
+                        amount_in: 1_000,
+                        minimum_amount_out: 0,
+                    },
                 )
-            );
-        }
-
-        accounts.initialize_swap().unwrap();
-
-        // wrong owner for swap account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
-            assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
                     &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-            accounts.swap_account = old_swap_account;
-        }
-
-        // wrong bump seed for authority_key
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
+                    &mut swap_token_a_account,
+                    &mut swap_token_b_account,
                     &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-            accounts.authority_key = old_authority;
-        }
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.pool_fee_account,
+                    &mut token_a_mint_account,
+                    &mut token_b_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        );
 
-        // not enough pool tokens
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount / 1000,
-            );
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-        }
+        // only 9_900 of the 10_000 sent reaches the pool, and the user only
+        // receives 99% of what leaves it
+        let amount_in = 10_000;
+        let results = swap_curve
+            .swap(
+                9_900,
+                token_a_amount.into(),
+                token_b_amount.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let curve_amount_out: u64 = results.destination_amount_swapped.try_into().unwrap();
+        let amount_received = curve_amount_out - (curve_amount_out + 99) / 100;
 
-        // wrong pool token account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                maximum_pool_token_amount,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-        }
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                amount_in,
+                curve_amount_out,
+            )
+        );
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                amount_in,
+                amount_received,
+            )
+            .unwrap();
 
-        // wrong pool fee account
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                wrong_pool_key,
-                wrong_pool_account,
-            ) = accounts.setup_token_accounts(
+        let token_a =
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&token_a_account.data)
+                .unwrap();
+        assert_eq!(token_a.base.amount, initial_a - amount_in);
+        let token_b =
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&token_b_account.data)
+                .unwrap();
+        assert_eq!(token_b.base.amount, amount_received);
+        let swap_token_a = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &accounts.token_a_account.data,
+        )
+        .unwrap();
+        assert_eq!(
+            u128::from(swap_token_a.base.amount),
+            u128::from(token_a_amount) + results.source_amount_swapped
+        );
+
+        // withdrawing an exact amount delivers that amount after the fee
+        let destination_token_amount = 1_000;
+        let pool_key = accounts.pool_token_key;
+        let mut pool_account = accounts.pool_token_account.clone();
+        let maximum_pool_token_amount = spl_token::state::Account::unpack(&pool_account.data)
+            .unwrap()
+            .amount;
+        accounts
+            .withdraw_single_token_type_exact_amount_out(
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
+                &pool_key,
+                &mut pool_account,
+                &token_b_key,
+                &mut token_b_account,
+                destination_token_amount,
                 maximum_pool_token_amount,
-            );
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
+            )
+            .unwrap();
+        let token_b =
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&token_b_account.data)
+                .unwrap();
+        assert_eq!(
+            token_b.base.amount,
+            amount_received + destination_token_amount
+        );
+    }
+
+    #[test]
+    fn test_swap_exact_amount_out_with_transfer_fees() {
+        let fees = Fees {
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 1,
+            owner_trade_fee_numerator: 0,
+            owner_trade_fee_denominator: 1,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+
+        // both trading tokens withhold 1% on every transfer
+        let mut accounts = SwapAccountInfo::new_with_token_programs(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+            &spl_token::id(),
+            &spl_token_2022::id(),
+            &spl_token_2022::id(),
+            100,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let initial_a = 100_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        // the pool sends 10_000 so that 9_900 arrives, and the user sends
+        // enough for the curve's source amount to arrive after the fee
+        let amount_out = 9_900;
+        let results = swap_curve
+            .swap_exact_out(
+                10_000,
+                token_a_amount.into(),
+                token_b_amount.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let curve_amount_in: u64 = results.source_amount_swapped.try_into().unwrap();
+
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.swap_exact_amount_out(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                amount_out,
+                curve_amount_in,
+            )
+        );
+        accounts
+            .swap_exact_amount_out(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                amount_out,
                 initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let old_pool_fee_account = accounts.pool_fee_account;
-            let old_pool_fee_key = accounts.pool_fee_key;
-            accounts.pool_fee_account = wrong_pool_account;
-            accounts.pool_fee_key = wrong_pool_key;
-            assert_eq!(
-                Err(SwapError::IncorrectFeeAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-            accounts.pool_fee_account = old_pool_fee_account;
-            accounts.pool_fee_key = old_pool_fee_key;
-        }
+            )
+            .unwrap();
 
-        // no approval
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                0,
-                0,
-                maximum_pool_token_amount,
+        let token_a =
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&token_a_account.data)
+                .unwrap();
+        let amount_sent = initial_a - token_a.base.amount;
+        assert_eq!(amount_sent - (amount_sent + 99) / 100, curve_amount_in);
+        let token_b =
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&token_b_account.data)
+                .unwrap();
+        assert_eq!(token_b.base.amount, amount_out);
+        let swap_token_a = StateWithExtensions::<spl_token_2022::state::Account>::unpack(
+            &accounts.token_a_account.data,
+        )
+        .unwrap();
+        assert_eq!(
+            u128::from(swap_token_a.base.amount),
+            u128::from(token_a_amount) + results.source_amount_swapped
+        );
+    }
+
+    #[test]
+    fn test_quote() {
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+            host_fee_numerator: 20,
+            host_fee_denominator: 100,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+
+        // both trading tokens withhold 1% on every transfer
+        let mut accounts = SwapAccountInfo::new_with_token_programs(
+            &user_key,
+            fees,
+            swap_curve,
+            1_000_000_000,
+            5_000_000_000,
+            &spl_token::id(),
+            &spl_token_2022::id(),
+            &spl_token_2022::id(),
+            100,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let initial_a = 1_000_000;
+        let initial_b = 1_000_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let amount = |account: &Account| {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+                .unwrap()
+                .base
+                .amount
+        };
+        let check_swap_reserves = |accounts: &SwapAccountInfo, quote_result: &QuoteResult| {
+            assert_eq!(
+                amount(&accounts.token_a_account),
+                quote_result.new_token_a_amount
             );
-            let user_transfer_authority_key = Pubkey::new_unique();
             assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    withdraw_single_token_type_exact_amount_out(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        WithdrawSingleTokenTypeExactAmountOut {
-                            destination_token_amount: destination_a_amount,
-                            maximum_pool_token_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.pool_fee_account,
-                        &mut Account::default(),
-                    ],
-                )
+                amount(&accounts.token_b_account),
+                quote_result.new_token_b_amount
             );
-        }
+        };
+
+        // swap, quoted without changing the swap
+        let swap_account = accounts.swap_account.clone();
+        let quote_result = accounts
+            .quote(QuoteOperation::Swap, TradeDirection::AtoB, 100_000)
+            .unwrap();
+        assert_eq!(accounts.swap_account, swap_account);
+        assert!(quote_result.trade_fee > 0 && quote_result.host_fee > 0);
+        let pool_fee_amount = amount(&accounts.pool_fee_account);
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                100_000,
+                quote_result.amount_out,
+            )
+            .unwrap();
+        assert_eq!(amount(&token_a_account), initial_a - quote_result.amount_in);
+        assert_eq!(
+            amount(&token_b_account),
+            initial_b + quote_result.amount_out
+        );
+        assert_eq!(
+            amount(&accounts.pool_fee_account),
+            pool_fee_amount + quote_result.owner_fee
+        );
+        check_swap_reserves(&accounts, &quote_result);
+
+        // exact out swap
+        let token_a_amount = amount(&token_a_account);
+        let token_b_amount = amount(&token_b_account);
+        let quote_result = accounts
+            .quote(
+                QuoteOperation::SwapExactAmountOut,
+                TradeDirection::BtoA,
+                10_000,
+            )
+            .unwrap();
+        assert_eq!(quote_result.amount_out, 10_000);
+        let pool_fee_amount = amount(&accounts.pool_fee_account);
+        accounts
+            .swap_exact_amount_out(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                10_000,
+                quote_result.amount_in,
+            )
+            .unwrap();
+        assert_eq!(
+            amount(&token_b_account),
+            token_b_amount - quote_result.amount_in
+        );
+        assert_eq!(amount(&token_a_account), token_a_amount + 10_000);
+        assert_eq!(
+            amount(&accounts.pool_fee_account),
+            pool_fee_amount + quote_result.owner_fee
+        );
+        check_swap_reserves(&accounts, &quote_result);
+
+        // single token deposit
+        let token_a_amount = amount(&token_a_account);
+        let quote_result = accounts
+            .quote(
+                QuoteOperation::DepositSingleTokenTypeExactAmountIn,
+                TradeDirection::AtoB,
+                50_000,
+            )
+            .unwrap();
+        assert!(quote_result.trade_fee > 0 && quote_result.owner_fee == 0);
+        accounts
+            .deposit_single_token_type_exact_amount_in(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &pool_key,
+                &mut pool_account,
+                50_000,
+                quote_result.amount_out,
+            )
+            .unwrap();
+        assert_eq!(amount(&token_a_account), token_a_amount - 50_000);
+        assert_eq!(amount(&pool_account), quote_result.amount_out);
+        check_swap_reserves(&accounts, &quote_result);
+
+        // single token withdrawal
+        let token_b_amount = amount(&token_b_account);
+        let pool_token_amount = amount(&pool_account);
+        let quote_result = accounts
+            .quote(
+                QuoteOperation::WithdrawSingleTokenTypeExactAmountOut,
+                TradeDirection::BtoA,
+                20_000,
+            )
+            .unwrap();
+        assert!(quote_result.owner_fee > 0);
+        let pool_fee_amount = amount(&accounts.pool_fee_account);
+        accounts
+            .withdraw_single_token_type_exact_amount_out(
+                &swapper_key,
+                &pool_key,
+                &mut pool_account,
+                &token_b_key,
+                &mut token_b_account,
+                20_000,
+                quote_result.amount_in,
+            )
+            .unwrap();
+        assert_eq!(
+            amount(&pool_account),
+            pool_token_amount - quote_result.amount_in
+        );
+        assert_eq!(amount(&token_b_account), token_b_amount + 20_000);
+        assert_eq!(
+            amount(&accounts.pool_fee_account),
+            pool_fee_amount + quote_result.owner_fee
+        );
+        check_swap_reserves(&accounts, &quote_result);
+
+        // quotes fail like the operation they quote
+        assert_eq!(
+            Err(SwapError::ZeroTradingTokens.into()),
+            accounts.quote(QuoteOperation::Swap, TradeDirection::AtoB, 0)
+        );
 
-        // wrong token program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    withdraw_single_token_type_exact_amount_out(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        WithdrawSingleTokenTypeExactAmountOut {
-                            destination_token_amount: destination_a_amount,
-                            maximum_pool_token_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.pool_fee_account,
-                        &mut Account::default(),
-                    ],
+        // wrong swap token accounts
+        assert_eq!(
+            Err(SwapError::IncorrectSwapAccount.into()),
+            do_process_instruction(
+                quote(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &accounts.token_b_key,
+                    &accounts.token_a_key,
+                    &accounts.pool_mint_key,
+                    &accounts.token_a_mint_key,
+                    &accounts.token_b_mint_key,
+                    Quote {
+                        operation: QuoteOperation::Swap,
+                        trade_direction: TradeDirection::AtoB,
+                        amount: 1_000,
+                    },
                 )
-            );
-        }
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut accounts.token_b_account,
+                    &mut accounts.token_a_account,
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.token_a_mint_account,
+                    &mut accounts.token_b_mint_account,
+                ],
+            )
+        );
+    }
 
-        // wrong swap token accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
+    // Routes `amount_in` through each (swap, swap source, swap destination)
+    // leg in order, writing the legs' accounts back on success
+    #[allow(clippy::too_many_arguments)]
+    fn do_route_swap(
+        user_key: &Pubkey,
+        source_key: &Pubkey,
+        source_account: &mut Account,
+        destination_key: &Pubkey,
+        destination_account: &mut Account,
+        legs: &mut [(&mut SwapAccountInfo, Pubkey, Pubkey)],
+        amount_in: u64,
+        minimum_amount_out: u64,
+    ) -> ProgramResult {
+        let user_transfer_key = Pubkey::new_unique();
+        let source_token_program_id = *legs[0].0.get_token_program_id(&legs[0].1);
+        // approve moving from user source account
+        do_process_instruction(
+            approve(
+                &source_token_program_id,
+                source_key,
+                &user_transfer_key,
+                user_key,
+                &[],
+                amount_in,
+            )
+            .unwrap(),
+            vec![
+                source_account,
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
 
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
+        let route_legs = legs
+            .iter()
+            .map(
+                |(swap, swap_source_key, swap_destination_key)| RouteSwapLeg {
+                    swap_pubkey: swap.swap_key,
+                    authority_pubkey: swap.authority_key,
+                    swap_source_pubkey: *swap_source_key,
+                    swap_destination_pubkey: *swap_destination_key,
+                    pool_mint_pubkey: swap.pool_mint_key,
+                    pool_fee_pubkey: swap.pool_fee_key,
+                    source_mint_pubkey: swap.get_token_mint(swap_source_key).0,
+                    destination_mint_pubkey: swap.get_token_mint(swap_destination_key).0,
+                    source_token_program_id: *swap.get_token_program_id(swap_source_key),
+                    destination_token_program_id: *swap.get_token_program_id(swap_destination_key),
+                    pool_token_program_id: swap.pool_token_program_id,
+                },
+            )
+            .collect::<Vec<_>>();
+        let mut leg_accounts = legs
+            .iter()
+            .map(|(swap, swap_source_key, swap_destination_key)| {
+                vec![
+                    swap.swap_account.clone(),
+                    Account::default(),
+                    swap.get_token_account(swap_source_key).clone(),
+                    swap.get_token_account(swap_destination_key).clone(),
+                    swap.pool_mint_account.clone(),
+                    swap.pool_fee_account.clone(),
+                    swap.get_token_mint(swap_source_key).1,
+                    swap.get_token_mint(swap_destination_key).1,
+                    Account::default(),
+                    Account::default(),
+                    Account::default(),
+                ]
+            })
+            .collect::<Vec<_>>();
 
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
+        let mut user_transfer_account = Account::default();
+        let mut accounts = vec![
+            &mut user_transfer_account,
+            source_account,
+            destination_account,
+        ];
+        accounts.extend(leg_accounts.iter_mut().flatten());
+        do_process_instruction(
+            route_swap(
+                &SWAP_PROGRAM_ID,
+                &user_transfer_key,
+                source_key,
+                destination_key,
+                &route_legs,
+                RouteSwap {
+                    amount_in,
+                    minimum_amount_out,
+                },
+            )
+            .unwrap(),
+            accounts,
+        )?;
 
-            // wrong swap token a account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
+        for ((swap, swap_source_key, swap_destination_key), accounts) in
+            legs.iter_mut().zip(leg_accounts)
+        {
+            swap.swap_account = accounts[0].clone();
+            swap.set_token_account(swap_source_key, accounts[2].clone());
+            swap.set_token_account(swap_destination_key, accounts[3].clone());
+            swap.pool_mint_account = accounts[4].clone();
+            swap.pool_fee_account = accounts[5].clone();
+        }
+        Ok(())
+    }
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
+    #[test]
+    fn test_route_swap() {
+        let fees = Fees {
+            trade_fee_numerator: 25,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 5,
+            owner_trade_fee_denominator: 10_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 2_000_000;
+        let token_c_amount = 4_000_000;
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
 
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+        // an A/B pool, and a B/C pool whose token A is the first pool's token B
+        let mut first = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+        );
+        let mut second = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_b_amount,
+            token_c_amount,
+        );
+        let (second_token_a_key, second_token_a_account) = mint_token(
+            &spl_token::id(),
+            &first.token_b_mint_key,
+            &mut first.token_b_mint_account,
+            &user_key,
+            &second.authority_key,
+            token_b_amount,
+        );
+        second.token_a_key = second_token_a_key;
+        second.token_a_account = second_token_a_account;
+        second.token_a_mint_key = first.token_b_mint_key;
+        second.token_a_mint_account = first.token_b_mint_account.clone();
+        first.initialize_swap().unwrap();
+        second.initialize_swap().unwrap();
+
+        let initial_a = 100_000;
+        let (token_a_key, mut token_a_account, _, _, _, _) =
+            first.setup_token_accounts(&user_key, &swapper_key, initial_a, 0, 0);
+        let (_, _, token_c_key, mut token_c_account, _, _) =
+            second.setup_token_accounts(&user_key, &swapper_key, 0, 0, 0);
+        let first_token_a_key = first.token_a_key;
+        let first_token_b_key = first.token_b_key;
+        let second_token_b_key = second.token_b_key;
+
+        let amount_in = 10_000;
+        let first_results = swap_curve
+            .swap(
+                amount_in.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let second_results = swap_curve
+            .swap(
+                first_results.destination_amount_swapped,
+                token_b_amount.into(),
+                token_c_amount.into(),
+                TradeDirection::AtoB,
+                &fees,
+            )
+            .unwrap();
+        let amount_out: u64 = second_results
+            .destination_amount_swapped
+            .try_into()
+            .unwrap();
 
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+        // legs must connect through the same mint
+        assert_eq!(
+            Err(SwapError::InvalidInput.into()),
+            do_route_swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_c_key,
+                &mut token_c_account,
+                &mut [
+                    (&mut first, first_token_a_key, first_token_b_key),
+                    (&mut second, second_token_b_key, second_token_a_key),
+                ],
+                amount_in,
+                0,
+            )
+        );
 
-            // wrong swap token b account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
+        // slippage is only checked on the final amount
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            do_route_swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_c_key,
+                &mut token_c_account,
+                &mut [
+                    (&mut first, first_token_a_key, first_token_b_key),
+                    (&mut second, second_token_a_key, second_token_b_key),
+                ],
+                amount_in,
+                amount_out + 1,
+            )
+        );
 
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
-        }
+        do_route_swap(
+            &swapper_key,
+            &token_a_key,
+            &mut token_a_account,
+            &token_c_key,
+            &mut token_c_account,
+            &mut [
+                (&mut first, first_token_a_key, first_token_b_key),
+                (&mut second, second_token_a_key, second_token_b_key),
+            ],
+            amount_in,
+            amount_out,
+        )
+        .unwrap();
 
-        // wrong mint
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, initial_a - amount_in);
+        let token_c = spl_token::state::Account::unpack(&token_c_account.data).unwrap();
+        assert_eq!(token_c.amount, amount_out);
 
-            assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
+        // the intermediate token moves straight from the first pool into the
+        // second one
+        let first_token_b = spl_token::state::Account::unpack(&first.token_b_account.data).unwrap();
+        assert_eq!(
+            u128::from(first_token_b.amount),
+            u128::from(token_b_amount) - first_results.destination_amount_swapped
+        );
+        let second_token_a =
+            spl_token::state::Account::unpack(&second.token_a_account.data).unwrap();
+        assert_eq!(
+            u128::from(second_token_a.amount),
+            u128::from(token_b_amount) + first_results.destination_amount_swapped
+        );
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
-        }
+        // both pools collect their owner fees
+        let first_fee = spl_token::state::Account::unpack(&first.pool_fee_account.data).unwrap();
+        assert!(first_fee.amount > 0);
+        let second_fee = spl_token::state::Account::unpack(&second.pool_fee_account.data).unwrap();
+        assert!(second_fee.amount > 0);
+    }
 
-        // slippage exceeded
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
+    #[test]
+    fn test_price_observation() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 1,
+            owner_trade_fee_numerator: 0,
+            owner_trade_fee_denominator: 1,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 4_000_000;
+        let one = 1u128 << PRICE_FRACTIONAL_BITS;
 
-            // maximum pool token amount too low
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount / 1000,
-                )
-            );
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount / 1000,
-                )
-            );
-        }
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(1_000));
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+        let start = SwapVersion::unpack(&accounts.swap_account.data)
+            .unwrap()
+            .price_observation()
+            .unwrap();
+        assert_eq!(
+            start,
+            PriceObservation {
+                price_a_cumulative: 0,
+                price_b_cumulative: 0,
+                timestamp: 1_000,
+            }
+        );
 
-        // invalid input: can't use swap pool tokens as destination
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
-                )
-            );
-        }
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, token_a_amount, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
 
-        // correct withdrawal
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
+        // the swap accumulates the balances held before it, 4 B per A
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(1_100));
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                token_a_amount,
+                0,
+            )
+            .unwrap();
+        let after_swap = SwapVersion::unpack(&accounts.swap_account.data)
+            .unwrap()
+            .price_observation()
+            .unwrap();
+        assert_eq!(
+            after_swap.time_weighted_average_prices(&start),
+            Some((4 * one, one / 4))
+        );
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        // the swap brings the price down to 1 B per A for the next 300 seconds
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_a.amount, 2 * token_a_amount);
+        assert_eq!(swap_token_b.amount, token_b_amount / 2);
+        let end = SwapVersion::unpack(&accounts.swap_account.data)
+            .unwrap()
+            .price_observation()
+            .unwrap()
+            .accumulate(swap_token_a.amount, swap_token_b.amount, 1_400);
+        assert_eq!(
+            end.time_weighted_average_prices(&start),
+            Some((
+                (4 * one * 100 + one * 300) / 400,
+                (one / 4 * 100 + one * 300) / 400
+            ))
+        );
+    }
 
-            let pool_token_amount = accounts
-                .swap_curve
-                .withdraw_single_token_type_exact_out(
-                    destination_a_amount.try_into().unwrap(),
-                    swap_token_a.amount.try_into().unwrap(),
-                    swap_token_b.amount.try_into().unwrap(),
-                    pool_mint.supply.try_into().unwrap(),
-                    TradeDirection::AtoB,
-                    &accounts.fees,
-                )
-                .unwrap();
-            let withdraw_fee = accounts.fees.owner_withdraw_fee(pool_token_amount).unwrap();
+    #[test]
+    fn test_swap_v1_account() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 30,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
 
-            accounts
-                .withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
-                )
-                .unwrap();
+        // accounts sized for the original layout still initialize and swap,
+        // without price accumulators
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.swap_account = Account::new(0, SwapVersion::V1_LEN, &SWAP_PROGRAM_ID);
+        accounts.initialize_swap().unwrap();
+        assert_eq!(accounts.swap_account.data[0], 1);
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 1_000, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let swap_data = accounts.swap_account.data.clone();
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+            )
+            .unwrap();
+        assert_eq!(accounts.swap_account.data, swap_data);
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.price_observation(), None);
+    }
 
-            assert_eq!(swap_token_a.amount, token_a_amount - destination_a_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, initial_a + destination_a_amount);
+    #[test]
+    fn test_update_fees() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 30,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 30,
+            host_fee_numerator: 20,
+            host_fee_denominator: 100,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let new_fees = Fees {
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1000,
+            ..fees.clone()
+        };
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees.clone(), swap_curve, 1_000_000, 1_000_000);
+        accounts.initialize_swap().unwrap();
+
+        // admin not owning the pool fee account
+        {
+            let wrong_admin_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                do_process_instruction(
+                    update_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &wrong_admin_key,
+                        &accounts.pool_fee_key,
+                        UpdateFees {
+                            fees: new_fees.clone()
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                    ],
+                )
+            );
+        }
 
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+        // admin not signing
+        {
+            let mut instruction = update_fees(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &accounts.pool_fee_key,
+                UpdateFees {
+                    fees: new_fees.clone(),
+                },
+            )
+            .unwrap();
+            instruction.accounts[1].is_signer = false;
             assert_eq!(
-                pool_account.amount,
-                to_u64(initial_pool - pool_token_amount - withdraw_fee).unwrap()
+                Err(ProgramError::MissingRequiredSignature),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                    ],
+                )
             );
-            let fee_account =
-                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
-            assert_eq!(fee_account.amount, to_u64(withdraw_fee).unwrap());
         }
 
-        // correct withdrawal from fee account
+        // wrong pool fee account
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-
-            let fee_a_amount = 2;
-            let pool_fee_key = accounts.pool_fee_key;
-            let mut pool_fee_account = accounts.pool_fee_account.clone();
-            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
-            let pool_fee_amount = fee_account.amount;
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let (wrong_fee_key, mut wrong_fee_account) = mint_token(
+                &accounts.pool_token_program_id,
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                0,
+            );
+            assert_eq!(
+                Err(SwapError::IncorrectFeeAccount.into()),
+                do_process_instruction(
+                    update_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &wrong_fee_key,
+                        UpdateFees {
+                            fees: new_fees.clone()
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut wrong_fee_account,
+                    ],
+                )
+            );
+        }
 
-            let token_a_amount = swap_token_a.amount;
-            accounts
-                .withdraw_single_token_type_exact_amount_out(
-                    &user_key,
-                    &pool_fee_key,
-                    &mut pool_fee_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    fee_a_amount,
-                    pool_fee_amount,
+        // invalid fees
+        {
+            let invalid_fees = Fees {
+                trade_fee_numerator: 11,
+                trade_fee_denominator: 10,
+                ..fees.clone()
+            };
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                do_process_instruction(
+                    update_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        UpdateFees { fees: invalid_fees },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                    ],
                 )
-                .unwrap();
+            );
+        }
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        // fees above the update bounds
+        for excessive_fees in [
+            Fees {
+                trade_fee_numerator: 1_001,
+                trade_fee_denominator: 10_000,
+                ..new_fees.clone()
+            },
+            Fees {
+                owner_trade_fee_numerator: 1,
+                owner_trade_fee_denominator: 2,
+                ..new_fees.clone()
+            },
+            Fees {
+                owner_withdraw_fee_numerator: 1,
+                owner_withdraw_fee_denominator: 2,
+                ..new_fees.clone()
+            },
+        ] {
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                do_process_instruction(
+                    update_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        UpdateFees {
+                            fees: excessive_fees
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                    ],
+                )
+            );
+        }
 
-            assert_eq!(swap_token_a.amount, token_a_amount - fee_a_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, initial_a + fee_a_amount);
+        // fees below the constraints
+        {
+            let owner_key_str = &user_key.to_string();
+            let valid_curve_types = &[CurveType::ConstantProduct];
+            let constraints = Some(SwapConstraints {
+                owner_key: owner_key_str,
+                valid_curve_types,
+                fees: &fees,
+            });
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                do_process_instruction_with_fee_constraints(
+                    update_fees(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        UpdateFees {
+                            fees: new_fees.clone()
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                    ],
+                    &constraints,
+                )
+            );
         }
+
+        // correctly update fees
+        do_process_instruction(
+            update_fees(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &accounts.pool_fee_key,
+                UpdateFees {
+                    fees: new_fees.clone(),
+                },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut accounts.pool_fee_account,
+            ],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*swap_state.fees(), new_fees);
     }
 
-    fn check_valid_swap_curve(
-        fees: Fees,
-        curve_type: CurveType,
-        calculator: Arc<dyn CurveCalculator + Send + Sync>,
-        token_a_amount: u64,
-        token_b_amount: u64,
-    ) {
+    #[test]
+    fn test_set_paused() {
         let user_key = Pubkey::new_unique();
         let swapper_key = Pubkey::new_unique();
-
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 30,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
         let swap_curve = SwapCurve {
-            curve_type,
-            calculator,
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
         };
-
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
         let mut accounts = SwapAccountInfo::new(
             &user_key,
             fees.clone(),
@@ -5438,1538 +13371,2125 @@ mod tests {
             token_a_amount,
             token_b_amount,
         );
-        let initial_a = token_a_amount / 5;
-        let initial_b = token_b_amount / 5;
         accounts.initialize_swap().unwrap();
 
-        let swap_token_a_key = accounts.token_a_key;
-        let swap_token_b_key = accounts.token_b_key;
-
         let (
             token_a_key,
             mut token_a_account,
             token_b_key,
             mut token_b_account,
-            _pool_key,
-            _pool_account,
-        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-        // swap one way
-        let a_to_b_amount = initial_a / 10;
-        let minimum_token_b_amount = 0;
-        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-        let initial_supply = pool_mint.supply;
-        accounts
-            .swap(
-                &swapper_key,
-                &token_a_key,
-                &mut token_a_account,
-                &swap_token_a_key,
-                &swap_token_b_key,
-                &token_b_key,
-                &mut token_b_account,
-                a_to_b_amount,
-                minimum_token_b_amount,
-            )
-            .unwrap();
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 1_000, 1_000, 10_000_000);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
 
-        let results = swap_curve
-            .swap(
-                a_to_b_amount.try_into().unwrap(),
-                token_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                TradeDirection::AtoB,
-                &fees,
+        do_process_instruction(
+            set_paused(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &accounts.pool_fee_key,
+                SetPaused { is_paused: true },
             )
-            .unwrap();
-
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        let token_a_amount = swap_token_a.amount;
-        assert_eq!(
-            token_a_amount,
-            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
-        );
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, initial_a - a_to_b_amount);
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut accounts.pool_fee_account,
+            ],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.is_paused());
 
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        let token_b_amount = swap_token_b.amount;
-        assert_eq!(
-            token_b_amount,
-            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
-        );
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        // swaps and deposits are refused
         assert_eq!(
-            token_b.amount,
-            initial_b + to_u64(results.destination_amount_swapped).unwrap()
-        );
-
-        let first_fee = swap_curve
-            .withdraw_single_token_type_exact_out(
-                results.owner_fee,
-                token_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                initial_supply.try_into().unwrap(),
-                TradeDirection::AtoB,
-                &fees,
+            Err(SwapError::SwapPaused.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
             )
-            .unwrap();
-        let fee_account =
-            spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+        );
         assert_eq!(
-            fee_account.amount,
-            TryInto::<u64>::try_into(first_fee).unwrap()
+            Err(SwapError::SwapPaused.into()),
+            accounts.deposit_all_token_types(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                10,
+                1_000,
+                1_000,
+            )
         );
 
-        let first_swap_amount = results.destination_amount_swapped;
-
-        // swap the other way
-        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-        let initial_supply = pool_mint.supply;
-
-        let b_to_a_amount = initial_b / 10;
-        let minimum_a_amount = 0;
+        // withdrawals still go through
         accounts
-            .swap(
+            .withdraw_all_token_types(
                 &swapper_key,
-                &token_b_key,
-                &mut token_b_account,
-                &swap_token_b_key,
-                &swap_token_a_key,
+                &pool_key,
+                &mut pool_account,
                 &token_a_key,
                 &mut token_a_account,
-                b_to_a_amount,
-                minimum_a_amount,
+                &token_b_key,
+                &mut token_b_account,
+                10_000_000,
+                0,
+                0,
             )
             .unwrap();
 
-        let results = swap_curve
+        do_process_instruction(
+            set_paused(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &accounts.pool_fee_key,
+                SetPaused { is_paused: false },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut accounts.pool_fee_account,
+            ],
+        )
+        .unwrap();
+        accounts
             .swap(
-                b_to_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                token_a_amount.try_into().unwrap(),
-                TradeDirection::BtoA,
-                &fees,
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
             )
             .unwrap();
 
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        let token_a_amount = swap_token_a.amount;
-        assert_eq!(
-            token_a_amount,
-            TryInto::<u64>::try_into(results.new_swap_destination_amount).unwrap()
-        );
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(
-            token_a.amount,
-            initial_a - a_to_b_amount + to_u64(results.destination_amount_swapped).unwrap()
-        );
-
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        let token_b_amount = swap_token_b.amount;
-        assert_eq!(
-            token_b_amount,
-            TryInto::<u64>::try_into(results.new_swap_source_amount).unwrap()
-        );
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        // accounts in the original layout cannot be paused
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.swap_account = Account::new(0, SwapVersion::V1_LEN, &SWAP_PROGRAM_ID);
+        accounts.initialize_swap().unwrap();
         assert_eq!(
-            token_b.amount,
-            initial_b + to_u64(first_swap_amount).unwrap()
-                - to_u64(results.source_amount_swapped).unwrap()
-        );
-
-        let second_fee = swap_curve
-            .withdraw_single_token_type_exact_out(
-                results.owner_fee,
-                token_a_amount.try_into().unwrap(),
-                token_b_amount.try_into().unwrap(),
-                initial_supply.try_into().unwrap(),
-                TradeDirection::BtoA,
-                &fees,
+            Err(SwapError::UnsupportedSwapVersion.into()),
+            do_process_instruction(
+                set_paused(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    &accounts.pool_fee_key,
+                    SetPaused { is_paused: true },
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.pool_fee_account,
+                ],
             )
-            .unwrap();
-        let fee_account =
-            spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
-        assert_eq!(fee_account.amount, to_u64(first_fee + second_fee).unwrap());
-    }
-
-    #[test]
-    fn test_valid_swap_curves_all_fees() {
-        // All fees
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 10;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 30;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 30;
-        let host_fee_numerator = 20;
-        let host_fee_denominator = 100;
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
-        };
-
-        let token_a_amount = 10_000_000_000;
-        let token_b_amount = 50_000_000_000;
-
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantProduct,
-            Arc::new(ConstantProductCurve {}),
-            token_a_amount,
-            token_b_amount,
-        );
-        let token_b_price = 1;
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantPrice,
-            Arc::new(ConstantPriceCurve { token_b_price }),
-            token_a_amount,
-            token_b_amount,
-        );
-        let token_b_offset = 10_000_000_000;
-        check_valid_swap_curve(
-            fees,
-            CurveType::Offset,
-            Arc::new(OffsetCurve { token_b_offset }),
-            token_a_amount,
-            token_b_amount,
         );
     }
 
     #[test]
-    fn test_valid_swap_curves_trade_fee_only() {
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 10;
-        let owner_trade_fee_numerator = 0;
-        let owner_trade_fee_denominator = 0;
-        let owner_withdraw_fee_numerator = 0;
-        let owner_withdraw_fee_denominator = 0;
-        let host_fee_numerator = 0;
-        let host_fee_denominator = 0;
+    fn test_ramp_amp() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
         let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 1,
+            owner_trade_fee_numerator: 0,
+            owner_trade_fee_denominator: 1,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
         };
-
-        let token_a_amount = 10_000_000_000;
-        let token_b_amount = 50_000_000_000;
-
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantProduct,
-            Arc::new(ConstantProductCurve {}),
-            token_a_amount,
-            token_b_amount,
-        );
-        let token_b_price = 10_000;
-        check_valid_swap_curve(
-            fees.clone(),
-            CurveType::ConstantPrice,
-            Arc::new(ConstantPriceCurve { token_b_price }),
-            token_a_amount,
-            token_b_amount / token_b_price,
-        );
-        let token_b_offset = 1;
-        check_valid_swap_curve(
-            fees,
-            CurveType::Offset,
-            Arc::new(OffsetCurve { token_b_offset }),
-            token_a_amount,
-            token_b_amount,
-        );
-    }
-
-    #[test]
-    fn test_valid_swap_with_fee_constraints() {
-        let owner_key = Pubkey::new_unique();
-
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 10;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 30;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 30;
-        let host_fee_numerator = 10;
-        let host_fee_denominator = 100;
-
         let token_a_amount = 1_000_000;
-        let token_b_amount = 5_000_000;
+        let token_b_amount = 1_000_000;
+        let day = 86_400;
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(0));
 
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
-        };
+        // only stable curves have an amp
+        {
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::ConstantProduct,
+                calculator: Arc::new(ConstantProductCurve {}),
+            };
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            accounts.initialize_swap().unwrap();
+            assert_eq!(
+                Err(SwapError::UnsupportedCurveOperation.into()),
+                do_process_instruction(
+                    ramp_amp(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        RampAmp {
+                            target_amp: 200,
+                            stop_timestamp: 2 * day,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                    ],
+                )
+            );
+        }
 
-        let curve = ConstantProductCurve {};
         let swap_curve = SwapCurve {
-            curve_type: CurveType::ConstantProduct,
-            calculator: Arc::new(curve),
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
         };
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
 
-        let owner_key_str = &owner_key.to_string();
-        let valid_curve_types = &[CurveType::ConstantProduct];
-        let constraints = Some(SwapConstraints {
-            owner_key: owner_key_str,
-            valid_curve_types,
-            fees: &fees,
-        });
-        let mut accounts = SwapAccountInfo::new(
-            &owner_key,
-            fees.clone(),
-            swap_curve,
-            token_a_amount,
-            token_b_amount,
-        );
+        // amp changing too much, or too fast
+        for (target_amp, stop_timestamp) in [(1_001, 2 * day), (200, day - 1)] {
+            assert_eq!(
+                Err(SwapError::InvalidCurve.into()),
+                do_process_instruction(
+                    ramp_amp(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        RampAmp {
+                            target_amp,
+                            stop_timestamp,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                    ],
+                )
+            );
+        }
 
-        // initialize swap
-        do_process_instruction_with_fee_constraints(
-            initialize(
+        do_process_instruction(
+            ramp_amp(
                 &SWAP_PROGRAM_ID,
-                &spl_token::id(),
                 &accounts.swap_key,
-                &accounts.authority_key,
-                &accounts.token_a_key,
-                &accounts.token_b_key,
-                &accounts.pool_mint_key,
+                &user_key,
                 &accounts.pool_fee_key,
-                &accounts.pool_token_key,
-                accounts.fees.clone(),
-                accounts.swap_curve.clone(),
+                RampAmp {
+                    target_amp: 200,
+                    stop_timestamp: 2 * day,
+                },
             )
             .unwrap(),
             vec![
                 &mut accounts.swap_account,
                 &mut Account::default(),
-                &mut accounts.token_a_account,
-                &mut accounts.token_b_account,
-                &mut accounts.pool_mint_account,
                 &mut accounts.pool_fee_account,
-                &mut accounts.pool_token_account,
-                &mut Account::default(),
             ],
-            &constraints,
         )
         .unwrap();
 
-        let authority_key = accounts.authority_key;
-
+        // halfway through the ramp, a swap moves the curve along
         let (
             token_a_key,
             mut token_a_account,
             token_b_key,
             mut token_b_account,
-            pool_key,
-            mut pool_account,
-        ) = accounts.setup_token_accounts(
-            &owner_key,
-            &authority_key,
-            token_a_amount,
-            token_b_amount,
-            0,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 2_000, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(day));
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                1_000,
+                0,
+            )
+            .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(
+            *swap_state.swap_curve(),
+            SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(StableCurve { amp: 150 }),
+            }
         );
 
-        let amount_in = token_a_amount / 2;
-        let minimum_amount_out = 0;
-
-        // perform the swap
-        do_process_instruction_with_fee_constraints(
-            swap(
-                &SWAP_PROGRAM_ID,
-                &spl_token::id(),
-                &accounts.swap_key,
-                &accounts.authority_key,
-                &accounts.authority_key,
+        // the ramp ends at the target amp
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(3 * day));
+        accounts
+            .swap(
+                &swapper_key,
                 &token_a_key,
-                &accounts.token_a_key,
-                &accounts.token_b_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
                 &token_b_key,
-                &accounts.pool_mint_key,
-                &accounts.pool_fee_key,
-                Some(&pool_key),
-                Swap {
-                    amount_in,
-                    minimum_amount_out,
-                },
+                &mut token_b_account,
+                1_000,
+                0,
             )
-            .unwrap(),
-            vec![
-                &mut accounts.swap_account,
-                &mut Account::default(),
-                &mut Account::default(),
+            .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(
+            *swap_state.swap_curve(),
+            SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(StableCurve { amp: 200 }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_dynamic_fee() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 30,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 0,
+            owner_trade_fee_denominator: 1,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let half_life = 600;
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(0));
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+        let set_fee = |accounts: &mut SwapAccountInfo, instruction: SetDynamicFee| {
+            do_process_instruction(
+                set_dynamic_fee(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    &accounts.pool_fee_key,
+                    instruction,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.pool_fee_account,
+                ],
+            )
+        };
+        let dynamic_fee = SetDynamicFee {
+            min_trade_fee_numerator: 10,
+            max_trade_fee_numerator: 100,
+            max_volatility: 50_000,
+            volatility_half_life: half_life,
+        };
+
+        // fees out of order, reaching the trade fee denominator, or above the
+        // trade fee an admin can set
+        for (min_trade_fee_numerator, max_trade_fee_numerator) in
+            [(100, 10), (10, 10_000), (10, 1_001), (9_999, 9_999)]
+        {
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                set_fee(
+                    &mut accounts,
+                    SetDynamicFee {
+                        min_trade_fee_numerator,
+                        max_trade_fee_numerator,
+                        ..dynamic_fee.clone()
+                    },
+                )
+            );
+        }
+
+        // a calm pool starts at the minimum fee
+        set_fee(&mut accounts, dynamic_fee.clone()).unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.fees().trade_fee_numerator, 10);
+        assert_eq!(swap_state.dynamic_fee().unwrap().volatility, 0);
+
+        // a swap moving the price adds to the volatility
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 10_000, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
                 &mut token_a_account,
-                &mut accounts.token_a_account,
-                &mut accounts.token_b_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
                 &mut token_b_account,
-                &mut accounts.pool_mint_account,
-                &mut accounts.pool_fee_account,
-                &mut Account::default(),
-                &mut pool_account,
-            ],
-            &constraints,
+                10_000,
+                0,
+            )
+            .unwrap();
+        let amount = |account: &Account| {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&account.data)
+                .unwrap()
+                .base
+                .amount
+        };
+        let volatility = relative_price_change(
+            token_a_amount,
+            token_b_amount,
+            amount(&accounts.token_a_account),
+            amount(&accounts.token_b_account),
+        );
+        assert!(volatility > 19_000 && volatility < 21_000);
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        let stored_fee = swap_state.dynamic_fee().unwrap();
+        assert_eq!(stored_fee.volatility, volatility);
+
+        // the next trades pay a higher fee, going back down as the
+        // volatility decays
+        let amount_in = 100_000;
+        for (elapsed, volatility) in [
+            (0, volatility),
+            (2 * half_life as i64, volatility / 4),
+            (half_life as i64 / 2, volatility - volatility / 4),
+            (64 * half_life as i64, 0),
+        ] {
+            TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(elapsed));
+            let trade_fee_numerator = stored_fee.trade_fee_numerator(volatility);
+            let quote_result = accounts
+                .quote(QuoteOperation::Swap, TradeDirection::BtoA, amount_in)
+                .unwrap();
+            assert_eq!(
+                quote_result.trade_fee,
+                amount_in * trade_fee_numerator / 10_000
+            );
+        }
+        assert_eq!(
+            stored_fee.trade_fee_numerator(volatility),
+            10 + 90 * volatility / 50_000
+        );
+
+        // disabling the dynamic fee keeps the last trade fee
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(0));
+        accounts
+            .swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                1_000,
+                0,
+            )
+            .unwrap();
+        set_fee(
+            &mut accounts,
+            SetDynamicFee {
+                max_volatility: 0,
+                ..dynamic_fee
+            },
         )
         .unwrap();
-
-        // check that fees were taken in the host fee account
-        let host_fee_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-        let owner_fee_account =
-            spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
-        let total_fee = owner_fee_account.amount * host_fee_denominator
-            / (host_fee_denominator - host_fee_numerator);
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.dynamic_fee(), None);
         assert_eq!(
-            total_fee,
-            host_fee_account.amount + owner_fee_account.amount
+            swap_state.fees().trade_fee_numerator,
+            stored_fee.trade_fee_numerator(volatility)
         );
     }
 
-    #[test]
-    fn test_invalid_swap() {
-        let user_key = Pubkey::new_unique();
-        let swapper_key = Pubkey::new_unique();
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 4;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 9;
-        let host_fee_denominator = 100;
+    #[test]
+    fn test_initialize_canonical_pool() {
+        let user_key = Pubkey::new_unique();
+        let payer_key = Pubkey::new_unique();
         let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1_000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1_000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
         };
-
-        let token_a_amount = 1000;
-        let token_b_amount = 5000;
-        let curve_type = CurveType::ConstantProduct;
+        let fee_tier = 3_000;
         let swap_curve = SwapCurve {
-            curve_type,
+            curve_type: CurveType::ConstantProduct,
             calculator: Arc::new(ConstantProductCurve {}),
         };
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-
-        let initial_a = token_a_amount / 5;
-        let initial_b = token_b_amount / 5;
-        let minimum_token_b_amount = initial_b / 2;
+        let token_a_amount = 1_000;
+        let token_b_amount = 2_000;
+        let mut accounts = SwapAccountInfo::new_canonical(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+            fee_tier,
+        );
+        let payer_balance = 1_000_000_000;
+        let mut payer_account = Account::new(payer_balance, 0, &system_program::id());
+        let rent = Rent::default().minimum_balance(SwapVersion::LATEST_LEN);
 
-        let swap_token_a_key = accounts.token_a_key;
-        let swap_token_b_key = accounts.token_b_key;
+        // the mints may be given in either order
+        assert_eq!(
+            registry::find_pool_address(
+                &SWAP_PROGRAM_ID,
+                &accounts.token_b_mint_key,
+                &accounts.token_a_mint_key,
+                CurveType::ConstantProduct,
+                fee_tier,
+            )
+            .0,
+            accounts.swap_key
+        );
 
-        // swap not initialized
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_token_b_amount,
-                )
-            );
-        }
+        // fee tier not matching the trade fee
+        assert_eq!(
+            Err(SwapError::InvalidFee.into()),
+            accounts.initialize_canonical_swap(&payer_key, &mut payer_account, 500)
+        );
 
-        accounts.initialize_swap().unwrap();
+        // fee tier matching its fees, but not the pool address
+        accounts.fees.trade_fee_numerator = 5;
+        accounts.fees.trade_fee_denominator = 10_000;
+        assert_eq!(
+            Err(SwapError::InvalidProgramAddress.into()),
+            accounts.initialize_canonical_swap(&payer_key, &mut payer_account, 500)
+        );
+        accounts.fees = fees.clone();
 
-        // wrong swap account program id
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
-            assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_token_b_amount,
-                )
-            );
-            accounts.swap_account = old_swap_account;
-        }
+        // curve type not matching the pool address
+        accounts.swap_curve = SwapCurve {
+            curve_type: CurveType::Offset,
+            calculator: Arc::new(OffsetCurve {
+                token_b_offset: 1_000,
+            }),
+        };
+        assert_eq!(
+            Err(SwapError::InvalidProgramAddress.into()),
+            accounts.initialize_canonical_swap(&payer_key, &mut payer_account, fee_tier)
+        );
+        accounts.swap_curve = swap_curve.clone();
 
-        // wrong bump seed
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_token_b_amount,
-                )
-            );
-            accounts.authority_key = old_authority;
-        }
+        // mints out of order
+        std::mem::swap(&mut accounts.token_a_key, &mut accounts.token_b_key);
+        std::mem::swap(&mut accounts.token_a_account, &mut accounts.token_b_account);
+        assert_eq!(
+            Err(SwapError::InvalidInput.into()),
+            accounts.initialize_canonical_swap(&payer_key, &mut payer_account, fee_tier)
+        );
+        std::mem::swap(&mut accounts.token_a_key, &mut accounts.token_b_key);
+        std::mem::swap(&mut accounts.token_a_account, &mut accounts.token_b_account);
 
-        // wrong token program id
+        // payer must sign for the pool account
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let wrong_program_id = Pubkey::new_unique();
+            let mut instruction = initialize_canonical(
+                &SWAP_PROGRAM_ID,
+                &accounts.pool_token_program_id,
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &accounts.pool_mint_key,
+                &accounts.pool_fee_key,
+                &accounts.pool_token_key,
+                &payer_key,
+                fees.clone(),
+                swap_curve.clone(),
+                fee_tier,
+            )
+            .unwrap();
+            instruction.accounts[8].is_signer = false;
             assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
+                Err(ProgramError::MissingRequiredSignature),
                 do_process_instruction(
-                    swap(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_program_id,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        None,
-                        Swap {
-                            amount_in: initial_a,
-                            minimum_amount_out: minimum_token_b_amount,
-                        },
-                    )
-                    .unwrap(),
+                    instruction,
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
                         &mut accounts.token_a_account,
                         &mut accounts.token_b_account,
-                        &mut token_b_account,
                         &mut accounts.pool_mint_account,
                         &mut accounts.pool_fee_account,
+                        &mut accounts.pool_token_account,
+                        &mut Account::default(),
+                        &mut payer_account,
                         &mut Account::default(),
                     ],
-                ),
-            );
-        }
-
-        // not enough token a to swap
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a * 2,
-                    minimum_token_b_amount * 2,
                 )
             );
         }
 
-        // wrong swap token A / B accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let user_transfer_key = Pubkey::new_unique();
+        accounts
+            .initialize_canonical_swap(&payer_key, &mut payer_account, fee_tier)
+            .unwrap();
+        assert_eq!(accounts.swap_account.owner, SWAP_PROGRAM_ID);
+        assert_eq!(accounts.swap_account.daltons, rent);
+        assert_eq!(payer_account.daltons, payer_balance - rent);
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.is_initialized());
+        assert_eq!(*swap_state.token_a_mint(), accounts.token_a_mint_key);
+        assert_eq!(*swap_state.token_b_mint(), accounts.token_b_mint_key);
+        assert_eq!(*swap_state.fees(), fees);
+        assert_eq!(swap_state.fee_tier(), Some(fee_tier));
+
+        // only one pool per pair, curve type and fee tier
+        assert_eq!(
+            Err(SwapError::AlreadyInUse.into()),
+            accounts.initialize_canonical_swap(&payer_key, &mut payer_account, fee_tier)
+        );
+
+        // the admin cannot move the trade fee away from the fee tier
+        let update = |accounts: &mut SwapAccountInfo, fees: Fees| {
+            do_process_instruction(
+                update_fees(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    &accounts.pool_fee_key,
+                    UpdateFees { fees },
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.pool_fee_account,
+                ],
+            )
+        };
+        assert_eq!(
+            Err(SwapError::InvalidFee.into()),
+            update(
+                &mut accounts,
+                Fees {
+                    trade_fee_numerator: 100,
+                    trade_fee_denominator: 1_000,
+                    ..fees.clone()
+                }
+            )
+        );
+        for (min_trade_fee_numerator, max_trade_fee_numerator) in [(3, 100), (1, 3)] {
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
+                Err(SwapError::InvalidFee.into()),
                 do_process_instruction(
-                    swap(
+                    set_dynamic_fee(
                         &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
                         &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_key,
-                        &token_a_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &token_b_key,
-                        &accounts.pool_mint_key,
+                        &user_key,
                         &accounts.pool_fee_key,
-                        None,
-                        Swap {
-                            amount_in: initial_a,
-                            minimum_amount_out: minimum_token_b_amount,
+                        SetDynamicFee {
+                            min_trade_fee_numerator,
+                            max_trade_fee_numerator,
+                            max_volatility: 50_000,
+                            volatility_half_life: 600,
                         },
                     )
                     .unwrap(),
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account.clone(),
-                        &mut token_a_account,
-                        &mut token_b_account.clone(),
-                        &mut token_b_account,
-                        &mut accounts.pool_mint_account,
                         &mut accounts.pool_fee_account,
-                        &mut Account::default(),
                     ],
-                ),
-            );
-        }
-
-        // wrong user token A / B accounts
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    initial_a,
-                    minimum_token_b_amount,
                 )
             );
         }
+        // but may change the other fees, or restate the same trade fee
+        update(
+            &mut accounts,
+            Fees {
+                trade_fee_numerator: 30,
+                trade_fee_denominator: 10_000,
+                owner_withdraw_fee_numerator: 1,
+                owner_withdraw_fee_denominator: 1_000,
+                ..fees.clone()
+            },
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.fees().trade_fee_numerator, 30);
+        assert_eq!(swap_state.fee_tier(), Some(fee_tier));
 
-        // swap from a to a
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account.clone(),
-                    &swap_token_a_key,
-                    &swap_token_a_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    initial_a,
-                    minimum_token_b_amount,
+        // daltons sent ahead of time to the address cannot squat the pool
+        let mut accounts = SwapAccountInfo::new_canonical(
+            &user_key,
+            fees,
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+            fee_tier,
+        );
+        accounts.swap_account.daltons = 1_000;
+        let mut payer_account = Account::new(payer_balance, 0, &system_program::id());
+        accounts
+            .initialize_canonical_swap(&payer_key, &mut payer_account, fee_tier)
+            .unwrap();
+        assert_eq!(accounts.swap_account.owner, SWAP_PROGRAM_ID);
+        assert_eq!(accounts.swap_account.daltons, rent);
+        assert_eq!(payer_account.daltons, payer_balance - rent + 1_000);
+        assert!(SwapVersion::is_initialized(&accounts.swap_account.data));
+    }
+
+    #[test]
+    fn test_price_oracle() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 0,
+            owner_trade_fee_denominator: 1,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve { token_b_price: 10 }),
+        };
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(0));
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees.clone(), swap_curve, 10_000_000, 1_000_000);
+        accounts.initialize_swap().unwrap();
+        let set_oracle = |accounts: &mut SwapAccountInfo,
+                          price_key: &Pubkey,
+                          price_account: &mut Account,
+                          instruction: SetPriceOracle| {
+            do_process_instruction(
+                set_price_oracle(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    &accounts.pool_fee_key,
+                    price_key,
+                    instruction,
                 )
-            );
-        }
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.pool_fee_account,
+                    price_account,
+                ],
+            )
+        };
+        let refresh =
+            |accounts: &mut SwapAccountInfo, price_key: &Pubkey, price_account: &mut Account| {
+                do_process_instruction(
+                    refresh_oracle_price(&SWAP_PROGRAM_ID, &accounts.swap_key, price_key).unwrap(),
+                    vec![&mut accounts.swap_account, price_account],
+                )
+            };
+        let record_account = |price: u64, timestamp: UnixTimestamp| {
+            let mut data = vec![1u8; RECORD_PRICE_OFFSET];
+            data.extend_from_slice(&price.to_le_bytes());
+            data.extend_from_slice(&timestamp.to_le_bytes());
+            Account {
+                data,
+                owner: record_program::id(),
+                ..Account::default()
+            }
+        };
+        let amount_out = |accounts: &mut SwapAccountInfo| {
+            accounts
+                .quote(QuoteOperation::Swap, TradeDirection::BtoA, 1_000)
+                .map(|quote_result| quote_result.amount_out)
+        };
+        let price_key = Pubkey::new_unique();
+        let oracle = SetPriceOracle {
+            price_source: PriceSource::Record,
+            max_staleness: 600,
+            max_deviation_bps: 1_000,
+        };
 
-        // incorrect mint provided
-        {
+        // the oracle needs a record holding a price, and reasonable bounds
+        assert_eq!(
+            Err(SwapError::UnsupportedCurveOperation.into()),
+            refresh(&mut accounts, &price_key, &mut record_account(11, 0))
+        );
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            set_oracle(
+                &mut accounts,
+                &price_key,
+                &mut Account::default(),
+                oracle.clone()
+            )
+        );
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            set_oracle(
+                &mut accounts,
+                &price_key,
+                &mut Account {
+                    owner: Pubkey::new_unique(),
+                    ..record_account(11, 0)
+                },
+                oracle.clone()
+            )
+        );
+        assert_eq!(
+            Err(SwapError::InvalidCurve.into()),
+            set_oracle(
+                &mut accounts,
+                &price_key,
+                &mut record_account(11, 0),
+                SetPriceOracle {
+                    max_deviation_bps: 0,
+                    ..oracle.clone()
+                }
+            )
+        );
+        set_oracle(
+            &mut accounts,
+            &price_key,
+            &mut record_account(11, 0),
+            oracle.clone(),
+        )
+        .unwrap();
+
+        // no trading until the first refresh
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 0, 10_000, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        assert_eq!(
+            Err(SwapError::StaleOraclePrice.into()),
+            amount_out(&mut accounts)
+        );
+        assert_eq!(
+            Err(SwapError::StaleOraclePrice.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                1_000,
+                0,
+            )
+        );
+
+        // prices published too long ago, or from another account, are refused
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(1_000));
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            refresh(
+                &mut accounts,
+                &price_key,
+                &mut Account {
+                    owner: Pubkey::new_unique(),
+                    ..record_account(11, 1_000)
+                }
+            )
+        );
+        assert_eq!(
+            Err(SwapError::StaleOraclePrice.into()),
+            refresh(&mut accounts, &price_key, &mut record_account(11, 399))
+        );
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            refresh(
+                &mut accounts,
+                &Pubkey::new_unique(),
+                &mut record_account(11, 1_000)
+            )
+        );
+
+        // the first price replaces the initial one, however far it moves
+        refresh(&mut accounts, &price_key, &mut record_account(11, 900)).unwrap();
+        assert_eq!(amount_out(&mut accounts), Ok(11_000));
+        accounts
+            .swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                1_000,
+                11_000,
+            )
+            .unwrap();
+
+        // later prices move by at most 10%, and older ones are ignored
+        assert_eq!(
+            Err(SwapError::OraclePriceDeviation.into()),
+            refresh(&mut accounts, &price_key, &mut record_account(13, 1_000))
+        );
+        refresh(&mut accounts, &price_key, &mut record_account(12, 1_000)).unwrap();
+        refresh(&mut accounts, &price_key, &mut record_account(11, 950)).unwrap();
+        assert_eq!(amount_out(&mut accounts), Ok(12_000));
+
+        // trading halts once the price is stale, until the feed publishes again
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(1_601));
+        assert_eq!(
+            Err(SwapError::StaleOraclePrice.into()),
+            amount_out(&mut accounts)
+        );
+        assert_eq!(
+            Err(SwapError::StaleOraclePrice.into()),
+            accounts.swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                1_000,
+                0,
+            )
+        );
+        refresh(&mut accounts, &price_key, &mut record_account(12, 1_600)).unwrap();
+        assert_eq!(amount_out(&mut accounts), Ok(12_000));
+
+        // a jump beyond the deviation halts the pool until the admin sets the
+        // oracle again, accepting the next price
+        assert_eq!(
+            Err(SwapError::OraclePriceDeviation.into()),
+            refresh(&mut accounts, &price_key, &mut record_account(15, 1_601))
+        );
+        set_oracle(
+            &mut accounts,
+            &price_key,
+            &mut record_account(15, 1_601),
+            oracle.clone(),
+        )
+        .unwrap();
+        refresh(&mut accounts, &price_key, &mut record_account(15, 1_601)).unwrap();
+        assert_eq!(amount_out(&mut accounts), Ok(15_000));
+
+        // removing the oracle keeps the last price
+        set_oracle(
+            &mut accounts,
+            &price_key,
+            &mut Account::default(),
+            SetPriceOracle {
+                max_staleness: 0,
+                ..oracle.clone()
+            },
+        )
+        .unwrap();
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(10_000));
+        assert_eq!(amount_out(&mut accounts), Ok(15_000));
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.price_oracle(), None);
+
+        // another pool's time-weighted average price of 20 token A per token B
+        let feed_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let mut other_feed = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            feed_curve.clone(),
+            20_000_000,
+            1_000_000,
+        );
+        other_feed.initialize_swap().unwrap();
+        let other_feed_key = other_feed.swap_key;
+        // a feed trading this pool's tokens, reversed or in the same order
+        let new_feed = |accounts: &mut SwapAccountInfo,
+                        is_reversed: bool,
+                        token_a_amount: u64,
+                        token_b_amount: u64| {
+            let mut feed = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                feed_curve.clone(),
+                token_a_amount,
+                token_b_amount,
+            );
+            let (mut mint_a_key, mut mint_a_account, mut mint_b_key, mut mint_b_account) = (
+                accounts.token_a_mint_key,
+                accounts.token_a_mint_account.clone(),
+                accounts.token_b_mint_key,
+                accounts.token_b_mint_account.clone(),
+            );
+            if is_reversed {
+                std::mem::swap(&mut mint_a_key, &mut mint_b_key);
+                std::mem::swap(&mut mint_a_account, &mut mint_b_account);
+            }
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &mint_a_key,
+                &mut mint_a_account,
+                &user_key,
+                &feed.authority_key,
+                token_a_amount,
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &mint_b_key,
+                &mut mint_b_account,
+                &user_key,
+                &feed.authority_key,
+                token_b_amount,
+            );
+            feed.token_a_key = token_a_key;
+            feed.token_a_account = token_a_account;
+            feed.token_a_mint_key = mint_a_key;
+            feed.token_a_mint_account = mint_a_account;
+            feed.token_b_key = token_b_key;
+            feed.token_b_account = token_b_account;
+            feed.token_b_mint_key = mint_b_key;
+            feed.token_b_mint_account = mint_b_account;
+            feed.initialize_swap().unwrap();
+            feed
+        };
+        // a small trade on a feed, recording its price up to now
+        let trade_feed = |feed: &mut SwapAccountInfo| {
             let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                feed_token_a_key,
+                mut feed_token_a_account,
+                feed_token_b_key,
+                mut feed_token_b_account,
                 _pool_key,
                 _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
+            ) = feed.setup_token_accounts(&user_key, &swapper_key, 1_000, 0, 0);
+            let feed_swap_token_a_key = feed.token_a_key;
+            let feed_swap_token_b_key = feed.token_b_key;
+            feed.swap(
+                &swapper_key,
+                &feed_token_a_key,
+                &mut feed_token_a_account,
+                &feed_swap_token_a_key,
+                &feed_swap_token_b_key,
+                &feed_token_b_key,
+                &mut feed_token_b_account,
+                1_000,
+                0,
+            )
+            .unwrap();
+        };
+        let mut feed = new_feed(&mut accounts, false, 20_000_000, 1_000_000);
+        let feed_key = feed.swap_key;
+        assert_eq!(
+            Err(SwapError::UnsupportedCurveOperation.into()),
+            set_oracle(
+                &mut feed,
+                &price_key,
+                &mut record_account(11, 0),
+                oracle.clone()
+            )
+        );
+        let twap_oracle = SetPriceOracle {
+            price_source: PriceSource::SwapTokenB,
+            ..oracle
+        };
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            set_oracle(
+                &mut accounts,
+                &feed_key,
+                &mut Account::default(),
+                twap_oracle.clone()
+            )
+        );
+        // the feed must trade this pool's tokens, in the orientation of its
+        // price source
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            set_oracle(
+                &mut accounts,
+                &other_feed_key,
+                &mut other_feed.swap_account,
+                twap_oracle.clone()
+            )
+        );
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            set_oracle(
+                &mut accounts,
+                &feed_key,
+                &mut feed.swap_account,
+                SetPriceOracle {
+                    price_source: PriceSource::SwapTokenA,
+                    ..twap_oracle.clone()
+                }
+            )
+        );
+        set_oracle(
+            &mut accounts,
+            &feed_key,
+            &mut feed.swap_account,
+            twap_oracle.clone(),
+        )
+        .unwrap();
+
+        // nothing to average until the feed trades again
+        refresh(&mut accounts, &feed_key, &mut feed.swap_account).unwrap();
+        assert_eq!(
+            Err(SwapError::StaleOraclePrice.into()),
+            amount_out(&mut accounts)
+        );
 
-            assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_token_b_amount,
-                )
-            );
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(10_100));
+        trade_feed(&mut feed);
+        refresh(&mut accounts, &feed_key, &mut feed.swap_account).unwrap();
+        assert_eq!(amount_out(&mut accounts), Ok(20_000));
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
-        }
+        // the average price goes stale with the feed
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(10_701));
+        refresh(&mut accounts, &feed_key, &mut feed.swap_account).unwrap();
+        assert_eq!(
+            Err(SwapError::StaleOraclePrice.into()),
+            amount_out(&mut accounts)
+        );
 
-        // incorrect fee account provided
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                wrong_pool_key,
-                wrong_pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let old_pool_fee_account = accounts.pool_fee_account;
-            let old_pool_fee_key = accounts.pool_fee_key;
-            accounts.pool_fee_account = wrong_pool_account;
-            accounts.pool_fee_key = wrong_pool_key;
-            assert_eq!(
-                Err(SwapError::IncorrectFeeAccount.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_token_b_amount,
-                )
-            );
-            accounts.pool_fee_account = old_pool_fee_account;
-            accounts.pool_fee_key = old_pool_fee_key;
-        }
+        // average prices round to the nearest token A, here from 20.6 token A
+        // per token B in a reversed feed
+        let mut reversed_feed = new_feed(&mut accounts, true, 1_000_000, 20_600_000);
+        let reversed_feed_key = reversed_feed.swap_key;
+        set_oracle(
+            &mut accounts,
+            &reversed_feed_key,
+            &mut reversed_feed.swap_account,
+            SetPriceOracle {
+                price_source: PriceSource::SwapTokenA,
+                ..twap_oracle.clone()
+            },
+        )
+        .unwrap();
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(10_800));
+        trade_feed(&mut reversed_feed);
+        refresh(
+            &mut accounts,
+            &reversed_feed_key,
+            &mut reversed_feed.swap_account,
+        )
+        .unwrap();
+        assert_eq!(amount_out(&mut accounts), Ok(21_000));
+
+        // and a feed worth under half a token A per token B is refused
+        let mut cheap_feed = new_feed(&mut accounts, false, 400_000, 1_000_000);
+        let cheap_feed_key = cheap_feed.swap_key;
+        set_oracle(
+            &mut accounts,
+            &cheap_feed_key,
+            &mut cheap_feed.swap_account,
+            twap_oracle,
+        )
+        .unwrap();
+        TEST_UNIX_TIMESTAMP.with(|timestamp| timestamp.set(10_900));
+        trade_feed(&mut cheap_feed);
+        assert_eq!(
+            Err(SwapError::InvalidOracleAccount.into()),
+            refresh(&mut accounts, &cheap_feed_key, &mut cheap_feed.swap_account)
+        );
+    }
 
-        // no approval
+    #[test]
+    fn test_set_fee_account() {
+        let user_key = Pubkey::new_unique();
+        let new_owner_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 30,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 30,
+            host_fee_numerator: 20,
+            host_fee_denominator: 100,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees.clone(), swap_curve, 1_000_000, 1_000_000);
+        accounts.initialize_swap().unwrap();
+        let authority_key = accounts.authority_key;
+        let (new_fee_key, mut new_fee_account) = mint_token(
+            &accounts.pool_token_program_id,
+            &accounts.pool_mint_key,
+            &mut accounts.pool_mint_account,
+            &authority_key,
+            &new_owner_key,
+            0,
+        );
+
+        // new owner cannot take over the pool
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let user_transfer_key = Pubkey::new_unique();
             assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
+                Err(SwapError::InvalidOwner.into()),
                 do_process_instruction(
-                    swap(
+                    set_fee_account(
                         &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
                         &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_b_key,
-                        &accounts.pool_mint_key,
+                        &new_owner_key,
                         &accounts.pool_fee_key,
-                        None,
-                        Swap {
-                            amount_in: initial_a,
-                            minimum_amount_out: minimum_token_b_amount,
-                        },
+                        &new_fee_key,
                     )
                     .unwrap(),
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_b_account,
-                        &mut accounts.pool_mint_account,
                         &mut accounts.pool_fee_account,
-                        &mut Account::default(),
+                        &mut new_fee_account,
                     ],
-                ),
-            );
-        }
-
-        // output token value 0
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(SwapError::ZeroTradingTokens.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &swap_token_b_key,
-                    &swap_token_a_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    1,
-                    1,
                 )
             );
         }
 
-        // slippage exceeded: minimum out amount too high
+        // new fee account not for the pool mint
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
+            let (wrong_mint_key, mut wrong_mint_account) = mint_token(
+                &accounts.token_a_program_id,
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &new_owner_key,
+                0,
+            );
             assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_token_b_amount * 2,
+                Err(SwapError::IncorrectPoolMint.into()),
+                do_process_instruction(
+                    set_fee_account(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        &wrong_mint_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                        &mut wrong_mint_account,
+                    ],
                 )
             );
         }
 
-        // invalid input: can't use swap pool as user source / dest
+        // new fee account owned by the swap authority
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            let authority_key = accounts.authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.swap(
-                    &authority_key,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &token_b_key,
-                    &mut token_b_account,
-                    initial_a,
-                    minimum_token_b_amount,
-                )
+            let (authority_fee_key, mut authority_fee_account) = mint_token(
+                &accounts.pool_token_program_id,
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &authority_key,
+                &authority_key,
+                0,
             );
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
             assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.swap(
-                    &swapper_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &swap_token_a_key,
-                    &swap_token_b_key,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    initial_a,
-                    minimum_token_b_amount,
+                Err(SwapError::InvalidOutputOwner.into()),
+                do_process_instruction(
+                    set_fee_account(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        &authority_fee_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                        &mut authority_fee_account,
+                    ],
                 )
             );
-        }
-
-        // still correct: constraint specified, no host fee account
-        {
-            let authority_key = accounts.authority_key;
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
-            let owner_key = &swapper_key.to_string();
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types: &[],
-                fees: &fees,
-            });
-            do_process_instruction_with_fee_constraints(
-                swap(
+        }
+
+        do_process_instruction(
+            set_fee_account(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &accounts.pool_fee_key,
+                &new_fee_key,
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut accounts.pool_fee_account,
+                &mut new_fee_account,
+            ],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*swap_state.pool_fee_account(), new_fee_key);
+
+        // the previous owner is no longer the admin
+        assert_eq!(
+            Err(SwapError::IncorrectFeeAccount.into()),
+            do_process_instruction(
+                update_fees(
                     &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
                     &accounts.swap_key,
-                    &accounts.authority_key,
-                    &accounts.authority_key,
-                    &token_a_key,
-                    &accounts.token_a_key,
-                    &accounts.token_b_key,
-                    &token_b_key,
-                    &accounts.pool_mint_key,
+                    &user_key,
                     &accounts.pool_fee_key,
-                    None,
-                    Swap {
-                        amount_in: initial_a,
-                        minimum_amount_out: minimum_token_b_amount,
-                    },
+                    UpdateFees { fees: fees.clone() },
                 )
                 .unwrap(),
                 vec![
                     &mut accounts.swap_account,
                     &mut Account::default(),
-                    &mut Account::default(),
-                    &mut token_a_account,
-                    &mut accounts.token_a_account,
-                    &mut accounts.token_b_account,
-                    &mut token_b_account,
-                    &mut accounts.pool_mint_account,
                     &mut accounts.pool_fee_account,
-                    &mut Account::default(),
                 ],
-                &constraints,
             )
-            .unwrap();
-        }
+        );
+        do_process_instruction(
+            update_fees(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &new_owner_key,
+                &new_fee_key,
+                UpdateFees { fees },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut new_fee_account,
+            ],
+        )
+        .unwrap();
+    }
 
-        // invalid mint for host fee account
+    #[test]
+    fn test_flash_loan() {
+        let user_key = Pubkey::new_unique();
+        let borrower_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
+        };
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantProduct,
+            calculator: Arc::new(ConstantProductCurve {}),
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let amount = 100_000;
+        let fee = 300;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve.clone(),
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let receiver_accounts = [AccountMeta::new_readonly(borrower_key, true)];
+        let flash_loan_instruction = |accounts: &SwapAccountInfo, destination_key: &Pubkey| {
+            flash_loan(
+                &SWAP_PROGRAM_ID,
+                &accounts.token_a_program_id,
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.token_a_key,
+                destination_key,
+                &accounts.token_a_mint_key,
+                &FLASH_LOAN_RECEIVER_ID,
+                &receiver_accounts,
+                FlashLoan { amount },
+            )
+            .unwrap()
+        };
+
+        // the receiver only has the loan to pay back, without the fee
         {
-            let authority_key = accounts.authority_key;
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
-            let (
-                bad_token_a_key,
-                mut bad_token_a_account,
                 _token_b_key,
-                mut _token_b_account,
+                _token_b_account,
                 _pool_key,
                 _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &authority_key, initial_a, initial_b, 0);
-            let owner_key = &swapper_key.to_string();
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types: &[],
-                fees: &fees,
-            });
+            ) = accounts.setup_token_accounts(&user_key, &borrower_key, fee - 1, 0, 0);
+            let instruction = flash_loan_instruction(&accounts, &token_a_key);
             assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                do_process_instruction_with_fee_constraints(
-                    swap(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        Some(&bad_token_a_key),
-                        Swap {
-                            amount_in: initial_a,
-                            minimum_amount_out: 0,
-                        },
-                    )
-                    .unwrap(),
+                Err(SwapError::FlashLoanNotRepaid.into()),
+                do_process_instruction(
+                    instruction,
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
                         &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.pool_fee_account,
+                        &mut token_a_account,
+                        &mut accounts.token_a_mint_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
                         &mut Account::default(),
-                        &mut bad_token_a_account,
                     ],
-                    &constraints,
-                ),
+                )
             );
         }
-    }
-
-    #[test]
-    fn test_overdraw_offset_curve() {
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 10;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 30;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 30;
-        let host_fee_numerator = 10;
-        let host_fee_denominator = 100;
-
-        let token_a_amount = 1_000_000_000;
-        let token_b_amount = 0;
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
-        };
-
-        let token_b_offset = 2_000_000;
-        let swap_curve = SwapCurve {
-            curve_type: CurveType::Offset,
-            calculator: Arc::new(OffsetCurve { token_b_offset }),
-        };
-        let user_key = Pubkey::new_unique();
-        let swapper_key = Pubkey::new_unique();
-
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-
-        accounts.initialize_swap().unwrap();
-
-        let swap_token_a_key = accounts.token_a_key;
-        let swap_token_b_key = accounts.token_b_key;
-        let initial_a = 500_000;
-        let initial_b = 1_000;
 
+        // the receiver pays back the loan and the fee, which stays in the pool
         let (
             token_a_key,
             mut token_a_account,
-            token_b_key,
-            mut token_b_account,
+            _token_b_key,
+            _token_b_account,
             _pool_key,
             _pool_account,
-        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-
-        // swap a to b way, fails, there's no liquidity
-        let a_to_b_amount = initial_a;
-        let minimum_token_b_amount = 0;
-
-        assert_eq!(
-            Err(SwapError::ZeroTradingTokens.into()),
-            accounts.swap(
-                &swapper_key,
-                &token_a_key,
-                &mut token_a_account,
-                &swap_token_a_key,
-                &swap_token_b_key,
-                &token_b_key,
-                &mut token_b_account,
-                a_to_b_amount,
-                minimum_token_b_amount,
-            )
-        );
-
-        // swap b to a, succeeds at offset price
-        let b_to_a_amount = initial_b;
-        let minimum_token_a_amount = 0;
-        accounts
-            .swap(
-                &swapper_key,
-                &token_b_key,
-                &mut token_b_account,
-                &swap_token_b_key,
-                &swap_token_a_key,
-                &token_a_key,
+        ) = accounts.setup_token_accounts(&user_key, &borrower_key, fee, 0, 0);
+        let instruction = flash_loan_instruction(&accounts, &token_a_key);
+        do_process_instruction(
+            instruction,
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut accounts.token_a_account,
                 &mut token_a_account,
-                b_to_a_amount,
-                minimum_token_a_amount,
-            )
-            .unwrap();
+                &mut accounts.token_a_mint_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(swap_token_a.amount, token_a_amount + fee);
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, 0);
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(!swap_state.is_locked());
 
-        // try a to b again, succeeds due to new liquidity
-        accounts
-            .swap(
-                &swapper_key,
+        // a locked swap refuses every instruction
+        let mut swap_version = SwapVersion::unpack_version(&accounts.swap_account.data).unwrap();
+        if let SwapVersion::SwapV2(swap_v2) = &mut swap_version {
+            swap_v2.is_locked = true;
+        }
+        SwapVersion::pack(swap_version, &mut accounts.swap_account.data).unwrap();
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &borrower_key, 1_000, 0, 10_000_000);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        assert_eq!(
+            Err(SwapError::SwapLocked.into()),
+            accounts.swap(
+                &borrower_key,
                 &token_a_key,
                 &mut token_a_account,
                 &swap_token_a_key,
                 &swap_token_b_key,
                 &token_b_key,
                 &mut token_b_account,
-                a_to_b_amount,
-                minimum_token_b_amount,
+                1_000,
+                0,
             )
-            .unwrap();
-
-        // try a to b again, fails due to no more liquidity
+        );
         assert_eq!(
-            Err(SwapError::ZeroTradingTokens.into()),
-            accounts.swap(
-                &swapper_key,
+            Err(SwapError::SwapLocked.into()),
+            accounts.withdraw_all_token_types(
+                &borrower_key,
+                &pool_key,
+                &mut pool_account,
                 &token_a_key,
                 &mut token_a_account,
-                &swap_token_a_key,
-                &swap_token_b_key,
                 &token_b_key,
                 &mut token_b_account,
-                a_to_b_amount,
-                minimum_token_b_amount,
+                10_000_000,
+                0,
+                0,
             )
         );
-
-        // Try to deposit, fails because deposits are not allowed for offset
-        // curve swaps
-        {
-            let initial_a = 100;
-            let initial_b = 100;
-            let pool_amount = 100;
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, initial_b, 0);
-            assert_eq!(
-                Err(SwapError::UnsupportedCurveOperation.into()),
-                accounts.deposit_all_token_types(
-                    &swapper_key,
-                    &token_a_key,
+        let instruction = flash_loan_instruction(&accounts, &token_a_key);
+        assert_eq!(
+            Err(SwapError::SwapLocked.into()),
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount,
-                    initial_a,
-                    initial_b,
-                )
-            );
-        }
-    }
-
-    #[test]
-    fn test_withdraw_all_offset_curve() {
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 10;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 30;
-        let owner_withdraw_fee_numerator = 0;
-        let owner_withdraw_fee_denominator = 30;
-        let host_fee_numerator = 10;
-        let host_fee_denominator = 100;
-
-        let token_a_amount = 1_000_000_000;
-        let token_b_amount = 10;
-        let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
-        };
-
-        let token_b_offset = 2_000_000;
-        let swap_curve = SwapCurve {
-            curve_type: CurveType::Offset,
-            calculator: Arc::new(OffsetCurve { token_b_offset }),
-        };
-        let total_pool = swap_curve.calculator.new_pool_supply();
-        let user_key = Pubkey::new_unique();
-        let withdrawer_key = Pubkey::new_unique();
+                    &mut accounts.token_a_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        );
 
+        // accounts in the original layout have no lock
         let mut accounts =
             SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-
+        accounts.swap_account = Account::new(0, SwapVersion::V1_LEN, &SWAP_PROGRAM_ID);
         accounts.initialize_swap().unwrap();
-
         let (
             token_a_key,
             mut token_a_account,
-            token_b_key,
-            mut token_b_account,
+            _token_b_key,
+            _token_b_account,
             _pool_key,
             _pool_account,
-        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
-
-        let pool_key = accounts.pool_token_key;
-        let mut pool_account = accounts.pool_token_account.clone();
-
-        // WithdrawAllTokenTypes takes all tokens for A and B.
-        // The curve's calculation for token B will say to transfer
-        // `token_b_offset + token_b_amount`, but only `token_b_amount` will be
-        // moved.
-        accounts
-            .withdraw_all_token_types(
-                &user_key,
-                &pool_key,
-                &mut pool_account,
-                &token_a_key,
-                &mut token_a_account,
-                &token_b_key,
-                &mut token_b_account,
-                total_pool.try_into().unwrap(),
-                0,
-                0,
+        ) = accounts.setup_token_accounts(&user_key, &borrower_key, fee, 0, 0);
+        let instruction = flash_loan_instruction(&accounts, &token_a_key);
+        assert_eq!(
+            Err(SwapError::UnsupportedSwapVersion.into()),
+            do_process_instruction(
+                instruction,
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut token_a_account,
+                    &mut accounts.token_a_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
             )
-            .unwrap();
-
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, token_a_amount);
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, token_b_amount);
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        assert_eq!(swap_token_a.amount, 0);
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        assert_eq!(swap_token_b.amount, 0);
+        );
     }
 
     #[test]
-    fn test_withdraw_all_constant_price_curve() {
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 10;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 30;
-        let owner_withdraw_fee_numerator = 0;
-        let owner_withdraw_fee_denominator = 30;
-        let host_fee_numerator = 10;
-        let host_fee_denominator = 100;
-
-        // initialize "unbalanced", so that withdrawing all will have some issues
-        // A: 1_000_000_000
-        // B: 2_000_000_000 (1_000 * 2_000_000)
-        let swap_token_a_amount = 1_000_000_000;
-        let swap_token_b_amount = 1_000;
-        let token_b_price = 2_000_000;
+    fn test_multi_stable_swap() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
         let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 100,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
         };
-
-        let swap_curve = SwapCurve {
-            curve_type: CurveType::ConstantPrice,
-            calculator: Arc::new(ConstantPriceCurve { token_b_price }),
+        let amp = 100;
+        let curve = MultiStableCurve { amp };
+        let token_amount = 1_000_000;
+        let token_amount_of = |account: &Account| {
+            spl_token::state::Account::unpack(&account.data)
+                .unwrap()
+                .amount
         };
-        let total_pool = swap_curve.calculator.new_pool_supply();
-        let user_key = Pubkey::new_unique();
-        let withdrawer_key = Pubkey::new_unique();
 
-        let mut accounts = SwapAccountInfo::new(
-            &user_key,
-            fees,
-            swap_curve,
-            swap_token_a_amount,
-            swap_token_b_amount,
-        );
+        // one token, or five, is not a pool
+        for token_count in [1, 5].iter() {
+            let mut accounts = MultiStableSwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                amp,
+                &vec![token_amount; *token_count],
+            );
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.initialize_swap()
+            );
+        }
 
-        accounts.initialize_swap().unwrap();
+        // the amp must be usable by the curve
+        {
+            let mut accounts =
+                MultiStableSwapAccountInfo::new(&user_key, fees.clone(), 0, &[token_amount; 3]);
+            assert_eq!(
+                Err(SwapError::InvalidCurve.into()),
+                accounts.initialize_swap()
+            );
+        }
 
-        let (
-            token_a_key,
-            mut token_a_account,
-            token_b_key,
-            mut token_b_account,
-            _pool_key,
-            _pool_account,
-        ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, 0);
+        // every token needs a reserve
+        {
+            let mut accounts = MultiStableSwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                amp,
+                &[token_amount, 0, token_amount],
+            );
+            assert_eq!(
+                Err(SwapError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+        }
 
-        let pool_key = accounts.pool_token_key;
-        let mut pool_account = accounts.pool_token_account.clone();
+        let mut accounts =
+            MultiStableSwapAccountInfo::new(&user_key, fees.clone(), amp, &[token_amount; 3]);
+        accounts.initialize_swap().unwrap();
+        let swap_state = MultiStableSwap::unpack_account(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.is_initialized);
+        assert_eq!(swap_state.tokens(), &accounts.token_keys[..]);
+        assert_eq!(swap_state.token_mints(), &accounts.token_mint_keys[..]);
+        assert_eq!(swap_state.amp, amp);
+        assert_eq!(
+            token_amount_of(&accounts.pool_token_account),
+            INITIAL_SWAP_POOL_AMOUNT as u64
+        );
+        assert_eq!(
+            Err(SwapError::AlreadyInUse.into()),
+            accounts.initialize_swap()
+        );
 
-        // WithdrawAllTokenTypes will not take all token A and B, since their
-        // ratio is unbalanced.  It will try to take 1_500_000_000 worth of
-        // each token, which means 1_500_000_000 token A, and 750 token B.
-        // With no slippage, this will leave 250 token B in the pool.
+        // swap the first token for the last one
+        let amount_in = 10_000;
+        let (user_token_keys, mut user_token_accounts, _pool_key, _pool_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, &[amount_in, 0, 0], 0);
+        let reserves = accounts.reserves();
+        let trade_fee = fees.trading_fee(amount_in as u128).unwrap();
+        let owner_fee = fees.owner_trading_fee(amount_in as u128).unwrap();
+        let amount_out = curve
+            .swap_without_fees(amount_in as u128 - trade_fee - owner_fee, 0, 2, &reserves)
+            .unwrap() as u64;
+        {
+            let (source_accounts, destination_accounts) = user_token_accounts.split_at_mut(2);
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.swap(
+                    &depositor_key,
+                    0,
+                    &user_token_keys[0],
+                    &mut source_accounts[0],
+                    2,
+                    &user_token_keys[2],
+                    &mut destination_accounts[0],
+                    amount_in,
+                    amount_out + 1,
+                )
+            );
+            accounts
+                .swap(
+                    &depositor_key,
+                    0,
+                    &user_token_keys[0],
+                    &mut source_accounts[0],
+                    2,
+                    &user_token_keys[2],
+                    &mut destination_accounts[0],
+                    amount_in,
+                    amount_out,
+                )
+                .unwrap();
+        }
+        assert_eq!(token_amount_of(&user_token_accounts[0]), 0);
+        assert_eq!(token_amount_of(&user_token_accounts[2]), amount_out);
+        assert_eq!(
+            accounts.reserves(),
+            vec![
+                (token_amount + amount_in) as u128,
+                token_amount as u128,
+                (token_amount - amount_out) as u128
+            ]
+        );
+        // the owner fee is paid in pool tokens
+        assert!(token_amount_of(&accounts.pool_fee_account) > 0);
+
+        // deposit a single token
+        let deposit_amounts = [0, 50_000, 0];
+        let (user_token_keys, mut user_token_accounts, pool_key, mut pool_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, &deposit_amounts, 0);
+        let deposit_amounts_u128 = deposit_amounts
+            .iter()
+            .map(|amount| *amount as u128)
+            .collect::<Vec<_>>();
+        let pool_token_amount = curve
+            .deposit(
+                &deposit_amounts_u128,
+                &accounts.reserves(),
+                accounts.pool_supply(),
+                &fees,
+            )
+            .unwrap() as u64;
         assert_eq!(
             Err(SwapError::ExceededSlippage.into()),
-            accounts.withdraw_all_token_types(
-                &user_key,
+            accounts.deposit(
+                &depositor_key,
+                &user_token_keys,
+                &mut user_token_accounts,
                 &pool_key,
                 &mut pool_account,
-                &token_a_key,
-                &mut token_a_account,
-                &token_b_key,
-                &mut token_b_account,
-                total_pool.try_into().unwrap(),
-                swap_token_a_amount,
-                swap_token_b_amount,
+                &deposit_amounts,
+                pool_token_amount + 1,
             )
         );
-
         accounts
-            .withdraw_all_token_types(
-                &user_key,
+            .deposit(
+                &depositor_key,
+                &user_token_keys,
+                &mut user_token_accounts,
                 &pool_key,
                 &mut pool_account,
-                &token_a_key,
-                &mut token_a_account,
-                &token_b_key,
-                &mut token_b_account,
-                total_pool.try_into().unwrap(),
-                0,
-                0,
+                &deposit_amounts,
+                pool_token_amount,
             )
             .unwrap();
-
-        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, swap_token_a_amount);
-        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, 750);
-        let swap_token_a =
-            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        assert_eq!(swap_token_a.amount, 0);
-        let swap_token_b =
-            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        assert_eq!(swap_token_b.amount, 250);
-
-        // deposit now, not enough to cover the tokens already in there
-        let token_b_amount = 10;
-        let token_a_amount = token_b_amount * token_b_price;
-        let (
-            token_a_key,
-            mut token_a_account,
-            token_b_key,
-            mut token_b_account,
-            pool_key,
-            mut pool_account,
-        ) = accounts.setup_token_accounts(
-            &user_key,
-            &withdrawer_key,
-            token_a_amount,
-            token_b_amount,
-            0,
+        assert_eq!(token_amount_of(&pool_account), pool_token_amount);
+        assert_eq!(token_amount_of(&user_token_accounts[1]), 0);
+
+        // withdraw half of it in proportion to the reserves
+        let withdraw_amount = pool_token_amount / 2;
+        let withdraw_fee = fees.owner_withdraw_fee(withdraw_amount as u128).unwrap();
+        let fee_account_amount = token_amount_of(&accounts.pool_fee_account);
+        let (user_token_keys, mut user_token_accounts, _pool_key, _pool_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, &[0, 0, 0], 0);
+        let token_amounts = curve
+            .withdraw(
+                withdraw_amount as u128 - withdraw_fee,
+                accounts.pool_supply(),
+                &accounts.reserves(),
+            )
+            .unwrap();
+        accounts
+            .withdraw(
+                &depositor_key,
+                &pool_key,
+                &mut pool_account,
+                &user_token_keys,
+                &mut user_token_accounts,
+                withdraw_amount,
+                &[0, 0, 0],
+            )
+            .unwrap();
+        for (user_token_account, token_amount) in user_token_accounts.iter().zip(token_amounts) {
+            assert_eq!(token_amount_of(user_token_account) as u128, token_amount);
+        }
+        assert_eq!(
+            token_amount_of(&accounts.pool_fee_account) as u128,
+            fee_account_amount as u128 + withdraw_fee
         );
 
+        // withdraw the rest as the last token
+        let withdraw_amount = token_amount_of(&pool_account);
+        let withdraw_fee = fees.owner_withdraw_fee(withdraw_amount as u128).unwrap();
+        let destination_amount = token_amount_of(&user_token_accounts[2]);
+        let token_amount = curve
+            .withdraw_one(
+                withdraw_amount as u128 - withdraw_fee,
+                2,
+                &accounts.reserves(),
+                accounts.pool_supply(),
+                &fees,
+            )
+            .unwrap() as u64;
         assert_eq!(
             Err(SwapError::ExceededSlippage.into()),
-            accounts.deposit_all_token_types(
-                &withdrawer_key,
-                &token_a_key,
-                &mut token_a_account,
-                &token_b_key,
-                &mut token_b_account,
+            accounts.withdraw_one(
+                &depositor_key,
                 &pool_key,
                 &mut pool_account,
-                1, // doesn't matter
-                token_a_amount,
-                token_b_amount,
+                2,
+                &user_token_keys[2],
+                &mut user_token_accounts[2],
+                withdraw_amount,
+                token_amount + 1,
             )
         );
-
-        // deposit enough tokens, success!
-        let token_b_amount = 125;
-        let token_a_amount = token_b_amount * token_b_price;
-        let (
-            token_a_key,
-            mut token_a_account,
-            token_b_key,
-            mut token_b_account,
-            pool_key,
-            mut pool_account,
-        ) = accounts.setup_token_accounts(
-            &user_key,
-            &withdrawer_key,
-            token_a_amount,
-            token_b_amount,
-            0,
-        );
-
         accounts
-            .deposit_all_token_types(
-                &withdrawer_key,
-                &token_a_key,
-                &mut token_a_account,
-                &token_b_key,
-                &mut token_b_account,
+            .withdraw_one(
+                &depositor_key,
                 &pool_key,
                 &mut pool_account,
-                1, // doesn't matter
-                token_a_amount,
-                token_b_amount,
+                2,
+                &user_token_keys[2],
+                &mut user_token_accounts[2],
+                withdraw_amount,
+                token_amount,
             )
             .unwrap();
+        assert_eq!(token_amount_of(&pool_account), 0);
+        assert_eq!(
+            token_amount_of(&user_token_accounts[2]),
+            destination_amount + token_amount
+        );
+
+        // the pool's own token accounts can't be traded against it
+        let (user_token_keys, mut user_token_accounts, _pool_key, _pool_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, &[amount_in, 0, 0], 0);
+        let swap_token_key = accounts.token_keys[1];
+        let mut swap_token_account = accounts.token_accounts[1].clone();
+        assert_eq!(
+            Err(SwapError::InvalidInput.into()),
+            accounts.swap(
+                &depositor_key,
+                0,
+                &user_token_keys[0],
+                &mut user_token_accounts[0],
+                1,
+                &swap_token_key,
+                &mut swap_token_account,
+                amount_in,
+                0,
+            )
+        );
     }
 
     #[test]
-    fn test_deposits_allowed_single_token() {
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 10;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 30;
-        let owner_withdraw_fee_numerator = 0;
-        let owner_withdraw_fee_denominator = 30;
-        let host_fee_numerator = 10;
-        let host_fee_denominator = 100;
-
-        let token_a_amount = 1_000_000;
-        let token_b_amount = 0;
+    fn test_concentrated_liquidity() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        let fee_owner_key = Pubkey::new_unique();
         let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 1,
+            host_fee_numerator: 0,
+            host_fee_denominator: 1,
         };
-
-        let token_b_offset = 2_000_000;
-        let swap_curve = SwapCurve {
-            curve_type: CurveType::Offset,
-            calculator: Arc::new(OffsetCurve { token_b_offset }),
+        let tick_spacing = 10;
+        let initial_sqrt_price = sqrt_price_at_tick(0).unwrap();
+        let token_amount_of = |account: &Account| {
+            spl_token::state::Account::unpack(&account.data)
+                .unwrap()
+                .amount
         };
-        let creator_key = Pubkey::new_unique();
-        let depositor_key = Pubkey::new_unique();
 
-        let mut accounts = SwapAccountInfo::new(
-            &creator_key,
-            fees,
-            swap_curve,
-            token_a_amount,
-            token_b_amount,
-        );
+        // the curve needs a tick spacing
+        {
+            let mut accounts = ConcentratedSwapAccountInfo::new(
+                &user_key,
+                &fee_owner_key,
+                fees.clone(),
+                0,
+                initial_sqrt_price,
+            );
+            assert_eq!(
+                Err(SwapError::InvalidCurve.into()),
+                accounts.initialize_swap()
+            );
+        }
 
+        let mut accounts = ConcentratedSwapAccountInfo::new(
+            &user_key,
+            &fee_owner_key,
+            fees.clone(),
+            tick_spacing,
+            initial_sqrt_price,
+        );
         accounts.initialize_swap().unwrap();
+        let swap_state = accounts.state();
+        assert!(swap_state.is_initialized);
+        assert_eq!(swap_state.token_a, accounts.token_a_key);
+        assert_eq!(swap_state.token_b_mint, accounts.token_b_mint_key);
+        assert_eq!(swap_state.fee_owner, fee_owner_key);
+        assert_eq!(swap_state.curve.sqrt_price, initial_sqrt_price);
+        assert_eq!(
+            Err(SwapError::AlreadyInUse.into()),
+            accounts.initialize_swap()
+        );
 
-        let initial_a = 1_000_000;
-        let initial_b = 2_000_000;
-        let (
-            _depositor_token_a_key,
-            _depositor_token_a_account,
-            depositor_token_b_key,
-            mut depositor_token_b_account,
-            depositor_pool_key,
-            mut depositor_pool_account,
-        ) = accounts.setup_token_accounts(&creator_key, &depositor_key, initial_a, initial_b, 0);
+        // a position is bounded by multiples of the tick spacing, and is
+        // represented by a single indivisible token
+        let mut position = PositionAccountInfo::new(&accounts.authority_key, &depositor_key, 0);
+        assert_eq!(
+            Err(SwapError::InvalidTickRange.into()),
+            accounts.open_position(&mut position, -105, 100)
+        );
+        {
+            let mut position = PositionAccountInfo::new(&accounts.authority_key, &depositor_key, 2);
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.open_position(&mut position, -100, 100)
+            );
+        }
+        accounts.open_position(&mut position, -100, 100).unwrap();
+        assert_eq!(token_amount_of(&position.token_account), 1);
+        let position_mint = spl_token::state::Mint::unpack(&position.mint_account.data).unwrap();
+        assert_eq!(position_mint.mint_authority, COption::None);
+        assert_eq!(
+            Err(SwapError::AlreadyInUse.into()),
+            accounts.open_position(&mut position, -100, 100)
+        );
 
+        // add liquidity around the current price
+        let liquidity = 1_000_000_000;
+        let (token_a_amount, token_b_amount) = accounts
+            .state()
+            .curve
+            .token_amounts(-100, 100, liquidity, RoundDirection::Ceiling)
+            .unwrap();
+        let (token_a_amount, token_b_amount) = (token_a_amount as u64, token_b_amount as u64);
+        let (source_a_key, mut source_a_account, source_b_key, mut source_b_account) = accounts
+            .setup_token_accounts(&user_key, &depositor_key, token_a_amount, token_b_amount);
         assert_eq!(
-            Err(SwapError::UnsupportedCurveOperation.into()),
-            accounts.deposit_single_token_type_exact_amount_in(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.increase_liquidity(
                 &depositor_key,
-                &depositor_token_b_key,
-                &mut depositor_token_b_account,
-                &depositor_pool_key,
-                &mut depositor_pool_account,
-                initial_b,
+                &mut position,
+                &source_a_key,
+                &mut source_a_account,
+                &source_b_key,
+                &mut source_b_account,
+                liquidity,
+                token_a_amount - 1,
+                token_b_amount,
+            )
+        );
+        accounts
+            .increase_liquidity(
+                &depositor_key,
+                &mut position,
+                &source_a_key,
+                &mut source_a_account,
+                &source_b_key,
+                &mut source_b_account,
+                liquidity,
+                token_a_amount,
+                token_b_amount,
+            )
+            .unwrap();
+        assert_eq!(token_amount_of(&accounts.token_a_account), token_a_amount);
+        assert_eq!(token_amount_of(&accounts.token_b_account), token_b_amount);
+        assert_eq!(position.position().liquidity, liquidity);
+        assert_eq!(accounts.state().curve.liquidity, liquidity);
+
+        // and more below it, only made of token B
+        let mut lower_position =
+            PositionAccountInfo::new(&accounts.authority_key, &depositor_key, 0);
+        accounts
+            .open_position(&mut lower_position, -300, -100)
+            .unwrap();
+        let (lower_token_a_amount, lower_token_b_amount) = accounts
+            .state()
+            .curve
+            .token_amounts(-300, -100, liquidity, RoundDirection::Ceiling)
+            .unwrap();
+        assert_eq!(lower_token_a_amount, 0);
+        let lower_token_b_amount = lower_token_b_amount as u64;
+        let (source_a_key, mut source_a_account, source_b_key, mut source_b_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, 0, lower_token_b_amount);
+        accounts
+            .increase_liquidity(
+                &depositor_key,
+                &mut lower_position,
+                &source_a_key,
+                &mut source_a_account,
+                &source_b_key,
+                &mut source_b_account,
+                liquidity,
+                0,
+                lower_token_b_amount,
+            )
+            .unwrap();
+        assert_eq!(accounts.state().curve.liquidity, liquidity);
+
+        // swap token A down through the lower bound of the first position
+        let amount_in = 8_000_000;
+        let (user_a_key, mut user_a_account, user_b_key, mut user_b_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, amount_in, 0);
+        let trade_fee = fees.trading_fee(amount_in as u128).unwrap();
+        let owner_fee = fees.owner_trading_fee(amount_in as u128).unwrap();
+        let mut expected_curve = accounts.state().curve;
+        let amount_out = expected_curve
+            .swap(amount_in as u128 - trade_fee - owner_fee, trade_fee, true)
+            .unwrap() as u64;
+        assert!(expected_curve.tick < -100);
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.swap(
+                &depositor_key,
+                true,
+                &user_a_key,
+                &mut user_a_account,
+                &user_b_key,
+                &mut user_b_account,
+                amount_in,
+                amount_out + 1,
+            )
+        );
+        accounts
+            .swap(
+                &depositor_key,
+                true,
+                &user_a_key,
+                &mut user_a_account,
+                &user_b_key,
+                &mut user_b_account,
+                amount_in,
+                amount_out,
+            )
+            .unwrap();
+        assert_eq!(token_amount_of(&user_a_account), 0);
+        assert_eq!(token_amount_of(&user_b_account), amount_out);
+        let swap_state = accounts.state();
+        assert_eq!(swap_state.curve, expected_curve);
+        assert_eq!(swap_state.owner_fee_a, owner_fee as u64);
+        assert_eq!(swap_state.owner_fee_b, 0);
+
+        // only the position owner can remove its liquidity, or close it
+        // while it holds any
+        let (dest_a_key, mut dest_a_account, dest_b_key, mut dest_b_account) =
+            accounts.setup_token_accounts(&user_key, &depositor_key, 0, 0);
+        assert_eq!(
+            Err(SwapError::InvalidOwner.into()),
+            accounts.decrease_liquidity(
+                &user_key,
+                &mut position,
+                &dest_a_key,
+                &mut dest_a_account,
+                &dest_b_key,
+                &mut dest_b_account,
+                liquidity,
                 0,
+                0,
+            )
+        );
+        let rent_key = Pubkey::new_unique();
+        let mut rent_account = Account::default();
+        assert_eq!(
+            Err(SwapError::PositionNotEmpty.into()),
+            accounts.close_position(&depositor_key, &mut position, &rent_key, &mut rent_account)
+        );
+
+        // removing all liquidity pays out the position's share of the trade
+        // fees along with it
+        let mut expected_position = position.position();
+        expected_position
+            .settle_fees(swap_state.curve.fee_growth_inside(-100, 100))
+            .unwrap();
+        assert!(expected_position.fees_owed_a > 0);
+        assert_eq!(expected_position.fees_owed_b, 0);
+        let (token_a_amount, token_b_amount) = swap_state
+            .curve
+            .token_amounts(-100, 100, liquidity, RoundDirection::Floor)
+            .unwrap();
+        let token_a_amount = token_a_amount as u64 + expected_position.fees_owed_a;
+        let token_b_amount = token_b_amount as u64;
+        assert_eq!(
+            Err(SwapError::ExceededSlippage.into()),
+            accounts.decrease_liquidity(
+                &depositor_key,
+                &mut position,
+                &dest_a_key,
+                &mut dest_a_account,
+                &dest_b_key,
+                &mut dest_b_account,
+                liquidity,
+                token_a_amount + 1,
+                token_b_amount,
+            )
+        );
+        accounts
+            .decrease_liquidity(
+                &depositor_key,
+                &mut position,
+                &dest_a_key,
+                &mut dest_a_account,
+                &dest_b_key,
+                &mut dest_b_account,
+                liquidity,
+                token_a_amount,
+                token_b_amount,
+            )
+            .unwrap();
+        assert_eq!(token_amount_of(&dest_a_account), token_a_amount);
+        assert_eq!(token_amount_of(&dest_b_account), token_b_amount);
+        let emptied_position = position.position();
+        assert_eq!(emptied_position.liquidity, 0);
+        assert_eq!(emptied_position.fees_owed_a, 0);
+        let swap_state = accounts.state();
+        assert!(swap_state.curve.ticks.iter().all(|tick| tick.index != 100));
+
+        // the empty position can then be closed, burning its token
+        let position_balance = position.position_account.daltons;
+        accounts
+            .close_position(&depositor_key, &mut position, &rent_key, &mut rent_account)
+            .unwrap();
+        assert_eq!(rent_account.daltons, position_balance);
+        assert_eq!(position.position_account.daltons, 0);
+        assert_eq!(token_amount_of(&position.token_account), 0);
+        let position_mint = spl_token::state::Mint::unpack(&position.mint_account.data).unwrap();
+        assert_eq!(position_mint.supply, 0);
+        assert_eq!(
+            Err(ProgramError::UninitializedAccount),
+            accounts.close_position(&depositor_key, &mut position, &rent_key, &mut rent_account)
+        );
+
+        // the owner trading fees only go to the fee owner
+        let (dest_a_key, mut dest_a_account, dest_b_key, mut dest_b_account) =
+            accounts.setup_token_accounts(&user_key, &fee_owner_key, 0, 0);
+        assert_eq!(
+            Err(SwapError::InvalidOwner.into()),
+            accounts.collect_owner_fees(
+                &depositor_key,
+                &dest_a_key,
+                &mut dest_a_account,
+                &dest_b_key,
+                &mut dest_b_account,
             )
         );
+        accounts
+            .collect_owner_fees(
+                &fee_owner_key,
+                &dest_a_key,
+                &mut dest_a_account,
+                &dest_b_key,
+                &mut dest_b_account,
+            )
+            .unwrap();
+        assert_eq!(token_amount_of(&dest_a_account), owner_fee as u64);
+        assert_eq!(token_amount_of(&dest_b_account), 0);
+        assert_eq!(accounts.state().owner_fee_a, 0);
     }
 }
@@ -0,0 +1,127 @@
+//! Canonical pool addresses, one per token pair, curve type and fee tier
+
+use crate::{
+    curve::{base::CurveType, fees::Fees},
+    error::SwapError,
+};
+use solana_program::pubkey::Pubkey;
+
+/// Seed prefix of every canonical pool address
+pub const POOL_SEED_PREFIX: &[u8] = b"pool";
+
+/// Denominator of a fee tier, so that a tier is a trade fee expressed in
+/// hundredths of a basis point, ie. 3_000 for a 0.3% pool
+pub const FEE_TIER_DENOMINATOR: u64 = 1_000_000;
+
+/// Orders two mints by their bytes, so that a pair has the same canonical
+/// address whichever way round it is given
+pub fn sorted_mints<'a>(mint_a: &'a Pubkey, mint_b: &'a Pubkey) -> (&'a Pubkey, &'a Pubkey) {
+    if mint_a.as_ref() <= mint_b.as_ref() {
+        (mint_a, mint_b)
+    } else {
+        (mint_b, mint_a)
+    }
+}
+
+/// Checks that the trade fee of a pool is exactly the one named by its fee tier
+pub fn validate_fee_tier(fees: &Fees, fee_tier: u32) -> Result<(), SwapError> {
+    if fees.trade_fee_denominator != 0
+        && u128::from(fees.trade_fee_numerator) * u128::from(FEE_TIER_DENOMINATOR)
+            == u128::from(fee_tier) * u128::from(fees.trade_fee_denominator)
+    {
+        Ok(())
+    } else {
+        Err(SwapError::InvalidFee)
+    }
+}
+
+/// Checks that the mints of a canonical pool are in sorted order.  Curve
+/// parameters such as the token B price depend on which token is A, so a pair
+/// is only pooled one way round.
+pub fn validate_mint_order(token_a_mint: &Pubkey, token_b_mint: &Pubkey) -> Result<(), SwapError> {
+    if token_a_mint.as_ref() < token_b_mint.as_ref() {
+        Ok(())
+    } else {
+        Err(SwapError::InvalidInput)
+    }
+}
+
+/// Finds the canonical pool address and bump seed for a token pair, curve
+/// type and fee tier, the mints may be given in either order
+pub fn find_pool_address(
+    program_id: &Pubkey,
+    token_a_mint: &Pubkey,
+    token_b_mint: &Pubkey,
+    curve_type: CurveType,
+    fee_tier: u32,
+) -> (Pubkey, u8) {
+    let (first_mint, second_mint) = sorted_mints(token_a_mint, token_b_mint);
+    Pubkey::find_program_address(
+        &[
+            POOL_SEED_PREFIX,
+            first_mint.as_ref(),
+            second_mint.as_ref(),
+            &[curve_type as u8],
+            &fee_tier.to_le_bytes(),
+        ],
+        program_id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_address_ignores_mint_order() {
+        let program_id = crate::id();
+        let mint_a = Pubkey::new_unique();
+        let mint_b = Pubkey::new_unique();
+        let address = find_pool_address(&program_id, &mint_a, &mint_b, CurveType::Stable, 100);
+        assert_eq!(
+            address,
+            find_pool_address(&program_id, &mint_b, &mint_a, CurveType::Stable, 100)
+        );
+        assert_ne!(
+            address,
+            find_pool_address(&program_id, &mint_a, &mint_b, CurveType::Stable, 500)
+        );
+        assert_ne!(
+            address,
+            find_pool_address(&program_id, &mint_a, &mint_b, CurveType::Offset, 100)
+        );
+    }
+
+    #[test]
+    fn mint_order() {
+        let mint_a = Pubkey::new_unique();
+        let mint_b = Pubkey::new_unique();
+        let (first_mint, second_mint) = sorted_mints(&mint_a, &mint_b);
+        assert_eq!(validate_mint_order(first_mint, second_mint), Ok(()));
+        assert_eq!(
+            validate_mint_order(second_mint, first_mint),
+            Err(SwapError::InvalidInput)
+        );
+        assert_eq!(
+            validate_mint_order(first_mint, first_mint),
+            Err(SwapError::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn fee_tiers() {
+        let fees = Fees {
+            trade_fee_numerator: 3,
+            trade_fee_denominator: 1_000,
+            ..Fees::default()
+        };
+        assert_eq!(validate_fee_tier(&fees, 3_000), Ok(()));
+        assert_eq!(validate_fee_tier(&fees, 500), Err(SwapError::InvalidFee));
+        let fees = Fees {
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 0,
+            ..Fees::default()
+        };
+        assert_eq!(validate_fee_tier(&fees, 0), Err(SwapError::InvalidFee));
+    }
+}
@@ -1,14 +1,23 @@
 //! State transition types
 
-use crate::curve::{base::SwapCurve, fees::Fees};
+use crate::curve::{
+    base::{CurveType, SwapCurve},
+    concentrated::ConcentratedCurve,
+    constant_price::PriceOracle,
+    fees::{DynamicFee, Fees},
+    multi_stable::{MultiStableCurve, MAX_TOKENS, MIN_TOKENS},
+    stable::{AmpRamp, StableCurve},
+};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use enum_dispatch::enum_dispatch;
 use solana_program::{
+    clock::UnixTimestamp,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
-use std::sync::Arc;
+use spl_math::uint::U256;
+use std::{convert::TryFrom, sync::Arc};
 
 /// Trait representing access to program state across all versions
 #[enum_dispatch]
@@ -38,13 +47,33 @@ pub trait SwapState {
     fn fees(&self) -> &Fees;
     /// Curve associated with swap
     fn swap_curve(&self) -> &SwapCurve;
+
+    /// Cumulative prices as of the last update, if the swap tracks them
+    fn price_observation(&self) -> Option<PriceObservation>;
+
+    /// Are swaps and deposits paused by the admin, leaving only withdrawals
+    fn is_paused(&self) -> bool;
+
+    /// Is the swap locked by a flash loan in progress
+    fn is_locked(&self) -> bool;
+
+    /// Trade fee following the volatility of the pool price, if enabled
+    fn dynamic_fee(&self) -> Option<DynamicFee>;
+
+    /// Price feed pegging a constant price curve, if enabled
+    fn price_oracle(&self) -> Option<PriceOracle>;
+
+    /// Fee tier of a canonical pool, see `registry`
+    fn fee_tier(&self) -> Option<u32>;
 }
 
 /// All versions of SwapState
 #[enum_dispatch(SwapState)]
 pub enum SwapVersion {
-    /// Latest version, used for all new swaps
+    /// Original version, without price accumulators
     SwapV1,
+    /// Latest version, used for all new swaps
+    SwapV2,
 }
 
 /// SwapVersion does not implement program_pack::Pack because there are size
@@ -52,7 +81,10 @@ pub enum SwapVersion {
 /// special implementations are provided here
 impl SwapVersion {
     /// Size of the latest version of the SwapState
-    pub const LATEST_LEN: usize = 1 + SwapV1::LEN; // add one for the version enum
+    pub const LATEST_LEN: usize = 1 + SwapV2::LEN; // add one for the version enum
+
+    /// Size of a SwapV1 swap account, still accepted by initialize
+    pub const V1_LEN: usize = 1 + SwapV1::LEN;
 
     /// Pack a swap into a byte array, based on its version
     pub fn pack(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
@@ -61,6 +93,10 @@ impl SwapVersion {
                 dst[0] = 1;
                 SwapV1::pack(swap_info, &mut dst[1..])
             }
+            Self::SwapV2(swap_info) => {
+                dst[0] = 2;
+                SwapV2::pack(swap_info, &mut dst[1..])
+            }
         }
     }
 
@@ -72,13 +108,153 @@ impl SwapVersion {
             .ok_or(ProgramError::InvalidAccountData)?;
         match version {
             1 => Ok(Arc::new(SwapV1::unpack(rest)?)),
+            2 => Ok(Arc::new(SwapV2::unpack(rest)?)),
+            _ => Err(ProgramError::UninitializedAccount),
+        }
+    }
+
+    /// Accumulate the prices given by the token balances held since the last
+    /// update, up to `timestamp`.  Versions without price accumulators are
+    /// left untouched.
+    pub fn update_price_observation(
+        dst: &mut [u8],
+        token_a_amount: u64,
+        token_b_amount: u64,
+        timestamp: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        let (version, rest) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if *version == 2 {
+            let mut swap_info = SwapV2::unpack(rest)?;
+            let observation = PriceObservation {
+                price_a_cumulative: swap_info.price_a_cumulative,
+                price_b_cumulative: swap_info.price_b_cumulative,
+                timestamp: swap_info.last_update_timestamp,
+            }
+            .accumulate(token_a_amount, token_b_amount, timestamp);
+            swap_info.price_a_cumulative = observation.price_a_cumulative;
+            swap_info.price_b_cumulative = observation.price_b_cumulative;
+            swap_info.last_update_timestamp = observation.timestamp;
+            SwapV2::pack(swap_info, rest)?;
+        }
+        Ok(())
+    }
+
+    /// Moves a stable curve along its amp ramp, up to `timestamp`.  Versions
+    /// without amp ramps are left untouched.
+    pub fn update_amp_ramp(dst: &mut [u8], timestamp: UnixTimestamp) -> Result<(), ProgramError> {
+        let (version, rest) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if *version == 2 {
+            let mut swap_info = SwapV2::unpack(rest)?;
+            if swap_info.amp_ramp.is_active()
+                && swap_info.swap_curve.curve_type == CurveType::Stable
+            {
+                swap_info.swap_curve.calculator = Arc::new(StableCurve {
+                    amp: swap_info.amp_ramp.amp_at(timestamp),
+                });
+                if timestamp >= swap_info.amp_ramp.stop_timestamp {
+                    swap_info.amp_ramp.initial_amp = swap_info.amp_ramp.target_amp;
+                    swap_info.amp_ramp.start_timestamp = swap_info.amp_ramp.stop_timestamp;
+                }
+                SwapV2::pack(swap_info, rest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decays the volatility of a dynamic trade fee up to `timestamp`, and
+    /// sets the trade fee numerator from it.  Versions and swaps without
+    /// dynamic fees are left untouched.
+    pub fn update_dynamic_fee(
+        dst: &mut [u8],
+        timestamp: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        let (version, rest) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if *version == 2 {
+            let mut swap_info = SwapV2::unpack(rest)?;
+            if swap_info.dynamic_fee.is_enabled() {
+                swap_info.fees.trade_fee_numerator = swap_info.dynamic_fee.update(timestamp);
+                SwapV2::pack(swap_info, rest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the price change made by a swap, from the token balances before
+    /// it to the ones after it, to the volatility of a dynamic trade fee.
+    /// Versions and swaps without dynamic fees are left untouched.
+    pub fn accumulate_volatility(
+        dst: &mut [u8],
+        token_a_amount: u64,
+        token_b_amount: u64,
+        new_token_a_amount: u64,
+        new_token_b_amount: u64,
+    ) -> Result<(), ProgramError> {
+        let (version, rest) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if *version == 2 {
+            let mut swap_info = SwapV2::unpack(rest)?;
+            if swap_info.dynamic_fee.is_enabled() {
+                swap_info.dynamic_fee.accumulate(
+                    token_a_amount,
+                    token_b_amount,
+                    new_token_a_amount,
+                    new_token_b_amount,
+                );
+                SwapV2::pack(swap_info, rest)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Unpack the swap account into its version, to be modified and packed
+    /// again
+    pub fn unpack_version(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        match version {
+            1 => Ok(Self::SwapV1(SwapV1::unpack(rest)?)),
+            2 => Ok(Self::SwapV2(SwapV2::unpack(rest)?)),
             _ => Err(ProgramError::UninitializedAccount),
         }
     }
 
+    /// Replace the fees of the swap
+    pub fn set_fees(&mut self, fees: Fees) {
+        match self {
+            Self::SwapV1(swap_info) => swap_info.fees = fees,
+            Self::SwapV2(swap_info) => swap_info.fees = fees,
+        }
+    }
+
+    /// Replace the pool token account receiving the swap's fees
+    pub fn set_pool_fee_account(&mut self, pool_fee_account: Pubkey) {
+        match self {
+            Self::SwapV1(swap_info) => swap_info.pool_fee_account = pool_fee_account,
+            Self::SwapV2(swap_info) => swap_info.pool_fee_account = pool_fee_account,
+        }
+    }
+
     /// Special check to be done before any instruction processing, works for
     /// all versions
     pub fn is_initialized(input: &[u8]) -> bool {
+        if input.first() == Some(&MultiStableSwap::VERSION) {
+            return MultiStableSwap::unpack_account(input)
+                .map(|swap| swap.is_initialized)
+                .unwrap_or(false);
+        }
+        if input.first() == Some(&ConcentratedSwap::VERSION) {
+            return ConcentratedSwap::unpack_account(input)
+                .map(|swap| swap.is_initialized)
+                .unwrap_or(false);
+        }
         match Self::unpack(input) {
             Ok(swap) => swap.is_initialized(),
             Err(_) => false,
@@ -171,6 +347,30 @@ impl SwapState for SwapV1 {
     fn swap_curve(&self) -> &SwapCurve {
         &self.swap_curve
     }
+
+    fn price_observation(&self) -> Option<PriceObservation> {
+        None
+    }
+
+    fn is_paused(&self) -> bool {
+        false
+    }
+
+    fn is_locked(&self) -> bool {
+        false
+    }
+
+    fn dynamic_fee(&self) -> Option<DynamicFee> {
+        None
+    }
+
+    fn price_oracle(&self) -> Option<PriceOracle> {
+        None
+    }
+
+    fn fee_tier(&self) -> Option<u32> {
+        None
+    }
 }
 
 impl Sealed for SwapV1 {}
@@ -248,130 +448,1493 @@ impl Pack for SwapV1 {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::curve::stable::StableCurve;
+/// Number of fractional bits in the fixed-point prices accumulated by swaps
+pub const PRICE_FRACTIONAL_BITS: u32 = 64;
 
-    use std::convert::TryInto;
+/// Cumulative prices of a swap at some point in time.  Two observations of
+/// the same swap give the time-weighted average prices between them, which
+/// cannot be moved by trading within a single transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PriceObservation {
+    /// Sum over time of the price of token A in token B, in fixed-point
+    /// price-seconds with `PRICE_FRACTIONAL_BITS` fractional bits.  Wraps
+    /// on overflow, so only differences between observations are meaningful.
+    pub price_a_cumulative: u128,
+    /// Sum over time of the price of token B in token A, as above
+    pub price_b_cumulative: u128,
+    /// Unix timestamp of the observation
+    pub timestamp: UnixTimestamp,
+}
 
-    const TEST_FEES: Fees = Fees {
-        trade_fee_numerator: 1,
-        trade_fee_denominator: 4,
-        owner_trade_fee_numerator: 3,
-        owner_trade_fee_denominator: 10,
-        owner_withdraw_fee_numerator: 2,
-        owner_withdraw_fee_denominator: 7,
-        host_fee_numerator: 5,
-        host_fee_denominator: 20,
-    };
+impl PriceObservation {
+    /// Observation at `timestamp`, adding the prices given by the swap's
+    /// token balances held since this observation.  Use with the current
+    /// balances to bring a swap's last observation up to date.
+    pub fn accumulate(
+        &self,
+        token_a_amount: u64,
+        token_b_amount: u64,
+        timestamp: UnixTimestamp,
+    ) -> Self {
+        let elapsed = timestamp.saturating_sub(self.timestamp);
+        if elapsed <= 0 {
+            return *self;
+        }
+        let elapsed = elapsed as u128;
+        Self {
+            price_a_cumulative: self.price_a_cumulative.wrapping_add(
+                fixed_point_price(token_b_amount, token_a_amount).wrapping_mul(elapsed),
+            ),
+            price_b_cumulative: self.price_b_cumulative.wrapping_add(
+                fixed_point_price(token_a_amount, token_b_amount).wrapping_mul(elapsed),
+            ),
+            timestamp,
+        }
+    }
 
-    const TEST_BUMP_SEED: u8 = 255;
-    const TEST_TOKEN_PROGRAM_ID: Pubkey = Pubkey::new_from_array([1u8; 32]);
-    const TEST_TOKEN_A: Pubkey = Pubkey::new_from_array([2u8; 32]);
-    const TEST_TOKEN_B: Pubkey = Pubkey::new_from_array([3u8; 32]);
-    const TEST_POOL_MINT: Pubkey = Pubkey::new_from_array([4u8; 32]);
-    const TEST_TOKEN_A_MINT: Pubkey = Pubkey::new_from_array([5u8; 32]);
-    const TEST_TOKEN_B_MINT: Pubkey = Pubkey::new_from_array([6u8; 32]);
-    const TEST_POOL_FEE_ACCOUNT: Pubkey = Pubkey::new_from_array([7u8; 32]);
+    /// Time-weighted average prices of token A in token B, and of token B in
+    /// token A, between an earlier observation and this one, with
+    /// `PRICE_FRACTIONAL_BITS` fractional bits
+    pub fn time_weighted_average_prices(&self, earlier: &Self) -> Option<(u128, u128)> {
+        let elapsed = self.timestamp.checked_sub(earlier.timestamp)?;
+        if elapsed <= 0 {
+            return None;
+        }
+        let elapsed = elapsed as u128;
+        Some((
+            self.price_a_cumulative
+                .wrapping_sub(earlier.price_a_cumulative)
+                / elapsed,
+            self.price_b_cumulative
+                .wrapping_sub(earlier.price_b_cumulative)
+                / elapsed,
+        ))
+    }
+}
 
-    const TEST_CURVE_TYPE: u8 = 2;
-    const TEST_AMP: u64 = 1;
-    const TEST_CURVE: StableCurve = StableCurve { amp: TEST_AMP };
+/// Fixed-point price of one token in the other, zero for an empty swap
+fn fixed_point_price(numerator: u64, denominator: u64) -> u128 {
+    if denominator == 0 {
+        0
+    } else {
+        (u128::from(numerator) << PRICE_FRACTIONAL_BITS) / u128::from(denominator)
+    }
+}
 
-    #[test]
-    fn swap_version_pack() {
-        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
-        let calculator = Arc::new(TEST_CURVE);
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator,
-        };
-        let swap_info = SwapVersion::SwapV1(SwapV1 {
-            is_initialized: true,
-            bump_seed: TEST_BUMP_SEED,
-            token_program_id: TEST_TOKEN_PROGRAM_ID,
-            token_a: TEST_TOKEN_A,
-            token_b: TEST_TOKEN_B,
-            pool_mint: TEST_POOL_MINT,
-            token_a_mint: TEST_TOKEN_A_MINT,
-            token_b_mint: TEST_TOKEN_B_MINT,
-            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
-            fees: TEST_FEES,
-            swap_curve: swap_curve.clone(),
-        });
+/// Program states, with cumulative prices for time-weighted averages.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SwapV2 {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Bump seed used in program address.
+    /// The program address is created deterministically with the bump seed,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token A account, token B account, and pool
+    /// token mint.
+    pub bump_seed: u8,
 
-        let mut packed = [0u8; SwapVersion::LATEST_LEN];
-        SwapVersion::pack(swap_info, &mut packed).unwrap();
-        let unpacked = SwapVersion::unpack(&packed).unwrap();
+    /// Program ID of the tokens being exchanged.
+    pub token_program_id: Pubkey,
 
-        assert!(unpacked.is_initialized());
-        assert_eq!(unpacked.bump_seed(), TEST_BUMP_SEED);
-        assert_eq!(*unpacked.token_program_id(), TEST_TOKEN_PROGRAM_ID);
-        assert_eq!(*unpacked.token_a_account(), TEST_TOKEN_A);
-        assert_eq!(*unpacked.token_b_account(), TEST_TOKEN_B);
-        assert_eq!(*unpacked.pool_mint(), TEST_POOL_MINT);
-        assert_eq!(*unpacked.token_a_mint(), TEST_TOKEN_A_MINT);
-        assert_eq!(*unpacked.token_b_mint(), TEST_TOKEN_B_MINT);
-        assert_eq!(*unpacked.pool_fee_account(), TEST_POOL_FEE_ACCOUNT);
-        assert_eq!(*unpacked.fees(), TEST_FEES);
-        assert_eq!(*unpacked.swap_curve(), swap_curve);
+    /// Token A
+    pub token_a: Pubkey,
+    /// Token B
+    pub token_b: Pubkey,
+
+    /// Pool tokens are issued when A or B tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original A or B token.
+    pub pool_mint: Pubkey,
+
+    /// Mint information for token A
+    pub token_a_mint: Pubkey,
+    /// Mint information for token B
+    pub token_b_mint: Pubkey,
+
+    /// Pool token account to receive trading and / or withdrawal fees
+    pub pool_fee_account: Pubkey,
+
+    /// All fee information
+    pub fees: Fees,
+
+    /// Swap curve parameters, to be unpacked and used by the SwapCurve, which
+    /// calculates swaps, deposits, and withdrawals
+    pub swap_curve: SwapCurve,
+
+    /// Cumulative price of token A in token B, see `PriceObservation`
+    pub price_a_cumulative: u128,
+    /// Cumulative price of token B in token A, see `PriceObservation`
+    pub price_b_cumulative: u128,
+    /// Unix timestamp of the last update to the cumulative prices, made
+    /// before every swap, deposit and withdrawal
+    pub last_update_timestamp: UnixTimestamp,
+
+    /// Swaps and deposits are paused, withdrawals are still allowed
+    pub is_paused: bool,
+    /// Scheduled change of a stable curve's amp, applied before every swap,
+    /// deposit and withdrawal
+    pub amp_ramp: AmpRamp,
+    /// A flash loan is in progress, and every instruction on the swap is
+    /// refused until it is repaid
+    pub is_locked: bool,
+    /// Trade fee following the volatility of the pool price, replacing the
+    /// trade fee numerator before every trade while enabled
+    pub dynamic_fee: DynamicFee,
+    /// Price feed setting the price of a constant price curve, refreshed by
+    /// anyone, and checked for staleness before every trade while enabled
+    pub price_oracle: PriceOracle,
+    /// Fee tier of a canonical pool, created at its registry address, whose
+    /// trade fee must stay the one named by the tier
+    pub fee_tier: Option<u32>,
+}
+
+impl SwapState for SwapV2 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
     }
 
-    #[test]
-    fn swap_v1_pack() {
-        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
-        let calculator = Arc::new(TEST_CURVE);
-        let swap_curve = SwapCurve {
-            curve_type,
-            calculator,
-        };
-        let swap_info = SwapV1 {
-            is_initialized: true,
-            bump_seed: TEST_BUMP_SEED,
-            token_program_id: TEST_TOKEN_PROGRAM_ID,
-            token_a: TEST_TOKEN_A,
-            token_b: TEST_TOKEN_B,
-            pool_mint: TEST_POOL_MINT,
-            token_a_mint: TEST_TOKEN_A_MINT,
-            token_b_mint: TEST_TOKEN_B_MINT,
-            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
-            fees: TEST_FEES,
-            swap_curve,
-        };
+    fn bump_seed(&self) -> u8 {
+        self.bump_seed
+    }
 
-        let mut packed = [0u8; SwapV1::LEN];
-        SwapV1::pack_into_slice(&swap_info, &mut packed);
-        let unpacked = SwapV1::unpack(&packed).unwrap();
-        assert_eq!(swap_info, unpacked);
+    fn token_program_id(&self) -> &Pubkey {
+        &self.token_program_id
+    }
 
-        let mut packed = vec![1u8, TEST_BUMP_SEED];
-        packed.extend_from_slice(&TEST_TOKEN_PROGRAM_ID.to_bytes());
-        packed.extend_from_slice(&TEST_TOKEN_A.to_bytes());
-        packed.extend_from_slice(&TEST_TOKEN_B.to_bytes());
-        packed.extend_from_slice(&TEST_POOL_MINT.to_bytes());
-        packed.extend_from_slice(&TEST_TOKEN_A_MINT.to_bytes());
-        packed.extend_from_slice(&TEST_TOKEN_B_MINT.to_bytes());
-        packed.extend_from_slice(&TEST_POOL_FEE_ACCOUNT.to_bytes());
-        packed.extend_from_slice(&TEST_FEES.trade_fee_numerator.to_le_bytes());
-        packed.extend_from_slice(&TEST_FEES.trade_fee_denominator.to_le_bytes());
-        packed.extend_from_slice(&TEST_FEES.owner_trade_fee_numerator.to_le_bytes());
-        packed.extend_from_slice(&TEST_FEES.owner_trade_fee_denominator.to_le_bytes());
-        packed.extend_from_slice(&TEST_FEES.owner_withdraw_fee_numerator.to_le_bytes());
-        packed.extend_from_slice(&TEST_FEES.owner_withdraw_fee_denominator.to_le_bytes());
-        packed.extend_from_slice(&TEST_FEES.host_fee_numerator.to_le_bytes());
-        packed.extend_from_slice(&TEST_FEES.host_fee_denominator.to_le_bytes());
-        packed.push(TEST_CURVE_TYPE);
-        packed.extend_from_slice(&TEST_AMP.to_le_bytes());
-        packed.extend_from_slice(&[0u8; 24]);
-        let unpacked = SwapV1::unpack(&packed).unwrap();
-        assert_eq!(swap_info, unpacked);
+    fn token_a_account(&self) -> &Pubkey {
+        &self.token_a
+    }
 
-        let packed = [0u8; SwapV1::LEN];
-        let swap_info: SwapV1 = Default::default();
-        let unpack_unchecked = SwapV1::unpack_unchecked(&packed).unwrap();
-        assert_eq!(unpack_unchecked, swap_info);
-        let err = SwapV1::unpack(&packed).unwrap_err();
-        assert_eq!(err, ProgramError::UninitializedAccount);
+    fn token_b_account(&self) -> &Pubkey {
+        &self.token_b
+    }
+
+    fn pool_mint(&self) -> &Pubkey {
+        &self.pool_mint
+    }
+
+    fn token_a_mint(&self) -> &Pubkey {
+        &self.token_a_mint
+    }
+
+    fn token_b_mint(&self) -> &Pubkey {
+        &self.token_b_mint
+    }
+
+    fn pool_fee_account(&self) -> &Pubkey {
+        &self.pool_fee_account
+    }
+
+    fn fees(&self) -> &Fees {
+        &self.fees
+    }
+
+    fn swap_curve(&self) -> &SwapCurve {
+        &self.swap_curve
+    }
+
+    fn price_observation(&self) -> Option<PriceObservation> {
+        Some(PriceObservation {
+            price_a_cumulative: self.price_a_cumulative,
+            price_b_cumulative: self.price_b_cumulative,
+            timestamp: self.last_update_timestamp,
+        })
+    }
+
+    fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+
+    fn dynamic_fee(&self) -> Option<DynamicFee> {
+        Some(self.dynamic_fee).filter(DynamicFee::is_enabled)
+    }
+
+    fn price_oracle(&self) -> Option<PriceOracle> {
+        Some(self.price_oracle).filter(PriceOracle::is_enabled)
+    }
+
+    fn fee_tier(&self) -> Option<u32> {
+        self.fee_tier
+    }
+}
+
+impl Sealed for SwapV2 {}
+impl IsInitialized for SwapV2 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for SwapV2 {
+    const LEN: usize = 531;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 531];
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            pool_fee_account,
+            fees,
+            swap_curve,
+            price_a_cumulative,
+            price_b_cumulative,
+            last_update_timestamp,
+            is_paused,
+            amp_ramp,
+            is_locked,
+            dynamic_fee,
+            price_oracle,
+            is_canonical,
+            fee_tier,
+        ) = mut_array_refs![
+            output, 1, 1, 32, 32, 32, 32, 32, 32, 32, 64, 33, 16, 16, 8, 1, 32, 1, 48, 81, 1, 4
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        bump_seed[0] = self.bump_seed;
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        token_a.copy_from_slice(self.token_a.as_ref());
+        token_b.copy_from_slice(self.token_b.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
+        token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
+        pool_fee_account.copy_from_slice(self.pool_fee_account.as_ref());
+        self.fees.pack_into_slice(&mut fees[..]);
+        self.swap_curve.pack_into_slice(&mut swap_curve[..]);
+        *price_a_cumulative = self.price_a_cumulative.to_le_bytes();
+        *price_b_cumulative = self.price_b_cumulative.to_le_bytes();
+        *last_update_timestamp = self.last_update_timestamp.to_le_bytes();
+        is_paused[0] = self.is_paused as u8;
+        self.amp_ramp.pack_into_slice(&mut amp_ramp[..]);
+        is_locked[0] = self.is_locked as u8;
+        self.dynamic_fee.pack_into_slice(&mut dynamic_fee[..]);
+        self.price_oracle.pack_into_slice(&mut price_oracle[..]);
+        is_canonical[0] = self.fee_tier.is_some() as u8;
+        *fee_tier = self.fee_tier.unwrap_or_default().to_le_bytes();
+    }
+
+    /// Unpacks a byte buffer into a [SwapV2](struct.SwapV2.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 531];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            pool_fee_account,
+            fees,
+            swap_curve,
+            price_a_cumulative,
+            price_b_cumulative,
+            last_update_timestamp,
+            is_paused,
+            amp_ramp,
+            is_locked,
+            dynamic_fee,
+            price_oracle,
+            is_canonical,
+            fee_tier,
+        ) = array_refs![
+            input, 1, 1, 32, 32, 32, 32, 32, 32, 32, 64, 33, 16, 16, 8, 1, 32, 1, 48, 81, 1, 4
+        ];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            bump_seed: bump_seed[0],
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            token_a: Pubkey::new_from_array(*token_a),
+            token_b: Pubkey::new_from_array(*token_b),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            token_a_mint: Pubkey::new_from_array(*token_a_mint),
+            token_b_mint: Pubkey::new_from_array(*token_b_mint),
+            pool_fee_account: Pubkey::new_from_array(*pool_fee_account),
+            fees: Fees::unpack_from_slice(fees)?,
+            swap_curve: SwapCurve::unpack_from_slice(swap_curve)?,
+            price_a_cumulative: u128::from_le_bytes(*price_a_cumulative),
+            price_b_cumulative: u128::from_le_bytes(*price_b_cumulative),
+            last_update_timestamp: i64::from_le_bytes(*last_update_timestamp),
+            is_paused: match is_paused {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            amp_ramp: AmpRamp::unpack_from_slice(amp_ramp)?,
+            is_locked: match is_locked {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            dynamic_fee: DynamicFee::unpack_from_slice(dynamic_fee)?,
+            price_oracle: PriceOracle::unpack_from_slice(price_oracle)?,
+            fee_tier: match is_canonical {
+                [0] => None,
+                [1] => Some(u32::from_le_bytes(*fee_tier)),
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+        })
+    }
+}
+
+/// Stable swap pool of between `MIN_TOKENS` and `MAX_TOKENS` tokens.  Its
+/// account starts with `MultiStableSwap::VERSION` like a `SwapVersion`, so
+/// that neither can be mistaken for the other, but it does not implement
+/// `SwapState`, which only knows about two tokens.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct MultiStableSwap {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Bump seed used in program address.
+    /// The program address is created deterministically with the bump seed,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token accounts and pool token mint.
+    pub bump_seed: u8,
+
+    /// Program ID of the tokens being exchanged, shared by all of them.
+    pub token_program_id: Pubkey,
+
+    /// Pool tokens are issued when tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original tokens.
+    pub pool_mint: Pubkey,
+    /// Pool token account to receive trading and / or withdrawal fees
+    pub pool_fee_account: Pubkey,
+
+    /// All fee information
+    pub fees: Fees,
+    /// Amplifier constant of the curve
+    pub amp: u64,
+
+    /// Number of tokens in the pool, the rest of `tokens` and `token_mints`
+    /// is left as zeroes
+    pub token_count: u8,
+    /// Token accounts holding the reserves, in pool order
+    pub tokens: [Pubkey; MAX_TOKENS],
+    /// Mints of the token accounts, in pool order
+    pub token_mints: [Pubkey; MAX_TOKENS],
+}
+
+impl MultiStableSwap {
+    /// Version byte at the start of a multi-token stable swap account
+    pub const VERSION: u8 = 3;
+
+    /// Size of a multi-token stable swap account
+    pub const ACCOUNT_LEN: usize = 1 + Self::LEN; // add one for the version
+
+    /// Pack the swap into an account, after its version byte
+    pub fn pack_account(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        let (version, rest) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        *version = Self::VERSION;
+        Self::pack(src, rest)
+    }
+
+    /// Unpack the swap from an account, checking its version byte
+    pub fn unpack_account(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if version != Self::VERSION {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Self::unpack(rest)
+    }
+
+    /// Token accounts holding the reserves, in pool order
+    pub fn tokens(&self) -> &[Pubkey] {
+        &self.tokens[..usize::from(self.token_count)]
+    }
+
+    /// Mints of the token accounts, in pool order
+    pub fn token_mints(&self) -> &[Pubkey] {
+        &self.token_mints[..usize::from(self.token_count)]
+    }
+
+    /// Curve used by the swap
+    pub fn curve(&self) -> MultiStableCurve {
+        MultiStableCurve { amp: self.amp }
+    }
+}
+
+impl Sealed for MultiStableSwap {}
+impl IsInitialized for MultiStableSwap {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for MultiStableSwap {
+    const LEN: usize = 427;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 427];
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            pool_mint,
+            pool_fee_account,
+            fees,
+            amp,
+            token_count,
+            tokens,
+            token_mints,
+        ) = mut_array_refs![output, 1, 1, 32, 32, 32, 64, 8, 1, 128, 128];
+        is_initialized[0] = self.is_initialized as u8;
+        bump_seed[0] = self.bump_seed;
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        pool_fee_account.copy_from_slice(self.pool_fee_account.as_ref());
+        self.fees.pack_into_slice(&mut fees[..]);
+        *amp = self.amp.to_le_bytes();
+        token_count[0] = self.token_count;
+        for (dst, token) in tokens.chunks_exact_mut(32).zip(self.tokens.iter()) {
+            dst.copy_from_slice(token.as_ref());
+        }
+        for (dst, mint) in token_mints
+            .chunks_exact_mut(32)
+            .zip(self.token_mints.iter())
+        {
+            dst.copy_from_slice(mint.as_ref());
+        }
+    }
+
+    /// Unpacks a byte buffer into a [MultiStableSwap](struct.MultiStableSwap.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 427];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            pool_mint,
+            pool_fee_account,
+            fees,
+            amp,
+            token_count,
+            tokens,
+            token_mints,
+        ) = array_refs![input, 1, 1, 32, 32, 32, 64, 8, 1, 128, 128];
+        let is_initialized = match is_initialized {
+            [0] => false,
+            [1] => true,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let token_count = token_count[0];
+        if is_initialized && !(MIN_TOKENS..=MAX_TOKENS).contains(&usize::from(token_count)) {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut swap = Self {
+            is_initialized,
+            bump_seed: bump_seed[0],
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            pool_fee_account: Pubkey::new_from_array(*pool_fee_account),
+            fees: Fees::unpack_from_slice(fees)?,
+            amp: u64::from_le_bytes(*amp),
+            token_count,
+            ..Self::default()
+        };
+        for (token, src) in swap.tokens.iter_mut().zip(tokens.chunks_exact(32)) {
+            *token = Pubkey::new_from_array(*array_ref![src, 0, 32]);
+        }
+        for (mint, src) in swap
+            .token_mints
+            .iter_mut()
+            .zip(token_mints.chunks_exact(32))
+        {
+            *mint = Pubkey::new_from_array(*array_ref![src, 0, 32]);
+        }
+        Ok(swap)
+    }
+}
+
+/// Concentrated liquidity pool, whose liquidity is provided between price
+/// ticks by `Position`s rather than for pool tokens.  Like `MultiStableSwap`,
+/// its account starts with its own version byte and it does not implement
+/// `SwapState`.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct ConcentratedSwap {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Bump seed used in program address.
+    /// The program address is created deterministically with the bump seed,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token accounts and position mints.
+    pub bump_seed: u8,
+
+    /// Program ID of the tokens being exchanged and of the position mints.
+    pub token_program_id: Pubkey,
+
+    /// Token A
+    pub token_a: Pubkey,
+    /// Token B
+    pub token_b: Pubkey,
+
+    /// Mint information for token A
+    pub token_a_mint: Pubkey,
+    /// Mint information for token B
+    pub token_b_mint: Pubkey,
+
+    /// Signer allowed to collect the owner's share of trading fees
+    pub fee_owner: Pubkey,
+
+    /// All fee information.  The trade fee goes to the positions whose
+    /// liquidity was used, and the owner trade fee is held for the fee owner.
+    pub fees: Fees,
+    /// Owner trading fees of token A held by the pool, not yet collected
+    pub owner_fee_a: u64,
+    /// Owner trading fees of token B held by the pool, not yet collected
+    pub owner_fee_b: u64,
+
+    /// Price, liquidity and ticks of the pool
+    pub curve: ConcentratedCurve,
+}
+
+impl ConcentratedSwap {
+    /// Version byte at the start of a concentrated liquidity swap account
+    pub const VERSION: u8 = 4;
+
+    /// Size of a concentrated liquidity swap account
+    pub const ACCOUNT_LEN: usize = 1 + Self::LEN; // add one for the version
+
+    /// Pack the swap into an account, after its version byte
+    pub fn pack_account(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        let (version, rest) = dst
+            .split_first_mut()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        *version = Self::VERSION;
+        Self::pack(src, rest)
+    }
+
+    /// Unpack the swap from an account, checking its version byte
+    pub fn unpack_account(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        if version != Self::VERSION {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        Self::unpack(rest)
+    }
+}
+
+impl Sealed for ConcentratedSwap {}
+impl IsInitialized for ConcentratedSwap {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for ConcentratedSwap {
+    const LEN: usize = 274 + ConcentratedCurve::LEN;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, ConcentratedSwap::LEN];
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            token_a,
+            token_b,
+            token_a_mint,
+            token_b_mint,
+            fee_owner,
+            fees,
+            owner_fee_a,
+            owner_fee_b,
+            curve,
+        ) = mut_array_refs![
+            output,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            64,
+            8,
+            8,
+            ConcentratedCurve::LEN
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        bump_seed[0] = self.bump_seed;
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        token_a.copy_from_slice(self.token_a.as_ref());
+        token_b.copy_from_slice(self.token_b.as_ref());
+        token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
+        token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
+        fee_owner.copy_from_slice(self.fee_owner.as_ref());
+        self.fees.pack_into_slice(&mut fees[..]);
+        *owner_fee_a = self.owner_fee_a.to_le_bytes();
+        *owner_fee_b = self.owner_fee_b.to_le_bytes();
+        self.curve.pack_into_slice(&mut curve[..]);
+    }
+
+    /// Unpacks a byte buffer into a [ConcentratedSwap](struct.ConcentratedSwap.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, ConcentratedSwap::LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            token_a,
+            token_b,
+            token_a_mint,
+            token_b_mint,
+            fee_owner,
+            fees,
+            owner_fee_a,
+            owner_fee_b,
+            curve,
+        ) = array_refs![
+            input,
+            1,
+            1,
+            32,
+            32,
+            32,
+            32,
+            32,
+            32,
+            64,
+            8,
+            8,
+            ConcentratedCurve::LEN
+        ];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            bump_seed: bump_seed[0],
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            token_a: Pubkey::new_from_array(*token_a),
+            token_b: Pubkey::new_from_array(*token_b),
+            token_a_mint: Pubkey::new_from_array(*token_a_mint),
+            token_b_mint: Pubkey::new_from_array(*token_b_mint),
+            fee_owner: Pubkey::new_from_array(*fee_owner),
+            fees: Fees::unpack_from_slice(fees)?,
+            owner_fee_a: u64::from_le_bytes(*owner_fee_a),
+            owner_fee_b: u64::from_le_bytes(*owner_fee_b),
+            curve: ConcentratedCurve::unpack_from_slice(curve)?,
+        })
+    }
+}
+
+/// Liquidity provided to a `ConcentratedSwap` between two ticks, owned by
+/// whoever holds the single token of its position mint
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Position {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Swap the liquidity is provided to
+    pub swap: Pubkey,
+    /// Mint of the token representing the position
+    pub position_mint: Pubkey,
+    /// Lower tick of the range
+    pub tick_lower: i32,
+    /// Upper tick of the range
+    pub tick_upper: i32,
+    /// Liquidity provided between the ticks
+    pub liquidity: u128,
+    /// Token A fee growth inside the range when fees were last settled
+    pub fee_growth_inside_a: u128,
+    /// Token B fee growth inside the range when fees were last settled
+    pub fee_growth_inside_b: u128,
+    /// Token A fees settled but not yet paid out
+    pub fees_owed_a: u64,
+    /// Token B fees settled but not yet paid out
+    pub fees_owed_b: u64,
+}
+
+impl Position {
+    /// Credits the position with the fees earned by its liquidity since
+    /// they were last settled, given the current fee growths inside its
+    /// range
+    pub fn settle_fees(&mut self, fee_growth_inside: (u128, u128)) -> Option<()> {
+        let (fee_growth_inside_a, fee_growth_inside_b) = fee_growth_inside;
+        let earned = |fee_growth_inside: u128, fee_growth_inside_last: u128| {
+            let fee_growth = fee_growth_inside.wrapping_sub(fee_growth_inside_last);
+            let earned = U256::from(fee_growth).checked_mul(U256::from(self.liquidity))? >> 64;
+            u64::try_from(earned).ok()
+        };
+        let earned_a = earned(fee_growth_inside_a, self.fee_growth_inside_a)?;
+        let earned_b = earned(fee_growth_inside_b, self.fee_growth_inside_b)?;
+        self.fees_owed_a = self.fees_owed_a.checked_add(earned_a)?;
+        self.fees_owed_b = self.fees_owed_b.checked_add(earned_b)?;
+        self.fee_growth_inside_a = fee_growth_inside_a;
+        self.fee_growth_inside_b = fee_growth_inside_b;
+        Some(())
+    }
+}
+
+impl Sealed for Position {}
+impl IsInitialized for Position {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for Position {
+    const LEN: usize = 137;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 137];
+        let (
+            is_initialized,
+            swap,
+            position_mint,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            fee_growth_inside_a,
+            fee_growth_inside_b,
+            fees_owed_a,
+            fees_owed_b,
+        ) = mut_array_refs![output, 1, 32, 32, 4, 4, 16, 16, 16, 8, 8];
+        is_initialized[0] = self.is_initialized as u8;
+        swap.copy_from_slice(self.swap.as_ref());
+        position_mint.copy_from_slice(self.position_mint.as_ref());
+        *tick_lower = self.tick_lower.to_le_bytes();
+        *tick_upper = self.tick_upper.to_le_bytes();
+        *liquidity = self.liquidity.to_le_bytes();
+        *fee_growth_inside_a = self.fee_growth_inside_a.to_le_bytes();
+        *fee_growth_inside_b = self.fee_growth_inside_b.to_le_bytes();
+        *fees_owed_a = self.fees_owed_a.to_le_bytes();
+        *fees_owed_b = self.fees_owed_b.to_le_bytes();
+    }
+
+    /// Unpacks a byte buffer into a [Position](struct.Position.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 137];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            swap,
+            position_mint,
+            tick_lower,
+            tick_upper,
+            liquidity,
+            fee_growth_inside_a,
+            fee_growth_inside_b,
+            fees_owed_a,
+            fees_owed_b,
+        ) = array_refs![input, 1, 32, 32, 4, 4, 16, 16, 16, 8, 8];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            swap: Pubkey::new_from_array(*swap),
+            position_mint: Pubkey::new_from_array(*position_mint),
+            tick_lower: i32::from_le_bytes(*tick_lower),
+            tick_upper: i32::from_le_bytes(*tick_upper),
+            liquidity: u128::from_le_bytes(*liquidity),
+            fee_growth_inside_a: u128::from_le_bytes(*fee_growth_inside_a),
+            fee_growth_inside_b: u128::from_le_bytes(*fee_growth_inside_b),
+            fees_owed_a: u64::from_le_bytes(*fees_owed_a),
+            fees_owed_b: u64::from_le_bytes(*fees_owed_b),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::curve::{
+        concentrated::sqrt_price_at_tick, constant_price::PriceSource, stable::StableCurve,
+    };
+
+    use std::convert::TryInto;
+
+    const TEST_FEES: Fees = Fees {
+        trade_fee_numerator: 1,
+        trade_fee_denominator: 4,
+        owner_trade_fee_numerator: 3,
+        owner_trade_fee_denominator: 10,
+        owner_withdraw_fee_numerator: 2,
+        owner_withdraw_fee_denominator: 7,
+        host_fee_numerator: 5,
+        host_fee_denominator: 20,
+    };
+
+    const TEST_BUMP_SEED: u8 = 255;
+    const TEST_TOKEN_PROGRAM_ID: Pubkey = Pubkey::new_from_array([1u8; 32]);
+    const TEST_TOKEN_A: Pubkey = Pubkey::new_from_array([2u8; 32]);
+    const TEST_TOKEN_B: Pubkey = Pubkey::new_from_array([3u8; 32]);
+    const TEST_POOL_MINT: Pubkey = Pubkey::new_from_array([4u8; 32]);
+    const TEST_TOKEN_A_MINT: Pubkey = Pubkey::new_from_array([5u8; 32]);
+    const TEST_TOKEN_B_MINT: Pubkey = Pubkey::new_from_array([6u8; 32]);
+    const TEST_POOL_FEE_ACCOUNT: Pubkey = Pubkey::new_from_array([7u8; 32]);
+
+    const TEST_CURVE_TYPE: u8 = 2;
+    const TEST_AMP: u64 = 1;
+    const TEST_CURVE: StableCurve = StableCurve { amp: TEST_AMP };
+
+    const TEST_PRICE_A_CUMULATIVE: u128 = 1 << 100;
+    const TEST_PRICE_B_CUMULATIVE: u128 = u128::MAX - 5;
+    const TEST_LAST_UPDATE_TIMESTAMP: UnixTimestamp = 1_650_000_000;
+    const TEST_AMP_RAMP: AmpRamp = AmpRamp {
+        initial_amp: TEST_AMP,
+        target_amp: 10 * TEST_AMP,
+        start_timestamp: TEST_LAST_UPDATE_TIMESTAMP,
+        stop_timestamp: TEST_LAST_UPDATE_TIMESTAMP + 86_400,
+    };
+    const TEST_DYNAMIC_FEE: DynamicFee = DynamicFee {
+        min_trade_fee_numerator: 1,
+        max_trade_fee_numerator: 3,
+        max_volatility: 50_000,
+        volatility_half_life: 600,
+        volatility: 20_000,
+        last_update_timestamp: TEST_LAST_UPDATE_TIMESTAMP,
+    };
+    const TEST_FEE_TIER: u32 = 3_000;
+    const TEST_PRICE_ORACLE: PriceOracle = PriceOracle {
+        price_account: Pubkey::new_from_array([9u8; 32]),
+        price_source: PriceSource::SwapTokenB,
+        max_staleness: 600,
+        max_deviation_bps: 50,
+        price_timestamp: TEST_LAST_UPDATE_TIMESTAMP,
+        price_cumulative: 1 << 70,
+        observation_timestamp: TEST_LAST_UPDATE_TIMESTAMP - 300,
+    };
+
+    #[test]
+    fn swap_version_pack() {
+        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
+        let calculator = Arc::new(TEST_CURVE);
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+        let swap_info = SwapVersion::SwapV1(SwapV1 {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            pool_mint: TEST_POOL_MINT,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES,
+            swap_curve: swap_curve.clone(),
+        });
+
+        let mut packed = [0u8; SwapVersion::V1_LEN];
+        SwapVersion::pack(swap_info, &mut packed).unwrap();
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+
+        assert!(unpacked.is_initialized());
+        assert_eq!(unpacked.bump_seed(), TEST_BUMP_SEED);
+        assert_eq!(*unpacked.token_program_id(), TEST_TOKEN_PROGRAM_ID);
+        assert_eq!(*unpacked.token_a_account(), TEST_TOKEN_A);
+        assert_eq!(*unpacked.token_b_account(), TEST_TOKEN_B);
+        assert_eq!(*unpacked.pool_mint(), TEST_POOL_MINT);
+        assert_eq!(*unpacked.token_a_mint(), TEST_TOKEN_A_MINT);
+        assert_eq!(*unpacked.token_b_mint(), TEST_TOKEN_B_MINT);
+        assert_eq!(*unpacked.pool_fee_account(), TEST_POOL_FEE_ACCOUNT);
+        assert_eq!(*unpacked.fees(), TEST_FEES);
+        assert_eq!(*unpacked.swap_curve(), swap_curve);
+        assert_eq!(unpacked.price_observation(), None);
+        assert!(!unpacked.is_paused());
+        assert!(!unpacked.is_locked());
+        assert_eq!(unpacked.dynamic_fee(), None);
+        assert_eq!(unpacked.price_oracle(), None);
+        assert_eq!(unpacked.fee_tier(), None);
+
+        let swap_info = SwapVersion::SwapV2(SwapV2 {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            pool_mint: TEST_POOL_MINT,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES,
+            swap_curve: swap_curve.clone(),
+            price_a_cumulative: TEST_PRICE_A_CUMULATIVE,
+            price_b_cumulative: TEST_PRICE_B_CUMULATIVE,
+            last_update_timestamp: TEST_LAST_UPDATE_TIMESTAMP,
+            is_paused: true,
+            amp_ramp: TEST_AMP_RAMP,
+            is_locked: true,
+            dynamic_fee: TEST_DYNAMIC_FEE,
+            price_oracle: TEST_PRICE_ORACLE,
+            fee_tier: Some(TEST_FEE_TIER),
+        });
+
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(swap_info, &mut packed).unwrap();
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+
+        assert!(unpacked.is_initialized());
+        assert_eq!(unpacked.bump_seed(), TEST_BUMP_SEED);
+        assert_eq!(*unpacked.token_program_id(), TEST_TOKEN_PROGRAM_ID);
+        assert_eq!(*unpacked.token_a_account(), TEST_TOKEN_A);
+        assert_eq!(*unpacked.token_b_account(), TEST_TOKEN_B);
+        assert_eq!(*unpacked.pool_mint(), TEST_POOL_MINT);
+        assert_eq!(*unpacked.token_a_mint(), TEST_TOKEN_A_MINT);
+        assert_eq!(*unpacked.token_b_mint(), TEST_TOKEN_B_MINT);
+        assert_eq!(*unpacked.pool_fee_account(), TEST_POOL_FEE_ACCOUNT);
+        assert_eq!(*unpacked.fees(), TEST_FEES);
+        assert_eq!(*unpacked.swap_curve(), swap_curve);
+        assert_eq!(
+            unpacked.price_observation(),
+            Some(PriceObservation {
+                price_a_cumulative: TEST_PRICE_A_CUMULATIVE,
+                price_b_cumulative: TEST_PRICE_B_CUMULATIVE,
+                timestamp: TEST_LAST_UPDATE_TIMESTAMP,
+            })
+        );
+        assert!(unpacked.is_paused());
+        assert!(unpacked.is_locked());
+        assert_eq!(unpacked.dynamic_fee(), Some(TEST_DYNAMIC_FEE));
+        assert_eq!(unpacked.price_oracle(), Some(TEST_PRICE_ORACLE));
+        assert_eq!(unpacked.fee_tier(), Some(TEST_FEE_TIER));
+    }
+
+    #[test]
+    fn swap_v1_pack() {
+        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
+        let calculator = Arc::new(TEST_CURVE);
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+        let swap_info = SwapV1 {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            pool_mint: TEST_POOL_MINT,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES,
+            swap_curve,
+        };
+
+        let mut packed = [0u8; SwapV1::LEN];
+        SwapV1::pack_into_slice(&swap_info, &mut packed);
+        let unpacked = SwapV1::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        let mut packed = vec![1u8, TEST_BUMP_SEED];
+        packed.extend_from_slice(&TEST_TOKEN_PROGRAM_ID.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_A.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_B.to_bytes());
+        packed.extend_from_slice(&TEST_POOL_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_A_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_B_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_POOL_FEE_ACCOUNT.to_bytes());
+        packed.extend_from_slice(&TEST_FEES.trade_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.trade_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_trade_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_trade_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_withdraw_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_withdraw_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.host_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.host_fee_denominator.to_le_bytes());
+        packed.push(TEST_CURVE_TYPE);
+        packed.extend_from_slice(&TEST_AMP.to_le_bytes());
+        packed.extend_from_slice(&[0u8; 24]);
+        let unpacked = SwapV1::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        let packed = [0u8; SwapV1::LEN];
+        let swap_info: SwapV1 = Default::default();
+        let unpack_unchecked = SwapV1::unpack_unchecked(&packed).unwrap();
+        assert_eq!(unpack_unchecked, swap_info);
+        let err = SwapV1::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
+
+    #[test]
+    fn swap_v2_pack() {
+        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
+        let calculator = Arc::new(TEST_CURVE);
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+        let swap_info = SwapV2 {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            pool_mint: TEST_POOL_MINT,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES,
+            swap_curve,
+            price_a_cumulative: TEST_PRICE_A_CUMULATIVE,
+            price_b_cumulative: TEST_PRICE_B_CUMULATIVE,
+            last_update_timestamp: TEST_LAST_UPDATE_TIMESTAMP,
+            is_paused: true,
+            amp_ramp: TEST_AMP_RAMP,
+            is_locked: true,
+            dynamic_fee: TEST_DYNAMIC_FEE,
+            price_oracle: TEST_PRICE_ORACLE,
+            fee_tier: Some(TEST_FEE_TIER),
+        };
+
+        let mut packed = [0u8; SwapV2::LEN];
+        SwapV2::pack_into_slice(&swap_info, &mut packed);
+        let unpacked = SwapV2::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        // the V2 layout is the V1 layout followed by the price accumulators,
+        // the pause flag, the amp ramp, the lock flag, the dynamic fee, the
+        // price oracle and the fee tier
+        let mut packed_v1 = [0u8; SwapV1::LEN];
+        SwapV1::pack_into_slice(
+            &SwapV1::unpack_unchecked(&packed[..SwapV1::LEN]).unwrap(),
+            &mut packed_v1,
+        );
+        assert_eq!(packed[..SwapV1::LEN], packed_v1[..]);
+        let mut accumulators = vec![];
+        accumulators.extend_from_slice(&TEST_PRICE_A_CUMULATIVE.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_PRICE_B_CUMULATIVE.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_LAST_UPDATE_TIMESTAMP.to_le_bytes());
+        accumulators.push(1);
+        accumulators.extend_from_slice(&TEST_AMP_RAMP.initial_amp.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_AMP_RAMP.target_amp.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_AMP_RAMP.start_timestamp.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_AMP_RAMP.stop_timestamp.to_le_bytes());
+        accumulators.push(1);
+        accumulators.extend_from_slice(&TEST_DYNAMIC_FEE.min_trade_fee_numerator.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_DYNAMIC_FEE.max_trade_fee_numerator.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_DYNAMIC_FEE.max_volatility.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_DYNAMIC_FEE.volatility_half_life.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_DYNAMIC_FEE.volatility.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_DYNAMIC_FEE.last_update_timestamp.to_le_bytes());
+        accumulators.extend_from_slice(TEST_PRICE_ORACLE.price_account.as_ref());
+        accumulators.push(2);
+        accumulators.extend_from_slice(&TEST_PRICE_ORACLE.max_staleness.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_PRICE_ORACLE.max_deviation_bps.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_PRICE_ORACLE.price_timestamp.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_PRICE_ORACLE.price_cumulative.to_le_bytes());
+        accumulators.extend_from_slice(&TEST_PRICE_ORACLE.observation_timestamp.to_le_bytes());
+        accumulators.push(1);
+        accumulators.extend_from_slice(&TEST_FEE_TIER.to_le_bytes());
+        assert_eq!(packed[SwapV1::LEN..], accumulators[..]);
+
+        let packed = [0u8; SwapV2::LEN];
+        let swap_info: SwapV2 = Default::default();
+        let unpack_unchecked = SwapV2::unpack_unchecked(&packed).unwrap();
+        assert_eq!(unpack_unchecked, swap_info);
+        let err = SwapV2::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
+
+    #[test]
+    fn update_price_observation() {
+        let swap_curve = SwapCurve {
+            curve_type: TEST_CURVE_TYPE.try_into().unwrap(),
+            calculator: Arc::new(TEST_CURVE),
+        };
+        let swap_info = SwapV2 {
+            is_initialized: true,
+            swap_curve,
+            last_update_timestamp: TEST_LAST_UPDATE_TIMESTAMP,
+            ..Default::default()
+        };
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV2(swap_info), &mut packed).unwrap();
+        let start = SwapVersion::unpack(&packed)
+            .unwrap()
+            .price_observation()
+            .unwrap();
+
+        // a price of 2 B per A held for 10 seconds, then 1 B per 2 A for 30
+        SwapVersion::update_price_observation(
+            &mut packed,
+            1_000,
+            2_000,
+            TEST_LAST_UPDATE_TIMESTAMP + 10,
+        )
+        .unwrap();
+        SwapVersion::update_price_observation(
+            &mut packed,
+            2_000,
+            1_000,
+            TEST_LAST_UPDATE_TIMESTAMP + 40,
+        )
+        .unwrap();
+        let end = SwapVersion::unpack(&packed)
+            .unwrap()
+            .price_observation()
+            .unwrap();
+        assert_eq!(end.timestamp, TEST_LAST_UPDATE_TIMESTAMP + 40);
+
+        let one = 1u128 << PRICE_FRACTIONAL_BITS;
+        let (price_a, price_b) = end.time_weighted_average_prices(&start).unwrap();
+        assert_eq!(price_a, (2 * one * 10 + one / 2 * 30) / 40);
+        assert_eq!(price_b, (one / 2 * 10 + 2 * one * 30) / 40);
+
+        // no time has passed between identical observations
+        assert_eq!(end.time_weighted_average_prices(&end), None);
+        assert_eq!(start.time_weighted_average_prices(&end), None);
+
+        // accumulators wrap, but differences stay correct
+        let wrapping = PriceObservation {
+            price_a_cumulative: u128::MAX,
+            price_b_cumulative: u128::MAX - one,
+            timestamp: 0,
+        };
+        let later = wrapping.accumulate(1, 1, 7);
+        assert_eq!(
+            later.time_weighted_average_prices(&wrapping),
+            Some((one, one))
+        );
+
+        // V1 swaps have no accumulators to update
+        let mut packed = [0u8; SwapVersion::V1_LEN];
+        packed[0] = 1;
+        SwapVersion::update_price_observation(&mut packed, 1, 1, 1).unwrap();
+        assert_eq!(packed[1..], [0u8; SwapV1::LEN][..]);
+    }
+
+    #[test]
+    fn update_amp_ramp() {
+        let swap_info = SwapV2 {
+            is_initialized: true,
+            swap_curve: SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(TEST_CURVE),
+            },
+            amp_ramp: TEST_AMP_RAMP,
+            ..Default::default()
+        };
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV2(swap_info), &mut packed).unwrap();
+        let amp_at = |packed: &[u8]| match SwapVersion::unpack_version(packed).unwrap() {
+            SwapVersion::SwapV2(swap_info) => {
+                let mut packed_curve = [0u8; SwapCurve::LEN];
+                swap_info.swap_curve.pack_into_slice(&mut packed_curve);
+                (
+                    StableCurve::unpack_from_slice(&packed_curve[1..])
+                        .unwrap()
+                        .amp,
+                    swap_info.amp_ramp,
+                )
+            }
+            SwapVersion::SwapV1(_) => panic!("expected a SwapV2"),
+        };
+
+        SwapVersion::update_amp_ramp(&mut packed, TEST_LAST_UPDATE_TIMESTAMP + 43_200).unwrap();
+        assert_eq!(
+            amp_at(&packed),
+            (5 * TEST_AMP + TEST_AMP / 2, TEST_AMP_RAMP)
+        );
+
+        // the finished ramp stops updating the curve
+        SwapVersion::update_amp_ramp(&mut packed, TEST_LAST_UPDATE_TIMESTAMP + 90_000).unwrap();
+        let (amp, amp_ramp) = amp_at(&packed);
+        assert_eq!(amp, TEST_AMP_RAMP.target_amp);
+        assert!(!amp_ramp.is_active());
+        let finished = packed;
+        SwapVersion::update_amp_ramp(&mut packed, TEST_LAST_UPDATE_TIMESTAMP + 100_000).unwrap();
+        assert_eq!(packed, finished);
+    }
+
+    #[test]
+    fn update_dynamic_fee() {
+        let swap_info = SwapV2 {
+            is_initialized: true,
+            swap_curve: SwapCurve {
+                curve_type: TEST_CURVE_TYPE.try_into().unwrap(),
+                calculator: Arc::new(TEST_CURVE),
+            },
+            fees: TEST_FEES,
+            dynamic_fee: TEST_DYNAMIC_FEE,
+            ..Default::default()
+        };
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(SwapVersion::SwapV2(swap_info), &mut packed).unwrap();
+        let fees_at = |packed: &[u8]| {
+            let swap_info = SwapVersion::unpack(packed).unwrap();
+            (
+                swap_info.fees().trade_fee_numerator,
+                swap_info.dynamic_fee().unwrap(),
+            )
+        };
+
+        // one half life later, the volatility has halved
+        SwapVersion::update_dynamic_fee(&mut packed, TEST_LAST_UPDATE_TIMESTAMP + 600).unwrap();
+        let (trade_fee_numerator, dynamic_fee) = fees_at(&packed);
+        assert_eq!(dynamic_fee.volatility, 10_000);
+        assert_eq!(
+            dynamic_fee.last_update_timestamp,
+            TEST_LAST_UPDATE_TIMESTAMP + 600
+        );
+        assert_eq!(trade_fee_numerator, 1);
+
+        // a swap doubling the price reaches the maximum fee
+        SwapVersion::accumulate_volatility(&mut packed, 100, 100, 100, 200).unwrap();
+        let (_, dynamic_fee) = fees_at(&packed);
+        assert_eq!(dynamic_fee.volatility, 1_010_000);
+        SwapVersion::update_dynamic_fee(&mut packed, TEST_LAST_UPDATE_TIMESTAMP + 600).unwrap();
+        assert_eq!(fees_at(&packed).0, 3);
+
+        // swaps without a dynamic fee are left untouched
+        let mut packed = [0u8; SwapVersion::LATEST_LEN];
+        SwapVersion::pack(
+            SwapVersion::SwapV2(SwapV2 {
+                is_initialized: true,
+                swap_curve: SwapCurve {
+                    curve_type: TEST_CURVE_TYPE.try_into().unwrap(),
+                    calculator: Arc::new(TEST_CURVE),
+                },
+                fees: TEST_FEES,
+                ..Default::default()
+            }),
+            &mut packed,
+        )
+        .unwrap();
+        let unchanged = packed;
+        SwapVersion::update_dynamic_fee(&mut packed, TEST_LAST_UPDATE_TIMESTAMP).unwrap();
+        SwapVersion::accumulate_volatility(&mut packed, 100, 100, 100, 200).unwrap();
+        assert_eq!(packed, unchanged);
+    }
+
+    #[test]
+    fn multi_stable_swap_pack() {
+        let swap_info = MultiStableSwap {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            pool_mint: TEST_POOL_MINT,
+            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES,
+            amp: TEST_AMP,
+            token_count: 3,
+            tokens: [
+                TEST_TOKEN_A,
+                TEST_TOKEN_B,
+                TEST_TOKEN_PROGRAM_ID,
+                Pubkey::default(),
+            ],
+            token_mints: [
+                TEST_TOKEN_A_MINT,
+                TEST_TOKEN_B_MINT,
+                TEST_POOL_MINT,
+                Pubkey::default(),
+            ],
+        };
+
+        let mut packed = [0u8; MultiStableSwap::ACCOUNT_LEN];
+        MultiStableSwap::pack_account(swap_info, &mut packed).unwrap();
+        let unpacked = MultiStableSwap::unpack_account(&packed).unwrap();
+        assert!(unpacked.is_initialized);
+        assert_eq!(
+            unpacked.tokens(),
+            &[TEST_TOKEN_A, TEST_TOKEN_B, TEST_TOKEN_PROGRAM_ID]
+        );
+        assert_eq!(
+            unpacked.token_mints(),
+            &[TEST_TOKEN_A_MINT, TEST_TOKEN_B_MINT, TEST_POOL_MINT]
+        );
+        assert_eq!(unpacked.fees, TEST_FEES);
+        assert_eq!(unpacked.amp, TEST_AMP);
+
+        let mut expected = vec![MultiStableSwap::VERSION, 1u8, TEST_BUMP_SEED];
+        expected.extend_from_slice(&TEST_TOKEN_PROGRAM_ID.to_bytes());
+        expected.extend_from_slice(&TEST_POOL_MINT.to_bytes());
+        expected.extend_from_slice(&TEST_POOL_FEE_ACCOUNT.to_bytes());
+        let mut packed_fees = [0u8; Fees::LEN];
+        TEST_FEES.pack_into_slice(&mut packed_fees);
+        expected.extend_from_slice(&packed_fees);
+        expected.extend_from_slice(&TEST_AMP.to_le_bytes());
+        expected.push(3);
+        for key in [
+            TEST_TOKEN_A,
+            TEST_TOKEN_B,
+            TEST_TOKEN_PROGRAM_ID,
+            Pubkey::default(),
+        ]
+        .iter()
+        {
+            expected.extend_from_slice(&key.to_bytes());
+        }
+        for key in [
+            TEST_TOKEN_A_MINT,
+            TEST_TOKEN_B_MINT,
+            TEST_POOL_MINT,
+            Pubkey::default(),
+        ]
+        .iter()
+        {
+            expected.extend_from_slice(&key.to_bytes());
+        }
+        assert_eq!(packed.to_vec(), expected);
+        assert!(SwapVersion::is_initialized(&packed));
+        // neither kind of account unpacks as the other
+        assert_eq!(
+            SwapVersion::unpack(&packed).err(),
+            Some(ProgramError::UninitializedAccount)
+        );
+        let mut packed_v2 = [0u8; SwapVersion::LATEST_LEN];
+        packed_v2[0] = 2;
+        assert_eq!(
+            MultiStableSwap::unpack_account(&packed_v2),
+            Err(ProgramError::UninitializedAccount)
+        );
+
+        // five tokens don't fit
+        packed[1 + 1 + 1 + 32 * 3 + Fees::LEN + 8] = 5;
+        assert_eq!(
+            MultiStableSwap::unpack_account(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn concentrated_swap_pack() {
+        let mut curve = ConcentratedCurve::new(8, sqrt_price_at_tick(-3).unwrap()).unwrap();
+        curve.update_liquidity(-16, 64, 1_000_000).unwrap();
+        curve.fee_growth_global_b = TEST_PRICE_B_CUMULATIVE;
+        let swap_info = ConcentratedSwap {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            fee_owner: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES,
+            owner_fee_a: 5,
+            owner_fee_b: u64::MAX,
+            curve: curve.clone(),
+        };
+
+        let mut packed = vec![0u8; ConcentratedSwap::ACCOUNT_LEN];
+        ConcentratedSwap::pack_account(swap_info, &mut packed).unwrap();
+        let unpacked = ConcentratedSwap::unpack_account(&packed).unwrap();
+        assert!(unpacked.is_initialized);
+        assert_eq!(unpacked.token_a_mint, TEST_TOKEN_A_MINT);
+        assert_eq!(unpacked.fee_owner, TEST_POOL_FEE_ACCOUNT);
+        assert_eq!(unpacked.fees, TEST_FEES);
+        assert_eq!(unpacked.owner_fee_b, u64::MAX);
+        assert_eq!(unpacked.curve, curve);
+
+        let mut expected = vec![ConcentratedSwap::VERSION, 1u8, TEST_BUMP_SEED];
+        for key in [
+            TEST_TOKEN_PROGRAM_ID,
+            TEST_TOKEN_A,
+            TEST_TOKEN_B,
+            TEST_TOKEN_A_MINT,
+            TEST_TOKEN_B_MINT,
+            TEST_POOL_FEE_ACCOUNT,
+        ]
+        .iter()
+        {
+            expected.extend_from_slice(&key.to_bytes());
+        }
+        let mut packed_fees = [0u8; Fees::LEN];
+        TEST_FEES.pack_into_slice(&mut packed_fees);
+        expected.extend_from_slice(&packed_fees);
+        expected.extend_from_slice(&5u64.to_le_bytes());
+        expected.extend_from_slice(&u64::MAX.to_le_bytes());
+        let mut packed_curve = vec![0u8; ConcentratedCurve::LEN];
+        curve.pack_into_slice(&mut packed_curve);
+        expected.extend_from_slice(&packed_curve);
+        assert_eq!(packed, expected);
+        assert!(SwapVersion::is_initialized(&packed));
+        assert_eq!(
+            SwapVersion::unpack(&packed).err(),
+            Some(ProgramError::UninitializedAccount)
+        );
+        assert_eq!(
+            MultiStableSwap::unpack_account(&packed),
+            Err(ProgramError::UninitializedAccount)
+        );
+    }
+
+    #[test]
+    fn position_pack() {
+        let position = Position {
+            is_initialized: true,
+            swap: TEST_TOKEN_A,
+            position_mint: TEST_POOL_MINT,
+            tick_lower: -64,
+            tick_upper: 128,
+            liquidity: 1 << 70,
+            fee_growth_inside_a: TEST_PRICE_A_CUMULATIVE,
+            fee_growth_inside_b: TEST_PRICE_B_CUMULATIVE,
+            fees_owed_a: 10,
+            fees_owed_b: 20,
+        };
+        let mut packed = [0u8; Position::LEN];
+        Position::pack(position.clone(), &mut packed).unwrap();
+        assert_eq!(Position::unpack(&packed).unwrap(), position);
+
+        let mut expected = vec![1u8];
+        expected.extend_from_slice(&TEST_TOKEN_A.to_bytes());
+        expected.extend_from_slice(&TEST_POOL_MINT.to_bytes());
+        expected.extend_from_slice(&(-64i32).to_le_bytes());
+        expected.extend_from_slice(&128i32.to_le_bytes());
+        expected.extend_from_slice(&(1u128 << 70).to_le_bytes());
+        expected.extend_from_slice(&TEST_PRICE_A_CUMULATIVE.to_le_bytes());
+        expected.extend_from_slice(&TEST_PRICE_B_CUMULATIVE.to_le_bytes());
+        expected.extend_from_slice(&10u64.to_le_bytes());
+        expected.extend_from_slice(&20u64.to_le_bytes());
+        assert_eq!(packed.to_vec(), expected);
+
+        let packed = [0u8; Position::LEN];
+        assert_eq!(
+            Position::unpack(&packed),
+            Err(ProgramError::UninitializedAccount)
+        );
+    }
+
+    #[test]
+    fn position_settle_fees() {
+        let mut position = Position {
+            liquidity: 1_000,
+            fee_growth_inside_a: u128::MAX - (1 << 64) + 1,
+            fees_owed_b: 7,
+            ..Position::default()
+        };
+        // the fee growth wraps around
+        position.settle_fees((1 << 64, 3 << 63)).unwrap();
+        assert_eq!(position.fees_owed_a, 2_000);
+        assert_eq!(position.fees_owed_b, 1_507);
+        assert_eq!(position.fee_growth_inside_a, 1 << 64);
+        position.settle_fees((1 << 64, 3 << 63)).unwrap();
+        assert_eq!(position.fees_owed_a, 2_000);
+        assert_eq!(position.fees_owed_b, 1_507);
     }
 }
@@ -0,0 +1,1020 @@
+/// The `apply` subcommand
+use {
+    crate::{
+        config::Config,
+        get_signer, handle_tx, new_throwaway_signer,
+        output::{println_display, CliMintApply},
+        CommandName, CommandResult, Error, TransactionReturnData,
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    clap::{App, Arg, ArgMatches, SubCommand},
+    serde::Deserialize,
+    solana_clap_utils::{input_validators::is_valid_signer, keypair::CliSignerInfo},
+    solana_remote_wallet::remote_wallet::RemoteWalletManager,
+    solana_sdk::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        signature::Signer,
+        system_instruction, system_program,
+    },
+    spl_associated_token_account::{
+        get_associated_token_address_with_program_id,
+        instruction::create_associated_token_account_idempotent,
+    },
+    spl_token_2022::{
+        extension::{
+            default_account_state::DefaultAccountState,
+            interest_bearing_mint::{self, InterestBearingConfig},
+            mint_close_authority::MintCloseAuthority,
+            transfer_fee::{self, TransferFeeConfig, MAX_FEE_BASIS_POINTS},
+            ExtensionType, StateWithExtensionsOwned,
+        },
+        instruction::{initialize_mint, mint_to_checked, set_authority, AuthorityType},
+        state::{AccountState, Mint},
+    },
+    spl_token_client::token::ExtensionInitializationParams,
+    spl_token_metadata::{
+        instruction::{CreateMetadataAccountArgs, MetadataInstruction, UpdateMetadataAccountArgs},
+        pda::PREFIX,
+        state::{Data, Metadata, MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH},
+    },
+    std::{
+        convert::TryFrom,
+        fs,
+        io::{self, Write},
+        path::Path,
+        str::FromStr,
+        sync::Arc,
+    },
+};
+
+const EXAMPLE_FILE: &str = r#"EXAMPLE:
+    address = "<MINT_ADDRESS>"
+    decimals = 6
+
+    [authorities]
+    mint = "<PUBKEY>"
+    freeze = "none"
+
+    [extensions.transfer-fee]
+    basis-points = 50
+    maximum-fee = 5000.0
+
+    [[distribution]]
+    recipient = "<WALLET_ADDRESS>"
+    amount = 1000000.0
+
+    [metadata]
+    name = "Example"
+    symbol = "EXMPL"
+    uri = "https://example.com/token.json""#;
+
+pub(crate) trait ApplySubCommand {
+    fn apply_subcommand(self) -> Self;
+}
+
+impl ApplySubCommand for App<'_, '_> {
+    fn apply_subcommand(self) -> Self {
+        self.subcommand(
+            SubCommand::with_name(CommandName::Apply.into())
+                .about("Create or update a mint to match a declarative TOML description")
+                .after_help(EXAMPLE_FILE)
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .required(true)
+                        .help(
+                            "Path to a TOML file describing the mint: its decimals, \
+                             authorities, extensions, initial supply distribution and metadata. \
+                             An authority that is omitted is left as it is, `none` disables it",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("mint_keypair")
+                        .long("mint-keypair")
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .help(
+                            "Keypair of the mint to create when it does not exist yet. \
+                             [default: the keypair must be given if the file names an address, \
+                             otherwise a randomly generated keypair]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("authority")
+                        .long("authority")
+                        .value_name("SIGNER")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .help(
+                            "Specify the current authority of every setting that changes, \
+                             and the mint authority of a newly created mint. \
+                             Defaults to the client keypair.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help(
+                            "Print the changes the file calls for without sending any transactions",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .short("y")
+                        .takes_value(false)
+                        .help("Apply the changes without asking for confirmation"),
+                ),
+        )
+    }
+}
+
+pub(crate) async fn apply_process_command(
+    arg_matches: &ArgMatches<'_>,
+    config: &Config<'_>,
+    mut signers: Vec<Box<dyn Signer>>,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+) -> CommandResult {
+    assert!(!config.sign_only);
+
+    let path = Path::new(arg_matches.value_of("file").unwrap());
+    let spec = MintSpec::load(path)?;
+    let (authority_signer, authority) =
+        config.signer_or_default(arg_matches, "authority", wallet_manager);
+    signers.push(authority_signer);
+    let mint_signer = get_signer(arg_matches, "mint_keypair", wallet_manager);
+    let dry_run = arg_matches.is_present("dry_run");
+    let skip_confirmation = arg_matches.is_present("yes");
+
+    let address = match (spec.address, mint_signer) {
+        (Some(address), Some((signer, pubkey))) => {
+            if address != pubkey {
+                return Err(format!(
+                    "--mint-keypair {} does not match the address {} in {}",
+                    pubkey,
+                    address,
+                    path.display()
+                )
+                .into());
+            }
+            signers.push(signer);
+            address
+        }
+        (Some(address), None) => address,
+        (None, Some((signer, pubkey))) => {
+            signers.push(signer);
+            pubkey
+        }
+        (None, None) => {
+            let (signer, pubkey) = new_throwaway_signer();
+            signers.push(signer);
+            pubkey
+        }
+    };
+
+    command_apply(
+        config,
+        path,
+        &spec,
+        address,
+        authority,
+        dry_run,
+        skip_confirmation,
+        signers,
+    )
+    .await
+}
+
+/// Contents of the mint description file
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct MintSpecFile {
+    address: Option<String>,
+    program_id: Option<String>,
+    decimals: u8,
+    #[serde(default)]
+    authorities: AuthoritiesSpec,
+    #[serde(default)]
+    extensions: ExtensionsSpec,
+    #[serde(default)]
+    distribution: Vec<DistributionSpec>,
+    metadata: Option<MetadataSpec>,
+}
+
+/// Authorities are given as addresses, or `none` to disable them
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct AuthoritiesSpec {
+    mint: Option<String>,
+    freeze: Option<String>,
+    close_mint: Option<String>,
+    transfer_fee_config: Option<String>,
+    withheld_withdraw: Option<String>,
+    interest_rate: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ExtensionsSpec {
+    transfer_fee: Option<TransferFeeSpec>,
+    interest_bearing: Option<InterestBearingSpec>,
+    default_account_state: Option<String>,
+    #[serde(default)]
+    non_transferable: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct TransferFeeSpec {
+    basis_points: u16,
+    /// In tokens, not base units
+    maximum_fee: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct InterestBearingSpec {
+    rate: i16,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct DistributionSpec {
+    recipient: String,
+    /// In tokens, not base units
+    amount: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct MetadataSpec {
+    name: String,
+    symbol: String,
+    uri: String,
+}
+
+/// The mint description with every address parsed
+struct MintSpec {
+    address: Option<Pubkey>,
+    program_id: Option<Pubkey>,
+    decimals: u8,
+    /// Declared authorities, `None` meaning the authority is disabled
+    authorities: Vec<(AuthorityType, Option<Pubkey>)>,
+    transfer_fee: Option<TransferFeeSpec>,
+    interest_bearing: Option<InterestBearingSpec>,
+    default_account_state: Option<AccountState>,
+    non_transferable: bool,
+    distribution: Vec<(Pubkey, f64)>,
+    metadata: Option<MetadataSpec>,
+}
+
+impl MintSpec {
+    fn load(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let file: MintSpecFile = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid mint description {}: {}", path.display(), e))?;
+
+        let parse_pubkey = |field: &str, value: &str| {
+            Pubkey::from_str(value).map_err(|_| format!("Invalid {}: {}", field, value))
+        };
+        let address = file
+            .address
+            .map(|address| parse_pubkey("address", &address))
+            .transpose()?;
+        let program_id = file
+            .program_id
+            .map(|program_id| parse_pubkey("program-id", &program_id))
+            .transpose()?;
+
+        let mut authorities = vec![];
+        for (authority_type, field, value) in [
+            (AuthorityType::MintTokens, "mint", file.authorities.mint),
+            (
+                AuthorityType::FreezeAccount,
+                "freeze",
+                file.authorities.freeze,
+            ),
+            (
+                AuthorityType::CloseMint,
+                "close-mint",
+                file.authorities.close_mint,
+            ),
+            (
+                AuthorityType::TransferFeeConfig,
+                "transfer-fee-config",
+                file.authorities.transfer_fee_config,
+            ),
+            (
+                AuthorityType::WithheldWithdraw,
+                "withheld-withdraw",
+                file.authorities.withheld_withdraw,
+            ),
+            (
+                AuthorityType::InterestRate,
+                "interest-rate",
+                file.authorities.interest_rate,
+            ),
+        ] {
+            if let Some(value) = value {
+                let authority = if value == "none" {
+                    None
+                } else {
+                    Some(parse_pubkey(&format!("{} authority", field), &value)?)
+                };
+                authorities.push((authority_type, authority));
+            }
+        }
+
+        let extensions = file.extensions;
+        if let Some(transfer_fee) = &extensions.transfer_fee {
+            if transfer_fee.basis_points > MAX_FEE_BASIS_POINTS {
+                return Err(format!(
+                    "Transfer fee of {} basis points exceeds the maximum of {}",
+                    transfer_fee.basis_points, MAX_FEE_BASIS_POINTS
+                )
+                .into());
+            }
+        }
+        let has_authority = |authority_type| authorities.iter().any(|(t, _)| *t == authority_type);
+        if extensions.transfer_fee.is_none()
+            && (has_authority(AuthorityType::TransferFeeConfig)
+                || has_authority(AuthorityType::WithheldWithdraw))
+        {
+            return Err("Transfer fee authorities require the transfer-fee extension".into());
+        }
+        if extensions.interest_bearing.is_none() && has_authority(AuthorityType::InterestRate) {
+            return Err(
+                "The interest-rate authority requires the interest-bearing extension".into(),
+            );
+        }
+        let default_account_state = extensions
+            .default_account_state
+            .map(|state| match state.as_str() {
+                "initialized" => Ok(AccountState::Initialized),
+                "frozen" => Ok(AccountState::Frozen),
+                _ => Err(format!(
+                    "Invalid default-account-state {}, expected `initialized` or `frozen`",
+                    state
+                )),
+            })
+            .transpose()?;
+
+        let distribution = file
+            .distribution
+            .into_iter()
+            .map(|entry| {
+                Ok((
+                    parse_pubkey("distribution recipient", &entry.recipient)?,
+                    entry.amount,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some(metadata) = &file.metadata {
+            if metadata.name.len() > MAX_NAME_LENGTH
+                || metadata.symbol.len() > MAX_SYMBOL_LENGTH
+                || metadata.uri.len() > MAX_URI_LENGTH
+            {
+                return Err(format!(
+                    "Metadata name, symbol and uri are limited to {}, {} and {} bytes",
+                    MAX_NAME_LENGTH, MAX_SYMBOL_LENGTH, MAX_URI_LENGTH
+                )
+                .into());
+            }
+        }
+
+        Ok(Self {
+            address,
+            program_id,
+            decimals: file.decimals,
+            authorities,
+            transfer_fee: extensions.transfer_fee,
+            interest_bearing: extensions.interest_bearing,
+            default_account_state,
+            non_transferable: extensions.non_transferable,
+            distribution,
+            metadata: file.metadata,
+        })
+    }
+
+    fn authority(&self, authority_type: AuthorityType) -> Option<Option<Pubkey>> {
+        self.authorities
+            .iter()
+            .find(|(t, _)| *t == authority_type)
+            .map(|(_, authority)| *authority)
+    }
+
+    fn extension_types(&self) -> Vec<ExtensionType> {
+        let mut extension_types = vec![];
+        if self.transfer_fee.is_some() {
+            extension_types.push(ExtensionType::TransferFeeConfig);
+        }
+        if self.authority(AuthorityType::CloseMint).is_some() {
+            extension_types.push(ExtensionType::MintCloseAuthority);
+        }
+        if self.default_account_state.is_some() {
+            extension_types.push(ExtensionType::DefaultAccountState);
+        }
+        if self.non_transferable {
+            extension_types.push(ExtensionType::NonTransferable);
+        }
+        if self.interest_bearing.is_some() {
+            extension_types.push(ExtensionType::InterestBearingConfig);
+        }
+        extension_types
+    }
+}
+
+/// One step of bringing the mint in line with its description, sent as its
+/// own transaction
+struct Change {
+    description: String,
+    instructions: Vec<Instruction>,
+    minimum_balance_for_rent_exemption: u64,
+}
+
+impl Change {
+    fn new(description: String, instructions: Vec<Instruction>) -> Self {
+        Self {
+            description,
+            instructions,
+            minimum_balance_for_rent_exemption: 0,
+        }
+    }
+}
+
+fn authority_name(authority_type: &AuthorityType) -> &'static str {
+    match authority_type {
+        AuthorityType::MintTokens => "mint",
+        AuthorityType::FreezeAccount => "freeze",
+        AuthorityType::AccountOwner => "owner",
+        AuthorityType::CloseAccount => "close",
+        AuthorityType::CloseMint => "close-mint",
+        AuthorityType::TransferFeeConfig => "transfer-fee-config",
+        AuthorityType::WithheldWithdraw => "withheld-withdraw",
+        AuthorityType::InterestRate => "interest-rate",
+    }
+}
+
+fn display_authority(authority: &Option<Pubkey>) -> String {
+    authority
+        .map(|pubkey| pubkey.to_string())
+        .unwrap_or_else(|| "disabled".to_string())
+}
+
+fn metadata_address(mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            PREFIX.as_bytes(),
+            spl_token_metadata::id().as_ref(),
+            mint.as_ref(),
+        ],
+        &spl_token_metadata::id(),
+    )
+    .0
+}
+
+fn create_metadata_instruction(
+    mint: &Pubkey,
+    mint_authority: &Pubkey,
+    payer: &Pubkey,
+    metadata: &MetadataSpec,
+) -> Result<Instruction, Error> {
+    let data = MetadataInstruction::CreateMetadataAccount(CreateMetadataAccountArgs {
+        data: Data {
+            name: metadata.name.clone(),
+            symbol: metadata.symbol.clone(),
+            uri: metadata.uri.clone(),
+            ..Data::default()
+        },
+        is_mutable: true,
+        collection_details: None,
+    })
+    .try_to_vec()?;
+    Ok(Instruction {
+        program_id: spl_token_metadata::id(),
+        accounts: vec![
+            AccountMeta::new(metadata_address(mint), false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*mint_authority, true),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(*mint_authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data,
+    })
+}
+
+fn update_metadata_instruction(
+    mint: &Pubkey,
+    update_authority: &Pubkey,
+    data: Data,
+) -> Result<Instruction, Error> {
+    let data = MetadataInstruction::UpdateMetadataAccount(UpdateMetadataAccountArgs {
+        data: Some(data),
+        update_authority: None,
+        primary_sale_happened: None,
+        is_mutable: None,
+    })
+    .try_to_vec()?;
+    Ok(Instruction {
+        program_id: spl_token_metadata::id(),
+        accounts: vec![
+            AccountMeta::new(metadata_address(mint), false),
+            AccountMeta::new_readonly(*update_authority, true),
+        ],
+        data,
+    })
+}
+
+fn distribution_changes(
+    spec: &MintSpec,
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    mint_authority: &Pubkey,
+    payer: &Pubkey,
+) -> Result<Vec<Change>, Error> {
+    spec.distribution
+        .iter()
+        .map(|(recipient, ui_amount)| {
+            let account = get_associated_token_address_with_program_id(recipient, mint, program_id);
+            Ok(Change::new(
+                format!("mint {} tokens to {}", ui_amount, recipient),
+                vec![
+                    create_associated_token_account_idempotent(payer, recipient, mint, program_id),
+                    mint_to_checked(
+                        program_id,
+                        mint,
+                        &account,
+                        mint_authority,
+                        &[],
+                        spl_token::ui_amount_to_amount(*ui_amount, spec.decimals),
+                        spec.decimals,
+                    )?,
+                ],
+            ))
+        })
+        .collect()
+}
+
+/// Changes that create the mint with its final configuration. Only the mint
+/// authority starts out as `authority`, so that it can create the metadata and
+/// the initial supply before being handed over.
+async fn plan_create(
+    config: &Config<'_>,
+    spec: &MintSpec,
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    authority: &Pubkey,
+) -> Result<Vec<Change>, Error> {
+    let declared_or_authority =
+        |authority_type| spec.authority(authority_type).unwrap_or(Some(*authority));
+    let mut extensions = vec![];
+    if let Some(transfer_fee) = &spec.transfer_fee {
+        extensions.push(ExtensionInitializationParams::TransferFeeConfig {
+            transfer_fee_config_authority: declared_or_authority(AuthorityType::TransferFeeConfig),
+            withdraw_withheld_authority: declared_or_authority(AuthorityType::WithheldWithdraw),
+            transfer_fee_basis_points: transfer_fee.basis_points,
+            maximum_fee: spl_token::ui_amount_to_amount(transfer_fee.maximum_fee, spec.decimals),
+        });
+    }
+    if let Some(close_authority) = spec.authority(AuthorityType::CloseMint) {
+        extensions.push(ExtensionInitializationParams::MintCloseAuthority { close_authority });
+    }
+    if let Some(state) = spec.default_account_state {
+        extensions.push(ExtensionInitializationParams::DefaultAccountState { state });
+    }
+    if spec.non_transferable {
+        extensions.push(ExtensionInitializationParams::NonTransferable);
+    }
+    if let Some(interest_bearing) = &spec.interest_bearing {
+        extensions.push(ExtensionInitializationParams::InterestBearingConfig {
+            rate_authority: declared_or_authority(AuthorityType::InterestRate),
+            rate: interest_bearing.rate,
+        });
+    }
+    if !extensions.is_empty() && *program_id != spl_token_2022::id() {
+        return Err(format!(
+            "Error: mint extensions are only supported by token program {}",
+            spl_token_2022::id()
+        )
+        .into());
+    }
+
+    let extension_types = spec.extension_types();
+    let space = ExtensionType::get_account_len::<Mint>(&extension_types);
+    let minimum_balance_for_rent_exemption = config
+        .program_client
+        .get_minimum_balance_for_rent_exemption(space)
+        .await?;
+    let freeze_authority = spec.authority(AuthorityType::FreezeAccount).flatten();
+
+    let mut instructions = vec![system_instruction::create_account(
+        &config.fee_payer,
+        mint,
+        minimum_balance_for_rent_exemption,
+        space as u64,
+        program_id,
+    )];
+    // Every mint extension must be initialized before the mint itself
+    for params in extensions {
+        instructions.push(params.instruction(program_id, mint)?);
+    }
+    instructions.push(initialize_mint(
+        program_id,
+        mint,
+        authority,
+        freeze_authority.as_ref(),
+        spec.decimals,
+    )?);
+    let mut changes = vec![Change {
+        description: format!(
+            "create mint with {} decimals, freeze authority {}, extensions {:?}",
+            spec.decimals,
+            display_authority(&freeze_authority),
+            extension_types
+        ),
+        instructions,
+        minimum_balance_for_rent_exemption,
+    }];
+
+    if let Some(metadata) = &spec.metadata {
+        changes.push(Change::new(
+            format!(
+                "create metadata {} ({}) {}",
+                metadata.name, metadata.symbol, metadata.uri
+            ),
+            vec![create_metadata_instruction(
+                mint,
+                authority,
+                &config.fee_payer,
+                metadata,
+            )?],
+        ));
+    }
+    changes.extend(distribution_changes(
+        spec,
+        program_id,
+        mint,
+        authority,
+        &config.fee_payer,
+    )?);
+    if let Some(mint_authority) = spec.authority(AuthorityType::MintTokens) {
+        if mint_authority != Some(*authority) {
+            changes.push(Change::new(
+                format!(
+                    "set mint authority to {}",
+                    display_authority(&mint_authority)
+                ),
+                vec![set_authority(
+                    program_id,
+                    mint,
+                    mint_authority.as_ref(),
+                    AuthorityType::MintTokens,
+                    authority,
+                    &[],
+                )?],
+            ));
+        }
+    }
+    Ok(changes)
+}
+
+/// Changes that bring an existing mint in line with its description.
+/// Settings that cannot be changed after creation are reported as errors.
+async fn plan_update(
+    config: &Config<'_>,
+    spec: &MintSpec,
+    program_id: &Pubkey,
+    mint: &Pubkey,
+    authority: &Pubkey,
+    data: Vec<u8>,
+) -> Result<Vec<Change>, Error> {
+    let state = StateWithExtensionsOwned::<Mint>::unpack(data)
+        .map_err(|_| format!("Could not find mint account {}", mint))?;
+    if state.base.decimals != spec.decimals {
+        return Err(format!(
+            "Mint {} has {} decimals, which cannot be changed to {}",
+            mint, state.base.decimals, spec.decimals
+        )
+        .into());
+    }
+    let existing_extensions = state.get_extension_types()?;
+    for extension_type in spec.extension_types() {
+        if !existing_extensions.contains(&extension_type) {
+            return Err(format!(
+                "Mint {} does not have the {:?} extension, \
+                 which can only be enabled when the mint is created",
+                mint, extension_type
+            )
+            .into());
+        }
+    }
+    if let Some(desired) = spec.default_account_state {
+        let current = AccountState::try_from(state.get_extension::<DefaultAccountState>()?.state)
+            .map_err(|_| format!("Mint {} has an invalid default account state", mint))?;
+        if current != desired {
+            return Err(format!(
+                "Mint {} has default account state {:?}, which apply does not change",
+                mint, current
+            )
+            .into());
+        }
+    }
+
+    let check_authority = |name: &str, current: Option<Pubkey>| {
+        if current == Some(*authority) {
+            Ok(())
+        } else {
+            Err(format!(
+                "The {} authority of {} is {}, specify it with --authority",
+                name,
+                mint,
+                display_authority(&current)
+            ))
+        }
+    };
+
+    let mut changes = vec![];
+    if let Some(transfer_fee) = &spec.transfer_fee {
+        let extension = state.get_extension::<TransferFeeConfig>()?;
+        let newer_fee = &extension.newer_transfer_fee;
+        let maximum_fee = spl_token::ui_amount_to_amount(transfer_fee.maximum_fee, spec.decimals);
+        if u16::from(newer_fee.transfer_fee_basis_points) != transfer_fee.basis_points
+            || u64::from(newer_fee.maximum_fee) != maximum_fee
+        {
+            check_authority(
+                "transfer-fee-config",
+                extension.transfer_fee_config_authority.into(),
+            )?;
+            changes.push(Change::new(
+                format!(
+                    "set transfer fee from {} bps, maximum {} to {} bps, maximum {}",
+                    u16::from(newer_fee.transfer_fee_basis_points),
+                    spl_token::amount_to_ui_amount(u64::from(newer_fee.maximum_fee), spec.decimals),
+                    transfer_fee.basis_points,
+                    transfer_fee.maximum_fee
+                ),
+                vec![transfer_fee::instruction::set_transfer_fee(
+                    program_id,
+                    mint,
+                    authority,
+                    &[],
+                    transfer_fee.basis_points,
+                    maximum_fee,
+                )?],
+            ));
+        }
+    }
+
+    if let Some(interest_bearing) = &spec.interest_bearing {
+        let extension = state.get_extension::<InterestBearingConfig>()?;
+        let current_rate = i16::from(extension.current_rate);
+        if current_rate != interest_bearing.rate {
+            check_authority("interest-rate", extension.rate_authority.into())?;
+            changes.push(Change::new(
+                format!(
+                    "update interest rate from {} to {} bps",
+                    current_rate, interest_bearing.rate
+                ),
+                vec![interest_bearing_mint::instruction::update_rate(
+                    program_id,
+                    mint,
+                    authority,
+                    &[],
+                    interest_bearing.rate,
+                )?],
+            ));
+        }
+    }
+
+    let mint_authority = Option::<Pubkey>::from(state.base.mint_authority);
+    if let Some(metadata) = &spec.metadata {
+        match config
+            .rpc_client
+            .get_account_with_commitment(&metadata_address(mint), config.rpc_client.commitment())
+            .await?
+            .value
+        {
+            None => {
+                check_authority("mint", mint_authority)?;
+                changes.push(Change::new(
+                    format!(
+                        "create metadata {} ({}) {}",
+                        metadata.name, metadata.symbol, metadata.uri
+                    ),
+                    vec![create_metadata_instruction(
+                        mint,
+                        authority,
+                        &config.fee_payer,
+                        metadata,
+                    )?],
+                ));
+            }
+            Some(account) => {
+                let current = Metadata::deserialize(&mut account.data.as_slice())?;
+                // The metadata program pads every string with zeros
+                let trim = |s: &str| s.trim_end_matches('\0').to_string();
+                let (name, symbol, uri) = (
+                    trim(&current.data.name),
+                    trim(&current.data.symbol),
+                    trim(&current.data.uri),
+                );
+                if name != metadata.name || symbol != metadata.symbol || uri != metadata.uri {
+                    if !current.is_mutable {
+                        return Err(format!("The metadata of {} is immutable", mint).into());
+                    }
+                    check_authority("metadata update", Some(current.update_authority))?;
+                    changes.push(Change::new(
+                        format!(
+                            "update metadata from {} ({}) {} to {} ({}) {}",
+                            name, symbol, uri, metadata.name, metadata.symbol, metadata.uri
+                        ),
+                        vec![update_metadata_instruction(
+                            mint,
+                            authority,
+                            Data {
+                                name: metadata.name.clone(),
+                                symbol: metadata.symbol.clone(),
+                                uri: metadata.uri.clone(),
+                                ..current.data
+                            },
+                        )?],
+                    ));
+                }
+            }
+        }
+    }
+
+    // The distribution is the initial supply: it is only minted while the
+    // mint has none, which also lets an interrupted creation be resumed
+    if state.base.supply == 0 && !spec.distribution.is_empty() {
+        check_authority("mint", mint_authority)?;
+        changes.extend(distribution_changes(
+            spec,
+            program_id,
+            mint,
+            authority,
+            &config.fee_payer,
+        )?);
+    }
+
+    // Authorities change last, once every change above has been signed by
+    // the current ones
+    for (authority_type, desired) in &spec.authorities {
+        let current = match authority_type {
+            AuthorityType::MintTokens => mint_authority,
+            AuthorityType::FreezeAccount => state.base.freeze_authority.into(),
+            AuthorityType::CloseMint => state
+                .get_extension::<MintCloseAuthority>()?
+                .close_authority
+                .into(),
+            AuthorityType::TransferFeeConfig => state
+                .get_extension::<TransferFeeConfig>()?
+                .transfer_fee_config_authority
+                .into(),
+            AuthorityType::WithheldWithdraw => state
+                .get_extension::<TransferFeeConfig>()?
+                .withdraw_withheld_authority
+                .into(),
+            AuthorityType::InterestRate => state
+                .get_extension::<InterestBearingConfig>()?
+                .rate_authority
+                .into(),
+            AuthorityType::AccountOwner | AuthorityType::CloseAccount => unreachable!(),
+        };
+        if current == *desired {
+            continue;
+        }
+        let name = authority_name(authority_type);
+        if current.is_none() {
+            return Err(format!(
+                "The {} authority of {} is disabled and cannot be set again",
+                name, mint
+            )
+            .into());
+        }
+        check_authority(name, current)?;
+        changes.push(Change::new(
+            format!(
+                "set {} authority from {} to {}",
+                name,
+                display_authority(&current),
+                display_authority(desired)
+            ),
+            vec![set_authority(
+                program_id,
+                mint,
+                desired.as_ref(),
+                authority_type.clone(),
+                authority,
+                &[],
+            )?],
+        ));
+    }
+    Ok(changes)
+}
+
+fn confirm(prompt: &str) -> Result<bool, Error> {
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn command_apply(
+    config: &Config<'_>,
+    path: &Path,
+    spec: &MintSpec,
+    mint: Pubkey,
+    authority: Pubkey,
+    dry_run: bool,
+    skip_confirmation: bool,
+    signers: Vec<Box<dyn Signer>>,
+) -> CommandResult {
+    let program_id = spec.program_id.unwrap_or(config.program_id);
+    let existing = config
+        .rpc_client
+        .get_account_with_commitment(&mint, config.rpc_client.commitment())
+        .await?
+        .value;
+
+    let create = existing.is_none();
+    let changes = match existing {
+        None => {
+            if spec.address.is_some() && !signers.iter().any(|s| s.pubkey() == mint) {
+                return Err(format!(
+                    "Mint {} does not exist, specify its keypair with --mint-keypair to create it",
+                    mint
+                )
+                .into());
+            }
+            println_display(
+                config,
+                format!("Mint {} does not exist and will be created", mint),
+            );
+            plan_create(config, spec, &program_id, &mint, &authority).await?
+        }
+        Some(account) => {
+            if account.owner != program_id {
+                return Err(format!(
+                    "Mint {} is owned by {}, not by the token program {}",
+                    mint, account.owner, program_id
+                )
+                .into());
+            }
+            plan_update(config, spec, &program_id, &mint, &authority, account.data).await?
+        }
+    };
+
+    let mut cli_apply = CliMintApply {
+        address: mint.to_string(),
+        file: path.display().to_string(),
+        dry_run,
+        create,
+        changes: changes
+            .iter()
+            .map(|change| change.description.clone())
+            .collect(),
+        signatures: vec![],
+    };
+    if changes.is_empty() || dry_run {
+        return Ok(config.output_format.formatted_string(&cli_apply));
+    }
+
+    if !skip_confirmation {
+        println_display(config, format!("Changes to {}:", mint));
+        for change in &changes {
+            println_display(config, format!("  {}", change.description));
+        }
+        if !confirm(&format!("Apply {} change(s)?", changes.len()))? {
+            return Err("Aborted, no transactions were sent".into());
+        }
+    }
+
+    let signer_info = CliSignerInfo { signers };
+    for change in changes {
+        println_display(config, format!("Applying: {}", change.description));
+        let tx_return = handle_tx(
+            &signer_info,
+            config,
+            false,
+            change.minimum_balance_for_rent_exemption,
+            change.instructions,
+        )
+        .await?;
+        if let TransactionReturnData::CliSignature(signature) = tx_return {
+            cli_apply.signatures.push(signature.signature);
+        }
+    }
+    Ok(config.output_format.formatted_string(&cli_apply))
+}
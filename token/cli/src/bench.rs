@@ -1,29 +1,52 @@
 /// The `bench` subcommand
 use {
-    crate::{config::Config, owner_address_arg, CommandResult, Error},
-    clap::{value_t_or_exit, App, AppSettings, Arg, ArgMatches, SubCommand},
+    crate::{
+        config::Config, is_transfer_fee_basis_points, owner_address_arg, CommandResult, Error,
+    },
+    clap::{value_t, value_t_or_exit, App, AppSettings, Arg, ArgMatches, SubCommand},
+    futures::stream::{self, StreamExt},
     solana_clap_utils::{
         input_parsers::pubkey_of_signer,
         input_validators::{is_amount, is_parsable, is_valid_pubkey},
     },
     solana_client::{
         nonblocking::rpc_client::RpcClient, rpc_client::RpcClient as BlockingRpcClient,
-        tpu_client::TpuClient, tpu_client::TpuClientConfig,
+        rpc_config::RpcTransactionConfig, tpu_client::TpuClient, tpu_client::TpuClientConfig,
     },
     solana_remote_wallet::remote_wallet::RemoteWalletManager,
     solana_sdk::{
-        message::Message, native_token::Sol, program_pack::Pack, pubkey::Pubkey, signature::Signer,
+        hash::Hash,
+        message::Message,
+        native_token::Sol,
+        pubkey::Pubkey,
+        signature::{Keypair, Signature, Signer},
         system_instruction,
+        transaction::Transaction,
     },
+    solana_transaction_status::UiTransactionEncoding,
     spl_associated_token_account::*,
     spl_token_2022::{
-        extension::StateWithExtensions,
+        extension::{
+            memo_transfer::{self, MemoTransfer},
+            transfer_fee::{self, TransferFeeConfig},
+            ExtensionType, StateWithExtensions, StateWithExtensionsOwned,
+        },
         instruction,
         state::{Account, Mint},
     },
-    std::{sync::Arc, time::Instant},
+    spl_token_client::token::ExtensionInitializationParams,
+    std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
 };
 
+// Transactions whose compute units are looked up after a transfer benchmark
+const COMPUTE_UNIT_SAMPLE_SIZE: usize = 100;
+
+// Refresh the blockhash used for new transfers well before it expires
+const BLOCKHASH_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
 pub(crate) trait BenchSubCommand {
     fn bench_subcommand(self) -> Self;
 }
@@ -35,6 +58,27 @@ impl BenchSubCommand for App<'_, '_> {
                 .about("Token benchmarking facilities")
                 .setting(AppSettings::InferSubcommands)
                 .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("create-token")
+                        .about("Create a token for benchmarking, owned by the configured token program")
+                        .arg(
+                            Arg::with_name("decimals")
+                                .long("decimals")
+                                .validator(is_parsable::<u8>)
+                                .value_name("DECIMALS")
+                                .takes_value(true)
+                                .help("Number of base 10 digits to the right of the decimal place [default: 9]"),
+                        )
+                        .arg(
+                            Arg::with_name("transfer_fee")
+                                .long("transfer-fee")
+                                .value_names(&["FEE_IN_BASIS_POINTS", "MAXIMUM_FEE"])
+                                .takes_value(true)
+                                .number_of_values(2)
+                                .help("Charge a transfer fee on every transfer. Requires the Token-2022 program"),
+                        )
+                        .arg(owner_address_arg()),
+                )
                 .subcommand(
                     SubCommand::with_name("create-accounts")
                         .about("Create multiple token accounts for benchmarking")
@@ -56,6 +100,12 @@ impl BenchSubCommand for App<'_, '_> {
                                 .required(true)
                                 .help("The number of accounts to create"),
                         )
+                        .arg(
+                            Arg::with_name("require_memo")
+                                .long("require-memo")
+                                .takes_value(false)
+                                .help("Require memos on incoming transfers to the new accounts. Requires the Token-2022 program"),
+                        )
                         .arg(owner_address_arg()),
                 )
                 .subcommand(
@@ -160,6 +210,64 @@ impl BenchSubCommand for App<'_, '_> {
                                 .help("The recipient token account address [default: associated token account for --owner]")
                         )
                         .arg(owner_address_arg()),
+                )
+                .subcommand(
+                    SubCommand::with_name("transfer")
+                        .about("Measure transfer throughput and latency between benchmarking accounts")
+                        .arg(
+                            Arg::with_name("token")
+                                .validator(is_valid_pubkey)
+                                .value_name("TOKEN_ADDRESS")
+                                .takes_value(true)
+                                .index(1)
+                                .required(true)
+                                .help("The token that the accounts hold"),
+                        )
+                        .arg(
+                            Arg::with_name("n")
+                                .validator(is_parsable::<usize>)
+                                .value_name("N")
+                                .takes_value(true)
+                                .index(2)
+                                .required(true)
+                                .help("The number of accounts to transfer between, created with `create-accounts`"),
+                        )
+                        .arg(
+                            Arg::with_name("transfers")
+                                .validator(is_parsable::<usize>)
+                                .value_name("TRANSFERS")
+                                .takes_value(true)
+                                .index(3)
+                                .required(true)
+                                .help("The total number of transfers to send"),
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .validator(is_amount)
+                                .value_name("TOKEN_AMOUNT")
+                                .takes_value(true)
+                                .index(4)
+                                .required(true)
+                                .help(
+                                    "Amount of each transfer, in tokens. Every pass over the accounts \
+                                     adds one base unit so that repeated transfers stay distinct",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("concurrency")
+                                .long("concurrency")
+                                .validator(is_parsable::<usize>)
+                                .value_name("CONCURRENCY")
+                                .takes_value(true)
+                                .help("Number of transfers in flight at once [default: 16]"),
+                        )
+                        .arg(
+                            Arg::with_name("with_memo")
+                                .long("with-memo")
+                                .takes_value(false)
+                                .help("Precede every transfer with a memo. Implied when the accounts require incoming transfer memos"),
+                        )
+                        .arg(owner_address_arg()),
                 ),
         )
     }
@@ -174,17 +282,37 @@ pub(crate) async fn bench_process_command(
     assert!(!config.sign_only);
 
     match matches.subcommand() {
+        ("create-token", Some(arg_matches)) => {
+            let decimals =
+                value_t!(arg_matches, "decimals", u8).unwrap_or(spl_token::native_mint::DECIMALS);
+            let owner = config.pubkey_or_default(arg_matches, "owner", wallet_manager);
+            let transfer_fee = match arg_matches.values_of("transfer_fee") {
+                Some(values) => {
+                    let values = values.collect::<Vec<_>>();
+                    is_transfer_fee_basis_points(values[0].to_string())
+                        .map_err(|err| format!("FEE_IN_BASIS_POINTS {}", err))?;
+                    let maximum_fee = values[1]
+                        .parse::<f64>()
+                        .map_err(|err| format!("MAXIMUM_FEE {}", err))?;
+                    Some((values[0].parse::<u16>().unwrap(), maximum_fee))
+                }
+                None => None,
+            };
+
+            command_create_token(config, signers, decimals, &owner, transfer_fee).await?;
+        }
         ("create-accounts", Some(arg_matches)) => {
             let token = pubkey_of_signer(arg_matches, "token", wallet_manager)
                 .unwrap()
                 .unwrap();
             let n = value_t_or_exit!(arg_matches, "n", usize);
+            let require_memo = arg_matches.is_present("require_memo");
 
             let (owner_signer, owner) =
                 config.signer_or_default(arg_matches, "owner", wallet_manager);
             signers.push(owner_signer);
 
-            command_create_accounts(config, signers, &token, n, &owner).await?;
+            command_create_accounts(config, signers, &token, n, &owner, require_memo).await?;
         }
         ("close-accounts", Some(arg_matches)) => {
             let token = pubkey_of_signer(arg_matches, "token", wallet_manager)
@@ -227,6 +355,32 @@ pub(crate) async fn bench_process_command(
             )
             .await?;
         }
+        ("transfer", Some(arg_matches)) => {
+            let token = pubkey_of_signer(arg_matches, "token", wallet_manager)
+                .unwrap()
+                .unwrap();
+            let n = value_t_or_exit!(arg_matches, "n", usize);
+            let transfers = value_t_or_exit!(arg_matches, "transfers", usize);
+            let ui_amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let concurrency = value_t!(arg_matches, "concurrency", usize).unwrap_or(16);
+            let with_memo = arg_matches.is_present("with_memo");
+            let (owner_signer, owner) =
+                config.signer_or_default(arg_matches, "owner", wallet_manager);
+            signers.push(owner_signer);
+
+            command_transfer(
+                config,
+                signers,
+                &token,
+                n,
+                &owner,
+                transfers,
+                ui_amount,
+                concurrency,
+                with_memo,
+            )
+            .await?;
+        }
         _ => unreachable!(),
     }
 
@@ -257,18 +411,87 @@ fn get_token_addresses_with_seed(
         .collect()
 }
 
-async fn get_valid_mint_program_id(
+async fn get_valid_mint(
     rpc_client: &RpcClient,
     token: &Pubkey,
-) -> Result<Pubkey, Error> {
+) -> Result<(Pubkey, StateWithExtensionsOwned<Mint>), Error> {
     let mint_account = rpc_client
         .get_account(token)
         .await
         .map_err(|err| format!("Token mint {} does not exist: {}", token, err))?;
 
-    StateWithExtensions::<Mint>::unpack(&mint_account.data)
+    let mint = StateWithExtensionsOwned::<Mint>::unpack(mint_account.data)
         .map_err(|err| format!("Invalid token mint {}: {}", token, err))?;
-    Ok(mint_account.owner)
+    Ok((mint_account.owner, mint))
+}
+
+async fn get_valid_mint_program_id(
+    rpc_client: &RpcClient,
+    token: &Pubkey,
+) -> Result<Pubkey, Error> {
+    Ok(get_valid_mint(rpc_client, token).await?.0)
+}
+
+async fn command_create_token(
+    config: &Config<'_>,
+    mut signers: Vec<Box<dyn Signer>>,
+    decimals: u8,
+    owner: &Pubkey,
+    transfer_fee: Option<(u16, f64)>,
+) -> Result<(), Error> {
+    let program_id = config.program_id;
+    let mut extensions = vec![];
+    if let Some((transfer_fee_basis_points, maximum_fee)) = transfer_fee {
+        if program_id != spl_token_2022::id() {
+            return Err(format!(
+                "Transfer fees are only supported by token program {}",
+                spl_token_2022::id()
+            )
+            .into());
+        }
+        extensions.push(ExtensionInitializationParams::TransferFeeConfig {
+            transfer_fee_config_authority: Some(*owner),
+            withdraw_withheld_authority: Some(*owner),
+            transfer_fee_basis_points,
+            maximum_fee: spl_token::ui_amount_to_amount(maximum_fee, decimals),
+        });
+    }
+    let extension_types = extensions.iter().map(|e| e.extension()).collect::<Vec<_>>();
+    let space = ExtensionType::get_account_len::<Mint>(&extension_types);
+    let minimum_balance_for_rent_exemption = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(space)
+        .await?;
+
+    let token = Keypair::new();
+    let mut instructions = vec![system_instruction::create_account(
+        &config.fee_payer,
+        &token.pubkey(),
+        minimum_balance_for_rent_exemption,
+        space as u64,
+        &program_id,
+    )];
+    for params in extensions {
+        instructions.push(params.instruction(&program_id, &token.pubkey())?);
+    }
+    instructions.push(instruction::initialize_mint(
+        &program_id,
+        &token.pubkey(),
+        owner,
+        None,
+        decimals,
+    )?);
+    let message = Message::new(&instructions, Some(&config.fee_payer));
+
+    println!("Creating token {}", token.pubkey());
+    signers.push(Box::new(token));
+    send_messages(
+        config,
+        &[message],
+        minimum_balance_for_rent_exemption,
+        signers,
+    )
+    .await
 }
 
 async fn command_create_accounts(
@@ -277,14 +500,28 @@ async fn command_create_accounts(
     token: &Pubkey,
     n: usize,
     owner: &Pubkey,
+    require_memo: bool,
 ) -> Result<(), Error> {
     let rpc_client = &config.rpc_client;
 
     println!("Scanning accounts...");
-    let program_id = get_valid_mint_program_id(rpc_client, token).await?;
-
+    let (program_id, mint) = get_valid_mint(rpc_client, token).await?;
+
+    let mut extension_types =
+        ExtensionType::get_required_init_account_extensions(&mint.get_extension_types()?);
+    if require_memo {
+        if program_id != spl_token_2022::id() {
+            return Err(format!(
+                "Required transfer memos are only supported by token program {}",
+                spl_token_2022::id()
+            )
+            .into());
+        }
+        extension_types.push(ExtensionType::MemoTransfer);
+    }
+    let account_len = ExtensionType::get_account_len::<Account>(&extension_types);
     let minimum_balance_for_rent_exemption = rpc_client
-        .get_minimum_balance_for_rent_exemption(Account::get_packed_len())
+        .get_minimum_balance_for_rent_exemption(account_len)
         .await?;
 
     let mut lamports_required = 0;
@@ -299,21 +536,27 @@ async fn command_create_accounts(
         for (account, (address, seed)) in accounts_chunk.iter().zip(address_chunk) {
             if account.is_none() {
                 lamports_required += minimum_balance_for_rent_exemption;
-                messages.push(Message::new(
-                    &[
-                        system_instruction::create_account_with_seed(
-                            &config.fee_payer,
-                            address,
-                            owner,
-                            seed,
-                            minimum_balance_for_rent_exemption,
-                            Account::get_packed_len() as u64,
-                            &program_id,
-                        ),
-                        instruction::initialize_account(&program_id, address, token, owner)?,
-                    ],
-                    Some(&config.fee_payer),
-                ));
+                let mut instructions = vec![
+                    system_instruction::create_account_with_seed(
+                        &config.fee_payer,
+                        address,
+                        owner,
+                        seed,
+                        minimum_balance_for_rent_exemption,
+                        account_len as u64,
+                        &program_id,
+                    ),
+                    instruction::initialize_account(&program_id, address, token, owner)?,
+                ];
+                if require_memo {
+                    instructions.push(memo_transfer::instruction::enable_required_transfer_memos(
+                        &program_id,
+                        address,
+                        owner,
+                        &[],
+                    )?);
+                }
+                messages.push(Message::new(&instructions, Some(&config.fee_payer)));
             }
         }
     }
@@ -426,6 +669,219 @@ async fn command_deposit_into_or_withdraw_from(
     send_messages(config, &messages, 0, signers).await
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn command_transfer(
+    config: &Config<'_>,
+    signers: Vec<Box<dyn Signer>>,
+    token: &Pubkey,
+    n: usize,
+    owner: &Pubkey,
+    transfers: usize,
+    ui_amount: f64,
+    concurrency: usize,
+    mut with_memo: bool,
+) -> Result<(), Error> {
+    if n < 2 {
+        return Err("At least two accounts are needed to transfer between".into());
+    }
+    let rpc_client = &config.rpc_client;
+
+    println!("Scanning accounts...");
+    let (program_id, mint) = get_valid_mint(rpc_client, token).await?;
+    let decimals = mint.base.decimals;
+    let amount = spl_token::ui_amount_to_amount(ui_amount, decimals);
+    let transfer_fee_config = mint.get_extension::<TransferFeeConfig>().ok();
+    let epoch = rpc_client.get_epoch_info().await?.epoch;
+
+    let token_addresses = get_token_addresses_with_seed(&program_id, token, owner, n)
+        .into_iter()
+        .map(|(address, _seed)| address)
+        .collect::<Vec<_>>();
+    for address_chunk in token_addresses.chunks(100) {
+        let accounts_chunk = rpc_client.get_multiple_accounts(address_chunk).await?;
+        for (account, address) in accounts_chunk.iter().zip(address_chunk) {
+            let account = account.as_ref().ok_or_else(|| {
+                format!(
+                    "Token account does not exist: {}. Run `create-accounts` first",
+                    address
+                )
+            })?;
+            let token_account = StateWithExtensions::<Account>::unpack(&account.data)
+                .map_err(|err| format!("Invalid token account {}: {}", address, err))?;
+            if let Ok(memo_transfer) = token_account.get_extension::<MemoTransfer>() {
+                with_memo |= bool::from(memo_transfer.require_incoming_transfer_memos);
+            }
+        }
+    }
+
+    let (blockhash, fee_calculator, _last_valid_block_height) = rpc_client
+        .get_recent_blockhash_with_commitment(rpc_client.commitment())
+        .await?
+        .value;
+    let build_instructions = |i: usize| -> Result<Vec<_>, Error> {
+        let mut instructions = vec![];
+        if with_memo {
+            instructions.push(spl_memo::build_memo(
+                format!("bench transfer {}", i).as_bytes(),
+                &[&config.fee_payer],
+            ));
+        }
+        let amount = amount + (i / n) as u64;
+        instructions.push(match transfer_fee_config {
+            Some(transfer_fee_config) => transfer_fee::instruction::transfer_checked_with_fee(
+                &program_id,
+                &token_addresses[i % n],
+                token,
+                &token_addresses[(i + 1) % n],
+                owner,
+                &[],
+                amount,
+                decimals,
+                transfer_fee_config
+                    .calculate_epoch_fee(epoch, amount)
+                    .ok_or("Transfer fee overflow")?,
+            )?,
+            None => instruction::transfer_checked(
+                &program_id,
+                &token_addresses[i % n],
+                token,
+                &token_addresses[(i + 1) % n],
+                owner,
+                &[],
+                amount,
+                decimals,
+            )?,
+        });
+        Ok(instructions)
+    };
+    let fee = fee_calculator.calculate_fee(&Message::new(
+        &build_instructions(0)?,
+        Some(&config.fee_payer),
+    ));
+    println!(
+        "Sending {} transfers, {} at a time, for ~{}",
+        transfers,
+        concurrency,
+        Sol(fee * transfers as u64)
+    );
+    crate::check_fee_payer_balance(config, fee * transfers as u64).await?;
+
+    let latest_blockhash = Mutex::new((blockhash, Instant::now()));
+    let current_blockhash = || async {
+        let (blockhash, fetched_at) = *latest_blockhash.lock().unwrap();
+        if fetched_at.elapsed() < BLOCKHASH_REFRESH_INTERVAL {
+            return Ok::<Hash, Error>(blockhash);
+        }
+        let blockhash = rpc_client.get_latest_blockhash().await?;
+        *latest_blockhash.lock().unwrap() = (blockhash, Instant::now());
+        Ok(blockhash)
+    };
+
+    let signers = &signers;
+    let start = Instant::now();
+    let results = stream::iter(0..transfers)
+        .map(|i| async move {
+            let mut transaction =
+                Transaction::new_with_payer(&build_instructions(i)?, Some(&config.fee_payer));
+            transaction.try_sign(signers, current_blockhash().await?)?;
+            let sent_at = Instant::now();
+            let signature = rpc_client
+                .send_and_confirm_transaction(&transaction)
+                .await?;
+            Ok::<(Signature, Duration), Error>((signature, sent_at.elapsed()))
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    let elapsed = start.elapsed();
+
+    let mut signatures = vec![];
+    let mut latencies = vec![];
+    let mut failures = 0;
+    for result in results {
+        match result {
+            Ok((signature, latency)) => {
+                signatures.push(signature);
+                latencies.push(latency);
+            }
+            Err(err) => {
+                if failures < 10 {
+                    eprintln!("Transfer failed: {}", err);
+                }
+                failures += 1;
+            }
+        }
+    }
+    latencies.sort();
+    let percentile = |p: usize| {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+            .map(|latency| format!("{} ms", latency.as_millis()))
+            .unwrap_or_else(|| "n/a".to_string())
+    };
+
+    let mut compute_units = vec![];
+    for signature in signatures.iter().take(COMPUTE_UNIT_SAMPLE_SIZE) {
+        let transaction = rpc_client
+            .get_transaction_with_config(
+                signature,
+                RpcTransactionConfig {
+                    encoding: Some(UiTransactionEncoding::Json),
+                    commitment: Some(rpc_client.commitment()),
+                    ..RpcTransactionConfig::default()
+                },
+            )
+            .await?;
+        let log_messages = transaction
+            .transaction
+            .meta
+            .and_then(|meta| meta.log_messages)
+            .unwrap_or_default();
+        compute_units.push(consumed_compute_units(&log_messages));
+    }
+
+    println!(
+        "Transfers: {}\nFailures: {}\nAverage TPS: {:.2}\nElapsed time: {} seconds",
+        signatures.len(),
+        failures,
+        signatures.len() as f64 / elapsed.as_secs_f64(),
+        elapsed.as_secs_f64(),
+    );
+    println!(
+        "Confirmation latency: p50 {}, p90 {}, p99 {}, max {}",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100),
+    );
+    if !compute_units.is_empty() {
+        println!(
+            "Average compute units: {} (over {} transactions)",
+            compute_units.iter().sum::<u64>() / compute_units.len() as u64,
+            compute_units.len(),
+        );
+    }
+    Ok(())
+}
+
+// Sum the "Program <id> consumed <n> of <m> compute units" lines of a transaction's logs
+fn consumed_compute_units(log_messages: &[String]) -> u64 {
+    log_messages
+        .iter()
+        .filter_map(|log| {
+            let mut words = log.split_whitespace();
+            if words.next() != Some("Program") {
+                return None;
+            }
+            let mut words = words.skip(1);
+            if words.next() != Some("consumed") {
+                return None;
+            }
+            words.next()?.parse::<u64>().ok()
+        })
+        .sum()
+}
+
 async fn send_messages(
     config: &Config<'_>,
     messages: &[Message],
@@ -27,6 +27,18 @@ pub(crate) enum KeypairOrPath {
     Path(String),
 }
 
+impl Clone for KeypairOrPath {
+    fn clone(&self) -> Self {
+        match self {
+            #[cfg(test)]
+            KeypairOrPath::Keypair(keypair) => {
+                KeypairOrPath::Keypair(Keypair::from_bytes(&keypair.to_bytes()).unwrap())
+            }
+            KeypairOrPath::Path(path) => KeypairOrPath::Path(path.clone()),
+        }
+    }
+}
+
 pub(crate) struct MintInfo {
     pub program_id: Pubkey,
     pub address: Pubkey,
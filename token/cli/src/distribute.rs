@@ -0,0 +1,699 @@
+/// The `distribute` subcommand
+use {
+    crate::{
+        check_fee_payer_balance,
+        config::Config,
+        multisig_signer_arg,
+        output::{println_display, CliDistribution},
+        owner_address_arg, CommandName, CommandResult, Error,
+    },
+    clap::{value_t, App, Arg, ArgMatches, SubCommand},
+    serde::{Deserialize, Serialize},
+    solana_clap_utils::{
+        input_parsers::pubkey_of_signer,
+        input_validators::{is_parsable, is_valid_pubkey},
+        keypair::CliSignerInfo,
+    },
+    solana_remote_wallet::remote_wallet::RemoteWalletManager,
+    solana_sdk::{
+        instruction::Instruction,
+        message::Message,
+        native_token::lamports_to_sol,
+        packet::PACKET_DATA_SIZE,
+        pubkey::Pubkey,
+        signature::{Signature, Signer},
+        transaction::Transaction,
+    },
+    spl_associated_token_account::{
+        get_associated_token_address_with_program_id,
+        instruction::create_associated_token_account_idempotent,
+    },
+    spl_token_2022::{
+        extension::{ExtensionType, StateWithExtensionsOwned},
+        instruction,
+        state::{Account, Mint},
+    },
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        fs,
+        path::{Path, PathBuf},
+        str::FromStr,
+        sync::Arc,
+        thread::sleep,
+        time::Duration,
+    },
+};
+
+const DEFAULT_BATCH_SIZE: usize = 8;
+
+pub(crate) trait DistributeSubCommand {
+    fn distribute_subcommand(self) -> Self;
+}
+
+impl DistributeSubCommand for App<'_, '_> {
+    fn distribute_subcommand(self) -> Self {
+        self.subcommand(
+            SubCommand::with_name(CommandName::Distribute.into())
+                .about("Transfer tokens to every recipient listed in a CSV file")
+                .arg(
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The token to distribute"),
+                )
+                .arg(
+                    Arg::with_name("csv")
+                        .value_name("CSV_FILE")
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .help(
+                            "Path to a CSV file with one `RECIPIENT,AMOUNT[,MEMO]` row per transfer. \
+                             RECIPIENT is a wallet address or a token account address, \
+                             AMOUNT is in tokens",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("state_file")
+                        .long("state-file")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .help(
+                            "Path to the file recording confirmed transfers. \
+                             Re-running the command with the same state file resumes an \
+                             interrupted distribution without repeating any transfer \
+                             [default: CSV_FILE with a `.state.json` suffix]",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .validator(is_valid_pubkey)
+                        .value_name("SOURCE_TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .help("The source token account address [default: associated token account for --owner]"),
+                )
+                .arg(owner_address_arg())
+                .arg(
+                    Arg::with_name("batch_size")
+                        .long("batch-size")
+                        .validator(is_parsable::<usize>)
+                        .value_name("N")
+                        .takes_value(true)
+                        .help("Maximum number of transfers to pack into one transaction [default: 8]"),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .takes_value(false)
+                        .help("Print the amount and fees the distribution would cost without sending any transactions"),
+                )
+                .arg(multisig_signer_arg()),
+        )
+    }
+}
+
+pub(crate) async fn distribute_process_command(
+    arg_matches: &ArgMatches<'_>,
+    config: &Config<'_>,
+    mut signers: Vec<Box<dyn Signer>>,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+) -> CommandResult {
+    assert!(!config.sign_only);
+
+    let token = pubkey_of_signer(arg_matches, "token", wallet_manager)
+        .unwrap()
+        .unwrap();
+    let csv_path = PathBuf::from(arg_matches.value_of("csv").unwrap());
+    let state_path = arg_matches
+        .value_of("state_file")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            let mut path = csv_path.clone().into_os_string();
+            path.push(".state.json");
+            PathBuf::from(path)
+        });
+    let (owner_signer, owner) = config.signer_or_default(arg_matches, "owner", wallet_manager);
+    signers.push(owner_signer);
+    let from = pubkey_of_signer(arg_matches, "from", wallet_manager).unwrap();
+    let batch_size = value_t!(arg_matches, "batch_size", usize).unwrap_or(DEFAULT_BATCH_SIZE);
+    if batch_size == 0 {
+        return Err("--batch-size must be at least 1".into());
+    }
+    let dry_run = arg_matches.is_present("dry_run");
+
+    command_distribute(
+        config,
+        token,
+        &csv_path,
+        &state_path,
+        owner,
+        from,
+        batch_size,
+        dry_run,
+        signers,
+    )
+    .await
+}
+
+/// One row of the distribution CSV
+struct DistributionRow {
+    line: usize,
+    recipient: Pubkey,
+    amount: u64,
+    memo: Option<String>,
+}
+
+/// Contents of the state file. Transfers are keyed by their CSV line number.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DistributionState {
+    mint: String,
+    source: String,
+    transfers: BTreeMap<usize, TransferRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransferRecord {
+    recipient: String,
+    amount: u64,
+    signature: String,
+    /// Set while the transaction carrying this transfer is in flight. The
+    /// transaction can no longer land once the cluster passes this height.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending_until_block_height: Option<u64>,
+}
+
+impl DistributionState {
+    fn load_or_new(path: &Path, mint: &Pubkey, source: &Pubkey) -> Result<Self, Error> {
+        if !path.exists() {
+            return Ok(Self {
+                mint: mint.to_string(),
+                source: source.to_string(),
+                transfers: BTreeMap::new(),
+            });
+        }
+
+        let state: Self = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|err| format!("Invalid state file {}: {}", path.display(), err))?;
+        if state.mint != mint.to_string() || state.source != source.to_string() {
+            return Err(format!(
+                "State file {} belongs to a distribution of {} from {}",
+                path.display(),
+                state.mint,
+                state.source
+            )
+            .into());
+        }
+        Ok(state)
+    }
+
+    // Write to a temporary file first so that an interruption never leaves a
+    // truncated state file behind
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut tmp_path = path.to_path_buf().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    fn is_confirmed(&self, line: usize) -> bool {
+        matches!(
+            self.transfers.get(&line),
+            Some(TransferRecord {
+                pending_until_block_height: None,
+                ..
+            })
+        )
+    }
+
+    fn has_pending(&self) -> bool {
+        self.transfers
+            .values()
+            .any(|record| record.pending_until_block_height.is_some())
+    }
+}
+
+fn parse_distribution_csv(path: &Path, decimals: u8) -> Result<Vec<DistributionRow>, Error> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+
+    let mut rows = vec![];
+    for (i, text) in contents.lines().enumerate() {
+        let line = i + 1;
+        let text = text.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = text.splitn(3, ',').map(str::trim);
+        let recipient_field = fields.next().unwrap_or_default();
+        let recipient = match Pubkey::from_str(recipient_field) {
+            Ok(recipient) => recipient,
+            // Tolerate a header row
+            Err(_) if line == 1 => continue,
+            Err(_) => {
+                return Err(format!(
+                    "{}:{}: invalid recipient address `{}`",
+                    path.display(),
+                    line,
+                    recipient_field
+                )
+                .into())
+            }
+        };
+        let amount_field = fields.next().unwrap_or_default();
+        let ui_amount = amount_field
+            .parse::<f64>()
+            .ok()
+            .filter(|ui_amount| *ui_amount > 0.0)
+            .ok_or_else(|| {
+                format!(
+                    "{}:{}: invalid amount `{}`",
+                    path.display(),
+                    line,
+                    amount_field
+                )
+            })?;
+        let memo = fields
+            .next()
+            .map(|memo| memo.trim_matches('"').to_string())
+            .filter(|memo| !memo.is_empty());
+
+        rows.push(DistributionRow {
+            line,
+            recipient,
+            amount: spl_token::ui_amount_to_amount(ui_amount, decimals),
+            memo,
+        });
+    }
+    Ok(rows)
+}
+
+// Settle every transfer recorded as in flight by a previous run: confirmed
+// transactions are kept, failed or expired ones are dropped so that their
+// transfers are sent again. Waits until every in-flight transaction has
+// either landed or expired.
+async fn resolve_pending_transfers(
+    config: &Config<'_>,
+    state: &mut DistributionState,
+    state_path: &Path,
+) -> Result<(), Error> {
+    while state.has_pending() {
+        let block_height = config.rpc_client.get_block_height().await?;
+        let signatures = state
+            .transfers
+            .values()
+            .filter(|record| record.pending_until_block_height.is_some())
+            .map(|record| Signature::from_str(&record.signature))
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(|err| format!("Invalid signature in state file: {}", err))?
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut confirmed = HashSet::new();
+        let mut landed = HashSet::new();
+        let mut failed = HashSet::new();
+        for signature_chunk in signatures.chunks(256) {
+            let statuses = config
+                .rpc_client
+                .get_signature_statuses_with_history(signature_chunk)
+                .await?
+                .value;
+            for (signature, status) in signature_chunk.iter().zip(statuses) {
+                match status {
+                    Some(status) if status.err.is_some() => {
+                        failed.insert(signature.to_string());
+                    }
+                    Some(status) if status.satisfies_commitment(config.rpc_client.commitment()) => {
+                        confirmed.insert(signature.to_string());
+                    }
+                    // Processed but not yet at the configured commitment, so it may
+                    // still confirm and must not be sent again
+                    Some(_) => {
+                        landed.insert(signature.to_string());
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        state
+            .transfers
+            .retain(|_, record| match record.pending_until_block_height {
+                Some(last_valid_block_height) => {
+                    !failed.contains(&record.signature)
+                        && (confirmed.contains(&record.signature)
+                            || landed.contains(&record.signature)
+                            || block_height <= last_valid_block_height)
+                }
+                None => true,
+            });
+        for record in state.transfers.values_mut() {
+            if confirmed.contains(&record.signature) {
+                record.pending_until_block_height = None;
+            }
+        }
+        state.save(state_path)?;
+
+        if state.has_pending() {
+            println_display(
+                config,
+                "Waiting for transactions from the previous run to land or expire...".to_string(),
+            );
+            sleep(Duration::from_secs(2));
+        }
+    }
+    Ok(())
+}
+
+fn transaction_size(message: &Message) -> usize {
+    // signature count (compact-u16) + signatures + message
+    1 + message.header.num_required_signatures as usize * 64 + message.serialize().len()
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn command_distribute(
+    config: &Config<'_>,
+    token: Pubkey,
+    csv_path: &Path,
+    state_path: &Path,
+    owner: Pubkey,
+    from: Option<Pubkey>,
+    batch_size: usize,
+    dry_run: bool,
+    bulk_signers: Vec<Box<dyn Signer>>,
+) -> CommandResult {
+    let mint_info = config.get_mint_info(&token, None).await?;
+    let program_id = mint_info.program_id;
+    let source = from.unwrap_or_else(|| {
+        get_associated_token_address_with_program_id(&owner, &token, &program_id)
+    });
+    config.check_account(&source, Some(token)).await?;
+
+    let rows = parse_distribution_csv(csv_path, mint_info.decimals)?;
+    let mut state = DistributionState::load_or_new(state_path, &token, &source)?;
+
+    let rows_by_line = rows
+        .iter()
+        .map(|row| (row.line, row))
+        .collect::<HashMap<_, _>>();
+    for (line, record) in &state.transfers {
+        match rows_by_line.get(line) {
+            Some(row)
+                if row.recipient.to_string() == record.recipient && row.amount == record.amount => {
+            }
+            _ => {
+                return Err(format!(
+                    "{}:{} no longer matches the transfer recorded in {}",
+                    csv_path.display(),
+                    line,
+                    state_path.display()
+                )
+                .into())
+            }
+        }
+    }
+
+    if !dry_run {
+        resolve_pending_transfers(config, &mut state, state_path).await?;
+    }
+
+    let previously_distributed = rows
+        .iter()
+        .filter(|row| state.is_confirmed(row.line))
+        .count();
+    let remaining = rows
+        .iter()
+        .filter(|row| !state.transfers.contains_key(&row.line))
+        .collect::<Vec<_>>();
+
+    // Work out where each remaining transfer lands. Recipients that are token
+    // accounts of this mint are paid directly, everything else is treated as a
+    // wallet address and paid through its associated token account.
+    let recipients = remaining
+        .iter()
+        .map(|row| row.recipient)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let mut destinations = HashMap::new();
+    let mut associated_addresses = vec![];
+    for recipient_chunk in recipients.chunks(100) {
+        let accounts = config
+            .rpc_client
+            .get_multiple_accounts(recipient_chunk)
+            .await?;
+        for (recipient, account) in recipient_chunk.iter().zip(accounts) {
+            let token_account = account
+                .filter(|account| account.owner == program_id)
+                .and_then(|account| StateWithExtensionsOwned::<Account>::unpack(account.data).ok());
+            match token_account {
+                Some(token_account) if token_account.base.mint != token => {
+                    return Err(format!(
+                        "Recipient {} is a token account for mint {}",
+                        recipient, token_account.base.mint
+                    )
+                    .into());
+                }
+                Some(_) => {
+                    destinations.insert(*recipient, *recipient);
+                }
+                None => {
+                    let address = get_associated_token_address_with_program_id(
+                        recipient,
+                        &token,
+                        &program_id,
+                    );
+                    destinations.insert(*recipient, address);
+                    associated_addresses.push(address);
+                }
+            }
+        }
+    }
+    let mut missing_accounts = HashSet::new();
+    for address_chunk in associated_addresses.chunks(100) {
+        let accounts = config
+            .rpc_client
+            .get_multiple_accounts(address_chunk)
+            .await?;
+        for (address, account) in address_chunk.iter().zip(accounts) {
+            if account.is_none() {
+                missing_accounts.insert(*address);
+            }
+        }
+    }
+    let accounts_to_create = missing_accounts.len();
+
+    // Pack transfers into transactions, keeping an associated account's
+    // creation in the same transaction as the first transfer into it
+    let mut batches: Vec<(Vec<Instruction>, Vec<usize>)> = vec![];
+    let mut instructions = vec![];
+    let mut lines = vec![];
+    for row in &remaining {
+        let destination = destinations[&row.recipient];
+        let mut row_instructions = vec![];
+        if missing_accounts.remove(&destination) {
+            row_instructions.push(create_associated_token_account_idempotent(
+                &config.fee_payer,
+                &row.recipient,
+                &token,
+                &program_id,
+            ));
+        }
+        if let Some(memo) = &row.memo {
+            row_instructions.push(spl_memo::build_memo(memo.as_bytes(), &[&config.fee_payer]));
+        }
+        row_instructions.push(instruction::transfer_checked(
+            &program_id,
+            &source,
+            &token,
+            &destination,
+            &owner,
+            &config.multisigner_pubkeys,
+            row.amount,
+            mint_info.decimals,
+        )?);
+
+        let mut candidate = instructions.clone();
+        candidate.extend(row_instructions.iter().cloned());
+        if !lines.is_empty()
+            && (lines.len() >= batch_size
+                || transaction_size(&Message::new(&candidate, Some(&config.fee_payer)))
+                    > PACKET_DATA_SIZE)
+        {
+            batches.push((
+                std::mem::take(&mut instructions),
+                std::mem::take(&mut lines),
+            ));
+            candidate = row_instructions;
+        }
+        if transaction_size(&Message::new(&candidate, Some(&config.fee_payer))) > PACKET_DATA_SIZE {
+            return Err(format!(
+                "{}:{}: transfer does not fit in a transaction, try a shorter memo",
+                csv_path.display(),
+                row.line
+            )
+            .into());
+        }
+        instructions = candidate;
+        lines.push(row.line);
+    }
+    if !lines.is_empty() {
+        batches.push((instructions, lines));
+    }
+
+    let total_amount = remaining
+        .iter()
+        .try_fold(0u64, |total, row| total.checked_add(row.amount))
+        .ok_or("Total distribution amount overflow")?;
+    let mint_extensions = if program_id == spl_token_2022::id() {
+        let mint_account = config.rpc_client.get_account(&token).await?;
+        StateWithExtensionsOwned::<Mint>::unpack(mint_account.data)?.get_extension_types()?
+    } else {
+        vec![]
+    };
+    let account_len = ExtensionType::get_account_len::<Account>(
+        &ExtensionType::get_required_init_account_extensions(&mint_extensions),
+    );
+    let rent = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(account_len)
+        .await?
+        * accounts_to_create as u64;
+    let (_blockhash, fee_calculator, _last_valid_block_height) = config
+        .rpc_client
+        .get_recent_blockhash_with_commitment(config.rpc_client.commitment())
+        .await?
+        .value;
+    let fees = batches
+        .iter()
+        .map(|(instructions, _)| {
+            fee_calculator.calculate_fee(&Message::new(instructions, Some(&config.fee_payer)))
+        })
+        .sum::<u64>();
+
+    let mut cli_distribution = CliDistribution {
+        mint: token.to_string(),
+        source: source.to_string(),
+        state_file: state_path.display().to_string(),
+        dry_run,
+        recipients: rows.len(),
+        previously_distributed,
+        transfers: remaining.len(),
+        ui_amount: spl_token_2022::amount_to_ui_amount_string_trimmed(
+            total_amount,
+            mint_info.decimals,
+        ),
+        accounts_created: accounts_to_create,
+        transactions: batches.len(),
+        fee_sol: lamports_to_sol(fees),
+        rent_sol: lamports_to_sol(rent),
+        signatures: vec![],
+        failed_lines: vec![],
+    };
+
+    if !dry_run && !batches.is_empty() {
+        let source_amount = StateWithExtensionsOwned::<Account>::unpack(
+            config.rpc_client.get_account(&source).await?.data,
+        )?
+        .base
+        .amount;
+        if source_amount < total_amount {
+            return Err(format!(
+                "Error: Sender has insufficient funds, current balance is {}",
+                spl_token_2022::amount_to_ui_amount_string_trimmed(
+                    source_amount,
+                    mint_info.decimals
+                )
+            )
+            .into());
+        }
+        check_fee_payer_balance(config, fees + rent).await?;
+
+        let signer_info = CliSignerInfo {
+            signers: bulk_signers,
+        };
+        for (i, (instructions, lines)) in batches.iter().enumerate() {
+            let (blockhash, last_valid_block_height) = config
+                .rpc_client
+                .get_latest_blockhash_with_commitment(config.rpc_client.commitment())
+                .await?;
+            let message =
+                Message::new_with_blockhash(instructions, Some(&config.fee_payer), &blockhash);
+            let signers = signer_info.signers_for_message(&message);
+            let mut transaction = Transaction::new_unsigned(message);
+            transaction.try_sign(&signers, blockhash)?;
+            let signature = transaction.signatures[0];
+
+            // Record the transaction before sending it, so that a run that is
+            // interrupted at any point can tell whether these transfers landed
+            for line in lines {
+                let row = rows_by_line[line];
+                state.transfers.insert(
+                    *line,
+                    TransferRecord {
+                        recipient: row.recipient.to_string(),
+                        amount: row.amount,
+                        signature: signature.to_string(),
+                        pending_until_block_height: Some(last_valid_block_height),
+                    },
+                );
+            }
+            state.save(state_path)?;
+
+            match config
+                .rpc_client
+                .send_and_confirm_transaction(&transaction)
+                .await
+            {
+                Ok(signature) => {
+                    for line in lines {
+                        if let Some(record) = state.transfers.get_mut(line) {
+                            record.pending_until_block_height = None;
+                        }
+                    }
+                    state.save(state_path)?;
+                    println_display(
+                        config,
+                        format!(
+                            "[{}/{}] Transferred to lines {:?}: {}",
+                            i + 1,
+                            batches.len(),
+                            lines,
+                            signature
+                        ),
+                    );
+                    cli_distribution.signatures.push(signature.to_string());
+                }
+                Err(err) if err.get_transaction_error().is_some() => {
+                    // The transaction was rejected, so none of its transfers happened
+                    for line in lines {
+                        state.transfers.remove(line);
+                    }
+                    state.save(state_path)?;
+                    eprintln!(
+                        "Transfers on lines {:?} failed: {}; run the command again to retry them",
+                        lines, err
+                    );
+                    cli_distribution.failed_lines.extend(lines);
+                }
+                Err(err) => {
+                    return Err(format!(
+                        "{}. The state of transaction {} is unknown; \
+                         run the command again to resume the distribution",
+                        err, signature
+                    )
+                    .into());
+                }
+            }
+        }
+    }
+
+    Ok(config.output_format.formatted_string(&cli_distribution))
+}
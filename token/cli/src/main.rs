@@ -6,7 +6,7 @@ use clap::{
 use serde::Serialize;
 use solana_account_decoder::{
     parse_token::{TokenAccountType, UiAccountState},
-    UiAccountData,
+    UiAccountData, UiAccountEncoding,
 };
 use solana_clap_utils::{
     fee_payer::fee_payer_arg,
@@ -25,7 +25,12 @@ use solana_cli_output::{
     return_signers_data, CliSignOnlyData, CliSignature, OutputFormat, QuietDisplay,
     ReturnSignersConfig, VerboseDisplay,
 };
-use solana_client::{nonblocking::rpc_client::RpcClient, rpc_request::TokenAccountsFilter};
+use solana_client::{
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
+    rpc_request::TokenAccountsFilter,
+};
 use solana_remote_wallet::remote_wallet::RemoteWalletManager;
 use solana_sdk::{
     commitment_config::CommitmentConfig,
@@ -43,13 +48,35 @@ use spl_associated_token_account::{
     get_associated_token_address_with_program_id, instruction::create_associated_token_account,
 };
 use spl_token_2022::{
-    extension::StateWithExtensionsOwned,
+    extension::{
+        confidential_transfer::{
+            self, ConfidentialTransferAccount, ConfidentialTransferMint, EncryptedWithheldAmount,
+        },
+        default_account_state::DefaultAccountState,
+        interest_bearing_mint::{self, InterestBearingConfig},
+        memo_transfer::{self, MemoTransfer},
+        mint_close_authority::MintCloseAuthority,
+        transfer_fee::{self, TransferFee, TransferFeeAmount, TransferFeeConfig},
+        BaseState, ExtensionType, StateWithExtensionsOwned,
+    },
     instruction::*,
-    state::{Account, Mint, Multisig},
+    solana_zk_token_sdk::{
+        encryption::{
+            auth_encryption::{AeCiphertext, AeKey},
+            elgamal::{self, ElGamalCiphertext, ElGamalKeypair},
+        },
+        instruction::transfer_with_fee::FeeParameters,
+        zk_token_elgamal::pod::{ElGamalPubkey, Zeroable},
+    },
+    state::{Account, AccountState, Mint, Multisig},
+};
+use spl_token_client::{
+    client::{ProgramClient, ProgramRpcClient, ProgramRpcClientSendTransaction},
+    token::ExtensionInitializationParams,
 };
-use spl_token_client::client::{ProgramClient, ProgramRpcClient, ProgramRpcClientSendTransaction};
 use std::{
-    collections::HashMap, fmt::Display, process::exit, str::FromStr, string::ToString, sync::Arc,
+    collections::HashMap, convert::TryInto, fmt::Display, process::exit, str::FromStr,
+    string::ToString, sync::Arc,
 };
 use strum_macros::{EnumString, IntoStaticStr, ToString};
 
@@ -65,6 +92,15 @@ use sort::{is_supported_program, sort_and_parse_token_accounts};
 mod bench;
 use bench::*;
 
+mod distribute;
+use distribute::*;
+
+mod apply;
+use apply::*;
+
+mod multisig;
+use multisig::*;
+
 pub const OWNER_ADDRESS_ARG: ArgConstant<'static> = ArgConstant {
     name: "owner",
     long: "owner",
@@ -127,6 +163,24 @@ pub enum CommandName {
     MultisigInfo,
     Gc,
     SyncNative,
+    SetTransferFee,
+    WithdrawWithheldTokens,
+    HarvestWithheld,
+    EnableRequiredTransferMemos,
+    DisableRequiredTransferMemos,
+    Reallocate,
+    UpdateInterestRate,
+    ConfigureConfidentialAccount,
+    DepositConfidential,
+    ApplyPendingBalance,
+    ConfidentialTransfer,
+    WithdrawConfidential,
+    ShowConfidentialBalance,
+    Distribute,
+    Holders,
+    Multisig,
+    Display,
+    Apply,
 }
 
 pub fn owner_address_arg<'a, 'b>() -> Arg<'a, 'b> {
@@ -222,6 +276,30 @@ fn is_multisig_minimum_signers(string: String) -> Result<(), String> {
     }
 }
 
+fn is_transfer_fee_basis_points(string: String) -> Result<(), String> {
+    let v = u16::from_str(&string).map_err(|e| e.to_string())?;
+    if v > transfer_fee::MAX_FEE_BASIS_POINTS {
+        Err(format!(
+            "must be at most {}",
+            transfer_fee::MAX_FEE_BASIS_POINTS
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_elgamal_pubkey(string: &str) -> Result<ElGamalPubkey, String> {
+    let bytes = base64::decode(string).map_err(|e| e.to_string())?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| "must be a base64-encoded 32-byte ElGamal public key".to_string())?;
+    Ok(ElGamalPubkey(bytes))
+}
+
+fn is_elgamal_pubkey(string: String) -> Result<(), String> {
+    parse_elgamal_pubkey(&string).map(|_| ())
+}
+
 pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
 
 type BulkSigners = Vec<Box<dyn Signer>>;
@@ -313,37 +391,50 @@ async fn command_create_token(
     token: Pubkey,
     authority: Pubkey,
     enable_freeze: bool,
+    extensions: Vec<ExtensionInitializationParams>,
     memo: Option<String>,
     bulk_signers: Vec<Box<dyn Signer>>,
 ) -> CommandResult {
     println_display(config, format!("Creating token {}", token));
 
+    if !extensions.is_empty() && config.program_id != spl_token_2022::id() {
+        return Err(format!(
+            "Error: mint extensions are only supported by token program {}",
+            spl_token_2022::id()
+        )
+        .into());
+    }
+    let extension_types = extensions.iter().map(|e| e.extension()).collect::<Vec<_>>();
+    let space = ExtensionType::get_account_len::<Mint>(&extension_types);
+
     let minimum_balance_for_rent_exemption = if !config.sign_only {
         config
             .program_client
-            .get_minimum_balance_for_rent_exemption(Mint::LEN)
+            .get_minimum_balance_for_rent_exemption(space)
             .await?
     } else {
         0
     };
     let freeze_authority_pubkey = if enable_freeze { Some(authority) } else { None };
 
-    let mut instructions = vec![
-        system_instruction::create_account(
-            &config.fee_payer,
-            &token,
-            minimum_balance_for_rent_exemption,
-            Mint::LEN as u64,
-            &config.program_id,
-        ),
-        initialize_mint(
-            &config.program_id,
-            &token,
-            &authority,
-            freeze_authority_pubkey.as_ref(),
-            decimals,
-        )?,
-    ];
+    let mut instructions = vec![system_instruction::create_account(
+        &config.fee_payer,
+        &token,
+        minimum_balance_for_rent_exemption,
+        space as u64,
+        &config.program_id,
+    )];
+    // Every mint extension must be initialized before the mint itself
+    for params in extensions {
+        instructions.push(params.instruction(&config.program_id, &token)?);
+    }
+    instructions.push(initialize_mint(
+        &config.program_id,
+        &token,
+        &authority,
+        freeze_authority_pubkey.as_ref(),
+        decimals,
+    )?);
     if let Some(text) = memo {
         instructions.push(spl_memo::build_memo(text.as_bytes(), &[&config.fee_payer]));
     }
@@ -1510,6 +1601,104 @@ async fn command_accounts(
     Ok(config.output_format.formatted_string(&cli_token_accounts))
 }
 
+async fn command_holders(
+    config: &Config<'_>,
+    token: Pubkey,
+    min_balance: Option<f64>,
+    by_owner: bool,
+    csv: bool,
+) -> CommandResult {
+    let program_id = validate_mint(config, token).await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+    let min_amount = min_balance
+        .map(|ui_amount| spl_token::ui_amount_to_amount(ui_amount, mint_info.decimals))
+        .unwrap_or_default();
+    let ui_amount =
+        |amount| spl_token_2022::amount_to_ui_amount_string_trimmed(amount, mint_info.decimals);
+
+    let mut accounts = get_mint_token_accounts(config, &token, &program_id).await?;
+    accounts.sort_by(|(address_a, a), (address_b, b)| {
+        b.base
+            .amount
+            .cmp(&a.base.amount)
+            .then(address_a.cmp(address_b))
+    });
+
+    let mut holder_accounts = vec![];
+    let mut owners: Vec<CliHolderOwner> = vec![];
+    let mut owner_amounts: HashMap<Pubkey, (u64, usize)> = HashMap::new();
+    for (address, account) in &accounts {
+        let entry = owner_amounts.entry(account.base.owner).or_default();
+        entry.0 = entry.0.saturating_add(account.base.amount);
+        entry.1 += 1;
+
+        if by_owner || account.base.amount < min_amount {
+            continue;
+        }
+        holder_accounts.push(CliHolderAccount {
+            address: address.to_string(),
+            owner: account.base.owner.to_string(),
+            amount: account.base.amount.to_string(),
+            ui_amount: ui_amount(account.base.amount),
+            delegate: Option::<Pubkey>::from(account.base.delegate).map(|d| d.to_string()),
+            delegated_amount: Option::<Pubkey>::from(account.base.delegate)
+                .map(|_| ui_amount(account.base.delegated_amount)),
+            state: match account.base.state {
+                AccountState::Uninitialized => "uninitialized",
+                AccountState::Initialized => "initialized",
+                AccountState::Frozen => "frozen",
+            }
+            .to_string(),
+            extensions: account
+                .get_extension_types()?
+                .iter()
+                .map(|extension_type| format!("{:?}", extension_type))
+                .collect(),
+            is_associated: get_associated_token_address_with_program_id(
+                &account.base.owner,
+                &token,
+                &program_id,
+            ) == *address,
+        });
+    }
+    if by_owner {
+        let mut owner_amounts = owner_amounts
+            .into_iter()
+            .filter(|(_, (amount, _))| *amount >= min_amount)
+            .collect::<Vec<_>>();
+        owner_amounts
+            .sort_by(|(owner_a, (a, _)), (owner_b, (b, _))| b.cmp(a).then(owner_a.cmp(owner_b)));
+        owners = owner_amounts
+            .into_iter()
+            .map(|(owner, (amount, accounts))| CliHolderOwner {
+                owner: owner.to_string(),
+                amount: amount.to_string(),
+                ui_amount: ui_amount(amount),
+                accounts,
+            })
+            .collect();
+    }
+
+    let total_amount = accounts.iter().fold(0u64, |total, (_, account)| {
+        total.saturating_add(account.base.amount)
+    });
+    let cli_holders = CliHolders {
+        mint: token.to_string(),
+        program_id: program_id.to_string(),
+        decimals: mint_info.decimals,
+        ui_total_amount: ui_amount(total_amount),
+        by_owner,
+        accounts: holder_accounts,
+        owners,
+    };
+    // `OutputFormat` has no CSV variant, see `CliHolders::csv`
+    if csv {
+        Ok(cli_holders.csv())
+    } else {
+        Ok(config.output_format.formatted_string(&cli_holders))
+    }
+}
+
 async fn command_address(
     config: &Config<'_>,
     token: Option<Pubkey>,
@@ -1548,6 +1737,328 @@ async fn command_account_info(config: &Config<'_>, address: Pubkey) -> CommandRe
     Ok(config.output_format.formatted_string(&cli_token_account))
 }
 
+fn display_optional_pubkey(pubkey: Option<Pubkey>) -> String {
+    pubkey
+        .map(|pubkey| pubkey.to_string())
+        .unwrap_or_else(|| "(not set)".to_string())
+}
+
+fn display_account_state(state: u8) -> String {
+    match state {
+        0 => "uninitialized",
+        1 => "initialized",
+        2 => "frozen",
+        _ => "invalid",
+    }
+    .to_string()
+}
+
+fn display_transfer_fee(transfer_fee: &TransferFee, decimals: u8, epoch: u64) -> String {
+    let fee_epoch = u64::from(transfer_fee.epoch);
+    format!(
+        "{} basis points, maximum fee {}, from epoch {}{}",
+        u16::from(transfer_fee.transfer_fee_basis_points),
+        spl_token_2022::amount_to_ui_amount_string_trimmed(
+            u64::from(transfer_fee.maximum_fee),
+            decimals
+        ),
+        fee_epoch,
+        if fee_epoch > epoch { " (pending)" } else { "" },
+    )
+}
+
+fn display_extensions<S: BaseState>(
+    state: &StateWithExtensionsOwned<S>,
+    decimals: u8,
+    epoch: u64,
+) -> Result<Vec<(String, Vec<(String, String)>)>, Error> {
+    let ui_amount =
+        |amount: u64| spl_token_2022::amount_to_ui_amount_string_trimmed(amount, decimals);
+    let mut extensions = vec![];
+    for extension_type in state.get_extension_types()? {
+        let fields: Vec<(&str, String)> = match extension_type {
+            ExtensionType::Uninitialized => continue,
+            ExtensionType::TransferFeeConfig => {
+                let extension = state.get_extension::<TransferFeeConfig>()?;
+                vec![
+                    (
+                        "transferFeeConfigAuthority",
+                        display_optional_pubkey(extension.transfer_fee_config_authority.into()),
+                    ),
+                    (
+                        "withdrawWithheldAuthority",
+                        display_optional_pubkey(extension.withdraw_withheld_authority.into()),
+                    ),
+                    (
+                        "withheldAmount",
+                        ui_amount(extension.withheld_amount.into()),
+                    ),
+                    (
+                        "olderTransferFee",
+                        display_transfer_fee(&extension.older_transfer_fee, decimals, epoch),
+                    ),
+                    (
+                        "newerTransferFee",
+                        display_transfer_fee(&extension.newer_transfer_fee, decimals, epoch),
+                    ),
+                ]
+            }
+            ExtensionType::TransferFeeAmount => {
+                let extension = state.get_extension::<TransferFeeAmount>()?;
+                vec![(
+                    "withheldAmount",
+                    ui_amount(extension.withheld_amount.into()),
+                )]
+            }
+            ExtensionType::MintCloseAuthority => {
+                let extension = state.get_extension::<MintCloseAuthority>()?;
+                vec![(
+                    "closeAuthority",
+                    display_optional_pubkey(extension.close_authority.into()),
+                )]
+            }
+            ExtensionType::ConfidentialTransferMint => {
+                let extension = state.get_extension::<ConfidentialTransferMint>()?;
+                let encryption_pubkey = |pubkey: &ElGamalPubkey| {
+                    if pubkey.0 == [0; 32] {
+                        "(not set)".to_string()
+                    } else {
+                        base64::encode(pubkey.0)
+                    }
+                };
+                vec![
+                    ("authority", extension.authority.to_string()),
+                    (
+                        "autoApproveNewAccounts",
+                        bool::from(extension.auto_approve_new_accounts).to_string(),
+                    ),
+                    (
+                        "auditorEncryptionPubkey",
+                        encryption_pubkey(&extension.auditor_encryption_pubkey),
+                    ),
+                    (
+                        "withdrawWithheldAuthorityEncryptionPubkey",
+                        encryption_pubkey(&extension.withdraw_withheld_authority_encryption_pubkey),
+                    ),
+                    (
+                        "withheldAmount",
+                        base64::encode(extension.withheld_amount.0),
+                    ),
+                ]
+            }
+            ExtensionType::ConfidentialTransferAccount => {
+                let extension = state.get_extension::<ConfidentialTransferAccount>()?;
+                vec![
+                    ("approved", bool::from(extension.approved).to_string()),
+                    (
+                        "encryptionPubkey",
+                        base64::encode(extension.encryption_pubkey.0),
+                    ),
+                    (
+                        "pendingBalanceLo",
+                        base64::encode(extension.pending_balance_lo.0),
+                    ),
+                    (
+                        "pendingBalanceHi",
+                        base64::encode(extension.pending_balance_hi.0),
+                    ),
+                    (
+                        "availableBalance",
+                        base64::encode(extension.available_balance.0),
+                    ),
+                    (
+                        "decryptableAvailableBalance",
+                        base64::encode(extension.decryptable_available_balance.0),
+                    ),
+                    (
+                        "allowBalanceCredits",
+                        bool::from(extension.allow_balance_credits).to_string(),
+                    ),
+                    (
+                        "pendingBalanceCreditCounter",
+                        u64::from(extension.pending_balance_credit_counter).to_string(),
+                    ),
+                    (
+                        "maximumPendingBalanceCreditCounter",
+                        u64::from(extension.maximum_pending_balance_credit_counter).to_string(),
+                    ),
+                    (
+                        "expectedPendingBalanceCreditCounter",
+                        u64::from(extension.expected_pending_balance_credit_counter).to_string(),
+                    ),
+                    (
+                        "actualPendingBalanceCreditCounter",
+                        u64::from(extension.actual_pending_balance_credit_counter).to_string(),
+                    ),
+                    (
+                        "withheldAmount",
+                        base64::encode(extension.withheld_amount.0),
+                    ),
+                ]
+            }
+            ExtensionType::DefaultAccountState => {
+                let extension = state.get_extension::<DefaultAccountState>()?;
+                vec![("state", display_account_state(extension.state))]
+            }
+            ExtensionType::ImmutableOwner | ExtensionType::NonTransferable => vec![],
+            ExtensionType::MemoTransfer => {
+                let extension = state.get_extension::<MemoTransfer>()?;
+                vec![(
+                    "requireIncomingTransferMemos",
+                    bool::from(extension.require_incoming_transfer_memos).to_string(),
+                )]
+            }
+            ExtensionType::InterestBearingConfig => {
+                let extension = state.get_extension::<InterestBearingConfig>()?;
+                vec![
+                    (
+                        "rateAuthority",
+                        display_optional_pubkey(extension.rate_authority.into()),
+                    ),
+                    (
+                        "initializationTimestamp",
+                        i64::from(extension.initialization_timestamp).to_string(),
+                    ),
+                    (
+                        "preUpdateAverageRate",
+                        i16::from(extension.pre_update_average_rate).to_string(),
+                    ),
+                    (
+                        "lastUpdateTimestamp",
+                        i64::from(extension.last_update_timestamp).to_string(),
+                    ),
+                    ("currentRate", i16::from(extension.current_rate).to_string()),
+                ]
+            }
+        };
+        let name = format!("{:?}", extension_type);
+        extensions.push((
+            name[..1].to_lowercase() + &name[1..],
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        ));
+    }
+    Ok(extensions)
+}
+
+async fn command_display(config: &Config<'_>, address: Pubkey) -> CommandResult {
+    let account = config
+        .rpc_client
+        .get_account(&address)
+        .await
+        .map_err(|_| format!("Could not find account {}", address))?;
+    if account.owner != spl_token::id() && account.owner != spl_token_2022::id() {
+        return Err(format!(
+            "Account {} is owned by {}, not a token program",
+            address, account.owner
+        )
+        .into());
+    }
+    let epoch = config.rpc_client.get_epoch_info().await?.epoch;
+
+    let mut cli_display = CliTokenDisplay {
+        address: address.to_string(),
+        program_id: account.owner.to_string(),
+        account_type: String::new(),
+        fields: vec![],
+        extensions: vec![],
+    };
+    let (account_type, fields): (&str, Vec<(&str, String)>) = if account.data.len() == Multisig::LEN
+    {
+        let multisig = Multisig::unpack(&account.data)?;
+        (
+            "multisig",
+            vec![
+                ("m", multisig.m.to_string()),
+                ("n", multisig.n.to_string()),
+                ("isInitialized", multisig.is_initialized.to_string()),
+                (
+                    "signers",
+                    multisig.signers[..multisig.n as usize]
+                        .iter()
+                        .map(|signer| signer.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            ],
+        )
+    } else if let Ok(mint) = StateWithExtensionsOwned::<Mint>::unpack(account.data.clone()) {
+        let decimals = mint.base.decimals;
+        cli_display.extensions = display_extensions(&mint, decimals, epoch)?;
+        (
+            "mint",
+            vec![
+                (
+                    "mintAuthority",
+                    display_optional_pubkey(mint.base.mint_authority.into()),
+                ),
+                (
+                    "supply",
+                    spl_token_2022::amount_to_ui_amount_string_trimmed(mint.base.supply, decimals),
+                ),
+                ("decimals", decimals.to_string()),
+                ("isInitialized", mint.base.is_initialized.to_string()),
+                (
+                    "freezeAuthority",
+                    display_optional_pubkey(mint.base.freeze_authority.into()),
+                ),
+            ],
+        )
+    } else if let Ok(token_account) = StateWithExtensionsOwned::<Account>::unpack(account.data) {
+        let mint = config
+            .rpc_client
+            .get_account(&token_account.base.mint)
+            .await?;
+        let decimals = StateWithExtensionsOwned::<Mint>::unpack(mint.data)?
+            .base
+            .decimals;
+        let ui_amount =
+            |amount: u64| spl_token_2022::amount_to_ui_amount_string_trimmed(amount, decimals);
+        cli_display.extensions = display_extensions(&token_account, decimals, epoch)?;
+        let base = token_account.base;
+        (
+            "account",
+            vec![
+                ("mint", base.mint.to_string()),
+                ("owner", base.owner.to_string()),
+                ("amount", ui_amount(base.amount)),
+                ("delegate", display_optional_pubkey(base.delegate.into())),
+                ("delegatedAmount", ui_amount(base.delegated_amount)),
+                ("state", display_account_state(base.state as u8)),
+                (
+                    "isNative",
+                    Option::<u64>::from(base.is_native)
+                        .map(|rent_exempt_reserve| {
+                            format!(
+                                "true, rent-exempt reserve {}",
+                                lamports_to_sol(rent_exempt_reserve)
+                            )
+                        })
+                        .unwrap_or_else(|| "false".to_string()),
+                ),
+                (
+                    "closeAuthority",
+                    display_optional_pubkey(base.close_authority.into()),
+                ),
+            ],
+        )
+    } else {
+        return Err(format!(
+            "Account {} is not a token mint, token account or multisig",
+            address
+        )
+        .into());
+    };
+    cli_display.account_type = account_type.to_string();
+    cli_display.fields = fields
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect();
+    Ok(config.output_format.formatted_string(&cli_display))
+}
+
 async fn get_multisig(config: &Config<'_>, address: &Pubkey) -> Result<Multisig, Error> {
     let account = config.rpc_client.get_account(address).await?;
     Multisig::unpack(&account.data).map_err(|e| e.into())
@@ -1796,501 +2307,1900 @@ async fn command_sync_native(
     })
 }
 
-struct SignOnlyNeedsFullMintSpec {}
-impl offline::ArgsConfig for SignOnlyNeedsFullMintSpec {
-    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
-        arg.requires_all(&[MINT_ADDRESS_ARG.name, MINT_DECIMALS_ARG.name])
-    }
-}
+/// Maximum number of token accounts referenced by a single withheld-fee instruction
+const MAX_WITHHELD_SOURCES_PER_TX: usize = 25;
 
-struct SignOnlyNeedsMintDecimals {}
-impl offline::ArgsConfig for SignOnlyNeedsMintDecimals {
-    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
-        arg.requires_all(&[MINT_DECIMALS_ARG.name])
+async fn get_mint_token_accounts(
+    config: &Config<'_>,
+    mint: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<Vec<(Pubkey, StateWithExtensionsOwned<Account>)>, Error> {
+    let mut filters = vec![RpcFilterType::Memcmp(Memcmp {
+        offset: 0,
+        bytes: MemcmpEncodedBytes::Bytes(mint.to_bytes().to_vec()),
+        encoding: None,
+    })];
+    if program_id == &spl_token::id() {
+        filters.push(RpcFilterType::DataSize(Account::LEN as u64));
     }
+    let accounts = config
+        .rpc_client
+        .get_program_accounts_with_config(
+            program_id,
+            RpcProgramAccountsConfig {
+                filters: Some(filters),
+                account_config: RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    commitment: Some(config.rpc_client.commitment()),
+                    ..RpcAccountInfoConfig::default()
+                },
+                ..RpcProgramAccountsConfig::default()
+            },
+        )
+        .await?;
+    // Mints and multisigs never start with a mint address, but skip anything
+    // that fails to deserialize as a token account anyway
+    Ok(accounts
+        .into_iter()
+        .filter_map(|(address, account)| {
+            StateWithExtensionsOwned::<Account>::unpack(account.data)
+                .ok()
+                .map(|state| (address, state))
+        })
+        .collect())
 }
 
-struct SignOnlyNeedsMintAddress {}
-impl offline::ArgsConfig for SignOnlyNeedsMintAddress {
-    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
-        arg.requires_all(&[MINT_ADDRESS_ARG.name])
-    }
+async fn get_accounts_with_withheld_tokens(
+    config: &Config<'_>,
+    mint: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<Vec<Pubkey>, Error> {
+    Ok(get_mint_token_accounts(config, mint, program_id)
+        .await?
+        .into_iter()
+        .filter_map(|(address, state)| {
+            state
+                .get_extension::<TransferFeeAmount>()
+                .ok()
+                .filter(|extension| u64::from(extension.withheld_amount) > 0)
+                .map(|_| address)
+        })
+        .collect())
 }
 
-struct SignOnlyNeedsDelegateAddress {}
-impl offline::ArgsConfig for SignOnlyNeedsDelegateAddress {
-    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
-        arg.requires_all(&[DELEGATE_ADDRESS_ARG.name])
-    }
-}
+#[allow(clippy::too_many_arguments)]
+async fn command_set_transfer_fee(
+    config: &Config<'_>,
+    token: Pubkey,
+    transfer_fee_authority: Pubkey,
+    transfer_fee_basis_points: u16,
+    ui_maximum_fee: f64,
+    mint_decimals: Option<u8>,
+    bulk_signers: BulkSigners,
+) -> CommandResult {
+    let mint_info = config.get_mint_info(&token, mint_decimals).await?;
+    let maximum_fee = spl_token::ui_amount_to_amount(ui_maximum_fee, mint_info.decimals);
 
-fn minimum_signers_help_string() -> String {
-    format!(
-        "The minimum number of signers required to allow the operation. [{} <= M <= N]",
-        MIN_SIGNERS
-    )
-}
+    println_display(
+        config,
+        format!(
+            "Setting transfer fee for {} to {} bps, {} maximum",
+            token, transfer_fee_basis_points, ui_maximum_fee
+        ),
+    );
 
-fn multisig_member_help_string() -> String {
-    format!(
-        "The public keys for each of the N signing members of this account. [{} <= N <= {}]",
-        MIN_SIGNERS, MAX_SIGNERS
+    let instructions = vec![transfer_fee::instruction::set_transfer_fee(
+        &mint_info.program_id,
+        &token,
+        &transfer_fee_authority,
+        &config.multisigner_pubkeys,
+        transfer_fee_basis_points,
+        maximum_fee,
+    )?];
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
     )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
 }
 
-fn app<'a, 'b>(
-    default_decimals: &'a str,
-    default_program_id: &'a str,
-    minimum_signers_help: &'b str,
-    multisig_member_help: &'b str,
-) -> App<'a, 'b> {
-    App::new(crate_name!())
-        .about(crate_description!())
-        .version(crate_version!())
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .arg(
-            Arg::with_name("config_file")
-                .short("C")
-                .long("config")
-                .value_name("PATH")
-                .takes_value(true)
-                .global(true)
-                .help("Configuration file to use"),
-        )
-        .arg(
-            Arg::with_name("verbose")
-                .short("v")
-                .long("verbose")
-                .takes_value(false)
-                .global(true)
-                .help("Show additional information"),
+async fn command_withdraw_withheld_tokens(
+    config: &Config<'_>,
+    destination_token_account: Pubkey,
+    source_token_accounts: Vec<Pubkey>,
+    mint_address: Option<Pubkey>,
+    withdraw_withheld_authority: Pubkey,
+    withdraw_from_mint: bool,
+    bulk_signers: BulkSigners,
+) -> CommandResult {
+    if source_token_accounts.is_empty() && !withdraw_from_mint {
+        return Err("No accounts specified to withdraw from".into());
+    }
+    let token = config
+        .check_account(&destination_token_account, mint_address)
+        .await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+
+    let mut instructions = vec![];
+    if withdraw_from_mint {
+        println_display(
+            config,
+            format!(
+                "Withdrawing withheld tokens from mint {} to {}",
+                token, destination_token_account
+            ),
+        );
+        instructions.push(vec![
+            transfer_fee::instruction::withdraw_withheld_tokens_from_mint(
+                &mint_info.program_id,
+                &token,
+                &destination_token_account,
+                &withdraw_withheld_authority,
+                &config.multisigner_pubkeys,
+            )?,
+        ]);
+    }
+    for sources in source_token_accounts.chunks(MAX_WITHHELD_SOURCES_PER_TX) {
+        println_display(
+            config,
+            format!(
+                "Withdrawing withheld tokens from {} accounts to {}",
+                sources.len(),
+                destination_token_account
+            ),
+        );
+        instructions.push(vec![
+            transfer_fee::instruction::withdraw_withheld_tokens_from_accounts(
+                &mint_info.program_id,
+                &token,
+                &destination_token_account,
+                &withdraw_withheld_authority,
+                &config.multisigner_pubkeys,
+                sources.iter().collect::<Vec<_>>().as_slice(),
+            )?,
+        ]);
+    }
+    // A nonce or an offline blockhash can only be used by one transaction
+    if (config.sign_only || config.nonce_account.is_some()) && instructions.len() > 1 {
+        return Err(format!(
+            "This withdrawal takes {} transactions, which cannot share a nonce or an offline \
+             blockhash; withdraw from the mint or up to {} accounts at a time",
+            instructions.len(),
+            MAX_WITHHELD_SOURCES_PER_TX
         )
-        .arg(
-            Arg::with_name("output_format")
-                .long("output")
-                .value_name("FORMAT")
+        .into());
+    }
+
+    let cli_signer_info = CliSignerInfo {
+        signers: bulk_signers,
+    };
+
+    let mut result = String::from("");
+    for tx_instructions in instructions {
+        let tx_return = handle_tx(&cli_signer_info, config, false, 0, tx_instructions).await?;
+        result += &match tx_return {
+            TransactionReturnData::CliSignature(signature) => {
+                config.output_format.formatted_string(&signature)
+            }
+            TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+                config.output_format.formatted_string(&sign_only_data)
+            }
+        };
+        result += "\n";
+    }
+    Ok(result)
+}
+
+async fn command_harvest_withheld(
+    config: &Config<'_>,
+    token: Pubkey,
+    source_token_accounts: Vec<Pubkey>,
+    bulk_signers: BulkSigners,
+) -> CommandResult {
+    let mint_info = config.get_mint_info(&token, None).await?;
+    let source_token_accounts = if source_token_accounts.is_empty() && !config.sign_only {
+        println_display(
+            config,
+            format!("Fetching accounts of {} with withheld tokens", token),
+        );
+        get_accounts_with_withheld_tokens(config, &token, &mint_info.program_id).await?
+    } else {
+        source_token_accounts
+    };
+    if source_token_accounts.is_empty() {
+        println_display(config, "Nothing to do".to_string());
+        return Ok("".to_string());
+    }
+
+    let cli_signer_info = CliSignerInfo {
+        signers: bulk_signers,
+    };
+
+    let mut result = String::from("");
+    for sources in source_token_accounts.chunks(MAX_WITHHELD_SOURCES_PER_TX) {
+        println_display(
+            config,
+            format!(
+                "Harvesting withheld tokens from {} accounts to mint {}",
+                sources.len(),
+                token
+            ),
+        );
+        let instructions = vec![transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+            &mint_info.program_id,
+            &token,
+            sources.iter().collect::<Vec<_>>().as_slice(),
+        )?];
+        let tx_return = handle_tx(&cli_signer_info, config, false, 0, instructions).await?;
+        result += &match tx_return {
+            TransactionReturnData::CliSignature(signature) => {
+                config.output_format.formatted_string(&signature)
+            }
+            TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+                config.output_format.formatted_string(&sign_only_data)
+            }
+        };
+        result += "\n";
+    }
+    Ok(result)
+}
+
+async fn command_required_transfer_memos(
+    config: &Config<'_>,
+    account: Pubkey,
+    owner: Pubkey,
+    enable: bool,
+    bulk_signers: BulkSigners,
+) -> CommandResult {
+    config.check_account(&account, None).await?;
+    let program_id = config.program_id;
+
+    let instructions = if enable {
+        println_display(
+            config,
+            format!("Enabling required transfer memos for {}", account),
+        );
+        vec![
+            reallocate(
+                &program_id,
+                &account,
+                &config.fee_payer,
+                &owner,
+                &config.multisigner_pubkeys,
+                &[ExtensionType::MemoTransfer],
+            )?,
+            memo_transfer::instruction::enable_required_transfer_memos(
+                &program_id,
+                &account,
+                &owner,
+                &config.multisigner_pubkeys,
+            )?,
+        ]
+    } else {
+        println_display(
+            config,
+            format!("Disabling required transfer memos for {}", account),
+        );
+        vec![memo_transfer::instruction::disable_required_transfer_memos(
+            &program_id,
+            &account,
+            &owner,
+            &config.multisigner_pubkeys,
+        )?]
+    };
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn command_reallocate(
+    config: &Config<'_>,
+    account: Pubkey,
+    owner: Pubkey,
+    extension_types: Vec<ExtensionType>,
+    bulk_signers: BulkSigners,
+) -> CommandResult {
+    config.check_account(&account, None).await?;
+    println_display(
+        config,
+        format!(
+            "Reallocating {} for extensions: {:?}",
+            account, extension_types
+        ),
+    );
+
+    let instructions = vec![reallocate(
+        &config.program_id,
+        &account,
+        &config.fee_payer,
+        &owner,
+        &config.multisigner_pubkeys,
+        &extension_types,
+    )?];
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn command_update_interest_rate(
+    config: &Config<'_>,
+    token: Pubkey,
+    rate_authority: Pubkey,
+    rate_bps: i16,
+    bulk_signers: BulkSigners,
+) -> CommandResult {
+    let mint_info = config.get_mint_info(&token, None).await?;
+    println_display(
+        config,
+        format!("Updating interest rate for {} to {} bps", token, rate_bps),
+    );
+
+    let instructions = vec![interest_bearing_mint::instruction::update_rate(
+        &mint_info.program_id,
+        &token,
+        &rate_authority,
+        &config.multisigner_pubkeys,
+        rate_bps,
+    )?];
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn get_confidential_transfer_account(
+    config: &Config<'_>,
+    address: &Pubkey,
+) -> Result<(Pubkey, ConfidentialTransferAccount), Error> {
+    let account = config.rpc_client.get_account(address).await?;
+    config.check_owner(address, &account.owner)?;
+    let state = StateWithExtensionsOwned::<Account>::unpack(account.data)
+        .map_err(|_| format!("Could not find token account {}", address))?;
+    let extension = state
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| {
+            format!(
+                "Token account {} is not configured for confidential transfers",
+                address
+            )
+        })?;
+    Ok((state.base.mint, *extension))
+}
+
+fn decrypt_available_balance(
+    extension: &ConfidentialTransferAccount,
+    ae_key: &AeKey,
+) -> Result<u64, Error> {
+    let ciphertext: AeCiphertext = extension
+        .decryptable_available_balance
+        .try_into()
+        .map_err(|e| format!("Invalid decryptable balance: {:?}", e))?;
+    ciphertext
+        .decrypt(ae_key)
+        .ok_or_else(|| "Could not decrypt available balance".into())
+}
+
+fn decrypt_pending_balance(
+    extension: &ConfidentialTransferAccount,
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<u64, Error> {
+    let pending_balance_lo = extension
+        .pending_balance_lo
+        .decrypt(&elgamal_keypair.secret)
+        .ok_or("Could not decrypt pending balance")?;
+    let pending_balance_hi = extension
+        .pending_balance_hi
+        .decrypt(&elgamal_keypair.secret)
+        .ok_or("Could not decrypt pending balance")?;
+    pending_balance_hi
+        .checked_shl(confidential_transfer::PENDING_BALANCE_LO_BIT_LENGTH as u32)
+        .and_then(|hi| hi.checked_add(pending_balance_lo))
+        .ok_or_else(|| "Could not decrypt pending balance".into())
+}
+
+fn check_confidential_amount(amount: u64) -> Result<(), Error> {
+    if amount >> confidential_transfer::MAXIMUM_DEPOSIT_TRANSFER_AMOUNT_BIT_LENGTH != 0 {
+        Err(format!(
+            "Error: confidential amounts must be less than 2^{}",
+            confidential_transfer::MAXIMUM_DEPOSIT_TRANSFER_AMOUNT_BIT_LENGTH
+        )
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Default number of deposits and incoming transfers an account may receive
+/// before its pending balance must be applied, keeping it decryptable
+const DEFAULT_MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER: u64 = 65536;
+
+async fn command_configure_confidential_account(
+    config: &Config<'_>,
+    account: Pubkey,
+    owner_signer: Box<dyn Signer>,
+    maximum_pending_balance_credit_counter: u64,
+    mut bulk_signers: BulkSigners,
+) -> CommandResult {
+    let owner = owner_signer.pubkey();
+    let token = config.check_account(&account, None).await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+    println_display(
+        config,
+        format!("Configuring {} for confidential transfers", account),
+    );
+
+    let elgamal_pubkey = ElGamalKeypair::new(&*owner_signer, &account)?.public;
+    let decryptable_zero_balance = AeKey::new(&*owner_signer, &account)?.encrypt(0);
+    bulk_signers.push(owner_signer);
+
+    let instructions = vec![
+        reallocate(
+            &mint_info.program_id,
+            &account,
+            &config.fee_payer,
+            &owner,
+            &config.multisigner_pubkeys,
+            &[ExtensionType::ConfidentialTransferAccount],
+        )?,
+        confidential_transfer::instruction::configure_account(
+            &mint_info.program_id,
+            &account,
+            &token,
+            elgamal_pubkey.into(),
+            decryptable_zero_balance,
+            maximum_pending_balance_credit_counter,
+            &owner,
+            &config.multisigner_pubkeys,
+        )?,
+    ];
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn command_deposit_confidential(
+    config: &Config<'_>,
+    account: Pubkey,
+    owner: Pubkey,
+    ui_amount: f64,
+    bulk_signers: BulkSigners,
+) -> CommandResult {
+    let (token, _) = get_confidential_transfer_account(config, &account).await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+    let amount = spl_token::ui_amount_to_amount(ui_amount, mint_info.decimals);
+    check_confidential_amount(amount)?;
+    println_display(
+        config,
+        format!(
+            "Depositing {} tokens into the pending balance of {}",
+            ui_amount, account
+        ),
+    );
+
+    let instructions = vec![confidential_transfer::instruction::deposit(
+        &mint_info.program_id,
+        &account,
+        &token,
+        &account,
+        amount,
+        mint_info.decimals,
+        &owner,
+        &config.multisigner_pubkeys,
+    )?];
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn command_apply_pending_balance(
+    config: &Config<'_>,
+    account: Pubkey,
+    owner_signer: Box<dyn Signer>,
+    mut bulk_signers: BulkSigners,
+) -> CommandResult {
+    let owner = owner_signer.pubkey();
+    let (token, extension) = get_confidential_transfer_account(config, &account).await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+
+    let elgamal_keypair = ElGamalKeypair::new(&*owner_signer, &account)?;
+    let ae_key = AeKey::new(&*owner_signer, &account)?;
+    bulk_signers.push(owner_signer);
+
+    let available_balance = decrypt_available_balance(&extension, &ae_key)?;
+    let pending_balance = decrypt_pending_balance(&extension, &elgamal_keypair)?;
+    let new_decryptable_available_balance = available_balance
+        .checked_add(pending_balance)
+        .ok_or("Available balance overflow")?;
+    println_display(
+        config,
+        format!(
+            "Applying pending balance of {} tokens to {}",
+            spl_token::amount_to_ui_amount(pending_balance, mint_info.decimals),
+            account
+        ),
+    );
+
+    let instructions = vec![confidential_transfer::instruction::apply_pending_balance(
+        &mint_info.program_id,
+        &account,
+        u64::from(extension.pending_balance_credit_counter),
+        ae_key.encrypt(new_decryptable_available_balance),
+        &owner,
+        &config.multisigner_pubkeys,
+    )?];
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn command_confidential_transfer(
+    config: &Config<'_>,
+    sender: Pubkey,
+    recipient: Pubkey,
+    owner_signer: Box<dyn Signer>,
+    ui_amount: f64,
+    mut bulk_signers: BulkSigners,
+) -> CommandResult {
+    let owner = owner_signer.pubkey();
+    let (token, sender_extension) = get_confidential_transfer_account(config, &sender).await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+    // A recipient that isn't a token account is treated as a wallet address
+    let recipient = match config
+        .rpc_client
+        .get_account_with_commitment(&recipient, config.rpc_client.commitment())
+        .await?
+        .value
+    {
+        Some(account) if account.owner == mint_info.program_id => recipient,
+        _ => {
+            get_associated_token_address_with_program_id(&recipient, &token, &mint_info.program_id)
+        }
+    };
+    let (recipient_token, recipient_extension) =
+        get_confidential_transfer_account(config, &recipient).await?;
+    if token != recipient_token {
+        return Err(format!(
+            "Recipient {:?} does not contain {:?} tokens",
+            recipient, token
+        )
+        .into());
+    }
+    let mint_account = config.rpc_client.get_account(&token).await?;
+    let mint = StateWithExtensionsOwned::<Mint>::unpack(mint_account.data)
+        .map_err(|_| format!("Could not find mint account {}", token))?;
+    let ct_mint = mint.get_extension::<ConfidentialTransferMint>()?;
+    let decimals = mint_info.decimals;
+    let amount = spl_token::ui_amount_to_amount(ui_amount, decimals);
+    check_confidential_amount(amount)?;
+
+    let source_elgamal_keypair = ElGamalKeypair::new(&*owner_signer, &sender)?;
+    let source_ae_key = AeKey::new(&*owner_signer, &sender)?;
+    bulk_signers.push(owner_signer);
+
+    let source_available_balance = decrypt_available_balance(&sender_extension, &source_ae_key)?;
+    let source_remaining_balance =
+        source_available_balance
+            .checked_sub(amount)
+            .ok_or_else(|| {
+                format!(
+                    "Error: Sender has insufficient confidential funds, available balance is {}",
+                    spl_token::amount_to_ui_amount(source_available_balance, decimals)
+                )
+            })?;
+    let source_available_balance_ciphertext: ElGamalCiphertext = sender_extension
+        .available_balance
+        .try_into()
+        .map_err(|e| format!("Invalid available balance: {:?}", e))?;
+    let destination_elgamal_pubkey: elgamal::ElGamalPubkey = recipient_extension
+        .encryption_pubkey
+        .try_into()
+        .map_err(|e| format!("Invalid recipient encryption key: {:?}", e))?;
+    let auditor_elgamal_pubkey: elgamal::ElGamalPubkey = ct_mint
+        .auditor_encryption_pubkey
+        .try_into()
+        .map_err(|e| format!("Invalid auditor encryption key: {:?}", e))?;
+    let new_source_decryptable_available_balance = source_ae_key.encrypt(source_remaining_balance);
+
+    println_display(
+        config,
+        format!(
+            "Confidentially transfer {} tokens\n  Sender: {}\n  Recipient: {}",
+            ui_amount, sender, recipient
+        ),
+    );
+
+    let instructions = if let Ok(transfer_fee_config) = mint.get_extension::<TransferFeeConfig>() {
+        let epoch = config.rpc_client.get_epoch_info().await?.epoch;
+        let fee = transfer_fee_config.get_epoch_fee(epoch);
+        let withdraw_withheld_authority_elgamal_pubkey: elgamal::ElGamalPubkey = ct_mint
+            .withdraw_withheld_authority_encryption_pubkey
+            .try_into()
+            .map_err(|e| format!("Invalid withdraw withheld encryption key: {:?}", e))?;
+        let proof_data = confidential_transfer::instruction::TransferWithFeeData::new(
+            amount,
+            (
+                source_available_balance,
+                &source_available_balance_ciphertext,
+            ),
+            &source_elgamal_keypair,
+            (&destination_elgamal_pubkey, &auditor_elgamal_pubkey),
+            FeeParameters {
+                fee_rate_basis_points: u16::from(fee.transfer_fee_basis_points),
+                maximum_fee: u64::from(fee.maximum_fee),
+            },
+            &withdraw_withheld_authority_elgamal_pubkey,
+        )
+        .map_err(|e| format!("Could not generate transfer proof: {:?}", e))?;
+        confidential_transfer::instruction::transfer_with_fee(
+            &mint_info.program_id,
+            &sender,
+            &recipient,
+            &token,
+            new_source_decryptable_available_balance,
+            &owner,
+            &config.multisigner_pubkeys,
+            &proof_data,
+        )?
+    } else {
+        let proof_data = confidential_transfer::instruction::TransferData::new(
+            amount,
+            (
+                source_available_balance,
+                &source_available_balance_ciphertext,
+            ),
+            &source_elgamal_keypair,
+            (&destination_elgamal_pubkey, &auditor_elgamal_pubkey),
+        )
+        .map_err(|e| format!("Could not generate transfer proof: {:?}", e))?;
+        confidential_transfer::instruction::transfer(
+            &mint_info.program_id,
+            &sender,
+            &recipient,
+            &token,
+            new_source_decryptable_available_balance,
+            &owner,
+            &config.multisigner_pubkeys,
+            &proof_data,
+        )?
+    };
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn command_withdraw_confidential(
+    config: &Config<'_>,
+    account: Pubkey,
+    owner_signer: Box<dyn Signer>,
+    ui_amount: f64,
+    mut bulk_signers: BulkSigners,
+) -> CommandResult {
+    let owner = owner_signer.pubkey();
+    let (token, extension) = get_confidential_transfer_account(config, &account).await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+    let amount = spl_token::ui_amount_to_amount(ui_amount, mint_info.decimals);
+
+    let elgamal_keypair = ElGamalKeypair::new(&*owner_signer, &account)?;
+    let ae_key = AeKey::new(&*owner_signer, &account)?;
+    bulk_signers.push(owner_signer);
+
+    let available_balance = decrypt_available_balance(&extension, &ae_key)?;
+    let remaining_balance = available_balance.checked_sub(amount).ok_or_else(|| {
+        format!(
+            "Error: Account has insufficient confidential funds, available balance is {}",
+            spl_token::amount_to_ui_amount(available_balance, mint_info.decimals)
+        )
+    })?;
+    let available_balance_ciphertext: ElGamalCiphertext = extension
+        .available_balance
+        .try_into()
+        .map_err(|e| format!("Invalid available balance: {:?}", e))?;
+    let proof_data = confidential_transfer::instruction::WithdrawData::new(
+        amount,
+        &elgamal_keypair,
+        available_balance,
+        &available_balance_ciphertext,
+    )
+    .map_err(|e| format!("Could not generate withdraw proof: {:?}", e))?;
+    println_display(
+        config,
+        format!(
+            "Withdrawing {} confidential tokens from {}",
+            ui_amount, account
+        ),
+    );
+
+    let instructions = confidential_transfer::instruction::withdraw(
+        &mint_info.program_id,
+        &account,
+        &account,
+        &token,
+        amount,
+        mint_info.decimals,
+        ae_key.encrypt(remaining_balance),
+        &owner,
+        &config.multisigner_pubkeys,
+        &proof_data,
+    )?;
+    let tx_return = handle_tx(
+        &CliSignerInfo {
+            signers: bulk_signers,
+        },
+        config,
+        false,
+        0,
+        instructions,
+    )
+    .await?;
+    Ok(match tx_return {
+        TransactionReturnData::CliSignature(signature) => {
+            config.output_format.formatted_string(&signature)
+        }
+        TransactionReturnData::CliSignOnlyData(sign_only_data) => {
+            config.output_format.formatted_string(&sign_only_data)
+        }
+    })
+}
+
+async fn command_show_confidential_balance(
+    config: &Config<'_>,
+    account: Pubkey,
+    owner_signer: Box<dyn Signer>,
+) -> CommandResult {
+    let (token, extension) = get_confidential_transfer_account(config, &account).await?;
+    let mint_info = config.get_mint_info(&token, None).await?;
+    let elgamal_keypair = ElGamalKeypair::new(&*owner_signer, &account)?;
+    let ae_key = AeKey::new(&*owner_signer, &account)?;
+
+    let available_balance = decrypt_available_balance(&extension, &ae_key)?;
+    let pending_balance = decrypt_pending_balance(&extension, &elgamal_keypair)?;
+    let cli_confidential_balance = CliConfidentialBalance {
+        address: account.to_string(),
+        mint: token.to_string(),
+        decimals: mint_info.decimals,
+        available_balance: spl_token_2022::amount_to_ui_amount_string_trimmed(
+            available_balance,
+            mint_info.decimals,
+        ),
+        pending_balance: spl_token_2022::amount_to_ui_amount_string_trimmed(
+            pending_balance,
+            mint_info.decimals,
+        ),
+        approved: bool::from(extension.approved),
+        allow_balance_credits: bool::from(extension.allow_balance_credits),
+    };
+    Ok(config
+        .output_format
+        .formatted_string(&cli_confidential_balance))
+}
+
+struct SignOnlyNeedsFullMintSpec {}
+impl offline::ArgsConfig for SignOnlyNeedsFullMintSpec {
+    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
+        arg.requires_all(&[MINT_ADDRESS_ARG.name, MINT_DECIMALS_ARG.name])
+    }
+}
+
+struct SignOnlyNeedsMintDecimals {}
+impl offline::ArgsConfig for SignOnlyNeedsMintDecimals {
+    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
+        arg.requires_all(&[MINT_DECIMALS_ARG.name])
+    }
+}
+
+struct SignOnlyNeedsMintAddress {}
+impl offline::ArgsConfig for SignOnlyNeedsMintAddress {
+    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
+        arg.requires_all(&[MINT_ADDRESS_ARG.name])
+    }
+}
+
+struct SignOnlyNeedsDelegateAddress {}
+impl offline::ArgsConfig for SignOnlyNeedsDelegateAddress {
+    fn sign_only_arg<'a, 'b>(&self, arg: Arg<'a, 'b>) -> Arg<'a, 'b> {
+        arg.requires_all(&[DELEGATE_ADDRESS_ARG.name])
+    }
+}
+
+fn minimum_signers_help_string() -> String {
+    format!(
+        "The minimum number of signers required to allow the operation. [{} <= M <= N]",
+        MIN_SIGNERS
+    )
+}
+
+fn multisig_member_help_string() -> String {
+    format!(
+        "The public keys for each of the N signing members of this account. [{} <= N <= {}]",
+        MIN_SIGNERS, MAX_SIGNERS
+    )
+}
+
+fn app<'a, 'b>(
+    default_decimals: &'a str,
+    default_program_id: &'a str,
+    minimum_signers_help: &'b str,
+    multisig_member_help: &'b str,
+) -> App<'a, 'b> {
+    App::new(crate_name!())
+        .about(crate_description!())
+        .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("config_file")
+                .short("C")
+                .long("config")
+                .value_name("PATH")
+                .takes_value(true)
+                .global(true)
+                .help("Configuration file to use"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .takes_value(false)
+                .global(true)
+                .help("Show additional information"),
+        )
+        .arg(
+            Arg::with_name("output_format")
+                .long("output")
+                .value_name("FORMAT")
                 .global(true)
                 .takes_value(true)
                 .possible_values(&["json", "json-compact"])
                 .help("Return information in specified output format"),
         )
-        .arg(
-            Arg::with_name("program_id")
-                .short("p")
-                .long("program-id")
-                .value_name("ADDRESS")
-                .takes_value(true)
-                .global(true)
-                .default_value(default_program_id)
-                .validator(is_valid_pubkey)
-                .help("SPL Token program id"),
+        .arg(
+            Arg::with_name("program_id")
+                .short("p")
+                .long("program-id")
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .global(true)
+                .default_value(default_program_id)
+                .validator(is_valid_pubkey)
+                .help("SPL Token program id"),
+        )
+        .arg(
+            Arg::with_name("json_rpc_url")
+                .short("u")
+                .long("url")
+                .value_name("URL_OR_MONIKER")
+                .takes_value(true)
+                .global(true)
+                .validator(is_url_or_moniker)
+                .help(
+                    "URL for Solana's JSON RPC or moniker (or their first letter): \
+                       [mainnet-beta, testnet, devnet, localhost] \
+                    Default from the configuration file."
+                ),
+        )
+        .arg(fee_payer_arg().global(true))
+        .arg(
+            Arg::with_name("use_unchecked_instruction")
+                .long("use-unchecked-instruction")
+                .takes_value(false)
+                .global(true)
+                .hidden(true)
+                .help("Use unchecked instruction if appropriate. Supports transfer, burn, mint, and approve."),
+        )
+        .bench_subcommand()
+        .distribute_subcommand()
+        .multisig_subcommand()
+        .apply_subcommand()
+        .subcommand(SubCommand::with_name(CommandName::CreateToken.into()).about("Create a new token")
+                .arg(
+                    Arg::with_name("token_keypair")
+                        .value_name("TOKEN_KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .index(1)
+                        .help(
+                            "Specify the token keypair. \
+                             This may be a keypair file or the ASK keyword. \
+                             [default: randomly generated keypair]"
+                        ),
+                )
+                .arg(
+                    Arg::with_name("mint_authority")
+                        .long("mint-authority")
+                        .alias("owner")
+                        .value_name("ADDRESS")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .help(
+                            "Specify the mint authority address. \
+                             Defaults to the client keypair address."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("decimals")
+                        .long("decimals")
+                        .validator(is_mint_decimals)
+                        .value_name("DECIMALS")
+                        .takes_value(true)
+                        .default_value(default_decimals)
+                        .help("Number of base 10 digits to the right of the decimal place"),
+                )
+                .arg(
+                    Arg::with_name("enable_freeze")
+                        .long("enable-freeze")
+                        .takes_value(false)
+                        .help(
+                            "Enable the mint authority to freeze associated token accounts."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("transfer_fee")
+                        .long("transfer-fee")
+                        .value_names(&["FEE_IN_BASIS_POINTS", "MAXIMUM_FEE"])
+                        .validator(is_amount)
+                        .takes_value(true)
+                        .number_of_values(2)
+                        .help(
+                            "Add a transfer fee to the mint. \
+                             The mint authority can set and collect the fee. \
+                             The maximum fee is given in token units, not base units."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("interest_rate")
+                        .long("interest-rate")
+                        .value_name("RATE_BPS")
+                        .validator(is_parsable::<i16>)
+                        .takes_value(true)
+                        .allow_hyphen_values(true)
+                        .help(
+                            "Specify the interest rate in basis points. \
+                             Rate authority defaults to the mint authority."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("default_account_state")
+                        .long("default-account-state")
+                        .value_name("STATE")
+                        .takes_value(true)
+                        .possible_values(&["initialized", "frozen"])
+                        .help(
+                            "Specify the state of newly created token accounts. \
+                             `frozen` requires --enable-freeze."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("enable_close")
+                        .long("enable-close")
+                        .takes_value(false)
+                        .help(
+                            "Enable the mint authority to close this mint once its supply is zero."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("enable_non_transferable")
+                        .long("enable-non-transferable")
+                        .alias("enable-nontransferable")
+                        .takes_value(false)
+                        .help("Prevent tokens of this mint from being transferred."),
+                )
+                .arg(
+                    Arg::with_name("enable_confidential_transfers")
+                        .long("enable-confidential-transfers")
+                        .takes_value(false)
+                        .help(
+                            "Enable confidential transfers for this mint. \
+                             New accounts are approved automatically and the \
+                             mint authority can update the configuration."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("confidential_transfer_auditor")
+                        .long("confidential-transfer-auditor")
+                        .value_name("ELGAMAL_PUBKEY")
+                        .validator(is_elgamal_pubkey)
+                        .takes_value(true)
+                        .requires("enable_confidential_transfers")
+                        .help(
+                            "Base64-encoded ElGamal public key of the auditor \
+                             able to decrypt every confidential transfer amount."
+                        ),
+                )
+                .nonce_args(true)
+                .arg(memo_arg())
+                .offline_args(),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::CreateAccount.into())
+                .about("Create a new token account")
+                .arg(
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The token that the account will hold"),
+                )
+                .arg(
+                    Arg::with_name("account_keypair")
+                        .value_name("ACCOUNT_KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .index(2)
+                        .help(
+                            "Specify the account keypair. \
+                             This may be a keypair file or the ASK keyword. \
+                             [default: associated token account for --owner]"
+                        ),
+                )
+                .arg(owner_address_arg())
+                .nonce_args(true)
+                .offline_args(),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::CreateMultisig.into())
+                .about("Create a new account describing an M:N multisignature")
+                .arg(
+                    Arg::with_name("minimum_signers")
+                        .value_name("MINIMUM_SIGNERS")
+                        .validator(is_multisig_minimum_signers)
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help(minimum_signers_help),
+                )
+                .arg(
+                    Arg::with_name("multisig_member")
+                        .value_name("MULTISIG_MEMBER_PUBKEY")
+                        .validator(is_valid_pubkey)
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .min_values(MIN_SIGNERS as u64)
+                        .max_values(MAX_SIGNERS as u64)
+                        .help(multisig_member_help),
+                )
+                .arg(
+                    Arg::with_name("address_keypair")
+                        .long("address-keypair")
+                        .value_name("ADDRESS_KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .help(
+                            "Specify the address keypair. \
+                             This may be a keypair file or the ASK keyword. \
+                             [default: randomly generated keypair]"
+                        ),
+                )
+                .nonce_args(true)
+                .offline_args(),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Authorize.into())
+                .about("Authorize a new signing keypair to a token or token account")
+                .arg(
+                    Arg::with_name("address")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The address of the token account"),
+                )
+                .arg(
+                    Arg::with_name("authority_type")
+                        .value_name("AUTHORITY_TYPE")
+                        .takes_value(true)
+                        .possible_values(&[
+                            "mint", "freeze", "owner", "close",
+                            "close-mint", "transfer-fee-config", "withheld-withdraw",
+                            "interest-rate",
+                        ])
+                        .index(2)
+                        .required(true)
+                        .help("The new authority type. \
+                            Token mints support `mint` and `freeze` authorities;\
+                            Token accounts support `owner` and `close` authorities."),
+                )
+                .arg(
+                    Arg::with_name("new_authority")
+                        .validator(is_valid_pubkey)
+                        .value_name("AUTHORITY_ADDRESS")
+                        .takes_value(true)
+                        .index(3)
+                        .required_unless("disable")
+                        .help("The address of the new authority"),
+                )
+                .arg(
+                    Arg::with_name("authority")
+                        .long("authority")
+                        .alias("owner")
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .help(
+                            "Specify the current authority keypair. \
+                             Defaults to the client keypair."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("disable")
+                        .long("disable")
+                        .takes_value(false)
+                        .conflicts_with("new_authority")
+                        .help("Disable mint, freeze, or close functionality by setting authority to None.")
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .hidden(true)
+                        .help("Force re-authorize the wallet's associate token account. Don't use this flag"),
+                )
+                .arg(multisig_signer_arg())
+                .nonce_args(true)
+                .offline_args(),
         )
-        .arg(
-            Arg::with_name("json_rpc_url")
-                .short("u")
-                .long("url")
-                .value_name("URL_OR_MONIKER")
-                .takes_value(true)
-                .global(true)
-                .validator(is_url_or_moniker)
-                .help(
-                    "URL for Solana's JSON RPC or moniker (or their first letter): \
-                       [mainnet-beta, testnet, devnet, localhost] \
-                    Default from the configuration file."
-                ),
+        .subcommand(
+            SubCommand::with_name(CommandName::Transfer.into())
+                .about("Transfer tokens between accounts")
+                .arg(
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("Token to transfer"),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .validator(is_amount_or_all)
+                        .value_name("TOKEN_AMOUNT")
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .help("Amount to send, in tokens; accepts keyword ALL"),
+                )
+                .arg(
+                    Arg::with_name("recipient")
+                        .validator(is_valid_pubkey)
+                        .value_name("RECIPIENT_ADDRESS or RECIPIENT_TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .index(3)
+                        .required(true)
+                        .help("If a token account address is provided, use it as the recipient. \
+                               Otherwise assume the recipient address is a user wallet and transfer to \
+                               the associated token account")
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .validator(is_valid_pubkey)
+                        .value_name("SENDER_TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .long("from")
+                        .help("Specify the sending token account \
+                            [default: owner's associated token account]")
+                )
+                .arg(owner_keypair_arg_with_value_name("SENDER_TOKEN_OWNER_KEYPAIR")
+                        .help(
+                            "Specify the owner of the sending token account. \
+                            This may be a keypair file, the ASK keyword. \
+                            Defaults to the client keypair.",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("allow_unfunded_recipient")
+                        .long("allow-unfunded-recipient")
+                        .takes_value(false)
+                        .help("Complete the transfer even if the recipient address is not funded")
+                )
+                .arg(
+                    Arg::with_name("allow_empty_recipient")
+                        .long("allow-empty-recipient")
+                        .takes_value(false)
+                        .hidden(true) // Deprecated, use --allow-unfunded-recipient instead
+                )
+                .arg(
+                    Arg::with_name("fund_recipient")
+                        .long("fund-recipient")
+                        .takes_value(false)
+                        .help("Create the associated token account for the recipient if doesn't already exist")
+                )
+                .arg(
+                    Arg::with_name("no_wait")
+                        .long("no-wait")
+                        .takes_value(false)
+                        .help("Return signature immediately after submitting the transaction, instead of waiting for confirmations"),
+                )
+                .arg(
+                    Arg::with_name("allow_non_system_account_recipient")
+                        .long("allow-non-system-account-recipient")
+                        .takes_value(false)
+                        .help("Send tokens to the recipient even if the recipient is not a wallet owned by System Program."),
+                )
+                .arg(
+                    Arg::with_name("recipient_is_ata_owner")
+                        .long("recipient-is-ata-owner")
+                        .takes_value(false)
+                        .requires("sign_only")
+                        .help("In sign-only mode, specifies that the recipient is the owner of the associated token account rather than an actual token account"),
+                )
+                .arg(multisig_signer_arg())
+                .arg(mint_decimals_arg())
+                .nonce_args(true)
+                .arg(memo_arg())
+                .offline_args_config(&SignOnlyNeedsMintDecimals{}),
         )
-        .arg(fee_payer_arg().global(true))
-        .arg(
-            Arg::with_name("use_unchecked_instruction")
-                .long("use-unchecked-instruction")
-                .takes_value(false)
-                .global(true)
-                .hidden(true)
-                .help("Use unchecked instruction if appropriate. Supports transfer, burn, mint, and approve."),
+        .subcommand(
+            SubCommand::with_name(CommandName::Burn.into())
+                .about("Burn tokens from an account")
+                .arg(
+                    Arg::with_name("source")
+                        .validator(is_valid_pubkey)
+                        .value_name("SOURCE_TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The token account address to burn from"),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .validator(is_amount)
+                        .value_name("TOKEN_AMOUNT")
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .help("Amount to burn, in tokens"),
+                )
+                .arg(owner_keypair_arg_with_value_name("SOURCE_TOKEN_OWNER_KEYPAIR")
+                        .help(
+                            "Specify the source token owner account. \
+                            This may be a keypair file, the ASK keyword. \
+                            Defaults to the client keypair.",
+                        ),
+                )
+                .arg(multisig_signer_arg())
+                .mint_args()
+                .nonce_args(true)
+                .arg(memo_arg())
+                .offline_args_config(&SignOnlyNeedsFullMintSpec{}),
         )
-        .bench_subcommand()
-        .subcommand(SubCommand::with_name(CommandName::CreateToken.into()).about("Create a new token")
+        .subcommand(
+            SubCommand::with_name(CommandName::Mint.into())
+                .about("Mint new tokens")
                 .arg(
-                    Arg::with_name("token_keypair")
-                        .value_name("TOKEN_KEYPAIR")
-                        .validator(is_valid_signer)
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The token to mint"),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .validator(is_amount)
+                        .value_name("TOKEN_AMOUNT")
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .help("Amount to mint, in tokens"),
+                )
+                .arg(
+                    Arg::with_name("recipient")
+                        .validator(is_valid_pubkey)
+                        .value_name("RECIPIENT_TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
-                        .index(1)
-                        .help(
-                            "Specify the token keypair. \
-                             This may be a keypair file or the ASK keyword. \
-                             [default: randomly generated keypair]"
-                        ),
+                        .index(3)
+                        .help("The token account address of recipient [default: associated token account for --owner]"),
                 )
                 .arg(
                     Arg::with_name("mint_authority")
                         .long("mint-authority")
                         .alias("owner")
-                        .value_name("ADDRESS")
-                        .validator(is_valid_pubkey)
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
                         .takes_value(true)
                         .help(
-                            "Specify the mint authority address. \
-                             Defaults to the client keypair address."
+                            "Specify the mint authority keypair. \
+                             This may be a keypair file or the ASK keyword. \
+                             Defaults to the client keypair."
                         ),
                 )
+                .arg(mint_decimals_arg())
+                .arg(multisig_signer_arg())
+                .nonce_args(true)
+                .offline_args_config(&SignOnlyNeedsMintDecimals{}),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Freeze.into())
+                .about("Freeze a token account")
                 .arg(
-                    Arg::with_name("decimals")
-                        .long("decimals")
-                        .validator(is_mint_decimals)
-                        .value_name("DECIMALS")
+                    Arg::with_name("account")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
-                        .default_value(default_decimals)
-                        .help("Number of base 10 digits to the right of the decimal place"),
+                        .index(1)
+                        .required(true)
+                        .help("The address of the token account to freeze"),
                 )
                 .arg(
-                    Arg::with_name("enable_freeze")
-                        .long("enable-freeze")
-                        .takes_value(false)
+                    Arg::with_name("freeze_authority")
+                        .long("freeze-authority")
+                        .alias("owner")
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
                         .help(
-                            "Enable the mint authority to freeze associated token accounts."
+                            "Specify the freeze authority keypair. \
+                             This may be a keypair file or the ASK keyword. \
+                             Defaults to the client keypair."
                         ),
                 )
+                .arg(mint_address_arg())
+                .arg(multisig_signer_arg())
                 .nonce_args(true)
-                .arg(memo_arg())
-                .offline_args(),
+                .offline_args_config(&SignOnlyNeedsMintAddress{}),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::CreateAccount.into())
-                .about("Create a new token account")
+            SubCommand::with_name(CommandName::Thaw.into())
+                .about("Thaw a token account")
                 .arg(
-                    Arg::with_name("token")
+                    Arg::with_name("account")
                         .validator(is_valid_pubkey)
-                        .value_name("TOKEN_ADDRESS")
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
                         .index(1)
                         .required(true)
-                        .help("The token that the account will hold"),
+                        .help("The address of the token account to thaw"),
                 )
                 .arg(
-                    Arg::with_name("account_keypair")
-                        .value_name("ACCOUNT_KEYPAIR")
+                    Arg::with_name("freeze_authority")
+                        .long("freeze-authority")
+                        .alias("owner")
+                        .value_name("KEYPAIR")
                         .validator(is_valid_signer)
                         .takes_value(true)
-                        .index(2)
                         .help(
-                            "Specify the account keypair. \
+                            "Specify the freeze authority keypair. \
                              This may be a keypair file or the ASK keyword. \
-                             [default: associated token account for --owner]"
+                             Defaults to the client keypair."
                         ),
                 )
-                .arg(owner_address_arg())
+                .arg(mint_address_arg())
+                .arg(multisig_signer_arg())
                 .nonce_args(true)
-                .offline_args(),
+                .offline_args_config(&SignOnlyNeedsMintAddress{}),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::CreateMultisig.into())
-                .about("Create a new account describing an M:N multisignature")
+            SubCommand::with_name(CommandName::Wrap.into())
+                .about("Wrap native SOL in a SOL token account")
                 .arg(
-                    Arg::with_name("minimum_signers")
-                        .value_name("MINIMUM_SIGNERS")
-                        .validator(is_multisig_minimum_signers)
+                    Arg::with_name("amount")
+                        .validator(is_amount)
+                        .value_name("AMOUNT")
                         .takes_value(true)
                         .index(1)
                         .required(true)
-                        .help(minimum_signers_help),
+                        .help("Amount of SOL to wrap"),
                 )
                 .arg(
-                    Arg::with_name("multisig_member")
-                        .value_name("MULTISIG_MEMBER_PUBKEY")
+                    Arg::with_name("wallet_keypair")
+                        .alias("owner")
+                        .value_name("KEYPAIR")
+                        .validator(is_valid_signer)
+                        .takes_value(true)
+                        .help(
+                            "Specify the keypair for the wallet which will have its native SOL wrapped. \
+                             This wallet will be assigned as the owner of the wrapped SOL token account. \
+                             This may be a keypair file or the ASK keyword. \
+                             Defaults to the client keypair."
+                        ),
+                )
+                .arg(
+                    Arg::with_name("create_aux_account")
+                        .takes_value(false)
+                        .long("create-aux-account")
+                        .help("Wrap SOL in an auxiliary account instead of associated token account"),
+                )
+                .nonce_args(true)
+                .offline_args(),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Unwrap.into())
+                .about("Unwrap a SOL token account")
+                .arg(
+                    Arg::with_name("address")
                         .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
-                        .index(2)
-                        .required(true)
-                        .min_values(MIN_SIGNERS as u64)
-                        .max_values(MAX_SIGNERS as u64)
-                        .help(multisig_member_help),
+                        .index(1)
+                        .help("The address of the auxiliary token account to unwrap \
+                            [default: associated token account for --owner]"),
                 )
                 .arg(
-                    Arg::with_name("address_keypair")
-                        .long("address-keypair")
-                        .value_name("ADDRESS_KEYPAIR")
+                    Arg::with_name("wallet_keypair")
+                        .alias("owner")
+                        .value_name("KEYPAIR")
                         .validator(is_valid_signer)
                         .takes_value(true)
                         .help(
-                            "Specify the address keypair. \
+                            "Specify the keypair for the wallet which owns the wrapped SOL. \
+                             This wallet will receive the unwrapped SOL. \
                              This may be a keypair file or the ASK keyword. \
-                             [default: randomly generated keypair]"
+                             Defaults to the client keypair."
                         ),
                 )
+                .arg(multisig_signer_arg())
                 .nonce_args(true)
                 .offline_args(),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Authorize.into())
-                .about("Authorize a new signing keypair to a token or token account")
+            SubCommand::with_name(CommandName::Approve.into())
+                .about("Approve a delegate for a token account")
                 .arg(
-                    Arg::with_name("address")
+                    Arg::with_name("account")
                         .validator(is_valid_pubkey)
-                        .value_name("TOKEN_ADDRESS")
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
                         .index(1)
                         .required(true)
-                        .help("The address of the token account"),
+                        .help("The address of the token account to delegate"),
                 )
                 .arg(
-                    Arg::with_name("authority_type")
-                        .value_name("AUTHORITY_TYPE")
+                    Arg::with_name("amount")
+                        .validator(is_amount)
+                        .value_name("TOKEN_AMOUNT")
                         .takes_value(true)
-                        .possible_values(&[
-                            "mint", "freeze", "owner", "close",
-                            "close-mint", "transfer-fee-config", "withheld-withdraw",
-                            "interest-rate",
-                        ])
                         .index(2)
                         .required(true)
-                        .help("The new authority type. \
-                            Token mints support `mint` and `freeze` authorities;\
-                            Token accounts support `owner` and `close` authorities."),
+                        .help("Amount to approve, in tokens"),
                 )
                 .arg(
-                    Arg::with_name("new_authority")
+                    Arg::with_name("delegate")
                         .validator(is_valid_pubkey)
-                        .value_name("AUTHORITY_ADDRESS")
+                        .value_name("DELEGATE_TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
                         .index(3)
-                        .required_unless("disable")
-                        .help("The address of the new authority"),
+                        .required(true)
+                        .help("The token account address of delegate"),
                 )
                 .arg(
-                    Arg::with_name("authority")
-                        .long("authority")
+                    owner_keypair_arg()
+                )
+                .arg(multisig_signer_arg())
+                .mint_args()
+                .nonce_args(true)
+                .offline_args_config(&SignOnlyNeedsFullMintSpec{}),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Revoke.into())
+                .about("Revoke a delegate's authority")
+                .arg(
+                    Arg::with_name("account")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The address of the token account"),
+                )
+                .arg(owner_keypair_arg()
+                )
+                .arg(delegate_address_arg())
+                .arg(multisig_signer_arg())
+                .nonce_args(true)
+                .offline_args_config(&SignOnlyNeedsDelegateAddress{}),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Close.into())
+                .about("Close a token account")
+                .arg(
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required_unless("address")
+                        .help("Token to close. To close a specific account, use the `--address` parameter instead"),
+                )
+                .arg(owner_address_arg())
+                .arg(
+                    Arg::with_name("recipient")
+                        .long("recipient")
+                        .validator(is_valid_pubkey)
+                        .value_name("REFUND_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .help("The address of the account to receive remaining SOL [default: --owner]"),
+                )
+                .arg(
+                    Arg::with_name("close_authority")
+                        .long("close-authority")
                         .alias("owner")
                         .value_name("KEYPAIR")
                         .validator(is_valid_signer)
                         .takes_value(true)
                         .help(
-                            "Specify the current authority keypair. \
-                             Defaults to the client keypair."
+                            "Specify the token's close authority if it has one, \
+                            otherwise specify the token's owner keypair. \
+                            This may be a keypair file, the ASK keyword. \
+                            Defaults to the client keypair.",
                         ),
                 )
                 .arg(
-                    Arg::with_name("disable")
-                        .long("disable")
-                        .takes_value(false)
-                        .conflicts_with("new_authority")
-                        .help("Disable mint, freeze, or close functionality by setting authority to None.")
-                )
-                .arg(
-                    Arg::with_name("force")
-                        .long("force")
-                        .hidden(true)
-                        .help("Force re-authorize the wallet's associate token account. Don't use this flag"),
+                    Arg::with_name("address")
+                        .long("address")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .conflicts_with("token")
+                        .help("Specify the token account to close \
+                            [default: owner's associated token account]"),
                 )
                 .arg(multisig_signer_arg())
                 .nonce_args(true)
                 .offline_args(),
         )
-        .subcommand(
-            SubCommand::with_name(CommandName::Transfer.into())
-                .about("Transfer tokens between accounts")
+        .subcommand(
+            SubCommand::with_name(CommandName::Balance.into())
+                .about("Get token account balance")
                 .arg(
                     Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
-                        .required(true)
-                        .help("Token to transfer"),
+                        .required_unless("address")
+                        .help("Token of associated account. To query a specific account, use the `--address` parameter instead"),
                 )
+                .arg(owner_address_arg().conflicts_with("address"))
                 .arg(
-                    Arg::with_name("amount")
-                        .validator(is_amount_or_all)
-                        .value_name("TOKEN_AMOUNT")
+                    Arg::with_name("address")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
-                        .index(2)
-                        .required(true)
-                        .help("Amount to send, in tokens; accepts keyword ALL"),
-                )
+                        .long("address")
+                        .conflicts_with("token")
+                        .help("Specify the token account to query \
+                            [default: owner's associated token account]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Supply.into())
+                .about("Get token supply")
                 .arg(
-                    Arg::with_name("recipient")
+                    Arg::with_name("address")
                         .validator(is_valid_pubkey)
-                        .value_name("RECIPIENT_ADDRESS or RECIPIENT_TOKEN_ACCOUNT_ADDRESS")
+                        .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
-                        .index(3)
+                        .index(1)
                         .required(true)
-                        .help("If a token account address is provided, use it as the recipient. \
-                               Otherwise assume the recipient address is a user wallet and transfer to \
-                               the associated token account")
-                )
+                        .help("The token address"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Accounts.into())
+                .about("List all token accounts by owner")
                 .arg(
-                    Arg::with_name("from")
+                    Arg::with_name("token")
                         .validator(is_valid_pubkey)
-                        .value_name("SENDER_TOKEN_ACCOUNT_ADDRESS")
+                        .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
-                        .long("from")
-                        .help("Specify the sending token account \
-                            [default: owner's associated token account]")
-                )
-                .arg(owner_keypair_arg_with_value_name("SENDER_TOKEN_OWNER_KEYPAIR")
-                        .help(
-                            "Specify the owner of the sending token account. \
-                            This may be a keypair file, the ASK keyword. \
-                            Defaults to the client keypair.",
-                        ),
-                )
-                .arg(
-                    Arg::with_name("allow_unfunded_recipient")
-                        .long("allow-unfunded-recipient")
-                        .takes_value(false)
-                        .help("Complete the transfer even if the recipient address is not funded")
-                )
-                .arg(
-                    Arg::with_name("allow_empty_recipient")
-                        .long("allow-empty-recipient")
-                        .takes_value(false)
-                        .hidden(true) // Deprecated, use --allow-unfunded-recipient instead
+                        .index(1)
+                        .help("Limit results to the given token. [Default: list accounts for all tokens]"),
                 )
+                .arg(owner_address_arg())
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Holders.into())
+                .about("List every token account of a token and its holder")
                 .arg(
-                    Arg::with_name("fund_recipient")
-                        .long("fund-recipient")
-                        .takes_value(false)
-                        .help("Create the associated token account for the recipient if doesn't already exist")
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The token address"),
                 )
                 .arg(
-                    Arg::with_name("no_wait")
-                        .long("no-wait")
-                        .takes_value(false)
-                        .help("Return signature immediately after submitting the transaction, instead of waiting for confirmations"),
+                    Arg::with_name("min_balance")
+                        .long("min-balance")
+                        .validator(is_amount)
+                        .value_name("TOKEN_AMOUNT")
+                        .takes_value(true)
+                        .help("Only list holders with at least this balance, in tokens"),
                 )
                 .arg(
-                    Arg::with_name("allow_non_system_account_recipient")
-                        .long("allow-non-system-account-recipient")
+                    Arg::with_name("by_owner")
+                        .long("by-owner")
                         .takes_value(false)
-                        .help("Send tokens to the recipient even if the recipient is not a wallet owned by System Program."),
+                        .help("Sum the balances of all token accounts belonging to the same owner"),
                 )
                 .arg(
-                    Arg::with_name("recipient_is_ata_owner")
-                        .long("recipient-is-ata-owner")
+                    Arg::with_name("csv")
+                        .long("csv")
                         .takes_value(false)
-                        .requires("sign_only")
-                        .help("In sign-only mode, specifies that the recipient is the owner of the associated token account rather than an actual token account"),
+                        .conflicts_with("output_format")
+                        .help("Print the snapshot as CSV rather than as text or JSON"),
                 )
-                .arg(multisig_signer_arg())
-                .arg(mint_decimals_arg())
-                .nonce_args(true)
-                .arg(memo_arg())
-                .offline_args_config(&SignOnlyNeedsMintDecimals{}),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Burn.into())
-                .about("Burn tokens from an account")
+            SubCommand::with_name(CommandName::Address.into())
+                .about("Get wallet address")
                 .arg(
-                    Arg::with_name("source")
+                    Arg::with_name("token")
                         .validator(is_valid_pubkey)
-                        .value_name("SOURCE_TOKEN_ACCOUNT_ADDRESS")
+                        .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
-                        .index(1)
-                        .required(true)
-                        .help("The token account address to burn from"),
+                        .long("token")
+                        .requires("verbose")
+                        .help("Return the associated token address for the given token. \
+                               [Default: return the client keypair address]")
                 )
                 .arg(
-                    Arg::with_name("amount")
-                        .validator(is_amount)
-                        .value_name("TOKEN_AMOUNT")
-                        .takes_value(true)
-                        .index(2)
-                        .required(true)
-                        .help("Amount to burn, in tokens"),
-                )
-                .arg(owner_keypair_arg_with_value_name("SOURCE_TOKEN_OWNER_KEYPAIR")
-                        .help(
-                            "Specify the source token owner account. \
-                            This may be a keypair file, the ASK keyword. \
-                            Defaults to the client keypair.",
-                        ),
-                )
-                .arg(multisig_signer_arg())
-                .mint_args()
-                .nonce_args(true)
-                .arg(memo_arg())
-                .offline_args_config(&SignOnlyNeedsFullMintSpec{}),
+                    owner_address_arg()
+                        .requires("token")
+                        .help("Return the associated token address for the given owner. \
+                               [Default: return the associated token address for the client keypair]"),
+                ),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Mint.into())
-                .about("Mint new tokens")
+            SubCommand::with_name(CommandName::AccountInfo.into())
+                .about("Query details of an SPL Token account by address")
                 .arg(
                     Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
-                        .required(true)
-                        .help("The token to mint"),
+                        .conflicts_with("address")
+                        .required_unless("address")
+                        .help("Token of associated account. \
+                               To query a specific account, use the `--address` parameter instead"),
                 )
                 .arg(
-                    Arg::with_name("amount")
-                        .validator(is_amount)
-                        .value_name("TOKEN_AMOUNT")
-                        .takes_value(true)
+                    owner_address_arg()
                         .index(2)
-                        .required(true)
-                        .help("Amount to mint, in tokens"),
+                        .conflicts_with("address")
+                        .help("Owner of the associated account for the specified token. \
+                               To query a specific account, use the `--address` parameter instead. \
+                               Defaults to the client keypair."),
                 )
                 .arg(
-                    Arg::with_name("recipient")
+                    Arg::with_name("address")
                         .validator(is_valid_pubkey)
-                        .value_name("RECIPIENT_TOKEN_ACCOUNT_ADDRESS")
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
-                        .index(3)
-                        .help("The token account address of recipient [default: associated token account for --owner]"),
-                )
+                        .long("address")
+                        .conflicts_with("token")
+                        .help("Specify the token account to query"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::MultisigInfo.into())
+                .about("Query details about and SPL Token multisig account by address")
                 .arg(
-                    Arg::with_name("mint_authority")
-                        .long("mint-authority")
-                        .alias("owner")
-                        .value_name("KEYPAIR")
-                        .validator(is_valid_signer)
-                        .takes_value(true)
-                        .help(
-                            "Specify the mint authority keypair. \
-                             This may be a keypair file or the ASK keyword. \
-                             Defaults to the client keypair."
-                        ),
+                    Arg::with_name("address")
+                    .validator(is_valid_pubkey)
+                    .value_name("MULTISIG_ACCOUNT_ADDRESS")
+                    .takes_value(true)
+                    .index(1)
+                    .required(true)
+                    .help("The address of the SPL Token multisig account to query"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Display.into())
+                .about("Decode and display any token mint, token account or multisig, including all of its extensions")
+                .arg(
+                    Arg::with_name("address")
+                    .validator(is_valid_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .index(1)
+                    .required(true)
+                    .help("The address of the account to display"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::Gc.into())
+                .about("Cleanup unnecessary token accounts")
+                .arg(owner_keypair_arg())
+                .arg(
+                    Arg::with_name("close_empty_associated_accounts")
+                    .long("close-empty-associated-accounts")
+                    .takes_value(false)
+                    .help("close all empty associated token accounts (to get SOL back)")
                 )
-                .arg(mint_decimals_arg())
-                .arg(multisig_signer_arg())
-                .nonce_args(true)
-                .offline_args_config(&SignOnlyNeedsMintDecimals{}),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Freeze.into())
-                .about("Freeze a token account")
+            SubCommand::with_name(CommandName::SyncNative.into())
+                .about("Sync a native SOL token account to its underlying daltons")
                 .arg(
-                    Arg::with_name("account")
+                    owner_address_arg()
+                        .index(1)
+                        .conflicts_with("address")
+                        .help("Owner of the associated account for the native token. \
+                               To query a specific account, use the `--address` parameter instead. \
+                               Defaults to the client keypair."),
+                )
+                .arg(
+                    Arg::with_name("address")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
-                        .index(1)
+                        .long("address")
+                        .conflicts_with("owner")
+                        .help("Specify the specific token account address to sync"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(CommandName::SetTransferFee.into())
+                .about("Set the transfer fee for a token with a configured transfer fee")
+                .arg(
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                        .help("The token address with a configured transfer fee"),
+                )
+                .arg(
+                    Arg::with_name("transfer_fee_basis_points")
+                        .value_name("FEE_IN_BASIS_POINTS")
+                        .validator(is_transfer_fee_basis_points)
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .help("The new transfer fee in basis points"),
+                )
+                .arg(
+                    Arg::with_name("maximum_fee")
+                        .value_name("MAXIMUM_FEE")
+                        .validator(is_amount)
+                        .takes_value(true)
+                        .index(3)
                         .required(true)
-                        .help("The address of the token account to freeze"),
+                        .help("The new maximum transfer fee, in tokens"),
                 )
                 .arg(
-                    Arg::with_name("freeze_authority")
-                        .long("freeze-authority")
-                        .alias("owner")
-                        .value_name("KEYPAIR")
+                    Arg::with_name("transfer_fee_authority")
+                        .long("transfer-fee-authority")
                         .validator(is_valid_signer)
+                        .value_name("SIGNER")
                         .takes_value(true)
                         .help(
-                            "Specify the freeze authority keypair. \
-                             This may be a keypair file or the ASK keyword. \
+                            "Specify the transfer fee authority keypair. \
                              Defaults to the client keypair."
                         ),
                 )
-                .arg(mint_address_arg())
+                .arg(mint_decimals_arg())
                 .arg(multisig_signer_arg())
                 .nonce_args(true)
-                .offline_args_config(&SignOnlyNeedsMintAddress{}),
+                .offline_args_config(&SignOnlyNeedsMintDecimals{}),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Thaw.into())
-                .about("Thaw a token account")
+            SubCommand::with_name(CommandName::WithdrawWithheldTokens.into())
+                .about("Withdraw withheld transfer fee tokens from mint and / or account(s)")
                 .arg(
                     Arg::with_name("account")
                         .validator(is_valid_pubkey)
@@ -2298,18 +4208,32 @@ fn app<'a, 'b>(
                         .takes_value(true)
                         .index(1)
                         .required(true)
-                        .help("The address of the token account to thaw"),
+                        .help("The address of the token account to receive withdrawn tokens"),
                 )
                 .arg(
-                    Arg::with_name("freeze_authority")
-                        .long("freeze-authority")
-                        .alias("owner")
+                    Arg::with_name("source")
+                        .validator(is_valid_pubkey)
+                        .value_name("ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .multiple(true)
+                        .min_values(0u64)
+                        .index(2)
+                        .help("The token accounts to withdraw from")
+                )
+                .arg(
+                    Arg::with_name("include_mint")
+                        .long("include-mint")
+                        .takes_value(false)
+                        .help("Also withdraw withheld tokens from the mint"),
+                )
+                .arg(
+                    Arg::with_name("withdraw_withheld_authority")
+                        .long("withdraw-withheld-authority")
                         .value_name("KEYPAIR")
                         .validator(is_valid_signer)
                         .takes_value(true)
                         .help(
-                            "Specify the freeze authority keypair. \
-                             This may be a keypair file or the ASK keyword. \
+                            "Specify the withdraw withheld authority keypair. \
                              Defaults to the client keypair."
                         ),
                 )
@@ -2319,71 +4243,51 @@ fn app<'a, 'b>(
                 .offline_args_config(&SignOnlyNeedsMintAddress{}),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Wrap.into())
-                .about("Wrap native SOL in a SOL token account")
+            SubCommand::with_name(CommandName::HarvestWithheld.into())
+                .about("Permissionlessly move withheld transfer fee tokens from accounts to their mint")
                 .arg(
-                    Arg::with_name("amount")
-                        .validator(is_amount)
-                        .value_name("AMOUNT")
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
                         .required(true)
-                        .help("Amount of SOL to wrap"),
+                        .help("The token with a configured transfer fee"),
                 )
                 .arg(
-                    Arg::with_name("wallet_keypair")
-                        .alias("owner")
-                        .value_name("KEYPAIR")
-                        .validator(is_valid_signer)
+                    Arg::with_name("source")
+                        .validator(is_valid_pubkey)
+                        .value_name("ACCOUNT_ADDRESS")
                         .takes_value(true)
+                        .multiple(true)
+                        .min_values(0u64)
+                        .index(2)
                         .help(
-                            "Specify the keypair for the wallet which will have its native SOL wrapped. \
-                             This wallet will be assigned as the owner of the wrapped SOL token account. \
-                             This may be a keypair file or the ASK keyword. \
-                             Defaults to the client keypair."
+                            "The token accounts to harvest from. \
+                             [default: every account of the token with withheld tokens]"
                         ),
-                )
-                .arg(
-                    Arg::with_name("create_aux_account")
-                        .takes_value(false)
-                        .long("create-aux-account")
-                        .help("Wrap SOL in an auxiliary account instead of associated token account"),
-                )
-                .nonce_args(true)
-                .offline_args(),
+                ),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Unwrap.into())
-                .about("Unwrap a SOL token account")
+            SubCommand::with_name(CommandName::EnableRequiredTransferMemos.into())
+                .about("Require incoming transfers to a token account to be preceded by a memo")
                 .arg(
-                    Arg::with_name("address")
+                    Arg::with_name("account")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
                         .index(1)
-                        .help("The address of the auxiliary token account to unwrap \
-                            [default: associated token account for --owner]"),
-                )
-                .arg(
-                    Arg::with_name("wallet_keypair")
-                        .alias("owner")
-                        .value_name("KEYPAIR")
-                        .validator(is_valid_signer)
-                        .takes_value(true)
-                        .help(
-                            "Specify the keypair for the wallet which owns the wrapped SOL. \
-                             This wallet will receive the unwrapped SOL. \
-                             This may be a keypair file or the ASK keyword. \
-                             Defaults to the client keypair."
-                        ),
+                        .required(true)
+                        .help("The address of the token account"),
                 )
+                .arg(owner_keypair_arg_with_value_name("OWNER_KEYPAIR"))
                 .arg(multisig_signer_arg())
                 .nonce_args(true)
                 .offline_args(),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Approve.into())
-                .about("Approve a delegate for a token account")
+            SubCommand::with_name(CommandName::DisableRequiredTransferMemos.into())
+                .about("Stop requiring memos on incoming transfers to a token account")
                 .arg(
                     Arg::with_name("account")
                         .validator(is_valid_pubkey)
@@ -2391,37 +4295,16 @@ fn app<'a, 'b>(
                         .takes_value(true)
                         .index(1)
                         .required(true)
-                        .help("The address of the token account to delegate"),
-                )
-                .arg(
-                    Arg::with_name("amount")
-                        .validator(is_amount)
-                        .value_name("TOKEN_AMOUNT")
-                        .takes_value(true)
-                        .index(2)
-                        .required(true)
-                        .help("Amount to approve, in tokens"),
-                )
-                .arg(
-                    Arg::with_name("delegate")
-                        .validator(is_valid_pubkey)
-                        .value_name("DELEGATE_TOKEN_ACCOUNT_ADDRESS")
-                        .takes_value(true)
-                        .index(3)
-                        .required(true)
-                        .help("The token account address of delegate"),
-                )
-                .arg(
-                    owner_keypair_arg()
+                        .help("The address of the token account"),
                 )
+                .arg(owner_keypair_arg_with_value_name("OWNER_KEYPAIR"))
                 .arg(multisig_signer_arg())
-                .mint_args()
                 .nonce_args(true)
-                .offline_args_config(&SignOnlyNeedsFullMintSpec{}),
+                .offline_args(),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Revoke.into())
-                .about("Revoke a delegate's authority")
+            SubCommand::with_name(CommandName::Reallocate.into())
+                .about("Grow a token account to make room for additional extensions")
                 .arg(
                     Arg::with_name("account")
                         .validator(is_valid_pubkey)
@@ -2431,153 +4314,213 @@ fn app<'a, 'b>(
                         .required(true)
                         .help("The address of the token account"),
                 )
-                .arg(owner_keypair_arg()
+                .arg(
+                    Arg::with_name("extension")
+                        .long("extension")
+                        .value_name("EXTENSION_TYPE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true)
+                        .possible_values(&[
+                            "memo-transfer", "confidential-transfer-account",
+                        ])
+                        .help("Extension to make room for. \
+                            May be specified multiple times."),
                 )
-                .arg(delegate_address_arg())
+                .arg(owner_keypair_arg_with_value_name("OWNER_KEYPAIR"))
                 .arg(multisig_signer_arg())
                 .nonce_args(true)
-                .offline_args_config(&SignOnlyNeedsDelegateAddress{}),
+                .offline_args(),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Close.into())
-                .about("Close a token account")
+            SubCommand::with_name(CommandName::UpdateInterestRate.into())
+                .about("Update the interest rate of an interest-bearing token")
                 .arg(
                     Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
-                        .required_unless("address")
-                        .help("Token to close. To close a specific account, use the `--address` parameter instead"),
+                        .required(true)
+                        .help("The interest-bearing token address"),
                 )
-                .arg(owner_address_arg())
                 .arg(
-                    Arg::with_name("recipient")
-                        .long("recipient")
-                        .validator(is_valid_pubkey)
-                        .value_name("REFUND_ACCOUNT_ADDRESS")
+                    Arg::with_name("rate")
+                        .value_name("RATE_BPS")
+                        .validator(is_parsable::<i16>)
                         .takes_value(true)
-                        .help("The address of the account to receive remaining SOL [default: --owner]"),
+                        .allow_hyphen_values(true)
+                        .index(2)
+                        .required(true)
+                        .help("The new interest rate in basis points"),
                 )
                 .arg(
-                    Arg::with_name("close_authority")
-                        .long("close-authority")
-                        .alias("owner")
-                        .value_name("KEYPAIR")
+                    Arg::with_name("rate_authority")
+                        .long("rate-authority")
                         .validator(is_valid_signer)
+                        .value_name("SIGNER")
                         .takes_value(true)
                         .help(
-                            "Specify the token's close authority if it has one, \
-                            otherwise specify the token's owner keypair. \
-                            This may be a keypair file, the ASK keyword. \
-                            Defaults to the client keypair.",
+                            "Specify the rate authority keypair. \
+                             Defaults to the client keypair."
                         ),
                 )
-                .arg(
-                    Arg::with_name("address")
-                        .long("address")
-                        .validator(is_valid_pubkey)
-                        .value_name("TOKEN_ACCOUNT_ADDRESS")
-                        .takes_value(true)
-                        .conflicts_with("token")
-                        .help("Specify the token account to close \
-                            [default: owner's associated token account]"),
-                )
                 .arg(multisig_signer_arg())
                 .nonce_args(true)
                 .offline_args(),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Balance.into())
-                .about("Get token account balance")
+            SubCommand::with_name(CommandName::ConfigureConfidentialAccount.into())
+                .about("Configure a token account for confidential transfers")
                 .arg(
                     Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
-                        .required_unless("address")
-                        .help("Token of associated account. To query a specific account, use the `--address` parameter instead"),
+                        .required(true)
+                        .help("The token with confidential transfers enabled"),
                 )
-                .arg(owner_address_arg().conflicts_with("address"))
                 .arg(
                     Arg::with_name("address")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
                         .long("address")
-                        .conflicts_with("token")
-                        .help("Specify the token account to query \
+                        .help("Specify the token account \
                             [default: owner's associated token account]"),
-                ),
+                )
+                .arg(
+                    Arg::with_name("maximum_pending_balance_credit_counter")
+                        .validator(is_parsable::<u64>)
+                        .value_name("COUNT")
+                        .takes_value(true)
+                        .long("maximum-pending-balance-credit-counter")
+                        .help("Maximum number of deposits and incoming transfers before \
+                            the pending balance must be applied [default: 65536]"),
+                )
+                .arg(owner_keypair_arg()),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Supply.into())
-                .about("Get token supply")
+            SubCommand::with_name(CommandName::DepositConfidential.into())
+                .about("Deposit tokens from the public balance of a token account into its confidential pending balance")
                 .arg(
-                    Arg::with_name("address")
+                    Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
                         .required(true)
-                        .help("The token address"),
-                ),
+                        .help("The token with confidential transfers enabled"),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .validator(is_amount)
+                        .value_name("TOKEN_AMOUNT")
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .help("Amount to deposit, in tokens"),
+                )
+                .arg(
+                    Arg::with_name("address")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .long("address")
+                        .help("Specify the token account \
+                            [default: owner's associated token account]"),
+                )
+                .arg(owner_keypair_arg())
+                .arg(multisig_signer_arg()),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Accounts.into())
-                .about("List all token accounts by owner")
+            SubCommand::with_name(CommandName::ApplyPendingBalance.into())
+                .about("Move the confidential pending balance of a token account into its available balance")
                 .arg(
                     Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
-                        .help("Limit results to the given token. [Default: list accounts for all tokens]"),
+                        .required(true)
+                        .help("The token with confidential transfers enabled"),
                 )
-                .arg(owner_address_arg())
+                .arg(
+                    Arg::with_name("address")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .long("address")
+                        .help("Specify the token account \
+                            [default: owner's associated token account]"),
+                )
+                .arg(owner_keypair_arg()),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::Address.into())
-                .about("Get wallet address")
+            SubCommand::with_name(CommandName::ConfidentialTransfer.into())
+                .about("Transfer tokens confidentially between two configured token accounts")
                 .arg(
                     Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
-                        .long("token")
-                        .requires("verbose")
-                        .help("Return the associated token address for the given token. \
-                               [Default: return the client keypair address]")
+                        .index(1)
+                        .required(true)
+                        .help("The token with confidential transfers enabled"),
                 )
                 .arg(
-                    owner_address_arg()
-                        .requires("token")
-                        .help("Return the associated token address for the given owner. \
-                               [Default: return the associated token address for the client keypair]"),
-                ),
+                    Arg::with_name("amount")
+                        .validator(is_amount)
+                        .value_name("TOKEN_AMOUNT")
+                        .takes_value(true)
+                        .index(2)
+                        .required(true)
+                        .help("Amount to send, in tokens"),
+                )
+                .arg(
+                    Arg::with_name("recipient")
+                        .validator(is_valid_pubkey)
+                        .value_name("RECIPIENT_ADDRESS or RECIPIENT_TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .index(3)
+                        .required(true)
+                        .help("If a token account address is provided, use it as the recipient. \
+                               Otherwise assume the recipient address is a user wallet and transfer to \
+                               the associated token account")
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ACCOUNT_ADDRESS")
+                        .takes_value(true)
+                        .long("from")
+                        .help("Specify the sending token account \
+                            [default: owner's associated token account]"),
+                )
+                .arg(owner_keypair_arg()),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::AccountInfo.into())
-                .about("Query details of an SPL Token account by address")
+            SubCommand::with_name(CommandName::WithdrawConfidential.into())
+                .about("Withdraw tokens from the confidential available balance of a token account into its public balance")
                 .arg(
                     Arg::with_name("token")
                         .validator(is_valid_pubkey)
                         .value_name("TOKEN_ADDRESS")
                         .takes_value(true)
                         .index(1)
-                        .conflicts_with("address")
-                        .required_unless("address")
-                        .help("Token of associated account. \
-                               To query a specific account, use the `--address` parameter instead"),
+                        .required(true)
+                        .help("The token with confidential transfers enabled"),
                 )
                 .arg(
-                    owner_address_arg()
+                    Arg::with_name("amount")
+                        .validator(is_amount)
+                        .value_name("TOKEN_AMOUNT")
+                        .takes_value(true)
                         .index(2)
-                        .conflicts_with("address")
-                        .help("Owner of the associated account for the specified token. \
-                               To query a specific account, use the `--address` parameter instead. \
-                               Defaults to the client keypair."),
+                        .required(true)
+                        .help("Amount to withdraw, in tokens"),
                 )
                 .arg(
                     Arg::with_name("address")
@@ -2585,44 +4528,22 @@ fn app<'a, 'b>(
                         .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
                         .long("address")
-                        .conflicts_with("token")
-                        .help("Specify the token account to query"),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name(CommandName::MultisigInfo.into())
-                .about("Query details about and SPL Token multisig account by address")
-                .arg(
-                    Arg::with_name("address")
-                    .validator(is_valid_pubkey)
-                    .value_name("MULTISIG_ACCOUNT_ADDRESS")
-                    .takes_value(true)
-                    .index(1)
-                    .required(true)
-                    .help("The address of the SPL Token multisig account to query"),
-                ),
-        )
-        .subcommand(
-            SubCommand::with_name(CommandName::Gc.into())
-                .about("Cleanup unnecessary token accounts")
-                .arg(owner_keypair_arg())
-                .arg(
-                    Arg::with_name("close_empty_associated_accounts")
-                    .long("close-empty-associated-accounts")
-                    .takes_value(false)
-                    .help("close all empty associated token accounts (to get SOL back)")
+                        .help("Specify the token account \
+                            [default: owner's associated token account]"),
                 )
+                .arg(owner_keypair_arg()),
         )
         .subcommand(
-            SubCommand::with_name(CommandName::SyncNative.into())
-                .about("Sync a native SOL token account to its underlying daltons")
+            SubCommand::with_name(CommandName::ShowConfidentialBalance.into())
+                .about("Decrypt and display the confidential balances of a token account")
                 .arg(
-                    owner_address_arg()
+                    Arg::with_name("token")
+                        .validator(is_valid_pubkey)
+                        .value_name("TOKEN_ADDRESS")
+                        .takes_value(true)
                         .index(1)
-                        .conflicts_with("address")
-                        .help("Owner of the associated account for the native token. \
-                               To query a specific account, use the `--address` parameter instead. \
-                               Defaults to the client keypair."),
+                        .required(true)
+                        .help("The token with confidential transfers enabled"),
                 )
                 .arg(
                     Arg::with_name("address")
@@ -2630,9 +4551,10 @@ fn app<'a, 'b>(
                         .value_name("TOKEN_ACCOUNT_ADDRESS")
                         .takes_value(true)
                         .long("address")
-                        .conflicts_with("owner")
-                        .help("Specify the specific token account address to sync"),
-                ),
+                        .help("Specify the token account \
+                            [default: owner's associated token account]"),
+                )
+                .arg(owner_keypair_arg()),
         )
 }
 
@@ -2654,1127 +4576,2640 @@ async fn main() -> Result<(), Error> {
     let mut bulk_signers: Vec<Box<dyn Signer>> = Vec::new();
     let mut multisigner_ids = Vec::new();
 
-    let (sub_command, sub_matches) = app_matches.subcommand();
-    let sub_command = CommandName::from_str(sub_command).unwrap();
-    let matches = sub_matches.unwrap();
+    let (sub_command, sub_matches) = app_matches.subcommand();
+    let sub_command = CommandName::from_str(sub_command).unwrap();
+    let matches = sub_matches.unwrap();
+
+    let config = {
+        let cli_config = if let Some(config_file) = matches.value_of("config_file") {
+            solana_cli_config::Config::load(config_file).unwrap_or_else(|_| {
+                eprintln!("error: Could not find config file `{}`", config_file);
+                exit(1);
+            })
+        } else if let Some(ref config_file) = *solana_cli_config::CONFIG_FILE {
+            solana_cli_config::Config::load(config_file).unwrap_or_default()
+        } else {
+            solana_cli_config::Config::default()
+        };
+        let json_rpc_url = normalize_to_url_if_moniker(
+            matches
+                .value_of("json_rpc_url")
+                .unwrap_or(&cli_config.json_rpc_url),
+        );
+        let websocket_url = solana_cli_config::Config::compute_websocket_url(&json_rpc_url);
+
+        let (signer, fee_payer) = signer_from_path(
+            matches,
+            matches
+                .value_of("fee_payer")
+                .unwrap_or(&cli_config.keypair_path),
+            "fee_payer",
+            &mut wallet_manager,
+        )
+        .map(|s| {
+            let p = s.pubkey();
+            (s, p)
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            exit(1);
+        });
+        bulk_signers.push(signer);
+
+        let verbose = matches.is_present("verbose");
+        let output_format = matches
+            .value_of("output_format")
+            .map(|value| match value {
+                "json" => OutputFormat::Json,
+                "json-compact" => OutputFormat::JsonCompact,
+                _ => unreachable!(),
+            })
+            .unwrap_or(if verbose {
+                OutputFormat::DisplayVerbose
+            } else {
+                OutputFormat::Display
+            });
+
+        let nonce_account = pubkey_of_signer(matches, NONCE_ARG.name, &mut wallet_manager)
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                exit(1);
+            });
+        let nonce_authority = if nonce_account.is_some() {
+            let (signer, nonce_authority) = signer_from_path(
+                matches,
+                matches
+                    .value_of(NONCE_AUTHORITY_ARG.name)
+                    .unwrap_or(&cli_config.keypair_path),
+                NONCE_AUTHORITY_ARG.name,
+                &mut wallet_manager,
+            )
+            .map(|s| {
+                let p = s.pubkey();
+                (s, p)
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                exit(1);
+            });
+            bulk_signers.push(signer);
+
+            Some(nonce_authority)
+        } else {
+            None
+        };
+
+        let sign_only = matches.is_present(SIGN_ONLY_ARG.name);
+        let dump_transaction_message = matches.is_present(DUMP_TRANSACTION_MESSAGE.name);
+        let program_id = pubkey_of(matches, "program_id").unwrap();
+
+        let multisig_signers = signers_of(matches, MULTISIG_SIGNER_ARG.name, &mut wallet_manager)
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                exit(1);
+            });
+        if let Some(mut multisig_signers) = multisig_signers {
+            multisig_signers.sort_by(|(_, lp), (_, rp)| lp.cmp(rp));
+            let (signers, pubkeys): (Vec<_>, Vec<_>) = multisig_signers.into_iter().unzip();
+            bulk_signers.extend(signers);
+            multisigner_ids = pubkeys;
+        }
+        let multisigner_pubkeys = multisigner_ids.iter().collect::<Vec<_>>();
+
+        let rpc_client = Arc::new(RpcClient::new_with_commitment(
+            json_rpc_url,
+            CommitmentConfig::confirmed(),
+        ));
+        let program_client: Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>> = Arc::new(
+            ProgramRpcClient::new(rpc_client.clone(), ProgramRpcClientSendTransaction),
+        );
+        Config {
+            rpc_client,
+            program_client,
+            websocket_url,
+            output_format,
+            fee_payer,
+            default_keypair: KeypairOrPath::Path(cli_config.keypair_path),
+            nonce_account,
+            nonce_authority,
+            sign_only,
+            dump_transaction_message,
+            multisigner_pubkeys,
+            program_id,
+        }
+    };
+
+    solana_logger::setup_with_default("solana=info");
+    let result =
+        process_command(&sub_command, matches, &config, wallet_manager, bulk_signers).await?;
+    println!("{}", result);
+    Ok(())
+}
+
+async fn process_command<'a>(
+    sub_command: &CommandName,
+    sub_matches: &ArgMatches<'_>,
+    config: &Config<'a>,
+    mut wallet_manager: Option<Arc<RemoteWalletManager>>,
+    mut bulk_signers: Vec<Box<dyn Signer>>,
+) -> CommandResult {
+    match (sub_command, sub_matches) {
+        (CommandName::Bench, arg_matches) => {
+            bench_process_command(
+                arg_matches,
+                config,
+                std::mem::take(&mut bulk_signers),
+                &mut wallet_manager,
+            )
+            .await
+        }
+        (CommandName::Distribute, arg_matches) => {
+            distribute_process_command(
+                arg_matches,
+                config,
+                std::mem::take(&mut bulk_signers),
+                &mut wallet_manager,
+            )
+            .await
+        }
+        (CommandName::Multisig, arg_matches) => {
+            multisig_process_command(
+                arg_matches,
+                config,
+                std::mem::take(&mut bulk_signers),
+                &mut wallet_manager,
+            )
+            .await
+        }
+        (CommandName::Apply, arg_matches) => {
+            apply_process_command(
+                arg_matches,
+                config,
+                std::mem::take(&mut bulk_signers),
+                &mut wallet_manager,
+            )
+            .await
+        }
+        (CommandName::CreateToken, arg_matches) => {
+            let decimals = value_t_or_exit!(arg_matches, "decimals", u8);
+            let mint_authority =
+                config.pubkey_or_default(arg_matches, "mint_authority", &mut wallet_manager);
+            let memo = value_t!(arg_matches, "memo", String).ok();
+
+            let enable_freeze = arg_matches.is_present("enable_freeze");
+
+            let mut extensions = vec![];
+            if let Some(values) = arg_matches.values_of("transfer_fee") {
+                let values = values.collect::<Vec<_>>();
+                is_transfer_fee_basis_points(values[0].to_string()).unwrap_or_else(|e| {
+                    eprintln!("error: FEE_IN_BASIS_POINTS {}", e);
+                    exit(1);
+                });
+                let transfer_fee_basis_points = u16::from_str(values[0]).unwrap();
+                let maximum_fee = values[1].parse::<f64>().unwrap_or_else(|e| {
+                    eprintln!("error: MAXIMUM_FEE {}", e);
+                    exit(1);
+                });
+                extensions.push(ExtensionInitializationParams::TransferFeeConfig {
+                    transfer_fee_config_authority: Some(mint_authority),
+                    withdraw_withheld_authority: Some(mint_authority),
+                    transfer_fee_basis_points,
+                    maximum_fee: spl_token::ui_amount_to_amount(maximum_fee, decimals),
+                });
+            }
+            if let Some(rate) = value_of::<i16>(arg_matches, "interest_rate") {
+                extensions.push(ExtensionInitializationParams::InterestBearingConfig {
+                    rate_authority: Some(mint_authority),
+                    rate,
+                });
+            }
+            if let Some(state) = arg_matches.value_of("default_account_state") {
+                let state = match state {
+                    "initialized" => AccountState::Initialized,
+                    "frozen" => AccountState::Frozen,
+                    _ => unreachable!(),
+                };
+                if state == AccountState::Frozen && !enable_freeze {
+                    eprintln!("error: a `frozen` default account state requires --enable-freeze");
+                    exit(1);
+                }
+                extensions.push(ExtensionInitializationParams::DefaultAccountState { state });
+            }
+            if arg_matches.is_present("enable_close") {
+                extensions.push(ExtensionInitializationParams::MintCloseAuthority {
+                    close_authority: Some(mint_authority),
+                });
+            }
+            if arg_matches.is_present("enable_non_transferable") {
+                extensions.push(ExtensionInitializationParams::NonTransferable);
+            }
+            if arg_matches.is_present("enable_confidential_transfers") {
+                if arg_matches.is_present("transfer_fee") {
+                    eprintln!(
+                        "error: confidential transfers cannot be combined with --transfer-fee"
+                    );
+                    exit(1);
+                }
+                let auditor_encryption_pubkey = arg_matches
+                    .value_of("confidential_transfer_auditor")
+                    .map(|s| parse_elgamal_pubkey(s).unwrap())
+                    .unwrap_or_else(ElGamalPubkey::zeroed);
+                extensions.push(ExtensionInitializationParams::ConfidentialTransferMint {
+                    ct_mint: ConfidentialTransferMint {
+                        authority: mint_authority,
+                        auto_approve_new_accounts: true.into(),
+                        auditor_encryption_pubkey,
+                        withdraw_withheld_authority_encryption_pubkey: ElGamalPubkey::zeroed(),
+                        withheld_amount: EncryptedWithheldAmount::zeroed(),
+                    },
+                });
+            }
+
+            let (token_signer, token) =
+                get_signer(arg_matches, "token_keypair", &mut wallet_manager)
+                    .unwrap_or_else(new_throwaway_signer);
+            bulk_signers.push(token_signer);
+
+            command_create_token(
+                config,
+                decimals,
+                token,
+                mint_authority,
+                enable_freeze,
+                extensions,
+                memo,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::CreateAccount, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+
+            // No need to add a signer when creating an associated token account
+            let account = get_signer(arg_matches, "account_keypair", &mut wallet_manager).map(
+                |(signer, account)| {
+                    bulk_signers.push(signer);
+                    account
+                },
+            );
 
-    let config = {
-        let cli_config = if let Some(config_file) = matches.value_of("config_file") {
-            solana_cli_config::Config::load(config_file).unwrap_or_else(|_| {
-                eprintln!("error: Could not find config file `{}`", config_file);
+            let owner = config.pubkey_or_default(arg_matches, "owner", &mut wallet_manager);
+            command_create_account(config, token, owner, account, bulk_signers).await
+        }
+        (CommandName::CreateMultisig, arg_matches) => {
+            let minimum_signers = value_of::<u8>(arg_matches, "minimum_signers").unwrap();
+            let multisig_members =
+                pubkeys_of_multiple_signers(arg_matches, "multisig_member", &mut wallet_manager)
+                    .unwrap_or_else(|e| {
+                        eprintln!("error: {}", e);
+                        exit(1);
+                    })
+                    .unwrap();
+            if minimum_signers as usize > multisig_members.len() {
+                eprintln!(
+                    "error: MINIMUM_SIGNERS cannot be greater than the number \
+                          of MULTISIG_MEMBERs passed"
+                );
                 exit(1);
-            })
-        } else if let Some(ref config_file) = *solana_cli_config::CONFIG_FILE {
-            solana_cli_config::Config::load(config_file).unwrap_or_default()
-        } else {
-            solana_cli_config::Config::default()
-        };
-        let json_rpc_url = normalize_to_url_if_moniker(
-            matches
-                .value_of("json_rpc_url")
-                .unwrap_or(&cli_config.json_rpc_url),
-        );
-        let websocket_url = solana_cli_config::Config::compute_websocket_url(&json_rpc_url);
+            }
 
-        let (signer, fee_payer) = signer_from_path(
-            matches,
-            matches
-                .value_of("fee_payer")
-                .unwrap_or(&cli_config.keypair_path),
-            "fee_payer",
-            &mut wallet_manager,
-        )
-        .map(|s| {
-            let p = s.pubkey();
-            (s, p)
-        })
-        .unwrap_or_else(|e| {
-            eprintln!("error: {}", e);
-            exit(1);
-        });
-        bulk_signers.push(signer);
+            let (signer, account) = get_signer(arg_matches, "address_keypair", &mut wallet_manager)
+                .unwrap_or_else(new_throwaway_signer);
+            bulk_signers.push(signer);
 
-        let verbose = matches.is_present("verbose");
-        let output_format = matches
-            .value_of("output_format")
-            .map(|value| match value {
-                "json" => OutputFormat::Json,
-                "json-compact" => OutputFormat::JsonCompact,
+            command_create_multisig(
+                config,
+                account,
+                minimum_signers,
+                multisig_members,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::Authorize, arg_matches) => {
+            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let authority_type = arg_matches.value_of("authority_type").unwrap();
+            let authority_type = match authority_type {
+                "mint" => AuthorityType::MintTokens,
+                "freeze" => AuthorityType::FreezeAccount,
+                "owner" => AuthorityType::AccountOwner,
+                "close" => AuthorityType::CloseAccount,
+                "close-mint" => AuthorityType::CloseMint,
+                "transfer-fee-config" => AuthorityType::TransferFeeConfig,
+                "withheld-withdraw" => AuthorityType::WithheldWithdraw,
+                "interest-rate" => AuthorityType::InterestRate,
                 _ => unreachable!(),
-            })
-            .unwrap_or(if verbose {
-                OutputFormat::DisplayVerbose
+            };
+
+            let (authority_signer, authority) =
+                config.signer_or_default(arg_matches, "authority", &mut wallet_manager);
+            bulk_signers.push(authority_signer);
+
+            let new_authority =
+                pubkey_of_signer(arg_matches, "new_authority", &mut wallet_manager).unwrap();
+            let force_authorize = arg_matches.is_present("force");
+            command_authorize(
+                config,
+                address,
+                authority_type,
+                authority,
+                new_authority,
+                force_authorize,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::Transfer, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let amount = match arg_matches.value_of("amount").unwrap() {
+                "ALL" => None,
+                amount => Some(amount.parse::<f64>().unwrap()),
+            };
+            let recipient = pubkey_of_signer(arg_matches, "recipient", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let sender = pubkey_of_signer(arg_matches, "from", &mut wallet_manager).unwrap();
+
+            let (owner_signer, owner) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
+
+            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
+            let fund_recipient = arg_matches.is_present("fund_recipient");
+            let allow_unfunded_recipient = arg_matches.is_present("allow_empty_recipient")
+                || arg_matches.is_present("allow_unfunded_recipient");
+
+            let recipient_is_ata_owner = arg_matches.is_present("recipient_is_ata_owner");
+            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
+            let memo = value_t!(arg_matches, "memo", String).ok();
+
+            command_transfer(
+                config,
+                token,
+                amount,
+                recipient,
+                sender,
+                owner,
+                allow_unfunded_recipient,
+                fund_recipient,
+                mint_decimals,
+                recipient_is_ata_owner,
+                use_unchecked_instruction,
+                memo,
+                bulk_signers,
+                arg_matches.is_present("no_wait"),
+                arg_matches.is_present("allow_non_system_account_recipient"),
+            )
+            .await
+        }
+        (CommandName::Burn, arg_matches) => {
+            let source = pubkey_of_signer(arg_matches, "source", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+
+            let (owner_signer, owner) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
+
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let mint_address =
+                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
+            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
+            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
+            let memo = value_t!(arg_matches, "memo", String).ok();
+            command_burn(
+                config,
+                source,
+                owner,
+                amount,
+                mint_address,
+                mint_decimals,
+                use_unchecked_instruction,
+                memo,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::Mint, arg_matches) => {
+            let (mint_authority_signer, mint_authority) =
+                config.signer_or_default(arg_matches, "mint_authority", &mut wallet_manager);
+            bulk_signers.push(mint_authority_signer);
+
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
+            let mint_info = config.get_mint_info(&token, mint_decimals).await?;
+            let recipient = if let Some(address) =
+                pubkey_of_signer(arg_matches, "recipient", &mut wallet_manager).unwrap()
+            {
+                address
+            } else {
+                config.associated_token_address_for_token_and_program(
+                    arg_matches,
+                    &mut wallet_manager,
+                    &mint_info.address,
+                    &mint_info.program_id,
+                )
+            };
+            config.check_account(&recipient, Some(token)).await?;
+            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
+            command_mint(
+                config,
+                token,
+                amount,
+                recipient,
+                mint_info,
+                mint_authority,
+                use_unchecked_instruction,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::Freeze, arg_matches) => {
+            let (freeze_authority_signer, freeze_authority) =
+                config.signer_or_default(arg_matches, "freeze_authority", &mut wallet_manager);
+            bulk_signers.push(freeze_authority_signer);
+
+            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let mint_address =
+                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
+            command_freeze(
+                config,
+                account,
+                mint_address,
+                freeze_authority,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::Thaw, arg_matches) => {
+            let (freeze_authority_signer, freeze_authority) =
+                config.signer_or_default(arg_matches, "freeze_authority", &mut wallet_manager);
+            bulk_signers.push(freeze_authority_signer);
+
+            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let mint_address =
+                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
+            command_thaw(
+                config,
+                account,
+                mint_address,
+                freeze_authority,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::Wrap, arg_matches) => {
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let account = if arg_matches.is_present("create_aux_account") {
+                let (signer, account) = new_throwaway_signer();
+                bulk_signers.push(signer);
+                Some(account)
             } else {
-                OutputFormat::Display
-            });
+                // No need to add a signer when creating an associated token account
+                None
+            };
 
-        let nonce_account = pubkey_of_signer(matches, NONCE_ARG.name, &mut wallet_manager)
-            .unwrap_or_else(|e| {
-                eprintln!("error: {}", e);
-                exit(1);
-            });
-        let nonce_authority = if nonce_account.is_some() {
-            let (signer, nonce_authority) = signer_from_path(
-                matches,
-                matches
-                    .value_of(NONCE_AUTHORITY_ARG.name)
-                    .unwrap_or(&cli_config.keypair_path),
-                NONCE_AUTHORITY_ARG.name,
-                &mut wallet_manager,
-            )
-            .map(|s| {
-                let p = s.pubkey();
-                (s, p)
-            })
-            .unwrap_or_else(|e| {
-                eprintln!("error: {}", e);
-                exit(1);
-            });
-            bulk_signers.push(signer);
+            let (wallet_signer, wallet_address) =
+                config.signer_or_default(arg_matches, "wallet_keypair", &mut wallet_manager);
+            bulk_signers.push(wallet_signer);
 
-            Some(nonce_authority)
-        } else {
-            None
-        };
+            command_wrap(config, amount, wallet_address, account, bulk_signers).await
+        }
+        (CommandName::Unwrap, arg_matches) => {
+            let (wallet_signer, wallet_address) =
+                config.signer_or_default(arg_matches, "wallet_keypair", &mut wallet_manager);
+            bulk_signers.push(wallet_signer);
 
-        let sign_only = matches.is_present(SIGN_ONLY_ARG.name);
-        let dump_transaction_message = matches.is_present(DUMP_TRANSACTION_MESSAGE.name);
-        let program_id = pubkey_of(matches, "program_id").unwrap();
+            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager).unwrap();
+            command_unwrap(config, wallet_address, address, bulk_signers).await
+        }
+        (CommandName::Approve, arg_matches) => {
+            let (owner_signer, owner_address) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
 
-        let multisig_signers = signers_of(matches, MULTISIG_SIGNER_ARG.name, &mut wallet_manager)
-            .unwrap_or_else(|e| {
-                eprintln!("error: {}", e);
-                exit(1);
-            });
-        if let Some(mut multisig_signers) = multisig_signers {
-            multisig_signers.sort_by(|(_, lp), (_, rp)| lp.cmp(rp));
-            let (signers, pubkeys): (Vec<_>, Vec<_>) = multisig_signers.into_iter().unzip();
-            bulk_signers.extend(signers);
-            multisigner_ids = pubkeys;
+            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let delegate = pubkey_of_signer(arg_matches, "delegate", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let mint_address =
+                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
+            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
+            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
+            command_approve(
+                config,
+                account,
+                owner_address,
+                amount,
+                delegate,
+                mint_address,
+                mint_decimals,
+                use_unchecked_instruction,
+                bulk_signers,
+            )
+            .await
         }
-        let multisigner_pubkeys = multisigner_ids.iter().collect::<Vec<_>>();
+        (CommandName::Revoke, arg_matches) => {
+            let (owner_signer, owner_address) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
 
-        let rpc_client = Arc::new(RpcClient::new_with_commitment(
-            json_rpc_url,
-            CommitmentConfig::confirmed(),
-        ));
-        let program_client: Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>> = Arc::new(
-            ProgramRpcClient::new(rpc_client.clone(), ProgramRpcClientSendTransaction),
-        );
-        Config {
-            rpc_client,
-            program_client,
-            websocket_url,
-            output_format,
-            fee_payer,
-            default_keypair: KeypairOrPath::Path(cli_config.keypair_path),
-            nonce_account,
-            nonce_authority,
-            sign_only,
-            dump_transaction_message,
-            multisigner_pubkeys,
-            program_id,
+            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let delegate_address =
+                pubkey_of_signer(arg_matches, DELEGATE_ADDRESS_ARG.name, &mut wallet_manager)
+                    .unwrap();
+            command_revoke(
+                config,
+                account,
+                owner_address,
+                delegate_address,
+                bulk_signers,
+            )
+            .await
         }
-    };
+        (CommandName::Close, arg_matches) => {
+            let (close_authority_signer, close_authority) =
+                config.signer_or_default(arg_matches, "close_authority", &mut wallet_manager);
+            bulk_signers.push(close_authority_signer);
 
-    solana_logger::setup_with_default("solana=info");
-    let result =
-        process_command(&sub_command, matches, &config, wallet_manager, bulk_signers).await?;
-    println!("{}", result);
-    Ok(())
-}
+            let address = config
+                .associated_token_address_or_override(arg_matches, "address", &mut wallet_manager)
+                .await;
+            let recipient = config.pubkey_or_default(arg_matches, "recipient", &mut wallet_manager);
+            command_close(config, address, close_authority, recipient, bulk_signers).await
+        }
+        (CommandName::Balance, arg_matches) => {
+            let address = config
+                .associated_token_address_or_override(arg_matches, "address", &mut wallet_manager)
+                .await;
+            command_balance(config, address).await
+        }
+        (CommandName::Supply, arg_matches) => {
+            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            command_supply(config, address).await
+        }
+        (CommandName::Accounts, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager).unwrap();
+            let owner = config.pubkey_or_default(arg_matches, "owner", &mut wallet_manager);
+            command_accounts(config, token, owner).await
+        }
+        (CommandName::Holders, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let min_balance = value_of::<f64>(arg_matches, "min_balance");
+            let by_owner = arg_matches.is_present("by_owner");
+            let csv = arg_matches.is_present("csv");
+            command_holders(config, token, min_balance, by_owner, csv).await
+        }
+        (CommandName::Address, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager).unwrap();
+            let owner = config.pubkey_or_default(arg_matches, "owner", &mut wallet_manager);
+            command_address(config, token, owner).await
+        }
+        (CommandName::AccountInfo, arg_matches) => {
+            let address = config
+                .associated_token_address_or_override(arg_matches, "address", &mut wallet_manager)
+                .await;
+            command_account_info(config, address).await
+        }
+        (CommandName::Display, arg_matches) => {
+            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            command_display(config, address).await
+        }
+        (CommandName::MultisigInfo, arg_matches) => {
+            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            command_multisig(config, address).await
+        }
+        (CommandName::Gc, arg_matches) => {
+            match config.output_format {
+                OutputFormat::Json | OutputFormat::JsonCompact => {
+                    eprintln!(
+                        "`spl-token gc` does not support the `--ouput` parameter at this time"
+                    );
+                    exit(1);
+                }
+                _ => {}
+            }
 
-async fn process_command<'a>(
-    sub_command: &CommandName,
-    sub_matches: &ArgMatches<'_>,
-    config: &Config<'a>,
-    mut wallet_manager: Option<Arc<RemoteWalletManager>>,
-    mut bulk_signers: Vec<Box<dyn Signer>>,
-) -> CommandResult {
-    match (sub_command, sub_matches) {
-        (CommandName::Bench, arg_matches) => {
-            bench_process_command(
-                arg_matches,
+            let close_empty_associated_accounts =
+                arg_matches.is_present("close_empty_associated_accounts");
+
+            let (owner_signer, owner_address) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
+
+            command_gc(
                 config,
-                std::mem::take(&mut bulk_signers),
+                owner_address,
+                close_empty_associated_accounts,
+                bulk_signers,
+            )
+            .await
+        }
+        (CommandName::SyncNative, arg_matches) => {
+            let program_id = config.program_id;
+            let native_mint = native_mint(&program_id)?;
+            let address = config
+                .associated_token_address_for_token_or_override(
+                    arg_matches,
+                    "address",
+                    &mut wallet_manager,
+                    Some(native_mint),
+                )
+                .await;
+            command_sync_native(address, bulk_signers, config).await
+        }
+        (CommandName::SetTransferFee, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let transfer_fee_basis_points =
+                value_t_or_exit!(arg_matches, "transfer_fee_basis_points", u16);
+            let maximum_fee = value_t_or_exit!(arg_matches, "maximum_fee", f64);
+            let (transfer_fee_authority_signer, transfer_fee_authority) = config.signer_or_default(
+                arg_matches,
+                "transfer_fee_authority",
                 &mut wallet_manager,
+            );
+            bulk_signers.push(transfer_fee_authority_signer);
+            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
+
+            command_set_transfer_fee(
+                config,
+                token,
+                transfer_fee_authority,
+                transfer_fee_basis_points,
+                maximum_fee,
+                mint_decimals,
+                bulk_signers,
             )
             .await
         }
-        (CommandName::CreateToken, arg_matches) => {
-            let decimals = value_t_or_exit!(arg_matches, "decimals", u8);
-            let mint_authority =
-                config.pubkey_or_default(arg_matches, "mint_authority", &mut wallet_manager);
-            let memo = value_t!(arg_matches, "memo", String).ok();
-
-            let (token_signer, token) =
-                get_signer(arg_matches, "token_keypair", &mut wallet_manager)
-                    .unwrap_or_else(new_throwaway_signer);
-            bulk_signers.push(token_signer);
-
-            command_create_token(
+        (CommandName::WithdrawWithheldTokens, arg_matches) => {
+            let (authority_signer, authority) = config.signer_or_default(
+                arg_matches,
+                "withdraw_withheld_authority",
+                &mut wallet_manager,
+            );
+            bulk_signers.push(authority_signer);
+            let destination_token_account =
+                pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                    .unwrap()
+                    .unwrap();
+            let include_mint = arg_matches.is_present("include_mint");
+            let source_accounts =
+                pubkeys_of_multiple_signers(arg_matches, "source", &mut wallet_manager)
+                    .unwrap_or_else(|e| {
+                        eprintln!("error: {}", e);
+                        exit(1);
+                    })
+                    .unwrap_or_default();
+            let mint_address =
+                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
+            command_withdraw_withheld_tokens(
                 config,
-                decimals,
-                token,
-                mint_authority,
-                arg_matches.is_present("enable_freeze"),
-                memo,
+                destination_token_account,
+                source_accounts,
+                mint_address,
+                authority,
+                include_mint,
                 bulk_signers,
             )
             .await
         }
-        (CommandName::CreateAccount, arg_matches) => {
+        (CommandName::HarvestWithheld, arg_matches) => {
             let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
                 .unwrap()
                 .unwrap();
-
-            // No need to add a signer when creating an associated token account
-            let account = get_signer(arg_matches, "account_keypair", &mut wallet_manager).map(
-                |(signer, account)| {
-                    bulk_signers.push(signer);
-                    account
-                },
-            );
-
-            let owner = config.pubkey_or_default(arg_matches, "owner", &mut wallet_manager);
-            command_create_account(config, token, owner, account, bulk_signers).await
-        }
-        (CommandName::CreateMultisig, arg_matches) => {
-            let minimum_signers = value_of::<u8>(arg_matches, "minimum_signers").unwrap();
-            let multisig_members =
-                pubkeys_of_multiple_signers(arg_matches, "multisig_member", &mut wallet_manager)
+            let source_accounts =
+                pubkeys_of_multiple_signers(arg_matches, "source", &mut wallet_manager)
                     .unwrap_or_else(|e| {
                         eprintln!("error: {}", e);
                         exit(1);
                     })
-                    .unwrap();
-            if minimum_signers as usize > multisig_members.len() {
-                eprintln!(
-                    "error: MINIMUM_SIGNERS cannot be greater than the number \
-                          of MULTISIG_MEMBERs passed"
-                );
-                exit(1);
-            }
-
-            let (signer, account) = get_signer(arg_matches, "address_keypair", &mut wallet_manager)
-                .unwrap_or_else(new_throwaway_signer);
-            bulk_signers.push(signer);
-
-            command_create_multisig(
+                    .unwrap_or_default();
+            command_harvest_withheld(config, token, source_accounts, bulk_signers).await
+        }
+        (CommandName::EnableRequiredTransferMemos, arg_matches) => {
+            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let (owner_signer, owner) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
+            command_required_transfer_memos(config, account, owner, true, bulk_signers).await
+        }
+        (CommandName::DisableRequiredTransferMemos, arg_matches) => {
+            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let (owner_signer, owner) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
+            command_required_transfer_memos(config, account, owner, false, bulk_signers).await
+        }
+        (CommandName::Reallocate, arg_matches) => {
+            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let extension_types = arg_matches
+                .values_of("extension")
+                .unwrap()
+                .map(|extension| match extension {
+                    "memo-transfer" => ExtensionType::MemoTransfer,
+                    "confidential-transfer-account" => ExtensionType::ConfidentialTransferAccount,
+                    _ => unreachable!(),
+                })
+                .collect();
+            let (owner_signer, owner) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
+            command_reallocate(config, account, owner, extension_types, bulk_signers).await
+        }
+        (CommandName::UpdateInterestRate, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let rate_bps = value_t_or_exit!(arg_matches, "rate", i16);
+            let (rate_authority_signer, rate_authority) =
+                config.signer_or_default(arg_matches, "rate_authority", &mut wallet_manager);
+            bulk_signers.push(rate_authority_signer);
+            command_update_interest_rate(config, token, rate_authority, rate_bps, bulk_signers)
+                .await
+        }
+        (CommandName::ConfigureConfidentialAccount, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let address = config
+                .associated_token_address_for_token_or_override(
+                    arg_matches,
+                    "address",
+                    &mut wallet_manager,
+                    Some(token),
+                )
+                .await;
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            let maximum_pending_balance_credit_counter =
+                value_t!(arg_matches, "maximum_pending_balance_credit_counter", u64)
+                    .unwrap_or(DEFAULT_MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER);
+            command_configure_confidential_account(
                 config,
-                account,
-                minimum_signers,
-                multisig_members,
+                address,
+                owner_signer,
+                maximum_pending_balance_credit_counter,
                 bulk_signers,
             )
             .await
         }
-        (CommandName::Authorize, arg_matches) => {
-            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager)
+        (CommandName::DepositConfidential, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
                 .unwrap()
                 .unwrap();
-            let authority_type = arg_matches.value_of("authority_type").unwrap();
-            let authority_type = match authority_type {
-                "mint" => AuthorityType::MintTokens,
-                "freeze" => AuthorityType::FreezeAccount,
-                "owner" => AuthorityType::AccountOwner,
-                "close" => AuthorityType::CloseAccount,
-                "close-mint" => AuthorityType::CloseMint,
-                "transfer-fee-config" => AuthorityType::TransferFeeConfig,
-                "withheld-withdraw" => AuthorityType::WithheldWithdraw,
-                "interest-rate" => AuthorityType::InterestRate,
-                _ => unreachable!(),
-            };
-
-            let (authority_signer, authority) =
-                config.signer_or_default(arg_matches, "authority", &mut wallet_manager);
-            bulk_signers.push(authority_signer);
-
-            let new_authority =
-                pubkey_of_signer(arg_matches, "new_authority", &mut wallet_manager).unwrap();
-            let force_authorize = arg_matches.is_present("force");
-            command_authorize(
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let address = config
+                .associated_token_address_for_token_or_override(
+                    arg_matches,
+                    "address",
+                    &mut wallet_manager,
+                    Some(token),
+                )
+                .await;
+            let (owner_signer, owner) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            bulk_signers.push(owner_signer);
+            command_deposit_confidential(config, address, owner, amount, bulk_signers).await
+        }
+        (CommandName::ApplyPendingBalance, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let address = config
+                .associated_token_address_for_token_or_override(
+                    arg_matches,
+                    "address",
+                    &mut wallet_manager,
+                    Some(token),
+                )
+                .await;
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            command_apply_pending_balance(config, address, owner_signer, bulk_signers).await
+        }
+        (CommandName::ConfidentialTransfer, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let recipient = pubkey_of_signer(arg_matches, "recipient", &mut wallet_manager)
+                .unwrap()
+                .unwrap();
+            let address = config
+                .associated_token_address_for_token_or_override(
+                    arg_matches,
+                    "from",
+                    &mut wallet_manager,
+                    Some(token),
+                )
+                .await;
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            command_confidential_transfer(
                 config,
                 address,
-                authority_type,
-                authority,
-                new_authority,
-                force_authorize,
+                recipient,
+                owner_signer,
+                amount,
                 bulk_signers,
             )
             .await
         }
-        (CommandName::Transfer, arg_matches) => {
+        (CommandName::WithdrawConfidential, arg_matches) => {
             let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
                 .unwrap()
                 .unwrap();
-            let amount = match arg_matches.value_of("amount").unwrap() {
-                "ALL" => None,
-                amount => Some(amount.parse::<f64>().unwrap()),
-            };
-            let recipient = pubkey_of_signer(arg_matches, "recipient", &mut wallet_manager)
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let address = config
+                .associated_token_address_for_token_or_override(
+                    arg_matches,
+                    "address",
+                    &mut wallet_manager,
+                    Some(token),
+                )
+                .await;
+            let (owner_signer, _) =
+                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
+            command_withdraw_confidential(config, address, owner_signer, amount, bulk_signers).await
+        }
+        (CommandName::ShowConfidentialBalance, arg_matches) => {
+            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
                 .unwrap()
                 .unwrap();
-            let sender = pubkey_of_signer(arg_matches, "from", &mut wallet_manager).unwrap();
-
-            let (owner_signer, owner) =
+            let address = config
+                .associated_token_address_for_token_or_override(
+                    arg_matches,
+                    "address",
+                    &mut wallet_manager,
+                    Some(token),
+                )
+                .await;
+            let (owner_signer, _) =
                 config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
-            bulk_signers.push(owner_signer);
+            command_show_confidential_balance(config, address, owner_signer).await
+        }
+    }
+}
+
+fn format_output<T>(command_output: T, command_name: &CommandName, config: &Config) -> String
+where
+    T: Serialize + Display + QuietDisplay + VerboseDisplay,
+{
+    config.output_format.formatted_string(&CommandOutput {
+        command_name: command_name.to_string(),
+        command_output,
+    })
+}
+enum TransactionReturnData {
+    CliSignature(CliSignature),
+    CliSignOnlyData(CliSignOnlyData),
+}
+async fn handle_tx<'a>(
+    signer_info: &CliSignerInfo,
+    config: &Config<'a>,
+    no_wait: bool,
+    minimum_balance_for_rent_exemption: u64,
+    instructions: Vec<Instruction>,
+) -> Result<TransactionReturnData, Error> {
+    let fee_payer = Some(&config.fee_payer);
+
+    let recent_blockhash = config.rpc_client.get_latest_blockhash().await?;
+    let message = if let Some(nonce_account) = config.nonce_account.as_ref() {
+        let mut message = Message::new_with_nonce(
+            instructions,
+            fee_payer,
+            nonce_account,
+            config.nonce_authority.as_ref().unwrap(),
+        );
+        message.recent_blockhash = recent_blockhash;
+        message
+    } else {
+        Message::new_with_blockhash(&instructions, fee_payer, &recent_blockhash)
+    };
+    let fee = config.rpc_client.get_fee_for_message(&message).await?;
+
+    if !config.sign_only {
+        check_fee_payer_balance(config, minimum_balance_for_rent_exemption + fee).await?;
+    }
+
+    let signers = signer_info.signers_for_message(&message);
+    let mut transaction = Transaction::new_unsigned(message);
+
+    if config.sign_only {
+        transaction.try_partial_sign(&signers, recent_blockhash)?;
+        Ok(TransactionReturnData::CliSignOnlyData(return_signers_data(
+            &transaction,
+            &ReturnSignersConfig {
+                dump_transaction_message: config.dump_transaction_message,
+            },
+        )))
+    } else {
+        transaction.try_sign(&signers, recent_blockhash)?;
+        let signature = if no_wait {
+            config.rpc_client.send_transaction(&transaction).await?
+        } else {
+            config
+                .rpc_client
+                .send_and_confirm_transaction_with_spinner(&transaction)
+                .await?
+        };
+        Ok(TransactionReturnData::CliSignature(CliSignature {
+            signature: signature.to_string(),
+        }))
+    }
+}
 
-            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
-            let fund_recipient = arg_matches.is_present("fund_recipient");
-            let allow_unfunded_recipient = arg_matches.is_present("allow_empty_recipient")
-                || arg_matches.is_present("allow_unfunded_recipient");
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        serial_test::serial,
+        solana_sdk::{
+            bpf_loader,
+            signature::{write_keypair_file, Keypair, Signature, Signer},
+        },
+        solana_test_validator::{ProgramInfo, TestValidator, TestValidatorGenesis},
+        std::path::PathBuf,
+        tempfile::NamedTempFile,
+    };
 
-            let recipient_is_ata_owner = arg_matches.is_present("recipient_is_ata_owner");
-            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
-            let memo = value_t!(arg_matches, "memo", String).ok();
+    fn clone_keypair(keypair: &Keypair) -> Keypair {
+        Keypair::from_bytes(&keypair.to_bytes()).unwrap()
+    }
 
-            command_transfer(
-                config,
-                token,
-                amount,
-                recipient,
-                sender,
-                owner,
-                allow_unfunded_recipient,
-                fund_recipient,
-                mint_decimals,
-                recipient_is_ata_owner,
-                use_unchecked_instruction,
-                memo,
-                bulk_signers,
-                arg_matches.is_present("no_wait"),
-                arg_matches.is_present("allow_non_system_account_recipient"),
-            )
-            .await
-        }
-        (CommandName::Burn, arg_matches) => {
-            let source = pubkey_of_signer(arg_matches, "source", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
+    const TEST_DECIMALS: u8 = 0;
 
-            let (owner_signer, owner) =
-                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
-            bulk_signers.push(owner_signer);
+    async fn new_validator_for_test() -> (TestValidator, Keypair) {
+        solana_logger::setup();
+        let mut test_validator_genesis = TestValidatorGenesis::default();
+        test_validator_genesis.add_programs_with_path(&[
+            ProgramInfo {
+                program_id: spl_token::id(),
+                loader: bpf_loader::id(),
+                program_path: PathBuf::from("../../target/deploy/spl_token.so"),
+            },
+            ProgramInfo {
+                program_id: spl_associated_token_account::id(),
+                loader: bpf_loader::id(),
+                program_path: PathBuf::from("../../target/deploy/spl_associated_token_account.so"),
+            },
+            ProgramInfo {
+                program_id: spl_token_2022::id(),
+                loader: bpf_loader::id(),
+                program_path: PathBuf::from("../../target/deploy/spl_token_2022.so"),
+            },
+        ]);
+        test_validator_genesis.start_async().await
+    }
 
-            let amount = value_t_or_exit!(arg_matches, "amount", f64);
-            let mint_address =
-                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
-            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
-            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
-            let memo = value_t!(arg_matches, "memo", String).ok();
-            command_burn(
-                config,
-                source,
-                owner,
-                amount,
-                mint_address,
-                mint_decimals,
-                use_unchecked_instruction,
-                memo,
-                bulk_signers,
-            )
-            .await
+    fn test_config<'a>(
+        test_validator: &TestValidator,
+        payer: &Keypair,
+        program_id: &Pubkey,
+    ) -> Config<'a> {
+        let websocket_url = test_validator.rpc_pubsub_url();
+        let rpc_client = Arc::new(test_validator.get_async_rpc_client());
+        let program_client: Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>> = Arc::new(
+            ProgramRpcClient::new(rpc_client.clone(), ProgramRpcClientSendTransaction),
+        );
+        Config {
+            rpc_client,
+            program_client,
+            websocket_url,
+            output_format: OutputFormat::JsonCompact,
+            fee_payer: payer.pubkey(),
+            default_keypair: KeypairOrPath::Keypair(clone_keypair(payer)),
+            nonce_account: None,
+            nonce_authority: None,
+            sign_only: false,
+            dump_transaction_message: false,
+            multisigner_pubkeys: vec![],
+            program_id: *program_id,
         }
-        (CommandName::Mint, arg_matches) => {
-            let (mint_authority_signer, mint_authority) =
-                config.signer_or_default(arg_matches, "mint_authority", &mut wallet_manager);
-            bulk_signers.push(mint_authority_signer);
+    }
 
-            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            let amount = value_t_or_exit!(arg_matches, "amount", f64);
-            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
-            let mint_info = config.get_mint_info(&token, mint_decimals).await?;
-            let recipient = if let Some(address) =
-                pubkey_of_signer(arg_matches, "recipient", &mut wallet_manager).unwrap()
-            {
-                address
-            } else {
-                config.associated_token_address_for_token_and_program(
-                    arg_matches,
-                    &mut wallet_manager,
-                    &mint_info.address,
-                    &mint_info.program_id,
-                )
-            };
-            config.check_account(&recipient, Some(token)).await?;
-            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
-            command_mint(
-                config,
-                token,
-                amount,
-                recipient,
-                mint_info,
-                mint_authority,
-                use_unchecked_instruction,
-                bulk_signers,
-            )
-            .await
+    async fn do_create_native_mint(config: &Config<'_>, program_id: &Pubkey, payer: &Keypair) {
+        if program_id == &spl_token_2022::id() {
+            let native_mint = spl_token_2022::native_mint::id();
+            if config.rpc_client.get_account(&native_mint).await.is_err() {
+                let transaction = Transaction::new_signed_with_payer(
+                    &[create_native_mint(program_id, &payer.pubkey()).unwrap()],
+                    Some(&payer.pubkey()),
+                    &[payer],
+                    config.rpc_client.get_latest_blockhash().await.unwrap(),
+                );
+                config
+                    .rpc_client
+                    .send_and_confirm_transaction(&transaction)
+                    .await
+                    .unwrap();
+            }
         }
-        (CommandName::Freeze, arg_matches) => {
-            let (freeze_authority_signer, freeze_authority) =
-                config.signer_or_default(arg_matches, "freeze_authority", &mut wallet_manager);
-            bulk_signers.push(freeze_authority_signer);
+    }
 
-            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            let mint_address =
-                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
-            command_freeze(
-                config,
-                account,
-                mint_address,
-                freeze_authority,
-                bulk_signers,
-            )
+    async fn create_token(config: &Config<'_>, payer: &Keypair) -> Pubkey {
+        let token = Keypair::new();
+        let token_pubkey = token.pubkey();
+        let bulk_signers: Vec<Box<dyn Signer>> =
+            vec![Box::new(clone_keypair(payer)), Box::new(token)];
+
+        command_create_token(
+            config,
+            TEST_DECIMALS,
+            token_pubkey,
+            payer.pubkey(),
+            false,
+            vec![],
+            None,
+            bulk_signers,
+        )
+        .await
+        .unwrap();
+        token_pubkey
+    }
+
+    async fn create_auxiliary_account(
+        config: &Config<'_>,
+        payer: &Keypair,
+        mint: Pubkey,
+    ) -> Pubkey {
+        let auxiliary = Keypair::new();
+        let address = auxiliary.pubkey();
+        let bulk_signers: Vec<Box<dyn Signer>> =
+            vec![Box::new(clone_keypair(payer)), Box::new(auxiliary)];
+        command_create_account(config, mint, payer.pubkey(), Some(address), bulk_signers)
             .await
-        }
-        (CommandName::Thaw, arg_matches) => {
-            let (freeze_authority_signer, freeze_authority) =
-                config.signer_or_default(arg_matches, "freeze_authority", &mut wallet_manager);
-            bulk_signers.push(freeze_authority_signer);
+            .unwrap();
+        address
+    }
 
-            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            let mint_address =
-                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
-            command_thaw(
-                config,
-                account,
-                mint_address,
-                freeze_authority,
-                bulk_signers,
-            )
+    async fn show_confidential_balance(
+        config: &Config<'_>,
+        payer: &Keypair,
+        token: Pubkey,
+        address: Pubkey,
+    ) -> serde_json::Value {
+        let result = process_test_command(
+            config,
+            payer,
+            &[
+                "spl-token",
+                CommandName::ShowConfidentialBalance.into(),
+                &token.to_string(),
+                "--address",
+                &address.to_string(),
+            ],
+        )
+        .await;
+        serde_json::from_str(&result.unwrap()).unwrap()
+    }
+
+    async fn create_associated_account(
+        config: &Config<'_>,
+        payer: &Keypair,
+        mint: Pubkey,
+    ) -> Pubkey {
+        let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(payer))];
+        command_create_account(config, mint, payer.pubkey(), None, bulk_signers)
             .await
-        }
-        (CommandName::Wrap, arg_matches) => {
-            let amount = value_t_or_exit!(arg_matches, "amount", f64);
-            let account = if arg_matches.is_present("create_aux_account") {
-                let (signer, account) = new_throwaway_signer();
-                bulk_signers.push(signer);
-                Some(account)
-            } else {
-                // No need to add a signer when creating an associated token account
-                None
-            };
+            .unwrap();
+        get_associated_token_address_with_program_id(&payer.pubkey(), &mint, &config.program_id)
+    }
 
-            let (wallet_signer, wallet_address) =
-                config.signer_or_default(arg_matches, "wallet_keypair", &mut wallet_manager);
-            bulk_signers.push(wallet_signer);
+    async fn mint_tokens(
+        config: &Config<'_>,
+        payer: &Keypair,
+        mint: Pubkey,
+        ui_amount: f64,
+        recipient: Pubkey,
+    ) {
+        let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(payer))];
+        command_mint(
+            config,
+            mint,
+            ui_amount,
+            recipient,
+            MintInfo {
+                program_id: config.program_id,
+                address: mint,
+                decimals: TEST_DECIMALS,
+            },
+            payer.pubkey(),
+            false,
+            bulk_signers,
+        )
+        .await
+        .unwrap();
+    }
 
-            command_wrap(config, amount, wallet_address, account, bulk_signers).await
-        }
-        (CommandName::Unwrap, arg_matches) => {
-            let (wallet_signer, wallet_address) =
-                config.signer_or_default(arg_matches, "wallet_keypair", &mut wallet_manager);
-            bulk_signers.push(wallet_signer);
+    async fn process_test_command(
+        config: &Config<'_>,
+        payer: &Keypair,
+        args: &[&str],
+    ) -> CommandResult {
+        let default_decimals = format!("{}", spl_token_2022::native_mint::DECIMALS);
+        let default_program_id = spl_token::id().to_string();
+        let minimum_signers_help = minimum_signers_help_string();
+        let multisig_member_help = multisig_member_help_string();
 
-            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager).unwrap();
-            command_unwrap(config, wallet_address, address, bulk_signers).await
-        }
-        (CommandName::Approve, arg_matches) => {
-            let (owner_signer, owner_address) =
-                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
-            bulk_signers.push(owner_signer);
+        let app_matches = app(
+            &default_decimals,
+            &default_program_id,
+            &minimum_signers_help,
+            &multisig_member_help,
+        )
+        .get_matches_from(args);
+        let (sub_command, sub_matches) = app_matches.subcommand();
+        let sub_command = CommandName::from_str(sub_command).unwrap();
+        let matches = sub_matches.unwrap();
 
-            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            let amount = value_t_or_exit!(arg_matches, "amount", f64);
-            let delegate = pubkey_of_signer(arg_matches, "delegate", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            let mint_address =
-                pubkey_of_signer(arg_matches, MINT_ADDRESS_ARG.name, &mut wallet_manager).unwrap();
-            let mint_decimals = value_of::<u8>(arg_matches, MINT_DECIMALS_ARG.name);
-            let use_unchecked_instruction = arg_matches.is_present("use_unchecked_instruction");
-            command_approve(
-                config,
-                account,
-                owner_address,
-                amount,
-                delegate,
-                mint_address,
-                mint_decimals,
-                use_unchecked_instruction,
-                bulk_signers,
-            )
-            .await
-        }
-        (CommandName::Revoke, arg_matches) => {
-            let (owner_signer, owner_address) =
-                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
-            bulk_signers.push(owner_signer);
+        let wallet_manager = None;
+        let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(payer))];
+        process_command(&sub_command, matches, config, wallet_manager, bulk_signers).await
+    }
 
-            let account = pubkey_of_signer(arg_matches, "account", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            let delegate_address =
-                pubkey_of_signer(arg_matches, DELEGATE_ADDRESS_ARG.name, &mut wallet_manager)
-                    .unwrap();
-            command_revoke(
-                config,
-                account,
-                owner_address,
-                delegate_address,
-                bulk_signers,
+    #[tokio::test]
+    #[serial]
+    async fn create_token_default() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let result = process_test_command(
+                &config,
+                &payer,
+                &["spl-token", CommandName::CreateToken.into()],
             )
-            .await
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            let mint =
+                Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
+            let account = config.rpc_client.get_account(&mint).await.unwrap();
+            assert_eq!(account.owner, program_id);
         }
-        (CommandName::Close, arg_matches) => {
-            let (close_authority_signer, close_authority) =
-                config.signer_or_default(arg_matches, "close_authority", &mut wallet_manager);
-            bulk_signers.push(close_authority_signer);
+    }
 
-            let address = config
-                .associated_token_address_or_override(arg_matches, "address", &mut wallet_manager)
-                .await;
-            let recipient = config.pubkey_or_default(arg_matches, "recipient", &mut wallet_manager);
-            command_close(config, address, close_authority, recipient, bulk_signers).await
-        }
-        (CommandName::Balance, arg_matches) => {
-            let address = config
-                .associated_token_address_or_override(arg_matches, "address", &mut wallet_manager)
-                .await;
-            command_balance(config, address).await
-        }
-        (CommandName::Supply, arg_matches) => {
-            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            command_supply(config, address).await
-        }
-        (CommandName::Accounts, arg_matches) => {
-            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager).unwrap();
-            let owner = config.pubkey_or_default(arg_matches, "owner", &mut wallet_manager);
-            command_accounts(config, token, owner).await
-        }
-        (CommandName::Address, arg_matches) => {
-            let token = pubkey_of_signer(arg_matches, "token", &mut wallet_manager).unwrap();
-            let owner = config.pubkey_or_default(arg_matches, "owner", &mut wallet_manager);
-            command_address(config, token, owner).await
-        }
-        (CommandName::AccountInfo, arg_matches) => {
-            let address = config
-                .associated_token_address_or_override(arg_matches, "address", &mut wallet_manager)
-                .await;
-            command_account_info(config, address).await
-        }
-        (CommandName::MultisigInfo, arg_matches) => {
-            let address = pubkey_of_signer(arg_matches, "address", &mut wallet_manager)
-                .unwrap()
-                .unwrap();
-            command_multisig(config, address).await
-        }
-        (CommandName::Gc, arg_matches) => {
-            match config.output_format {
-                OutputFormat::Json | OutputFormat::JsonCompact => {
-                    eprintln!(
-                        "`spl-token gc` does not support the `--ouput` parameter at this time"
-                    );
-                    exit(1);
-                }
-                _ => {}
-            }
+    #[tokio::test]
+    #[serial]
+    async fn create_token_with_extensions() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        let program_id = spl_token_2022::id();
+        let config = test_config(&test_validator, &payer, &program_id);
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::CreateToken.into(),
+                "--transfer-fee",
+                "100",
+                "10",
+                "--interest-rate",
+                "-50",
+                "--enable-freeze",
+                "--default-account-state",
+                "frozen",
+                "--enable-close",
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        let mint = Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
+        let account = config.rpc_client.get_account(&mint).await.unwrap();
+        let mint_state = StateWithExtensionsOwned::<Mint>::unpack(account.data).unwrap();
+        let mut extension_types = mint_state.get_extension_types().unwrap();
+        extension_types.sort_by_key(|e| *e as u16);
+        assert_eq!(
+            extension_types,
+            vec![
+                ExtensionType::TransferFeeConfig,
+                ExtensionType::MintCloseAuthority,
+                ExtensionType::DefaultAccountState,
+                ExtensionType::InterestBearingConfig,
+            ]
+        );
 
-            let close_empty_associated_accounts =
-                arg_matches.is_present("close_empty_associated_accounts");
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::CreateToken.into(),
+                "--enable-non-transferable",
+                "--enable-confidential-transfers",
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        let mint = Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
+        let account = config.rpc_client.get_account(&mint).await.unwrap();
+        let mint_state = StateWithExtensionsOwned::<Mint>::unpack(account.data).unwrap();
+        let mut extension_types = mint_state.get_extension_types().unwrap();
+        extension_types.sort_by_key(|e| *e as u16);
+        assert_eq!(
+            extension_types,
+            vec![
+                ExtensionType::ConfidentialTransferMint,
+                ExtensionType::NonTransferable,
+            ]
+        );
+        let ct_mint = mint_state
+            .get_extension::<ConfidentialTransferMint>()
+            .unwrap();
+        assert_eq!(ct_mint.authority, payer.pubkey());
+        assert!(bool::from(ct_mint.auto_approve_new_accounts));
 
-            let (owner_signer, owner_address) =
-                config.signer_or_default(arg_matches, "owner", &mut wallet_manager);
-            bulk_signers.push(owner_signer);
+        // extensions are rejected by the original token program
+        let config = test_config(&test_validator, &payer, &spl_token::id());
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::CreateToken.into(),
+                "--enable-close",
+            ],
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn account_and_mint_toggles() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        let config = test_config(&test_validator, &payer, &spl_token_2022::id());
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::CreateToken.into(),
+                "--interest-rate",
+                "5",
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        let mint = Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
+        let account = create_associated_account(&config, &payer, mint).await;
+
+        // memo requirement can be switched on and off
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::EnableRequiredTransferMemos.into(),
+                &account.to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+        let data = config.rpc_client.get_account(&account).await.unwrap().data;
+        let state = StateWithExtensionsOwned::<Account>::unpack(data).unwrap();
+        let extension = state.get_extension::<MemoTransfer>().unwrap();
+        assert!(bool::from(extension.require_incoming_transfer_memos));
 
-            command_gc(
-                config,
-                owner_address,
-                close_empty_associated_accounts,
-                bulk_signers,
-            )
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::DisableRequiredTransferMemos.into(),
+                &account.to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+        let data = config.rpc_client.get_account(&account).await.unwrap().data;
+        let state = StateWithExtensionsOwned::<Account>::unpack(data).unwrap();
+        let extension = state.get_extension::<MemoTransfer>().unwrap();
+        assert!(!bool::from(extension.require_incoming_transfer_memos));
+
+        // reallocate makes room without enabling anything
+        let auxiliary = create_auxiliary_account(&config, &payer, mint).await;
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::Reallocate.into(),
+                &auxiliary.to_string(),
+                "--extension",
+                "memo-transfer",
+            ],
+        )
+        .await
+        .unwrap();
+        let data = config
+            .rpc_client
+            .get_account(&auxiliary)
             .await
-        }
-        (CommandName::SyncNative, arg_matches) => {
-            let program_id = config.program_id;
-            let native_mint = native_mint(&program_id)?;
-            let address = config
-                .associated_token_address_for_token_or_override(
-                    arg_matches,
-                    "address",
-                    &mut wallet_manager,
-                    Some(native_mint),
-                )
-                .await;
-            command_sync_native(address, bulk_signers, config).await
-        }
+            .unwrap()
+            .data;
+        let state = StateWithExtensionsOwned::<Account>::unpack(data).unwrap();
+        assert!(state
+            .get_extension_types()
+            .unwrap()
+            .contains(&ExtensionType::MemoTransfer));
+
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::UpdateInterestRate.into(),
+                &mint.to_string(),
+                "-10",
+            ],
+        )
+        .await
+        .unwrap();
+        let data = config.rpc_client.get_account(&mint).await.unwrap().data;
+        let state = StateWithExtensionsOwned::<Mint>::unpack(data).unwrap();
+        let extension = state.get_extension::<InterestBearingConfig>().unwrap();
+        assert_eq!(i16::from(extension.current_rate), -10);
     }
-}
 
-fn format_output<T>(command_output: T, command_name: &CommandName, config: &Config) -> String
-where
-    T: Serialize + Display + QuietDisplay + VerboseDisplay,
-{
-    config.output_format.formatted_string(&CommandOutput {
-        command_name: command_name.to_string(),
-        command_output,
-    })
-}
-enum TransactionReturnData {
-    CliSignature(CliSignature),
-    CliSignOnlyData(CliSignOnlyData),
-}
-async fn handle_tx<'a>(
-    signer_info: &CliSignerInfo,
-    config: &Config<'a>,
-    no_wait: bool,
-    minimum_balance_for_rent_exemption: u64,
-    instructions: Vec<Instruction>,
-) -> Result<TransactionReturnData, Error> {
-    let fee_payer = Some(&config.fee_payer);
+    #[tokio::test]
+    #[serial]
+    async fn display() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        let config = test_config(&test_validator, &payer, &spl_token_2022::id());
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::CreateToken.into(),
+                "--decimals",
+                "0",
+                "--transfer-fee",
+                "100",
+                "10",
+                "--interest-rate",
+                "5",
+                "--enable-close",
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        let mint = Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
 
-    let recent_blockhash = config.rpc_client.get_latest_blockhash().await?;
-    let message = if let Some(nonce_account) = config.nonce_account.as_ref() {
-        let mut message = Message::new_with_nonce(
-            instructions,
-            fee_payer,
-            nonce_account,
-            config.nonce_authority.as_ref().unwrap(),
+        let result = process_test_command(
+            &config,
+            &payer,
+            &["spl-token", CommandName::Display.into(), &mint.to_string()],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        assert_eq!(value["accountType"], "mint");
+        assert_eq!(value["fields"]["mintAuthority"], payer.pubkey().to_string());
+        assert_eq!(value["fields"]["decimals"], "0");
+        let extensions = &value["extensions"];
+        assert_eq!(
+            extensions["mintCloseAuthority"]["closeAuthority"],
+            payer.pubkey().to_string()
         );
-        message.recent_blockhash = recent_blockhash;
-        message
-    } else {
-        Message::new_with_blockhash(&instructions, fee_payer, &recent_blockhash)
-    };
-    let fee = config.rpc_client.get_fee_for_message(&message).await?;
-
-    if !config.sign_only {
-        check_fee_payer_balance(config, minimum_balance_for_rent_exemption + fee).await?;
-    }
+        assert_eq!(extensions["interestBearingConfig"]["currentRate"], "5");
+        assert!(extensions["transferFeeConfig"]["newerTransferFee"]
+            .as_str()
+            .unwrap()
+            .starts_with("100 basis points, maximum fee 10"));
 
-    let signers = signer_info.signers_for_message(&message);
-    let mut transaction = Transaction::new_unsigned(message);
+        let account = create_associated_account(&config, &payer, mint).await;
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::Display.into(),
+                &account.to_string(),
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        assert_eq!(value["accountType"], "account");
+        assert_eq!(value["fields"]["mint"], mint.to_string());
+        assert_eq!(value["fields"]["state"], "initialized");
+        assert_eq!(
+            value["extensions"]["transferFeeAmount"]["withheldAmount"],
+            "0"
+        );
 
-    if config.sign_only {
-        transaction.try_partial_sign(&signers, recent_blockhash)?;
-        Ok(TransactionReturnData::CliSignOnlyData(return_signers_data(
-            &transaction,
-            &ReturnSignersConfig {
-                dump_transaction_message: config.dump_transaction_message,
-            },
-        )))
-    } else {
-        transaction.try_sign(&signers, recent_blockhash)?;
-        let signature = if no_wait {
-            config.rpc_client.send_transaction(&transaction).await?
-        } else {
-            config
-                .rpc_client
-                .send_and_confirm_transaction_with_spinner(&transaction)
-                .await?
-        };
-        Ok(TransactionReturnData::CliSignature(CliSignature {
-            signature: signature.to_string(),
-        }))
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::Display.into(),
+                &payer.pubkey().to_string(),
+            ],
+        )
+        .await;
+        assert!(result.is_err());
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use {
-        super::*,
-        serial_test::serial,
-        solana_sdk::{
-            bpf_loader,
-            signature::{write_keypair_file, Keypair, Signer},
-        },
-        solana_test_validator::{ProgramInfo, TestValidator, TestValidatorGenesis},
-        std::path::PathBuf,
-        tempfile::NamedTempFile,
-    };
 
-    fn clone_keypair(keypair: &Keypair) -> Keypair {
-        Keypair::from_bytes(&keypair.to_bytes()).unwrap()
+    #[tokio::test]
+    #[serial]
+    async fn supply() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let token = create_token(&config, &payer).await;
+            let result = process_test_command(
+                &config,
+                &payer,
+                &["spl-token", CommandName::Supply.into(), &token.to_string()],
+            )
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["amount"], "0");
+            assert_eq!(value["uiAmountString"], "0");
+        }
     }
 
-    const TEST_DECIMALS: u8 = 0;
+    #[tokio::test]
+    #[serial]
+    async fn create_account_default() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let token = create_token(&config, &payer).await;
+            let result = process_test_command(
+                &config,
+                &payer,
+                &[
+                    "spl-token",
+                    CommandName::CreateAccount.into(),
+                    &token.to_string(),
+                ],
+            )
+            .await;
+            result.unwrap();
+        }
+    }
 
-    async fn new_validator_for_test() -> (TestValidator, Keypair) {
-        solana_logger::setup();
-        let mut test_validator_genesis = TestValidatorGenesis::default();
-        test_validator_genesis.add_programs_with_path(&[
-            ProgramInfo {
-                program_id: spl_token::id(),
-                loader: bpf_loader::id(),
-                program_path: PathBuf::from("../../target/deploy/spl_token.so"),
-            },
-            ProgramInfo {
-                program_id: spl_associated_token_account::id(),
-                loader: bpf_loader::id(),
-                program_path: PathBuf::from("../../target/deploy/spl_associated_token_account.so"),
-            },
-            ProgramInfo {
-                program_id: spl_token_2022::id(),
-                loader: bpf_loader::id(),
-                program_path: PathBuf::from("../../target/deploy/spl_token_2022.so"),
-            },
-        ]);
-        test_validator_genesis.start_async().await
+    #[tokio::test]
+    #[serial]
+    async fn account_info() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let token = create_token(&config, &payer).await;
+            let _account = create_associated_account(&config, &payer, token).await;
+            let result = process_test_command(
+                &config,
+                &payer,
+                &[
+                    "spl-token",
+                    CommandName::AccountInfo.into(),
+                    &token.to_string(),
+                ],
+            )
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            let account = get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                &token,
+                &config.program_id,
+            );
+            assert_eq!(value["address"], account.to_string());
+            assert_eq!(value["mint"], token.to_string());
+            assert_eq!(value["isAssociated"], true);
+            assert_eq!(value["isNative"], false);
+            assert_eq!(value["owner"], payer.pubkey().to_string());
+            assert_eq!(value["state"], "initialized");
+        }
     }
 
-    fn test_config<'a>(
-        test_validator: &TestValidator,
-        payer: &Keypair,
-        program_id: &Pubkey,
-    ) -> Config<'a> {
-        let websocket_url = test_validator.rpc_pubsub_url();
-        let rpc_client = Arc::new(test_validator.get_async_rpc_client());
-        let program_client: Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>> = Arc::new(
-            ProgramRpcClient::new(rpc_client.clone(), ProgramRpcClientSendTransaction),
-        );
-        Config {
-            rpc_client,
-            program_client,
-            websocket_url,
-            output_format: OutputFormat::JsonCompact,
-            fee_payer: payer.pubkey(),
-            default_keypair: KeypairOrPath::Keypair(clone_keypair(payer)),
-            nonce_account: None,
-            nonce_authority: None,
-            sign_only: false,
-            dump_transaction_message: false,
-            multisigner_pubkeys: vec![],
-            program_id: *program_id,
+    #[tokio::test]
+    #[serial]
+    async fn balance() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let token = create_token(&config, &payer).await;
+            let _account = create_associated_account(&config, &payer, token).await;
+            let result = process_test_command(
+                &config,
+                &payer,
+                &["spl-token", CommandName::Balance.into(), &token.to_string()],
+            )
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["amount"], "0");
+            assert_eq!(value["uiAmountString"], "0");
         }
     }
 
-    async fn do_create_native_mint(config: &Config<'_>, program_id: &Pubkey, payer: &Keypair) {
-        if program_id == &spl_token_2022::id() {
-            let native_mint = spl_token_2022::native_mint::id();
-            if config.rpc_client.get_account(&native_mint).await.is_err() {
-                let transaction = Transaction::new_signed_with_payer(
-                    &[create_native_mint(program_id, &payer.pubkey()).unwrap()],
-                    Some(&payer.pubkey()),
-                    &[payer],
-                    config.rpc_client.get_latest_blockhash().await.unwrap(),
-                );
-                config
-                    .rpc_client
-                    .send_and_confirm_transaction(&transaction)
-                    .await
-                    .unwrap();
-            }
+    #[tokio::test]
+    #[serial]
+    async fn mint() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let token = create_token(&config, &payer).await;
+            let account = create_associated_account(&config, &payer, token).await;
+            let result = process_test_command(
+                &config,
+                &payer,
+                &[
+                    "spl-token",
+                    CommandName::Mint.into(),
+                    &token.to_string(),
+                    "100",
+                ],
+            )
+            .await;
+            result.unwrap();
+            let account = config.rpc_client.get_account(&account).await.unwrap();
+            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
+            assert_eq!(token_account.base.amount, 100);
+            assert_eq!(token_account.base.mint, token);
+            assert_eq!(token_account.base.owner, payer.pubkey());
         }
     }
 
-    async fn create_token(config: &Config<'_>, payer: &Keypair) -> Pubkey {
-        let token = Keypair::new();
-        let token_pubkey = token.pubkey();
-        let bulk_signers: Vec<Box<dyn Signer>> =
-            vec![Box::new(clone_keypair(payer)), Box::new(token)];
-
-        command_create_token(
-            config,
-            TEST_DECIMALS,
-            token_pubkey,
-            payer.pubkey(),
-            false,
-            None,
-            bulk_signers,
-        )
-        .await
-        .unwrap();
-        token_pubkey
+    #[tokio::test]
+    #[serial]
+    async fn balance_after_mint() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let token = create_token(&config, &payer).await;
+            let account = create_associated_account(&config, &payer, token).await;
+            let ui_amount = 100.0;
+            mint_tokens(&config, &payer, token, ui_amount, account).await;
+            let result = process_test_command(
+                &config,
+                &payer,
+                &["spl-token", CommandName::Balance.into(), &token.to_string()],
+            )
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["amount"], format!("{}", ui_amount));
+            assert_eq!(value["uiAmountString"], format!("{}", ui_amount));
+        }
     }
 
-    async fn create_auxiliary_account(
-        config: &Config<'_>,
-        payer: &Keypair,
-        mint: Pubkey,
-    ) -> Pubkey {
-        let auxiliary = Keypair::new();
-        let address = auxiliary.pubkey();
-        let bulk_signers: Vec<Box<dyn Signer>> =
-            vec![Box::new(clone_keypair(payer)), Box::new(auxiliary)];
-        command_create_account(config, mint, payer.pubkey(), Some(address), bulk_signers)
+    #[tokio::test]
+    #[serial]
+    async fn accounts() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let config = test_config(&test_validator, &payer, &program_id);
+            let token1 = create_token(&config, &payer).await;
+            let _account1 = create_associated_account(&config, &payer, token1).await;
+            let token2 = create_token(&config, &payer).await;
+            let _account2 = create_associated_account(&config, &payer, token2).await;
+            let token3 = create_token(&config, &payer).await;
+            let result = process_test_command(
+                &config,
+                &payer,
+                &["spl-token", CommandName::Accounts.into()],
+            )
             .await
             .unwrap();
-        address
+            assert!(result.contains(&token1.to_string()));
+            assert!(result.contains(&token2.to_string()));
+            assert!(!result.contains(&token3.to_string()));
+        }
     }
 
-    async fn create_associated_account(
-        config: &Config<'_>,
-        payer: &Keypair,
-        mint: Pubkey,
-    ) -> Pubkey {
-        let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(payer))];
-        command_create_account(config, mint, payer.pubkey(), None, bulk_signers)
+    #[tokio::test]
+    #[serial]
+    async fn wrap() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        for program_id in [spl_token::id(), spl_token_2022::id()] {
+            let native_mint = native_mint(&program_id).unwrap();
+            let config = test_config(&test_validator, &payer, &program_id);
+            do_create_native_mint(&config, &program_id, &payer).await;
+            let _result = process_test_command(
+                &config,
+                &payer,
+                &["spl-token", CommandName::Wrap.into(), "0.5"],
+            )
             .await
             .unwrap();
-        get_associated_token_address_with_program_id(&payer.pubkey(), &mint, &config.program_id)
-    }
-
-    async fn mint_tokens(
-        config: &Config<'_>,
-        payer: &Keypair,
-        mint: Pubkey,
-        ui_amount: f64,
-        recipient: Pubkey,
-    ) {
-        let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(payer))];
-        command_mint(
-            config,
-            mint,
-            ui_amount,
-            recipient,
-            MintInfo {
-                program_id: config.program_id,
-                address: mint,
-                decimals: TEST_DECIMALS,
-            },
-            payer.pubkey(),
-            false,
-            bulk_signers,
-        )
-        .await
-        .unwrap();
-    }
-
-    async fn process_test_command(
-        config: &Config<'_>,
-        payer: &Keypair,
-        args: &[&str],
-    ) -> CommandResult {
-        let default_decimals = format!("{}", spl_token_2022::native_mint::DECIMALS);
-        let default_program_id = spl_token::id().to_string();
-        let minimum_signers_help = minimum_signers_help_string();
-        let multisig_member_help = multisig_member_help_string();
-
-        let app_matches = app(
-            &default_decimals,
-            &default_program_id,
-            &minimum_signers_help,
-            &multisig_member_help,
-        )
-        .get_matches_from(args);
-        let (sub_command, sub_matches) = app_matches.subcommand();
-        let sub_command = CommandName::from_str(sub_command).unwrap();
-        let matches = sub_matches.unwrap();
-
-        let wallet_manager = None;
-        let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(payer))];
-        process_command(&sub_command, matches, config, wallet_manager, bulk_signers).await
+            let account = get_associated_token_address_with_program_id(
+                &payer.pubkey(),
+                &native_mint,
+                &config.program_id,
+            );
+            let account = config.rpc_client.get_account(&account).await.unwrap();
+            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
+            assert_eq!(token_account.base.mint, native_mint);
+            assert_eq!(token_account.base.owner, payer.pubkey());
+            assert!(token_account.base.is_native());
+        }
     }
 
     #[tokio::test]
     #[serial]
-    async fn create_token_default() {
+    async fn unwrap() {
         let (test_validator, payer) = new_validator_for_test().await;
         for program_id in [spl_token::id(), spl_token_2022::id()] {
             let config = test_config(&test_validator, &payer, &program_id);
+            do_create_native_mint(&config, &program_id, &payer).await;
+            let (signer, account) = new_throwaway_signer();
+            let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(&payer)), signer];
+            command_wrap(&config, 0.5, payer.pubkey(), Some(account), bulk_signers)
+                .await
+                .unwrap();
             let result = process_test_command(
                 &config,
                 &payer,
-                &["spl-token", CommandName::CreateToken.into()],
-            )
-            .await;
-            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
-            let mint =
-                Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
-            let account = config.rpc_client.get_account(&mint).await.unwrap();
-            assert_eq!(account.owner, program_id);
+                &[
+                    "spl-token",
+                    CommandName::Unwrap.into(),
+                    &account.to_string(),
+                ],
+            )
+            .await;
+            result.unwrap();
+            config.rpc_client.get_account(&account).await.unwrap_err();
         }
     }
 
     #[tokio::test]
     #[serial]
-    async fn supply() {
+    async fn transfer() {
         let (test_validator, payer) = new_validator_for_test().await;
         for program_id in [spl_token::id(), spl_token_2022::id()] {
             let config = test_config(&test_validator, &payer, &program_id);
             let token = create_token(&config, &payer).await;
+            let source = create_associated_account(&config, &payer, token).await;
+            let destination = create_auxiliary_account(&config, &payer, token).await;
+            let ui_amount = 100.0;
+            mint_tokens(&config, &payer, token, ui_amount, source).await;
             let result = process_test_command(
                 &config,
                 &payer,
-                &["spl-token", CommandName::Supply.into(), &token.to_string()],
+                &[
+                    "spl-token",
+                    CommandName::Transfer.into(),
+                    &token.to_string(),
+                    "10",
+                    &destination.to_string(),
+                ],
             )
             .await;
-            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
-            assert_eq!(value["amount"], "0");
-            assert_eq!(value["uiAmountString"], "0");
+            result.unwrap();
+
+            let account = config.rpc_client.get_account(&source).await.unwrap();
+            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
+            assert_eq!(token_account.base.amount, 90);
+            let account = config.rpc_client.get_account(&destination).await.unwrap();
+            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
+            assert_eq!(token_account.base.amount, 10);
         }
     }
 
     #[tokio::test]
     #[serial]
-    async fn create_account_default() {
+    async fn transfer_fund_recipient() {
         let (test_validator, payer) = new_validator_for_test().await;
         for program_id in [spl_token::id(), spl_token_2022::id()] {
             let config = test_config(&test_validator, &payer, &program_id);
             let token = create_token(&config, &payer).await;
+            let source = create_associated_account(&config, &payer, token).await;
+            let recipient = Keypair::new().pubkey().to_string();
+            let ui_amount = 100.0;
+            mint_tokens(&config, &payer, token, ui_amount, source).await;
             let result = process_test_command(
                 &config,
                 &payer,
                 &[
                     "spl-token",
-                    CommandName::CreateAccount.into(),
+                    CommandName::Transfer.into(),
+                    "--fund-recipient",
+                    "--allow-unfunded-recipient",
                     &token.to_string(),
+                    "10",
+                    &recipient,
                 ],
             )
             .await;
             result.unwrap();
+
+            let account = config.rpc_client.get_account(&source).await.unwrap();
+            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
+            assert_eq!(token_account.base.amount, 90);
         }
     }
 
     #[tokio::test]
     #[serial]
-    async fn account_info() {
+    async fn transfer_fee_harvest_and_withdraw() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        let program_id = spl_token_2022::id();
+        let config = test_config(&test_validator, &payer, &program_id);
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::CreateToken.into(),
+                "--decimals",
+                "0",
+                "--transfer-fee",
+                "100",
+                "10",
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        let token = Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
+        let source = create_associated_account(&config, &payer, token).await;
+        mint_tokens(&config, &payer, token, 100.0, source).await;
+        let recipient = Keypair::new().pubkey();
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::Transfer.into(),
+                "--fund-recipient",
+                "--allow-unfunded-recipient",
+                &token.to_string(),
+                "100",
+                &recipient.to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::HarvestWithheld.into(),
+                &token.to_string(),
+            ],
+        )
+        .await
+        .unwrap();
+        let account = config.rpc_client.get_account(&token).await.unwrap();
+        let mint = StateWithExtensionsOwned::<Mint>::unpack(account.data).unwrap();
+        let extension = mint.get_extension::<TransferFeeConfig>().unwrap();
+        assert_eq!(u64::from(extension.withheld_amount), 1);
+
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::WithdrawWithheldTokens.into(),
+                &source.to_string(),
+                "--include-mint",
+            ],
+        )
+        .await
+        .unwrap();
+        let account = config.rpc_client.get_account(&source).await.unwrap();
+        let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
+        assert_eq!(token_account.base.amount, 1);
+
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::SetTransferFee.into(),
+                &token.to_string(),
+                "200",
+                "5",
+            ],
+        )
+        .await
+        .unwrap();
+        let account = config.rpc_client.get_account(&token).await.unwrap();
+        let mint = StateWithExtensionsOwned::<Mint>::unpack(account.data).unwrap();
+        let extension = mint.get_extension::<TransferFeeConfig>().unwrap();
+        assert_eq!(
+            u16::from(extension.newer_transfer_fee.transfer_fee_basis_points),
+            200
+        );
+        assert_eq!(u64::from(extension.newer_transfer_fee.maximum_fee), 5);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn confidential_deposit_transfer_and_withdraw() {
+        let (test_validator, payer) = new_validator_for_test().await;
+        let program_id = spl_token_2022::id();
+        let config = test_config(&test_validator, &payer, &program_id);
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::CreateToken.into(),
+                "--decimals",
+                "0",
+                "--enable-confidential-transfers",
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        let token = Pubkey::from_str(value["commandOutput"]["address"].as_str().unwrap()).unwrap();
+        let account = create_associated_account(&config, &payer, token).await;
+        mint_tokens(&config, &payer, token, 200_000.0, account).await;
+
+        // deposit enough to use the high bits of the pending balance
+        for args in [
+            vec![CommandName::ConfigureConfidentialAccount.into()],
+            vec![CommandName::DepositConfidential.into(), "100000"],
+            vec![CommandName::ApplyPendingBalance.into()],
+        ] {
+            let token = token.to_string();
+            let mut command = vec!["spl-token", args[0], &token];
+            command.extend_from_slice(&args[1..]);
+            process_test_command(&config, &payer, &command)
+                .await
+                .unwrap();
+        }
+
+        let account_data = config.rpc_client.get_account(&account).await.unwrap();
+        let token_account = StateWithExtensionsOwned::<Account>::unpack(account_data.data).unwrap();
+        assert_eq!(token_account.base.amount, 100_000);
+        let extension = token_account
+            .get_extension::<ConfidentialTransferAccount>()
+            .unwrap();
+        assert_eq!(
+            u64::from(extension.maximum_pending_balance_credit_counter),
+            DEFAULT_MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER
+        );
+
+        let value = show_confidential_balance(&config, &payer, token, account).await;
+        assert_eq!(value["availableBalance"], "100000");
+        assert_eq!(value["pendingBalance"], "0");
+
+        // transfer to another configured account, landing in its pending
+        // balance until applied
+        let recipient = create_auxiliary_account(&config, &payer, token).await;
+        let recipient_address = recipient.to_string();
+        for args in [
+            vec![
+                CommandName::ConfigureConfidentialAccount.into(),
+                "--address",
+                recipient_address.as_str(),
+            ],
+            vec![
+                CommandName::ConfidentialTransfer.into(),
+                "70000",
+                recipient_address.as_str(),
+            ],
+        ] {
+            let token = token.to_string();
+            let mut command = vec!["spl-token", args[0], &token];
+            command.extend_from_slice(&args[1..]);
+            process_test_command(&config, &payer, &command)
+                .await
+                .unwrap();
+        }
+        let value = show_confidential_balance(&config, &payer, token, account).await;
+        assert_eq!(value["availableBalance"], "30000");
+        let value = show_confidential_balance(&config, &payer, token, recipient).await;
+        assert_eq!(value["availableBalance"], "0");
+        assert_eq!(value["pendingBalance"], "70000");
+
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::ApplyPendingBalance.into(),
+                &token.to_string(),
+                "--address",
+                &recipient_address,
+            ],
+        )
+        .await
+        .unwrap();
+        let value = show_confidential_balance(&config, &payer, token, recipient).await;
+        assert_eq!(value["availableBalance"], "70000");
+        assert_eq!(value["pendingBalance"], "0");
+
+        // withdraw the rest of the sender's confidential balance
+        process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::WithdrawConfidential.into(),
+                &token.to_string(),
+                "30000",
+            ],
+        )
+        .await
+        .unwrap();
+        let value = show_confidential_balance(&config, &payer, token, account).await;
+        assert_eq!(value["availableBalance"], "0");
+        let account_data = config.rpc_client.get_account(&account).await.unwrap();
+        let token_account = StateWithExtensionsOwned::<Account>::unpack(account_data.data).unwrap();
+        assert_eq!(token_account.base.amount, 130_000);
+    }
+
+    #[tokio::test]
+    #[serial]
+    async fn distribute() {
         let (test_validator, payer) = new_validator_for_test().await;
         for program_id in [spl_token::id(), spl_token_2022::id()] {
             let config = test_config(&test_validator, &payer, &program_id);
             let token = create_token(&config, &payer).await;
-            let _account = create_associated_account(&config, &payer, token).await;
+            let source = create_associated_account(&config, &payer, token).await;
+            mint_tokens(&config, &payer, token, 100.0, source).await;
+            let token_account = create_auxiliary_account(&config, &payer, token).await;
+            let wallet_a = Pubkey::new_unique();
+            let wallet_b = Pubkey::new_unique();
+
+            let dir = tempfile::tempdir().unwrap();
+            let csv_path = dir.path().join("recipients.csv");
+            std::fs::write(
+                &csv_path,
+                format!(
+                    "recipient,amount,memo\n{},10,first\n{},20\n{},5\n{},1,\"second, again\"\n",
+                    wallet_a, wallet_b, token_account, wallet_a
+                ),
+            )
+            .unwrap();
+            let csv_path = csv_path.to_str().unwrap();
+            let token = token.to_string();
+
             let result = process_test_command(
                 &config,
                 &payer,
                 &[
                     "spl-token",
-                    CommandName::AccountInfo.into(),
-                    &token.to_string(),
+                    CommandName::Distribute.into(),
+                    &token,
+                    csv_path,
+                    "--dry-run",
                 ],
             )
             .await;
             let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
-            let account = get_associated_token_address_with_program_id(
-                &payer.pubkey(),
-                &token,
-                &config.program_id,
-            );
-            assert_eq!(value["address"], account.to_string());
-            assert_eq!(value["mint"], token.to_string());
-            assert_eq!(value["isAssociated"], true);
-            assert_eq!(value["isNative"], false);
-            assert_eq!(value["owner"], payer.pubkey().to_string());
-            assert_eq!(value["state"], "initialized");
+            assert_eq!(value["transfers"], 4);
+            assert_eq!(value["uiAmount"], "36");
+            assert_eq!(value["accountsCreated"], 2);
+            let ui_account = config
+                .rpc_client
+                .get_token_account(&source)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(ui_account.token_amount.amount, "100");
+
+            let result = process_test_command(
+                &config,
+                &payer,
+                &[
+                    "spl-token",
+                    CommandName::Distribute.into(),
+                    &token,
+                    csv_path,
+                    "--batch-size",
+                    "2",
+                ],
+            )
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["transactions"], 2);
+            assert_eq!(value["signatures"].as_array().unwrap().len(), 2);
+
+            let mint = Pubkey::from_str(&token).unwrap();
+            for (address, amount) in [
+                (
+                    get_associated_token_address_with_program_id(&wallet_a, &mint, &program_id),
+                    "11",
+                ),
+                (
+                    get_associated_token_address_with_program_id(&wallet_b, &mint, &program_id),
+                    "20",
+                ),
+                (token_account, "5"),
+                (source, "64"),
+            ] {
+                let ui_account = config
+                    .rpc_client
+                    .get_token_account(&address)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(ui_account.token_amount.amount, amount);
+            }
+
+            // Running again must not pay anyone twice
+            let result = process_test_command(
+                &config,
+                &payer,
+                &[
+                    "spl-token",
+                    CommandName::Distribute.into(),
+                    &token,
+                    csv_path,
+                ],
+            )
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["previouslyDistributed"], 4);
+            assert_eq!(value["transfers"], 0);
+            let ui_account = config
+                .rpc_client
+                .get_token_account(&source)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(ui_account.token_amount.amount, "64");
         }
     }
 
     #[tokio::test]
     #[serial]
-    async fn balance() {
+    async fn distribute_resolves_pending_transfers() {
         let (test_validator, payer) = new_validator_for_test().await;
         for program_id in [spl_token::id(), spl_token_2022::id()] {
             let config = test_config(&test_validator, &payer, &program_id);
             let token = create_token(&config, &payer).await;
-            let _account = create_associated_account(&config, &payer, token).await;
-            let result = process_test_command(
-                &config,
-                &payer,
-                &["spl-token", CommandName::Balance.into(), &token.to_string()],
+            let source = create_associated_account(&config, &payer, token).await;
+            mint_tokens(&config, &payer, token, 100.0, source).await;
+            let landed_wallet = Pubkey::new_unique();
+            let expired_wallet = Pubkey::new_unique();
+            let failed_wallet = Pubkey::new_unique();
+            let landed_address =
+                get_associated_token_address_with_program_id(&landed_wallet, &token, &program_id);
+
+            // A previous run sent the first transfer, which landed...
+            let blockhash = config.rpc_client.get_latest_blockhash().await.unwrap();
+            let transaction = Transaction::new_signed_with_payer(
+                &[
+                    create_associated_token_account(
+                        &payer.pubkey(),
+                        &landed_wallet,
+                        &token,
+                        &program_id,
+                    ),
+                    transfer_checked(
+                        &program_id,
+                        &source,
+                        &token,
+                        &landed_address,
+                        &payer.pubkey(),
+                        &[],
+                        10,
+                        TEST_DECIMALS,
+                    )
+                    .unwrap(),
+                ],
+                Some(&payer.pubkey()),
+                &[&payer],
+                blockhash,
+            );
+            let landed_signature = config
+                .rpc_client
+                .send_and_confirm_transaction(&transaction)
+                .await
+                .unwrap();
+
+            // ...and the last one, which failed on chain
+            let transaction = Transaction::new_signed_with_payer(
+                &[transfer_checked(
+                    &program_id,
+                    &source,
+                    &token,
+                    &landed_address,
+                    &payer.pubkey(),
+                    &[],
+                    1_000,
+                    TEST_DECIMALS,
+                )
+                .unwrap()],
+                Some(&payer.pubkey()),
+                &[&payer],
+                blockhash,
+            );
+            let failed_signature = config
+                .rpc_client
+                .send_transaction_with_config(
+                    &transaction,
+                    solana_client::rpc_config::RpcSendTransactionConfig {
+                        skip_preflight: true,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+            loop {
+                let status = config
+                    .rpc_client
+                    .get_signature_status(&failed_signature)
+                    .await
+                    .unwrap();
+                if let Some(status) = status {
+                    assert!(status.is_err());
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+
+            // The run was interrupted before it recorded any outcome, and the
+            // middle transfer's transaction expired without landing
+            let dir = tempfile::tempdir().unwrap();
+            let csv_path = dir.path().join("recipients.csv");
+            std::fs::write(
+                &csv_path,
+                format!(
+                    "recipient,amount\n{},10\n{},20\n{},5\n",
+                    landed_wallet, expired_wallet, failed_wallet
+                ),
             )
-            .await;
-            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
-            assert_eq!(value["amount"], "0");
-            assert_eq!(value["uiAmountString"], "0");
-        }
-    }
+            .unwrap();
+            let state_path = dir.path().join("recipients.csv.state.json");
+            let pending_record = |wallet: &Pubkey, amount: u64, signature: String, height: u64| {
+                serde_json::json!({
+                    "recipient": wallet.to_string(),
+                    "amount": amount,
+                    "signature": signature,
+                    "pendingUntilBlockHeight": height,
+                })
+            };
+            std::fs::write(
+                &state_path,
+                serde_json::json!({
+                    "mint": token.to_string(),
+                    "source": source.to_string(),
+                    "transfers": {
+                        "2": pending_record(
+                            &landed_wallet,
+                            10,
+                            landed_signature.to_string(),
+                            u64::MAX,
+                        ),
+                        "3": pending_record(
+                            &expired_wallet,
+                            20,
+                            Signature::new_unique().to_string(),
+                            0,
+                        ),
+                        "4": pending_record(
+                            &failed_wallet,
+                            5,
+                            failed_signature.to_string(),
+                            u64::MAX,
+                        ),
+                    },
+                })
+                .to_string(),
+            )
+            .unwrap();
 
-    #[tokio::test]
-    #[serial]
-    async fn mint() {
-        let (test_validator, payer) = new_validator_for_test().await;
-        for program_id in [spl_token::id(), spl_token_2022::id()] {
-            let config = test_config(&test_validator, &payer, &program_id);
-            let token = create_token(&config, &payer).await;
-            let account = create_associated_account(&config, &payer, token).await;
             let result = process_test_command(
                 &config,
                 &payer,
                 &[
                     "spl-token",
-                    CommandName::Mint.into(),
+                    CommandName::Distribute.into(),
                     &token.to_string(),
-                    "100",
+                    csv_path.to_str().unwrap(),
                 ],
             )
             .await;
-            result.unwrap();
-            let account = config.rpc_client.get_account(&account).await.unwrap();
-            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
-            assert_eq!(token_account.base.amount, 100);
-            assert_eq!(token_account.base.mint, token);
-            assert_eq!(token_account.base.owner, payer.pubkey());
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["previouslyDistributed"], 1);
+            assert_eq!(value["transfers"], 2);
+            assert_eq!(value["uiAmount"], "25");
+
+            // The landed transfer is kept, the expired and failed ones are
+            // sent again, and nobody is paid twice
+            for (address, amount) in [
+                (landed_address, "10"),
+                (
+                    get_associated_token_address_with_program_id(
+                        &expired_wallet,
+                        &token,
+                        &program_id,
+                    ),
+                    "20",
+                ),
+                (
+                    get_associated_token_address_with_program_id(
+                        &failed_wallet,
+                        &token,
+                        &program_id,
+                    ),
+                    "5",
+                ),
+                (source, "65"),
+            ] {
+                let ui_account = config
+                    .rpc_client
+                    .get_token_account(&address)
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(ui_account.token_amount.amount, amount);
+            }
+            let state: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(&state_path).unwrap()).unwrap();
+            assert_eq!(
+                state["transfers"]["2"]["signature"],
+                landed_signature.to_string()
+            );
+            for line in ["2", "3", "4"] {
+                assert!(state["transfers"][line]
+                    .get("pendingUntilBlockHeight")
+                    .is_none());
+            }
         }
     }
 
     #[tokio::test]
     #[serial]
-    async fn balance_after_mint() {
+    async fn apply() {
         let (test_validator, payer) = new_validator_for_test().await;
-        for program_id in [spl_token::id(), spl_token_2022::id()] {
-            let config = test_config(&test_validator, &payer, &program_id);
-            let token = create_token(&config, &payer).await;
-            let account = create_associated_account(&config, &payer, token).await;
-            let ui_amount = 100.0;
-            mint_tokens(&config, &payer, token, ui_amount, account).await;
-            let result = process_test_command(
-                &config,
-                &payer,
-                &["spl-token", CommandName::Balance.into(), &token.to_string()],
+        let config = test_config(&test_validator, &payer, &spl_token_2022::id());
+        let mint = Keypair::new();
+        let holder = Pubkey::new_unique();
+
+        let dir = tempfile::tempdir().unwrap();
+        let keypair_path = dir.path().join("mint.json");
+        write_keypair_file(&mint, &keypair_path).unwrap();
+        let keypair_path = keypair_path.to_str().unwrap();
+        let spec_path = dir.path().join("mint.toml");
+        let write_spec = |basis_points: u16, rate: i16, mint_authority: &str| {
+            std::fs::write(
+                &spec_path,
+                format!(
+                    "address = \"{}\"\n\
+                     decimals = 0\n\
+                     [authorities]\n\
+                     mint = \"{}\"\n\
+                     freeze = \"none\"\n\
+                     [extensions.transfer-fee]\n\
+                     basis-points = {}\n\
+                     maximum-fee = 10.0\n\
+                     [extensions.interest-bearing]\n\
+                     rate = {}\n\
+                     [[distribution]]\n\
+                     recipient = \"{}\"\n\
+                     amount = 100.0\n",
+                    mint.pubkey(),
+                    mint_authority,
+                    basis_points,
+                    rate,
+                    holder
+                ),
             )
-            .await;
-            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
-            assert_eq!(value["amount"], format!("{}", ui_amount));
-            assert_eq!(value["uiAmountString"], format!("{}", ui_amount));
-        }
+            .unwrap();
+        };
+        let spec_file = spec_path.to_str().unwrap();
+        let payer_pubkey = payer.pubkey().to_string();
+
+        // missing mints are created with their initial supply
+        write_spec(100, 5, &payer_pubkey);
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::Apply.into(),
+                "-f",
+                spec_file,
+                "--mint-keypair",
+                keypair_path,
+                "--yes",
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        assert_eq!(value["create"], true);
+        assert_eq!(value["changes"].as_array().unwrap().len(), 2);
+        let account = get_associated_token_address_with_program_id(
+            &holder,
+            &mint.pubkey(),
+            &config.program_id,
+        );
+        let ui_account = config
+            .rpc_client
+            .get_token_account(&account)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ui_account.token_amount.amount, "100");
+
+        // existing mints only get the instructions for what differs
+        write_spec(200, -5, "none");
+        let args = [
+            "spl-token",
+            CommandName::Apply.into(),
+            "-f",
+            spec_file,
+            "--yes",
+        ];
+        let result =
+            process_test_command(&config, &payer, &[args.as_slice(), &["--dry-run"]].concat())
+                .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        assert_eq!(value["create"], false);
+        assert_eq!(value["changes"].as_array().unwrap().len(), 3);
+        assert!(value["signatures"].is_null());
+
+        process_test_command(&config, &payer, &args).await.unwrap();
+        let data = config
+            .rpc_client
+            .get_account(&mint.pubkey())
+            .await
+            .unwrap()
+            .data;
+        let state = StateWithExtensionsOwned::<Mint>::unpack(data).unwrap();
+        assert_eq!(state.base.mint_authority, COption::None);
+        assert_eq!(state.base.supply, 100);
+        let extension = state.get_extension::<TransferFeeConfig>().unwrap();
+        assert_eq!(
+            u16::from(extension.newer_transfer_fee.transfer_fee_basis_points),
+            200
+        );
+        let extension = state.get_extension::<InterestBearingConfig>().unwrap();
+        assert_eq!(i16::from(extension.current_rate), -5);
+
+        let result = process_test_command(&config, &payer, &args).await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        assert!(value["changes"].as_array().unwrap().is_empty());
     }
 
     #[tokio::test]
     #[serial]
-    async fn accounts() {
+    async fn holders() {
         let (test_validator, payer) = new_validator_for_test().await;
         for program_id in [spl_token::id(), spl_token_2022::id()] {
             let config = test_config(&test_validator, &payer, &program_id);
-            let token1 = create_token(&config, &payer).await;
-            let _account1 = create_associated_account(&config, &payer, token1).await;
-            let token2 = create_token(&config, &payer).await;
-            let _account2 = create_associated_account(&config, &payer, token2).await;
-            let token3 = create_token(&config, &payer).await;
-            let result = process_test_command(
+            let token = create_token(&config, &payer).await;
+            let source = create_associated_account(&config, &payer, token).await;
+            let aux = create_auxiliary_account(&config, &payer, token).await;
+            mint_tokens(&config, &payer, token, 100.0, source).await;
+            mint_tokens(&config, &payer, token, 5.0, aux).await;
+            let recipient = Keypair::new().pubkey();
+            process_test_command(
                 &config,
                 &payer,
-                &["spl-token", CommandName::Accounts.into()],
+                &[
+                    "spl-token",
+                    CommandName::Transfer.into(),
+                    "--fund-recipient",
+                    "--allow-unfunded-recipient",
+                    &token.to_string(),
+                    "20",
+                    &recipient.to_string(),
+                ],
             )
             .await
             .unwrap();
-            assert!(result.contains(&token1.to_string()));
-            assert!(result.contains(&token2.to_string()));
-            assert!(!result.contains(&token3.to_string()));
-        }
-    }
 
-    #[tokio::test]
-    #[serial]
-    async fn wrap() {
-        let (test_validator, payer) = new_validator_for_test().await;
-        for program_id in [spl_token::id(), spl_token_2022::id()] {
-            let native_mint = native_mint(&program_id).unwrap();
-            let config = test_config(&test_validator, &payer, &program_id);
-            do_create_native_mint(&config, &program_id, &payer).await;
-            let _result = process_test_command(
+            let token = token.to_string();
+            let result = process_test_command(
                 &config,
                 &payer,
-                &["spl-token", CommandName::Wrap.into(), "0.5"],
+                &["spl-token", CommandName::Holders.into(), &token],
             )
-            .await
-            .unwrap();
-            let account = get_associated_token_address_with_program_id(
-                &payer.pubkey(),
-                &native_mint,
-                &config.program_id,
-            );
-            let account = config.rpc_client.get_account(&account).await.unwrap();
-            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
-            assert_eq!(token_account.base.mint, native_mint);
-            assert_eq!(token_account.base.owner, payer.pubkey());
-            assert!(token_account.base.is_native());
-        }
-    }
+            .await;
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["uiTotalAmount"], "105");
+            let accounts = value["accounts"].as_array().unwrap();
+            assert_eq!(accounts.len(), 3);
+            assert_eq!(accounts[0]["address"], source.to_string());
+            assert_eq!(accounts[0]["uiAmount"], "80");
+            assert_eq!(accounts[0]["isAssociated"], true);
+            assert_eq!(accounts[2]["address"], aux.to_string());
+            assert_eq!(accounts[2]["state"], "initialized");
 
-    #[tokio::test]
-    #[serial]
-    async fn unwrap() {
-        let (test_validator, payer) = new_validator_for_test().await;
-        for program_id in [spl_token::id(), spl_token_2022::id()] {
-            let config = test_config(&test_validator, &payer, &program_id);
-            do_create_native_mint(&config, &program_id, &payer).await;
-            let (signer, account) = new_throwaway_signer();
-            let bulk_signers: Vec<Box<dyn Signer>> = vec![Box::new(clone_keypair(&payer)), signer];
-            command_wrap(&config, 0.5, payer.pubkey(), Some(account), bulk_signers)
-                .await
-                .unwrap();
             let result = process_test_command(
                 &config,
                 &payer,
                 &[
                     "spl-token",
-                    CommandName::Unwrap.into(),
-                    &account.to_string(),
+                    CommandName::Holders.into(),
+                    &token,
+                    "--min-balance",
+                    "10",
                 ],
             )
             .await;
-            result.unwrap();
-            config.rpc_client.get_account(&account).await.unwrap_err();
-        }
-    }
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["accounts"].as_array().unwrap().len(), 2);
 
-    #[tokio::test]
-    #[serial]
-    async fn transfer() {
-        let (test_validator, payer) = new_validator_for_test().await;
-        for program_id in [spl_token::id(), spl_token_2022::id()] {
-            let config = test_config(&test_validator, &payer, &program_id);
-            let token = create_token(&config, &payer).await;
-            let source = create_associated_account(&config, &payer, token).await;
-            let destination = create_auxiliary_account(&config, &payer, token).await;
-            let ui_amount = 100.0;
-            mint_tokens(&config, &payer, token, ui_amount, source).await;
             let result = process_test_command(
                 &config,
                 &payer,
                 &[
                     "spl-token",
-                    CommandName::Transfer.into(),
-                    &token.to_string(),
-                    "10",
-                    &destination.to_string(),
+                    CommandName::Holders.into(),
+                    &token,
+                    "--by-owner",
                 ],
             )
             .await;
-            result.unwrap();
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            let owners = value["owners"].as_array().unwrap();
+            assert_eq!(owners.len(), 2);
+            assert_eq!(owners[0]["owner"], payer.pubkey().to_string());
+            assert_eq!(owners[0]["uiAmount"], "85");
+            assert_eq!(owners[0]["accounts"], 2);
+            assert_eq!(owners[1]["owner"], recipient.to_string());
 
-            let account = config.rpc_client.get_account(&source).await.unwrap();
-            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
-            assert_eq!(token_account.base.amount, 90);
-            let account = config.rpc_client.get_account(&destination).await.unwrap();
-            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
-            assert_eq!(token_account.base.amount, 10);
+            let result = process_test_command(
+                &config,
+                &payer,
+                &[
+                    "spl-token",
+                    CommandName::Holders.into(),
+                    &token,
+                    "--by-owner",
+                    "--min-balance",
+                    "50",
+                    "--csv",
+                ],
+            )
+            .await;
+            assert_eq!(
+                result.unwrap(),
+                format!("owner,amount,accounts\n{},85,2\n", payer.pubkey())
+            );
         }
     }
 
     #[tokio::test]
     #[serial]
-    async fn transfer_fund_recipient() {
+    async fn multisig_propose_sign_broadcast() {
         let (test_validator, payer) = new_validator_for_test().await;
-        for program_id in [spl_token::id(), spl_token_2022::id()] {
-            let config = test_config(&test_validator, &payer, &program_id);
-            let token = create_token(&config, &payer).await;
-            let source = create_associated_account(&config, &payer, token).await;
-            let recipient = Keypair::new().pubkey().to_string();
-            let ui_amount = 100.0;
-            mint_tokens(&config, &payer, token, ui_amount, source).await;
+        let config = test_config(&test_validator, &payer, &spl_token::id());
+        let token = create_token(&config, &payer).await;
+        let recipient = create_associated_account(&config, &payer, token).await;
+
+        let members = [Keypair::new(), Keypair::new(), Keypair::new()];
+        let multisig = Keypair::new();
+        let multisig_pubkey = multisig.pubkey();
+        command_create_multisig(
+            &config,
+            multisig_pubkey,
+            2,
+            members.iter().map(|member| member.pubkey()).collect(),
+            vec![Box::new(clone_keypair(&payer)), Box::new(multisig)],
+        )
+        .await
+        .unwrap();
+        command_create_account(
+            &config,
+            token,
+            multisig_pubkey,
+            None,
+            vec![Box::new(clone_keypair(&payer))],
+        )
+        .await
+        .unwrap();
+        let source = get_associated_token_address_with_program_id(
+            &multisig_pubkey,
+            &token,
+            &config.program_id,
+        );
+        mint_tokens(&config, &payer, token, 100.0, source).await;
+
+        let nonce = Keypair::new();
+        let nonce_rent = config
+            .rpc_client
+            .get_minimum_balance_for_rent_exemption(solana_sdk::nonce::State::size())
+            .await
+            .unwrap();
+        let mut transaction = Transaction::new_with_payer(
+            &system_instruction::create_nonce_account(
+                &payer.pubkey(),
+                &nonce.pubkey(),
+                &payer.pubkey(),
+                nonce_rent,
+            ),
+            Some(&payer.pubkey()),
+        );
+        let blockhash = config.rpc_client.get_latest_blockhash().await.unwrap();
+        transaction.sign(&[&payer, &nonce], blockhash);
+        config
+            .rpc_client
+            .send_and_confirm_transaction(&transaction)
+            .await
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let proposal = dir.path().join("proposal.json");
+        let proposal = proposal.to_str().unwrap();
+        let result = process_test_command(
+            &config,
+            &payer,
+            &[
+                "spl-token",
+                CommandName::Multisig.into(),
+                "propose",
+                proposal,
+                CommandName::Transfer.into(),
+                &token.to_string(),
+                "10",
+                &recipient.to_string(),
+                "--owner",
+                &multisig_pubkey.to_string(),
+                "--multisig-signer",
+                &members[0].pubkey().to_string(),
+                "--multisig-signer",
+                &members[1].pubkey().to_string(),
+                "--mint-decimals",
+                "0",
+                "--nonce",
+                &nonce.pubkey().to_string(),
+            ],
+        )
+        .await;
+        let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+        assert_eq!(value["multisig"], multisig_pubkey.to_string());
+        assert_eq!(value["minimumSigners"], 2);
+        assert_eq!(
+            value["signed"],
+            serde_json::json!([payer.pubkey().to_string()])
+        );
+        assert_eq!(value["ready"], false);
+
+        let broadcast = [
+            "spl-token",
+            CommandName::Multisig.into(),
+            "broadcast",
+            proposal,
+        ];
+        process_test_command(&config, &payer, &broadcast)
+            .await
+            .unwrap_err();
+
+        for (i, member) in members[..2].iter().enumerate() {
+            let file = NamedTempFile::new().unwrap();
+            write_keypair_file(member, &file).unwrap();
             let result = process_test_command(
                 &config,
                 &payer,
                 &[
                     "spl-token",
-                    CommandName::Transfer.into(),
-                    "--fund-recipient",
-                    "--allow-unfunded-recipient",
-                    &token.to_string(),
-                    "10",
-                    &recipient,
+                    CommandName::Multisig.into(),
+                    "sign",
+                    proposal,
+                    file.path().to_str().unwrap(),
                 ],
             )
             .await;
-            result.unwrap();
-
-            let account = config.rpc_client.get_account(&source).await.unwrap();
-            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data).unwrap();
-            assert_eq!(token_account.base.amount, 90);
+            let value: serde_json::Value = serde_json::from_str(&result.unwrap()).unwrap();
+            assert_eq!(value["ready"], i == 1);
         }
+
+        process_test_command(&config, &payer, &broadcast)
+            .await
+            .unwrap();
+        let ui_account = config
+            .rpc_client
+            .get_token_account(&recipient)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(ui_account.token_amount.amount, "10");
     }
 
     #[tokio::test]
@@ -0,0 +1,494 @@
+/// The `multisig` subcommand
+use {
+    crate::{
+        app,
+        config::Config,
+        minimum_signers_help_string, multisig_member_help_string,
+        output::{println_display, CliMultisigProposal},
+        process_command, signers_of, CommandName, CommandResult, Error, MULTISIG_SIGNER_ARG,
+    },
+    clap::{App, AppSettings, Arg, ArgMatches, SubCommand},
+    serde::{Deserialize, Serialize},
+    solana_clap_utils::{
+        input_parsers::{pubkey_of, pubkey_of_signer},
+        input_validators::is_valid_signer,
+        nonce::{NONCE_ARG, NONCE_AUTHORITY_ARG},
+    },
+    solana_cli_output::{CliSignature, OutputFormat},
+    solana_client::nonce_utils,
+    solana_remote_wallet::remote_wallet::RemoteWalletManager,
+    solana_sdk::{
+        hash::Hash,
+        message::Message,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        signature::{Signature, Signer},
+        transaction::Transaction,
+    },
+    spl_token_2022::{instruction::TokenInstruction, state::Multisig},
+    std::{
+        collections::BTreeMap, fs, future::Future, path::Path, pin::Pin, str::FromStr, sync::Arc,
+    },
+};
+
+pub(crate) trait MultisigSubCommand {
+    fn multisig_subcommand(self) -> Self;
+}
+
+impl MultisigSubCommand for App<'_, '_> {
+    fn multisig_subcommand(self) -> Self {
+        self.subcommand(
+            SubCommand::with_name(CommandName::Multisig.into())
+                .about("Collect multisig signatures for a transaction offline")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("propose")
+                        .about(
+                            "Write the unsigned transaction of a token command to a proposal file",
+                        )
+                        .setting(AppSettings::TrailingVarArg)
+                        .arg(
+                            Arg::with_name("proposal_file")
+                                .value_name("PROPOSAL_FILE")
+                                .takes_value(true)
+                                .index(1)
+                                .required(true)
+                                .help("Path of the proposal file to create"),
+                        )
+                        .arg(
+                            Arg::with_name("command")
+                                .value_name("COMMAND")
+                                .takes_value(true)
+                                .index(2)
+                                .required(true)
+                                .multiple(true)
+                                .allow_hyphen_values(true)
+                                .help(
+                                    "The token command to propose, with its arguments. \
+                                     The command must name its multisig signers with \
+                                     --multisig-signer and use a durable nonce with --nonce, \
+                                     the nonce's blockhash is filled in automatically",
+                                ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("sign")
+                        .about("Add a signature to a proposal file")
+                        .arg(
+                            Arg::with_name("proposal_file")
+                                .value_name("PROPOSAL_FILE")
+                                .takes_value(true)
+                                .index(1)
+                                .required(true)
+                                .help("Path of the proposal file"),
+                        )
+                        .arg(
+                            Arg::with_name("signer")
+                                .value_name("SIGNER_KEYPAIR")
+                                .validator(is_valid_signer)
+                                .takes_value(true)
+                                .index(2)
+                                .help(
+                                    "Specify the signer keypair. \
+                                     This may be a keypair file or the ASK keyword. \
+                                     [default: the client keypair]",
+                                ),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("broadcast")
+                        .about("Send a proposal once it has collected every signature")
+                        .arg(
+                            Arg::with_name("proposal_file")
+                                .value_name("PROPOSAL_FILE")
+                                .takes_value(true)
+                                .index(1)
+                                .required(true)
+                                .help("Path of the proposal file"),
+                        ),
+                ),
+        )
+    }
+}
+
+pub(crate) async fn multisig_process_command(
+    matches: &ArgMatches<'_>,
+    config: &Config<'_>,
+    signers: Vec<Box<dyn Signer>>,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+) -> CommandResult {
+    match matches.subcommand() {
+        ("propose", Some(arg_matches)) => {
+            let proposal_file = Path::new(arg_matches.value_of("proposal_file").unwrap());
+            let command = arg_matches
+                .values_of("command")
+                .unwrap()
+                .collect::<Vec<_>>();
+            command_propose(config, proposal_file, command, signers, wallet_manager).await
+        }
+        ("sign", Some(arg_matches)) => {
+            let proposal_file = Path::new(arg_matches.value_of("proposal_file").unwrap());
+            let (signer, _) = config.signer_or_default(arg_matches, "signer", wallet_manager);
+            command_sign(config, proposal_file, signer)
+        }
+        ("broadcast", Some(arg_matches)) => {
+            let proposal_file = Path::new(arg_matches.value_of("proposal_file").unwrap());
+            command_broadcast(config, proposal_file).await
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Contents of a proposal file
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MultisigProposal {
+    multisig: String,
+    minimum_signers: u8,
+    members: Vec<String>,
+    /// Base64-encoded transaction message
+    message: String,
+    /// Signatures collected so far, by signer address
+    signatures: BTreeMap<String, String>,
+}
+
+impl MultisigProposal {
+    fn load(path: &Path) -> Result<Self, Error> {
+        let data =
+            fs::read(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        serde_json::from_slice(&data)
+            .map_err(|err| format!("Invalid proposal file {}: {}", path.display(), err).into())
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn message_data(&self) -> Result<Vec<u8>, Error> {
+        base64::decode(&self.message).map_err(|err| format!("Invalid message: {}", err).into())
+    }
+
+    fn message(&self) -> Result<Message, Error> {
+        bincode::deserialize(&self.message_data()?)
+            .map_err(|err| format!("Invalid message: {}", err).into())
+    }
+
+    // Signatures that are valid for the proposed message, in the order the
+    // message expects them
+    fn verified_signatures(
+        &self,
+        message: &Message,
+    ) -> Result<Vec<(Pubkey, Option<Signature>)>, Error> {
+        let message_data = self.message_data()?;
+        message.account_keys[..message.header.num_required_signatures as usize]
+            .iter()
+            .map(|signer| {
+                let signature = match self.signatures.get(&signer.to_string()) {
+                    Some(signature) => {
+                        let signature = Signature::from_str(signature)
+                            .map_err(|err| format!("Invalid signature for {}: {}", signer, err))?;
+                        if !signature.verify(signer.as_ref(), &message_data) {
+                            return Err(format!("Invalid signature for {}", signer).into());
+                        }
+                        Some(signature)
+                    }
+                    None => None,
+                };
+                Ok((*signer, signature))
+            })
+            .collect()
+    }
+
+    fn status(&self, path: &Path) -> Result<CliMultisigProposal, Error> {
+        let message = self.message()?;
+        let signatures = self.verified_signatures(&message)?;
+        let signed_members = signatures
+            .iter()
+            .filter(|(signer, signature)| {
+                signature.is_some() && self.members.contains(&signer.to_string())
+            })
+            .count();
+        let (signed, missing): (Vec<_>, Vec<_>) = signatures
+            .iter()
+            .partition(|(_, signature)| signature.is_some());
+        Ok(CliMultisigProposal {
+            proposal_file: path.display().to_string(),
+            multisig: self.multisig.clone(),
+            minimum_signers: self.minimum_signers,
+            instructions: describe_instructions(&message),
+            signed: signed
+                .iter()
+                .map(|(signer, _)| signer.to_string())
+                .collect(),
+            missing: missing
+                .iter()
+                .map(|(signer, _)| signer.to_string())
+                .collect(),
+            ready: missing.is_empty() && signed_members >= self.minimum_signers as usize,
+        })
+    }
+}
+
+fn describe_instructions(message: &Message) -> Vec<String> {
+    message
+        .instructions
+        .iter()
+        .map(|instruction| {
+            let program_id = message.account_keys[instruction.program_id_index as usize];
+            let accounts = instruction
+                .accounts
+                .iter()
+                .map(|index| message.account_keys[*index as usize].to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            if program_id == spl_token::id() || program_id == spl_token_2022::id() {
+                match TokenInstruction::unpack(&instruction.data) {
+                    Ok(token_instruction) => format!("{:?} [{}]", token_instruction, accounts),
+                    Err(_) => format!("{}: invalid instruction [{}]", program_id, accounts),
+                }
+            } else {
+                format!("{} [{}]", program_id, accounts)
+            }
+        })
+        .collect()
+}
+
+async fn get_multisig(config: &Config<'_>, message: &Message) -> Result<(Pubkey, Multisig), Error> {
+    let accounts = config
+        .rpc_client
+        .get_multiple_accounts(&message.account_keys)
+        .await?;
+    message
+        .account_keys
+        .iter()
+        .zip(accounts)
+        .find_map(|(address, account)| {
+            account
+                .filter(|account| {
+                    account.owner == spl_token::id() || account.owner == spl_token_2022::id()
+                })
+                .and_then(|account| Multisig::unpack(&account.data).ok())
+                .map(|multisig| (*address, multisig))
+        })
+        .ok_or_else(|| "The proposed transaction does not use a multisig account".into())
+}
+
+async fn command_propose(
+    config: &Config<'_>,
+    proposal_file: &Path,
+    command: Vec<&str>,
+    mut bulk_signers: Vec<Box<dyn Signer>>,
+    wallet_manager: &mut Option<Arc<RemoteWalletManager>>,
+) -> CommandResult {
+    if proposal_file.exists() {
+        return Err(format!("{} already exists", proposal_file.display()).into());
+    }
+
+    let default_decimals = format!("{}", spl_token_2022::native_mint::DECIMALS);
+    let default_program_id = spl_token::id().to_string();
+    let minimum_signers_help = minimum_signers_help_string();
+    let multisig_member_help = multisig_member_help_string();
+    let args = |blockhash: &Hash| {
+        let mut args = vec!["spl-token".to_string()];
+        args.extend(command.iter().map(|arg| arg.to_string()));
+        args.extend([
+            "--sign-only".to_string(),
+            "--blockhash".to_string(),
+            blockhash.to_string(),
+        ]);
+        args
+    };
+
+    // Parse once to find the nonce, then again with the nonce's blockhash
+    let app_matches = app(
+        &default_decimals,
+        &default_program_id,
+        &minimum_signers_help,
+        &multisig_member_help,
+    )
+    .get_matches_from_safe(args(&Hash::default()))?;
+    let nonce_account = app_matches
+        .subcommand()
+        .1
+        .and_then(|matches| pubkey_of(matches, NONCE_ARG.name))
+        .ok_or("The proposed command must use a durable nonce, add `--nonce`")?;
+    let nonce_data =
+        nonce_utils::data_from_account(&config.rpc_client.get_account(&nonce_account).await?)?;
+
+    let app_matches = app(
+        &default_decimals,
+        &default_program_id,
+        &minimum_signers_help,
+        &multisig_member_help,
+    )
+    .get_matches_from_safe(args(&nonce_data.blockhash))?;
+    let (sub_command, sub_matches) = app_matches.subcommand();
+    let sub_command = CommandName::from_str(sub_command)?;
+    let matches = sub_matches.unwrap();
+    if sub_command == CommandName::Multisig {
+        return Err("Unable to propose a multisig command".into());
+    }
+
+    let (nonce_authority_signer, nonce_authority) =
+        config.signer_or_default(matches, NONCE_AUTHORITY_ARG.name, wallet_manager);
+    bulk_signers.push(nonce_authority_signer);
+    let mut multisigner_ids = vec![];
+    if let Some(mut multisig_signers) =
+        signers_of(matches, MULTISIG_SIGNER_ARG.name, wallet_manager)
+            .map_err(|err| err.to_string())?
+    {
+        multisig_signers.sort_by(|(_, lp), (_, rp)| lp.cmp(rp));
+        let (signers, pubkeys): (Vec<_>, Vec<_>) = multisig_signers.into_iter().unzip();
+        bulk_signers.extend(signers);
+        multisigner_ids = pubkeys;
+    }
+    let fee_payer = pubkey_of_signer(matches, "fee_payer", wallet_manager)
+        .map_err(|err| err.to_string())?
+        .unwrap_or(config.fee_payer);
+    let program_id = if matches.occurrences_of("program_id") > 0 {
+        pubkey_of(matches, "program_id").unwrap()
+    } else {
+        config.program_id
+    };
+
+    let sign_only_config = Config {
+        rpc_client: config.rpc_client.clone(),
+        program_client: config.program_client.clone(),
+        websocket_url: config.websocket_url.clone(),
+        output_format: OutputFormat::JsonCompact,
+        fee_payer,
+        default_keypair: config.default_keypair.clone(),
+        nonce_account: Some(nonce_account),
+        nonce_authority: Some(nonce_authority),
+        sign_only: true,
+        dump_transaction_message: true,
+        multisigner_pubkeys: multisigner_ids.iter().collect(),
+        program_id,
+    };
+    // `process_command` dispatches to this function, so the nested call has
+    // to be boxed
+    let output: Pin<Box<dyn Future<Output = CommandResult> + '_>> = Box::pin(process_command(
+        &sub_command,
+        matches,
+        &sign_only_config,
+        wallet_manager.clone(),
+        bulk_signers,
+    ));
+    let output: serde_json::Value = serde_json::from_str(&output.await?)?;
+    let sign_only_data = output.get("commandOutput").unwrap_or(&output);
+    let message = sign_only_data["message"]
+        .as_str()
+        .ok_or("The proposed command did not produce a transaction")?;
+    let signatures = sign_only_data["signers"]
+        .as_array()
+        .map(|signers| {
+            signers
+                .iter()
+                .filter_map(|signer| signer.as_str()?.split_once('='))
+                .map(|(signer, signature)| (signer.to_string(), signature.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut proposal = MultisigProposal {
+        multisig: String::new(),
+        minimum_signers: 0,
+        members: vec![],
+        message: message.to_string(),
+        signatures,
+    };
+    let (multisig, multisig_state) = get_multisig(config, &proposal.message()?).await?;
+    proposal.multisig = multisig.to_string();
+    proposal.minimum_signers = multisig_state.m;
+    proposal.members = multisig_state.signers[..multisig_state.n as usize]
+        .iter()
+        .map(|member| member.to_string())
+        .collect();
+    proposal.save(proposal_file)?;
+
+    println_display(
+        config,
+        format!("Wrote proposal to {}", proposal_file.display()),
+    );
+    Ok(config
+        .output_format
+        .formatted_string(&proposal.status(proposal_file)?))
+}
+
+fn command_sign(
+    config: &Config<'_>,
+    proposal_file: &Path,
+    signer: Box<dyn Signer>,
+) -> CommandResult {
+    let mut proposal = MultisigProposal::load(proposal_file)?;
+    let message = proposal.message()?;
+    let signer_pubkey = signer.pubkey();
+    if !message.account_keys[..message.header.num_required_signatures as usize]
+        .contains(&signer_pubkey)
+    {
+        return Err(format!(
+            "{} is not a signer of the proposed transaction",
+            signer_pubkey
+        )
+        .into());
+    }
+
+    let signature = signer.try_sign_message(&proposal.message_data()?)?;
+    proposal
+        .signatures
+        .insert(signer_pubkey.to_string(), signature.to_string());
+    let status = proposal.status(proposal_file)?;
+    proposal.save(proposal_file)?;
+
+    println_display(
+        config,
+        format!(
+            "Signed proposal {} as {}",
+            proposal_file.display(),
+            signer_pubkey
+        ),
+    );
+    Ok(config.output_format.formatted_string(&status))
+}
+
+async fn command_broadcast(config: &Config<'_>, proposal_file: &Path) -> CommandResult {
+    let proposal = MultisigProposal::load(proposal_file)?;
+    let message = proposal.message()?;
+    let signatures = proposal.verified_signatures(&message)?;
+
+    // The multisig may have been reconfigured since the proposal was made
+    let (multisig, multisig_state) = get_multisig(config, &message).await?;
+    let signed_members = signatures
+        .iter()
+        .filter(|(signer, signature)| {
+            signature.is_some()
+                && multisig_state.signers[..multisig_state.n as usize].contains(signer)
+        })
+        .count();
+    if signed_members < multisig_state.m as usize {
+        return Err(format!(
+            "Multisig {} requires {} signatures, the proposal has {}",
+            multisig, multisig_state.m, signed_members
+        )
+        .into());
+    }
+    let signatures = signatures
+        .into_iter()
+        .map(|(signer, signature)| {
+            signature.ok_or_else(|| format!("Missing signature for {}", signer))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let transaction = Transaction {
+        signatures,
+        message,
+    };
+    let signature = config
+        .rpc_client
+        .send_and_confirm_transaction_with_spinner(&transaction)
+        .await?;
+    Ok(config.output_format.formatted_string(&CliSignature {
+        signature: signature.to_string(),
+    }))
+}
@@ -233,6 +233,402 @@ impl fmt::Display for CliTokenAccount {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliConfidentialBalance {
+    pub(crate) address: String,
+    pub(crate) mint: String,
+    pub(crate) decimals: u8,
+    pub(crate) available_balance: String,
+    pub(crate) pending_balance: String,
+    pub(crate) approved: bool,
+    pub(crate) allow_balance_credits: bool,
+}
+
+impl QuietDisplay for CliConfidentialBalance {}
+impl VerboseDisplay for CliConfidentialBalance {}
+
+impl fmt::Display for CliConfidentialBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        writeln_name_value(f, "Address:", &self.address)?;
+        writeln_name_value(f, "Mint:", &self.mint)?;
+        writeln_name_value(f, "Available balance:", &self.available_balance)?;
+        writeln_name_value(f, "Pending balance:", &self.pending_balance)?;
+        writeln_name_value(f, "Approved:", &self.approved.to_string())?;
+        writeln_name_value(
+            f,
+            "Balance credits:",
+            if self.allow_balance_credits {
+                "enabled"
+            } else {
+                "disabled"
+            },
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliDistribution {
+    pub(crate) mint: String,
+    pub(crate) source: String,
+    pub(crate) state_file: String,
+    pub(crate) dry_run: bool,
+    pub(crate) recipients: usize,
+    pub(crate) previously_distributed: usize,
+    pub(crate) transfers: usize,
+    pub(crate) ui_amount: String,
+    pub(crate) accounts_created: usize,
+    pub(crate) transactions: usize,
+    pub(crate) fee_sol: f64,
+    pub(crate) rent_sol: f64,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub(crate) signatures: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub(crate) failed_lines: Vec<usize>,
+}
+
+impl QuietDisplay for CliDistribution {}
+impl VerboseDisplay for CliDistribution {
+    fn write_str(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        write!(w, "{}", self)?;
+        for signature in &self.signatures {
+            writeln!(w, "  {}", signature)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CliDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        if self.dry_run {
+            writeln!(f, "Dry run, no transactions were sent")?;
+        }
+        writeln_name_value(f, "Mint:", &self.mint)?;
+        writeln_name_value(f, "Source:", &self.source)?;
+        writeln_name_value(f, "State file:", &self.state_file)?;
+        writeln_name_value(f, "Recipients:", &self.recipients.to_string())?;
+        writeln_name_value(
+            f,
+            "Previously distributed:",
+            &self.previously_distributed.to_string(),
+        )?;
+        writeln_name_value(f, "Transfers:", &self.transfers.to_string())?;
+        writeln_name_value(f, "Amount:", &self.ui_amount)?;
+        writeln_name_value(f, "Accounts created:", &self.accounts_created.to_string())?;
+        writeln_name_value(f, "Transactions:", &self.transactions.to_string())?;
+        writeln_name_value(f, "Fees:", &format!("{} SOL", self.fee_sol))?;
+        writeln_name_value(f, "Account rent:", &format!("{} SOL", self.rent_sol))?;
+        if !self.failed_lines.is_empty() {
+            writeln_name_value(f, "Failed lines:", &format!("{:?}", self.failed_lines))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliHolders {
+    pub(crate) mint: String,
+    pub(crate) program_id: String,
+    pub(crate) decimals: u8,
+    pub(crate) ui_total_amount: String,
+    #[serde(skip)]
+    pub(crate) by_owner: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub(crate) accounts: Vec<CliHolderAccount>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub(crate) owners: Vec<CliHolderOwner>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliHolderAccount {
+    pub(crate) address: String,
+    pub(crate) owner: String,
+    pub(crate) amount: String,
+    pub(crate) ui_amount: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) delegate: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) delegated_amount: Option<String>,
+    pub(crate) state: String,
+    pub(crate) extensions: Vec<String>,
+    pub(crate) is_associated: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliHolderOwner {
+    pub(crate) owner: String,
+    pub(crate) amount: String,
+    pub(crate) ui_amount: String,
+    pub(crate) accounts: usize,
+}
+
+impl CliHolders {
+    /// Renders the snapshot as CSV, one row per account or per owner.
+    ///
+    /// CSV cannot go through `config.output_format`: `OutputFormat` is
+    /// defined by `solana-cli-output` and only knows the display and JSON
+    /// formats, and the global `--output` flag applies to every command,
+    /// most of which have no tabular form. `holders --csv` is therefore a
+    /// command-level flag that picks this renderer instead.
+    pub(crate) fn csv(&self) -> String {
+        let mut csv = String::new();
+        if !self.by_owner {
+            csv.push_str("address,owner,amount,delegate,delegated_amount,state,extensions\n");
+            for account in &self.accounts {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{}\n",
+                    account.address,
+                    account.owner,
+                    account.ui_amount,
+                    account.delegate.as_deref().unwrap_or_default(),
+                    account.delegated_amount.as_deref().unwrap_or_default(),
+                    account.state,
+                    account.extensions.join(";"),
+                ));
+            }
+        } else {
+            csv.push_str("owner,amount,accounts\n");
+            for owner in &self.owners {
+                csv.push_str(&format!(
+                    "{},{},{}\n",
+                    owner.owner, owner.ui_amount, owner.accounts
+                ));
+            }
+        }
+        csv
+    }
+}
+
+impl QuietDisplay for CliHolders {}
+impl VerboseDisplay for CliHolders {}
+
+impl fmt::Display for CliHolders {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        writeln_name_value(f, "Mint:", &self.mint)?;
+        writeln_name_value(f, "Total balance:", &self.ui_total_amount)?;
+        writeln!(f)?;
+        if !self.by_owner {
+            writeln!(
+                f,
+                "{:<44}  {:<44}  {:<20}  State",
+                "Account", "Owner", "Balance"
+            )?;
+            writeln!(f, "{}", "-".repeat(128))?;
+            for account in &self.accounts {
+                writeln!(
+                    f,
+                    "{:<44}  {:<44}  {:<20}  {}{}",
+                    account.address,
+                    account.owner,
+                    account.ui_amount,
+                    account.state,
+                    if account.delegate.is_some() {
+                        "  (delegated)"
+                    } else {
+                        ""
+                    },
+                )?;
+                if !account.extensions.is_empty() {
+                    writeln!(f, "  Extensions: {}", account.extensions.join(", "))?;
+                }
+            }
+        } else {
+            writeln!(f, "{:<44}  {:<20}  Accounts", "Owner", "Balance")?;
+            writeln!(f, "{}", "-".repeat(76))?;
+            for owner in &self.owners {
+                writeln!(
+                    f,
+                    "{:<44}  {:<20}  {}",
+                    owner.owner, owner.ui_amount, owner.accounts
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliMultisigProposal {
+    pub(crate) proposal_file: String,
+    pub(crate) multisig: String,
+    pub(crate) minimum_signers: u8,
+    pub(crate) instructions: Vec<String>,
+    pub(crate) signed: Vec<String>,
+    pub(crate) missing: Vec<String>,
+    pub(crate) ready: bool,
+}
+
+impl QuietDisplay for CliMultisigProposal {}
+impl VerboseDisplay for CliMultisigProposal {}
+
+impl fmt::Display for CliMultisigProposal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        writeln_name_value(f, "Proposal:", &self.proposal_file)?;
+        writeln_name_value(f, "Multisig:", &self.multisig)?;
+        writeln_name_value(f, "Minimum signers:", &self.minimum_signers.to_string())?;
+        writeln!(f, "Instructions:")?;
+        for instruction in &self.instructions {
+            writeln!(f, "  {}", instruction)?;
+        }
+        writeln!(f, "Signed by:")?;
+        for signer in &self.signed {
+            writeln!(f, "  {}", signer)?;
+        }
+        writeln!(f, "Awaiting signatures from:")?;
+        for signer in &self.missing {
+            writeln!(f, "  {}", signer)?;
+        }
+        writeln_name_value(
+            f,
+            "Status:",
+            if self.ready {
+                "ready to broadcast"
+            } else {
+                "awaiting signatures"
+            },
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliTokenDisplay {
+    pub(crate) address: String,
+    pub(crate) program_id: String,
+    pub(crate) account_type: String,
+    #[serde(serialize_with = "fields_map")]
+    pub(crate) fields: Vec<(String, String)>,
+    #[serde(serialize_with = "extensions_map")]
+    pub(crate) extensions: Vec<(String, Vec<(String, String)>)>,
+}
+
+struct FieldsMap<'a>(&'a [(String, String)]);
+
+impl Serialize for FieldsMap<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+fn fields_map<S: Serializer>(
+    fields: &[(String, String)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    FieldsMap(fields).serialize(serializer)
+}
+
+fn extensions_map<S: Serializer>(
+    extensions: &[(String, Vec<(String, String)>)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(
+        extensions
+            .iter()
+            .map(|(name, fields)| (name, FieldsMap(fields))),
+    )
+}
+
+// "transferFeeConfigAuthority" -> "Transfer fee config authority"
+fn field_label(key: &str) -> String {
+    let mut label = String::new();
+    for (i, c) in key.chars().enumerate() {
+        if i == 0 {
+            label.extend(c.to_uppercase());
+        } else if c.is_uppercase() {
+            label.push(' ');
+            label.extend(c.to_lowercase());
+        } else {
+            label.push(c);
+        }
+    }
+    label
+}
+
+impl QuietDisplay for CliTokenDisplay {}
+impl VerboseDisplay for CliTokenDisplay {}
+
+impl fmt::Display for CliTokenDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        writeln_name_value(f, "Address:", &self.address)?;
+        writeln_name_value(f, "Program:", &self.program_id)?;
+        writeln_name_value(f, "Type:", &field_label(&self.account_type))?;
+        for (key, value) in &self.fields {
+            writeln_name_value(f, &format!("{}:", field_label(key)), value)?;
+        }
+        if !self.extensions.is_empty() {
+            writeln!(f, "Extensions:")?;
+            for (name, fields) in &self.extensions {
+                writeln!(f, "  {}:", field_label(name))?;
+                for (key, value) in fields {
+                    writeln_name_value(f, &format!("    {}:", field_label(key)), value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CliMintApply {
+    pub(crate) address: String,
+    pub(crate) file: String,
+    pub(crate) dry_run: bool,
+    pub(crate) create: bool,
+    pub(crate) changes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub(crate) signatures: Vec<String>,
+}
+
+impl QuietDisplay for CliMintApply {}
+impl VerboseDisplay for CliMintApply {
+    fn write_str(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        write!(w, "{}", self)?;
+        for signature in &self.signatures {
+            writeln!(w, "  {}", signature)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for CliMintApply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f)?;
+        if self.dry_run {
+            writeln!(f, "Dry run, no transactions were sent")?;
+        }
+        writeln_name_value(f, "Mint:", &self.address)?;
+        writeln_name_value(f, "File:", &self.file)?;
+        if self.changes.is_empty() {
+            writeln!(f, "Mint is up to date")?;
+        } else {
+            writeln!(
+                f,
+                "{}:",
+                if self.dry_run {
+                    "Pending changes"
+                } else {
+                    "Applied changes"
+                }
+            )?;
+            for change in &self.changes {
+                writeln!(f, "  {}", change)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct CliTokenAccounts {
@@ -49,6 +49,42 @@ impl TransferFee {
             Some(cmp::min(raw_fee, u64::from(self.maximum_fee)))
         }
     }
+
+    /// Calculate the amount that must be sent so that `post_fee_amount`
+    /// arrives after the fee is withheld
+    pub fn calculate_pre_fee_amount(&self, post_fee_amount: u64) -> Option<u64> {
+        let maximum_fee = u64::from(self.maximum_fee);
+        let transfer_fee_basis_points = u16::from(self.transfer_fee_basis_points) as u128;
+        match (transfer_fee_basis_points, post_fee_amount) {
+            (0, _) => Some(post_fee_amount),
+            (_, 0) => Some(0),
+            (ONE_IN_BASIS_POINTS, _) => maximum_fee.checked_add(post_fee_amount),
+            _ => {
+                let numerator = (post_fee_amount as u128).checked_mul(ONE_IN_BASIS_POINTS)?;
+                let denominator = ONE_IN_BASIS_POINTS.checked_sub(transfer_fee_basis_points)?;
+                let raw_pre_fee_amount = ceil_div(numerator, denominator)?;
+                if raw_pre_fee_amount.checked_sub(post_fee_amount as u128)? >= maximum_fee as u128 {
+                    post_fee_amount.checked_add(maximum_fee)
+                } else {
+                    u64::try_from(raw_pre_fee_amount).ok()
+                }
+            }
+        }
+    }
+
+    /// Calculate the fee withheld when sending enough to deliver
+    /// `post_fee_amount`
+    pub fn calculate_inverse_fee(&self, post_fee_amount: u64) -> Option<u64> {
+        let pre_fee_amount = self.calculate_pre_fee_amount(post_fee_amount)?;
+        self.calculate(pre_fee_amount)
+    }
+}
+
+fn ceil_div(numerator: u128, denominator: u128) -> Option<u128> {
+    numerator
+        .checked_add(denominator)?
+        .checked_sub(1)?
+        .checked_div(denominator)
 }
 
 /// Transfer fee extension data for mints.
@@ -79,6 +115,12 @@ impl TransferFeeConfig {
     pub fn calculate_epoch_fee(&self, epoch: Epoch, amount: u64) -> Option<u64> {
         self.get_epoch_fee(epoch).calculate(amount)
     }
+    /// Calculate the fee for the given epoch, given the amount that must
+    /// arrive after the fee is withheld
+    pub fn calculate_inverse_epoch_fee(&self, epoch: Epoch, post_fee_amount: u64) -> Option<u64> {
+        self.get_epoch_fee(epoch)
+            .calculate_inverse_fee(post_fee_amount)
+    }
 }
 impl Extension for TransferFeeConfig {
     const TYPE: ExtensionType = ExtensionType::TransferFeeConfig;
@@ -241,4 +283,40 @@ pub(crate) mod test {
         assert_eq!(0, transfer_fee.calculate(1).unwrap());
         assert_eq!(0, transfer_fee.calculate(one).unwrap());
     }
+
+    #[test]
+    fn calculate_inverse_fee() {
+        let transfer_fee = TransferFee {
+            epoch: PodU64::from(0),
+            maximum_fee: PodU64::from(5_000),
+            transfer_fee_basis_points: PodU16::from(100),
+        };
+        for post_fee_amount in [
+            0, 1, 99, 100, 101, 9_999, 10_000, 499_999, 500_000, 1_000_000,
+        ] {
+            let pre_fee_amount = transfer_fee
+                .calculate_pre_fee_amount(post_fee_amount)
+                .unwrap();
+            let fee = transfer_fee.calculate(pre_fee_amount).unwrap();
+            assert_eq!(pre_fee_amount - fee, post_fee_amount);
+            assert_eq!(
+                fee,
+                transfer_fee.calculate_inverse_fee(post_fee_amount).unwrap()
+            );
+        }
+        // capped by the maximum fee
+        assert_eq!(
+            5_000,
+            transfer_fee
+                .calculate_inverse_fee(u64::MAX - 5_000)
+                .unwrap()
+        );
+        // 100% fee always takes the maximum
+        let transfer_fee = TransferFee {
+            epoch: PodU64::from(0),
+            maximum_fee: PodU64::from(5_000),
+            transfer_fee_basis_points: PodU16::from(MAX_FEE_BASIS_POINTS),
+        };
+        assert_eq!(5_100, transfer_fee.calculate_pre_fee_amount(100).unwrap());
+    }
 }